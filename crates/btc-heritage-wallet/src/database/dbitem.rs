@@ -14,12 +14,18 @@ pub trait DatabaseItem: Serialize + DeserializeOwned {
         format!("{}{name}", Self::item_key_prefix())
     }
 
+    /// The key of the item named `name` in the namespace of `db`, see [Database::with_namespace]
+    fn db_key(db: &Database, name: &str) -> String {
+        db.namespaced_key(&Self::name_to_key(name))
+    }
+
     fn list_names(db: &Database) -> Result<Vec<String>> {
-        let keys_with_prefix = db.list_keys(Some(Self::item_key_prefix()))?;
+        let prefix = db.namespaced_key(Self::item_key_prefix());
+        let keys_with_prefix = db.list_keys(Some(&prefix))?;
         Ok(keys_with_prefix
             .into_iter()
             .map(|k| {
-                k.strip_prefix(Self::item_key_prefix())
+                k.strip_prefix(&prefix)
                     .expect("we asked for keys with this prefix")
                     .to_owned()
             })
@@ -27,25 +33,26 @@ pub trait DatabaseItem: Serialize + DeserializeOwned {
     }
 
     fn all_in_db(db: &Database) -> Result<Vec<Self>> {
-        db.query(Self::item_key_prefix())
+        db.query(&db.namespaced_key(Self::item_key_prefix()))
     }
 
     /// Get the default name of the item
     /// It is "default" by default but can be changed by invoking [DatabaseItem::set_default_wallet_name]
     fn get_default_item_name(db: &Database) -> Result<String> {
         Ok(db
-            .get_item(Self::item_default_name_key_prefix())?
+            .get_item(&db.namespaced_key(Self::item_default_name_key_prefix()))?
             .unwrap_or_else(|| "default".to_owned()))
     }
     /// Set the default name of the item
     fn set_default_item_name(db: &mut Database, name: String) -> Result<()> {
-        db.update_item(Self::item_default_name_key_prefix(), &name)?;
+        let key = db.namespaced_key(Self::item_default_name_key_prefix());
+        db.update_item(&key, &name)?;
         Ok(())
     }
 
     /// Verify that the given item name is not already in the database
     fn verify_name_is_free(db: &Database, name: &str) -> Result<()> {
-        let key = Self::name_to_key(name);
+        let key = Self::db_key(db, name);
         if db.contains_key(&key)? {
             Err(DbError::KeyAlreadyExists(key))
         } else {
@@ -54,30 +61,30 @@ pub trait DatabaseItem: Serialize + DeserializeOwned {
     }
 
     fn create(&self, db: &mut Database) -> Result<()> {
-        db.put_item(&Self::name_to_key(self.name()), self)?;
+        db.put_item(&Self::db_key(db, self.name()), self)?;
         Ok(())
     }
 
     fn delete(&self, db: &mut Database) -> Result<()> {
-        db.delete_item::<Self>(&Self::name_to_key(self.name()))?;
+        db.delete_item::<Self>(&Self::db_key(db, self.name()))?;
         Ok(())
     }
 
     fn save(&self, db: &mut Database) -> Result<()> {
-        db.update_item(&Self::name_to_key(&self.name()), self)?;
+        db.update_item(&Self::db_key(db, self.name()), self)?;
         Ok(())
     }
 
     fn load(db: &Database, name: &str) -> Result<Self> {
-        let key = Self::name_to_key(name);
+        let key = Self::db_key(db, name);
         db.get_item(&key)?.ok_or(DbError::KeyDoesNotExists(key))
     }
 
     fn db_rename(&mut self, db: &mut Database, new_name: String) -> Result<()> {
         let old_name = self.name().to_owned();
         self.rename(new_name);
-        db.put_item(&Self::name_to_key(&self.name()), self)?;
-        db.delete_item::<Self>(&Self::name_to_key(&old_name))?;
+        db.put_item(&Self::db_key(db, self.name()), self)?;
+        db.delete_item::<Self>(&Self::db_key(db, &old_name))?;
         Ok(())
     }
 }
@@ -110,3 +117,82 @@ macro_rules! impl_db_item {
     };
 }
 pub(crate) use impl_db_item;
+
+#[cfg(test)]
+mod tests {
+    use btc_heritage::bitcoin::Network;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    struct Item {
+        name: String,
+    }
+    impl_db_item!(Item, "item#", "default_item_name");
+
+    fn item(name: &str) -> Item {
+        Item {
+            name: name.to_owned(),
+        }
+    }
+
+    #[test]
+    fn namespaces() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut db = Database::new(tmpdir.path(), Network::Regtest).unwrap();
+        let mut personal = db.with_namespace(Some("personal")).unwrap();
+        let mut business = db.with_namespace(Some("business")).unwrap();
+        assert_eq!(business.namespace(), Some("business"));
+        assert!(db.list_namespaces().unwrap().is_empty());
+
+        // The same name can be used in every namespace
+        item("main").create(&mut db).unwrap();
+        item("main").create(&mut personal).unwrap();
+        item("main").create(&mut business).unwrap();
+        assert!(item("main").create(&mut business).is_err());
+        item("other").create(&mut business).unwrap();
+
+        assert_eq!(Item::list_names(&db).unwrap(), vec!["main"]);
+        assert_eq!(Item::list_names(&personal).unwrap(), vec!["main"]);
+        assert_eq!(Item::list_names(&business).unwrap(), vec!["main", "other"]);
+        assert_eq!(Item::all_in_db(&business).unwrap().len(), 2);
+        assert!(Item::verify_name_is_free(&personal, "other").is_ok());
+        assert!(matches!(
+            Item::load(&personal, "other"),
+            Err(DbError::KeyDoesNotExists(_))
+        ));
+        assert_eq!(
+            db.list_namespaces().unwrap(),
+            vec!["business".to_owned(), "personal".to_owned()]
+        );
+
+        // Default names are per namespace
+        Item::set_default_item_name(&mut business, "other".to_owned()).unwrap();
+        assert_eq!(Item::get_default_item_name(&business).unwrap(), "other");
+        assert_eq!(Item::get_default_item_name(&personal).unwrap(), "default");
+        assert_eq!(Item::get_default_item_name(&db).unwrap(), "default");
+
+        // Renaming and deleting stay in the namespace
+        let mut other = Item::load(&business, "other").unwrap();
+        other
+            .db_rename(&mut business, "renamed".to_owned())
+            .unwrap();
+        assert_eq!(
+            Item::list_names(&business).unwrap(),
+            vec!["main", "renamed"]
+        );
+        item("main").delete(&mut personal).unwrap();
+        assert!(Item::list_names(&personal).unwrap().is_empty());
+        assert_eq!(Item::list_names(&db).unwrap(), vec!["main"]);
+
+        // Invalid namespaces
+        for namespace in ["", "a#b", "a b", "é"] {
+            assert!(matches!(
+                db.with_namespace(Some(namespace)),
+                Err(DbError::InvalidNamespace(_))
+            ));
+        }
+        assert!(db.with_namespace(None).unwrap().namespace().is_none());
+    }
+}
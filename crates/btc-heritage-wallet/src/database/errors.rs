@@ -28,6 +28,15 @@ pub enum DbError {
     SerDeError { key: String, error: String },
     #[error("Prefix must not be empty")]
     EmptyPrefix,
+    #[error(
+        "The database is locked by {}",
+        .pid.map_or("another process".to_owned(), |pid| format!("PID {pid}"))
+    )]
+    Locked { pid: Option<u32> },
+    #[error("Invalid namespace: {0:?}, only ASCII alphanumerics, '-' and '_' are allowed")]
+    InvalidNamespace(String),
+    #[error("Invalid database snapshot: {0}")]
+    InvalidSnapshot(String),
     #[error("RedbError: {0}")]
     RedbError(redb::Error),
     #[error("Generic DbError: {0}")]
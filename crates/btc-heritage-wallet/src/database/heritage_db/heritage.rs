@@ -2,13 +2,16 @@ use std::collections::HashSet;
 
 use btc_heritage::{
     bdk_types,
-    bitcoin::{FeeRate, OutPoint, Txid},
+    bitcoin::{FeeRate, Network, OutPoint, Txid},
     database::{
         paginate::{ContinuationToken, Paginated},
         HeritageDatabase, TransacHeritageDatabase, TransacHeritageOperation,
     },
     errors::DatabaseError,
-    heritage_wallet::{HeritageUtxo, SubwalletConfigId, TransactionSummary},
+    heritage_wallet::{
+        heir_rotation::HeirRotation, journal::PendingOperation, GapLimit, HeirSpendingQuotas,
+        HeritageUtxo, LabelRef, SubwalletConfigId, TransactionSummary,
+    },
     subwallet_config::SubwalletConfig,
     AccountXPub, BlockInclusionObjective, HeritageWalletBalance,
 };
@@ -290,6 +293,84 @@ impl HeritageDatabase for HeritageWalletDatabase {
         Ok(page)
     }
 
+    fn set_utxo_label(&mut self, outpoint: &OutPoint, label: &str) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::set_utxo_label - outpoint={outpoint} label={label}");
+        let key = self.key(&KeyMapper::UtxoLabel(Some(outpoint)));
+        self.db.update_item(&key, &(outpoint, label))?;
+        Ok(())
+    }
+
+    fn delete_utxo_label(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::delete_utxo_label - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::UtxoLabel(Some(outpoint)));
+        self.db.delete_item::<(OutPoint, String)>(&key)?;
+        Ok(())
+    }
+
+    fn get_utxo_label(&self, outpoint: &OutPoint) -> Result<Option<String>> {
+        log::debug!("HeritageWalletDatabase::get_utxo_label - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::UtxoLabel(Some(outpoint)));
+        Ok(self
+            .db
+            .get_item::<(OutPoint, String)>(&key)?
+            .map(|(_, label)| label))
+    }
+
+    fn list_utxo_labels(&self) -> Result<Vec<(OutPoint, String)>> {
+        log::debug!("HeritageWalletDatabase::list_utxo_labels");
+        let prefix = self.key(&KeyMapper::UtxoLabel(None));
+        Ok(self.db.query(&prefix)?)
+    }
+
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::set_label - label_ref={label_ref} label={label}");
+        let key = self.key(&KeyMapper::Label(Some(label_ref)));
+        self.db.update_item(&key, &(label_ref, label))?;
+        Ok(())
+    }
+
+    fn delete_label(&mut self, label_ref: &LabelRef) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::delete_label - label_ref={label_ref}");
+        let key = self.key(&KeyMapper::Label(Some(label_ref)));
+        self.db.delete_item::<(LabelRef, String)>(&key)?;
+        Ok(())
+    }
+
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>> {
+        log::debug!("HeritageWalletDatabase::get_label - label_ref={label_ref}");
+        let key = self.key(&KeyMapper::Label(Some(label_ref)));
+        Ok(self
+            .db
+            .get_item::<(LabelRef, String)>(&key)?
+            .map(|(_, label)| label))
+    }
+
+    fn list_labels(&self) -> Result<Vec<(LabelRef, String)>> {
+        log::debug!("HeritageWalletDatabase::list_labels");
+        let prefix = self.key(&KeyMapper::Label(None));
+        Ok(self.db.query(&prefix)?)
+    }
+
+    fn freeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::freeze_utxo - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::FrozenUtxo(Some(outpoint)));
+        self.db.update_item(&key, outpoint)?;
+        Ok(())
+    }
+
+    fn unfreeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::unfreeze_utxo - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::FrozenUtxo(Some(outpoint)));
+        self.db.delete_item::<OutPoint>(&key)?;
+        Ok(())
+    }
+
+    fn list_frozen_utxos(&self) -> Result<Vec<OutPoint>> {
+        log::debug!("HeritageWalletDatabase::list_frozen_utxos");
+        let prefix = self.key(&KeyMapper::FrozenUtxo(None));
+        Ok(self.db.query(&prefix)?)
+    }
+
     fn add_transaction_summaries(
         &mut self,
         transaction_summaries: &Vec<TransactionSummary>,
@@ -396,4 +477,78 @@ impl HeritageDatabase for HeritageWalletDatabase {
         self.db.update_item(&key, &new_objective)?;
         Ok(())
     }
+
+    fn get_gap_limit(&self) -> Result<Option<GapLimit>> {
+        log::debug!("HeritageWalletDatabase::get_gap_limit");
+        let key = self.key(&KeyMapper::GapLimit);
+        Ok(self.db.get_item(&key)?)
+    }
+
+    fn set_gap_limit(&mut self, new_gap_limit: GapLimit) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::set_gap_limit - new_gap_limit={new_gap_limit:?}");
+        let key = self.key(&KeyMapper::GapLimit);
+        self.db.update_item(&key, &new_gap_limit)?;
+        Ok(())
+    }
+
+    fn get_heir_spending_quotas(&self) -> Result<Option<HeirSpendingQuotas>> {
+        log::debug!("HeritageWalletDatabase::get_heir_spending_quotas");
+        let key = self.key(&KeyMapper::HeirSpendingQuotas);
+        Ok(self.db.get_item(&key)?)
+    }
+
+    fn set_heir_spending_quotas(&mut self, new_quotas: &HeirSpendingQuotas) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::set_heir_spending_quotas - new_quotas={new_quotas:?}");
+        let key = self.key(&KeyMapper::HeirSpendingQuotas);
+        self.db.update_item(&key, new_quotas)?;
+        Ok(())
+    }
+
+    fn get_network(&self) -> Result<Option<Network>> {
+        log::debug!("HeritageWalletDatabase::get_network");
+        let key = self.key(&KeyMapper::Network);
+        Ok(self.db.get_item(&key)?)
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::set_network - network={network:?}");
+        let key = self.key(&KeyMapper::Network);
+        self.db.update_item(&key, &network)?;
+        Ok(())
+    }
+
+    fn get_pending_operation(&self) -> Result<Option<PendingOperation>> {
+        log::debug!("HeritageWalletDatabase::get_pending_operation");
+        let key = self.key(&KeyMapper::PendingOperation);
+        Ok(self.db.get_item(&key)?)
+    }
+
+    fn set_pending_operation(&mut self, pending_operation: &PendingOperation) -> Result<()> {
+        log::debug!(
+            "HeritageWalletDatabase::set_pending_operation - pending_operation={pending_operation:?}"
+        );
+        let key = self.key(&KeyMapper::PendingOperation);
+        self.db.update_item(&key, pending_operation)?;
+        Ok(())
+    }
+
+    fn delete_pending_operation(&mut self) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::delete_pending_operation");
+        let key = self.key(&KeyMapper::PendingOperation);
+        self.db.delete_item::<PendingOperation>(&key)?;
+        Ok(())
+    }
+
+    fn add_heir_rotation(&mut self, heir_rotation: &HeirRotation) -> Result<()> {
+        log::debug!("HeritageWalletDatabase::add_heir_rotation - heir_rotation={heir_rotation:?}");
+        let key = self.key(&KeyMapper::HeirRotation(Some(heir_rotation)));
+        self.db.update_item(&key, heir_rotation)?;
+        Ok(())
+    }
+
+    fn list_heir_rotations(&self) -> Result<Vec<HeirRotation>> {
+        log::debug!("HeritageWalletDatabase::list_heir_rotations");
+        let prefix = self.key(&KeyMapper::HeirRotation(None));
+        Ok(self.db.query(&prefix)?)
+    }
 }
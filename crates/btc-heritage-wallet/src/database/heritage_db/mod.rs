@@ -5,7 +5,7 @@ use btc_heritage::{
     bitcoin::{OutPoint, Script, Txid},
     database::{PartitionableDatabase, SubdatabaseId},
     errors::DatabaseError,
-    heritage_wallet::SubwalletConfigId,
+    heritage_wallet::{heir_rotation::HeirRotation, LabelRef, SubwalletConfigId},
     AccountXPubId,
 };
 
@@ -17,10 +17,18 @@ enum KeyMapper<'a> {
     SubwalletConfig(Option<SubwalletConfigId>),
     UnusedAccountXPub(Option<AccountXPubId>),
     HeritageUtxo(Option<&'a OutPoint>),
+    UtxoLabel(Option<&'a OutPoint>),
+    Label(Option<&'a LabelRef>),
+    FrozenUtxo(Option<&'a OutPoint>),
     TxSummary(Option<(&'a Txid, Option<&'a bdk_types::BlockTime>)>),
     WalletBalance,
     FeeRate,
     BlockInclusionObjective,
+    GapLimit,
+    HeirSpendingQuotas,
+    Network,
+    PendingOperation,
+    HeirRotation(Option<&'a HeirRotation>),
     // bdk::Wallet DB related
     SyncTime,
     Path((Option<bdk_types::KeychainKind>, Option<u32>)),
@@ -39,10 +47,18 @@ impl KeyMapper<'_> {
             KeyMapper::SubwalletConfig(_) => "w",
             KeyMapper::UnusedAccountXPub(_) => "x",
             KeyMapper::HeritageUtxo(_) => "h",
+            KeyMapper::UtxoLabel(_) => "n",
+            KeyMapper::Label(_) => "m",
+            KeyMapper::FrozenUtxo(_) => "z",
             KeyMapper::TxSummary(_) => "y",
             KeyMapper::WalletBalance => "b",
             KeyMapper::FeeRate => "f",
             KeyMapper::BlockInclusionObjective => "o",
+            KeyMapper::GapLimit => "g",
+            KeyMapper::HeirSpendingQuotas => "q",
+            KeyMapper::Network => "e",
+            KeyMapper::PendingOperation => "j",
+            KeyMapper::HeirRotation(_) => "k",
             // bdk::Wallet DB related
             KeyMapper::Path(_) => "p",
             KeyMapper::Script(_) => "s",
@@ -68,7 +84,15 @@ impl KeyMapper<'_> {
             KeyMapper::UnusedAccountXPub(Some(id)) => {
                 format!("{:0>10}", id)
             }
-            KeyMapper::HeritageUtxo(Some(op)) => op.to_string(),
+            KeyMapper::HeritageUtxo(Some(op))
+            | KeyMapper::UtxoLabel(Some(op))
+            | KeyMapper::FrozenUtxo(Some(op)) => op.to_string(),
+            KeyMapper::Label(Some(label_ref)) => label_ref.to_string(),
+            KeyMapper::HeirRotation(Some(heir_rotation)) => format!(
+                "{:0>20}#{}",
+                heir_rotation.timestamp,
+                heir_rotation.new_heir_config.fingerprint()
+            ),
             KeyMapper::TxSummary(Some((txid, confirmation_time))) => format!(
                 "{:0>10}#{}",
                 confirmation_time
@@ -157,6 +181,7 @@ impl HeritageWalletDatabase {
             db: Database {
                 internal_db: Arc::clone(&db.internal_db),
                 table_name: Some(wallet_id),
+                namespace: None,
             },
             prefix: String::new(),
         }
@@ -180,6 +205,7 @@ impl PartitionableDatabase for HeritageWalletDatabase {
             db: Database {
                 internal_db: Arc::clone(&self.db.internal_db),
                 table_name: self.db.table_name.clone(),
+                namespace: None,
             },
             prefix: subdatabase_id.to_string(),
         })
@@ -233,12 +259,21 @@ mod tests {
     impl_heritage_test!(get_set_balance);
     impl_heritage_test!(get_set_fee_rate);
     impl_heritage_test!(get_set_block_inclusion_objective);
+    impl_heritage_test!(get_set_gap_limit);
+    impl_heritage_test!(get_set_heir_spending_quotas);
+    impl_heritage_test!(get_set_network);
+    impl_heritage_test!(heir_rotations_management);
     impl_heritage_test!(list_obsolete_subwallet_configs);
+    impl_heritage_test!(pending_operation_management);
     impl_heritage_test!(safe_update_current_subwallet_config);
     impl_heritage_test!(transaction);
     impl_heritage_test!(unused_account_xpub_management);
     impl_heritage_test!(heritage_utxo_management);
+    impl_heritage_test!(utxo_labels_management);
+    impl_heritage_test!(labels_management);
+    impl_heritage_test!(frozen_utxos_management);
     impl_heritage_test!(transaction_summaries_management);
+    impl_heritage_test!(verify_repair_integrity);
 
     macro_rules! impl_bdk_test {
         ($tn: tt) => {
@@ -0,0 +1,128 @@
+//! Exclusive access to a database file across processes.
+//!
+//! redb holds an exclusive OS lock on the database file for as long as it is open, and the OS
+//! releases it even if the process crashes. On top of that, the PID of the process holding the
+//! lock is written in a companion `.pid` file so the other processes can report who holds it.
+
+use core::ops::Deref;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use btc_heritage::bitcoin::Network;
+
+use super::errors::{DbError, Result};
+
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+pub(super) struct LockedDatabase {
+    db: redb::Database,
+    network: Network,
+    pid_file: PathBuf,
+}
+
+impl LockedDatabase {
+    /// Open or create the database of the `network` at `database_path`, waiting at most
+    /// `wait` if another process holds it. [Duration::MAX] waits indefinitely.
+    pub(super) fn open(database_path: &Path, network: Network, wait: Duration) -> Result<Self> {
+        let pid_file = database_path.with_extension("pid");
+        let deadline = Instant::now().checked_add(wait);
+        loop {
+            match redb::Database::create(database_path) {
+                Ok(db) => {
+                    if let Err(e) = std::fs::write(&pid_file, std::process::id().to_string()) {
+                        log::warn!("Cannot write {}: {e}", pid_file.display());
+                    }
+                    return Ok(Self {
+                        db,
+                        network,
+                        pid_file,
+                    });
+                }
+                Err(redb::DatabaseError::DatabaseAlreadyOpen) => {
+                    let pid = std::fs::read_to_string(&pid_file)
+                        .ok()
+                        .and_then(|s| s.trim().parse().ok());
+                    let now = Instant::now();
+                    if deadline.is_some_and(|deadline| now >= deadline) {
+                        return Err(DbError::Locked { pid });
+                    }
+                    log::debug!(
+                        "Database {} is locked by PID {pid:?}, waiting",
+                        database_path.display()
+                    );
+                    std::thread::sleep(match deadline {
+                        Some(deadline) => LOCK_RETRY_INTERVAL.min(deadline - now),
+                        None => LOCK_RETRY_INTERVAL,
+                    });
+                }
+                Err(e) => {
+                    return Err(DbError::Generic(format!(
+                        "Cannot create database at {}: {}",
+                        database_path.display(),
+                        e.to_string()
+                    )))
+                }
+            }
+        }
+    }
+
+    pub(super) fn network(&self) -> Network {
+        self.network
+    }
+}
+
+impl Deref for LockedDatabase {
+    type Target = redb::Database;
+
+    fn deref(&self) -> &Self::Target {
+        &self.db
+    }
+}
+
+impl Drop for LockedDatabase {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.pid_file) {
+            log::warn!("Cannot remove {}: {e}", self.pid_file.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+
+    #[test]
+    fn database_lock() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db = Database::new(tmpdir.path(), Network::Regtest).unwrap();
+
+        // A second opening fails, reporting the PID holding the database
+        match Database::new(tmpdir.path(), Network::Regtest) {
+            Err(DbError::Locked { pid }) => assert_eq!(pid, Some(std::process::id())),
+            r => panic!("expected DbError::Locked, got {r:?}"),
+        }
+        assert!(matches!(
+            Database::new_with_lock_wait(
+                tmpdir.path(),
+                Network::Regtest,
+                Duration::from_millis(300)
+            ),
+            Err(DbError::Locked { .. })
+        ));
+        // Other networks use another database
+        assert!(Database::new(tmpdir.path(), Network::Testnet).is_ok());
+
+        // Waiting succeeds once the database is released
+        let releaser = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            drop(db);
+        });
+        let res = Database::new_with_lock_wait(tmpdir.path(), Network::Regtest, Duration::MAX);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        releaser.join().unwrap();
+    }
+}
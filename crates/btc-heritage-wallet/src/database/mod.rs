@@ -1,14 +1,17 @@
-use std::{fmt::Debug, path::Path, sync::Arc, usize};
+use std::{fmt::Debug, path::Path, sync::Arc, time::Duration, usize};
 
 use btc_heritage::bitcoin::Network;
 
 pub(crate) mod dbitem;
 pub(crate) mod errors;
 mod heritage_db;
+mod lock;
+mod snapshot;
 mod utils;
 
 use errors::{DbError, Result};
 use heritage_service_api_client::TokenCache;
+use lock::LockedDatabase;
 use redb::{ReadOnlyTable, ReadableTable, Table, TableDefinition};
 use serde::{de::DeserializeOwned, Serialize};
 use utils::prepare_data_dir;
@@ -19,6 +22,7 @@ pub use heritage_db::HeritageWalletDatabase;
 const DEFAULT_TABLE_NAME: &'static str = "heritage";
 const DEFAULT_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new(DEFAULT_TABLE_NAME);
 const TOKEN_KEY: &'static str = "api_auth_tokens";
+const NAMESPACE_KEY_PREFIX: &'static str = "ns:";
 
 pub enum DatabaseTransactionOperation {
     Update(String, Vec<u8>),
@@ -93,12 +97,24 @@ impl DatabaseTransaction {
 
 #[derive(Debug)]
 pub struct Database {
-    internal_db: Arc<redb::Database>,
+    internal_db: Arc<LockedDatabase>,
     table_name: Option<String>,
+    namespace: Option<String>,
 }
 
 impl Database {
+    /// Open or create the database of the `network` in `data_dir`.
+    ///
+    /// # Errors
+    /// Returns [DbError::Locked] if another process, or another [Database] of this
+    /// process, has the database open. See [Database::new_with_lock_wait] to wait for it.
     pub fn new(data_dir: &Path, network: Network) -> Result<Self> {
+        Self::new_with_lock_wait(data_dir, network, Duration::ZERO)
+    }
+
+    /// Same as [Database::new] but if the database is locked, wait at most `wait`
+    /// for it to be released. [Duration::MAX] waits indefinitely.
+    pub fn new_with_lock_wait(data_dir: &Path, network: Network, wait: Duration) -> Result<Self> {
         prepare_data_dir(data_dir)?;
 
         // We will maintain different DBs for each network
@@ -106,22 +122,77 @@ impl Database {
         let mut database_path = data_dir.to_path_buf();
         database_path.push(format!("{database_name}.redb"));
 
-        let db = redb::Database::create(database_path.as_path()).map_err(|e| {
-            DbError::Generic(format!(
-                "Cannot create database at {}: {}",
-                database_path.as_path().display(),
-                e.to_string()
-            ))
-        })?;
+        let db = LockedDatabase::open(database_path.as_path(), network, wait)?;
 
         log::debug!("Main database opened successfully");
 
         Ok(Database {
             internal_db: Arc::new(db),
             table_name: None,
+            namespace: None,
         })
     }
 
+    /// Return a [Database] on the same database file whose [DatabaseItem]s are isolated in
+    /// `namespace`, e.g. to keep a personal and a business wallet apart. [None] is the
+    /// default namespace, where the items created before namespaces existed are.
+    ///
+    /// The authentication tokens of the Heritage service are shared by all the namespaces.
+    ///
+    /// # Errors
+    /// Returns [DbError::InvalidNamespace] if `namespace` is empty or contains other
+    /// characters than ASCII alphanumerics, `-` and `_`
+    pub fn with_namespace(&self, namespace: Option<&str>) -> Result<Self> {
+        if let Some(namespace) = namespace {
+            if namespace.is_empty()
+                || !namespace
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                return Err(DbError::InvalidNamespace(namespace.to_owned()));
+            }
+        }
+        Ok(Database {
+            internal_db: Arc::clone(&self.internal_db),
+            table_name: self.table_name.clone(),
+            namespace: namespace.map(ToOwned::to_owned),
+        })
+    }
+
+    /// Return the [Network] of the database
+    pub fn network(&self) -> Network {
+        self.internal_db.network()
+    }
+
+    /// Return the namespace of the [DatabaseItem]s, [None] for the default one
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// List the namespaces containing at least one item, the default namespace excluded
+    pub fn list_namespaces(&self) -> Result<Vec<String>> {
+        let mut namespaces = self
+            .list_keys(Some(NAMESPACE_KEY_PREFIX))?
+            .into_iter()
+            .filter_map(|key| {
+                key.strip_prefix(NAMESPACE_KEY_PREFIX)
+                    .and_then(|k| k.split_once('#'))
+                    .map(|(namespace, _)| namespace.to_owned())
+            })
+            .collect::<Vec<_>>();
+        // Keys are sorted so the duplicates are consecutive
+        namespaces.dedup();
+        Ok(namespaces)
+    }
+
+    /// Return the actual key of `key` in the namespace of this [Database]
+    pub(crate) fn namespaced_key(&self, key: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{NAMESPACE_KEY_PREFIX}{namespace}#{key}"),
+            None => key.to_owned(),
+        }
+    }
+
     pub fn begin_transac(&self) -> DatabaseTransaction {
         DatabaseTransaction(Vec::new())
     }
@@ -0,0 +1,196 @@
+//! Portable snapshot of a whole [Database], see [Database::snapshot].
+
+use std::{
+    collections::BTreeMap,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use btc_heritage::bitcoin::Network;
+use redb::{ReadableTable, TableDefinition, TableHandle};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    errors::{DbError, Result},
+    Database, DEFAULT_TABLE_NAME, TOKEN_KEY,
+};
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// The content of a snapshot file. Values are kept as the JSON strings stored in the
+/// database so that they are restored byte for byte.
+#[derive(Debug, Serialize, Deserialize)]
+struct DatabaseSnapshot {
+    version: u32,
+    network: Network,
+    tables: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Database {
+    /// Write every table of the database, i.e. the wallets, the heirs and the
+    /// [HeritageWalletDatabase](super::HeritageWalletDatabase)s with all their subdatabases,
+    /// in a single snapshot file at `path`. Importing it with [Database::import_snapshot] on
+    /// another machine avoids to rescan the blockchain, unlike restoring a descriptors backup.
+    ///
+    /// The authentication tokens of the Heritage service are not part of the snapshot.
+    ///
+    /// # Errors
+    /// Returns an error if `path` already exists
+    pub fn snapshot(&self, path: &Path) -> Result<()> {
+        log::debug!("Database::snapshot - path={}", path.display());
+        let txn = self.internal_db.begin_read()?;
+        let mut tables = BTreeMap::new();
+        for handle in txn.list_tables()? {
+            let table_name = handle.name().to_owned();
+            let table_def: TableDefinition<'_, &'static str, &'static [u8]> =
+                TableDefinition::new(&table_name);
+            let table = txn.open_table(table_def)?;
+            let mut items = BTreeMap::new();
+            for entry in table.iter()? {
+                let (key, value) = entry?;
+                let key = key.value().to_owned();
+                if table_name == DEFAULT_TABLE_NAME && key == TOKEN_KEY {
+                    continue;
+                }
+                let value = String::from_utf8(value.value().to_vec())
+                    .map_err(|e| DbError::Generic(format!("Invalid value for key {key}: {e}")))?;
+                items.insert(key, value);
+            }
+            tables.insert(table_name, items);
+        }
+        let snapshot = DatabaseSnapshot {
+            version: SNAPSHOT_VERSION,
+            network: self.internal_db.network(),
+            tables,
+        };
+
+        let file = std::fs::File::create_new(path)
+            .map_err(|e| DbError::Generic(format!("Cannot create {}: {}", path.display(), e)))?;
+        serde_json::to_writer(BufWriter::new(file), &snapshot)
+            .map_err(|e| DbError::Generic(format!("Cannot write {}: {}", path.display(), e)))?;
+        log::info!(
+            "Database::snapshot - {} table(s) written to {}",
+            snapshot.tables.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Import the snapshot file at `path`, created by [Database::snapshot], in this database.
+    /// Everything is imported in a single transaction: either all the items are imported
+    /// or none is.
+    ///
+    /// # Errors
+    /// Returns [DbError::InvalidSnapshot] if the file is not a snapshot of a database of the
+    /// same [Network], and [DbError::KeyAlreadyExists] if an item of the snapshot is
+    /// already in this database, e.g. a wallet with the same name.
+    pub fn import_snapshot(&mut self, path: &Path) -> Result<()> {
+        log::debug!("Database::import_snapshot - path={}", path.display());
+        let file = std::fs::File::open(path)
+            .map_err(|e| DbError::Generic(format!("Cannot open {}: {}", path.display(), e)))?;
+        let snapshot: DatabaseSnapshot = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| DbError::InvalidSnapshot(e.to_string()))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(DbError::InvalidSnapshot(format!(
+                "unsupported version {}",
+                snapshot.version
+            )));
+        }
+        if snapshot.network != self.internal_db.network() {
+            return Err(DbError::InvalidSnapshot(format!(
+                "the snapshot is for {} but the database is for {}",
+                snapshot.network,
+                self.internal_db.network()
+            )));
+        }
+
+        let txn = self.internal_db.begin_write()?;
+        let import_res = 'txn: {
+            for (table_name, items) in snapshot.tables.iter() {
+                let table_def: TableDefinition<'_, &'static str, &'static [u8]> =
+                    TableDefinition::new(table_name);
+                let mut table = txn.open_table(table_def)?;
+                for (key, value) in items {
+                    if table.get(key.as_str())?.is_some() {
+                        break 'txn Err(DbError::KeyAlreadyExists(key.to_owned()));
+                    }
+                    table.insert(key.as_str(), value.as_bytes())?;
+                }
+            }
+            Ok(())
+        };
+        if import_res.is_ok() {
+            txn.commit()?;
+            log::info!(
+                "Database::import_snapshot - {} table(s) imported from {}",
+                snapshot.tables.len(),
+                path.display()
+            );
+        } else {
+            txn.abort()?;
+            log::warn!("Database::import_snapshot - Failure");
+        }
+        import_res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let snapshot_path = tmpdir.path().join("snapshot.json");
+
+        let mut db = Database::new(&tmpdir.path().join("source"), Network::Regtest).unwrap();
+        db.put_item("wallet#name", &"value").unwrap();
+        db.put_item(TOKEN_KEY, &"secret").unwrap();
+        let mut wallet_db = Database {
+            internal_db: Arc::clone(&db.internal_db),
+            table_name: Some("wallet".to_owned()),
+            namespace: None,
+        };
+        wallet_db.put_item("sub#u#item", &vec![1, 2, 3]).unwrap();
+
+        db.snapshot(&snapshot_path).unwrap();
+        // Never overwrite an existing file
+        assert!(db.snapshot(&snapshot_path).is_err());
+
+        // Another network
+        let mut other = Database::new(&tmpdir.path().join("target"), Network::Testnet).unwrap();
+        assert!(matches!(
+            other.import_snapshot(&snapshot_path),
+            Err(DbError::InvalidSnapshot(_))
+        ));
+
+        let mut target = Database::new(&tmpdir.path().join("target"), Network::Regtest).unwrap();
+        let res = target.import_snapshot(&snapshot_path);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert_eq!(
+            target.get_item::<String>("wallet#name").unwrap().as_deref(),
+            Some("value")
+        );
+        // The authentication tokens are not exported
+        assert!(target.get_item::<String>(TOKEN_KEY).unwrap().is_none());
+        let target_wallet_db = Database {
+            internal_db: Arc::clone(&target.internal_db),
+            table_name: Some("wallet".to_owned()),
+            namespace: None,
+        };
+        assert_eq!(
+            target_wallet_db.get_item::<Vec<u32>>("sub#u#item").unwrap(),
+            Some(vec![1, 2, 3])
+        );
+
+        // Importing again conflicts with the existing items and imports nothing
+        target.delete_item::<String>("wallet#name").unwrap();
+        assert!(matches!(
+            target.import_snapshot(&snapshot_path),
+            Err(DbError::KeyAlreadyExists(_))
+        ));
+        assert!(target.get_item::<String>("wallet#name").unwrap().is_none());
+    }
+}
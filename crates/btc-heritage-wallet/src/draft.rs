@@ -0,0 +1,338 @@
+//! Drafts: unsigned or partially signed transactions of a [Wallet](crate::Wallet) saved
+//! under a name in the [Database](crate::Database), so that a signing ceremony involving
+//! several devices can span days without keeping PSBT files around.
+//!
+//! Saving a draft again with a PSBT of the same transaction combines their signatures,
+//! see [Wallet::save_draft](crate::Wallet::save_draft).
+
+use btc_heritage::{heritage_wallet::TransactionSummary, PartiallySignedTransaction};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{errors::DbError, DatabaseItem},
+    errors::{Error, Result},
+    Database,
+};
+
+/// A named transaction of a [DatabaseItem], waiting for its signatures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Draft {
+    /// The database key of the draft: the key of its owner followed by `draft_name`
+    name: String,
+    /// The database key of the [DatabaseItem] owning the draft, e.g. `wallet#main`
+    owner: String,
+    draft_name: String,
+    /// The PSBT, stored in base64
+    #[serde(with = "crate::psbt_io::serde_base64")]
+    psbt: PartiallySignedTransaction,
+    tx_summary: TransactionSummary,
+    /// A free text giving the context of the transaction, e.g. who must sign next
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+    created_at: u64,
+    updated_at: u64,
+}
+
+impl Draft {
+    pub fn draft_name(&self) -> &str {
+        &self.draft_name
+    }
+    pub fn psbt(&self) -> &PartiallySignedTransaction {
+        &self.psbt
+    }
+    pub fn into_psbt(self) -> PartiallySignedTransaction {
+        self.psbt
+    }
+    pub fn tx_summary(&self) -> &TransactionSummary {
+        &self.tx_summary
+    }
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+    /// The timestamp of the first save of the draft
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+    /// The timestamp of the last save of the draft
+    pub fn updated_at(&self) -> u64 {
+        self.updated_at
+    }
+
+    /// The `#` of `draft_name` are escaped so that the draft name is always what follows the
+    /// last `#` of the key, and the key cannot collide with the one of a draft of another item
+    /// whose name contains `#`
+    fn draft_key_name<T: DatabaseItem>(item_name: &str, draft_name: &str) -> String {
+        let draft_name = draft_name.replace('%', "%25").replace('#', "%23");
+        format!("{}#{draft_name}", T::name_to_key(item_name))
+    }
+
+    /// Save `psbt` as the draft `draft_name` of the [DatabaseItem] `T` named `item_name`.
+    /// If the draft already exists, `psbt` must be for the same transaction: the signatures
+    /// of both PSBTs are combined and the previous note is kept if `note` is [None].
+    ///
+    /// # Errors
+    /// Returns [Error::DraftTransactionMismatch] if the existing draft is for another transaction
+    pub(crate) fn save_for<T: DatabaseItem>(
+        db: &mut Database,
+        item_name: &str,
+        draft_name: &str,
+        mut psbt: PartiallySignedTransaction,
+        tx_summary: TransactionSummary,
+        note: Option<String>,
+    ) -> Result<Self> {
+        let name = Self::draft_key_name::<T>(item_name, draft_name);
+        let now = btc_heritage::utils::timestamp_now();
+        let draft = match Self::load(db, &name) {
+            Ok(existing) => {
+                if existing.psbt.unsigned_tx.txid() != psbt.unsigned_tx.txid() {
+                    return Err(Error::DraftTransactionMismatch(draft_name.to_owned()));
+                }
+                psbt.combine(existing.psbt)
+                    .map_err(|e| Error::InvalidPsbt(e.to_string()))?;
+                Self {
+                    psbt,
+                    tx_summary,
+                    note: note.or(existing.note),
+                    updated_at: now,
+                    ..existing
+                }
+            }
+            Err(DbError::KeyDoesNotExists(_)) => Self {
+                name,
+                owner: T::name_to_key(item_name),
+                draft_name: draft_name.to_owned(),
+                psbt,
+                tx_summary,
+                note,
+                created_at: now,
+                updated_at: now,
+            },
+            Err(e) => return Err(e.into()),
+        };
+        draft.save(db)?;
+        log::info!("Draft::save_for - {} saved", draft.name);
+        Ok(draft)
+    }
+
+    /// Load the draft `draft_name` of the [DatabaseItem] `T` named `item_name`
+    pub(crate) fn load_for<T: DatabaseItem>(
+        db: &Database,
+        item_name: &str,
+        draft_name: &str,
+    ) -> core::result::Result<Self, DbError> {
+        Self::load(db, &Self::draft_key_name::<T>(item_name, draft_name))
+    }
+
+    /// List the drafts of the [DatabaseItem] `T` named `item_name`, ordered by name
+    pub(crate) fn list_for<T: DatabaseItem>(
+        db: &Database,
+        item_name: &str,
+    ) -> core::result::Result<Vec<Self>, DbError> {
+        let owner = T::name_to_key(item_name);
+        let prefix = Self::db_key(db, &format!("{owner}#"));
+        Ok(db
+            .query::<Self>(&prefix)?
+            .into_iter()
+            // Another item name may start with `item_name#`
+            .filter(|draft| draft.owner == owner)
+            .collect())
+    }
+
+    /// Delete the draft `draft_name` of the [DatabaseItem] `T` named `item_name`,
+    /// returning `true` if it existed
+    pub(crate) fn delete_for<T: DatabaseItem>(
+        db: &mut Database,
+        item_name: &str,
+        draft_name: &str,
+    ) -> core::result::Result<bool, DbError> {
+        let key = Self::db_key(db, &Self::draft_key_name::<T>(item_name, draft_name));
+        Ok(db.delete_item::<Self>(&key)?.is_some())
+    }
+
+    /// Delete all the drafts of the [DatabaseItem] `T` named `item_name`
+    pub(crate) fn delete_all_for<T: DatabaseItem>(
+        db: &mut Database,
+        item_name: &str,
+    ) -> core::result::Result<(), DbError> {
+        for draft in Self::list_for::<T>(db, item_name)? {
+            draft.delete(db)?;
+        }
+        Ok(())
+    }
+
+    /// Move the drafts of the [DatabaseItem] `T` when it is renamed
+    pub(crate) fn rename_for<T: DatabaseItem>(
+        db: &mut Database,
+        old_item_name: &str,
+        new_item_name: &str,
+    ) -> core::result::Result<(), DbError> {
+        for mut draft in Self::list_for::<T>(db, old_item_name)? {
+            draft.owner = T::name_to_key(new_item_name);
+            let new_name = Self::draft_key_name::<T>(new_item_name, &draft.draft_name);
+            draft.db_rename(db, new_name)?;
+        }
+        Ok(())
+    }
+}
+
+crate::database::dbitem::impl_db_item!(Draft, "draft#", "default_draft_name");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KeyProvider, LocalKey, Mnemonic, Wallet};
+    use btc_heritage::{
+        bitcoin::Network,
+        psbttests::{get_test_unsigned_psbt, TestPsbt},
+    };
+
+    fn tx_summary(psbt: &PartiallySignedTransaction) -> TransactionSummary {
+        serde_json::from_value(serde_json::json!({
+            "txid": psbt.unsigned_tx.txid(),
+            "owned_inputs": [],
+            "owned_outputs": [],
+            "fee": 1000,
+            "fee_rate": 250,
+            "parent_txids": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn drafts() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut db = Database::new(tmpdir.path(), Network::Regtest).unwrap();
+        let psbt = get_test_unsigned_psbt(TestPsbt::OwnerRecipients);
+        let summary = tx_summary(&psbt);
+
+        let draft = Draft::save_for::<Wallet>(
+            &mut db,
+            "main",
+            "payroll",
+            psbt.clone(),
+            summary.clone(),
+            Some("Alice signs first".to_owned()),
+        )
+        .unwrap();
+        assert_eq!(draft.draft_name(), "payroll");
+        assert_eq!(draft.created_at(), draft.updated_at());
+        // Another wallet whose name starts like the first one
+        Draft::save_for::<Wallet>(
+            &mut db,
+            "main#2",
+            "payroll",
+            psbt.clone(),
+            summary.clone(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(Draft::list_for::<Wallet>(&db, "main").unwrap().len(), 1);
+
+        // Saving a signed PSBT of the same transaction keeps the note
+        let mut signed_psbt = psbt.clone();
+        LocalKey::restore(
+            Mnemonic::parse(
+                "owner owner owner owner owner owner owner owner owner owner owner panther",
+            )
+            .unwrap(),
+            None,
+            Network::Regtest,
+        )
+        .sign_psbt(&mut signed_psbt)
+        .unwrap();
+        Draft::save_for::<Wallet>(
+            &mut db,
+            "main",
+            "payroll",
+            signed_psbt.clone(),
+            summary.clone(),
+            None,
+        )
+        .unwrap();
+        // Saving the unsigned PSBT again does not lose the signatures
+        Draft::save_for::<Wallet>(&mut db, "main", "payroll", psbt, summary.clone(), None).unwrap();
+        let draft = Draft::load_for::<Wallet>(&db, "main", "payroll").unwrap();
+        assert_eq!(draft.psbt(), &signed_psbt);
+        assert_eq!(draft.note(), Some("Alice signs first"));
+
+        // A draft cannot change of transaction
+        let other_psbt = get_test_unsigned_psbt(TestPsbt::OwnerDrain);
+        assert!(matches!(
+            Draft::save_for::<Wallet>(
+                &mut db,
+                "main",
+                "payroll",
+                other_psbt.clone(),
+                tx_summary(&other_psbt),
+                None
+            ),
+            Err(Error::DraftTransactionMismatch(_))
+        ));
+
+        // The drafts follow the wallet
+        Draft::rename_for::<Wallet>(&mut db, "main", "treasury").unwrap();
+        assert!(Draft::list_for::<Wallet>(&db, "main").unwrap().is_empty());
+        let drafts = Draft::list_for::<Wallet>(&db, "treasury").unwrap();
+        assert_eq!(drafts.len(), 1);
+        assert_eq!(drafts[0].psbt(), &signed_psbt);
+        assert!(Draft::delete_for::<Wallet>(&mut db, "treasury", "payroll").unwrap());
+        assert!(!Draft::delete_for::<Wallet>(&mut db, "treasury", "payroll").unwrap());
+        Draft::delete_all_for::<Wallet>(&mut db, "main#2").unwrap();
+        assert!(Draft::list_for::<Wallet>(&db, "main#2").unwrap().is_empty());
+    }
+
+    #[test]
+    fn draft_names_with_separator() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let mut db = Database::new(tmpdir.path(), Network::Regtest).unwrap();
+        let psbt = get_test_unsigned_psbt(TestPsbt::OwnerRecipients);
+        let other_psbt = get_test_unsigned_psbt(TestPsbt::OwnerDrain);
+
+        // Without escaping, both drafts would be stored under the same key
+        Draft::save_for::<Wallet>(
+            &mut db,
+            "main#2",
+            "payroll",
+            psbt.clone(),
+            tx_summary(&psbt),
+            None,
+        )
+        .unwrap();
+        Draft::save_for::<Wallet>(
+            &mut db,
+            "main",
+            "2#payroll",
+            other_psbt.clone(),
+            tx_summary(&other_psbt),
+            None,
+        )
+        .unwrap();
+        // Nor should an escaped name collide with a literal one
+        Draft::save_for::<Wallet>(
+            &mut db,
+            "main",
+            "2%23payroll",
+            psbt.clone(),
+            tx_summary(&psbt),
+            None,
+        )
+        .unwrap();
+
+        let draft = Draft::load_for::<Wallet>(&db, "main#2", "payroll").unwrap();
+        assert_eq!(draft.psbt(), &psbt);
+        let draft = Draft::load_for::<Wallet>(&db, "main", "2#payroll").unwrap();
+        assert_eq!(draft.draft_name(), "2#payroll");
+        assert_eq!(draft.psbt(), &other_psbt);
+        let draft = Draft::load_for::<Wallet>(&db, "main", "2%23payroll").unwrap();
+        assert_eq!(draft.psbt(), &psbt);
+        assert_eq!(Draft::list_for::<Wallet>(&db, "main").unwrap().len(), 2);
+        assert_eq!(Draft::list_for::<Wallet>(&db, "main#2").unwrap().len(), 1);
+
+        // Renaming keeps the draft names intact
+        Draft::rename_for::<Wallet>(&mut db, "main", "treasury").unwrap();
+        let draft = Draft::load_for::<Wallet>(&db, "treasury", "2#payroll").unwrap();
+        assert_eq!(draft.psbt(), &other_psbt);
+        assert!(Draft::delete_for::<Wallet>(&mut db, "treasury", "2#payroll").unwrap());
+        assert!(Draft::load_for::<Wallet>(&db, "main#2", "payroll").is_ok());
+    }
+}
@@ -1,4 +1,4 @@
-use btc_heritage::AccountXPubId;
+use btc_heritage::{bitcoin::bip32::Fingerprint, AccountXPubId};
 use core::fmt::Debug;
 use thiserror::Error;
 
@@ -14,6 +14,8 @@ pub enum Error {
     IncorrectOnlineWallet(&'static str),
     #[error("This operation cannot be performed because there is no key provider component")]
     MissingKeyProvider,
+    #[error("This operation cannot be performed because the wallet is a read-only observer")]
+    ObserverWallet,
     #[error(
         "This operation cannot be performed because the key provider is not the expected type ({0})"
     )]
@@ -44,8 +46,38 @@ pub enum Error {
     LedgerHeirUnsupported,
     #[error("It is impossible to extract the wallet Mnemonic from a Ledger device")]
     LedgerBackupMnemonicUnsupported,
+    #[error("Missing registered BitBox policy (wanted: {0:?})")]
+    BitBoxMissingRegisteredPolicy(Vec<AccountXPubId>),
+    #[error("HeirConfig from BitBox are not supported because we cannot sign Heir transactions at the moment")]
+    BitBoxHeirUnsupported,
+    #[error("It is impossible to extract the wallet Mnemonic from a BitBox device")]
+    BitBoxBackupMnemonicUnsupported,
+    #[error("An air-gapped key needs at least one account eXtended Public Key")]
+    AirGappedMissingAccountXPubs,
+    #[error("The account eXtended Public Key {0} of the air-gapped key is unknown")]
+    AirGappedMissingAccountXPub(u32),
+    #[error("An air-gapped key cannot sign directly, export the PSBT to the device and import the signed PSBT")]
+    AirGappedSigningRequired,
+    #[error("The signed PSBT is not for the same transaction")]
+    AirGappedPsbtMismatch,
+    #[error("The signed PSBT contains an invalid or unexpected signature for input #{0}")]
+    AirGappedInvalidSignature(usize),
+    #[error("HeirConfig from air-gapped keys are not supported because we cannot sign Heir transactions with them")]
+    AirGappedHeirUnsupported,
+    #[error("It is impossible to extract the wallet Mnemonic from an air-gapped device")]
+    AirGappedBackupMnemonicUnsupported,
+    #[error("No device with fingerprint {0} is connected")]
+    HwiDeviceNotFound(Fingerprint),
+    #[error("The PSBT signed by HWI is not for the same transaction")]
+    HwiPsbtMismatch,
+    #[error("HeirConfig from HWI devices are not supported because we cannot sign Heir transactions at the moment")]
+    HwiHeirUnsupported,
+    #[error("It is impossible to extract the wallet Mnemonic from an HWI device")]
+    HwiBackupMnemonicUnsupported,
     #[error("The account derivation index {0} is too big (max 2^31-1)")]
     AccountDerivationIndexOutOfBound(u32),
+    #[error("Silent payment error: {0}")]
+    SilentPaymentError(String),
     #[error("No wallet found in the service")]
     NoServiceWalletFound,
     #[error("Multiple wallets found in the service")]
@@ -62,8 +94,43 @@ pub enum Error {
     UninitializedServiceClient,
     #[error("No Ledger Client has been provided to perform this operation")]
     UninitializedLedgerClient,
+    #[error("The wallet fingerprint on the connected BitBox is not the one stored in the local database")]
+    IncoherentBitBoxWalletFingerprint,
+    #[error("No BitBox Client has been provided to perform this operation")]
+    UninitializedBitBoxClient,
     #[error("The retrieved wallet fingerprint is not the one stored in the local database. Wrong password.")]
     IncoherentLocalKeyFingerprint,
+    #[error("Invalid SLIP-39 shares: {0}")]
+    InvalidSlip39Shares(String),
+    #[error("The passphrase derives the wallet {actual} instead of the expected {expected}, it is most likely mistyped")]
+    LocalKeyFingerprintMismatch {
+        expected: Fingerprint,
+        actual: Fingerprint,
+    },
+    #[error("The LocalKey keystore is locked, unlock it with its keystore password first")]
+    LocalKeyLocked,
+    #[error("Cannot decrypt the LocalKey keystore, the keystore password is wrong or the keystore is corrupted")]
+    LocalKeyKeystoreDecryptionFailed,
+    #[error("LocalKey keystore error: {0}")]
+    LocalKeyKeystoreError(String),
+    #[error("The heir {0} does not appear in the descriptors of the Heritage wallet backup")]
+    HeirNotInBackup(Fingerprint),
+    #[error("Nothing to claim yet{}", .next_maturity.map(|ts| format!(", the next heritage matures at timestamp {ts}")).unwrap_or_default())]
+    NothingToClaim { next_maturity: Option<u64> },
+    #[error(
+        "The key provider did not sign the claim transaction, it does not hold the key of the heir"
+    )]
+    HeirKeyMissing,
+    #[error("Invalid heir metadata: {0}")]
+    InvalidHeirMetadata(String),
+    #[error("The blockchain backend is unavailable: {0}")]
+    BackendUnavailable(String),
+    #[error("Spend guardrail violated: {0}")]
+    GuardrailViolation(crate::guardrails::GuardrailViolation),
+    #[error("Invalid PSBT: {0}")]
+    InvalidPsbt(String),
+    #[error("The draft {0} is for another transaction")]
+    DraftTransactionMismatch(String),
     #[error("Heritage error: {source}")]
     HeritageError {
         #[from]
@@ -91,6 +158,12 @@ pub enum Error {
     },
     #[error("Ledger client error: {0}")]
     LedgerClientError(String),
+    #[error("HWI error: {0}")]
+    HwiError(String),
+    #[error("BitBox client error: {0}")]
+    BitBoxClientError(String),
+    #[error("The key provider self-test failed: {0}")]
+    KeyProviderSelfTestFailed(String),
     #[error("Generic error: {0}")]
     Generic(String),
 }
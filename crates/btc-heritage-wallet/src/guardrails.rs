@@ -0,0 +1,322 @@
+//! Spend guardrails: rules checked before a key provider signs a PSBT, such as
+//! "never sign more than X BTC within 24h" or "only send to these addresses".
+//!
+//! The [SpendGuardrails] of a [Wallet](crate::Wallet) or an [HeirWallet](crate::HeirWallet)
+//! are persisted in the [Database](crate::Database) and enforced by their `sign_psbt_guarded`
+//! method. Each rule can be explicitly bypassed for a single signature using [GuardrailOverrides].
+
+use btc_heritage::{
+    bitcoin::{address::NetworkUnchecked, bip32::Fingerprint, Address, Amount, ScriptBuf},
+    PartiallySignedTransaction,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{errors::DbError, DatabaseItem},
+    errors::{Error, Result},
+    key_provider::KeyProvider,
+    BoundFingerprint,
+};
+
+/// A maximum amount that can be signed within a rolling time window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendLimit {
+    #[serde(with = "btc_heritage::bitcoin::amount::serde::as_sat")]
+    pub amount: Amount,
+    /// The duration of the window, in seconds
+    pub window_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct SignedSpend {
+    timestamp: u64,
+    #[serde(with = "btc_heritage::bitcoin::amount::serde::as_sat")]
+    amount: Amount,
+}
+
+/// A rule of the [SpendGuardrails] that a PSBT does not respect
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum GuardrailViolation {
+    #[error("sending {amount} would exceed the limit of {limit} per {window_secs} seconds ({already_spent} already signed)")]
+    SpendLimitExceeded {
+        amount: Amount,
+        already_spent: Amount,
+        limit: Amount,
+        window_secs: u64,
+    },
+    #[error("the output script {0} does not belong to an address of the allowlist")]
+    AddressNotAllowed(String),
+}
+
+/// Explicitly bypass some rules of the [SpendGuardrails] for a single signature
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GuardrailOverrides {
+    pub ignore_spend_limit: bool,
+    pub ignore_address_allowlist: bool,
+}
+
+/// The spending rules of a wallet, checked before signing a PSBT
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpendGuardrails {
+    /// The database key of the guarded wallet
+    name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    spend_limit: Option<SpendLimit>,
+    /// If not empty, the only addresses the PSBTs may send funds to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    allowed_addresses: Vec<String>,
+    /// The amounts signed within the window of the spend limit
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    history: Vec<SignedSpend>,
+}
+
+impl SpendGuardrails {
+    /// Load the [SpendGuardrails] of the [DatabaseItem] `T` named `item_name`,
+    /// or empty guardrails if none were saved
+    pub fn load_for<T: DatabaseItem>(
+        db: &crate::Database,
+        item_name: &str,
+    ) -> core::result::Result<Self, DbError> {
+        let name = T::name_to_key(item_name);
+        match Self::load(db, &name) {
+            Ok(guardrails) => Ok(guardrails),
+            Err(DbError::KeyDoesNotExists(_)) => Ok(Self {
+                name,
+                ..Default::default()
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delete the [SpendGuardrails] of the [DatabaseItem] `T` named `item_name`, if any
+    pub(crate) fn delete_for<T: DatabaseItem>(
+        db: &mut crate::Database,
+        item_name: &str,
+    ) -> core::result::Result<(), DbError> {
+        db.delete_item::<Self>(&Self::db_key(db, &T::name_to_key(item_name)))?;
+        Ok(())
+    }
+
+    /// Move the [SpendGuardrails] of the [DatabaseItem] `T` when it is renamed
+    pub(crate) fn rename_for<T: DatabaseItem>(
+        db: &mut crate::Database,
+        old_item_name: &str,
+        new_item_name: &str,
+    ) -> core::result::Result<(), DbError> {
+        let key = Self::db_key(db, &T::name_to_key(old_item_name));
+        if db.contains_key(&key)? {
+            Self::load_for::<T>(db, old_item_name)?.db_rename(db, T::name_to_key(new_item_name))?;
+        }
+        Ok(())
+    }
+
+    pub fn spend_limit(&self) -> Option<SpendLimit> {
+        self.spend_limit
+    }
+    pub fn set_spend_limit(&mut self, spend_limit: Option<SpendLimit>) {
+        self.spend_limit = spend_limit;
+    }
+
+    pub fn allowed_addresses(&self) -> &[String] {
+        &self.allowed_addresses
+    }
+    /// Add `address` to the allowlist. Once the allowlist is not empty, every PSBT
+    /// sending to another address is refused.
+    ///
+    /// # Errors
+    /// Returns an error if `address` is not a valid Bitcoin address
+    pub fn allow_address(&mut self, address: &str) -> Result<()> {
+        address
+            .parse::<Address<NetworkUnchecked>>()
+            .map_err(Error::generic)?;
+        if !self.allowed_addresses.iter().any(|a| a == address) {
+            self.allowed_addresses.push(address.to_owned());
+        }
+        Ok(())
+    }
+    /// Remove `address` from the allowlist, returning `true` if it was present
+    pub fn disallow_address(&mut self, address: &str) -> bool {
+        let len = self.allowed_addresses.len();
+        self.allowed_addresses.retain(|a| a != address);
+        len != self.allowed_addresses.len()
+    }
+
+    /// Verify that `psbt`, to be signed at `timestamp` by the owner of `fingerprint`,
+    /// respects the guardrails, except for the rules bypassed by `overrides`.
+    ///
+    /// The outputs carrying a key origin of `fingerprint` are considered as change and
+    /// ignored. The others are sent out of the wallet.
+    ///
+    /// # Errors
+    /// Returns [Error::GuardrailViolation] for the first rule that `psbt` does not respect
+    pub fn check(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        fingerprint: Fingerprint,
+        timestamp: u64,
+        overrides: GuardrailOverrides,
+    ) -> Result<()> {
+        if !overrides.ignore_address_allowlist && !self.allowed_addresses.is_empty() {
+            let allowed_scripts = self
+                .allowed_addresses
+                .iter()
+                .filter_map(|a| a.parse::<Address<NetworkUnchecked>>().ok())
+                .map(|a| a.assume_checked().script_pubkey())
+                .collect::<Vec<ScriptBuf>>();
+            for (tx_out, _) in sent_out(psbt, fingerprint) {
+                if !allowed_scripts.contains(&tx_out.script_pubkey) {
+                    return Err(Error::GuardrailViolation(
+                        GuardrailViolation::AddressNotAllowed(tx_out.script_pubkey.to_string()),
+                    ));
+                }
+            }
+        }
+        if let Some(limit) = self.spend_limit.filter(|_| !overrides.ignore_spend_limit) {
+            let amount = sent_out_amount(psbt, fingerprint);
+            let already_spent = self.spent_within(limit.window_secs, timestamp);
+            if already_spent + amount > limit.amount {
+                return Err(Error::GuardrailViolation(
+                    GuardrailViolation::SpendLimitExceeded {
+                        amount,
+                        already_spent,
+                        limit: limit.amount,
+                        window_secs: limit.window_secs,
+                    },
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check `psbt` then sign it with `key_provider`. The amount sent out is recorded
+    /// in the history of the spend limit: the [SpendGuardrails] must be saved afterward.
+    pub fn sign_psbt<K: KeyProvider + ?Sized>(
+        &mut self,
+        key_provider: &K,
+        psbt: &mut PartiallySignedTransaction,
+        overrides: GuardrailOverrides,
+    ) -> Result<usize> {
+        let fingerprint = key_provider.fingerprint()?;
+        let now = btc_heritage::utils::timestamp_now();
+        self.check(psbt, fingerprint, now, overrides)?;
+        let signed = key_provider.sign_psbt(psbt)?;
+        if signed > 0 {
+            let window_secs = self.spend_limit.map(|l| l.window_secs).unwrap_or_default();
+            self.history.retain(|ss| ss.timestamp + window_secs > now);
+            self.history.push(SignedSpend {
+                timestamp: now,
+                amount: sent_out_amount(psbt, fingerprint),
+            });
+        }
+        Ok(signed)
+    }
+
+    fn spent_within(&self, window_secs: u64, timestamp: u64) -> Amount {
+        self.history
+            .iter()
+            .filter(|ss| ss.timestamp + window_secs > timestamp)
+            .map(|ss| ss.amount)
+            .sum()
+    }
+}
+
+/// The outputs of `psbt` that are not change outputs of `fingerprint`
+fn sent_out(
+    psbt: &PartiallySignedTransaction,
+    fingerprint: Fingerprint,
+) -> impl Iterator<
+    Item = (
+        &btc_heritage::bitcoin::TxOut,
+        &btc_heritage::bitcoin::psbt::Output,
+    ),
+> {
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .zip(psbt.outputs.iter())
+        .filter(move |(_, psbt_out)| {
+            !psbt_out
+                .tap_key_origins
+                .values()
+                .any(|(_, (fp, _))| *fp == fingerprint)
+        })
+}
+
+fn sent_out_amount(psbt: &PartiallySignedTransaction, fingerprint: Fingerprint) -> Amount {
+    sent_out(psbt, fingerprint)
+        .map(|(tx_out, _)| Amount::from_sat(tx_out.value))
+        .sum()
+}
+
+crate::database::dbitem::impl_db_item!(SpendGuardrails, "guardrails#", "default_guardrails_name");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btc_heritage::psbttests::{get_test_unsigned_psbt, TestPsbt};
+
+    #[test]
+    fn check_guardrails() {
+        let psbt = get_test_unsigned_psbt(TestPsbt::BackupPresent);
+        // Heir PSBTs do not have change
+        let fingerprint = Fingerprint::from([0u8; 4]);
+        let amount = sent_out_amount(&psbt, fingerprint);
+        assert!(amount > Amount::ZERO);
+
+        let mut guardrails = SpendGuardrails::default();
+        assert!(guardrails
+            .check(&psbt, fingerprint, 0, GuardrailOverrides::default())
+            .is_ok());
+
+        guardrails.set_spend_limit(Some(SpendLimit {
+            amount,
+            window_secs: 3600,
+        }));
+        assert!(guardrails
+            .check(&psbt, fingerprint, 0, GuardrailOverrides::default())
+            .is_ok());
+        guardrails.history.push(SignedSpend {
+            timestamp: 1000,
+            amount: Amount::from_sat(1),
+        });
+        assert!(matches!(
+            guardrails.check(&psbt, fingerprint, 1000, GuardrailOverrides::default()),
+            Err(Error::GuardrailViolation(
+                GuardrailViolation::SpendLimitExceeded { .. }
+            ))
+        ));
+        // Out of the window
+        assert!(guardrails
+            .check(&psbt, fingerprint, 4600, GuardrailOverrides::default())
+            .is_ok());
+        let overrides = GuardrailOverrides {
+            ignore_spend_limit: true,
+            ..Default::default()
+        };
+        assert!(guardrails
+            .check(&psbt, fingerprint, 1000, overrides)
+            .is_ok());
+
+        guardrails
+            .allow_address("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+            .unwrap();
+        assert!(guardrails.allow_address("not an address").is_err());
+        assert!(matches!(
+            guardrails.check(&psbt, fingerprint, 1000, overrides),
+            Err(Error::GuardrailViolation(
+                GuardrailViolation::AddressNotAllowed(_)
+            ))
+        ));
+        assert!(guardrails
+            .check(
+                &psbt,
+                fingerprint,
+                1000,
+                GuardrailOverrides {
+                    ignore_spend_limit: true,
+                    ignore_address_allowlist: true
+                }
+            )
+            .is_ok());
+    }
+}
@@ -1,18 +1,112 @@
+use std::collections::BTreeSet;
+
 use btc_heritage::{bitcoin::bip32::Fingerprint, HeirConfig};
+use heritage_service_api_client::EmailAddress;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     database::DatabaseItem,
-    errors::Result,
+    errors::{Error, Result},
     key_provider::{AnyKeyProvider, KeyProvider},
     BoundFingerprint,
 };
 
+/// How to reach an [Heir]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeirContactInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<EmailAddress>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone: Option<String>,
+    /// A BCP 47 language tag, e.g. `en` or `fr-FR`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preferred_language: Option<String>,
+}
+
+/// A channel through which an [Heir] can be notified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationChannel {
+    Email,
+    Phone,
+}
+
+/// How an [Heir] wishes to be notified about their inheritance
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NotificationPreferences {
+    /// The channels to use, each requiring the matching [HeirContactInfo]
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub channels: BTreeSet<NotificationChannel>,
+    /// Remind the heir this many days before their inheritance matures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maturity_reminder_days: Option<u16>,
+}
+
+/// The estate documentation kept about an [Heir], alongside its keys
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeirMetadata {
+    #[serde(default)]
+    pub contact: HeirContactInfo,
+    #[serde(default)]
+    pub notifications: NotificationPreferences,
+    /// Free-form notes of the owner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+impl HeirMetadata {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Verify that the phone number and the language tag are well-formed and that
+    /// every notification channel has the matching contact information
+    pub fn validate(&self) -> Result<()> {
+        if let Some(phone) = &self.contact.phone {
+            let valid_chars = phone
+                .chars()
+                .all(|c| c.is_ascii_digit() || " +-.()".contains(c));
+            let digits = phone.chars().filter(char::is_ascii_digit).count();
+            if !valid_chars || digits < 4 {
+                return Err(Error::InvalidHeirMetadata(format!(
+                    "{phone} is not a valid phone number"
+                )));
+            }
+        }
+        if let Some(language) = &self.contact.preferred_language {
+            let valid = !language.is_empty()
+                && language.split('-').all(|subtag| {
+                    (1..=8).contains(&subtag.len())
+                        && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+                });
+            if !valid {
+                return Err(Error::InvalidHeirMetadata(format!(
+                    "{language} is not a valid language tag"
+                )));
+            }
+        }
+        for channel in self.notifications.channels.iter() {
+            let has_contact = match channel {
+                NotificationChannel::Email => self.contact.email.is_some(),
+                NotificationChannel::Phone => self.contact.phone.is_some(),
+            };
+            if !has_contact {
+                return Err(Error::InvalidHeirMetadata(format!(
+                    "the {channel:?} notification channel requires the matching contact information"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Heir {
     pub name: String,
     pub heir_config: HeirConfig,
     key_provider: AnyKeyProvider,
+    #[serde(default, skip_serializing_if = "HeirMetadata::is_empty")]
+    metadata: HeirMetadata,
 }
 
 impl Heir {
@@ -21,8 +115,23 @@ impl Heir {
             name,
             heir_config,
             key_provider,
+            metadata: HeirMetadata::default(),
         }
     }
+
+    pub fn metadata(&self) -> &HeirMetadata {
+        &self.metadata
+    }
+
+    /// Replace the [HeirMetadata] of the heir. Call [DatabaseItem::save] to persist it.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidHeirMetadata] if `metadata` is invalid, see [HeirMetadata::validate]
+    pub fn set_metadata(&mut self, metadata: HeirMetadata) -> Result<()> {
+        metadata.validate()?;
+        self.metadata = metadata;
+        Ok(())
+    }
 }
 crate::database::dbitem::impl_db_item!(Heir, "heir#", "default_heir_name");
 
@@ -33,3 +142,48 @@ impl BoundFingerprint for Heir {
         Ok(self.heir_config.fingerprint())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heir_metadata_validation() {
+        let mut metadata = HeirMetadata {
+            contact: HeirContactInfo {
+                email: Some(EmailAddress::try_from("heir@example.com").unwrap()),
+                phone: Some("+33 (0)6 12-34-56-78".to_owned()),
+                preferred_language: Some("fr-FR".to_owned()),
+            },
+            notifications: NotificationPreferences {
+                channels: BTreeSet::from([NotificationChannel::Email, NotificationChannel::Phone]),
+                maturity_reminder_days: Some(30),
+            },
+            notes: Some("Safe deposit box #12".to_owned()),
+        };
+        assert!(metadata.validate().is_ok());
+        assert!(HeirMetadata::default().validate().is_ok());
+
+        // A notification channel without the matching contact
+        metadata.contact.phone = None;
+        assert!(matches!(
+            metadata.validate(),
+            Err(Error::InvalidHeirMetadata(_))
+        ));
+        metadata
+            .notifications
+            .channels
+            .remove(&NotificationChannel::Phone);
+        assert!(metadata.validate().is_ok());
+
+        for phone in ["123", "+33 6 abc", "phone"] {
+            metadata.contact.phone = Some(phone.to_owned());
+            assert!(metadata.validate().is_err(), "{phone}");
+        }
+        metadata.contact.phone = None;
+        for language in ["", "fr_FR", "en-", "toolongsubtag"] {
+            metadata.contact.preferred_language = Some(language.to_owned());
+            assert!(metadata.validate().is_err(), "{language}");
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{Read, Write};
+
+use btc_heritage::{
+    bitcoin::bip32::Fingerprint,
+    heritage_wallet::{backup::EncryptedHeritageWalletBackup, InheritanceSchedule},
+    utils::timestamp_now,
+    HeirConfig, HeritageWalletBackup,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{Error, Result};
+
+/// Current version of the [HeirBundle] format
+const HEIR_BUNDLE_VERSION: u32 = 1;
+
+/// Everything an Heir needs to claim an inheritance without the Heritage service:
+/// the encrypted descriptors backup of the Heritage wallet, instructions from the owner
+/// and the maturity schedule of the Heir at the time of the export.
+///
+/// It is created with [crate::Wallet::export_heir_bundle] and imported with
+/// [crate::HeirWallet::import_heir_bundle]. The passphrase of the backup should be
+/// transmitted to the Heir separately.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeirBundle {
+    version: u32,
+    /// The fingerprint of the Heir this bundle is for
+    pub heir_fingerprint: Fingerprint,
+    /// The timestamp of the export
+    pub created_at: u64,
+    /// Free-form instructions for the Heir
+    pub instructions: String,
+    /// The events of the [InheritanceSchedule] of the Heir at the time of the export.
+    /// The schedule becomes stale if the owner moves the funds afterward.
+    pub schedule: InheritanceSchedule,
+    /// The descriptors backup of the Heritage wallet
+    pub encrypted_backup: EncryptedHeritageWalletBackup,
+}
+
+impl HeirBundle {
+    /// Create a new [HeirBundle] for the Heir with `heir_config`, encrypting `backup`
+    /// with `passphrase`. The `schedule` is restricted to the events of the Heir.
+    ///
+    /// If `instructions` is [None], generic instructions are generated.
+    pub fn new(
+        heir_config: &HeirConfig,
+        backup: &HeritageWalletBackup,
+        passphrase: &str,
+        schedule: &InheritanceSchedule,
+        instructions: Option<String>,
+    ) -> Result<Self> {
+        let heir_fingerprint = heir_config.fingerprint();
+        let schedule = schedule.for_heir(heir_config);
+        let instructions =
+            instructions.unwrap_or_else(|| default_instructions(heir_fingerprint, &schedule));
+        Ok(Self {
+            version: HEIR_BUNDLE_VERSION,
+            heir_fingerprint,
+            created_at: timestamp_now(),
+            instructions,
+            schedule,
+            encrypted_backup: EncryptedHeritageWalletBackup::encrypt(backup, passphrase)?,
+        })
+    }
+
+    /// Decrypt the [HeritageWalletBackup] of the bundle
+    ///
+    /// # Errors
+    /// Returns an error if the passphrase is wrong or if the bundle was tampered with
+    pub fn decrypt_backup(&self, passphrase: &str) -> Result<HeritageWalletBackup> {
+        Ok(self.encrypted_backup.decrypt(passphrase)?)
+    }
+
+    /// Serialize the bundle to `writer`, as JSON
+    pub fn write_to(&self, writer: impl Write) -> Result<()> {
+        Ok(serde_json::to_writer_pretty(writer, self)?)
+    }
+
+    /// Deserialize a bundle from `reader`
+    ///
+    /// # Errors
+    /// Returns an error if the content is not a bundle or if its version is not supported
+    pub fn read_from(reader: impl Read) -> Result<Self> {
+        let bundle: Self = serde_json::from_reader(reader)?;
+        if bundle.version != HEIR_BUNDLE_VERSION {
+            return Err(Error::Generic(format!(
+                "Unsupported heir bundle version: {}",
+                bundle.version
+            )));
+        }
+        Ok(bundle)
+    }
+}
+
+fn default_instructions(heir_fingerprint: Fingerprint, schedule: &InheritanceSchedule) -> String {
+    let mut instructions = format!(
+        "This bundle allows the holder of the key with fingerprint {heir_fingerprint} \
+        to claim an inheritance without relying on the Heritage service.\n\
+        1. Restore your Heir wallet with your seed.\n\
+        2. Import this bundle with the passphrase given to you separately.\n\
+        3. Claim the funds once they are mature.\n"
+    );
+    match schedule.events.first() {
+        Some(event) => instructions.push_str(&format!(
+            "At the time of the export, the first funds were expected to become claimable \
+            around the timestamp {}.\n",
+            event.estimated_timestamp
+        )),
+        None => instructions
+            .push_str("At the time of the export, no funds were claimable by this heir.\n"),
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_provider::HeirConfigType, KeyProvider, LocalKey, Mnemonic};
+    use btc_heritage::{
+        bitcoin::Network, heritage_config::v1::Heritage, subwallet_config::SubwalletConfig,
+        HeritageConfig,
+    };
+
+    const MNEMONIC: &str =
+        "owner owner owner owner owner owner owner owner owner owner owner panther";
+
+    #[test]
+    fn bundle_roundtrip() {
+        let owner = LocalKey::restore(Mnemonic::parse(MNEMONIC).unwrap(), None, Network::Regtest);
+        let heir = LocalKey::restore(
+            Mnemonic::parse(MNEMONIC).unwrap(),
+            Some("heir".to_owned()),
+            Network::Regtest,
+        );
+        let heir_config = heir
+            .derive_heir_config(HeirConfigType::HeirXPubkey)
+            .unwrap();
+        let subwallet_config = SubwalletConfig::new(
+            owner.derive_accounts_xpubs(0..1).unwrap().remove(0),
+            HeritageConfig::builder_v1()
+                .add_heritage(Heritage::new(heir_config.clone()).time_lock(360))
+                .reference_time(1_700_000_000)
+                .minimum_lock_time(90)
+                .build(),
+        );
+        let backup =
+            HeritageWalletBackup::from_descriptors(&subwallet_config.ext_descriptor().to_string())
+                .unwrap();
+
+        let bundle = HeirBundle::new(
+            &heir_config,
+            &backup,
+            "passphrase",
+            &InheritanceSchedule::default(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(bundle.heir_fingerprint, heir.fingerprint().unwrap());
+        assert!(bundle
+            .instructions
+            .contains(&heir_config.fingerprint().to_string()));
+
+        let mut file = Vec::new();
+        bundle.write_to(&mut file).unwrap();
+        // The descriptors do not appear in the bundle
+        assert!(!String::from_utf8(file.clone()).unwrap().contains("tpub"));
+        let bundle = HeirBundle::read_from(file.as_slice()).unwrap();
+        assert_eq!(bundle.decrypt_backup("passphrase").unwrap(), backup);
+        assert!(bundle.decrypt_backup("wrong passphrase").is_err());
+    }
+}
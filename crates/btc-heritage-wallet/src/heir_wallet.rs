@@ -1,13 +1,43 @@
+use btc_heritage::{
+    bitcoin::{Address, Network, Transaction, Txid},
+    heritage_wallet::TransactionSummary,
+    miniscript::ForEachKey,
+    HeritageWalletBackup, PartiallySignedTransaction,
+};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     database::DatabaseItem,
     errors::{Error, Result},
-    heritage_provider::AnyHeritageProvider,
+    heir_bundle::HeirBundle,
+    heritage_provider::{AnyHeritageProvider, ClaimBatch, HeirDiscoveryReport, LocalWallet},
     key_provider::{AnyKeyProvider, KeyProvider},
-    BoundFingerprint, Broadcaster, Heritage, HeritageProvider,
+    online_wallet::AnyBlockchainFactory,
+    BoundFingerprint, Broadcaster, Database, GuardrailOverrides, Heritage, HeritageProvider,
+    SpendGuardrails,
 };
 
+/// The outcome of [HeirWallet::claim]
+#[derive(Debug, Clone)]
+pub struct HeirClaim {
+    pub txid: Txid,
+    /// The signed and finalized claim transaction
+    pub transaction: Transaction,
+    pub summary: TransactionSummary,
+    /// `true` if the transaction was broadcasted
+    pub broadcasted: bool,
+}
+
+/// A claim transaction of an Heir, signed in advance, see [HeirWallet::prepare_claims]
+#[derive(Debug, Clone)]
+pub struct PreparedClaim {
+    pub batch: ClaimBatch,
+    /// The signed and finalized claim transaction, that can be broadcasted once
+    /// the batch is mature
+    pub transaction: Transaction,
+    pub summary: TransactionSummary,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct HeirWallet {
     name: String,
@@ -36,6 +66,202 @@ impl HeirWallet {
             heritage_provider,
         })
     }
+
+    /// Discover the funds that the Heir can claim from the [HeritageWalletBackup] of an
+    /// Heritage wallet, e.g. restored with [HeritageWalletBackup::from_descriptors], without
+    /// needing the database of the owner.
+    ///
+    /// The backup is restored in a [LocalWallet] for the `network` that becomes the heritage
+    /// provider of the [HeirWallet], then synchronized using `blockchain_factory`. The returned report lists
+    /// the UTXOs the Heir can claim, with their maturity.
+    ///
+    /// # Errors
+    /// Returns an error if the [HeirWallet] has no key provider or already has a heritage
+    /// provider, if the Heir does not appear in the backup or if the synchronization fails
+    pub fn discover_from_backup(
+        &mut self,
+        db: &mut Database,
+        backup: HeritageWalletBackup,
+        network: Network,
+        blockchain_factory: AnyBlockchainFactory,
+    ) -> Result<HeirDiscoveryReport> {
+        let fingerprint = self.key_provider.fingerprint()?;
+        if !self.heritage_provider.is_none() {
+            return Err(Error::Generic(
+                "The heir wallet already has a heritage provider".to_owned(),
+            ));
+        }
+        if !backup.clone().into_iter().any(|sdb| {
+            sdb.external_descriptor
+                .for_any_key(|k| k.master_fingerprint() == fingerprint)
+        }) {
+            return Err(Error::HeirNotInBackup(fingerprint));
+        }
+
+        let mut local_wallet = LocalWallet::create(fingerprint, db, backup, network)?;
+        let report = local_wallet
+            .local_heritage_wallet_mut()
+            .init_blockchain_factory(blockchain_factory)
+            .and_then(|_| local_wallet.discover());
+        match report {
+            Ok(report) => {
+                self.heritage_provider = AnyHeritageProvider::LocalWallet(local_wallet);
+                Ok(report)
+            }
+            Err(e) => {
+                // Do not leave the restored wallet behind
+                local_wallet.local_heritage_wallet().delete(db)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Import an [HeirBundle] exported by the owner with [crate::Wallet::export_heir_bundle]:
+    /// its backup is decrypted with `passphrase` then used by [HeirWallet::discover_from_backup].
+    ///
+    /// # Errors
+    /// Returns [Error::IncoherentFingerprints] if the bundle is for another Heir, an error if
+    /// the passphrase is wrong, or any error of [HeirWallet::discover_from_backup]
+    pub fn import_heir_bundle(
+        &mut self,
+        db: &mut Database,
+        bundle: &HeirBundle,
+        passphrase: &str,
+        network: Network,
+        blockchain_factory: AnyBlockchainFactory,
+    ) -> Result<HeirDiscoveryReport> {
+        if self.key_provider.fingerprint()? != bundle.heir_fingerprint {
+            return Err(Error::IncoherentFingerprints);
+        }
+        let backup = bundle.decrypt_backup(passphrase)?;
+        self.discover_from_backup(db, backup, network, blockchain_factory)
+    }
+
+    /// Sign `psbt` with the key provider of the heir wallet after checking it against the
+    /// [SpendGuardrails] of the heir wallet, which are updated in `db`.
+    ///
+    /// # Errors
+    /// Returns [Error::GuardrailViolation] if `psbt` does not respect a rule that
+    /// `overrides` does not bypass, without signing it
+    pub fn sign_psbt_guarded(
+        &self,
+        db: &mut Database,
+        psbt: &mut PartiallySignedTransaction,
+        overrides: GuardrailOverrides,
+    ) -> Result<usize> {
+        let mut guardrails = SpendGuardrails::load_for::<Self>(db, self.name())?;
+        let signed = guardrails.sign_psbt(&self.key_provider, psbt, overrides)?;
+        guardrails.save(db)?;
+        Ok(signed)
+    }
+
+    /// Claim the mature heritages of the Heir in one call: discover the heritages,
+    /// create the transaction draining them to `drain_to`, sign it with the key provider,
+    /// finalize it and, if `broadcast` is `true`, broadcast it.
+    ///
+    /// If the heritage provider is a [LocalWallet], it is synchronized first.
+    ///
+    /// # Errors
+    /// - [Error::MissingKeyProvider] or [Error::MissingHeritageProvider] if a component is missing
+    /// - [Error::BackendUnavailable] if the heritages cannot be discovered
+    /// - [Error::NothingToClaim] if no heritage is mature yet
+    /// - [Error::HeirKeyMissing] if the key provider cannot sign the transaction
+    /// - [Error::HeritageError] if the time locks of the transaction are inconsistent, see
+    ///   [btc_heritage::utils::finalize_heir_psbt]
+    /// - the broadcast error, if the transaction is rejected
+    ///
+    /// # Panics
+    /// Panics if the heritage provider is a [LocalWallet] whose blockchain factory was not initialized
+    pub fn claim(&mut self, drain_to: Address, broadcast: bool) -> Result<HeirClaim> {
+        if self.key_provider.is_none() {
+            return Err(Error::MissingKeyProvider);
+        }
+        let heritages = match &mut self.heritage_provider {
+            AnyHeritageProvider::None => return Err(Error::MissingHeritageProvider),
+            AnyHeritageProvider::LocalWallet(local_wallet) => local_wallet
+                .discover()
+                .and_then(|_| local_wallet.list_heritages()),
+            heritage_provider => heritage_provider.list_heritages(),
+        }
+        .map_err(|e| Error::BackendUnavailable(e.to_string()))?;
+
+        let now = btc_heritage::utils::timestamp_now();
+        let Some(heritage) = heritages.iter().find(|h| h.maturity <= now) else {
+            return Err(Error::NothingToClaim {
+                next_maturity: heritages.iter().map(|h| h.maturity).min(),
+            });
+        };
+        let (mut psbt, summary) = self
+            .heritage_provider
+            .create_psbt(&heritage.heritage_id, drain_to)?;
+
+        if self.key_provider.sign_psbt(&mut psbt)? == 0 {
+            return Err(Error::HeirKeyMissing);
+        }
+        let transaction = btc_heritage::utils::finalize_heir_psbt(
+            psbt.clone(),
+            now,
+            btc_heritage::utils::DEFAULT_MAX_NON_FINAL_DAYS,
+        )?;
+        let txid = transaction.txid();
+        if broadcast {
+            self.heritage_provider.broadcast(psbt)?;
+        }
+        Ok(HeirClaim {
+            txid,
+            transaction,
+            summary,
+            broadcasted: broadcast,
+        })
+    }
+
+    /// Prepare, in one signing session, the claim transactions of every batch of UTXOs of the
+    /// Heir, including the batches that will only mature in the future. Each transaction is
+    /// time-locked until the maturity of its batch and can be broadcasted from then on.
+    ///
+    /// The heritage provider is synchronized first and must be a [LocalWallet], see
+    /// [LocalWallet::create_claim_batch_psbts].
+    ///
+    /// # Errors
+    /// - [Error::MissingKeyProvider] if there is no key provider
+    /// - [Error::IncorrectHeritageProvider] if the heritage provider is not a [LocalWallet]
+    /// - [Error::BackendUnavailable] if the synchronization fails
+    /// - [Error::NothingToClaim] if there is no confirmed UTXO to claim
+    /// - [Error::HeirKeyMissing] if the key provider cannot sign a transaction
+    ///
+    /// # Panics
+    /// Panics if the blockchain factory of the [LocalWallet] was not initialized
+    pub fn prepare_claims(&mut self, drain_to: Address) -> Result<Vec<PreparedClaim>> {
+        if self.key_provider.is_none() {
+            return Err(Error::MissingKeyProvider);
+        }
+        let AnyHeritageProvider::LocalWallet(local_wallet) = &mut self.heritage_provider else {
+            return Err(Error::IncorrectHeritageProvider("LocalWallet"));
+        };
+        local_wallet
+            .discover()
+            .map_err(|e| Error::BackendUnavailable(e.to_string()))?;
+        let batch_psbts = local_wallet.create_claim_batch_psbts(drain_to)?;
+        if batch_psbts.is_empty() {
+            return Err(Error::NothingToClaim {
+                next_maturity: None,
+            });
+        }
+
+        batch_psbts
+            .into_iter()
+            .map(|(batch, mut psbt, summary)| {
+                if self.key_provider.sign_psbt(&mut psbt)? == 0 {
+                    return Err(Error::HeirKeyMissing);
+                }
+                Ok(PreparedClaim {
+                    batch,
+                    transaction: btc_heritage::utils::finalize_psbt(psbt)?,
+                    summary,
+                })
+            })
+            .collect()
+    }
 }
 
 crate::database::dbitem::impl_db_item!(
@@ -46,7 +272,16 @@ crate::database::dbitem::impl_db_item!(
         if let AnyHeritageProvider::LocalWallet(lw) = &self.heritage_provider {
             lw.local_heritage_wallet().delete(db)?;
         }
-        db.delete_item::<Self>(&Self::name_to_key(self.name()))?;
+        crate::SpendGuardrails::delete_for::<Self>(db, self.name())?;
+        db.delete_item::<Self>(&Self::db_key(db, self.name()))?;
+        Ok(())
+    }
+    fn db_rename(&mut self, db: &mut crate::Database, new_name: String) -> crate::database::errors::Result<()> {
+        let old_name = self.name().to_owned();
+        self.rename(new_name);
+        db.put_item(&Self::db_key(db, self.name()), self)?;
+        db.delete_item::<Self>(&Self::db_key(db, &old_name))?;
+        crate::SpendGuardrails::rename_for::<Self>(db, &old_name, self.name())?;
         Ok(())
     }
 );
@@ -1,16 +1,20 @@
 use btc_heritage::{
-    database::HeritageDatabase, heritage_config::HeritageExplorerTrait,
-    heritage_wallet::CreatePsbtOptions, HeritageWalletBackup, PartiallySignedTransaction,
-    SpendingConfig,
+    bdk_types::BlockTime,
+    bitcoin::{Address, Network},
+    database::HeritageDatabase,
+    heritage_config::HeritageExplorerTrait,
+    heritage_wallet::{CreatePsbtOptions, UtxoSelection},
+    HeirConfig, HeritageWalletBackup, PartiallySignedTransaction, SpendingConfig,
 };
 
 use heritage_service_api_client::{Fingerprint, HeritageUtxo, TransactionSummary};
 
 use serde::{Deserialize, Serialize};
 
+use super::{ClaimBatch, ClaimableUtxo, HeirDiscoveryReport};
 use crate::{
     errors::{Error, Result},
-    online_wallet::LocalHeritageWallet,
+    online_wallet::{LocalHeritageWallet, OnlineWallet},
     BoundFingerprint, Broadcaster, Database,
 };
 
@@ -25,10 +29,11 @@ impl LocalWallet {
         fingerprint: Fingerprint,
         db: &Database,
         backup: HeritageWalletBackup,
+        network: Network,
     ) -> Result<Self> {
         Ok(Self {
             fingerprint,
-            local_heritage_wallet: LocalHeritageWallet::create(db, Some(backup), 6)?,
+            local_heritage_wallet: LocalHeritageWallet::create(db, Some(backup), 6, network)?,
         })
     }
 
@@ -46,10 +51,20 @@ impl LocalWallet {
             .database()
             .list_utxos()?)
     }
-}
 
-impl super::HeritageProvider for LocalWallet {
-    fn list_heritages(&self) -> Result<Vec<super::Heritage>> {
+    /// List the UTXOs of the Heritage wallet that the Heir can claim, now or in the future,
+    /// ordered by maturity
+    pub fn list_claimable_utxos(&self) -> Result<Vec<ClaimableUtxo>> {
+        Ok(self
+            .claimable_heritage_utxos()?
+            .into_iter()
+            .map(|(_, _, cu)| cu)
+            .collect())
+    }
+
+    /// Same as [LocalWallet::list_claimable_utxos] but also returns the [HeritageUtxo] and the
+    /// [HeirConfig] of the Heir that can spend it
+    fn claimable_heritage_utxos(&self) -> Result<Vec<(HeritageUtxo, HeirConfig, ClaimableUtxo)>> {
         let utxos = self.heritage_utxos()?;
         let mut result = vec![];
         for utxo in utxos.into_iter() {
@@ -60,13 +75,13 @@ impl super::HeritageProvider for LocalWallet {
                 // As long as there are still HeirConfig to explore
                 if let Some(hc) = heir_config_iter.next() {
                     // Verify if the HC match our fingerprint
-                    if hc.fingerprint() == self.fingerprint {
-                        // If yes, then the UTXO is spendable by us, we retrieve the estimated maturity
-                        let heir_spending_timestamp = utxo
-                            .estimate_heir_spending_timestamp(hc)
-                            .expect("cannot return none as heir_config is present");
+                    if hc.has_fingerprint(self.fingerprint) {
+                        // If yes, then we retrieve the estimated maturity
+                        // It is None if the UTXO is key-path-only, i.e. not spendable by heirs
                         // And break out of the loop
-                        break Some(heir_spending_timestamp);
+                        break utxo
+                            .estimate_heir_spending_timestamp(hc)
+                            .map(|maturity| (hc.clone(), maturity));
                     }
                 } else {
                     // We reached the end of the iterator without matching our fingerprint
@@ -76,24 +91,137 @@ impl super::HeritageProvider for LocalWallet {
             };
 
             // If we are able to spend (maturity is some)
-            // Then we can push a new Heritage in the results
-            if let Some(maturity) = heir_maturity {
-                let next_heir_maturity = heir_config_iter.next().map(|hc| {
-                    utxo.estimate_heir_spending_timestamp(hc)
-                        .expect("cannot return none as heir_config is present")
-                });
-                result.push(super::Heritage {
-                    // For a local wallet, this is irrelevant, just put the fingerprint
-                    heritage_id: self.fingerprint.to_string(),
+            // Then we can push a new ClaimableUtxo in the results
+            if let Some((heir_config, maturity)) = heir_maturity {
+                let next_heir_maturity = heir_config_iter
+                    .next()
+                    .and_then(|hc| utxo.estimate_heir_spending_timestamp(hc));
+                let claimable_utxo = ClaimableUtxo {
+                    outpoint: utxo.outpoint,
                     value: utxo.amount,
                     maturity,
                     next_heir_maturity,
-                });
+                };
+                result.push((utxo, heir_config, claimable_utxo));
             }
         }
+        result.sort_by_key(|(_, _, cu)| cu.maturity);
         Ok(result)
     }
 
+    /// Create one PSBT draining to `drain_to` for each batch of confirmed UTXOs that the Heir
+    /// can claim with the same [HeirConfig] and that mature at the same time, including the
+    /// batches that are not mature yet.
+    ///
+    /// The PSBTs of future batches carry the locks (nLockTime and nSequence) of the Heir
+    /// so they can be signed right away and broadcasted once mature. Their fee is computed
+    /// now, therefore it may be unfit by the time they are broadcasted.
+    ///
+    /// # Errors
+    /// Returns an error if the wallet was never synchronized or if a PSBT creation fails
+    pub fn create_claim_batch_psbts(
+        &self,
+        drain_to: Address,
+    ) -> Result<Vec<(ClaimBatch, PartiallySignedTransaction, TransactionSummary)>> {
+        let wallet = self.local_heritage_wallet.heritage_wallet();
+        let sync_height = wallet
+            .get_sync_time()?
+            .ok_or(btc_heritage::errors::Error::UnsyncedWallet)?
+            .height;
+
+        // Group the UTXOs by HeirConfig and maturity, keeping track of the block height
+        // at which the relative locks of every UTXO of the batch are satisfied
+        let mut batches: Vec<(HeirConfig, ClaimBatch, u32)> = vec![];
+        for (utxo, heir_config, claimable_utxo) in self.claimable_heritage_utxos()? {
+            let Some(confirmation_time) = &utxo.confirmation_time else {
+                log::info!(
+                    "Skipping the unconfirmed UTXO {}, its maturity is unknown",
+                    utxo.outpoint
+                );
+                continue;
+            };
+            let relative_block_lock = utxo
+                .heritage_config
+                .get_heritage_explorer(&heir_config)
+                .expect("the heir config comes from the heritage config")
+                .get_spend_conditions()
+                .get_relative_block_lock()
+                .unwrap_or(0);
+            let spendable_height = confirmation_time.height + relative_block_lock as u32;
+            match batches
+                .iter_mut()
+                .find(|(hc, b, _)| *hc == heir_config && b.maturity == claimable_utxo.maturity)
+            {
+                Some((_, batch, height)) => {
+                    batch.utxos.push(claimable_utxo);
+                    *height = (*height).max(spendable_height);
+                }
+                None => batches.push((
+                    heir_config,
+                    ClaimBatch {
+                        maturity: claimable_utxo.maturity,
+                        utxos: vec![claimable_utxo],
+                    },
+                    spendable_height.max(sync_height),
+                )),
+            }
+        }
+
+        let now = btc_heritage::utils::timestamp_now();
+        batches
+            .into_iter()
+            .map(|(heir_config, batch, height)| {
+                let (psbt, summary) = wallet.create_heir_psbt(
+                    heir_config,
+                    SpendingConfig::DrainTo(drain_to.clone()),
+                    CreatePsbtOptions {
+                        // Create the PSBT as-if we were at the maturity of the batch
+                        assume_blocktime: Some(BlockTime {
+                            height,
+                            timestamp: batch.maturity.max(now),
+                        }),
+                        utxo_selection: UtxoSelection::UseOnly(
+                            batch.utxos.iter().map(|cu| cu.outpoint).collect(),
+                        ),
+                        ..Default::default()
+                    },
+                )?;
+                Ok((batch, psbt, summary))
+            })
+            .collect()
+    }
+
+    /// Synchronize the Heritage wallet and report the funds the Heir can claim.
+    ///
+    /// # Errors
+    /// Returns an error if the synchronization fails
+    ///
+    /// # Panics
+    /// Panics if the blockchain factory of the [LocalHeritageWallet] was not initialized
+    pub fn discover(&mut self) -> Result<HeirDiscoveryReport> {
+        self.local_heritage_wallet.sync()?;
+        Ok(HeirDiscoveryReport {
+            fingerprint: self.fingerprint,
+            utxos: self.list_claimable_utxos()?,
+        })
+    }
+}
+
+impl super::HeritageProvider for LocalWallet {
+    fn list_heritages(&self) -> Result<Vec<super::Heritage>> {
+        Ok(self
+            .list_claimable_utxos()?
+            .into_iter()
+            .map(|cu| super::Heritage {
+                // For a local wallet, this is irrelevant, just put the fingerprint
+                heritage_id: self.fingerprint.to_string(),
+                value: cu.value,
+                maturity: cu.maturity,
+                next_heir_maturity: cu.next_heir_maturity,
+            })
+            .collect())
+    }
+
     /// Create a PSBT for an Heir
     ///
     /// # Important Note
@@ -127,7 +255,7 @@ impl super::HeritageProvider for LocalWallet {
                 })
             })
             .flatten()
-            .filter(|&hc| hc.fingerprint() == self.fingerprint)
+            .filter(|&hc| hc.has_fingerprint(self.fingerprint))
             .cloned()
             .next()
             .ok_or(Error::Generic("Nothing to spend".to_owned()))?;
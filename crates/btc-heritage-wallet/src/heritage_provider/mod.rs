@@ -3,7 +3,7 @@ use crate::{
     BoundFingerprint, Broadcaster,
 };
 use btc_heritage::{
-    bitcoin::{amount, bip32::Fingerprint, Address, Txid},
+    bitcoin::{amount, bip32::Fingerprint, Address, OutPoint, Txid},
     heritage_wallet::TransactionSummary,
     Amount, PartiallySignedTransaction,
 };
@@ -11,8 +11,10 @@ use btc_heritage::{
 use serde::{Deserialize, Serialize};
 
 mod local;
+mod self_hosted;
 mod service;
 pub use local::LocalWallet;
+pub use self_hosted::SelfHostedBinding;
 pub use service::ServiceBinding;
 
 type Timestamp = u64;
@@ -28,6 +30,83 @@ pub struct Heritage {
     pub next_heir_maturity: Option<Timestamp>,
 }
 
+/// An UTXO of an Heritage wallet that an Heir can claim, now or in the future
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimableUtxo {
+    pub outpoint: OutPoint,
+    #[serde(with = "amount::serde::as_sat")]
+    pub value: Amount,
+    /// The (estimated) timestamp after which the Heir is able to spend
+    pub maturity: Timestamp,
+    /// The maturity of the next heir, if any
+    pub next_heir_maturity: Option<Timestamp>,
+}
+
+/// The funds an Heir can claim from an Heritage wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeirDiscoveryReport {
+    /// The [Fingerprint] of the Heir
+    pub fingerprint: Fingerprint,
+    /// The claimable UTXOs, ordered by maturity
+    pub utxos: Vec<ClaimableUtxo>,
+}
+impl HeirDiscoveryReport {
+    /// The total value of the claimable UTXOs, mature or not
+    pub fn total_value(&self) -> Amount {
+        self.utxos.iter().map(|u| u.value).sum()
+    }
+    /// The value of the UTXOs that are mature at the given timestamp
+    pub fn value_mature_at(&self, ts: Timestamp) -> Amount {
+        self.utxos
+            .iter()
+            .filter(|u| u.maturity <= ts)
+            .map(|u| u.value)
+            .sum()
+    }
+    /// The value of the UTXOs that can be claimed right now
+    pub fn value_mature_now(&self) -> Amount {
+        self.value_mature_at(btc_heritage::utils::timestamp_now())
+    }
+    /// The next maturity after the given timestamp, if any
+    pub fn next_maturity_after(&self, ts: Timestamp) -> Option<Timestamp> {
+        self.utxos
+            .iter()
+            .map(|u| u.maturity)
+            .filter(|&maturity| maturity > ts)
+            .min()
+    }
+    /// Group the claimable UTXOs into [ClaimBatch]es sharing the same maturity,
+    /// ordered by maturity
+    pub fn batches(&self) -> Vec<ClaimBatch> {
+        let mut batches: Vec<ClaimBatch> = vec![];
+        for utxo in self.utxos.iter() {
+            match batches.iter_mut().find(|b| b.maturity == utxo.maturity) {
+                Some(batch) => batch.utxos.push(utxo.clone()),
+                None => batches.push(ClaimBatch {
+                    maturity: utxo.maturity,
+                    utxos: vec![utxo.clone()],
+                }),
+            }
+        }
+        batches.sort_by_key(|b| b.maturity);
+        batches
+    }
+}
+
+/// A group of [ClaimableUtxo]s that mature at the same time and can be claimed together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimBatch {
+    /// The (estimated) timestamp after which the Heir is able to spend the batch
+    pub maturity: Timestamp,
+    pub utxos: Vec<ClaimableUtxo>,
+}
+impl ClaimBatch {
+    /// The total value of the UTXOs of the batch
+    pub fn value(&self) -> Amount {
+        self.utxos.iter().map(|u| u.value).sum()
+    }
+}
+
 /// This trait regroup the functions of an Heritage wallet that does not need
 /// access to the private keys and can be safely operated in an online environment.
 pub trait HeritageProvider: Broadcaster + BoundFingerprint {
@@ -46,6 +125,7 @@ pub enum AnyHeritageProvider {
     None,
     Service(ServiceBinding),
     LocalWallet(LocalWallet),
+    SelfHosted(SelfHostedBinding),
 }
 
 impl AnyHeritageProvider {
@@ -68,6 +148,7 @@ macro_rules! impl_heritage_provider_fn {
                 AnyHeritageProvider::None => Err(Error::MissingHeritageProvider),
                 AnyHeritageProvider::Service(sb) => sb.$fn_name($($a),*),
                 AnyHeritageProvider::LocalWallet(lw) => lw.$fn_name($($a),*),
+                AnyHeritageProvider::SelfHosted(shb) => shb.$fn_name($($a),*),
             }
     };
 }
@@ -109,3 +190,46 @@ macro_rules! impl_heritage_provider {
     };
 }
 pub(crate) use impl_heritage_provider;
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+
+    fn claimable_utxo(vout: u32, value: u64, maturity: Timestamp) -> ClaimableUtxo {
+        ClaimableUtxo {
+            outpoint: OutPoint::from_str(&format!(
+                "0000000000000000000000000000000000000000000000000000000000000001:{vout}"
+            ))
+            .unwrap(),
+            value: Amount::from_sat(value),
+            maturity,
+            next_heir_maturity: None,
+        }
+    }
+
+    #[test]
+    fn discovery_report_batches() {
+        let report = HeirDiscoveryReport {
+            fingerprint: Fingerprint::from_str("9c7088e3").unwrap(),
+            utxos: vec![
+                claimable_utxo(0, 1_000, 100),
+                claimable_utxo(1, 2_000, 200),
+                claimable_utxo(2, 4_000, 100),
+            ],
+        };
+        assert_eq!(report.total_value(), Amount::from_sat(7_000));
+        assert_eq!(report.value_mature_at(99), Amount::ZERO);
+        assert_eq!(report.value_mature_at(150), Amount::from_sat(5_000));
+        assert_eq!(report.next_maturity_after(100), Some(200));
+        assert_eq!(report.next_maturity_after(200), None);
+
+        let batches = report.batches();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].maturity, 100);
+        assert_eq!(batches[0].value(), Amount::from_sat(5_000));
+        assert_eq!(batches[1].maturity, 200);
+        assert_eq!(batches[1].utxos, vec![claimable_utxo(1, 2_000, 200)]);
+    }
+}
@@ -0,0 +1,72 @@
+use btc_heritage::PartiallySignedTransaction;
+
+use heritage_service_api_client::{Fingerprint, HeritageServiceClient, TransactionSummary};
+use serde::{Deserialize, Serialize};
+
+use crate::{errors::Result, BoundFingerprint, Broadcaster};
+
+use super::{Heritage, HeritageProvider, ServiceBinding};
+
+/// An Heritage provider targeting a self-hosted Heritage service, reachable at a configurable
+/// base URL and authenticating with an API key.
+///
+/// The API key is never serialized, it must be provided again with
+/// [SelfHostedBinding::init_api_key] after the binding is loaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SelfHostedBinding {
+    service_api_url: String,
+    #[serde(flatten)]
+    service_binding: ServiceBinding,
+}
+
+impl SelfHostedBinding {
+    pub fn new(fingerprint: Fingerprint, service_api_url: String, api_key: String) -> Self {
+        let service_client =
+            HeritageServiceClient::new_with_api_key(service_api_url.clone(), api_key);
+        Self {
+            service_api_url,
+            service_binding: ServiceBinding::new(fingerprint, service_client),
+        }
+    }
+    pub fn init_api_key(&mut self, api_key: String) {
+        self.service_binding
+            .init_service_client(HeritageServiceClient::new_with_api_key(
+                self.service_api_url.clone(),
+                api_key,
+            ));
+    }
+    pub fn has_api_key(&self) -> bool {
+        self.service_binding.has_service_client()
+    }
+    pub fn service_api_url(&self) -> &str {
+        &self.service_api_url
+    }
+}
+
+impl HeritageProvider for SelfHostedBinding {
+    fn list_heritages(&self) -> Result<Vec<Heritage>> {
+        self.service_binding.list_heritages()
+    }
+    fn create_psbt(
+        &self,
+        heritage_id: &str,
+        drain_to: btc_heritage::bitcoin::Address,
+    ) -> Result<(PartiallySignedTransaction, TransactionSummary)> {
+        self.service_binding.create_psbt(heritage_id, drain_to)
+    }
+}
+
+impl Broadcaster for SelfHostedBinding {
+    fn broadcast(
+        &self,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<heritage_service_api_client::Txid> {
+        self.service_binding.broadcast(psbt)
+    }
+}
+
+impl BoundFingerprint for SelfHostedBinding {
+    fn fingerprint(&self) -> Result<Fingerprint> {
+        self.service_binding.fingerprint()
+    }
+}
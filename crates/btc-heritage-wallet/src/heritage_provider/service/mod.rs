@@ -47,7 +47,7 @@ impl super::HeritageProvider for ServiceBinding {
             .filter_map(|api_h| {
                 if api_h
                     .heir_config
-                    .is_some_and(|hc| hc.fingerprint() == self.fingerprint)
+                    .is_some_and(|hc| hc.has_fingerprint(self.fingerprint))
                     && api_h.value.is_some()
                     && api_h.maturity.is_some()
                     && api_h.next_heir_maturity.is_some()
@@ -0,0 +1,387 @@
+use std::collections::HashSet;
+
+use crate::{
+    errors::{Error, Result},
+    BoundFingerprint, SigningInstructions,
+};
+
+use btc_heritage::{
+    account_xpub::AccountXPubId,
+    bitcoin::{
+        bip32::{ChildNumber, Fingerprint},
+        key::{Secp256k1, TapTweak, XOnlyPublicKey},
+        psbt::Prevouts,
+        secp256k1,
+        sighash::{SighashCache, TapSighashType},
+        taproot::{Signature, TapLeafHash},
+        Network, TxOut,
+    },
+    miniscript::{descriptor::DescriptorXKey, DescriptorPublicKey},
+    AccountXPub, PartiallySignedTransaction,
+};
+use serde::{Deserialize, Serialize};
+
+use super::MnemonicBackup;
+
+/// A [KeyProvider](super::KeyProvider) for air-gapped signers that exchange PSBT files with
+/// the online machine, like the Coldcard with its SD-card.
+///
+/// As the device cannot be reached, the account eXtended Public Keys are provided at creation
+/// (usually exported from the device) and the signature happens in three steps:
+/// 1. [AirGappedKey::export_psbt] produces the binary PSBT to write on the SD-card, with the
+///    `PSBT_GLOBAL_XPUB` fields of the accounts involved so that the device can verify the change;
+/// 2. the user signs the PSBT on the device;
+/// 3. [AirGappedKey::import_signed_psbt] verifies every signature of the PSBT returned by the device
+///    against the expected keys and, if they are all valid, adds them to the original PSBT.
+///
+/// Consequently, [KeyProvider::sign_psbt](super::KeyProvider::sign_psbt) always fails for an [AirGappedKey].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AirGappedKey {
+    fingerprint: Fingerprint,
+    network: Network,
+    account_xpubs: Vec<AccountXPub>,
+}
+
+impl AirGappedKey {
+    /// Create an [AirGappedKey] from the [AccountXPub]s exported from the device.
+    ///
+    /// # Errors
+    /// Returns an error if `account_xpubs` is empty or if the [AccountXPub]s do not all
+    /// have the same fingerprint
+    pub fn new(account_xpubs: Vec<AccountXPub>, network: Network) -> Result<Self> {
+        let fingerprint = account_xpubs
+            .first()
+            .ok_or(Error::AirGappedMissingAccountXPubs)?
+            .descriptor_public_key()
+            .master_fingerprint();
+        if account_xpubs
+            .iter()
+            .any(|axpub| axpub.descriptor_public_key().master_fingerprint() != fingerprint)
+        {
+            return Err(Error::IncoherentFingerprints);
+        }
+        Ok(Self {
+            fingerprint,
+            network,
+            account_xpubs,
+        })
+    }
+
+    /// Return the [AccountXPubId] of the keys of the device used by `input`
+    fn input_account_ids(
+        &self,
+        input: &btc_heritage::bitcoin::psbt::Input,
+    ) -> HashSet<AccountXPubId> {
+        input
+            .tap_key_origins
+            .values()
+            .filter(|(_, (fg, _))| fg == &self.fingerprint)
+            .filter_map(|(_, (_, dp))| match dp.as_ref().get(2) {
+                Some(ChildNumber::Hardened { index }) => Some(*index),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Serialize `psbt` in the binary format expected by air-gapped signers, after adding the
+    /// `PSBT_GLOBAL_XPUB` fields of the accounts of the device used by its inputs and
+    /// the [SigningInstructions] of the PSBT.
+    ///
+    /// # Errors
+    /// Returns an error if an input uses an account for which the [AccountXPub] is unknown
+    pub fn export_psbt(&self, psbt: &PartiallySignedTransaction) -> Result<Vec<u8>> {
+        let mut psbt = psbt.clone();
+        let account_ids = psbt
+            .inputs
+            .iter()
+            .flat_map(|input| self.input_account_ids(input))
+            .collect::<HashSet<_>>();
+        for account_id in account_ids {
+            let account_xpub = self
+                .account_xpubs
+                .iter()
+                .find(|axpub| axpub.descriptor_id() == account_id)
+                .ok_or(Error::AirGappedMissingAccountXPub(account_id))?;
+            match account_xpub.descriptor_public_key() {
+                DescriptorPublicKey::XPub(DescriptorXKey {
+                    origin: Some(origin),
+                    xkey,
+                    ..
+                }) => {
+                    psbt.xpub.insert(*xkey, origin.clone());
+                }
+                _ => unreachable!("AccountXPub is checked at creation"),
+            }
+        }
+        SigningInstructions::from_psbt(&psbt).embed(&mut psbt)?;
+        Ok(psbt.serialize())
+    }
+
+    /// Verify the signatures of `signed_psbt`, the PSBT returned by the air-gapped signer in any
+    /// of the encodings supported by [crate::psbt_io::parse_psbt], and add them to `psbt`. Return the number of inputs signed.
+    ///
+    /// Every signature must be a valid signature of the input by a key of the device,
+    /// i.e. by the tweaked internal key for the key-path or by a key of the spending leaf
+    /// for the script-path.
+    ///
+    /// # Errors
+    /// Returns an error, without modifying `psbt`, if `signed_psbt` cannot be parsed, if it is not
+    /// for the same transaction as `psbt` or if any of its signatures is invalid or unexpected
+    pub fn import_signed_psbt(
+        &self,
+        psbt: &mut PartiallySignedTransaction,
+        signed_psbt: &[u8],
+    ) -> Result<usize> {
+        let signed_psbt = crate::psbt_io::parse_psbt(signed_psbt)?;
+        if signed_psbt.unsigned_tx != psbt.unsigned_tx
+            || signed_psbt.inputs.len() != psbt.inputs.len()
+        {
+            return Err(Error::AirGappedPsbtMismatch);
+        }
+
+        let secp = Secp256k1::verification_only();
+        let mut sig_cache = SighashCache::new(&psbt.unsigned_tx);
+        let witness_utxos = psbt
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| {
+                if let Some(wit_utxo) = &input.witness_utxo {
+                    Some(wit_utxo.clone())
+                } else if let Some(in_tx) = &input.non_witness_utxo {
+                    let vout = psbt.unsigned_tx.input[i].previous_output.vout;
+                    in_tx.output.get(vout as usize).cloned()
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        let all_witness_utxos = witness_utxos.iter().flatten().collect::<Vec<&TxOut>>();
+
+        let mut verify = |input_index: usize,
+                          signature: &Signature,
+                          key: &XOnlyPublicKey,
+                          leaf_hash: Option<TapLeafHash>|
+         -> Result<()> {
+            let prevouts = match signature.hash_ty {
+                TapSighashType::AllPlusAnyoneCanPay
+                | TapSighashType::NonePlusAnyoneCanPay
+                | TapSighashType::SinglePlusAnyoneCanPay => Prevouts::One(
+                    input_index,
+                    witness_utxos[input_index]
+                        .as_ref()
+                        .ok_or(Error::AirGappedInvalidSignature(input_index))?,
+                ),
+                _ => {
+                    if all_witness_utxos.len() != witness_utxos.len() {
+                        return Err(Error::AirGappedInvalidSignature(input_index));
+                    }
+                    Prevouts::All(&all_witness_utxos)
+                }
+            };
+            let sighash = sig_cache
+                .taproot_signature_hash(
+                    input_index,
+                    &prevouts,
+                    None,
+                    leaf_hash.map(|lh| (lh, 0xFFFFFFFF)),
+                    signature.hash_ty,
+                )
+                .map_err(|_| Error::AirGappedInvalidSignature(input_index))?;
+            secp.verify_schnorr(&signature.sig, &secp256k1::Message::from(sighash), key)
+                .map_err(|_| Error::AirGappedInvalidSignature(input_index))
+        };
+
+        // First verify everything, then update the PSBT
+        let mut verified = Vec::with_capacity(psbt.inputs.len());
+        for (input_index, (input, signed_input)) in psbt
+            .inputs
+            .iter()
+            .zip(signed_psbt.inputs.iter())
+            .enumerate()
+        {
+            let is_ours = |key: &XOnlyPublicKey| {
+                input
+                    .tap_key_origins
+                    .get(key)
+                    .filter(|(_, (fg, _))| fg == &self.fingerprint)
+                    .map(|(leaves, _)| leaves)
+            };
+            if let Some(signature) = &signed_input.tap_key_sig {
+                let internal_key = input
+                    .tap_internal_key
+                    .filter(|ik| is_ours(ik).is_some())
+                    .ok_or(Error::AirGappedInvalidSignature(input_index))?;
+                let (output_key, _) = internal_key.tap_tweak(&secp, input.tap_merkle_root);
+                verify(input_index, signature, &output_key.to_inner(), None)?;
+            }
+            for ((key, leaf_hash), signature) in signed_input.tap_script_sigs.iter() {
+                if !is_ours(key).is_some_and(|leaves| leaves.contains(leaf_hash)) {
+                    return Err(Error::AirGappedInvalidSignature(input_index));
+                }
+                verify(input_index, signature, key, Some(*leaf_hash))?;
+            }
+            verified.push(
+                signed_input.tap_key_sig.is_some() || !signed_input.tap_script_sigs.is_empty(),
+            );
+        }
+
+        for (input, signed_input) in psbt.inputs.iter_mut().zip(signed_psbt.inputs.into_iter()) {
+            if let Some(signature) = signed_input.tap_key_sig {
+                input.tap_key_sig = Some(signature);
+            }
+            input.tap_script_sigs.extend(signed_input.tap_script_sigs);
+        }
+        Ok(verified.into_iter().filter(|signed| *signed).count())
+    }
+}
+
+impl super::KeyProvider for AirGappedKey {
+    fn sign_psbt(&self, _psbt: &mut PartiallySignedTransaction) -> Result<usize> {
+        Err(Error::AirGappedSigningRequired)
+    }
+
+    fn derive_accounts_xpubs(&self, range: core::ops::Range<u32>) -> Result<Vec<AccountXPub>> {
+        range
+            .into_iter()
+            .map(|i| {
+                self.account_xpubs
+                    .iter()
+                    .find(|axpub| axpub.descriptor_id() == i)
+                    .cloned()
+                    .ok_or(Error::AirGappedMissingAccountXPub(i))
+            })
+            .collect()
+    }
+
+    fn derive_heir_config(
+        &self,
+        _heir_config_type: super::HeirConfigType,
+    ) -> Result<btc_heritage::HeirConfig> {
+        Err(Error::AirGappedHeirUnsupported)
+    }
+
+    fn backup_mnemonic(&self) -> Result<MnemonicBackup> {
+        Err(Error::AirGappedBackupMnemonicUnsupported)
+    }
+}
+
+impl BoundFingerprint for AirGappedKey {
+    fn fingerprint(&self) -> crate::errors::Result<Fingerprint> {
+        Ok(self.fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KeyProvider, LocalKey, Mnemonic};
+    use btc_heritage::psbttests::{get_test_unsigned_psbt, TestPsbt};
+
+    fn owner_local_key() -> LocalKey {
+        LocalKey::restore(
+            Mnemonic::parse(
+                "owner owner owner owner owner owner owner owner owner owner owner panther",
+            )
+            .unwrap(),
+            None,
+            Network::Regtest,
+        )
+    }
+
+    fn owner_air_gapped_key() -> AirGappedKey {
+        AirGappedKey::new(
+            owner_local_key().derive_accounts_xpubs(0..2).unwrap(),
+            Network::Regtest,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn export_psbt_adds_global_xpubs() {
+        let key = owner_air_gapped_key();
+        let psbt = get_test_unsigned_psbt(TestPsbt::OwnerRecipients);
+        let exported =
+            PartiallySignedTransaction::deserialize(&key.export_psbt(&psbt).unwrap()).unwrap();
+        assert_eq!(exported.unsigned_tx, psbt.unsigned_tx);
+        assert!(!exported.xpub.is_empty());
+        assert!(exported
+            .xpub
+            .values()
+            .all(|(fg, _)| *fg == key.fingerprint().unwrap()));
+        assert!(SigningInstructions::extract(&exported).unwrap().is_some());
+
+        // Without the account xpubs, the export fails
+        let key = AirGappedKey::new(
+            owner_local_key().derive_accounts_xpubs(5..6).unwrap(),
+            Network::Regtest,
+        )
+        .unwrap();
+        assert!(matches!(
+            key.export_psbt(&psbt),
+            Err(Error::AirGappedMissingAccountXPub(_))
+        ));
+    }
+
+    #[test]
+    fn import_signed_psbt() {
+        let key = owner_air_gapped_key();
+        let mut psbt = get_test_unsigned_psbt(TestPsbt::OwnerRecipients);
+        assert!(matches!(
+            key.sign_psbt(&mut psbt.clone()),
+            Err(Error::AirGappedSigningRequired)
+        ));
+
+        // Simulate the air-gapped device
+        let mut device_psbt =
+            PartiallySignedTransaction::deserialize(&key.export_psbt(&psbt).unwrap()).unwrap();
+        let signed_count = owner_local_key().sign_psbt(&mut device_psbt).unwrap();
+        assert!(signed_count > 0);
+
+        // Base64 is accepted too
+        let mut psbt_copy = psbt.clone();
+        assert_eq!(
+            key.import_signed_psbt(&mut psbt_copy, device_psbt.to_string().as_bytes())
+                .unwrap(),
+            signed_count
+        );
+        assert_eq!(
+            key.import_signed_psbt(&mut psbt, &device_psbt.serialize())
+                .unwrap(),
+            signed_count
+        );
+        assert_eq!(psbt_copy, psbt);
+        assert!(psbt.inputs.iter().all(|input| input.tap_key_sig.is_some()));
+    }
+
+    #[test]
+    fn import_signed_psbt_rejects_invalid() {
+        let key = owner_air_gapped_key();
+        let original = get_test_unsigned_psbt(TestPsbt::OwnerRecipients);
+        let mut device_psbt = original.clone();
+        owner_local_key().sign_psbt(&mut device_psbt).unwrap();
+
+        // Another transaction
+        let mut other = get_test_unsigned_psbt(TestPsbt::OwnerDrain);
+        assert!(matches!(
+            key.import_signed_psbt(&mut other, &device_psbt.serialize()),
+            Err(Error::AirGappedPsbtMismatch)
+        ));
+
+        // Signatures swapped between inputs are invalid
+        let mut tampered = device_psbt.clone();
+        let sig0 = tampered.inputs[0].tap_key_sig;
+        tampered.inputs[0].tap_key_sig = tampered.inputs[1].tap_key_sig;
+        tampered.inputs[1].tap_key_sig = sig0;
+        let mut psbt = original.clone();
+        assert!(matches!(
+            key.import_signed_psbt(&mut psbt, &tampered.serialize()),
+            Err(Error::AirGappedInvalidSignature(0))
+        ));
+        // The PSBT is left untouched
+        assert_eq!(psbt, original);
+
+        // Garbage
+        assert!(key.import_signed_psbt(&mut psbt, b"not a psbt").is_err());
+    }
+}
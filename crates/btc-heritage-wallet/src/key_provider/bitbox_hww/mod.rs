@@ -0,0 +1,407 @@
+use core::{fmt::Debug, str::FromStr};
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    errors::{Error, Result},
+    BoundFingerprint, LedgerPolicy,
+};
+
+use bitbox_api::{
+    btc::KeyOriginInfo, pb, runtime::TokioRuntime, usb, BitBox, NoiseConfig, PairedBitBox,
+};
+use btc_heritage::{
+    account_xpub::AccountXPubId,
+    bitcoin::{
+        bip32::{ChildNumber, DerivationPath, Fingerprint},
+        Network,
+    },
+    AccountXPub,
+};
+use serde::{Deserialize, Serialize};
+
+use super::MnemonicBackup;
+
+fn client_error<E: ToString>(e: E) -> Error {
+    Error::BitBoxClientError(e.to_string())
+}
+
+/// Connection with a paired BitBox02 device.
+///
+/// The `bitbox_api` crate is asynchronous, so the client owns a single-threaded
+/// Tokio runtime to drive it from our synchronous [KeyProvider](super::KeyProvider) API.
+struct BitBoxClient {
+    runtime: tokio::runtime::Runtime,
+    bitbox: PairedBitBox<TokioRuntime>,
+}
+impl Debug for BitBoxClient {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("BitBoxClient").finish()
+    }
+}
+impl BitBoxClient {
+    /// Connect to the BitBox02 and pair with it. If the device was never paired with this
+    /// host, `show_pairing_code` is called with a code that the user must confirm on the device.
+    pub fn new(
+        noise_config: Box<dyn NoiseConfig>,
+        show_pairing_code: &dyn Fn(&str),
+    ) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(Error::generic)?;
+        let bitbox = runtime.block_on(async {
+            let device = usb::get_any_bitbox02().map_err(client_error)?;
+            let pairing = BitBox::<TokioRuntime>::from_hid_device(device, noise_config)
+                .await
+                .map_err(client_error)?
+                .unlock_and_pair()
+                .await
+                .map_err(client_error)?;
+            if let Some(pairing_code) = pairing.get_pairing_code() {
+                show_pairing_code(&pairing_code);
+            }
+            pairing.wait_confirm().await.map_err(client_error)
+        })?;
+        Ok(Self { runtime, bitbox })
+    }
+
+    fn coin(network: Network) -> pb::BtcCoin {
+        match network {
+            Network::Bitcoin => pb::BtcCoin::Btc,
+            Network::Regtest => pb::BtcCoin::Rbtc,
+            _ => pb::BtcCoin::Tbtc,
+        }
+    }
+}
+
+/// Convert a `[fingerprint/path]xpub` key of a [LedgerPolicy] into a [KeyOriginInfo]
+fn key_origin_info(key: &str) -> Result<KeyOriginInfo> {
+    let (origin, xpub) = key
+        .strip_prefix('[')
+        .and_then(|key| key.split_once(']'))
+        .ok_or_else(|| Error::Generic(format!("Invalid policy key {key}")))?;
+    let (fingerprint, path) = origin
+        .split_once('/')
+        .ok_or_else(|| Error::Generic(format!("Invalid policy key {key}")))?;
+    // Because for now we are bound to the rust-bitcoin version of BDK
+    // which is different than the one used by bitbox_api
+    Ok(KeyOriginInfo {
+        root_fingerprint: Some(
+            bitcoin::bip32::Fingerprint::from_str(fingerprint).map_err(Error::generic)?,
+        ),
+        keypath: Some(
+            (&bitcoin::bip32::DerivationPath::from_str(&format!("m/{path}"))
+                .map_err(Error::generic)?)
+                .into(),
+        ),
+        xpub: bitcoin::bip32::Xpub::from_str(xpub).map_err(Error::generic)?,
+    })
+}
+
+/// The descriptor template and keys of a [LedgerPolicy] in the BitBox02 format. The Ledger
+/// and BitBox02 devices both use [BIP-388](https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki)
+/// wallet policies, so the same [LedgerPolicy] can be registered on both
+fn policy_template_and_keys(policy: &LedgerPolicy) -> Result<(String, Vec<KeyOriginInfo>)> {
+    let (descriptor_template, keys) = policy.descriptor_template_and_keys();
+    let keys = keys
+        .iter()
+        .map(|key| key_origin_info(key))
+        .collect::<Result<Vec<_>>>()?;
+    Ok((descriptor_template, keys))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BitBoxKey {
+    fingerprint: Fingerprint,
+    network: Network,
+    #[serde(default)]
+    registered_policies: HashMap<AccountXPubId, LedgerPolicy>,
+    #[serde(skip, default)]
+    bitbox_client: Option<BitBoxClient>,
+}
+
+impl BitBoxKey {
+    pub fn new(
+        network: Network,
+        noise_config: Box<dyn NoiseConfig>,
+        show_pairing_code: &dyn Fn(&str),
+    ) -> Result<Self> {
+        let bitbox_client = BitBoxClient::new(noise_config, show_pairing_code)?;
+        let fingerprint = bitbox_client
+            .runtime
+            .block_on(bitbox_client.bitbox.root_fingerprint())
+            .map_err(client_error)?;
+        Ok(Self {
+            fingerprint: Fingerprint::from_str(&fingerprint).map_err(Error::generic)?,
+            network,
+            registered_policies: HashMap::new(),
+            bitbox_client: Some(bitbox_client),
+        })
+    }
+    pub fn init_bitbox_client(
+        &mut self,
+        noise_config: Box<dyn NoiseConfig>,
+        show_pairing_code: &dyn Fn(&str),
+    ) -> Result<()> {
+        let bitbox_client = BitBoxClient::new(noise_config, show_pairing_code)?;
+        let fingerprint = bitbox_client
+            .runtime
+            .block_on(bitbox_client.bitbox.root_fingerprint())
+            .map_err(client_error)?;
+        if Fingerprint::from_str(&fingerprint).map_err(Error::generic)? != self.fingerprint {
+            return Err(Error::IncoherentBitBoxWalletFingerprint);
+        }
+        self.bitbox_client = Some(bitbox_client);
+        Ok(())
+    }
+    fn bitbox_client(&self) -> Result<&BitBoxClient> {
+        self.bitbox_client
+            .as_ref()
+            .ok_or(Error::UninitializedBitBoxClient)
+    }
+    /// Register the given policies on the BitBox02. Policies already registered on the device
+    /// are not registered again. The user must confirm each new registration on the device.
+    pub fn register_policies<P>(
+        &mut self,
+        policies: &Vec<LedgerPolicy>,
+        progress: P,
+    ) -> Result<usize>
+    where
+        P: Fn(&LedgerPolicy),
+    {
+        let client = self.bitbox_client()?;
+        let coin = BitBoxClient::coin(self.network);
+        let register_results = policies
+            .iter()
+            .map(|policy| {
+                let account_id = policy.get_account_id();
+                let (descriptor_template, keys) = policy_template_and_keys(policy)?;
+                let script_config =
+                    bitbox_api::btc::make_script_config_policy(&descriptor_template, &keys);
+                let already_registered = client
+                    .runtime
+                    .block_on(client.bitbox.btc_is_script_config_registered(
+                        coin,
+                        &script_config,
+                        None,
+                    ))
+                    .map_err(client_error)?;
+                if !already_registered {
+                    // Call the callback progress function so that the caller may display something
+                    progress(policy);
+                    client
+                        .runtime
+                        .block_on(client.bitbox.btc_register_policy(
+                            coin,
+                            &descriptor_template,
+                            &keys,
+                            pb::btc_register_script_config_request::XPubType::AutoElectrum,
+                            Some(&format!("Heritage #{account_id}")),
+                        ))
+                        .map_err(client_error)?;
+                }
+                Ok::<_, Error>((account_id, policy.clone()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let before = self.registered_policies.len();
+        self.registered_policies
+            .extend(register_results.into_iter());
+        Ok(self.registered_policies.len() - before)
+    }
+    pub fn list_registered_policies(&self) -> Vec<(AccountXPubId, LedgerPolicy)> {
+        self.registered_policies
+            .iter()
+            .map(|(account_id, p)| (*account_id, p.clone()))
+            .collect()
+    }
+}
+
+impl super::KeyProvider for BitBoxKey {
+    fn sign_psbt(&self, psbt: &mut btc_heritage::PartiallySignedTransaction) -> Result<usize> {
+        // We need to know what AccountXPubId are present in the PSBT inputs
+        let account_ids_present: HashSet<AccountXPubId> = psbt
+            .inputs
+            .iter()
+            .flat_map(|input| {
+                input
+                    .tap_key_origins
+                    .iter()
+                    .filter_map(|(_, (_, (fg, dp)))| {
+                        if fg == &self.fingerprint {
+                            match dp[2] {
+                                ChildNumber::Normal { .. } => None,
+                                ChildNumber::Hardened { index } => Some(index),
+                            }
+                        } else {
+                            None
+                        }
+                    })
+            })
+            .collect();
+        if !account_ids_present
+            .iter()
+            .all(|i| self.registered_policies.contains_key(i))
+        {
+            return Err(Error::BitBoxMissingRegisteredPolicy(
+                account_ids_present.into_iter().collect(),
+            ));
+        }
+
+        let client = self.bitbox_client()?;
+        let coin = BitBoxClient::coin(self.network);
+        let mut signed_inputs = HashSet::new();
+        for account_id in account_ids_present {
+            let policy = self
+                .registered_policies
+                .get(&account_id)
+                .expect("we ensured every ids are in the Hashtable");
+            let (descriptor_template, keys) = policy_template_and_keys(policy)?;
+            let script_config = pb::BtcScriptConfigWithKeypath {
+                script_config: Some(bitbox_api::btc::make_script_config_policy(
+                    &descriptor_template,
+                    &keys,
+                )),
+                keypath: DerivationPath::from(vec![
+                    ChildNumber::from_hardened_idx(86).unwrap(),
+                    ChildNumber::from_hardened_idx(match self.network {
+                        Network::Bitcoin => 0,
+                        _ => 1,
+                    })
+                    .unwrap(),
+                    ChildNumber::from_hardened_idx(account_id)
+                        .map_err(|_| Error::AccountDerivationIndexOutOfBound(account_id))?,
+                ])
+                .into_iter()
+                .map(|c| u32::from(*c))
+                .collect(),
+            };
+
+            // Because for now we are bound to the rust-bitcoin version of BDK
+            // which is different than the one used by bitbox_api
+            let mut psbt_v_bitbox = bitcoin::Psbt::deserialize(
+                &btc_heritage::bitcoin::psbt::PartiallySignedTransaction::serialize(psbt),
+            )
+            .map_err(Error::generic)?;
+            client
+                .runtime
+                .block_on(client.bitbox.btc_sign_psbt(
+                    coin,
+                    &mut psbt_v_bitbox,
+                    Some(script_config),
+                    pb::btc_sign_init_request::FormatUnit::Default,
+                ))
+                .map_err(client_error)?;
+            let signed_psbt = btc_heritage::bitcoin::psbt::PartiallySignedTransaction::deserialize(
+                &psbt_v_bitbox.serialize(),
+            )
+            .map_err(Error::generic)?;
+
+            for (index, (input, signed_input)) in psbt
+                .inputs
+                .iter_mut()
+                .zip(signed_psbt.inputs.into_iter())
+                .enumerate()
+            {
+                if signed_input.tap_key_sig.is_some() && input.tap_key_sig.is_none() {
+                    log::debug!("index: {index}, tap_key_sig");
+                    input.tap_key_sig = signed_input.tap_key_sig;
+                    signed_inputs.insert(index);
+                }
+                for (key, sig) in signed_input.tap_script_sigs {
+                    if !input.tap_script_sigs.contains_key(&key) {
+                        log::debug!("index: {index}, key: {}, tapleaf_hash: {}", key.0, key.1);
+                        input.tap_script_sigs.insert(key, sig);
+                        signed_inputs.insert(index);
+                    }
+                }
+            }
+        }
+        Ok(signed_inputs.len())
+    }
+
+    fn derive_accounts_xpubs(&self, range: core::ops::Range<u32>) -> Result<Vec<AccountXPub>> {
+        let client = self.bitbox_client()?;
+        let coin = BitBoxClient::coin(self.network);
+        let cointype_path_segment = match self.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let base_derivation_path = DerivationPath::from(vec![
+            ChildNumber::from_hardened_idx(86).unwrap(),
+            ChildNumber::from_hardened_idx(cointype_path_segment).unwrap(),
+        ]);
+
+        range
+            .into_iter()
+            .map(|i| {
+                let derivation_path = base_derivation_path
+                    .extend([ChildNumber::from_hardened_idx(i)
+                        .map_err(|_| Error::AccountDerivationIndexOutOfBound(i))?]);
+                // Because for now we are bound to the rust-bitcoin version of BDK
+                // which is different than the one used by bitbox_api
+                let keypath =
+                    bitcoin::bip32::DerivationPath::from_str(&derivation_path.to_string())
+                        .map_err(Error::generic)?;
+                let xpub = client
+                    .runtime
+                    .block_on(client.bitbox.btc_xpub(
+                        coin,
+                        &(&keypath).into(),
+                        match self.network {
+                            Network::Bitcoin => pb::btc_pub_request::XPubType::Xpub,
+                            _ => pb::btc_pub_request::XPubType::Tpub,
+                        },
+                        false,
+                    ))
+                    .map_err(client_error)?;
+                let derivation_path_str = derivation_path.to_string();
+
+                let desc_pub_key = format!(
+                    "[{}/{}]{}/*",
+                    self.fingerprint,
+                    &derivation_path_str[2..],
+                    xpub
+                );
+                log::debug!("{derivation_path_str} from BitBox: {desc_pub_key}");
+                Ok(AccountXPub::try_from(desc_pub_key.as_str())?)
+            })
+            .collect()
+    }
+
+    fn derive_heir_config(
+        &self,
+        _heir_config_type: super::HeirConfigType,
+    ) -> Result<btc_heritage::HeirConfig> {
+        Err(Error::BitBoxHeirUnsupported)
+    }
+
+    fn backup_mnemonic(&self) -> Result<MnemonicBackup> {
+        Err(Error::BitBoxBackupMnemonicUnsupported)
+    }
+}
+
+impl BoundFingerprint for BitBoxKey {
+    fn fingerprint(&self) -> crate::errors::Result<Fingerprint> {
+        Ok(self.fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_key_origin_info() {
+        let key = "[9c7088e3/86'/1'/0']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv";
+        let koi = key_origin_info(key).unwrap();
+        assert_eq!(
+            koi.root_fingerprint,
+            Some(bitcoin::bip32::Fingerprint::from_str("9c7088e3").unwrap())
+        );
+        assert_eq!(
+            koi.xpub.to_string(),
+            "tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv"
+        );
+        assert!(key_origin_info("9c7088e3/86'/1'/0'tpub").is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use core::str::FromStr;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    errors::{Error, Result},
+    BoundFingerprint,
+};
+
+use btc_heritage::{
+    bitcoin::{
+        bip32::{ChildNumber, DerivationPath, ExtendedPubKey, Fingerprint},
+        Network,
+    },
+    AccountXPub, PartiallySignedTransaction,
+};
+use serde::{Deserialize, Serialize};
+
+use super::MnemonicBackup;
+
+const DEFAULT_HWI_EXECUTABLE: &str = "hwi";
+
+/// A hardware-wallet device, as listed by the `enumerate` command of HWI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HwiDevice {
+    #[serde(rename = "type")]
+    pub device_type: String,
+    pub model: String,
+    pub path: String,
+    /// The master fingerprint of the device, [None] if HWI could not retrieve it,
+    /// e.g. because the device is locked
+    #[serde(default)]
+    pub fingerprint: Option<Fingerprint>,
+    #[serde(default)]
+    pub needs_pin_sent: bool,
+    #[serde(default)]
+    pub needs_passphrase_sent: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A [KeyProvider](super::KeyProvider) bridging to any hardware-wallet supported by
+/// [HWI](https://github.com/bitcoin-core/HWI), by calling its command-line interface.
+///
+/// The device is identified by its master fingerprint, so it does not matter on which
+/// USB port it is connected. Whether a device can sign the inputs of an Heritage wallet
+/// depends on its firmware support of Taproot scripts.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HwiKey {
+    fingerprint: Fingerprint,
+    network: Network,
+    /// The HWI executable, `hwi` from the `PATH` if [None]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hwi_executable: Option<PathBuf>,
+}
+
+impl HwiKey {
+    /// Create an [HwiKey] for the connected device with the given `fingerprint`
+    ///
+    /// # Errors
+    /// Returns an error if HWI cannot be executed or if no such device is connected
+    pub fn new(
+        fingerprint: Fingerprint,
+        network: Network,
+        hwi_executable: Option<PathBuf>,
+    ) -> Result<Self> {
+        let devices = Self::enumerate(hwi_executable.as_deref())?;
+        if !devices
+            .iter()
+            .any(|device| device.fingerprint == Some(fingerprint))
+        {
+            return Err(Error::HwiDeviceNotFound(fingerprint));
+        }
+        Ok(Self {
+            fingerprint,
+            network,
+            hwi_executable,
+        })
+    }
+
+    /// List the hardware-wallet devices currently connected
+    pub fn enumerate(hwi_executable: Option<&Path>) -> Result<Vec<HwiDevice>> {
+        let output = run_hwi(
+            hwi_executable.unwrap_or(Path::new(DEFAULT_HWI_EXECUTABLE)),
+            &["enumerate"],
+        )?;
+        Ok(serde_json::from_value(output)?)
+    }
+
+    fn chain(&self) -> &'static str {
+        match self.network {
+            Network::Bitcoin => "main",
+            Network::Testnet => "test",
+            Network::Signet => "signet",
+            _ => "regtest",
+        }
+    }
+
+    /// Run an HWI command against the device of this [HwiKey]
+    fn hwi_command(&self, args: &[&str]) -> Result<serde_json::Value> {
+        let fingerprint = self.fingerprint.to_string();
+        let mut full_args = vec!["--fingerprint", &fingerprint, "--chain", self.chain()];
+        full_args.extend_from_slice(args);
+        run_hwi(
+            self.hwi_executable
+                .as_deref()
+                .unwrap_or(Path::new(DEFAULT_HWI_EXECUTABLE)),
+            &full_args,
+        )
+    }
+}
+
+/// Execute HWI with `args` and return its JSON output
+fn run_hwi(hwi_executable: &Path, args: &[&str]) -> Result<serde_json::Value> {
+    log::debug!("Executing {} {}", hwi_executable.display(), args.join(" "));
+    let output = Command::new(hwi_executable)
+        .args(args)
+        .output()
+        .map_err(|e| {
+            Error::HwiError(format!("cannot execute {}: {e}", hwi_executable.display()))
+        })?;
+    parse_hwi_output(&output.stdout)
+}
+
+/// Parse the JSON output of HWI, turning its `{"error": ..., "code": ...}` answers into errors
+fn parse_hwi_output(stdout: &[u8]) -> Result<serde_json::Value> {
+    let value: serde_json::Value = serde_json::from_slice(stdout)
+        .map_err(|e| Error::HwiError(format!("invalid HWI output: {e}")))?;
+    if let Some(error) = value.get("error") {
+        return Err(Error::HwiError(
+            error
+                .as_str()
+                .map(str::to_owned)
+                .unwrap_or(error.to_string()),
+        ));
+    }
+    Ok(value)
+}
+
+impl super::KeyProvider for HwiKey {
+    fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) -> Result<usize> {
+        let owned_inputs = psbt
+            .inputs
+            .iter()
+            .filter(|input| {
+                input
+                    .tap_key_origins
+                    .values()
+                    .any(|(_, (fg, _))| fg == &self.fingerprint)
+            })
+            .count();
+        if owned_inputs == 0 {
+            return Ok(0);
+        }
+
+        let output = self.hwi_command(&["signtx", &psbt.to_string()])?;
+        let signed_psbt = output
+            .get("psbt")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| Error::HwiError("signtx did not return a PSBT".to_owned()))?;
+        let signed_psbt =
+            PartiallySignedTransaction::from_str(signed_psbt).map_err(Error::generic)?;
+        if signed_psbt.unsigned_tx.txid() != psbt.unsigned_tx.txid() {
+            return Err(Error::HwiPsbtMismatch);
+        }
+
+        let mut signed_inputs = HashSet::new();
+        for (index, (input, signed_input)) in psbt
+            .inputs
+            .iter_mut()
+            .zip(signed_psbt.inputs.into_iter())
+            .enumerate()
+        {
+            if signed_input.tap_key_sig.is_some() && input.tap_key_sig.is_none() {
+                log::debug!("index: {index}, tap_key_sig");
+                input.tap_key_sig = signed_input.tap_key_sig;
+                signed_inputs.insert(index);
+            }
+            for (key, sig) in signed_input.tap_script_sigs {
+                if !input.tap_script_sigs.contains_key(&key) {
+                    log::debug!("index: {index}, key: {}, tapleaf_hash: {}", key.0, key.1);
+                    input.tap_script_sigs.insert(key, sig);
+                    signed_inputs.insert(index);
+                }
+            }
+        }
+        Ok(signed_inputs.len())
+    }
+
+    fn derive_accounts_xpubs(&self, range: core::ops::Range<u32>) -> Result<Vec<AccountXPub>> {
+        let cointype_path_segment = match self.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let base_derivation_path = DerivationPath::from(vec![
+            ChildNumber::from_hardened_idx(86).unwrap(),
+            ChildNumber::from_hardened_idx(cointype_path_segment).unwrap(),
+        ]);
+
+        range
+            .into_iter()
+            .map(|i| {
+                let derivation_path = base_derivation_path
+                    .extend([ChildNumber::from_hardened_idx(i)
+                        .map_err(|_| Error::AccountDerivationIndexOutOfBound(i))?]);
+                let derivation_path_str = derivation_path.to_string();
+                let output = self.hwi_command(&["getxpub", &derivation_path_str])?;
+                let xpub = output
+                    .get("xpub")
+                    .and_then(serde_json::Value::as_str)
+                    .ok_or_else(|| Error::HwiError("getxpub did not return an xpub".to_owned()))?;
+                let xpub = ExtendedPubKey::from_str(xpub).map_err(Error::generic)?;
+                // The descriptors of an Heritage wallet expect the origin of the key
+                // to be the master fingerprint and the full account derivation path
+                if xpub.depth != 3 || xpub.child_number != derivation_path[2] {
+                    return Err(Error::HwiError(format!(
+                        "the xpub returned for {derivation_path_str} has an unexpected origin"
+                    )));
+                }
+
+                let desc_pub_key = format!(
+                    "[{}/{}]{}/*",
+                    self.fingerprint,
+                    &derivation_path_str[2..],
+                    xpub
+                );
+                log::debug!("{derivation_path_str} from HWI: {desc_pub_key}");
+                Ok(AccountXPub::try_from(desc_pub_key.as_str())?)
+            })
+            .collect()
+    }
+
+    fn derive_heir_config(
+        &self,
+        _heir_config_type: super::HeirConfigType,
+    ) -> Result<btc_heritage::HeirConfig> {
+        Err(Error::HwiHeirUnsupported)
+    }
+
+    fn backup_mnemonic(&self) -> Result<MnemonicBackup> {
+        Err(Error::HwiBackupMnemonicUnsupported)
+    }
+}
+
+impl BoundFingerprint for HwiKey {
+    fn fingerprint(&self) -> crate::errors::Result<Fingerprint> {
+        Ok(self.fingerprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hwi_output_parsing() {
+        let devices: Vec<HwiDevice> = serde_json::from_value(
+            parse_hwi_output(
+                br#"[{"type": "trezor", "path": "webusb:001:1", "model": "trezor_t", "label": null, "needs_pin_sent": false, "needs_passphrase_sent": false, "fingerprint": "9c7088e3"},
+                     {"type": "coldcard", "path": "0001:0005:00", "model": "coldcard", "needs_pin_sent": false, "needs_passphrase_sent": false, "error": "Could not open client or get fingerprint information", "code": -13}]"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].device_type, "trezor");
+        assert_eq!(
+            devices[0].fingerprint,
+            Some(Fingerprint::from_str("9c7088e3").unwrap())
+        );
+        assert!(devices[1].fingerprint.is_none());
+        assert!(devices[1].error.is_some());
+
+        assert!(matches!(
+            parse_hwi_output(br#"{"error": "No device found", "code": -3}"#),
+            Err(Error::HwiError(e)) if e == "No device found"
+        ));
+        assert!(matches!(
+            parse_hwi_output(b"Traceback (most recent call last):"),
+            Err(Error::HwiError(_))
+        ));
+    }
+}
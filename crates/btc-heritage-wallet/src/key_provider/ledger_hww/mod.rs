@@ -86,6 +86,30 @@ impl Deref for LedgerClient {
     }
 }
 
+/// The registration status of a [LedgerPolicy], see [LedgerKey::verify_registered_policies]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerPolicyStatus {
+    /// The policy is registered and the Ledger accepts its HMAC
+    Registered,
+    /// No policy is registered for the account
+    Missing,
+    /// The policy registered for the account is not the one of the wallet,
+    /// or the account is not used by the wallet anymore
+    Stale,
+    /// The policy is registered but the Ledger rejects its HMAC,
+    /// e.g. because the device was reset
+    InvalidHmac,
+}
+
+/// The result of the verification of a [LedgerPolicy] registration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerPolicyVerification {
+    pub account_id: AccountXPubId,
+    pub policy: LedgerPolicy,
+    pub status: LedgerPolicyStatus,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LedgerKey {
     fingerprint: Fingerprint,
@@ -174,6 +198,89 @@ impl LedgerKey {
             .map(|(account_id, (p, id, hmac))| (*account_id, p.clone(), id.clone(), hmac.clone()))
             .collect()
     }
+    /// Register the `policies` that are not registered yet, or whose account has a different
+    /// policy registered, e.g. after a new subwallet was created. Return the number of policies registered.
+    pub fn register_missing_policies<P>(
+        &mut self,
+        policies: &[LedgerPolicy],
+        progress: P,
+    ) -> Result<usize>
+    where
+        P: Fn(&WalletPolicy),
+    {
+        let to_register = policies
+            .iter()
+            .filter(|policy| {
+                self.registered_policies
+                    .get(&policy.get_account_id())
+                    .map_or(true, |(registered, _, _)| registered != *policy)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if to_register.is_empty() {
+            return Ok(0);
+        }
+        self.register_policies(&to_register, progress)?;
+        Ok(to_register.len())
+    }
+    /// Cross-check the registered policies against `policies`, the current policies of the wallet,
+    /// and ask the Ledger to validate the HMAC of each registration.
+    ///
+    /// Registrations for accounts absent from `policies` are reported as [LedgerPolicyStatus::Stale].
+    pub fn verify_registered_policies(
+        &self,
+        policies: &[LedgerPolicy],
+    ) -> Result<Vec<LedgerPolicyVerification>> {
+        let client = self.ledger_client()?;
+        let mut verifications = policies
+            .iter()
+            .map(|policy| {
+                let account_id = policy.get_account_id();
+                let status = match self.registered_policies.get(&account_id) {
+                    None => LedgerPolicyStatus::Missing,
+                    Some((registered, _, _)) if registered != policy => LedgerPolicyStatus::Stale,
+                    Some((registered, _, hmac)) => {
+                        // The Ledger refuses to derive an address if the HMAC is not valid
+                        match client.get_wallet_address(
+                            &registered.into(),
+                            Some(hmac.into()),
+                            false,
+                            0,
+                            false,
+                        ) {
+                            Ok(_) => LedgerPolicyStatus::Registered,
+                            Err(e) => {
+                                log::warn!(
+                                    "Ledger rejected the policy of account {account_id}: {e:?}"
+                                );
+                                LedgerPolicyStatus::InvalidHmac
+                            }
+                        }
+                    }
+                };
+                LedgerPolicyVerification {
+                    account_id,
+                    policy: policy.clone(),
+                    status,
+                }
+            })
+            .collect::<Vec<_>>();
+        verifications.extend(
+            self.registered_policies
+                .iter()
+                .filter(|(account_id, _)| {
+                    !policies.iter().any(|p| p.get_account_id() == **account_id)
+                })
+                .map(
+                    |(account_id, (registered, _, _))| LedgerPolicyVerification {
+                        account_id: *account_id,
+                        policy: registered.clone(),
+                        status: LedgerPolicyStatus::Stale,
+                    },
+                ),
+        );
+        Ok(verifications)
+    }
 }
 
 impl super::KeyProvider for LedgerKey {
@@ -1,7 +1,7 @@
 use core::str::FromStr;
 
 use bitcoin::hex::{Case, DisplayHex, FromHex};
-use btc_heritage::{AccountXPub, AccountXPubId, SubwalletDescriptorBackup};
+use btc_heritage::{AccountXPub, AccountXPubId, HeritageWalletBackup, SubwalletDescriptorBackup};
 use ledger_bitcoin_client::{WalletPolicy, WalletPubKey};
 use serde::{Deserialize, Serialize};
 
@@ -81,7 +81,7 @@ macro_rules! new_byte_type {
 new_byte_type!(LedgerPolicyId);
 new_byte_type!(LedgerPolicyHMAC);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(into = "String", try_from = "String")]
 pub struct LedgerPolicy(String);
 impl LedgerPolicy {
@@ -97,6 +97,18 @@ impl LedgerPolicy {
             .expect("LedgerPolicy ensure correct format");
         key.descriptor_id()
     }
+    /// Returns the [LedgerPolicy] of every subwallet of `backup`. Subwallets that cannot be
+    /// expressed as a [LedgerPolicy], e.g. those with key-path-only change outputs, are skipped.
+    pub fn from_backup(backup: HeritageWalletBackup) -> Vec<LedgerPolicy> {
+        backup
+            .into_iter()
+            .filter_map(|subwallet_backup| {
+                LedgerPolicy::try_from(subwallet_backup)
+                    .inspect_err(|e| log::warn!("Skipping a subwallet for the Ledger: {e}"))
+                    .ok()
+            })
+            .collect()
+    }
 }
 impl core::fmt::Display for LedgerPolicy {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
@@ -153,21 +165,23 @@ impl TryFrom<String> for LedgerPolicy {
     }
 }
 
-impl From<&LedgerPolicy> for WalletPolicy {
-    fn from(value: &LedgerPolicy) -> Self {
-        let descriptor = &value.0;
+impl LedgerPolicy {
+    /// Split the policy in a [BIP-388](https://github.com/bitcoin/bips/blob/master/bip-0388.mediawiki)
+    /// descriptor template, where each key is replaced by a `@i/**` placeholder, and the list of
+    /// the keys (`[fingerprint/path]xpub`) the placeholders refer to
+    pub(crate) fn descriptor_template_and_keys(&self) -> (String, Vec<String>) {
+        let descriptor = &self.0;
         log::debug!("descriptor={descriptor}");
         let mut descriptor_template = descriptor.clone();
-        let mut keys: Vec<WalletPubKey> = Vec::new();
+        let mut keys: Vec<String> = Vec::new();
         for account_xpub in re_account_xpub().captures_iter(descriptor) {
             log::debug!("account_xpub={account_xpub:?}");
             let key = &account_xpub["key"];
             log::debug!("key={key}");
-            let pubkey = WalletPubKey::from_str(key).expect("xpub format is correct");
-            let desc_index = if let Some(i) = keys.iter().position(|e| e == &pubkey) {
+            let desc_index = if let Some(i) = keys.iter().position(|e| e == key) {
                 i
             } else {
-                keys.push(pubkey);
+                keys.push(key.to_owned());
                 keys.len() - 1
             };
 
@@ -178,11 +192,21 @@ impl From<&LedgerPolicy> for WalletPolicy {
         }
 
         log::debug!("descriptor_template={descriptor_template}");
+        (descriptor_template, keys)
+    }
+}
+
+impl From<&LedgerPolicy> for WalletPolicy {
+    fn from(value: &LedgerPolicy) -> Self {
+        let (descriptor_template, keys) = value.descriptor_template_and_keys();
         Self {
             name: "Heritage".to_owned(),
             version: ledger_bitcoin_client::wallet::Version::V2,
             descriptor_template,
-            keys,
+            keys: keys
+                .iter()
+                .map(|key| WalletPubKey::from_str(key).expect("xpub format is correct"))
+                .collect(),
             threshold: None,
         }
     }
@@ -226,6 +250,21 @@ mod tests {
         assert!(LedgerPolicy::try_from(valid_backup).is_ok())
     }
 
+    #[test]
+    fn from_wallet_backup() {
+        let backup = r#"[{
+            "external_descriptor": "tr([9c7088e3/86'/1'/0']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv/0/*,and_v(v:pk([f0d79bf6/86'/1'/1751476594']tpubDDFibSiSkFTfnLc4cG5X2wwkLjatiWbxb3T6PNbaCuv9uQpeq4i2sRrk7EKFgd56TTTHXpKDrW4JEDfsueAfLYC9CTPAung761RWMcWE3aP/0/*),and_v(v:older(12960),after(1731536000))))",
+            "change_descriptor": "tr([9c7088e3/86'/1'/0']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv/1/*,and_v(v:pk([f0d79bf6/86'/1'/1751476594']tpubDDFibSiSkFTfnLc4cG5X2wwkLjatiWbxb3T6PNbaCuv9uQpeq4i2sRrk7EKFgd56TTTHXpKDrW4JEDfsueAfLYC9CTPAung761RWMcWE3aP/1/*),and_v(v:older(12960),after(1731536000))))"
+        },{
+            "external_descriptor": "tr([44990794/86'/1'/0']tpubDE9DbziEKzUWbomb29YUwersoSERpmogW115aoegGezrf2uKJZfTqNCD5it8u8AzAuDUoCBcGgmwKppcFSEJ4fuLvBTDLsm5hmeK6L7LZcz/0/*,{and_v(v:pk([99ccb69a/86'/1'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b),and_v(v:older(8640),after(1706602192))),{and_v(v:pk([00bdc67c/86'/1'/1751476594'/0/0]03cb072f51f73029ba3023ee0ffb0caa0070ecde5fb849783579c6f8a9b9029157),and_v(v:older(17280),after(1722154192))),and_v(v:pk([53c80c75/86'/1'/1751476594'/0/0]035133a7acfda43784341da5e23a1ecd1ac25be2ded8ceaff151a9a4cd78199b20),and_v(v:older(25920),after(1737706192)))}})",
+            "change_descriptor": "tr([44990794/86'/1'/1']tpubDE9DbziEKzUWdSo28yKWmuEcgaXEF6tP11EB39RiZN5DW5XCEXRhWbMVRBsPv7yuWHBuueuN7WAhQ3kbEdvg4uMfCvwEYd8ay344UtfsWtz/1/*,{and_v(v:pk([99ccb69a/86'/1'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b),and_v(v:older(8640),after(1706602192))),{and_v(v:pk([00bdc67c/86'/1'/1751476594'/0/0]03cb072f51f73029ba3023ee0ffb0caa0070ecde5fb849783579c6f8a9b9029157),and_v(v:older(17280),after(1722154192))),and_v(v:pk([53c80c75/86'/1'/1751476594'/0/0]035133a7acfda43784341da5e23a1ecd1ac25be2ded8ceaff151a9a4cd78199b20),and_v(v:older(25920),after(1737706192)))}})"
+        }]"#;
+        let backup: HeritageWalletBackup = serde_json::from_str(backup).unwrap();
+        let policies = LedgerPolicy::from_backup(backup);
+        assert_eq!(policies.len(), 1);
+        assert_eq!(policies[0].get_account_id(), 0);
+    }
+
     #[test]
     fn from_invalid_backup() {
         let invalid_backup = r#"{
@@ -249,6 +288,24 @@ mod tests {
         assert!(LedgerPolicy::try_from(valid_descriptor).is_ok())
     }
     #[test]
+    fn descriptor_template_and_keys() {
+        let valid_descriptor = r"tr([9c7088e3/86'/1'/0']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv/*,and_v(v:pk([f0d79bf6/86'/1'/1751476594']tpubDDFibSiSkFTfnLc4cG5X2wwkLjatiWbxb3T6PNbaCuv9uQpeq4i2sRrk7EKFgd56TTTHXpKDrW4JEDfsueAfLYC9CTPAung761RWMcWE3aP/*),and_v(v:older(12960),after(1731536000))))";
+        let (descriptor_template, keys) = LedgerPolicy::try_from(valid_descriptor)
+            .unwrap()
+            .descriptor_template_and_keys();
+        assert_eq!(
+            descriptor_template,
+            "tr(@0/**,and_v(v:pk(@1/**),and_v(v:older(12960),after(1731536000))))"
+        );
+        assert_eq!(
+            keys,
+            vec![
+                "[9c7088e3/86'/1'/0']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv",
+                "[f0d79bf6/86'/1'/1751476594']tpubDDFibSiSkFTfnLc4cG5X2wwkLjatiWbxb3T6PNbaCuv9uQpeq4i2sRrk7EKFgd56TTTHXpKDrW4JEDfsueAfLYC9CTPAung761RWMcWE3aP",
+            ]
+        );
+    }
+    #[test]
     fn invalid_descriptor_1() {
         let invalid_descriptor_1 = r"wsh([9c7088e3/86'/1'/0']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv/*";
         assert!(
@@ -0,0 +1,253 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use bip39::Mnemonic;
+use btc_heritage::{
+    bitcoin::hex::{DisplayHex, FromHex},
+    heritage_wallet::backup::Argon2idParams,
+};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
+    AeadCore, Key, XChaCha20Poly1305,
+};
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, Zeroizing};
+
+use super::{LocalKey, StoredMnemonic};
+use crate::errors::{Error, Result};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+
+/// The entropy of a [Mnemonic] encrypted with XChaCha20-Poly1305, using a key derived from
+/// the keystore password with Argon2id
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(super) struct EncryptedMnemonic {
+    kdf: Argon2idParams,
+    /// Hex-encoded nonce
+    nonce: String,
+    /// Hex-encoded ciphertext of the entropy of the [Mnemonic]
+    ciphertext: String,
+}
+
+impl EncryptedMnemonic {
+    fn encrypt(mnemonic: &Mnemonic, keystore_password: &str) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let kdf = Argon2idParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+            salt: salt.as_slice().to_lower_hex_string(),
+        };
+        let cipher = Self::cipher(&kdf, keystore_password)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let entropy = Zeroizing::new(mnemonic.to_entropy());
+        let ciphertext = cipher
+            .encrypt(&nonce, entropy.as_slice())
+            .map_err(|e| Error::LocalKeyKeystoreError(e.to_string()))?;
+        Ok(Self {
+            kdf,
+            nonce: nonce.as_slice().to_lower_hex_string(),
+            ciphertext: ciphertext.as_slice().to_lower_hex_string(),
+        })
+    }
+
+    fn decrypt(&self, keystore_password: &str) -> Result<Mnemonic> {
+        let cipher = Self::cipher(&self.kdf, keystore_password)?;
+        let nonce: [u8; NONCE_LEN] = Vec::<u8>::from_hex(&self.nonce)
+            .ok()
+            .and_then(|nonce| nonce.try_into().ok())
+            .ok_or_else(|| Error::LocalKeyKeystoreError("invalid nonce".to_owned()))?;
+        let ciphertext = Vec::<u8>::from_hex(&self.ciphertext)
+            .map_err(|_| Error::LocalKeyKeystoreError("invalid ciphertext".to_owned()))?;
+        let entropy = Zeroizing::new(
+            cipher
+                .decrypt(&nonce.into(), ciphertext.as_slice())
+                .map_err(|_| Error::LocalKeyKeystoreDecryptionFailed)?,
+        );
+        Mnemonic::from_entropy(&entropy).map_err(|e| Error::LocalKeyKeystoreError(e.to_string()))
+    }
+
+    fn cipher(kdf: &Argon2idParams, keystore_password: &str) -> Result<XChaCha20Poly1305> {
+        let salt = Vec::<u8>::from_hex(&kdf.salt)
+            .ok()
+            .filter(|salt| salt.len() == SALT_LEN)
+            .ok_or_else(|| Error::LocalKeyKeystoreError("invalid salt".to_owned()))?;
+        let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+            .map_err(|e| Error::LocalKeyKeystoreError(e.to_string()))?;
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(keystore_password.as_bytes(), &salt, key.as_mut_slice())
+            .map_err(|e| Error::LocalKeyKeystoreError(e.to_string()))?;
+        Ok(XChaCha20Poly1305::new(Key::from_slice(key.as_slice())))
+    }
+}
+
+impl LocalKey {
+    /// Encrypt the mnemonic of the [LocalKey] with a key derived from `keystore_password`,
+    /// so that only the encrypted mnemonic is serialized, and therefore stored at rest.
+    ///
+    /// The `keystore_password` is unrelated to the optional BIP-39 passphrase of the
+    /// [LocalKey]. The [LocalKey] stays unlocked until [LocalKey::lock] is called.
+    ///
+    /// # Errors
+    /// Returns an error if the keystore is already encrypted
+    pub fn encrypt_keystore(&mut self, keystore_password: &str) -> Result<()> {
+        let StoredMnemonic::Clear(mnemonic) = &self.stored_mnemonic else {
+            return Err(Error::LocalKeyKeystoreError(
+                "the keystore is already encrypted".to_owned(),
+            ));
+        };
+        let encrypted_mnemonic = EncryptedMnemonic::encrypt(mnemonic, keystore_password)?;
+        let StoredMnemonic::Clear(mnemonic) = core::mem::replace(
+            &mut self.stored_mnemonic,
+            StoredMnemonic::Encrypted(encrypted_mnemonic),
+        ) else {
+            unreachable!("checked above");
+        };
+        self.unlocked_mnemonic = Some(mnemonic);
+        Ok(())
+    }
+
+    /// Remove the encryption of the keystore, the mnemonic of the [LocalKey] will
+    /// be serialized in clear again
+    ///
+    /// # Errors
+    /// Returns an error if the keystore is not encrypted or if the `keystore_password` is wrong
+    pub fn decrypt_keystore(&mut self, keystore_password: &str) -> Result<()> {
+        let StoredMnemonic::Encrypted(encrypted_mnemonic) = &self.stored_mnemonic else {
+            return Err(Error::LocalKeyKeystoreError(
+                "the keystore is not encrypted".to_owned(),
+            ));
+        };
+        self.stored_mnemonic =
+            StoredMnemonic::Clear(encrypted_mnemonic.decrypt(keystore_password)?);
+        self.unlocked_mnemonic = None;
+        Ok(())
+    }
+
+    /// Re-encrypt the keystore with a key derived from `new_keystore_password`, using a
+    /// fresh salt and nonce. The lock state of the [LocalKey] is left untouched.
+    ///
+    /// # Errors
+    /// Returns an error if the keystore is not encrypted or if the `current_keystore_password` is wrong
+    pub fn change_keystore_password(
+        &mut self,
+        current_keystore_password: &str,
+        new_keystore_password: &str,
+    ) -> Result<()> {
+        let StoredMnemonic::Encrypted(encrypted_mnemonic) = &self.stored_mnemonic else {
+            return Err(Error::LocalKeyKeystoreError(
+                "the keystore is not encrypted".to_owned(),
+            ));
+        };
+        let mnemonic = encrypted_mnemonic.decrypt(current_keystore_password)?;
+        self.stored_mnemonic = StoredMnemonic::Encrypted(EncryptedMnemonic::encrypt(
+            &mnemonic,
+            new_keystore_password,
+        )?);
+        Ok(())
+    }
+
+    /// Decrypt the mnemonic of an encrypted keystore and keep it in memory,
+    /// until [LocalKey::lock] is called or the [LocalKey] is dropped
+    ///
+    /// # Errors
+    /// Returns [Error::LocalKeyKeystoreDecryptionFailed] if the `keystore_password` is
+    /// wrong or if the keystore was tampered with
+    pub fn unlock(&mut self, keystore_password: &str) -> Result<()> {
+        if let StoredMnemonic::Encrypted(encrypted_mnemonic) = &self.stored_mnemonic {
+            self.unlocked_mnemonic = Some(encrypted_mnemonic.decrypt(keystore_password)?);
+        }
+        Ok(())
+    }
+
+    /// Erase the secrets of the [LocalKey] from memory: the decrypted mnemonic of an
+    /// encrypted keystore and the cached BIP-39 passphrase, if any
+    pub fn lock(&mut self) {
+        // The Mnemonic is zeroized on drop
+        self.unlocked_mnemonic = None;
+        self.cached_password.zeroize();
+    }
+
+    /// Return `true` if the mnemonic of the [LocalKey] is encrypted at rest
+    pub fn is_keystore_encrypted(&self) -> bool {
+        matches!(self.stored_mnemonic, StoredMnemonic::Encrypted(_))
+    }
+
+    /// Return `true` if the keystore is encrypted and must be unlocked before use
+    pub fn is_locked(&self) -> bool {
+        self.is_keystore_encrypted() && self.unlocked_mnemonic.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::KeyProvider;
+    use btc_heritage::bitcoin::Network;
+
+    const MNEMONIC: &str =
+        "owner owner owner owner owner owner owner owner owner owner owner panther";
+
+    #[test]
+    fn keystore_lifecycle() {
+        let mut local_key =
+            LocalKey::restore(Mnemonic::parse(MNEMONIC).unwrap(), None, Network::Regtest);
+        let account_xpubs = local_key.derive_accounts_xpubs(0..2).unwrap();
+        local_key.encrypt_keystore("keystore password").unwrap();
+        assert!(local_key.is_keystore_encrypted());
+        assert!(!local_key.is_locked());
+        assert!(local_key.encrypt_keystore("another password").is_err());
+
+        // Only the encrypted mnemonic is serialized
+        let serialized = serde_json::to_string(&local_key).unwrap();
+        assert!(!serialized.contains("owner"));
+        let mut local_key: LocalKey = serde_json::from_str(&serialized).unwrap();
+        assert!(local_key.is_locked());
+        assert!(matches!(
+            local_key.derive_accounts_xpubs(0..2),
+            Err(Error::LocalKeyLocked)
+        ));
+        assert!(matches!(
+            local_key.backup_mnemonic(),
+            Err(Error::LocalKeyLocked)
+        ));
+
+        assert!(matches!(
+            local_key.unlock("wrong password"),
+            Err(Error::LocalKeyKeystoreDecryptionFailed)
+        ));
+        local_key.unlock("keystore password").unwrap();
+        assert_eq!(
+            local_key.derive_accounts_xpubs(0..2).unwrap(),
+            account_xpubs
+        );
+        local_key.lock();
+        assert!(local_key.is_locked());
+
+        // Key rotation
+        assert!(matches!(
+            local_key.change_keystore_password("wrong password", "new password"),
+            Err(Error::LocalKeyKeystoreDecryptionFailed)
+        ));
+        local_key
+            .change_keystore_password("keystore password", "new password")
+            .unwrap();
+        assert!(local_key.is_locked());
+        assert!(matches!(
+            local_key.unlock("keystore password"),
+            Err(Error::LocalKeyKeystoreDecryptionFailed)
+        ));
+        local_key.unlock("new password").unwrap();
+        assert_eq!(
+            local_key.derive_accounts_xpubs(0..2).unwrap(),
+            account_xpubs
+        );
+
+        // Back to a clear keystore
+        local_key.decrypt_keystore("new password").unwrap();
+        assert!(!local_key.is_keystore_encrypted());
+        assert!(serde_json::to_string(&local_key).unwrap().contains("owner"));
+    }
+}
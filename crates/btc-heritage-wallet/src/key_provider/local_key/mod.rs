@@ -22,17 +22,40 @@ use btc_heritage::{
     AccountXPub, HeirConfig, SingleHeirPubkey,
 };
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 
 use super::{HeirConfigType, MnemonicBackup};
 
+mod keystore;
+#[cfg(feature = "slip39")]
+mod slip39;
+
+/// How the mnemonic of a [LocalKey] is stored at rest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum StoredMnemonic {
+    #[serde(rename = "mnemonic")]
+    Clear(Mnemonic),
+    #[serde(rename = "encrypted_mnemonic")]
+    Encrypted(keystore::EncryptedMnemonic),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LocalKey {
-    mnemonic: Mnemonic,
+    #[serde(flatten)]
+    stored_mnemonic: StoredMnemonic,
     network: Network,
     fingerprint: Fingerprint,
     with_password: bool,
     #[serde(default, skip)]
     cached_password: Option<String>,
+    /// The decrypted mnemonic, if the keystore is encrypted and unlocked
+    #[serde(default, skip)]
+    unlocked_mnemonic: Option<Mnemonic>,
+}
+impl Drop for LocalKey {
+    fn drop(&mut self) {
+        self.cached_password.zeroize();
+    }
 }
 impl LocalKey {
     /// Generate a new LocalKey with a random Mnemonic
@@ -54,12 +77,51 @@ impl LocalKey {
         let fingerprint = LocalKey::_xprv(&mnemo, password.as_ref().map(|s| s.as_str()), network)
             .fingerprint(&Secp256k1::signing_only());
         Self {
-            mnemonic: mnemo,
+            stored_mnemonic: StoredMnemonic::Clear(mnemo),
             network,
             fingerprint,
             with_password: password.is_some(),
             cached_password: password,
+            unlocked_mnemonic: None,
+        }
+    }
+
+    /// Same as [LocalKey::restore] but verifies that the `mnemo` and `password` derive the
+    /// wallet with the `expected_fingerprint`, so that a mistyped passphrase cannot silently
+    /// restore another wallet
+    ///
+    /// # Errors
+    /// Returns [Error::LocalKeyFingerprintMismatch] if the derived fingerprint is not the expected one
+    pub fn restore_with_expected_fingerprint(
+        mnemo: Mnemonic,
+        password: Option<String>,
+        network: Network,
+        expected_fingerprint: Fingerprint,
+    ) -> Result<Self> {
+        let local_key = Self::restore(mnemo, password, network);
+        local_key.verify_fingerprint(expected_fingerprint)?;
+        Ok(local_key)
+    }
+
+    /// Verify that the mnemonic and the current password of the [LocalKey] derive
+    /// the wallet with the `expected_fingerprint`, e.g. the fingerprint of the online wallet
+    ///
+    /// # Errors
+    /// Returns [Error::LocalKeyMissingPassword] if the password was not provided yet,
+    /// see [LocalKey::init_local_key], [Error::LocalKeyLocked] if the keystore is locked,
+    /// or [Error::LocalKeyFingerprintMismatch] if the derived fingerprint is not the expected one
+    pub fn verify_fingerprint(&self, expected_fingerprint: Fingerprint) -> Result<()> {
+        if self.with_password && self.cached_password.is_none() {
+            return Err(Error::LocalKeyMissingPassword);
         }
+        let actual = self.xprv()?.fingerprint(&Secp256k1::signing_only());
+        if actual != expected_fingerprint {
+            return Err(Error::LocalKeyFingerprintMismatch {
+                expected: expected_fingerprint,
+                actual,
+            });
+        }
+        Ok(())
     }
 
     pub fn init_local_key(&mut self, password: Option<String>) -> Result<()> {
@@ -68,7 +130,7 @@ impl LocalKey {
                 .replace(password.ok_or(Error::LocalKeyMissingPassword)?);
         }
 
-        if self.xprv().fingerprint(&Secp256k1::signing_only()) != self.fingerprint {
+        if self.xprv()?.fingerprint(&Secp256k1::signing_only()) != self.fingerprint {
             return Err(Error::IncoherentLocalKeyFingerprint);
         }
 
@@ -84,12 +146,75 @@ impl LocalKey {
             .expect("I really don't see how it could fail")
     }
 
-    fn xprv(&self) -> ExtendedPrivKey {
-        LocalKey::_xprv(
-            &self.mnemonic,
+    /// The mnemonic of the [LocalKey]
+    ///
+    /// # Errors
+    /// Returns [Error::LocalKeyLocked] if the keystore is encrypted and locked
+    fn mnemonic(&self) -> Result<&Mnemonic> {
+        match &self.stored_mnemonic {
+            StoredMnemonic::Clear(mnemonic) => Ok(mnemonic),
+            StoredMnemonic::Encrypted(_) => {
+                self.unlocked_mnemonic.as_ref().ok_or(Error::LocalKeyLocked)
+            }
+        }
+    }
+
+    fn xprv(&self) -> Result<ExtendedPrivKey> {
+        Ok(LocalKey::_xprv(
+            self.mnemonic()?,
             self.cached_password.as_ref().map(|s| s.as_str()),
             self.network,
-        )
+        ))
+    }
+
+    /// Same as [LocalKey::xprv] but ensures the password was provided and that it derives
+    /// the wallet of this [LocalKey], before the key is used to sign or derive anything
+    fn checked_xprv(&self) -> Result<ExtendedPrivKey> {
+        if self.with_password && self.cached_password.is_none() {
+            return Err(Error::LocalKeyMissingPassword);
+        }
+        let xprv = self.xprv()?;
+        if xprv.fingerprint(&Secp256k1::signing_only()) != self.fingerprint {
+            return Err(Error::IncoherentLocalKeyFingerprint);
+        }
+        Ok(xprv)
+    }
+
+    /// Derive the BIP-352 scan and spend keys of the given account, i.e.
+    /// `m/352'/<coin_type>'/<account>'/1'/0` and `m/352'/<coin_type>'/<account>'/0'/0`
+    #[cfg(feature = "silent-payments")]
+    pub fn derive_silent_payment_keys(
+        &self,
+        account: u32,
+    ) -> Result<crate::silent_payments::SilentPaymentKeys> {
+        let cointype_path_segment = match self.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let account_path = DerivationPath::from(vec![
+            ChildNumber::from_hardened_idx(352).unwrap(),
+            ChildNumber::from_hardened_idx(cointype_path_segment).unwrap(),
+            ChildNumber::from_hardened_idx(account)
+                .map_err(|_| Error::AccountDerivationIndexOutOfBound(account))?,
+        ]);
+        let secp = Secp256k1::new();
+        let xprv = self.checked_xprv()?;
+        let derive_key = |branch: u32| {
+            xprv.derive_priv(
+                &secp,
+                &account_path.extend([
+                    ChildNumber::from_hardened_idx(branch).unwrap(),
+                    ChildNumber::from_normal_idx(0).unwrap(),
+                ]),
+            )
+            .expect("I really don't see how it could fail")
+            .private_key
+        };
+        Ok(crate::silent_payments::SilentPaymentKeys::new(
+            derive_key(1),
+            derive_key(0).public_key(&secp),
+            self.network,
+        ))
     }
 }
 
@@ -108,10 +233,9 @@ impl LocalKey {
 
     fn derive_xpub(
         &self,
-        master_xprv: Option<ExtendedPrivKey>,
+        xprv: ExtendedPrivKey,
         path: DerivationPath,
     ) -> DescriptorXKey<ExtendedPubKey> {
-        let xprv = master_xprv.unwrap_or_else(|| self.xprv());
         // Just to be clear, this is the master private key
         // This assertion should never fail
         assert!(
@@ -147,7 +271,7 @@ impl super::KeyProvider for LocalKey {
         &self,
         psbt: &mut btc_heritage::PartiallySignedTransaction,
     ) -> crate::errors::Result<usize> {
-        let xprv = self.xprv();
+        let xprv = self.checked_xprv()?;
         // Just to be clear, this is the master private key
         // This assertion should never fail
         assert!(
@@ -382,7 +506,7 @@ impl super::KeyProvider for LocalKey {
         &self,
         range: core::ops::Range<u32>,
     ) -> crate::errors::Result<Vec<AccountXPub>> {
-        let xprv = self.xprv();
+        let xprv = self.checked_xprv()?;
         let base_derivation_path = self.base_derivation_path();
 
         let xpubs = range
@@ -391,7 +515,7 @@ impl super::KeyProvider for LocalKey {
                 let derivation_path = base_derivation_path
                     .extend([ChildNumber::from_hardened_idx(i)
                         .map_err(|_| Error::AccountDerivationIndexOutOfBound(i))?]);
-                let dxpub = self.derive_xpub(Some(xprv), derivation_path);
+                let dxpub = self.derive_xpub(xprv, derivation_path);
                 let xpub = DescriptorPublicKey::XPub(dxpub);
                 Ok(AccountXPub::try_from(xpub).expect("we ensured validity"))
             })
@@ -406,7 +530,7 @@ impl super::KeyProvider for LocalKey {
         let base_derivation_path = self.base_derivation_path();
         let heir_derivation_path = base_derivation_path
             .extend([ChildNumber::from_hardened_idx(u32::from_be_bytes(*b"heir")).unwrap()]);
-        let heir_xpub = self.derive_xpub(None, heir_derivation_path);
+        let heir_xpub = self.derive_xpub(self.checked_xprv()?, heir_derivation_path);
 
         match heir_config_type {
             HeirConfigType::SingleHeirPubkey => {
@@ -441,7 +565,7 @@ impl super::KeyProvider for LocalKey {
 
     fn backup_mnemonic(&self) -> Result<MnemonicBackup> {
         Ok(MnemonicBackup {
-            mnemonic: self.mnemonic.clone(),
+            mnemonic: self.mnemonic()?.clone(),
             fingerprint: self.fingerprint,
             with_password: self.with_password,
         })
@@ -461,8 +585,10 @@ mod tests {
     use super::*;
     use btc_heritage::{
         psbttests::{get_test_signed_psbt, get_test_unsigned_psbt, TestPsbt},
+        subwallet_config::SubwalletConfig,
         utils::extract_tx,
     };
+    use core::str::FromStr;
     use std::fmt::Write;
 
     const NETWORK: Network = Network::Regtest;
@@ -947,10 +1073,100 @@ mod tests {
             let key = bytes_to_hex_string(mnemo.to_seed(password));
             let xpriv = LocalKey::restore(mnemo, Some(password.to_owned()), Network::Bitcoin)
                 .xprv()
+                .unwrap()
                 .to_string();
             assert_eq!(mnemostr, v_mnemostr);
             assert_eq!(key, v_key);
             assert_eq!(xpriv, v_xpriv);
         }
     }
+
+    #[test]
+    fn passphrase_fingerprint_verification() {
+        let mnemo = Mnemonic::parse(KEY_PROVIDERS[TestKeyProvider::Owner as usize][1]).unwrap();
+        let owner_fingerprint = Fingerprint::from_str("9c7088e3").unwrap();
+
+        // A passphrase derives another wallet
+        let local_key = LocalKey::restore(mnemo.clone(), Some("passphrase".to_owned()), NETWORK);
+        assert!(local_key.require_password());
+        let passphrase_fingerprint = local_key.fingerprint().unwrap();
+        assert_ne!(passphrase_fingerprint, owner_fingerprint);
+        assert!(local_key.verify_fingerprint(passphrase_fingerprint).is_ok());
+
+        // Restoring with the wrong passphrase is detected
+        assert!(LocalKey::restore_with_expected_fingerprint(
+            mnemo.clone(),
+            Some("passphrase".to_owned()),
+            NETWORK,
+            passphrase_fingerprint
+        )
+        .is_ok());
+        assert!(matches!(
+            LocalKey::restore_with_expected_fingerprint(
+                mnemo.clone(),
+                Some("Passphrase".to_owned()),
+                NETWORK,
+                passphrase_fingerprint
+            ),
+            Err(Error::LocalKeyFingerprintMismatch { expected, .. }) if expected == passphrase_fingerprint
+        ));
+        assert!(LocalKey::restore_with_expected_fingerprint(
+            mnemo.clone(),
+            None,
+            NETWORK,
+            owner_fingerprint
+        )
+        .is_ok());
+
+        // The password is not persisted, so it must be provided again before signing
+        let mut local_key: LocalKey =
+            serde_json::from_str(&serde_json::to_string(&local_key).unwrap()).unwrap();
+        assert!(matches!(
+            local_key.derive_accounts_xpubs(0..1),
+            Err(Error::LocalKeyMissingPassword)
+        ));
+        assert!(matches!(
+            local_key.sign_psbt(&mut get_test_unsigned_psbt(TestPsbt::OwnerDrain)),
+            Err(Error::LocalKeyMissingPassword)
+        ));
+        assert!(matches!(
+            local_key.init_local_key(Some("Passphrase".to_owned())),
+            Err(Error::IncoherentLocalKeyFingerprint)
+        ));
+        // A wrong cached password cannot be used to sign
+        assert!(matches!(
+            local_key.sign_psbt(&mut get_test_unsigned_psbt(TestPsbt::OwnerDrain)),
+            Err(Error::IncoherentLocalKeyFingerprint)
+        ));
+        local_key
+            .init_local_key(Some("passphrase".to_owned()))
+            .unwrap();
+        assert_eq!(
+            local_key
+                .sign_psbt(&mut get_test_unsigned_psbt(TestPsbt::OwnerDrain))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn self_test() {
+        let owner = get_test_key_provider(TestKeyProvider::Owner);
+        let account_xpub = owner.derive_accounts_xpubs(0..1).unwrap().remove(0);
+        let descriptor = SubwalletConfig::create_keypath_descriptor(&account_xpub, 0);
+        assert!(owner.self_test(&descriptor).is_ok());
+
+        // Another key, or the same mnemonic with a passphrase, cannot sign the challenge
+        let backup = get_test_key_provider(TestKeyProvider::Backup);
+        assert!(matches!(
+            backup.self_test(&descriptor),
+            Err(Error::KeyProviderSelfTestFailed(_))
+        ));
+        let mnemo = Mnemonic::parse(KEY_PROVIDERS[TestKeyProvider::Owner as usize][1]).unwrap();
+        let with_passphrase = LocalKey::restore(mnemo, Some("passphrase".to_owned()), NETWORK);
+        assert!(matches!(
+            with_passphrase.self_test(&descriptor),
+            Err(Error::KeyProviderSelfTestFailed(_))
+        ));
+    }
 }
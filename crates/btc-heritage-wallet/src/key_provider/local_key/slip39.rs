@@ -0,0 +1,102 @@
+use bip39::Mnemonic;
+use btc_heritage::bitcoin::Network;
+
+use super::LocalKey;
+use crate::errors::{Error, Result};
+
+/// The iteration exponent of the SLIP-39 encryption, the one used by Trezor
+const ITERATION_EXPONENT: u8 = 1;
+
+impl LocalKey {
+    /// Split the entropy of the mnemonic of the [LocalKey] into `share_count` SLIP-39 shares,
+    /// `threshold` of which are needed to restore it with [LocalKey::restore_from_slip39_shares].
+    /// Each share is returned as a space-separated list of words.
+    ///
+    /// The BIP-39 password, if any, is not part of the shares and will still be needed
+    /// to restore the [LocalKey]. Everything is done offline.
+    ///
+    /// # Errors
+    /// Returns an error if `threshold` is not between 1 and `share_count`, if `share_count`
+    /// is more than 16 or if `threshold` is 1 while `share_count` is not
+    pub fn slip39_shares(&self, threshold: u8, share_count: u8) -> Result<Vec<String>> {
+        if threshold == 0 || threshold > share_count || share_count > 16 {
+            return Err(Error::InvalidSlip39Shares(format!(
+                "cannot create {threshold}-of-{share_count} shares"
+            )));
+        }
+        let group_shares = sssmc39::generate_mnemonics(
+            1,
+            &[(threshold, share_count)],
+            &self.mnemonic()?.to_entropy(),
+            "",
+            ITERATION_EXPONENT,
+        )
+        .map_err(|e| Error::InvalidSlip39Shares(e.to_string()))?;
+        Ok(group_shares
+            .iter()
+            .map(|group_share| group_share.mnemonic_list())
+            .collect::<core::result::Result<Vec<_>, _>>()
+            .map_err(|e| Error::InvalidSlip39Shares(e.to_string()))?
+            .into_iter()
+            .flatten()
+            .map(|words| words.join(" "))
+            .collect())
+    }
+
+    /// Restore a [LocalKey] from SLIP-39 `shares` created by [LocalKey::slip39_shares].
+    /// The `password` is the BIP-39 password of the original [LocalKey], if any.
+    ///
+    /// # Errors
+    /// Returns an error if the `shares` are invalid, inconsistent or not enough
+    pub fn restore_from_slip39_shares(
+        shares: &[String],
+        password: Option<String>,
+        network: Network,
+    ) -> Result<Self> {
+        let shares = shares
+            .iter()
+            .map(|share| share.split_whitespace().map(str::to_owned).collect())
+            .collect::<Vec<Vec<String>>>();
+        let entropy = sssmc39::combine_mnemonics(&shares, "")
+            .map_err(|e| Error::InvalidSlip39Shares(e.to_string()))?;
+        let mnemo = Mnemonic::from_entropy(&entropy)
+            .map_err(|e| Error::InvalidSlip39Shares(e.to_string()))?;
+        Ok(LocalKey::restore(mnemo, password, network))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BoundFingerprint;
+
+    #[test]
+    fn slip39_shares_round_trip() {
+        let local_key = LocalKey::generate(24, None, Network::Regtest);
+        let shares = local_key.slip39_shares(2, 3).unwrap();
+        assert_eq!(shares.len(), 3);
+
+        for (i, j) in [(0, 1), (0, 2), (2, 1)] {
+            let restored = LocalKey::restore_from_slip39_shares(
+                &[shares[i].clone(), shares[j].clone()],
+                None,
+                Network::Regtest,
+            )
+            .unwrap();
+            assert_eq!(restored.mnemonic().unwrap(), local_key.mnemonic().unwrap());
+            assert_eq!(
+                restored.fingerprint().unwrap(),
+                local_key.fingerprint().unwrap()
+            );
+        }
+
+        // Not enough shares
+        assert!(
+            LocalKey::restore_from_slip39_shares(&shares[..1], None, Network::Regtest).is_err()
+        );
+        // Invalid thresholds
+        assert!(local_key.slip39_shares(0, 3).is_err());
+        assert!(local_key.slip39_shares(4, 3).is_err());
+        assert!(local_key.slip39_shares(2, 17).is_err());
+    }
+}
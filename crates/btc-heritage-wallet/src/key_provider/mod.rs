@@ -6,11 +6,22 @@ use crate::{
 };
 use bip39::Mnemonic;
 use btc_heritage::{
-    bitcoin::bip32::Fingerprint, AccountXPub, HeirConfig, PartiallySignedTransaction,
+    bitcoin::bip32::Fingerprint,
+    miniscript::{Descriptor, DescriptorPublicKey},
+    AccountXPub, HeirConfig, PartiallySignedTransaction,
 };
 
+pub(crate) mod air_gapped;
+#[cfg(feature = "bitbox")]
+pub(crate) mod bitbox_hww;
+pub(crate) mod hwi;
 pub(crate) mod ledger_hww;
 pub(crate) mod local_key;
+mod self_test;
+use air_gapped::AirGappedKey;
+#[cfg(feature = "bitbox")]
+use bitbox_hww::BitBoxKey;
+use hwi::HwiKey;
 use ledger_hww::LedgerKey;
 use local_key::LocalKey;
 use serde::{Deserialize, Serialize};
@@ -35,7 +46,8 @@ pub trait KeyProvider: BoundFingerprint {
     /// Sign all the (Tap) inputs of the given PSBT that can be signed using the privates keys
     /// and return the number of inputs signed.
     fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) -> Result<usize>;
-    /// Return a list of the first `count` account eXtended Public Keys as a [Vec<AccountXPub>]
+    /// Return the account eXtended Public Keys of the accounts in `range` as a [Vec<AccountXPub>],
+    /// with their key origin, ready to be fed to an online wallet
     fn derive_accounts_xpubs(&self, range: Range<u32>) -> Result<Vec<AccountXPub>>;
     /// Return an [HeirConfig] of the [HeirConfigType] asked for.
     /// Both [HeirConfigType::SingleHeirPubkey] and [HeirConfigType::HeirXPubkey] are taken from the account 1751476594 which is the decimal value corresponding
@@ -47,6 +59,17 @@ pub trait KeyProvider: BoundFingerprint {
     /// This is critical information. Assuming there is no password-protection,
     /// the mnemonic is enough to generate any and all wallet private keys
     fn backup_mnemonic(&self) -> Result<MnemonicBackup>;
+    /// Sign a fixed challenge spending a fake output of the first address of `descriptor`,
+    /// a descriptor of the wallet, and verify the signatures against its keys.
+    ///
+    /// It detects a wrong passphrase, a wrong device or a corrupted keystore before the
+    /// [KeyProvider] is needed for a real spend. The challenge transaction spends an output
+    /// that does not exist and can never be broadcasted.
+    fn self_test(&self, descriptor: &Descriptor<DescriptorPublicKey>) -> Result<()> {
+        let mut psbt = self_test::challenge_psbt(descriptor)?;
+        self.sign_psbt(&mut psbt)?;
+        self_test::verify_challenge_signatures(&psbt)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,6 +77,10 @@ pub enum AnyKeyProvider {
     None,
     LocalKey(LocalKey),
     Ledger(LedgerKey),
+    AirGapped(AirGappedKey),
+    Hwi(HwiKey),
+    #[cfg(feature = "bitbox")]
+    BitBox(BitBoxKey),
 }
 
 impl AnyKeyProvider {
@@ -75,6 +102,25 @@ impl AnyKeyProvider {
             _ => false,
         }
     }
+    pub fn is_air_gapped(&self) -> bool {
+        match self {
+            AnyKeyProvider::AirGapped(_) => true,
+            _ => false,
+        }
+    }
+    pub fn is_hwi(&self) -> bool {
+        match self {
+            AnyKeyProvider::Hwi(_) => true,
+            _ => false,
+        }
+    }
+    #[cfg(feature = "bitbox")]
+    pub fn is_bitbox(&self) -> bool {
+        match self {
+            AnyKeyProvider::BitBox(_) => true,
+            _ => false,
+        }
+    }
 }
 
 macro_rules! impl_key_provider_fn {
@@ -88,6 +134,10 @@ macro_rules! impl_key_provider_fn {
                 AnyKeyProvider::None => Err(Error::MissingKeyProvider),
                 AnyKeyProvider::LocalKey(lk) => lk.$fn_name($($a),*),
                 AnyKeyProvider::Ledger(ledger) => ledger.$fn_name($($a),*),
+                AnyKeyProvider::AirGapped(air_gapped) => air_gapped.$fn_name($($a),*),
+                AnyKeyProvider::Hwi(hwi) => hwi.$fn_name($($a),*),
+                #[cfg(feature = "bitbox")]
+                AnyKeyProvider::BitBox(bitbox) => bitbox.$fn_name($($a),*),
             }
     };
 }
@@ -97,6 +147,7 @@ impl KeyProvider for AnyKeyProvider {
     impl_key_provider_fn!(derive_accounts_xpubs(&self, range: Range<u32>) -> Result<Vec<AccountXPub>>);
     impl_key_provider_fn!(derive_heir_config(&self, heir_config_type: HeirConfigType) -> Result<HeirConfig>);
     impl_key_provider_fn!(backup_mnemonic(&self) -> Result<MnemonicBackup>);
+    impl_key_provider_fn!(self_test(&self, descriptor: &Descriptor<DescriptorPublicKey>) -> Result<()>);
 }
 impl BoundFingerprint for AnyKeyProvider {
     impl_key_provider_fn!(fingerprint(&self) -> Result<Fingerprint>);
@@ -122,6 +173,7 @@ macro_rules! impl_key_provider {
             crate::key_provider::impl_key_provider!(derive_accounts_xpubs(&self, range: core::ops::Range<u32>) -> crate::errors::Result<Vec<btc_heritage::AccountXPub>>);
             crate::key_provider::impl_key_provider!(derive_heir_config(&self, heir_config_type: crate::key_provider::HeirConfigType) -> crate::errors::Result<btc_heritage::HeirConfig>);
             crate::key_provider::impl_key_provider!(backup_mnemonic(&self) -> crate::errors::Result<crate::key_provider::MnemonicBackup>);
+            crate::key_provider::impl_key_provider!(self_test(&self, descriptor: &btc_heritage::miniscript::Descriptor<btc_heritage::miniscript::DescriptorPublicKey>) -> crate::errors::Result<()>);
         }
     };
 }
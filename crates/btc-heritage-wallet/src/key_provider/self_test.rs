@@ -0,0 +1,136 @@
+use btc_heritage::{
+    bitcoin::{
+        absolute::LockTime,
+        hashes::{sha256d, Hash},
+        key::{Secp256k1, XOnlyPublicKey},
+        psbt::Psbt,
+        script::PushBytesBuf,
+        secp256k1::Message,
+        sighash::{Prevouts, SighashCache},
+        taproot::{LeafVersion, TapLeafHash},
+        OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    },
+    miniscript::{psbt::PsbtExt, Descriptor, DescriptorPublicKey},
+    subwallet_config::SubwalletConfig,
+    PartiallySignedTransaction,
+};
+
+use crate::errors::{Error, Result};
+
+/// The challenge committed in the self-test transaction. The transaction spends a
+/// non-existent output and can never be broadcasted.
+const SELF_TEST_CHALLENGE: &[u8] = b"Heritage key provider self-test";
+
+/// Value of the fake output spent by the self-test transaction
+const SELF_TEST_VALUE: u64 = 100_000;
+
+fn self_test_error(reason: impl core::fmt::Display) -> Error {
+    Error::KeyProviderSelfTestFailed(reason.to_string())
+}
+
+/// Create the self-test PSBT, spending a fake output of the first address of `descriptor`.
+///
+/// The input is reduced to the spend path of the owner: the key-path, or the owner
+/// quorum leaf if the key-path is unspendable.
+pub(crate) fn challenge_psbt(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+) -> Result<PartiallySignedTransaction> {
+    let descriptor = descriptor.at_derivation_index(0).map_err(self_test_error)?;
+    let Descriptor::Tr(tr) = &descriptor else {
+        return Err(self_test_error("the descriptor is not Taproot"));
+    };
+    let txout = TxOut {
+        value: SELF_TEST_VALUE,
+        script_pubkey: descriptor.script_pubkey(),
+    };
+    let unsigned_tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint {
+                txid: Txid::from_raw_hash(sha256d::Hash::hash(SELF_TEST_CHALLENGE)),
+                vout: 0,
+            },
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: ScriptBuf::new_op_return(
+                &PushBytesBuf::try_from(SELF_TEST_CHALLENGE.to_vec())
+                    .expect("the challenge is small enough"),
+            ),
+        }],
+    };
+    let mut psbt = Psbt::from_unsigned_tx(unsigned_tx).map_err(self_test_error)?;
+    psbt.inputs[0].witness_utxo = Some(txout);
+    psbt.update_input_with_descriptor(0, &descriptor)
+        .map_err(self_test_error)?;
+
+    let input = &mut psbt.inputs[0];
+    if tr.internal_key().to_string() == SubwalletConfig::UNSPENDABLE_INTERNAL_KEY {
+        // The owner quorum leaf is always the first one
+        let (_, owner_leaf) = tr
+            .iter_scripts()
+            .next()
+            .ok_or_else(|| self_test_error("the owner quorum leaf is missing"))?;
+        let owner_script = owner_leaf.encode();
+        let owner_leaf_hash = TapLeafHash::from_script(&owner_script, LeafVersion::TapScript);
+        input.tap_scripts.retain(|_, (s, _)| *s == owner_script);
+        input.tap_key_origins.retain(|_, (leaf_hashes, _)| {
+            leaf_hashes.retain(|leaf_hash| *leaf_hash == owner_leaf_hash);
+            !leaf_hashes.is_empty()
+        });
+    } else {
+        let internal_key = input.tap_internal_key;
+        input.tap_scripts.clear();
+        input
+            .tap_key_origins
+            .retain(|k, _| Some(*k) == internal_key);
+    }
+    Ok(psbt)
+}
+
+/// Verify every signature of the signed self-test PSBT
+///
+/// # Errors
+/// Returns an error if there is no signature or if one of them is invalid
+pub(crate) fn verify_challenge_signatures(psbt: &PartiallySignedTransaction) -> Result<()> {
+    let secp = Secp256k1::verification_only();
+    let input = &psbt.inputs[0];
+    let txout = input
+        .witness_utxo
+        .as_ref()
+        .expect("challenge_psbt sets the witness_utxo");
+    let prevouts = [txout];
+    let prevouts = Prevouts::All(&prevouts);
+    let mut sighash_cache = SighashCache::new(&psbt.unsigned_tx);
+
+    let mut verified_signatures = 0usize;
+    if let Some(signature) = &input.tap_key_sig {
+        let output_key = XOnlyPublicKey::from_slice(&txout.script_pubkey.as_bytes()[2..])
+            .expect("the script_pubkey is a Taproot output");
+        let sighash = sighash_cache
+            .taproot_key_spend_signature_hash(0, &prevouts, signature.hash_ty)
+            .map_err(self_test_error)?;
+        secp.verify_schnorr(&signature.sig, &Message::from(sighash), &output_key)
+            .map_err(|e| self_test_error(format!("invalid key-path signature ({e})")))?;
+        verified_signatures += 1;
+    }
+    for ((key, leaf_hash), signature) in &input.tap_script_sigs {
+        let sighash = sighash_cache
+            .taproot_script_spend_signature_hash(0, &prevouts, *leaf_hash, signature.hash_ty)
+            .map_err(self_test_error)?;
+        secp.verify_schnorr(&signature.sig, &Message::from(sighash), key)
+            .map_err(|e| self_test_error(format!("invalid signature for {key} ({e})")))?;
+        verified_signatures += 1;
+    }
+    if verified_signatures == 0 {
+        return Err(self_test_error(
+            "the key provider did not sign the challenge, it does not control the keys of the wallet",
+        ));
+    }
+    log::info!("Self-test: {verified_signatures} valid signature(s)");
+    Ok(())
+}
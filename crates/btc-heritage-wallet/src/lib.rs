@@ -1,30 +1,57 @@
 mod database;
+mod draft;
 pub mod errors;
+mod guardrails;
 mod heir;
+mod heir_bundle;
 mod heir_wallet;
+mod monitor;
+mod outbox;
 mod psbt_summary;
+mod signing_instructions;
 mod traits;
 mod wallet;
 
 pub mod heritage_provider;
 pub mod key_provider;
 pub mod online_wallet;
+pub mod price_provider;
+pub mod psbt_io;
+#[cfg(feature = "silent-payments")]
+pub mod silent_payments;
 
 pub use btc_heritage;
 pub mod ledger {
     pub use ledger_bitcoin_client::{wallet::Version, WalletPolicy, WalletPubKey};
 }
 
-pub use heritage_provider::{AnyHeritageProvider, Heritage};
+#[cfg(feature = "bitbox")]
+pub mod bitbox {
+    pub use bitbox_api::{NoiseConfig, NoiseConfigNoCache, PersistedNoiseConfig};
+}
+
+pub use heritage_provider::{
+    AnyHeritageProvider, ClaimBatch, ClaimableUtxo, HeirDiscoveryReport, Heritage,
+};
+#[cfg(feature = "bitbox")]
+pub use key_provider::bitbox_hww::BitBoxKey;
 pub use key_provider::{
-    ledger_hww::{policy::LedgerPolicy, LedgerKey},
+    air_gapped::AirGappedKey,
+    hwi::{HwiDevice, HwiKey},
+    ledger_hww::{policy::LedgerPolicy, LedgerKey, LedgerPolicyStatus, LedgerPolicyVerification},
     local_key::LocalKey,
     AnyKeyProvider, HeirConfigType,
 };
 pub use online_wallet::AnyOnlineWallet;
+pub use price_provider::{PriceProvider, ValuedTransactionSummary};
 
+pub use draft::Draft;
+pub use guardrails::{GuardrailOverrides, GuardrailViolation, SpendGuardrails, SpendLimit};
 pub use heir::Heir;
-pub use heir_wallet::HeirWallet;
+pub use heir_bundle::HeirBundle;
+pub use heir_wallet::{HeirClaim, HeirWallet, PreparedClaim};
+pub use monitor::{Alert, AlertSink, MonitorConfig, WalletMonitor};
+pub use outbox::{Outbox, OutboxEntry, OutboxPolicy, OutboxStatus};
 pub use wallet::Wallet;
 
 pub use bip39::{Language, Mnemonic};
@@ -32,5 +59,8 @@ pub use btc_heritage::bitcoin;
 pub use btc_heritage::miniscript;
 pub use database::{Database, DatabaseItem};
 pub use heritage_service_api_client;
-pub use psbt_summary::PsbtSummary;
+pub use psbt_summary::{PsbtSummary, PsbtVerification, SpendPath};
+pub use signing_instructions::{
+    InputInstruction, InstructionsVerification, LockTimeRationale, SigningInstructions,
+};
 pub use traits::*;
@@ -0,0 +1,389 @@
+//! Long-running monitoring of [Wallet]s: periodic synchronization, fee rate refresh and
+//! [Alert]s when heir maturities approach or when the current [HeritageConfig] nears expiration.
+//!
+//! The [WalletMonitor] does not deliver the alerts itself: it hands them to an [AlertSink],
+//! leaving the choice of the channel (stdout, webhook, email...) to the application.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use btc_heritage::{
+    bitcoin::{bip32::Fingerprint, Amount},
+    heritage_wallet::{HeritageUtxo, InheritanceSchedule},
+    utils::timestamp_now,
+    HeritageConfig,
+};
+use serde::Serialize;
+
+use crate::{
+    errors::Result,
+    online_wallet::{AnyOnlineWallet, OnlineWallet},
+    DatabaseItem, Wallet,
+};
+
+/// The schedule of a [WalletMonitor] and the horizons of its [Alert]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorConfig {
+    /// Delay between two synchronizations of a wallet
+    pub sync_interval: Duration,
+    /// Delay between two refreshes of the fee rate of a wallet. Only used for local wallets,
+    /// the Heritage service refreshes the fee rate of its wallets by itself.
+    pub fee_rate_interval: Duration,
+    /// Raise an [Alert::HeirMaturityApproaching] when an heir will be able to spend within this delay
+    pub maturity_horizon: Duration,
+    /// Raise an [Alert::HeritageConfigExpiring] when the current [HeritageConfig] expires within this delay
+    pub expiration_horizon: Duration,
+}
+
+impl Default for MonitorConfig {
+    /// Synchronize every hour, refresh the fee rate every 10 minutes and alert 30 days ahead
+    fn default() -> Self {
+        const DAY: u64 = 24 * 60 * 60;
+        Self {
+            sync_interval: Duration::from_secs(60 * 60),
+            fee_rate_interval: Duration::from_secs(10 * 60),
+            maturity_horizon: Duration::from_secs(30 * DAY),
+            expiration_horizon: Duration::from_secs(30 * DAY),
+        }
+    }
+}
+
+/// An alert raised by a [WalletMonitor]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Alert {
+    /// Some funds of the wallet will be, or already are, spendable by an heir
+    HeirMaturityApproaching {
+        wallet_name: String,
+        heir_fingerprint: Fingerprint,
+        /// The estimated timestamp after which the heir will be able to spend
+        maturity_ts: u64,
+        /// The amount the heir will be able to spend at `maturity_ts`
+        #[serde(with = "btc_heritage::bitcoin::amount::serde::as_sat")]
+        amount: Amount,
+    },
+    /// The current [HeritageConfig] of the wallet will soon expire: the owner should
+    /// set a new one and move the funds to it
+    HeritageConfigExpiring {
+        wallet_name: String,
+        /// The timestamp at which the first heir of the current [HeritageConfig] will be able to spend
+        expiration_ts: u64,
+    },
+    /// The synchronization of the wallet failed
+    SyncFailed { wallet_name: String, error: String },
+}
+
+impl Alert {
+    pub fn wallet_name(&self) -> &str {
+        match self {
+            Alert::HeirMaturityApproaching { wallet_name, .. }
+            | Alert::HeritageConfigExpiring { wallet_name, .. }
+            | Alert::SyncFailed { wallet_name, .. } => wallet_name,
+        }
+    }
+}
+
+impl core::fmt::Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Alert::HeirMaturityApproaching {
+                wallet_name,
+                heir_fingerprint,
+                maturity_ts,
+                amount,
+            } => write!(
+                f,
+                "[{wallet_name}] The heir {heir_fingerprint} will be able to spend {amount} \
+                after timestamp {maturity_ts}"
+            ),
+            Alert::HeritageConfigExpiring {
+                wallet_name,
+                expiration_ts,
+            } => write!(
+                f,
+                "[{wallet_name}] The current Heritage configuration expires at timestamp {expiration_ts}"
+            ),
+            Alert::SyncFailed { wallet_name, error } => {
+                write!(f, "[{wallet_name}] The synchronization failed: {error}")
+            }
+        }
+    }
+}
+
+/// The destination of the [Alert]s raised by a [WalletMonitor]
+pub trait AlertSink {
+    /// Deliver the `alert`
+    ///
+    /// # Errors
+    /// An error is logged by the [WalletMonitor] and the `alert` is delivered again at the next tick.
+    fn emit(&mut self, alert: &Alert) -> Result<()>;
+}
+
+impl<F: FnMut(&Alert) -> Result<()>> AlertSink for F {
+    fn emit(&mut self, alert: &Alert) -> Result<()> {
+        self(alert)
+    }
+}
+
+#[derive(Debug)]
+struct MonitoredWallet {
+    wallet: Wallet,
+    next_sync_ts: u64,
+    next_fee_rate_ts: u64,
+    /// The error of the last synchronization, if it failed
+    sync_error: Option<String>,
+    /// The alerts already delivered and still standing, so that they are not repeated at each tick
+    delivered: HashSet<Alert>,
+}
+
+/// Periodically synchronize a set of [Wallet]s and raise [Alert]s about them
+#[derive(Debug)]
+pub struct WalletMonitor {
+    config: MonitorConfig,
+    wallets: Vec<MonitoredWallet>,
+}
+
+impl WalletMonitor {
+    pub fn new(config: MonitorConfig) -> Self {
+        Self {
+            config,
+            wallets: Vec::new(),
+        }
+    }
+
+    pub fn config(&self) -> &MonitorConfig {
+        &self.config
+    }
+
+    /// Add a [Wallet] to monitor. It is synchronized at the next tick.
+    ///
+    /// Its online wallet must be ready to use, i.e. a [LocalHeritageWallet](crate::online_wallet::LocalHeritageWallet)
+    /// must have its heritage wallet and blockchain factory initialized.
+    pub fn add_wallet(&mut self, wallet: Wallet) {
+        self.wallets.push(MonitoredWallet {
+            wallet,
+            next_sync_ts: 0,
+            next_fee_rate_ts: 0,
+            sync_error: None,
+            delivered: HashSet::new(),
+        });
+    }
+
+    /// Iterate over the names of the monitored [Wallet]s
+    pub fn wallet_names(&self) -> impl Iterator<Item = &str> {
+        self.wallets.iter().map(|mw| mw.wallet.name())
+    }
+
+    /// Run the tasks that are due at `now`: synchronize the wallets and refresh their fee rate
+    /// if their interval elapsed, then deliver the new [Alert]s to the `sink`
+    pub fn tick<S: AlertSink + ?Sized>(&mut self, now: u64, sink: &mut S) {
+        let config = self.config;
+        for mw in self.wallets.iter_mut() {
+            let name = mw.wallet.name().to_owned();
+            let mut alerts = Vec::new();
+            if mw.next_sync_ts <= now {
+                log::info!("WalletMonitor::tick - Synchronizing {name}");
+                match mw.wallet.sync() {
+                    Ok(()) => {
+                        mw.sync_error = None;
+                        mw.next_sync_ts = now + config.sync_interval.as_secs();
+                        // The synchronization also refreshed the fee rate
+                        mw.next_fee_rate_ts = now + config.fee_rate_interval.as_secs();
+                    }
+                    Err(e) => {
+                        log::error!("WalletMonitor::tick - Synchronization of {name} failed: {e}");
+                        // Retry at the next fee rate refresh rather than waiting a full sync interval
+                        mw.next_sync_ts = now + config.fee_rate_interval.as_secs();
+                        mw.sync_error = Some(e.to_string());
+                    }
+                }
+            }
+            if let Some(error) = &mw.sync_error {
+                alerts.push(Alert::SyncFailed {
+                    wallet_name: name.clone(),
+                    error: error.clone(),
+                });
+            }
+            if mw.next_fee_rate_ts <= now {
+                if let AnyOnlineWallet::Local(local_wallet) = mw.wallet.online_wallet() {
+                    match local_wallet.refresh_fee_rate() {
+                        Ok(fee_rate) => log::debug!(
+                            "WalletMonitor::tick - Fee rate of {name} refreshed: {fee_rate:?}"
+                        ),
+                        Err(e) => log::warn!(
+                            "WalletMonitor::tick - Cannot refresh the fee rate of {name}: {e}"
+                        ),
+                    }
+                }
+                mw.next_fee_rate_ts = now + config.fee_rate_interval.as_secs();
+            }
+
+            match mw
+                .wallet
+                .list_heritage_utxos()
+                .and_then(|utxos| Ok((utxos, mw.wallet.list_heritage_configs()?)))
+            {
+                Ok((utxos, heritage_configs)) => alerts.extend(compute_alerts(
+                    &name,
+                    utxos,
+                    // The current HeritageConfig comes first
+                    heritage_configs.first(),
+                    now,
+                    &config,
+                )),
+                Err(e) => log::error!("WalletMonitor::tick - Cannot inspect {name}: {e}"),
+            }
+
+            // Forget the alerts that are no longer standing so that they are raised again if they reappear
+            mw.delivered.retain(|alert| alerts.contains(alert));
+            for alert in alerts {
+                if mw.delivered.contains(&alert) {
+                    continue;
+                }
+                match sink.emit(&alert) {
+                    Ok(()) => {
+                        mw.delivered.insert(alert);
+                    }
+                    Err(e) => log::error!("WalletMonitor::tick - Cannot deliver alert: {e}"),
+                }
+            }
+        }
+    }
+
+    /// Run [WalletMonitor::tick] every `tick_interval` until `stop` is set
+    pub fn run<S: AlertSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        tick_interval: Duration,
+        stop: &AtomicBool,
+    ) {
+        log::info!(
+            "WalletMonitor::run - Monitoring {} wallet(s)",
+            self.wallets.len()
+        );
+        while !stop.load(Ordering::Relaxed) {
+            self.tick(timestamp_now(), sink);
+            // Sleep by small steps to react quickly to the stop signal
+            let mut remaining = tick_interval;
+            while !remaining.is_zero() && !stop.load(Ordering::Relaxed) {
+                let step = remaining.min(Duration::from_secs(1));
+                std::thread::sleep(step);
+                remaining -= step;
+            }
+        }
+        log::info!("WalletMonitor::run - Stopped");
+    }
+}
+
+/// Compute the maturity and expiration [Alert]s of a wallet at `now`
+fn compute_alerts(
+    wallet_name: &str,
+    heritage_utxos: Vec<HeritageUtxo>,
+    current_heritage_config: Option<&HeritageConfig>,
+    now: u64,
+    config: &MonitorConfig,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+
+    // Group the events of each heir maturing at the same time
+    let maturity_deadline = now + config.maturity_horizon.as_secs();
+    let mut maturing = BTreeMap::<(u64, Fingerprint), Amount>::new();
+    for event in InheritanceSchedule::from_heritage_utxos(heritage_utxos)
+        .events
+        .into_iter()
+        .take_while(|event| event.estimated_timestamp <= maturity_deadline)
+    {
+        *maturing
+            .entry((event.estimated_timestamp, event.heir_config.fingerprint()))
+            .or_default() += event.amount;
+    }
+    alerts.extend(
+        maturing
+            .into_iter()
+            .map(
+                |((maturity_ts, heir_fingerprint), amount)| Alert::HeirMaturityApproaching {
+                    wallet_name: wallet_name.to_owned(),
+                    heir_fingerprint,
+                    maturity_ts,
+                    amount,
+                },
+            ),
+    );
+
+    if let Some(expiration_ts) = current_heritage_config.and_then(|hc| {
+        hc.iter_heir_configs()
+            .filter_map(|heir_config| {
+                hc.get_heritage_explorer(heir_config)
+                    .and_then(|he| he.get_spend_conditions().get_spendable_timestamp())
+            })
+            .min()
+    }) {
+        if expiration_ts <= now + config.expiration_horizon.as_secs() {
+            alerts.push(Alert::HeritageConfigExpiring {
+                wallet_name: wallet_name.to_owned(),
+                expiration_ts,
+            });
+        }
+    }
+
+    alerts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{key_provider::HeirConfigType, KeyProvider, LocalKey, Mnemonic};
+    use btc_heritage::{bitcoin::Network, heritage_config::v1::Heritage};
+
+    const MNEMONIC: &str =
+        "owner owner owner owner owner owner owner owner owner owner owner panther";
+
+    #[test]
+    fn config_expiration_alert() {
+        let heir = LocalKey::restore(
+            Mnemonic::parse(MNEMONIC).unwrap(),
+            Some("heir".to_owned()),
+            Network::Regtest,
+        );
+        let heir_config = heir
+            .derive_heir_config(HeirConfigType::HeirXPubkey)
+            .unwrap();
+        let heritage_config = HeritageConfig::builder_v1()
+            .add_heritage(Heritage::new(heir_config.clone()).time_lock(360))
+            .reference_time(1_700_000_000)
+            .minimum_lock_time(90)
+            .build();
+        let expiration_ts = heritage_config
+            .get_heritage_explorer(&heir_config)
+            .and_then(|he| he.get_spend_conditions().get_spendable_timestamp())
+            .unwrap();
+        let config = MonitorConfig::default();
+        let horizon = config.expiration_horizon.as_secs();
+
+        assert!(compute_alerts(
+            "w",
+            vec![],
+            Some(&heritage_config),
+            expiration_ts - horizon - 1,
+            &config
+        )
+        .is_empty());
+        assert_eq!(
+            compute_alerts(
+                "w",
+                vec![],
+                Some(&heritage_config),
+                expiration_ts - horizon,
+                &config
+            ),
+            vec![Alert::HeritageConfigExpiring {
+                wallet_name: "w".to_owned(),
+                expiration_ts
+            }]
+        );
+        assert!(compute_alerts("w", vec![], None, expiration_ts, &config).is_empty());
+    }
+}
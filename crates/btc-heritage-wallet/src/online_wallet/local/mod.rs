@@ -6,12 +6,21 @@ use crate::{
     BoundFingerprint, Broadcaster, Database,
 };
 use btc_heritage::{
-    bdk_types::{ElectrumBlockchain, RpcBlockchainFactory},
-    bitcoin::{bip32::Fingerprint, secp256k1::rand, Txid},
+    bdk_types::{
+        Blockchain, BlockchainFactory, ElectrumBlockchain, EsploraBlockchain, RpcBlockchainFactory,
+    },
+    bitcoin::{bip32::Fingerprint, secp256k1::rand, FeeRate, Network, Txid},
     bitcoincore_rpc::{Client, RpcApi},
-    database::HeritageDatabase,
-    electrum_client::ElectrumApi,
-    heritage_wallet::{CreatePsbtOptions, TransactionSummary, WalletAddress},
+    database::{integrity::IntegrityReport, HeritageDatabase},
+    electrum_client::{self, ElectrumApi},
+    errors::BroadcastError,
+    heritage_wallet::{
+        online::{
+            fee_estimator::BlockchainFeeEstimator,
+            migration::{MigrationPlan, MigrationSource},
+        },
+        CreatePsbtOptions, GapLimit, TransactionSummary, WalletAddress,
+    },
     AccountXPub, Amount, BlockInclusionObjective, HeritageConfig, HeritageWallet,
     HeritageWalletBackup, PartiallySignedTransaction, SpendingConfig,
 };
@@ -24,6 +33,32 @@ use super::OnlineWallet;
 pub enum AnyBlockchainFactory {
     Bitcoin(RpcBlockchainFactory),
     Electrum(Arc<ElectrumBlockchain>),
+    Esplora(Arc<EsploraBlockchain>),
+}
+
+impl AnyBlockchainFactory {
+    /// Create an [AnyBlockchainFactory::Electrum] from a backend URL of the form
+    /// `electrum://host:port` (plain TCP) or `electrums://host:port` (SSL)
+    pub fn new_electrum(backend_url: &str) -> Result<Self> {
+        let electrum_url = if let Some(host_port) = backend_url.strip_prefix("electrum://") {
+            format!("tcp://{host_port}")
+        } else if let Some(host_port) = backend_url.strip_prefix("electrums://") {
+            format!("ssl://{host_port}")
+        } else {
+            return Err(Error::generic(format!(
+                "Invalid Electrum backend URL: {backend_url}"
+            )));
+        };
+        let client = electrum_client::Client::new(&electrum_url).map_err(|e| Error::generic(e))?;
+        Ok(Self::Electrum(Arc::new(ElectrumBlockchain::from(client))))
+    }
+
+    /// Create an [AnyBlockchainFactory::Esplora] for the Esplora API at `esplora_url`,
+    /// e.g. the public one of a network given by [btc_heritage::BitcoinNetwork::esplora_url]
+    pub fn new_esplora(esplora_url: &str) -> Self {
+        let stop_gap = u32::from(GapLimit::default()) as usize;
+        Self::Esplora(Arc::new(EsploraBlockchain::new(esplora_url, stop_gap)))
+    }
 }
 
 impl Debug for AnyBlockchainFactory {
@@ -34,6 +69,7 @@ impl Debug for AnyBlockchainFactory {
             match self {
                 Self::Bitcoin(_) => "Bitcoin(...)",
                 Self::Electrum(_) => "Electrum(...)",
+                Self::Esplora(_) => "Esplora(...)",
             }
         )
     }
@@ -71,12 +107,13 @@ impl LocalHeritageWallet {
         db: &Database,
         backup: Option<HeritageWalletBackup>,
         block_inclusion_objective: u16,
+        network: Network,
     ) -> Result<Self> {
         let heritage_wallet_id = format!("{:032x}", rand::random::<u128>());
-        let heritage_wallet = HeritageWallet::new(HeritageWalletDatabase::create(
-            heritage_wallet_id.clone(),
-            db,
-        )?);
+        let heritage_wallet = HeritageWallet::new_with_network(
+            HeritageWalletDatabase::create(heritage_wallet_id.clone(), db)?,
+            network,
+        )?;
         if let Some(backup) = backup {
             heritage_wallet.restore_backup(backup)?;
         }
@@ -101,10 +138,11 @@ impl LocalHeritageWallet {
     }
 
     pub fn init_heritage_wallet(&mut self, db: &Database) -> Result<()> {
-        self.heritage_wallet = Some(HeritageWallet::new(HeritageWalletDatabase::get(
-            self.heritage_wallet_id.clone(),
-            db,
-        )?));
+        // Wallets created by previous versions do not record their network yet
+        self.heritage_wallet = Some(HeritageWallet::new_with_network(
+            HeritageWalletDatabase::get(self.heritage_wallet_id.clone(), db)?,
+            db.network(),
+        )?);
         Ok(())
     }
     pub(crate) fn heritage_wallet(&self) -> &HeritageWallet<HeritageWalletDatabase> {
@@ -125,6 +163,53 @@ impl LocalHeritageWallet {
             .as_ref()
             .expect("blockchain factory should have been initialized")
     }
+
+    /// Refresh the fee rate estimation of the wallet without synchronizing it
+    pub fn refresh_fee_rate(&self) -> Result<FeeRate> {
+        let wallet = self.heritage_wallet();
+        Ok(match self.blockchain_factory() {
+            AnyBlockchainFactory::Bitcoin(bcf) => {
+                let blockchain = bcf
+                    .build("unimportant", None)
+                    .map_err(|e| Error::BackendUnavailable(e.to_string()))?;
+                wallet.sync_fee_rate(&BlockchainFeeEstimator(blockchain))?
+            }
+            AnyBlockchainFactory::Electrum(bcf) => {
+                wallet.sync_fee_rate(&BlockchainFeeEstimator(bcf.clone()))?
+            }
+            AnyBlockchainFactory::Esplora(bcf) => {
+                wallet.sync_fee_rate(&BlockchainFeeEstimator(bcf.clone()))?
+            }
+        })
+    }
+
+    /// Plan the migration of the funds of an existing descriptor wallet into this wallet,
+    /// see [HeritageWallet::plan_migration]
+    pub fn plan_migration(
+        &self,
+        source: &MigrationSource,
+        heritage_config: Option<HeritageConfig>,
+        fee_rate: Option<FeeRate>,
+    ) -> Result<MigrationPlan> {
+        let wallet = self.heritage_wallet();
+        Ok(match self.blockchain_factory() {
+            AnyBlockchainFactory::Bitcoin(bcf) => {
+                wallet.plan_migration(source, heritage_config, bcf, fee_rate)?
+            }
+            AnyBlockchainFactory::Electrum(bcf) => {
+                wallet.plan_migration(source, heritage_config, bcf, fee_rate)?
+            }
+            AnyBlockchainFactory::Esplora(bcf) => {
+                wallet.plan_migration(source, heritage_config, bcf, fee_rate)?
+            }
+        })
+    }
+
+    /// Verify the integrity of the wallet database and optionally repair it,
+    /// see [HeritageWallet::verify_database_integrity]
+    pub fn verify_database_integrity(&self, repair: bool) -> Result<IntegrityReport> {
+        Ok(self.heritage_wallet().verify_database_integrity(repair)?)
+    }
 }
 
 impl super::OnlineWallet for LocalHeritageWallet {
@@ -141,10 +226,7 @@ impl super::OnlineWallet for LocalHeritageWallet {
     }
 
     fn list_transactions(&self) -> Result<Vec<TransactionSummary>> {
-        Ok(self
-            .heritage_wallet()
-            .database()
-            .list_transaction_summaries()?)
+        Ok(self.heritage_wallet().list_transaction_summaries()?)
     }
 
     fn list_heritage_utxos(&self) -> Result<Vec<heritage_service_api_client::HeritageUtxo>> {
@@ -201,6 +283,7 @@ impl super::OnlineWallet for LocalHeritageWallet {
         match self.blockchain_factory() {
             AnyBlockchainFactory::Bitcoin(bcf) => wallet.sync(bcf)?,
             AnyBlockchainFactory::Electrum(bcf) => wallet.sync(bcf)?,
+            AnyBlockchainFactory::Esplora(bcf) => wallet.sync(bcf)?,
         }
         Ok(())
     }
@@ -232,16 +315,27 @@ impl super::OnlineWallet for LocalHeritageWallet {
         } = new_tx;
         let spending_config = match spending_config {
             heritage_service_api_client::NewTxSpendingConfig::Recipients(recipients) => {
-                SpendingConfig::try_from(
+                SpendingConfig::from(
                     recipients
                         .into_iter()
-                        .map(|r| (r.address, Amount::from_sat(r.amount)))
-                        .collect::<Vec<_>>(),
-                )?
+                        .map(|r| {
+                            Ok((
+                                btc_heritage::utils::string_to_address_for_network(
+                                    &r.address,
+                                    wallet.network(),
+                                )?,
+                                Amount::from_sat(r.amount),
+                            ))
+                        })
+                        .collect::<Result<Vec<_>>>()?,
+                )
             }
             heritage_service_api_client::NewTxSpendingConfig::DrainTo(NewTxDrainTo {
                 drain_to,
-            }) => SpendingConfig::DrainTo(btc_heritage::utils::string_to_address(&drain_to)?),
+            }) => SpendingConfig::DrainTo(btc_heritage::utils::string_to_address_for_network(
+                &drain_to,
+                wallet.network(),
+            )?),
         };
         let create_psbt_options = CreatePsbtOptions {
             fee_policy: fee_policy.map(|fp| fp.into()),
@@ -255,20 +349,31 @@ impl super::OnlineWallet for LocalHeritageWallet {
 
 impl Broadcaster for LocalHeritageWallet {
     fn broadcast(&self, psbt: PartiallySignedTransaction) -> Result<Txid> {
-        let tx = btc_heritage::utils::extract_tx(psbt)?;
+        let tx = btc_heritage::utils::finalize_psbt(psbt)?;
+        // Map the reject reason of the backend to a typed error
+        let rejected = |e: &dyn core::fmt::Display| {
+            log::error!("Transaction {} rejected: {e}", tx.txid());
+            Error::from(btc_heritage::errors::Error::from(
+                BroadcastError::from_reject_message(&e.to_string()),
+            ))
+        };
         match self.blockchain_factory() {
             AnyBlockchainFactory::Bitcoin(bcf) => {
                 let rpc_client = Client::new(&bcf.url, bcf.auth.clone().into())
                     .map_err(|e| Error::generic(e))?;
                 Ok(rpc_client
                     .send_raw_transaction(&tx)
-                    .map_err(|e| Error::generic(e))?)
+                    .map_err(|e| rejected(&e))?)
             }
             AnyBlockchainFactory::Electrum(bcf) => Ok(bcf
                 .transaction_broadcast_raw(
                     btc_heritage::bitcoin::consensus::encode::serialize(&tx).as_ref(),
                 )
-                .map_err(|e| Error::generic(e))?),
+                .map_err(|e| rejected(&e))?),
+            AnyBlockchainFactory::Esplora(bcf) => {
+                bcf.broadcast(&tx).map_err(|e| rejected(&e))?;
+                Ok(tx.txid())
+            }
         }
     }
 }
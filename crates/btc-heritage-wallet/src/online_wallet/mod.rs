@@ -122,6 +122,13 @@ impl BoundFingerprint for AnyOnlineWallet {
 }
 
 macro_rules! impl_online_wallet {
+    (@subwallets_update $fn_name:ident(&mut $self:ident $(,$a:ident : $t:ty)*) -> $ret:ty) => {
+        fn $fn_name(&mut $self $(,$a : $t)*) -> $ret {
+            let ret = $self.online_wallet.$fn_name($($a),*)?;
+            $self.on_subwallets_update();
+            Ok(ret)
+        }
+    };
     ($fn_name:ident(&mut $self:ident $(,$a:ident : $t:ty)*) -> $ret:ty) => {
         fn $fn_name(&mut $self $(,$a : $t)*) -> $ret {
             $self.online_wallet.$fn_name($($a),*)
@@ -148,9 +155,9 @@ macro_rules! impl_online_wallet {
             crate::online_wallet::impl_online_wallet!(list_transactions(&self) -> Result<Vec<btc_heritage::heritage_wallet::TransactionSummary>>);
             crate::online_wallet::impl_online_wallet!(list_heritage_utxos(&self) -> Result<Vec<btc_heritage::heritage_wallet::HeritageUtxo>>);
             crate::online_wallet::impl_online_wallet!(list_account_xpubs(&self) -> Result<Vec<heritage_service_api_client::AccountXPubWithStatus>>);
-            crate::online_wallet::impl_online_wallet!(feed_account_xpubs(&mut self, account_xpubs: Vec<btc_heritage::AccountXPub>) -> Result<()>);
+            crate::online_wallet::impl_online_wallet!(@subwallets_update feed_account_xpubs(&mut self, account_xpubs: Vec<btc_heritage::AccountXPub>) -> Result<()>);
             crate::online_wallet::impl_online_wallet!(list_heritage_configs(&self) -> Result<Vec<btc_heritage::HeritageConfig>>);
-            crate::online_wallet::impl_online_wallet!(set_heritage_config(&mut self, new_hc: btc_heritage::HeritageConfig) -> Result<btc_heritage::HeritageConfig>);
+            crate::online_wallet::impl_online_wallet!(@subwallets_update set_heritage_config(&mut self, new_hc: btc_heritage::HeritageConfig) -> Result<btc_heritage::HeritageConfig>);
             crate::online_wallet::impl_online_wallet!(sync(&mut self) -> Result<()>);
             crate::online_wallet::impl_online_wallet!(get_wallet_status(&self) -> Result<crate::online_wallet::WalletStatus>);
             crate::online_wallet::impl_online_wallet!(set_block_inclusion_objective(&mut self, bio: u16) -> Result<crate::online_wallet::WalletStatus>);
@@ -0,0 +1,427 @@
+//! Outbox: signed transactions waiting to be broadcasted and confirmed.
+//!
+//! A transaction queued in the [Outbox] of a [Wallet](crate::Wallet) is persisted in the
+//! [Database](crate::Database) and broadcasted by [Wallet::process_outbox](crate::Wallet::process_outbox),
+//! which should be invoked periodically: a rejected broadcast is retried later and an unconfirmed
+//! transaction is broadcasted again, in case it was evicted from the mempools, until it is
+//! confirmed. A flaky connection to the blockchain backend cannot silently lose a signed
+//! transaction.
+
+use std::time::Duration;
+
+use btc_heritage::{
+    bitcoin::Txid, errors::BroadcastError, heritage_wallet::TransactionSummary,
+    PartiallySignedTransaction,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{errors::DbError, DatabaseItem},
+    errors::Error,
+    Broadcaster,
+};
+
+/// The retry schedule of an [Outbox]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutboxPolicy {
+    /// Delay before trying again to broadcast a transaction that was rejected
+    pub retry_interval: Duration,
+    /// Delay between two broadcasts of a transaction that is still unconfirmed
+    pub rebroadcast_interval: Duration,
+    /// The number of confirmations after which a transaction is [OutboxEntry::is_settled]
+    pub target_confirmations: u32,
+}
+
+impl Default for OutboxPolicy {
+    /// Retry every 5 minutes, rebroadcast every hour and settle after 6 confirmations
+    fn default() -> Self {
+        Self {
+            retry_interval: Duration::from_secs(5 * 60),
+            rebroadcast_interval: Duration::from_secs(60 * 60),
+            target_confirmations: 6,
+        }
+    }
+}
+
+/// The state of a transaction of the [Outbox]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutboxStatus {
+    /// No broadcast was accepted yet
+    Pending,
+    /// The broadcast was accepted, the transaction waits for its first confirmation
+    Unconfirmed,
+    /// The transaction is included in the block at `height`
+    Confirmed { height: u32, confirmations: u32 },
+    /// The transaction was rejected for good, e.g. because its inputs were spent by another
+    /// transaction. It is not broadcasted anymore, unless it shows up in the wallet history.
+    Failed,
+}
+
+/// A transaction of the [Outbox]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub txid: Txid,
+    /// The signed PSBT, stored in base64
+    #[serde(with = "crate::psbt_io::serde_base64")]
+    pub psbt: PartiallySignedTransaction,
+    pub status: OutboxStatus,
+    pub queued_at: u64,
+    /// The number of broadcasts attempted
+    pub attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt_at: Option<u64>,
+    /// The error of the last broadcast, if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// The timestamp after which the transaction is broadcasted again
+    next_attempt_at: u64,
+}
+
+impl OutboxEntry {
+    /// `true` if the transaction has at least `target_confirmations` confirmations
+    pub fn is_settled(&self, target_confirmations: u32) -> bool {
+        matches!(self.status, OutboxStatus::Confirmed { confirmations, .. } if confirmations >= target_confirmations)
+    }
+}
+
+/// The transactions of a [DatabaseItem] waiting to be broadcasted and confirmed
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Outbox {
+    /// The database key of the [DatabaseItem] owning the outbox
+    name: String,
+    /// The transactions, in the order they were queued
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    entries: Vec<OutboxEntry>,
+}
+
+impl Outbox {
+    /// Load the [Outbox] of the [DatabaseItem] `T` named `item_name`,
+    /// or an empty outbox if none was saved
+    pub(crate) fn load_for<T: DatabaseItem>(
+        db: &crate::Database,
+        item_name: &str,
+    ) -> core::result::Result<Self, DbError> {
+        let name = T::name_to_key(item_name);
+        match Self::load(db, &name) {
+            Ok(outbox) => Ok(outbox),
+            Err(DbError::KeyDoesNotExists(_)) => Ok(Self {
+                name,
+                ..Default::default()
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Delete the [Outbox] of the [DatabaseItem] `T` named `item_name`, if any
+    pub(crate) fn delete_for<T: DatabaseItem>(
+        db: &mut crate::Database,
+        item_name: &str,
+    ) -> core::result::Result<(), DbError> {
+        db.delete_item::<Self>(&Self::db_key(db, &T::name_to_key(item_name)))?;
+        Ok(())
+    }
+
+    /// Move the [Outbox] of the [DatabaseItem] `T` when it is renamed
+    pub(crate) fn rename_for<T: DatabaseItem>(
+        db: &mut crate::Database,
+        old_item_name: &str,
+        new_item_name: &str,
+    ) -> core::result::Result<(), DbError> {
+        let key = Self::db_key(db, &T::name_to_key(old_item_name));
+        if db.contains_key(&key)? {
+            Self::load_for::<T>(db, old_item_name)?.db_rename(db, T::name_to_key(new_item_name))?;
+        }
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[OutboxEntry] {
+        &self.entries
+    }
+
+    /// Return the [OutboxEntry] of the transaction `txid`, if it is in the outbox
+    pub fn get(&self, txid: &Txid) -> Option<&OutboxEntry> {
+        self.entries.iter().find(|entry| entry.txid == *txid)
+    }
+
+    /// Add the signed `psbt` to the outbox, to be broadcasted at the next [Outbox::process].
+    /// Queuing a transaction already in the outbox only replaces its PSBT.
+    pub fn queue(&mut self, psbt: PartiallySignedTransaction, now: u64) -> Txid {
+        let txid = psbt.unsigned_tx.txid();
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.txid == txid) {
+            entry.psbt = psbt;
+        } else {
+            self.entries.push(OutboxEntry {
+                txid,
+                psbt,
+                status: OutboxStatus::Pending,
+                queued_at: now,
+                attempts: 0,
+                last_attempt_at: None,
+                last_error: None,
+                next_attempt_at: now,
+            });
+        }
+        txid
+    }
+
+    /// Remove the transaction `txid` from the outbox, returning its [OutboxEntry] if it was present
+    pub fn remove(&mut self, txid: &Txid) -> Option<OutboxEntry> {
+        let index = self.entries.iter().position(|entry| entry.txid == *txid)?;
+        Some(self.entries.remove(index))
+    }
+
+    /// Remove the transactions that are [OutboxEntry::is_settled], returning how many were removed
+    pub fn prune_settled(&mut self, target_confirmations: u32) -> usize {
+        let len = self.entries.len();
+        self.entries
+            .retain(|entry| !entry.is_settled(target_confirmations));
+        len - self.entries.len()
+    }
+
+    /// Update the status of the transactions from the wallet `history` and the height of the
+    /// blockchain `tip_height`, then broadcast with `broadcaster` the transactions that are due at
+    /// `now` according to the `policy`.
+    ///
+    /// If `tip_height` is lower than the actual tip, the confirmations are under-estimated.
+    pub fn process<B: Broadcaster + ?Sized>(
+        &mut self,
+        broadcaster: &B,
+        history: &[TransactionSummary],
+        tip_height: u32,
+        now: u64,
+        policy: &OutboxPolicy,
+    ) {
+        for entry in self.entries.iter_mut() {
+            match history.iter().find(|ts| ts.txid == entry.txid) {
+                Some(TransactionSummary {
+                    confirmation_time: Some(block_time),
+                    ..
+                }) => {
+                    entry.status = OutboxStatus::Confirmed {
+                        height: block_time.height,
+                        confirmations: tip_height.max(block_time.height) - block_time.height + 1,
+                    };
+                    continue;
+                }
+                // Known by the wallet, therefore accepted by the network, or reorganized
+                Some(_) => entry.status = OutboxStatus::Unconfirmed,
+                None if matches!(entry.status, OutboxStatus::Confirmed { .. }) => {
+                    entry.status = OutboxStatus::Unconfirmed
+                }
+                None => (),
+            }
+            if entry.status == OutboxStatus::Failed || entry.next_attempt_at > now {
+                continue;
+            }
+
+            log::info!("Outbox::process - Broadcasting {}", entry.txid);
+            entry.attempts += 1;
+            entry.last_attempt_at = Some(now);
+            match broadcaster.broadcast(entry.psbt.clone()) {
+                Ok(_) => {
+                    entry.status = OutboxStatus::Unconfirmed;
+                    entry.last_error = None;
+                    entry.next_attempt_at = now + policy.rebroadcast_interval.as_secs();
+                }
+                Err(e) => {
+                    log::warn!("Outbox::process - Broadcast of {} failed: {e}", entry.txid);
+                    if entry.status == OutboxStatus::Pending && is_definitive_rejection(&e) {
+                        entry.status = OutboxStatus::Failed;
+                    }
+                    entry.last_error = Some(e.to_string());
+                    entry.next_attempt_at = now
+                        + match entry.status {
+                            OutboxStatus::Unconfirmed => policy.rebroadcast_interval,
+                            _ => policy.retry_interval,
+                        }
+                        .as_secs();
+                }
+            }
+        }
+    }
+}
+
+/// `true` if retrying the broadcast that failed with `error` is pointless
+fn is_definitive_rejection(error: &Error) -> bool {
+    use btc_heritage::errors::Error as HeritageError;
+    matches!(
+        error,
+        Error::HeritageError {
+            source: HeritageError::BroadcastError(BroadcastError::MissingInputs)
+                | HeritageError::UnfinalizablePsbt(_)
+                | HeritageError::UnfinalizablePsbtInputs(_)
+        }
+    )
+}
+
+crate::database::dbitem::impl_db_item!(Outbox, "outbox#", "default_outbox_name");
+
+#[cfg(test)]
+mod tests {
+    use core::cell::RefCell;
+
+    use super::*;
+    use btc_heritage::{
+        bdk_types::BlockTime,
+        bitcoin::Amount,
+        psbttests::{get_test_unsigned_psbt, TestPsbt},
+    };
+
+    /// A [Broadcaster] answering with the results it is given
+    struct MockBroadcaster(RefCell<Vec<crate::errors::Result<()>>>);
+    impl Broadcaster for MockBroadcaster {
+        fn broadcast(&self, psbt: PartiallySignedTransaction) -> crate::errors::Result<Txid> {
+            self.0.borrow_mut().remove(0)?;
+            Ok(psbt.unsigned_tx.txid())
+        }
+    }
+    impl MockBroadcaster {
+        fn new(results: Vec<crate::errors::Result<()>>) -> Self {
+            Self(RefCell::new(results))
+        }
+        fn exhausted(&self) -> bool {
+            self.0.borrow().is_empty()
+        }
+    }
+
+    fn rejection(reason: BroadcastError) -> crate::errors::Result<()> {
+        Err(btc_heritage::errors::Error::BroadcastError(reason).into())
+    }
+
+    fn summary(txid: Txid, height: Option<u32>) -> TransactionSummary {
+        TransactionSummary {
+            txid,
+            confirmation_time: height.map(|height| BlockTime {
+                height,
+                timestamp: 1_700_000_000,
+            }),
+            owned_inputs: vec![],
+            owned_outputs: vec![],
+            fee: Amount::from_sat(1000),
+            fee_rate: btc_heritage::bitcoin::FeeRate::from_sat_per_vb_unchecked(1),
+            parent_txids: Default::default(),
+            label: None,
+            address_reused: false,
+        }
+    }
+
+    #[test]
+    fn outbox_lifecycle() {
+        let policy = OutboxPolicy::default();
+        let retry = policy.retry_interval.as_secs();
+        let rebroadcast = policy.rebroadcast_interval.as_secs();
+        let mut outbox = Outbox::default();
+        let txid = outbox.queue(get_test_unsigned_psbt(TestPsbt::OwnerRecipients), 0);
+        assert_eq!(outbox.get(&txid).unwrap().status, OutboxStatus::Pending);
+
+        // A transient rejection is retried after the retry interval
+        let broadcaster = MockBroadcaster::new(vec![
+            rejection(BroadcastError::InsufficientFee),
+            Ok(()),
+            Ok(()),
+        ]);
+        outbox.process(&broadcaster, &[], 100, 0, &policy);
+        let entry = outbox.get(&txid).unwrap();
+        assert_eq!(entry.status, OutboxStatus::Pending);
+        assert_eq!(entry.attempts, 1);
+        assert!(entry.last_error.is_some());
+        outbox.process(&broadcaster, &[], 100, retry - 1, &policy);
+        assert_eq!(outbox.get(&txid).unwrap().attempts, 1);
+        outbox.process(&broadcaster, &[], 100, retry, &policy);
+        let entry = outbox.get(&txid).unwrap();
+        assert_eq!(entry.status, OutboxStatus::Unconfirmed);
+        assert!(entry.last_error.is_none());
+
+        // An unconfirmed transaction is broadcasted again after the rebroadcast interval
+        outbox.process(
+            &broadcaster,
+            &[summary(txid, None)],
+            100,
+            retry + rebroadcast,
+            &policy,
+        );
+        assert_eq!(outbox.get(&txid).unwrap().attempts, 3);
+        assert!(broadcaster.exhausted());
+
+        // Confirmations are tracked and a confirmed transaction is not broadcasted anymore
+        outbox.process(
+            &broadcaster,
+            &[summary(txid, Some(101))],
+            105,
+            u64::MAX,
+            &policy,
+        );
+        let entry = outbox.get(&txid).unwrap();
+        assert_eq!(
+            entry.status,
+            OutboxStatus::Confirmed {
+                height: 101,
+                confirmations: 5
+            }
+        );
+        assert!(!entry.is_settled(policy.target_confirmations));
+        // The tip height may lag behind the confirmation height
+        outbox.process(
+            &broadcaster,
+            &[summary(txid, Some(101))],
+            100,
+            u64::MAX,
+            &policy,
+        );
+        assert!(matches!(
+            outbox.get(&txid).unwrap().status,
+            OutboxStatus::Confirmed {
+                confirmations: 1,
+                ..
+            }
+        ));
+        outbox.process(
+            &broadcaster,
+            &[summary(txid, Some(101))],
+            106,
+            u64::MAX,
+            &policy,
+        );
+        assert!(outbox
+            .get(&txid)
+            .unwrap()
+            .is_settled(policy.target_confirmations));
+        assert_eq!(outbox.prune_settled(policy.target_confirmations), 1);
+        assert!(outbox.entries().is_empty());
+    }
+
+    #[test]
+    fn outbox_definitive_rejection() {
+        let policy = OutboxPolicy::default();
+        let mut outbox = Outbox::default();
+        let txid = outbox.queue(get_test_unsigned_psbt(TestPsbt::OwnerDrain), 0);
+        let broadcaster = MockBroadcaster::new(vec![rejection(BroadcastError::MissingInputs)]);
+        outbox.process(&broadcaster, &[], 100, 0, &policy);
+        assert_eq!(outbox.get(&txid).unwrap().status, OutboxStatus::Failed);
+        // Never broadcasted again...
+        outbox.process(&broadcaster, &[], 100, u64::MAX, &policy);
+        assert_eq!(outbox.get(&txid).unwrap().attempts, 1);
+        // ...unless it shows up in the wallet history
+        outbox.process(
+            &broadcaster,
+            &[summary(txid, Some(100))],
+            100,
+            u64::MAX,
+            &policy,
+        );
+        assert!(matches!(
+            outbox.get(&txid).unwrap().status,
+            OutboxStatus::Confirmed { .. }
+        ));
+
+        // The outbox survives a round-trip through the database serialization
+        let restored: Outbox =
+            serde_json::from_value(serde_json::to_value(&outbox).unwrap()).unwrap();
+        assert_eq!(restored.entries()[0].psbt, outbox.entries()[0].psbt);
+        assert_eq!(restored.entries()[0].status, outbox.entries()[0].status);
+
+        assert!(outbox.remove(&txid).is_some());
+        assert!(outbox.remove(&txid).is_none());
+    }
+}
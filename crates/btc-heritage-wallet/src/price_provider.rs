@@ -0,0 +1,303 @@
+//! Optional fiat valuation of amounts, used to display estimates alongside [PsbtSummary](crate::PsbtSummary)
+//! and [TransactionSummary].
+//!
+//! Nothing in the wallet depends on a [PriceProvider]: when none is given, or when the provider cannot
+//! be reached, summaries are simply displayed without fiat estimate. Fiat values are estimates for
+//! display purposes only and must never be used to build a transaction.
+
+use btc_heritage::{bitcoin::Amount, heritage_wallet::TransactionSummaryOwnedIO};
+use heritage_service_api_client::TransactionSummary;
+use serde::Serialize;
+
+use crate::errors::{Error, Result};
+
+/// The price of one bitcoin in a fiat currency, at a given time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FiatPrice {
+    /// The currency code, e.g. `USD`
+    pub currency: String,
+    /// The price of 1 BTC in `currency`
+    pub btc_price: f64,
+    /// The Unix timestamp of the price
+    pub timestamp: u64,
+}
+
+impl FiatPrice {
+    /// Return the value of `amount` in the currency of this [FiatPrice]
+    pub fn value_of(&self, amount: Amount) -> FiatAmount {
+        FiatAmount {
+            value: amount.to_btc() * self.btc_price,
+            currency: self.currency.clone(),
+        }
+    }
+}
+
+/// An amount in a fiat currency, displayed with 2 decimals, e.g. `1234.56 USD`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FiatAmount {
+    pub value: f64,
+    pub currency: String,
+}
+
+impl core::fmt::Display for FiatAmount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.2} {}", self.value, self.currency)
+    }
+}
+
+impl Serialize for FiatAmount {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// A source of BTC prices in fiat currencies
+pub trait PriceProvider {
+    /// Return the price of 1 BTC in `currency` at `timestamp`, or the current price if `timestamp`
+    /// is [None]
+    ///
+    /// # Errors
+    /// Returns an error if the provider cannot be reached or does not support the currency
+    fn get_price(&self, currency: &str, timestamp: Option<u64>) -> Result<FiatPrice>;
+}
+
+impl<T: PriceProvider + ?Sized> PriceProvider for &T {
+    fn get_price(&self, currency: &str, timestamp: Option<u64>) -> Result<FiatPrice> {
+        (**self).get_price(currency, timestamp)
+    }
+}
+
+/// Query `price_provider` for a price, if any, logging and ignoring errors so that
+/// fiat valuation never prevents an operation from succeeding, especially offline
+pub fn try_get_price(
+    price_provider: Option<&dyn PriceProvider>,
+    currency: &str,
+    timestamp: Option<u64>,
+) -> Option<FiatPrice> {
+    let price_provider = price_provider?;
+    match price_provider.get_price(currency, timestamp) {
+        Ok(price) => Some(price),
+        Err(e) => {
+            log::warn!(
+                "Could not retrieve the BTC price in {currency} (timestamp={timestamp:?}): {e}"
+            );
+            None
+        }
+    }
+}
+
+/// A [TransactionSummary] with the fiat value of its balance variation and fee
+#[derive(Debug, Clone, Serialize)]
+pub struct ValuedTransactionSummary {
+    #[serde(flatten)]
+    pub tx_summary: TransactionSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fiat_estimate: Option<TransactionFiatEstimate>,
+}
+
+/// The fiat values of a [ValuedTransactionSummary]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransactionFiatEstimate {
+    /// The fiat value of the owned outputs
+    pub received: FiatAmount,
+    /// The fiat value of the owned inputs
+    pub sent: FiatAmount,
+    pub fee: FiatAmount,
+    /// The price used for the estimate
+    pub price: FiatPrice,
+}
+
+impl ValuedTransactionSummary {
+    /// Value `tx_summary` in `currency` using the price at the time the transaction was confirmed,
+    /// or the current price if it is unconfirmed. The estimate is left empty if no price is available.
+    pub fn new(
+        tx_summary: TransactionSummary,
+        price_provider: Option<&dyn PriceProvider>,
+        currency: &str,
+    ) -> Self {
+        let timestamp = tx_summary.confirmation_time.as_ref().map(|bt| bt.timestamp);
+        let fiat_estimate = try_get_price(price_provider, currency, timestamp).map(|price| {
+            let sum =
+                |ios: &[TransactionSummaryOwnedIO]| ios.iter().map(|io| io.amount).sum::<Amount>();
+            TransactionFiatEstimate {
+                received: price.value_of(sum(&tx_summary.owned_outputs)),
+                sent: price.value_of(sum(&tx_summary.owned_inputs)),
+                fee: price.value_of(tx_summary.fee),
+                price,
+            }
+        });
+        Self {
+            tx_summary,
+            fiat_estimate,
+        }
+    }
+}
+
+impl core::fmt::Display for ValuedTransactionSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            serde_json::to_string_pretty(self).expect("know structure")
+        )
+    }
+}
+
+/// A [PriceProvider] relying on the price endpoints of a [mempool.space](https://mempool.space)
+/// instance: `/api/v1/prices` for the current price and `/api/v1/historical-price` for past prices.
+///
+/// Supported currencies are the ones of mempool.space: USD, EUR, GBP, CAD, CHF, AUD and JPY.
+#[cfg(feature = "mempool-space")]
+#[derive(Debug, Clone)]
+pub struct MempoolSpacePriceProvider {
+    base_url: String,
+}
+
+#[cfg(feature = "mempool-space")]
+impl Default for MempoolSpacePriceProvider {
+    /// Use the public instance at `https://mempool.space`
+    fn default() -> Self {
+        Self::new("https://mempool.space")
+    }
+}
+
+#[cfg(feature = "mempool-space")]
+impl MempoolSpacePriceProvider {
+    /// Create a [MempoolSpacePriceProvider] for the instance at `base_url`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let mut base_url: String = base_url.into();
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+        Self { base_url }
+    }
+
+    fn get_json(&self, url: &str) -> Result<serde_json::Value> {
+        let response = minreq::get(url).send().map_err(Error::generic)?;
+        if response.status_code != 200 {
+            return Err(Error::Generic(format!(
+                "{url} answered with HTTP status {}",
+                response.status_code
+            )));
+        }
+        Ok(serde_json::from_slice(response.as_bytes())?)
+    }
+
+    /// Extract the price from a `/api/v1/prices` answer or
+    /// from the first entry of a `/api/v1/historical-price` answer
+    fn parse_price(value: &serde_json::Value, currency: &str) -> Result<FiatPrice> {
+        let entry = match value.get("prices") {
+            Some(prices) => prices.get(0).ok_or_else(|| {
+                Error::Generic("No historical price returned by mempool.space".to_owned())
+            })?,
+            None => value,
+        };
+        let btc_price = entry
+            .get(currency)
+            .and_then(serde_json::Value::as_f64)
+            .filter(|price| *price > 0.0)
+            .ok_or_else(|| {
+                Error::Generic(format!("No {currency} price returned by mempool.space"))
+            })?;
+        let timestamp = entry
+            .get("time")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or_else(|| {
+                Error::Generic("Invalid mempool.space price: missing time".to_owned())
+            })?;
+        Ok(FiatPrice {
+            currency: currency.to_owned(),
+            btc_price,
+            timestamp,
+        })
+    }
+}
+
+#[cfg(feature = "mempool-space")]
+impl PriceProvider for MempoolSpacePriceProvider {
+    fn get_price(&self, currency: &str, timestamp: Option<u64>) -> Result<FiatPrice> {
+        log::debug!(
+            "MempoolSpacePriceProvider::get_price - currency={currency} timestamp={timestamp:?}"
+        );
+        let currency = currency.to_ascii_uppercase();
+        let url = match timestamp {
+            Some(timestamp) => format!(
+                "{}/api/v1/historical-price?currency={currency}&timestamp={timestamp}",
+                self.base_url
+            ),
+            None => format!("{}/api/v1/prices", self.base_url),
+        };
+        Self::parse_price(&self.get_json(&url)?, &currency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedPriceProvider(Option<f64>);
+    impl PriceProvider for FixedPriceProvider {
+        fn get_price(&self, currency: &str, timestamp: Option<u64>) -> Result<FiatPrice> {
+            self.0
+                .map(|btc_price| FiatPrice {
+                    currency: currency.to_owned(),
+                    btc_price,
+                    timestamp: timestamp.unwrap_or(1_700_000_000),
+                })
+                .ok_or(Error::Generic("offline".to_owned()))
+        }
+    }
+
+    #[test]
+    fn fiat_valuation() {
+        let price = FixedPriceProvider(Some(50_000.0))
+            .get_price("USD", None)
+            .unwrap();
+        let value = price.value_of(Amount::from_sat(1_234_567));
+        assert_eq!(value.to_string(), "617.28 USD");
+        assert_eq!(
+            serde_json::to_string(&value).unwrap(),
+            "\"617.28 USD\"".to_owned()
+        );
+    }
+
+    #[test]
+    fn offline_safe() {
+        assert!(try_get_price(None, "USD", None).is_none());
+        assert!(try_get_price(Some(&FixedPriceProvider(None)), "USD", None).is_none());
+        assert!(
+            try_get_price(Some(&FixedPriceProvider(Some(1.0))), "USD", Some(42))
+                .is_some_and(|p| p.timestamp == 42)
+        );
+    }
+
+    #[cfg(feature = "mempool-space")]
+    #[test]
+    fn mempool_space_parse_price() {
+        let current = serde_json::json!({
+            "time": 1703252411,
+            "USD": 43753,
+            "EUR": 40545,
+        });
+        let price = MempoolSpacePriceProvider::parse_price(&current, "EUR").unwrap();
+        assert_eq!(price.btc_price, 40545.0);
+        assert_eq!(price.timestamp, 1703252411);
+
+        let historical = serde_json::json!({
+            "prices": [{"time": 1499904000, "EUR": 1964, "USD": 2254.9}],
+            "exchangeRates": {"USDEUR": 0.92}
+        });
+        let price = MempoolSpacePriceProvider::parse_price(&historical, "USD").unwrap();
+        assert_eq!(price.btc_price, 2254.9);
+        assert_eq!(price.timestamp, 1499904000);
+
+        assert!(MempoolSpacePriceProvider::parse_price(&current, "XYZ").is_err());
+        assert!(
+            MempoolSpacePriceProvider::parse_price(&serde_json::json!({"prices": []}), "USD")
+                .is_err()
+        );
+    }
+}
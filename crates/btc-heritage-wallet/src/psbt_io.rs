@@ -0,0 +1,403 @@
+//! Import and export of PSBTs in the encodings used by wallets and signing devices:
+//! - [PsbtFormat::Base64], the usual textual encoding (BIP-174);
+//! - [PsbtFormat::Hex];
+//! - [PsbtFormat::Binary], the raw serialization, as found in `.psbt` files;
+//! - [PsbtFormat::Ur], the `crypto-psbt` type of the BC-UR specification, used by the
+//!   animated QR codes of air-gapped devices. Large PSBTs are split in multiple parts, see
+//!   [UrPsbtEncoder] and [UrPsbtDecoder].
+//!
+//! [parse_psbt] detects the encoding on its own, front-ends should use it for every PSBT
+//! provided by the user.
+
+use core::str::FromStr;
+
+use btc_heritage::{
+    bitcoin::hex::{DisplayHex, FromHex},
+    PartiallySignedTransaction,
+};
+
+use crate::errors::{Error, Result};
+
+/// The BC-UR type of a PSBT
+pub const UR_TYPE: &str = "crypto-psbt";
+/// The maximum length of a UR fragment fitting comfortably in a QR code
+pub const DEFAULT_UR_FRAGMENT_LEN: usize = 200;
+
+/// The magic bytes starting every serialized PSBT
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// An encoding of a PSBT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PsbtFormat {
+    Base64,
+    Hex,
+    Binary,
+    Ur,
+}
+
+impl PsbtFormat {
+    /// Detect the encoding of the PSBT in `data`, or [None] if it does not look like a PSBT.
+    ///
+    /// Surrounding whitespaces are ignored for the textual encodings.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(PSBT_MAGIC) {
+            return Some(Self::Binary);
+        }
+        let text = core::str::from_utf8(data).ok()?.trim();
+        if text.len() >= 3 && text[..3].eq_ignore_ascii_case("ur:") {
+            Some(Self::Ur)
+        } else if text.len() >= 10
+            && text[..10].eq_ignore_ascii_case(&PSBT_MAGIC.to_lower_hex_string())
+        {
+            Some(Self::Hex)
+        } else if text.starts_with("cHNidP") {
+            Some(Self::Base64)
+        } else {
+            None
+        }
+    }
+}
+
+impl core::fmt::Display for PsbtFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PsbtFormat::Base64 => "base64",
+                PsbtFormat::Hex => "hex",
+                PsbtFormat::Binary => "binary",
+                PsbtFormat::Ur => "ur",
+            }
+        )
+    }
+}
+
+impl FromStr for PsbtFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "base64" => Ok(PsbtFormat::Base64),
+            "hex" => Ok(PsbtFormat::Hex),
+            "binary" | "bin" => Ok(PsbtFormat::Binary),
+            "ur" => Ok(PsbtFormat::Ur),
+            _ => Err(Error::InvalidPsbt(format!("unknown PSBT format: {s}"))),
+        }
+    }
+}
+
+/// Parse a PSBT, detecting its encoding with [PsbtFormat::detect].
+///
+/// For [PsbtFormat::Ur], `data` may contain all the parts of a multi-part UR,
+/// separated by whitespaces, in any order.
+///
+/// # Errors
+/// Returns [Error::InvalidPsbt] if the encoding cannot be detected or if `data` is not a valid PSBT
+pub fn parse_psbt(data: &[u8]) -> Result<PartiallySignedTransaction> {
+    let format = PsbtFormat::detect(data)
+        .ok_or_else(|| Error::InvalidPsbt("unrecognized PSBT encoding".to_owned()))?;
+    parse_psbt_as(data, format)
+}
+
+/// Parse a PSBT in the given [PsbtFormat]
+///
+/// # Errors
+/// Returns [Error::InvalidPsbt] if `data` is not a valid PSBT in this format
+pub fn parse_psbt_as(data: &[u8], format: PsbtFormat) -> Result<PartiallySignedTransaction> {
+    let invalid = |e: &dyn core::fmt::Display| Error::InvalidPsbt(e.to_string());
+    let text = || {
+        core::str::from_utf8(data)
+            .map(str::trim)
+            .map_err(|e| invalid(&e))
+    };
+    match format {
+        PsbtFormat::Binary => {
+            PartiallySignedTransaction::deserialize(data).map_err(|e| invalid(&e))
+        }
+        PsbtFormat::Base64 => {
+            PartiallySignedTransaction::from_str(text()?).map_err(|e| invalid(&e))
+        }
+        PsbtFormat::Hex => {
+            let bytes = Vec::<u8>::from_hex(text()?).map_err(|e| invalid(&e))?;
+            PartiallySignedTransaction::deserialize(&bytes).map_err(|e| invalid(&e))
+        }
+        PsbtFormat::Ur => {
+            let mut decoder = UrPsbtDecoder::new();
+            for part in text()?.split_whitespace() {
+                decoder.receive(part)?;
+                if decoder.is_complete() {
+                    break;
+                }
+            }
+            decoder.psbt()?.ok_or_else(|| {
+                Error::InvalidPsbt("the UR parts do not contain the whole PSBT".to_owned())
+            })
+        }
+    }
+}
+
+/// Encode `psbt` in the given [PsbtFormat]. [PsbtFormat::Ur] produces a single-part UR,
+/// use [UrPsbtEncoder] for a multi-part UR.
+pub fn encode_psbt(psbt: &PartiallySignedTransaction, format: PsbtFormat) -> Vec<u8> {
+    match format {
+        PsbtFormat::Binary => psbt.serialize(),
+        PsbtFormat::Base64 => psbt.to_string().into_bytes(),
+        PsbtFormat::Hex => psbt.serialize().to_lower_hex_string().into_bytes(),
+        PsbtFormat::Ur => ur::encode(
+            &cbor_bytes(&psbt.serialize()),
+            &ur::ur::Type::Custom(UR_TYPE),
+        )
+        .into_bytes(),
+    }
+}
+
+/// Serialize a PSBT stored in the [Database](crate::Database) as [PsbtFormat::Base64],
+/// to be used with `#[serde(with = "crate::psbt_io::serde_base64")]`
+pub(crate) mod serde_base64 {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        psbt: &PartiallySignedTransaction,
+        serializer: S,
+    ) -> core::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(psbt)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> core::result::Result<PartiallySignedTransaction, D::Error> {
+        let psbt = String::deserialize(deserializer)?;
+        PartiallySignedTransaction::from_str(&psbt).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Produce the parts of a multi-part `crypto-psbt` UR, e.g. for an animated QR code.
+///
+/// The first [UrPsbtEncoder::fragment_count] parts are enough to rebuild the PSBT. The
+/// following parts are fountain-encoded mixes of the fragments, allowing a decoder that
+/// missed some parts to complete the PSBT without waiting for a whole new cycle.
+pub struct UrPsbtEncoder {
+    encoder: ur::Encoder<'static>,
+}
+
+impl core::fmt::Debug for UrPsbtEncoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UrPsbtEncoder")
+            .field("fragment_count", &self.fragment_count())
+            .field("current_index", &self.encoder.current_index())
+            .finish()
+    }
+}
+
+impl UrPsbtEncoder {
+    /// Create an encoder splitting `psbt` in fragments of at most `max_fragment_len` bytes,
+    /// see [DEFAULT_UR_FRAGMENT_LEN]
+    pub fn new(psbt: &PartiallySignedTransaction, max_fragment_len: usize) -> Result<Self> {
+        Ok(Self {
+            encoder: ur::Encoder::new(&cbor_bytes(&psbt.serialize()), max_fragment_len, UR_TYPE)
+                .map_err(Error::generic)?,
+        })
+    }
+
+    /// The number of fragments of the PSBT
+    pub fn fragment_count(&self) -> usize {
+        self.encoder.fragment_count()
+    }
+
+    /// Return the next part of the UR, this never ends
+    pub fn next_part(&mut self) -> String {
+        self.encoder
+            .next_part()
+            .expect("the encoder was successfully created")
+    }
+}
+
+/// Rebuild a PSBT from the parts of a `crypto-psbt` UR, single or multi-part, received in any order
+#[derive(Default)]
+pub struct UrPsbtDecoder {
+    decoder: ur::Decoder,
+    single_part: Option<Vec<u8>>,
+}
+
+impl core::fmt::Debug for UrPsbtDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UrPsbtDecoder")
+            .field("complete", &self.is_complete())
+            .finish()
+    }
+}
+
+impl UrPsbtDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process a part of the UR. The parts may be received in any order, and more than once.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPsbt] if `part` is not a valid part of a `crypto-psbt` UR
+    pub fn receive(&mut self, part: &str) -> Result<()> {
+        // QR codes usually carry the UR in upper case
+        let part = part.trim().to_ascii_lowercase();
+        let ur_type = part
+            .strip_prefix("ur:")
+            .and_then(|rest| rest.split_once('/'))
+            .map(|(ur_type, _)| ur_type);
+        // "psbt" is the newer name of the type
+        if !matches!(ur_type, Some(UR_TYPE | "psbt")) {
+            return Err(Error::InvalidPsbt(format!("not a {UR_TYPE} UR: {part}")));
+        }
+        match ur::decode(&part).map_err(|e| Error::InvalidPsbt(e.to_string()))? {
+            (ur::ur::Kind::SinglePart, cbor) => self.single_part = Some(cbor),
+            (ur::ur::Kind::MultiPart, _) => self
+                .decoder
+                .receive(&part)
+                .map_err(|e| Error::InvalidPsbt(e.to_string()))?,
+        }
+        Ok(())
+    }
+
+    /// Return `true` if enough parts were received to rebuild the PSBT
+    pub fn is_complete(&self) -> bool {
+        self.single_part.is_some() || self.decoder.complete()
+    }
+
+    /// Return the PSBT, or [None] if more parts are needed
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPsbt] if the UR does not contain a valid PSBT
+    pub fn psbt(&self) -> Result<Option<PartiallySignedTransaction>> {
+        let cbor = match &self.single_part {
+            Some(cbor) => cbor.clone(),
+            None => match self
+                .decoder
+                .message()
+                .map_err(|e| Error::InvalidPsbt(e.to_string()))?
+            {
+                Some(cbor) => cbor,
+                None => return Ok(None),
+            },
+        };
+        let bytes = cbor_bytes_content(&cbor)
+            .ok_or_else(|| Error::InvalidPsbt("malformed crypto-psbt payload".to_owned()))?;
+        PartiallySignedTransaction::deserialize(bytes)
+            .map(Some)
+            .map_err(|e| Error::InvalidPsbt(e.to_string()))
+    }
+}
+
+/// Wrap `bytes` in a CBOR byte string, the payload of a `crypto-psbt` UR
+fn cbor_bytes(bytes: &[u8]) -> Vec<u8> {
+    const MAJOR_TYPE_BYTES: u8 = 0x40;
+    let len = bytes.len();
+    let mut cbor = Vec::with_capacity(len + 9);
+    match len {
+        0..=23 => cbor.push(MAJOR_TYPE_BYTES | len as u8),
+        24..=0xff => cbor.extend([MAJOR_TYPE_BYTES | 24, len as u8]),
+        0x100..=0xffff => {
+            cbor.push(MAJOR_TYPE_BYTES | 25);
+            cbor.extend((len as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            cbor.push(MAJOR_TYPE_BYTES | 26);
+            cbor.extend((len as u32).to_be_bytes());
+        }
+        _ => {
+            cbor.push(MAJOR_TYPE_BYTES | 27);
+            cbor.extend((len as u64).to_be_bytes());
+        }
+    }
+    cbor.extend_from_slice(bytes);
+    cbor
+}
+
+/// Return the content of the CBOR byte string `cbor`, or [None] if it is not a single byte string
+fn cbor_bytes_content(cbor: &[u8]) -> Option<&[u8]> {
+    let (&head, rest) = cbor.split_first()?;
+    if head >> 5 != 2 {
+        return None;
+    }
+    let (len, content) = match head & 0x1f {
+        len @ 0..=23 => (len as usize, rest),
+        additional @ 24..=27 => {
+            let size = 1 << (additional - 24);
+            if rest.len() < size {
+                return None;
+            }
+            let (len, content) = rest.split_at(size);
+            let len = len.iter().fold(0u64, |acc, &byte| (acc << 8) | byte as u64);
+            (usize::try_from(len).ok()?, content)
+        }
+        _ => return None,
+    };
+    (content.len() == len).then_some(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btc_heritage::psbttests::{get_test_unsigned_psbt, TestPsbt};
+
+    #[test]
+    fn detect_and_parse() {
+        let psbt = get_test_unsigned_psbt(TestPsbt::OwnerDrain);
+        for format in [
+            PsbtFormat::Base64,
+            PsbtFormat::Hex,
+            PsbtFormat::Binary,
+            PsbtFormat::Ur,
+        ] {
+            let mut encoded = encode_psbt(&psbt, format);
+            assert_eq!(PsbtFormat::detect(&encoded), Some(format), "{format}");
+            assert_eq!(parse_psbt(&encoded).unwrap(), psbt, "{format}");
+            if format != PsbtFormat::Binary {
+                // Surrounding whitespaces and upper case are tolerated
+                encoded.push(b'\n');
+                if format != PsbtFormat::Base64 {
+                    encoded.make_ascii_uppercase();
+                }
+                assert_eq!(parse_psbt(&encoded).unwrap(), psbt, "{format}");
+            }
+        }
+        assert!(parse_psbt(b"not a psbt").is_err());
+        assert!(parse_psbt(b"cHNidP8BAA").is_err());
+    }
+
+    #[test]
+    fn ur_multipart() {
+        let psbt = get_test_unsigned_psbt(TestPsbt::OwnerDrain);
+        let mut encoder = UrPsbtEncoder::new(&psbt, 50).unwrap();
+        assert!(encoder.fragment_count() > 1);
+
+        // Skip the first part, the fountain-encoded parts make up for it
+        let mut parts = (0..encoder.fragment_count() * 3)
+            .map(|_| encoder.next_part())
+            .skip(1);
+        let mut decoder = UrPsbtDecoder::new();
+        while !decoder.is_complete() {
+            assert!(decoder.psbt().unwrap().is_none());
+            decoder.receive(&parts.next().unwrap()).unwrap();
+        }
+        assert_eq!(decoder.psbt().unwrap(), Some(psbt));
+
+        assert!(UrPsbtDecoder::new()
+            .receive("ur:bytes/aeadaolazmjendeoti")
+            .is_err());
+    }
+
+    #[test]
+    fn cbor() {
+        for len in [0, 23, 24, 255, 256, 70_000] {
+            let bytes = vec![7u8; len];
+            assert_eq!(
+                cbor_bytes_content(&cbor_bytes(&bytes)),
+                Some(bytes.as_slice())
+            );
+        }
+        assert_eq!(cbor_bytes_content(&[0x58]), None);
+        // Not a byte string
+        assert_eq!(cbor_bytes_content(&[0x61, b'a']), None);
+    }
+}
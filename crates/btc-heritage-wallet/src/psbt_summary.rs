@@ -1,14 +1,27 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use btc_heritage::{
-    bitcoin::{bip32::Fingerprint, Address, Amount, FeeRate, Network},
+    bitcoin::{
+        absolute::LockTime,
+        bip32::Fingerprint,
+        opcodes::all::{OP_CLTV, OP_CSV},
+        psbt::Input,
+        script::Instruction,
+        taproot::TapLeafHash,
+        Address, Amount, FeeRate, Network, Script,
+    },
     heritage_wallet::get_expected_tx_weight,
+    utils::{check_heir_time_locks, describe_lock_time},
     PartiallySignedTransaction,
 };
 use heritage_service_api_client::TransactionSummary;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use crate::errors::{Error, Result};
+use crate::{
+    errors::{Error, Result},
+    online_wallet::OnlineWallet,
+    price_provider::{try_get_price, FiatAmount, FiatPrice, PriceProvider},
+};
 
 pub fn serialize_amount<S>(amount: &Amount, serializer: S) -> core::result::Result<S::Ok, S::Error>
 where
@@ -66,6 +79,99 @@ where
     let fr = fee_rate.to_sat_per_kwu() as f32 / 250.0;
     serializer.serialize_str(&format!("{} sat/vB", fr))
 }
+pub fn serialize_option_fee_rate<S>(
+    opt: &Option<FeeRate>,
+    serializer: S,
+) -> core::result::Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    match opt {
+        Some(fee_rate) => serialize_fee_rate(fee_rate, serializer),
+        None => serializer.serialize_str("Unknown"),
+    }
+}
+
+/// The spend path an input of a PSBT will be spent through, as far as it can be told from the PSBT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendPath {
+    /// The key path of the owner
+    Owner,
+    /// The script of the owner quorum, without time lock
+    OwnerQuorum,
+    /// The time-locked script of the heir with this fingerprint
+    Heir(Fingerprint),
+    /// The PSBT input does not allow to tell
+    Unknown,
+}
+
+impl SpendPath {
+    /// Infer the [SpendPath] of a PSBT input. The PSBTs created by the wallet are minimized
+    /// for their spender, so they only keep the key or the script of the intended spend path.
+    pub(crate) fn of_input(psbt_in: &Input) -> Self {
+        if psbt_in.tap_internal_key.is_none() {
+            return SpendPath::Unknown;
+        }
+        let mut scripts = psbt_in.tap_scripts.values();
+        match (scripts.next(), scripts.next()) {
+            (None, _) => SpendPath::Owner,
+            (Some((script, _)), None) => {
+                if !is_time_locked(script) {
+                    SpendPath::OwnerQuorum
+                } else if let Some((_, (fingerprint, _))) = psbt_in
+                    .tap_key_origins
+                    .values()
+                    .find(|(leaf_hashes, _)| !leaf_hashes.is_empty())
+                {
+                    SpendPath::Heir(*fingerprint)
+                } else {
+                    SpendPath::Unknown
+                }
+            }
+            // Not minimized, any path could be used
+            (Some(_), Some(_)) => SpendPath::Unknown,
+        }
+    }
+
+    /// Return the [SpendPath] the signatures of a PSBT input go through, or [None] if
+    /// the input is not signed. Unlike [SpendPath::of_input], it does not rely on the
+    /// minimization of the PSBT.
+    pub(crate) fn of_signed_input(psbt_in: &Input) -> Option<Self> {
+        if psbt_in.tap_key_sig.is_some() {
+            return Some(SpendPath::Owner);
+        }
+        let Some((key, leaf_hash)) = psbt_in.tap_script_sigs.keys().next() else {
+            // A finalized key path spend only has the signature in its witness
+            return psbt_in
+                .final_script_witness
+                .as_ref()
+                .map(|witness| match witness.len() {
+                    1 => SpendPath::Owner,
+                    _ => SpendPath::Unknown,
+                });
+        };
+        let spend_path = match psbt_in.tap_scripts.values().find(|(script, leaf_version)| {
+            TapLeafHash::from_script(script, *leaf_version) == *leaf_hash
+        }) {
+            Some((script, _)) if !is_time_locked(script) => SpendPath::OwnerQuorum,
+            Some(_) => psbt_in
+                .tap_key_origins
+                .get(key)
+                .map(|(_, (fingerprint, _))| SpendPath::Heir(*fingerprint))
+                .unwrap_or(SpendPath::Unknown),
+            None => SpendPath::Unknown,
+        };
+        Some(spend_path)
+    }
+}
+
+/// Return `true` if `script` has an absolute or relative time lock
+fn is_time_locked(script: &Script) -> bool {
+    script.instructions().any(|instruction| {
+        matches!(instruction, Ok(Instruction::Op(op)) if op == OP_CLTV || op == OP_CSV)
+    })
+}
 
 #[derive(Debug, Serialize)]
 struct InputSummary {
@@ -76,6 +182,8 @@ struct InputSummary {
     known_owning_fingerprints: Vec<Fingerprint>,
     #[serde(serialize_with = "serialize_option")]
     known_owning_wallets: Option<Vec<String>>,
+    spend_path: SpendPath,
+    is_signed: bool,
 }
 #[derive(Debug, Serialize)]
 struct OutputSummary {
@@ -99,6 +207,187 @@ pub struct PsbtSummary {
     fee: Amount,
     #[serde(serialize_with = "serialize_fee_rate")]
     fee_rate: FeeRate,
+    /// The date or block height before which the transaction cannot be mined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_time: Option<String>,
+    /// The inconsistencies between the time locks of the transaction and the spend
+    /// conditions of its heir inputs, see [btc_heritage::utils::check_heir_time_locks]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    time_lock_issues: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fiat_estimate: Option<PsbtFiatEstimate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verification: Option<PsbtVerification>,
+}
+#[derive(Debug, Serialize)]
+struct PsbtFiatEstimate {
+    total_spend: FiatAmount,
+    send_out: FiatAmount,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    change: Option<FiatAmount>,
+    fee: FiatAmount,
+    price: FiatPrice,
+}
+
+/// The result of the verification of a PSBT against the state of the wallet, see [PsbtSummary::verify]
+#[derive(Debug, Clone, Serialize)]
+pub struct PsbtVerification {
+    /// The inputs that are not UTXOs of the wallet
+    unknown_inputs: Vec<String>,
+    /// The inputs for which the PSBT claims another address or amount than the UTXO of the wallet
+    mismatched_inputs: Vec<String>,
+    /// The outputs presented as change that do not pay to an address of the wallet
+    foreign_change_outputs: Vec<String>,
+    /// The addresses of the wallet receiving an output although they were already used
+    reused_addresses: Vec<String>,
+    /// The fee computed from the UTXOs of the wallet rather than from the PSBT,
+    /// unknown if some inputs are unknown
+    #[serde(serialize_with = "serialize_option_amount")]
+    fee: Option<Amount>,
+    #[serde(serialize_with = "serialize_option_fee_rate")]
+    fee_rate: Option<FeeRate>,
+}
+
+impl PsbtVerification {
+    /// Return `true` if nothing suspicious was found. Address reuse only harms privacy
+    /// and does not make the verification fail.
+    pub fn is_ok(&self) -> bool {
+        self.unknown_inputs.is_empty()
+            && self.mismatched_inputs.is_empty()
+            && self.foreign_change_outputs.is_empty()
+            && self.fee.is_some()
+    }
+    /// The fee of the PSBT, computed from the UTXOs of the wallet
+    pub fn fee(&self) -> Option<Amount> {
+        self.fee
+    }
+    /// The effective fee rate of the PSBT, computed from the UTXOs of the wallet
+    pub fn fee_rate(&self) -> Option<FeeRate> {
+        self.fee_rate
+    }
+}
+
+impl PsbtSummary {
+    /// Cross-check the inputs and outputs of `psbt`, the PSBT this [PsbtSummary] was built from,
+    /// against the UTXOs, addresses and transactions of `online_wallet`, independently of the
+    /// data carried by the PSBT itself. The result is added to the summary, see [PsbtSummary::verification].
+    ///
+    /// This is meant as a review step before signing, e.g. on a hardware device.
+    ///
+    /// # Errors
+    /// Returns an error if the state of `online_wallet` cannot be retrieved
+    pub fn verify<W: OnlineWallet + ?Sized>(
+        &mut self,
+        psbt: &PartiallySignedTransaction,
+        online_wallet: &W,
+    ) -> Result<&PsbtVerification> {
+        let utxos = online_wallet
+            .list_heritage_utxos()?
+            .into_iter()
+            .map(|hu| (hu.outpoint.to_string(), hu))
+            .collect::<HashMap<_, _>>();
+        let wallet_addresses = online_wallet
+            .list_addresses()?
+            .iter()
+            .map(|wa| wa.address().to_string())
+            .collect::<HashSet<_>>();
+        let used_addresses = online_wallet
+            .list_transactions()?
+            .iter()
+            .flat_map(|tx_summary| tx_summary.owned_outputs.iter())
+            .map(|owned_output| owned_output.address.to_string())
+            .chain(self.inputs.iter().map(|is| is.address.clone()))
+            .collect::<HashSet<_>>();
+
+        let mut unknown_inputs = vec![];
+        let mut mismatched_inputs = vec![];
+        let mut verified_total_spend = Amount::ZERO;
+        for input in self.inputs.iter() {
+            match utxos.get(&input.previous_output) {
+                Some(utxo) => {
+                    if utxo.amount != input.amount || utxo.address.to_string() != input.address {
+                        mismatched_inputs.push(input.previous_output.clone());
+                    }
+                    verified_total_spend += utxo.amount;
+                }
+                None => unknown_inputs.push(input.previous_output.clone()),
+            }
+        }
+
+        let mut foreign_change_outputs = vec![];
+        let mut reused_addresses = vec![];
+        for output in self.outputs.iter() {
+            let is_wallet_address = wallet_addresses.contains(&output.address);
+            if output.is_owned.is_some_and(|is_owned| is_owned) && !is_wallet_address {
+                foreign_change_outputs.push(output.address.clone());
+            }
+            if is_wallet_address && used_addresses.contains(&output.address) {
+                reused_addresses.push(output.address.clone());
+            }
+        }
+
+        let total_out = self.outputs.iter().map(|os| os.amount).sum::<Amount>();
+        let fee = if unknown_inputs.is_empty() {
+            Some(
+                verified_total_spend
+                    .checked_sub(total_out)
+                    .ok_or(Error::Generic(
+                        "Invalid PSBT. Fee cannot be negative".to_owned(),
+                    ))?,
+            )
+        } else {
+            None
+        };
+        // The weight can only be computed for the Taproot PSBTs of the wallet
+        let fee_rate = fee
+            .filter(|_| {
+                psbt.inputs.iter().all(|psbt_in| {
+                    psbt_in.tap_internal_key.is_some() && psbt_in.tap_merkle_root.is_some()
+                })
+            })
+            .map(|fee| fee / get_expected_tx_weight(psbt));
+
+        let verification = PsbtVerification {
+            unknown_inputs,
+            mismatched_inputs,
+            foreign_change_outputs,
+            reused_addresses,
+            fee,
+            fee_rate,
+        };
+        if !verification.is_ok() {
+            log::warn!("PsbtSummary::verify - verification={verification:?}");
+        }
+        Ok(self.verification.insert(verification))
+    }
+
+    /// The inconsistencies between the time locks of the PSBT and the spend conditions
+    /// of its heir inputs, empty if there is none
+    pub fn time_lock_issues(&self) -> &[String] {
+        &self.time_lock_issues
+    }
+
+    /// The result of [PsbtSummary::verify], if it was called
+    pub fn verification(&self) -> Option<&PsbtVerification> {
+        self.verification.as_ref()
+    }
+
+    /// Add an estimate of the amounts in `currency` at the current price, if `price_provider`
+    /// is able to provide one. Without a provider or if it fails, the summary is left untouched.
+    pub fn add_fiat_estimate(
+        &mut self,
+        price_provider: Option<&dyn PriceProvider>,
+        currency: &str,
+    ) {
+        self.fiat_estimate =
+            try_get_price(price_provider, currency, None).map(|price| PsbtFiatEstimate {
+                total_spend: price.value_of(self.total_spend),
+                send_out: price.value_of(self.send_out),
+                change: self.change.map(|change| price.value_of(change)),
+                fee: price.value_of(self.fee),
+                price,
+            });
+    }
 }
 
 impl TryFrom<(&PartiallySignedTransaction, Network)> for PsbtSummary {
@@ -229,12 +518,18 @@ impl
                     None
                 };
 
+                let is_signed = psbt_in.tap_key_sig.is_some()
+                    || !psbt_in.tap_script_sigs.is_empty()
+                    || psbt_in.final_script_witness.is_some();
+
                 Ok(InputSummary {
                     previous_output: tx_in.previous_output.to_string(),
                     address,
                     amount,
                     known_owning_fingerprints,
                     known_owning_wallets,
+                    spend_path: SpendPath::of_input(psbt_in),
+                    is_signed,
                 })
             })
             .collect::<Result<Vec<_>>>()?;
@@ -289,6 +584,16 @@ impl
             fee / get_expected_tx_weight(psbt)
         };
 
+        let lock_time = Some(psbt.unsigned_tx.lock_time)
+            .filter(|lock_time| *lock_time != LockTime::ZERO)
+            .map(|lock_time| describe_lock_time(&lock_time));
+        let time_lock_issues = match check_heir_time_locks(psbt) {
+            Err(btc_heritage::errors::Error::InconsistentTimeLocks(issues)) => {
+                issues.iter().map(|issue| issue.to_string()).collect()
+            }
+            _ => vec![],
+        };
+
         Ok(PsbtSummary {
             inputs,
             outputs,
@@ -301,6 +606,10 @@ impl
             },
             fee,
             fee_rate,
+            lock_time,
+            time_lock_issues,
+            fiat_estimate: None,
+            verification: None,
         })
     }
 }
@@ -314,3 +623,42 @@ impl core::fmt::Display for PsbtSummary {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btc_heritage::psbttests::{get_test_unsigned_psbt, TestPsbt};
+
+    #[test]
+    fn spend_path() {
+        let psbt = get_test_unsigned_psbt(TestPsbt::OwnerDrain);
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|psbt_in| SpendPath::of_input(psbt_in) == SpendPath::Owner));
+
+        let psbt = get_test_unsigned_psbt(TestPsbt::BackupPresent);
+        assert!(psbt
+            .inputs
+            .iter()
+            .all(|psbt_in| matches!(SpendPath::of_input(psbt_in), SpendPath::Heir(_))));
+
+        assert_eq!(SpendPath::of_input(&Input::default()), SpendPath::Unknown);
+    }
+
+    #[test]
+    fn time_lock_issues() {
+        let psbt = get_test_unsigned_psbt(TestPsbt::BackupPresent);
+        let summary = PsbtSummary::try_from((&psbt, Network::Regtest)).unwrap();
+        assert!(summary
+            .lock_time
+            .as_ref()
+            .is_some_and(|lt| lt.ends_with("UTC")));
+        assert!(summary.time_lock_issues().is_empty());
+
+        let mut tampered = psbt.clone();
+        tampered.unsigned_tx.input[0].sequence = btc_heritage::bitcoin::Sequence::MAX;
+        let summary = PsbtSummary::try_from((&tampered, Network::Regtest)).unwrap();
+        assert_eq!(summary.time_lock_issues().len(), 1);
+    }
+}
@@ -0,0 +1,298 @@
+//! Heritage context travelling with a PSBT handed to an external signer, see [SigningInstructions].
+
+use btc_heritage::{
+    bitcoin::{
+        absolute::LockTime, bip32::ChildNumber, psbt::raw::ProprietaryKey, psbt::Input, OutPoint,
+        Sequence, Txid,
+    },
+    AccountXPubId, PartiallySignedTransaction,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::{Error, Result},
+    SpendPath,
+};
+
+/// The prefix of the proprietary PSBT keys of the wallet (BIP-174 `PSBT_GLOBAL_PROPRIETARY`)
+pub const PROPRIETARY_PREFIX: &[u8] = b"heritage";
+/// The subtype of the proprietary PSBT key holding the [SigningInstructions]
+const SIGNING_INSTRUCTIONS_SUBTYPE: u8 = 0x00;
+
+fn proprietary_key() -> ProprietaryKey {
+    ProprietaryKey {
+        prefix: PROPRIETARY_PREFIX.to_vec(),
+        subtype: SIGNING_INSTRUCTIONS_SUBTYPE,
+        key: vec![],
+    }
+}
+
+/// Why the transaction has its lock time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LockTimeRationale {
+    /// The transaction has no lock time
+    None,
+    /// The lock time is the current block height, to discourage fee sniping
+    AntiFeeSniping,
+    /// The lock time is required by the time lock of an heir spend path
+    HeirTimeLock,
+}
+
+/// What the signer is expected to do with an input
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputInstruction {
+    pub previous_output: OutPoint,
+    /// The account of the subwallet owning the input, if the PSBT tells it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<AccountXPubId>,
+    pub spend_path: SpendPath,
+    /// The sequence of the input, it carries the relative time lock of the heir spend paths
+    pub sequence: Sequence,
+}
+
+/// The heritage context of a PSBT for an external signer: the subwallets and spend paths
+/// of its inputs and the time locks they require. They are embedded in the PSBT with
+/// [SigningInstructions::embed] or exported as a JSON sidecar file, and a signed PSBT
+/// returned by the signer is checked against them with [SigningInstructions::verify].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SigningInstructions {
+    /// The id of the transaction to sign, which does not change when signing
+    pub txid: Txid,
+    pub lock_time: LockTime,
+    pub lock_time_rationale: LockTimeRationale,
+    pub inputs: Vec<InputInstruction>,
+}
+
+/// The result of [SigningInstructions::verify]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InstructionsVerification {
+    /// The signed PSBT is not for the transaction of the instructions
+    transaction_mismatch: bool,
+    /// The lock time of the signed PSBT, if it is not the expected one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lock_time_mismatch: Option<LockTime>,
+    /// The inputs missing from the signed PSBT
+    missing_inputs: Vec<OutPoint>,
+    /// The inputs whose sequence is not the expected one
+    sequence_mismatches: Vec<OutPoint>,
+    /// The inputs that are not signed
+    unsigned_inputs: Vec<OutPoint>,
+    /// The inputs signed through another spend path than the expected one
+    unexpected_spend_paths: Vec<OutPoint>,
+}
+
+impl InstructionsVerification {
+    /// Return `true` if the signed PSBT follows the instructions and all its inputs are signed
+    pub fn is_ok(&self) -> bool {
+        !self.transaction_mismatch
+            && self.lock_time_mismatch.is_none()
+            && self.missing_inputs.is_empty()
+            && self.sequence_mismatches.is_empty()
+            && self.unsigned_inputs.is_empty()
+            && self.unexpected_spend_paths.is_empty()
+    }
+    /// The inputs that are not signed
+    pub fn unsigned_inputs(&self) -> &[OutPoint] {
+        &self.unsigned_inputs
+    }
+}
+
+/// Return the account of the owner key of `psbt_in`, i.e. the internal key of its Taproot output
+fn input_account(psbt_in: &Input) -> Option<AccountXPubId> {
+    let internal_key = psbt_in.tap_internal_key.as_ref()?;
+    let (_, (_, derivation_path)) = psbt_in.tap_key_origins.get(internal_key)?;
+    match derivation_path.as_ref().get(2) {
+        Some(ChildNumber::Hardened { index }) => Some(*index),
+        _ => None,
+    }
+}
+
+impl SigningInstructions {
+    /// Build the [SigningInstructions] of `psbt`, an unsigned PSBT created by the wallet
+    pub fn from_psbt(psbt: &PartiallySignedTransaction) -> Self {
+        let inputs = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .zip(psbt.inputs.iter())
+            .map(|(tx_in, psbt_in)| InputInstruction {
+                previous_output: tx_in.previous_output,
+                account: input_account(psbt_in),
+                spend_path: SpendPath::of_input(psbt_in),
+                sequence: tx_in.sequence,
+            })
+            .collect::<Vec<_>>();
+        let lock_time = psbt.unsigned_tx.lock_time;
+        let lock_time_rationale = if inputs
+            .iter()
+            .any(|ii| matches!(ii.spend_path, SpendPath::Heir(_)))
+        {
+            LockTimeRationale::HeirTimeLock
+        } else if lock_time == LockTime::ZERO {
+            LockTimeRationale::None
+        } else {
+            LockTimeRationale::AntiFeeSniping
+        };
+        Self {
+            txid: psbt.unsigned_tx.txid(),
+            lock_time,
+            lock_time_rationale,
+            inputs,
+        }
+    }
+
+    /// Embed the instructions in `psbt` as a global proprietary field, which signers that
+    /// do not know it simply ignore
+    ///
+    /// # Errors
+    /// Returns an error if the instructions cannot be serialized
+    pub fn embed(&self, psbt: &mut PartiallySignedTransaction) -> Result<()> {
+        psbt.proprietary
+            .insert(proprietary_key(), serde_json::to_vec(self)?);
+        Ok(())
+    }
+
+    /// Extract the instructions embedded in `psbt` by [SigningInstructions::embed], if any
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPsbt] if the embedded instructions cannot be read
+    pub fn extract(psbt: &PartiallySignedTransaction) -> Result<Option<Self>> {
+        psbt.proprietary
+            .get(&proprietary_key())
+            .map(|value| {
+                serde_json::from_slice(value)
+                    .map_err(|e| Error::InvalidPsbt(format!("invalid signing instructions: {e}")))
+            })
+            .transpose()
+    }
+
+    /// Check `signed_psbt`, the PSBT returned by the external signer, against the instructions:
+    /// it must be for the same transaction, with the same time locks, and its inputs must be
+    /// signed through the expected spend paths. An expected [SpendPath::Unknown] accepts any.
+    pub fn verify(&self, signed_psbt: &PartiallySignedTransaction) -> InstructionsVerification {
+        let mut verification = InstructionsVerification {
+            transaction_mismatch: signed_psbt.unsigned_tx.txid() != self.txid,
+            lock_time_mismatch: Some(signed_psbt.unsigned_tx.lock_time)
+                .filter(|lock_time| *lock_time != self.lock_time),
+            ..Default::default()
+        };
+        for instruction in self.inputs.iter() {
+            let outpoint = instruction.previous_output;
+            let Some((tx_in, psbt_in)) = signed_psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .zip(signed_psbt.inputs.iter())
+                .find(|(tx_in, _)| tx_in.previous_output == outpoint)
+            else {
+                verification.missing_inputs.push(outpoint);
+                continue;
+            };
+            if tx_in.sequence != instruction.sequence {
+                verification.sequence_mismatches.push(outpoint);
+            }
+            match SpendPath::of_signed_input(psbt_in) {
+                None => verification.unsigned_inputs.push(outpoint),
+                Some(spend_path) => {
+                    if instruction.spend_path != SpendPath::Unknown
+                        && spend_path != instruction.spend_path
+                    {
+                        verification.unexpected_spend_paths.push(outpoint);
+                    }
+                }
+            }
+        }
+        if !verification.is_ok() {
+            log::warn!("SigningInstructions::verify - verification={verification:?}");
+        }
+        verification
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KeyProvider, LocalKey, Mnemonic};
+    use btc_heritage::{
+        bitcoin::Network,
+        psbttests::{get_test_unsigned_psbt, TestPsbt},
+    };
+
+    fn owner_local_key() -> LocalKey {
+        LocalKey::restore(
+            Mnemonic::parse(
+                "owner owner owner owner owner owner owner owner owner owner owner panther",
+            )
+            .unwrap(),
+            None,
+            Network::Regtest,
+        )
+    }
+
+    #[test]
+    fn signing_instructions() {
+        let mut psbt = get_test_unsigned_psbt(TestPsbt::OwnerRecipients);
+        let instructions = SigningInstructions::from_psbt(&psbt);
+        assert_eq!(instructions.inputs.len(), psbt.inputs.len());
+        assert!(instructions
+            .inputs
+            .iter()
+            .all(|ii| ii.spend_path == SpendPath::Owner && ii.account.is_some()));
+        assert_ne!(
+            instructions.lock_time_rationale,
+            LockTimeRationale::HeirTimeLock
+        );
+
+        assert!(SigningInstructions::extract(&psbt).unwrap().is_none());
+        instructions.embed(&mut psbt).unwrap();
+        // Embedding does not change the transaction
+        assert_eq!(psbt.unsigned_tx.txid(), instructions.txid);
+        let exported = PartiallySignedTransaction::deserialize(&psbt.serialize()).unwrap();
+        assert_eq!(
+            SigningInstructions::extract(&exported).unwrap().as_ref(),
+            Some(&instructions)
+        );
+
+        // Nothing signed yet
+        let verification = instructions.verify(&psbt);
+        assert!(!verification.is_ok());
+        assert_eq!(verification.unsigned_inputs().len(), psbt.inputs.len());
+
+        let mut signed_psbt = psbt.clone();
+        owner_local_key().sign_psbt(&mut signed_psbt).unwrap();
+        assert!(instructions.verify(&signed_psbt).is_ok());
+
+        // Expecting another spend path
+        let mut heir_instructions = instructions.clone();
+        heir_instructions.inputs[0].spend_path = SpendPath::OwnerQuorum;
+        let verification = heir_instructions.verify(&signed_psbt);
+        assert_eq!(
+            verification.unexpected_spend_paths,
+            vec![instructions.inputs[0].previous_output]
+        );
+
+        // Another transaction
+        signed_psbt.unsigned_tx.input[0].sequence = Sequence::from_consensus(1);
+        let verification = instructions.verify(&signed_psbt);
+        assert!(verification.transaction_mismatch);
+        assert_eq!(
+            verification.sequence_mismatches,
+            vec![instructions.inputs[0].previous_output]
+        );
+    }
+
+    #[test]
+    fn signing_instructions_heir() {
+        let psbt = get_test_unsigned_psbt(TestPsbt::BackupPresent);
+        let instructions = SigningInstructions::from_psbt(&psbt);
+        assert_eq!(
+            instructions.lock_time_rationale,
+            LockTimeRationale::HeirTimeLock
+        );
+        assert!(instructions
+            .inputs
+            .iter()
+            .all(|ii| matches!(ii.spend_path, SpendPath::Heir(_))));
+    }
+}
@@ -0,0 +1,696 @@
+//! Silent Payments ([BIP-352](https://github.com/bitcoin/bips/blob/master/bip-0352.mediawiki))
+//! receive support.
+//!
+//! A [SilentPaymentAddress] is a static address that can be handed out publicly: each sender
+//! derives a unique Taproot output from it, so no address is ever reused on-chain and nothing
+//! derivable from the wallet AccountXPubs is disclosed.
+//!
+//! Outputs received this way are NOT protected by the Heritage scripts: they are simple
+//! Taproot key-path outputs of the silent payment spend key. The owner is expected to sweep them
+//! to a regular address of the Heritage wallet.
+//!
+//! Scanning is exposed transaction by transaction with [SilentPaymentKeys::scan_transaction]:
+//! the caller must provide the previous outputs of the transaction inputs. It is NOT part of
+//! the synchronization of the online wallets, as the BDK blockchain backends they use neither
+//! index the previous outputs nor serve the full blocks that scanning requires. An index
+//! server can instead publish the [tweak_data] of each transaction.
+
+use core::fmt::Display;
+
+use btc_heritage::bitcoin::{
+    bech32::{self, ToBase32, Variant},
+    hashes::{sha256, Hash, HashEngine},
+    script::Instruction,
+    secp256k1::{Parity, PublicKey, Scalar, Secp256k1, SecretKey, XOnlyPublicKey},
+    Network, OutPoint, Transaction, TxIn, TxOut,
+};
+
+use crate::errors::{Error, Result};
+
+/// The x coordinate of the NUMS point `H` of BIP-341. Taproot inputs spending through
+/// the script-path of an output with this internal key are not eligible.
+const NUMS_H: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+fn tagged_hash(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag_hash.as_byte_array());
+    engine.input(tag_hash.as_byte_array());
+    engine.input(msg);
+    sha256::Hash::from_engine(engine).to_byte_array()
+}
+
+fn to_scalar(bytes: [u8; 32]) -> Result<Scalar> {
+    Scalar::from_be_bytes(bytes)
+        .map_err(|_| Error::SilentPaymentError("hash is not a valid scalar".to_owned()))
+}
+
+/// A Silent Payment address, as given to the senders
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentAddress {
+    pub scan_pubkey: PublicKey,
+    pub spend_pubkey: PublicKey,
+    pub network: Network,
+}
+
+impl Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let hrp = match self.network {
+            Network::Bitcoin => "sp",
+            Network::Regtest => "sprt",
+            _ => "tsp",
+        };
+        let mut payload = Vec::with_capacity(66);
+        payload.extend_from_slice(&self.scan_pubkey.serialize());
+        payload.extend_from_slice(&self.spend_pubkey.serialize());
+        // Version 0
+        let mut data = vec![bech32::u5::try_from_u8(0).expect("0 is a valid u5")];
+        data.extend(payload.to_base32());
+        let address = bech32::encode(hrp, data, Variant::Bech32m).expect("hrp and data are valid");
+        f.write_str(&address)
+    }
+}
+
+/// An output of a transaction that was paid to our [SilentPaymentAddress]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentOutput {
+    pub outpoint: OutPoint,
+    pub value: u64,
+    /// The tweak to add to the spend private key in order to spend the output.
+    /// It includes the tweak of the label, if any.
+    pub tweak: Scalar,
+    /// The label `m` of the labeled address that was paid, if any
+    pub label: Option<u32>,
+}
+
+/// The keys needed to receive Silent Payments: the private scan key and the public spend key.
+///
+/// The spend private key is not needed to detect incoming payments, only to spend them.
+#[derive(Debug, Clone)]
+pub struct SilentPaymentKeys {
+    scan_key: SecretKey,
+    spend_pubkey: PublicKey,
+    network: Network,
+    labels: Vec<u32>,
+}
+
+impl SilentPaymentKeys {
+    pub fn new(scan_key: SecretKey, spend_pubkey: PublicKey, network: Network) -> Self {
+        Self {
+            scan_key,
+            spend_pubkey,
+            network,
+            labels: vec![],
+        }
+    }
+
+    /// Also look for the payments to the labeled addresses of `labels`,
+    /// see [SilentPaymentKeys::labeled_address]
+    pub fn with_labels(mut self, labels: impl IntoIterator<Item = u32>) -> Self {
+        self.labels = labels.into_iter().collect();
+        self
+    }
+
+    /// Return the [SilentPaymentAddress] to give to the senders
+    pub fn address(&self) -> SilentPaymentAddress {
+        SilentPaymentAddress {
+            scan_pubkey: self.scan_key.public_key(&Secp256k1::signing_only()),
+            spend_pubkey: self.spend_pubkey,
+            network: self.network,
+        }
+    }
+
+    /// Return the [SilentPaymentAddress] of the label `m`. By convention, the label 0 is
+    /// reserved for the change and must not be handed out.
+    ///
+    /// # Errors
+    /// Returns an error in the negligible case where the label tweak is not a valid key
+    pub fn labeled_address(&self, m: u32) -> Result<SilentPaymentAddress> {
+        let secp = Secp256k1::new();
+        let label_pubkey = self.label_key(m)?.public_key(&secp);
+        Ok(SilentPaymentAddress {
+            spend_pubkey: self
+                .spend_pubkey
+                .combine(&label_pubkey)
+                .map_err(|e| Error::SilentPaymentError(e.to_string()))?,
+            ..self.address()
+        })
+    }
+
+    /// The tweak of the label `m`, `hash_BIP0352/Label(ser256(b_scan) || ser32(m))`
+    fn label_key(&self, m: u32) -> Result<SecretKey> {
+        let mut label_msg = self.scan_key.secret_bytes().to_vec();
+        label_msg.extend_from_slice(&m.to_be_bytes());
+        SecretKey::from_slice(&tagged_hash("BIP0352/Label", &label_msg))
+            .map_err(|_| Error::SilentPaymentError("hash is not a valid key".to_owned()))
+    }
+
+    /// Return the outputs of `tx` that pay to our [SilentPaymentAddress] or to the labeled
+    /// addresses given to [SilentPaymentKeys::with_labels].
+    ///
+    /// `prevouts` must be the [TxOut] spent by each input of `tx`, in the same order.
+    ///
+    /// # Errors
+    /// Returns an error if `prevouts` does not match the inputs of `tx`.
+    pub fn scan_transaction(
+        &self,
+        tx: &Transaction,
+        prevouts: &[TxOut],
+    ) -> Result<Vec<SilentPaymentOutput>> {
+        let Some(tweak_data) = tweak_data(tx, prevouts)? else {
+            return Ok(vec![]);
+        };
+
+        let secp = Secp256k1::new();
+        let shared_secret = tweak_data
+            .mul_tweak(&secp, &Scalar::from(self.scan_key))
+            .map_err(|e| Error::SilentPaymentError(e.to_string()))?
+            .serialize();
+        let label_pubkeys = self
+            .labels
+            .iter()
+            .map(|&m| Ok((self.label_key(m)?.public_key(&secp), m)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let txid = tx.txid();
+        let mut found: Vec<SilentPaymentOutput> = vec![];
+        // Senders number their outputs to the same scan key with k = 0, 1, ...
+        // Stop at the first k that does not match any output.
+        for k in 0u32.. {
+            let mut shared_secret_msg = shared_secret.to_vec();
+            shared_secret_msg.extend_from_slice(&k.to_be_bytes());
+            let tweak = to_scalar(tagged_hash("BIP0352/SharedSecret", &shared_secret_msg))?;
+            let expected_pubkey = self
+                .spend_pubkey
+                .add_exp_tweak(&secp, &tweak)
+                .map_err(|e| Error::SilentPaymentError(e.to_string()))?;
+            let (expected_xonly, _) = expected_pubkey.x_only_public_key();
+            let negated_expected_pubkey = expected_pubkey.negate(&secp);
+            let Some((vout, txout, label)) = tx
+                .output
+                .iter()
+                .enumerate()
+                .filter(|(vout, _)| !found.iter().any(|o| o.outpoint.vout == *vout as u32))
+                .find_map(|(vout, txout)| {
+                    if !txout.script_pubkey.is_v1_p2tr() {
+                        return None;
+                    }
+                    let output_xonly =
+                        XOnlyPublicKey::from_slice(&txout.script_pubkey.as_bytes()[2..34]).ok()?;
+                    if output_xonly == expected_xonly {
+                        return Some((vout, txout, None));
+                    }
+                    // The output may pay a labeled address: output - P_k or -output - P_k
+                    // is then the public key of the label
+                    let output_pubkey =
+                        PublicKey::from_x_only_public_key(output_xonly, Parity::Even);
+                    [output_pubkey, output_pubkey.negate(&secp)]
+                        .iter()
+                        .filter_map(|o| o.combine(&negated_expected_pubkey).ok())
+                        .find_map(|candidate| {
+                            label_pubkeys
+                                .iter()
+                                .find(|(label_pubkey, _)| *label_pubkey == candidate)
+                        })
+                        .map(|&(_, m)| (vout, txout, Some(m)))
+                })
+            else {
+                break;
+            };
+            let tweak = match label {
+                Some(m) => {
+                    let label_key = self.label_key(m)?;
+                    Scalar::from(
+                        SecretKey::from_slice(&tweak.to_be_bytes())
+                            .and_then(|t| t.add_tweak(&Scalar::from(label_key)))
+                            .map_err(|e| Error::SilentPaymentError(e.to_string()))?,
+                    )
+                }
+                None => tweak,
+            };
+            found.push(SilentPaymentOutput {
+                outpoint: OutPoint {
+                    txid,
+                    vout: vout as u32,
+                },
+                value: txout.value,
+                tweak,
+                label,
+            });
+        }
+        log::debug!(
+            "SilentPaymentKeys::scan_transaction - txid={txid} found={}",
+            found.len()
+        );
+        Ok(found)
+    }
+}
+
+/// Return the tweak data of `tx`, `input_hash·A` where `A` is the sum of the public keys of its
+/// eligible inputs. Multiplied by the private scan key, it gives the shared secret of the
+/// receiver, so an index server can compute it once per transaction for all its clients.
+///
+/// `prevouts` must be the [TxOut] spent by each input of `tx`, in the same order.
+/// Return [None] if `tx` cannot be a silent payment, e.g. if none of its inputs is eligible
+/// or if it spends an output of a segwit version greater than 1.
+///
+/// # Errors
+/// Returns an error if `prevouts` does not match the inputs of `tx`.
+pub fn tweak_data(tx: &Transaction, prevouts: &[TxOut]) -> Result<Option<PublicKey>> {
+    if prevouts.len() != tx.input.len() {
+        return Err(Error::SilentPaymentError(format!(
+            "{} prevouts provided for {} inputs",
+            prevouts.len(),
+            tx.input.len()
+        )));
+    }
+    // Transactions spending outputs of a future segwit version must be skipped
+    if prevouts.iter().any(|prevout| {
+        prevout.script_pubkey.is_witness_program()
+            && prevout
+                .script_pubkey
+                .witness_version()
+                .is_some_and(|version| version.to_num() > 1)
+    }) {
+        return Ok(None);
+    }
+    let input_pubkeys = tx
+        .input
+        .iter()
+        .zip(prevouts)
+        .filter_map(|(txin, prevout)| input_public_key(txin, prevout))
+        .collect::<Vec<_>>();
+    if input_pubkeys.is_empty() {
+        return Ok(None);
+    }
+    let Ok(input_pubkeys_sum) = PublicKey::combine_keys(&input_pubkeys.iter().collect::<Vec<_>>())
+    else {
+        // The input keys cancel each other, the transaction cannot be a silent payment
+        return Ok(None);
+    };
+
+    let smallest_outpoint = tx
+        .input
+        .iter()
+        .map(|txin| {
+            let mut ser = txin.previous_output.txid.to_byte_array().to_vec();
+            ser.extend_from_slice(&txin.previous_output.vout.to_le_bytes());
+            ser
+        })
+        .min()
+        .expect("there is at least one input");
+    let mut input_hash_msg = smallest_outpoint;
+    input_hash_msg.extend_from_slice(&input_pubkeys_sum.serialize());
+    let input_hash = to_scalar(tagged_hash("BIP0352/Inputs", &input_hash_msg))?;
+
+    Ok(Some(
+        input_pubkeys_sum
+            .mul_tweak(&Secp256k1::verification_only(), &input_hash)
+            .map_err(|e| Error::SilentPaymentError(e.to_string()))?,
+    ))
+}
+
+/// Extract the public key of an input eligible for Silent Payments, as specified by BIP-352.
+/// Return [None] if the input is not eligible.
+fn input_public_key(txin: &TxIn, prevout: &TxOut) -> Option<PublicKey> {
+    let spk = &prevout.script_pubkey;
+    if spk.is_v1_p2tr() {
+        let mut witness = txin.witness.iter().collect::<Vec<_>>();
+        // Remove the annex, if any
+        if witness.len() > 1 && witness.last().is_some_and(|e| e.first() == Some(&0x50)) {
+            witness.pop();
+        }
+        // Script-path spend: ignore the input if the internal key is the NUMS point
+        if witness.len() > 1 {
+            let control_block = witness.last()?;
+            if control_block.get(1..33)? == NUMS_H {
+                return None;
+            }
+        }
+        let xonly = XOnlyPublicKey::from_slice(&spk.as_bytes()[2..34]).ok()?;
+        Some(PublicKey::from_x_only_public_key(xonly, Parity::Even))
+    } else if spk.is_v0_p2wpkh() {
+        compressed_public_key(txin.witness.last()?)
+    } else if spk.is_p2sh() {
+        // Only P2SH-P2WPKH inputs are eligible
+        let redeem_script = match txin.script_sig.instructions().last()?.ok()? {
+            Instruction::PushBytes(bytes) => bytes.as_bytes().to_vec(),
+            Instruction::Op(_) => return None,
+        };
+        let is_p2wpkh = redeem_script.len() == 22 && redeem_script[0] == 0x00;
+        if is_p2wpkh {
+            compressed_public_key(txin.witness.last()?)
+        } else {
+            None
+        }
+    } else if spk.is_p2pkh() {
+        match txin.script_sig.instructions().last()?.ok()? {
+            Instruction::PushBytes(bytes) => compressed_public_key(bytes.as_bytes()),
+            Instruction::Op(_) => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn compressed_public_key(bytes: &[u8]) -> Option<PublicKey> {
+    if bytes.len() == 33 {
+        PublicKey::from_slice(bytes).ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use btc_heritage::bitcoin::{absolute::LockTime, ScriptBuf, Sequence, Txid, Witness};
+    use core::str::FromStr;
+
+    use super::*;
+
+    fn secret_key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    fn p2tr_script(xonly: XOnlyPublicKey) -> ScriptBuf {
+        let mut bytes = vec![0x51, 0x20];
+        bytes.extend_from_slice(&xonly.serialize());
+        ScriptBuf::from(bytes)
+    }
+
+    /// Sender side of BIP-352: the sum of the private keys of the inputs, the keys of the
+    /// Taproot inputs being negated if their public key has an odd Y
+    fn sender_secret(inputs: &[(SecretKey, bool)]) -> SecretKey {
+        let secp = Secp256k1::new();
+        let mut keys = inputs.iter().map(|(key, is_taproot)| {
+            let (_, parity) = key.public_key(&secp).x_only_public_key();
+            if *is_taproot && parity == Parity::Odd {
+                key.negate()
+            } else {
+                *key
+            }
+        });
+        let first = keys.next().unwrap();
+        keys.fold(first, |sum, key| sum.add_tweak(&Scalar::from(key)).unwrap())
+    }
+
+    /// Sender side of BIP-352: the input hash of the transaction
+    fn sender_input_hash(sender_secret: &SecretKey, smallest_outpoint: &OutPoint) -> Scalar {
+        let secp = Secp256k1::new();
+        let mut input_hash_msg = smallest_outpoint.txid.to_byte_array().to_vec();
+        input_hash_msg.extend_from_slice(&smallest_outpoint.vout.to_le_bytes());
+        input_hash_msg.extend_from_slice(&sender_secret.public_key(&secp).serialize());
+        to_scalar(tagged_hash("BIP0352/Inputs", &input_hash_msg)).unwrap()
+    }
+
+    /// Sender side of BIP-352: the k-th output paying `address`
+    fn sender_output(
+        sender_secret: &SecretKey,
+        smallest_outpoint: &OutPoint,
+        address: &SilentPaymentAddress,
+        k: u32,
+    ) -> XOnlyPublicKey {
+        let secp = Secp256k1::new();
+        let input_hash = sender_input_hash(sender_secret, smallest_outpoint);
+        let shared_secret = address
+            .scan_pubkey
+            .mul_tweak(&secp, &input_hash)
+            .unwrap()
+            .mul_tweak(&secp, &Scalar::from(*sender_secret))
+            .unwrap()
+            .serialize();
+        let mut shared_secret_msg = shared_secret.to_vec();
+        shared_secret_msg.extend_from_slice(&k.to_be_bytes());
+        let tweak = to_scalar(tagged_hash("BIP0352/SharedSecret", &shared_secret_msg)).unwrap();
+        address
+            .spend_pubkey
+            .add_exp_tweak(&secp, &tweak)
+            .unwrap()
+            .x_only_public_key()
+            .0
+    }
+
+    fn taproot_input(outpoint: OutPoint) -> TxIn {
+        TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::from_slice(&[[0u8; 64].to_vec()]),
+        }
+    }
+
+    fn test_outpoint(vout: u32) -> OutPoint {
+        OutPoint {
+            txid: Txid::from_str(
+                "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+            )
+            .unwrap(),
+            vout,
+        }
+    }
+
+    fn test_keys() -> SilentPaymentKeys {
+        let secp = Secp256k1::new();
+        SilentPaymentKeys::new(
+            secret_key(1),
+            secret_key(2).public_key(&secp),
+            Network::Regtest,
+        )
+    }
+
+    #[test]
+    fn address_encoding() {
+        let keys = test_keys();
+        let address = keys.address().to_string();
+        assert!(address.starts_with("sprt1q"));
+        // hrp + separator + version + 66 bytes in base32 + checksum
+        assert_eq!(address.len(), 4 + 1 + 1 + 106 + 6);
+
+        let mainnet = SilentPaymentAddress {
+            network: Network::Bitcoin,
+            ..keys.address()
+        }
+        .to_string();
+        assert!(mainnet.starts_with("sp1q"));
+        assert_eq!(mainnet.len(), 116);
+    }
+
+    #[test]
+    fn labeled_address() {
+        let keys = test_keys();
+        let address = keys.address();
+        let labeled_address = keys.labeled_address(1).unwrap();
+        assert_eq!(labeled_address.scan_pubkey, address.scan_pubkey);
+        assert_ne!(labeled_address.spend_pubkey, address.spend_pubkey);
+        assert_ne!(labeled_address, keys.labeled_address(2).unwrap());
+        assert!(labeled_address.to_string().starts_with("sprt1q"));
+    }
+
+    #[test]
+    fn tweak_data() {
+        let secp = Secp256k1::new();
+        let taproot_key = secret_key(3);
+        let segwit_key = secret_key(6);
+        let segwit_pubkey = btc_heritage::bitcoin::PublicKey::new(segwit_key.public_key(&secp));
+        let prevouts = vec![
+            TxOut {
+                value: 100_000,
+                script_pubkey: ScriptBuf::new_v0_p2wpkh(&segwit_pubkey.wpubkey_hash().unwrap()),
+            },
+            TxOut {
+                value: 100_000,
+                script_pubkey: p2tr_script(taproot_key.public_key(&secp).x_only_public_key().0),
+            },
+            // P2WSH inputs are not eligible
+            TxOut {
+                value: 100_000,
+                script_pubkey: ScriptBuf::from(
+                    [[0x00, 0x20].to_vec(), [7u8; 32].to_vec()].concat(),
+                ),
+            },
+        ];
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![
+                TxIn {
+                    previous_output: test_outpoint(2),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_slice(&[[0u8; 71].to_vec(), segwit_pubkey.to_bytes()]),
+                },
+                // The smallest outpoint is not the one of the first input
+                taproot_input(test_outpoint(1)),
+                TxIn {
+                    previous_output: test_outpoint(0),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_slice(&[[0u8; 71].to_vec(), [0u8; 33].to_vec()]),
+                },
+            ],
+            output: vec![TxOut {
+                value: 10_000,
+                script_pubkey: p2tr_script(secret_key(4).public_key(&secp).x_only_public_key().0),
+            }],
+        };
+
+        // input_hash·A = (input_hash·a)·G
+        let secret = sender_secret(&[(segwit_key, false), (taproot_key, true)]);
+        let input_hash = sender_input_hash(&secret, &test_outpoint(0));
+        assert_eq!(
+            super::tweak_data(&tx, &prevouts).unwrap(),
+            Some(secret.mul_tweak(&input_hash).unwrap().public_key(&secp))
+        );
+
+        // Without eligible inputs, there is no tweak data
+        assert_eq!(
+            super::tweak_data(
+                &Transaction {
+                    input: vec![tx.input[2].clone()],
+                    ..tx.clone()
+                },
+                &prevouts[2..]
+            )
+            .unwrap(),
+            None
+        );
+
+        // Spending a segwit v2 output disqualifies the whole transaction
+        let mut future_prevouts = prevouts.clone();
+        future_prevouts[2].script_pubkey =
+            ScriptBuf::from([[0x52, 0x20].to_vec(), [7u8; 32].to_vec()].concat());
+        assert_eq!(super::tweak_data(&tx, &future_prevouts).unwrap(), None);
+
+        // Prevouts must match the inputs
+        assert!(super::tweak_data(&tx, &prevouts[1..]).is_err());
+    }
+
+    #[test]
+    fn scan_transaction() {
+        let secp = Secp256k1::new();
+        let keys = test_keys();
+        let address = keys.address();
+
+        let sender_key = secret_key(3);
+        let (sender_xonly, _) = sender_key.public_key(&secp).x_only_public_key();
+        let secret = sender_secret(&[(sender_key, true)]);
+        let outpoint = test_outpoint(0);
+        let prevouts = vec![TxOut {
+            value: 100_000,
+            script_pubkey: p2tr_script(sender_xonly),
+        }];
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![taproot_input(outpoint)],
+            output: vec![
+                TxOut {
+                    value: 10_000,
+                    script_pubkey: p2tr_script(
+                        secret_key(4).public_key(&secp).x_only_public_key().0,
+                    ),
+                },
+                TxOut {
+                    value: 20_000,
+                    script_pubkey: p2tr_script(sender_output(&secret, &outpoint, &address, 1)),
+                },
+                TxOut {
+                    value: 30_000,
+                    script_pubkey: p2tr_script(sender_output(&secret, &outpoint, &address, 0)),
+                },
+            ],
+        };
+
+        let found = keys.scan_transaction(&tx, &prevouts).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].outpoint.vout, 2);
+        assert_eq!(found[0].value, 30_000);
+        assert_eq!(found[0].label, None);
+        assert_eq!(found[1].outpoint.vout, 1);
+        assert_eq!(found[1].value, 20_000);
+        assert_eq!(found[1].label, None);
+
+        // Other keys do not find anything
+        let other_keys = SilentPaymentKeys::new(
+            secret_key(5),
+            secret_key(2).public_key(&secp),
+            Network::Regtest,
+        );
+        assert!(other_keys
+            .scan_transaction(&tx, &prevouts)
+            .unwrap()
+            .is_empty());
+
+        // Prevouts must match the inputs
+        assert!(keys.scan_transaction(&tx, &[]).is_err());
+    }
+
+    #[test]
+    fn scan_transaction_with_labels() {
+        let secp = Secp256k1::new();
+        let keys = test_keys().with_labels(0..8);
+
+        let sender_key = secret_key(3);
+        let (sender_xonly, _) = sender_key.public_key(&secp).x_only_public_key();
+        let secret = sender_secret(&[(sender_key, true)]);
+        let outpoint = test_outpoint(0);
+        let prevouts = vec![TxOut {
+            value: 100_000,
+            script_pubkey: p2tr_script(sender_xonly),
+        }];
+        // The outputs to the addresses of the same scan key share the k counter:
+        // the labels 0 to 7 use k = 0 to 7 and the address without label k = 8.
+        // The outputs are in reverse order.
+        let addresses = (0..8)
+            .map(|m| (keys.labeled_address(m).unwrap(), Some(m)))
+            .chain([(keys.address(), None)])
+            .collect::<Vec<_>>();
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![taproot_input(outpoint)],
+            output: addresses
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(k, (address, _))| TxOut {
+                    value: 10_000 + k as u64,
+                    script_pubkey: p2tr_script(sender_output(
+                        &secret, &outpoint, address, k as u32,
+                    )),
+                })
+                .collect(),
+        };
+
+        let found = keys.scan_transaction(&tx, &prevouts).unwrap();
+        assert_eq!(found.len(), 9);
+        let spend_key = secret_key(2);
+        for (k, ((_, label), output)) in addresses.iter().zip(&found).enumerate() {
+            assert_eq!(output.label, *label);
+            assert_eq!(output.value, 10_000 + k as u64);
+            assert_eq!(output.outpoint.vout, 8 - k as u32);
+            // The tweak, including the one of the label, gives the key spending the output
+            let output_key = spend_key.add_tweak(&output.tweak).unwrap();
+            assert_eq!(
+                p2tr_script(output_key.public_key(&secp).x_only_public_key().0),
+                tx.output[output.outpoint.vout as usize].script_pubkey
+            );
+        }
+
+        // Without the labels, the payment to the label 0 at k = 0 stops the scan
+        assert!(test_keys()
+            .scan_transaction(&tx, &prevouts)
+            .unwrap()
+            .is_empty());
+        // Only the given labels are found
+        let found = test_keys()
+            .with_labels([0])
+            .scan_transaction(&tx, &prevouts)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].label, Some(0));
+    }
+}
@@ -1,11 +1,24 @@
+use core::ops::Range;
+
+use btc_heritage::{
+    bitcoin::Txid,
+    heritage_wallet::{InheritanceSchedule, TransactionSummary},
+    miniscript::{Descriptor, DescriptorPublicKey},
+    subwallet_config::SubwalletConfig,
+    utils::timestamp_now,
+    AccountXPub, HeirConfig, PartiallySignedTransaction,
+};
+use heritage_service_api_client::AccountXPubWithStatus;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     database::{errors::DbError, DatabaseItem},
     errors::{Error, Result},
-    key_provider::{AnyKeyProvider, KeyProvider},
+    heir_bundle::HeirBundle,
+    key_provider::{AnyKeyProvider, HeirConfigType, KeyProvider, MnemonicBackup},
     online_wallet::{AnyOnlineWallet, OnlineWallet},
-    BoundFingerprint,
+    BoundFingerprint, Database, Draft, GuardrailOverrides, LedgerPolicy, LedgerPolicyVerification,
+    Outbox, OutboxPolicy, SpendGuardrails,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,6 +28,9 @@ pub struct Wallet {
     online_wallet: AnyOnlineWallet,
     #[serde(default)]
     fingerprints_controlled: bool,
+    /// A read-only wallet, see [Wallet::new_observer]
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    observer: bool,
 }
 
 impl Wallet {
@@ -31,12 +47,57 @@ impl Wallet {
                 key_provider,
                 online_wallet,
                 fingerprints_controlled: false,
+                observer: false,
             };
             wallet.control_fingerprints()?;
             Ok(wallet)
         }
     }
 
+    /// Create a read-only [Wallet] on `online_wallet`, e.g. for an accountant or a
+    /// monitoring server. It synchronizes, reports balances and history, verifies addresses
+    /// and creates PSBTs to be signed elsewhere, but it refuses any operation requiring a
+    /// key provider, even if one is set afterward.
+    ///
+    /// # Errors
+    /// Returns [Error::MissingOnlineWallet] if `online_wallet` is [AnyOnlineWallet::None]
+    pub fn new_observer(name: String, online_wallet: AnyOnlineWallet) -> Result<Self> {
+        if online_wallet.is_none() {
+            return Err(Error::MissingOnlineWallet);
+        }
+        Ok(Self {
+            name,
+            key_provider: AnyKeyProvider::None,
+            online_wallet,
+            fingerprints_controlled: true,
+            observer: true,
+        })
+    }
+
+    /// Return `true` if the wallet is a read-only observer, see [Wallet::new_observer]
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    pub fn key_provider(&self) -> &AnyKeyProvider {
+        &self.key_provider
+    }
+    pub fn key_provider_mut(&mut self) -> &mut AnyKeyProvider {
+        &mut self.key_provider
+    }
+
+    /// Return the key provider of the wallet
+    ///
+    /// # Errors
+    /// Returns [Error::ObserverWallet] if the wallet is a read-only observer
+    fn signer(&self) -> Result<&AnyKeyProvider> {
+        if self.observer {
+            log::error!("Wallet {} is a read-only observer", self.name);
+            return Err(Error::ObserverWallet);
+        }
+        Ok(&self.key_provider)
+    }
+
     fn control_fingerprints(&mut self) -> Result<()> {
         if !self.fingerprints_controlled {
             if !self.key_provider.is_none() && !self.online_wallet.is_none() {
@@ -67,6 +128,286 @@ impl Wallet {
         }
         Ok(())
     }
+
+    fn ledger_policies(&self) -> Result<Vec<LedgerPolicy>> {
+        Ok(LedgerPolicy::from_backup(
+            self.online_wallet.backup_descriptors()?,
+        ))
+    }
+
+    /// Called after an operation that may have created a new subwallet, so that
+    /// its policy is registered on the Ledger device, if any.
+    ///
+    /// A failure is only logged because the operation itself succeeded; the registrations
+    /// can be checked later with [Wallet::verify_ledger_policies].
+    fn on_subwallets_update(&mut self) {
+        let AnyKeyProvider::Ledger(_) = &self.key_provider else {
+            return;
+        };
+        let policies = match self.ledger_policies() {
+            Ok(policies) => policies,
+            Err(e) => {
+                log::warn!("Could not retrieve the Ledger policies of the wallet: {e}");
+                return;
+            }
+        };
+        let AnyKeyProvider::Ledger(ledger_key) = &mut self.key_provider else {
+            unreachable!("checked above");
+        };
+        match ledger_key.register_missing_policies(&policies, |wallet_policy| {
+            log::info!(
+                "Registering the policy {} on the Ledger, confirm on the device",
+                wallet_policy.descriptor_template
+            )
+        }) {
+            Ok(count) => log::info!("{count} new policies registered on the Ledger"),
+            Err(e) => log::warn!("Could not register the new policies on the Ledger: {e}"),
+        }
+    }
+
+    /// Verify the policies registered on the Ledger device of the wallet against
+    /// the current subwallets of the online wallet.
+    ///
+    /// # Errors
+    /// Returns an error if the key provider of the wallet is not a Ledger
+    pub fn verify_ledger_policies(&self) -> Result<Vec<LedgerPolicyVerification>> {
+        let AnyKeyProvider::Ledger(ledger_key) = &self.key_provider else {
+            return Err(Error::Generic(
+                "The key provider of the wallet is not a Ledger".to_owned(),
+            ));
+        };
+        ledger_key.verify_registered_policies(&self.ledger_policies()?)
+    }
+
+    /// Run the [KeyProvider::self_test] of the key provider against the current subwallet
+    /// of the online wallet or, if there is none yet, against the key-path of its first
+    /// account xpub.
+    ///
+    /// # Errors
+    /// Returns an error if the self-test fails or if the online wallet has no descriptor to
+    /// test against
+    pub fn run_self_test(&self) -> Result<()> {
+        let descriptor = match self.online_wallet.backup_descriptors()?.into_iter().last() {
+            Some(subwallet_backup) => subwallet_backup.external_descriptor,
+            None => {
+                let account_xpub =
+                    self.online_wallet
+                        .list_account_xpubs()?
+                        .into_iter()
+                        .map(|axps| match axps {
+                            AccountXPubWithStatus::Used(axp)
+                            | AccountXPubWithStatus::Unused(axp) => axp,
+                        })
+                        .next()
+                        .ok_or(Error::Generic(
+                            "The online wallet has no account xpub to test against".to_owned(),
+                        ))?;
+                SubwalletConfig::create_keypath_descriptor(&account_xpub, 0)
+            }
+        };
+        self.signer()?.self_test(&descriptor)
+    }
+
+    /// Make sure the online wallet has at least `min_unused` unused account xpubs, deriving
+    /// the missing ones from the key provider after the highest account index already known.
+    ///
+    /// Returns the number of account xpubs added, so that `update_heritage_config` does not
+    /// run out of fresh accounts.
+    ///
+    /// # Errors
+    /// Returns an error if the wallet lacks a key provider or an online wallet while
+    /// account xpubs are missing
+    pub fn auto_provision(&mut self, min_unused: usize) -> Result<usize> {
+        let Some(range) =
+            account_xpubs_to_provision(&self.online_wallet.list_account_xpubs()?, min_unused)
+        else {
+            return Ok(0);
+        };
+        log::info!("Provisioning the account xpubs {range:?}");
+        let account_xpubs = self.signer()?.derive_accounts_xpubs(range)?;
+        let count = account_xpubs.len();
+        self.feed_account_xpubs(account_xpubs)?;
+        Ok(count)
+    }
+
+    /// Sign `psbt` with the key provider of the wallet after checking it against the
+    /// [SpendGuardrails] of the wallet, which are updated in `db`.
+    ///
+    /// # Errors
+    /// Returns [Error::GuardrailViolation] if `psbt` does not respect a rule that
+    /// `overrides` does not bypass, without signing it
+    pub fn sign_psbt_guarded(
+        &self,
+        db: &mut Database,
+        psbt: &mut PartiallySignedTransaction,
+        overrides: GuardrailOverrides,
+    ) -> Result<usize> {
+        let mut guardrails = SpendGuardrails::load_for::<Self>(db, self.name())?;
+        let signed = guardrails.sign_psbt(self.signer()?, psbt, overrides)?;
+        guardrails.save(db)?;
+        Ok(signed)
+    }
+
+    /// Save `psbt` as the [Draft] `draft_name` of the wallet, along with its `tx_summary` and
+    /// an optional `note`, so that it can be signed later with [Wallet::resume_draft].
+    /// If the draft already exists, the signatures of `psbt` are added to the ones it holds.
+    ///
+    /// # Errors
+    /// Returns [Error::DraftTransactionMismatch] if the existing draft is for another transaction
+    pub fn save_draft(
+        &self,
+        db: &mut Database,
+        draft_name: &str,
+        psbt: PartiallySignedTransaction,
+        tx_summary: TransactionSummary,
+        note: Option<String>,
+    ) -> Result<Draft> {
+        Draft::save_for::<Self>(db, self.name(), draft_name, psbt, tx_summary, note)
+    }
+
+    /// List the [Draft]s of the wallet, ordered by name
+    pub fn list_drafts(&self, db: &Database) -> Result<Vec<Draft>> {
+        Ok(Draft::list_for::<Self>(db, self.name())?)
+    }
+
+    /// Load the [Draft] `draft_name` of the wallet to continue its signature
+    pub fn resume_draft(&self, db: &Database, draft_name: &str) -> Result<Draft> {
+        Ok(Draft::load_for::<Self>(db, self.name(), draft_name)?)
+    }
+
+    /// Delete the [Draft] `draft_name` of the wallet, typically once its transaction
+    /// is broadcasted, returning `true` if it existed
+    pub fn delete_draft(&self, db: &mut Database, draft_name: &str) -> Result<bool> {
+        Ok(Draft::delete_for::<Self>(db, self.name(), draft_name)?)
+    }
+
+    /// Queue the signed `psbt` in the [Outbox] of the wallet, to be broadcasted by
+    /// [Wallet::process_outbox] until it is confirmed. Return the [Txid] of the transaction.
+    pub fn queue_for_broadcast(
+        &self,
+        db: &mut Database,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<Txid> {
+        let mut outbox = Outbox::load_for::<Self>(db, self.name())?;
+        let txid = outbox.queue(psbt, timestamp_now());
+        outbox.save(db)?;
+        Ok(txid)
+    }
+
+    /// Return the [Outbox] of the wallet, giving the status of its queued transactions
+    pub fn outbox(&self, db: &Database) -> Result<Outbox> {
+        Ok(Outbox::load_for::<Self>(db, self.name())?)
+    }
+
+    /// Broadcast the transactions of the [Outbox] of the wallet that are due at `now`
+    /// according to the `policy` and update their confirmation status. The wallet should
+    /// be synchronized beforehand for the confirmations to be up to date.
+    ///
+    /// The transactions reaching [OutboxPolicy::target_confirmations] are removed from
+    /// the outbox. Return the updated [Outbox].
+    ///
+    /// # Errors
+    /// Returns an error if the transactions of the wallet cannot be listed or if the outbox
+    /// cannot be saved. A failed broadcast is not an error, it is recorded in its [OutboxEntry](crate::OutboxEntry).
+    pub fn process_outbox(
+        &self,
+        db: &mut Database,
+        now: u64,
+        policy: &OutboxPolicy,
+    ) -> Result<Outbox> {
+        let mut outbox = Outbox::load_for::<Self>(db, self.name())?;
+        if outbox.entries().is_empty() {
+            return Ok(outbox);
+        }
+        let history = self.online_wallet.list_transactions()?;
+        // The Heritage service does not expose the tip of the blockchain, the most recent
+        // confirmation of the wallet is a lower bound of it
+        let tip_height = match &self.online_wallet {
+            AnyOnlineWallet::Local(lw) => lw
+                .heritage_wallet()
+                .get_sync_time()?
+                .map(|block_time| block_time.height),
+            _ => None,
+        }
+        .into_iter()
+        .chain(
+            history
+                .iter()
+                .filter_map(|ts| ts.confirmation_time.as_ref().map(|bt| bt.height)),
+        )
+        .max()
+        .unwrap_or(0);
+        outbox.process(self, &history, tip_height, now, policy);
+        let settled = outbox.prune_settled(policy.target_confirmations);
+        if settled > 0 {
+            log::info!(
+                "Wallet::process_outbox - {settled} settled transaction(s) removed from the outbox of {}",
+                self.name
+            );
+        }
+        outbox.save(db)?;
+        Ok(outbox)
+    }
+
+    /// Export an [HeirBundle] for the Heir with `heir_config`: the descriptors backup of
+    /// the online wallet encrypted with `passphrase`, the `instructions` of the owner and
+    /// the current maturity schedule of the Heir.
+    ///
+    /// The Heir can later import it with [crate::HeirWallet::import_heir_bundle], without
+    /// any dependency on the Heritage service.
+    ///
+    /// # Errors
+    /// Returns [Error::HeirNotInBackup] if the Heir is not part of any Heritage configuration
+    /// of the wallet
+    pub fn export_heir_bundle(
+        &self,
+        heir_config: &HeirConfig,
+        passphrase: &str,
+        instructions: Option<String>,
+    ) -> Result<HeirBundle> {
+        if !self
+            .online_wallet
+            .list_heritage_configs()?
+            .iter()
+            .any(|hc| hc.iter_heir_configs().any(|hc| hc == heir_config))
+        {
+            return Err(Error::HeirNotInBackup(heir_config.fingerprint()));
+        }
+        let schedule =
+            InheritanceSchedule::from_heritage_utxos(self.online_wallet.list_heritage_utxos()?);
+        HeirBundle::new(
+            heir_config,
+            &self.online_wallet.backup_descriptors()?,
+            passphrase,
+            &schedule,
+            instructions,
+        )
+    }
+}
+
+/// Return the range of account indexes to derive so that `account_xpubs` contains at
+/// least `min_unused` unused account xpubs, or [None] if there are already enough
+fn account_xpubs_to_provision(
+    account_xpubs: &[AccountXPubWithStatus],
+    min_unused: usize,
+) -> Option<Range<u32>> {
+    let unused = account_xpubs
+        .iter()
+        .filter(|axps| matches!(axps, AccountXPubWithStatus::Unused(_)))
+        .count();
+    if unused >= min_unused {
+        return None;
+    }
+    let start = account_xpubs
+        .iter()
+        .map(|axps| match axps {
+            AccountXPubWithStatus::Used(axp) | AccountXPubWithStatus::Unused(axp) => {
+                axp.descriptor_id() + 1
+            }
+        })
+        .max()
+        .unwrap_or(0);
+    Some(start..start + (min_unused - unused) as u32)
 }
 
 crate::database::dbitem::impl_db_item!(
@@ -74,7 +415,7 @@ crate::database::dbitem::impl_db_item!(
     "wallet#",
     "default_wallet_name"
     fn load(db: &crate::Database, name: &str) -> crate::database::errors::Result<Self> {
-        let key = Self::name_to_key(name);
+        let key = Self::db_key(db, name);
         let mut wallet = db
             .get_item::<Self>(&key)?
             .ok_or(crate::database::errors::DbError::KeyDoesNotExists(key))?;
@@ -85,11 +426,40 @@ crate::database::dbitem::impl_db_item!(
         if let AnyOnlineWallet::Local(lw) = &self.online_wallet{
             lw.delete(db)?;
         }
-        db.delete_item::<Self>(&Self::name_to_key(self.name()))?;
+        crate::SpendGuardrails::delete_for::<Self>(db, self.name())?;
+        crate::Draft::delete_all_for::<Self>(db, self.name())?;
+        crate::Outbox::delete_for::<Self>(db, self.name())?;
+        db.delete_item::<Self>(&Self::db_key(db, self.name()))?;
+        Ok(())
+    }
+    fn db_rename(&mut self, db: &mut crate::Database, new_name: String) -> crate::database::errors::Result<()> {
+        let old_name = self.name().to_owned();
+        self.rename(new_name);
+        db.put_item(&Self::db_key(db, self.name()), self)?;
+        db.delete_item::<Self>(&Self::db_key(db, &old_name))?;
+        crate::SpendGuardrails::rename_for::<Self>(db, &old_name, self.name())?;
+        crate::Draft::rename_for::<Self>(db, &old_name, self.name())?;
+        crate::Outbox::rename_for::<Self>(db, &old_name, self.name())?;
         Ok(())
     }
 );
-crate::key_provider::impl_key_provider!(Wallet);
+impl KeyProvider for Wallet {
+    fn sign_psbt(&self, psbt: &mut PartiallySignedTransaction) -> Result<usize> {
+        self.signer()?.sign_psbt(psbt)
+    }
+    fn derive_accounts_xpubs(&self, range: Range<u32>) -> Result<Vec<AccountXPub>> {
+        self.signer()?.derive_accounts_xpubs(range)
+    }
+    fn derive_heir_config(&self, heir_config_type: HeirConfigType) -> Result<HeirConfig> {
+        self.signer()?.derive_heir_config(heir_config_type)
+    }
+    fn backup_mnemonic(&self) -> Result<MnemonicBackup> {
+        self.signer()?.backup_mnemonic()
+    }
+    fn self_test(&self, descriptor: &Descriptor<DescriptorPublicKey>) -> Result<()> {
+        self.signer()?.self_test(descriptor)
+    }
+}
 crate::online_wallet::impl_online_wallet!(Wallet);
 
 impl BoundFingerprint for Wallet {
@@ -103,3 +473,71 @@ impl BoundFingerprint for Wallet {
         unreachable!("Having both part at None is not allowed")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{online_wallet::LocalHeritageWallet, LocalKey};
+    use btc_heritage::bitcoin::Network;
+
+    fn account_xpub(index: u32) -> AccountXPub {
+        AccountXPub::try_from(
+            format!(
+                "[9c7088e3/86'/1'/{index}']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv/*"
+            )
+            .as_str(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn account_xpubs_provisioning() {
+        assert_eq!(account_xpubs_to_provision(&[], 0), None);
+        assert_eq!(account_xpubs_to_provision(&[], 3), Some(0..3));
+
+        let account_xpubs = vec![
+            AccountXPubWithStatus::Used(account_xpub(0)),
+            AccountXPubWithStatus::Used(account_xpub(1)),
+            AccountXPubWithStatus::Unused(account_xpub(4)),
+        ];
+        assert_eq!(account_xpubs_to_provision(&account_xpubs, 1), None);
+        // Derive after the highest known index, even if indexes are missing
+        assert_eq!(account_xpubs_to_provision(&account_xpubs, 3), Some(5..7));
+    }
+
+    #[test]
+    fn observer() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let db = Database::new(tmpdir.path(), Network::Regtest).unwrap();
+        let online_wallet = AnyOnlineWallet::Local(
+            LocalHeritageWallet::create(&db, None, 6, Network::Regtest).unwrap(),
+        );
+
+        assert!(matches!(
+            Wallet::new_observer("observer".to_owned(), AnyOnlineWallet::None),
+            Err(Error::MissingOnlineWallet)
+        ));
+        let mut wallet = Wallet::new_observer("observer".to_owned(), online_wallet).unwrap();
+        assert!(wallet.is_observer());
+
+        // Even with a key provider, nothing can be signed nor derived
+        *wallet.key_provider_mut() =
+            AnyKeyProvider::LocalKey(LocalKey::generate(12, None, Network::Regtest));
+        assert!(matches!(
+            wallet.derive_accounts_xpubs(0..1),
+            Err(Error::ObserverWallet)
+        ));
+        assert!(matches!(
+            wallet.derive_heir_config(HeirConfigType::HeirXPubkey),
+            Err(Error::ObserverWallet)
+        ));
+        assert!(matches!(
+            wallet.backup_mnemonic(),
+            Err(Error::ObserverWallet)
+        ));
+        assert!(matches!(
+            wallet.auto_provision(1),
+            Err(Error::ObserverWallet)
+        ));
+    }
+}
@@ -12,7 +12,6 @@ use crate::{
         descriptor::{DescriptorXKey, Wildcard},
         DescriptorPublicKey,
     },
-    utils,
 };
 
 pub type AccountXPubId = u32;
@@ -63,6 +62,16 @@ impl AccountXPub {
         &self.0
     }
 
+    /// Return `true` if the [AccountXPub] can be used in a wallet of the `network`,
+    /// i.e. it is a mainnet xpub and `network` is [Network::Bitcoin] or it is a
+    /// testnet tpub and `network` is one of the test networks
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        let DescriptorPublicKey::XPub(DescriptorXKey { xkey, .. }) = &self.0 else {
+            panic!("Invalid key variant, should never happen as AccountXPub is checked at creation")
+        };
+        (xkey.network == Network::Bitcoin) == (network == Network::Bitcoin)
+    }
+
     pub fn child_descriptor_public_key(&self, index: u32) -> DescriptorPublicKey {
         log::debug!("AccountXPub::child_descriptor_public_key - index={index}");
         let (fingerprint, derivation_path, account_xpub_key) = match &self.0 {
@@ -106,7 +115,7 @@ impl TryFrom<DescriptorPublicKey> for AccountXPub {
 
     fn try_from(descriptor: DescriptorPublicKey) -> Result<Self, Self::Error> {
         // If the DescriptorPublicKey is not XPub, bail
-        if let DescriptorPublicKey::XPub(xpub) = &descriptor {
+        let xkey_network = if let DescriptorPublicKey::XPub(xpub) = &descriptor {
             xpub.origin
                 .as_ref()
                 .ok_or(Error::InvalidDescriptorPublicKey(
@@ -118,6 +127,7 @@ impl TryFrom<DescriptorPublicKey> for AccountXPub {
                     "Derivation after the key",
                 ));
             }
+            xpub.xkey.network
         } else {
             return Err(Error::InvalidDescriptorPublicKey(
                 "Must be a DescriptorPublicKey::XPub variant",
@@ -125,7 +135,8 @@ impl TryFrom<DescriptorPublicKey> for AccountXPub {
         };
 
         // If the derivation path is not m/86'/[0,1]'/i'/*, bail
-        let cointype_path_segment = match utils::bitcoin_network_from_env() {
+        // The coin type must be consistent with the network of the xpub itself
+        let cointype_path_segment = match xkey_network {
             Network::Bitcoin => 0,
             _ => 1,
         };
@@ -211,4 +222,13 @@ mod tests {
             assert_eq!(get_test_account_xpub(i).descriptor_id(), i);
         }
     }
+
+    #[test]
+    fn account_xpub_network() {
+        let account_xpub = get_test_account_xpub(0);
+        assert!(account_xpub.is_valid_for_network(Network::Testnet));
+        assert!(account_xpub.is_valid_for_network(Network::Regtest));
+        assert!(account_xpub.is_valid_for_network(Network::Signet));
+        assert!(!account_xpub.is_valid_for_network(Network::Bitcoin));
+    }
 }
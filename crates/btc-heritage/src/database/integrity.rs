@@ -0,0 +1,163 @@
+//! Cross-checks of the invariants that an [HeritageDatabase] must uphold, see
+//! [HeritageDatabase::verify_integrity] and [HeritageDatabase::repair_integrity].
+//!
+//! The invariants are:
+//! - if there is any obsolete [SubwalletConfig], there is a Current one
+//! - a [SubwalletId] is never used by two [SubwalletConfig]s
+//! - a used [AccountXPub] is not in the unused set anymore
+//! - every [HeritageUtxo](crate::heritage_wallet::HeritageUtxo) belongs to a known subwallet
+
+use core::fmt::Display;
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::{
+    account_xpub::AccountXPub,
+    bitcoin::OutPoint,
+    heritage_wallet::SubwalletConfigId,
+    subwallet_config::{SubwalletConfig, SubwalletId},
+};
+
+use super::{HeritageDatabase, Result};
+
+/// An inconsistency found in an [HeritageDatabase]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityIssue {
+    /// There are obsolete [SubwalletConfig]s but no Current one
+    MissingCurrentSubwalletConfig,
+    /// The [SubwalletId] is used by more than one [SubwalletConfig]
+    DuplicateSubwalletId(SubwalletId),
+    /// The [AccountXPub] is used by a [SubwalletConfig] but is still in the unused set
+    UsedAccountXPubNotRemoved(AccountXPub),
+    /// The UTXO belongs to none of the subwallets of the database
+    UnknownSubwalletUtxo(OutPoint),
+}
+
+impl IntegrityIssue {
+    /// Return `true` if [HeritageDatabase::repair_integrity] can fix the issue
+    pub fn is_repairable(&self) -> bool {
+        match self {
+            IntegrityIssue::MissingCurrentSubwalletConfig
+            | IntegrityIssue::DuplicateSubwalletId(_) => false,
+            IntegrityIssue::UsedAccountXPubNotRemoved(_)
+            | IntegrityIssue::UnknownSubwalletUtxo(_) => true,
+        }
+    }
+}
+
+impl Display for IntegrityIssue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            IntegrityIssue::MissingCurrentSubwalletConfig => {
+                write!(f, "There are obsolete subwallets but no current one")
+            }
+            IntegrityIssue::DuplicateSubwalletId(subwallet_id) => {
+                write!(f, "Subwallet id {subwallet_id} is used more than once")
+            }
+            IntegrityIssue::UsedAccountXPubNotRemoved(account_xpub) => write!(
+                f,
+                "Account xpub {} is used but still listed as unused",
+                account_xpub.descriptor_id()
+            ),
+            IntegrityIssue::UnknownSubwalletUtxo(outpoint) => {
+                write!(f, "UTXO {outpoint} belongs to an unknown subwallet")
+            }
+        }
+    }
+}
+
+/// The result of [HeritageDatabase::verify_integrity] or [HeritageDatabase::repair_integrity]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct IntegrityReport {
+    /// The issues found in the database
+    pub issues: Vec<IntegrityIssue>,
+    /// `true` if the repairable issues were fixed
+    pub repaired: bool,
+}
+
+impl IntegrityReport {
+    /// Return `true` if no issue was found
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+pub(super) fn verify<D: HeritageDatabase + ?Sized>(db: &D) -> Result<IntegrityReport> {
+    log::debug!("integrity::verify");
+    let mut issues = vec![];
+
+    let obsolete_subwallet_configs = db.list_obsolete_subwallet_configs()?;
+    let current_subwallet_config = db.get_subwallet_config(SubwalletConfigId::Current)?;
+    if current_subwallet_config.is_none() && !obsolete_subwallet_configs.is_empty() {
+        issues.push(IntegrityIssue::MissingCurrentSubwalletConfig);
+    }
+    let subwallet_configs = obsolete_subwallet_configs
+        .into_iter()
+        .chain(current_subwallet_config)
+        .collect::<Vec<_>>();
+
+    let mut subwallet_ids = HashSet::new();
+    let mut duplicate_subwallet_ids = HashSet::new();
+    for subwallet_id in subwallet_configs.iter().map(SubwalletConfig::subwallet_id) {
+        if !subwallet_ids.insert(subwallet_id) && duplicate_subwallet_ids.insert(subwallet_id) {
+            issues.push(IntegrityIssue::DuplicateSubwalletId(subwallet_id));
+        }
+    }
+
+    let used_account_xpub_ids = db
+        .list_used_account_xpubs()?
+        .iter()
+        .map(AccountXPub::descriptor_id)
+        .collect::<HashSet<_>>();
+    issues.extend(
+        db.list_unused_account_xpubs()?
+            .into_iter()
+            .filter(|axp| used_account_xpub_ids.contains(&axp.descriptor_id()))
+            .map(IntegrityIssue::UsedAccountXPubNotRemoved),
+    );
+
+    issues.extend(
+        db.list_utxos()?
+            .into_iter()
+            .filter(|hu| {
+                !subwallet_configs
+                    .iter()
+                    .any(|swc| *swc.heritage_config() == hu.heritage_config)
+            })
+            .map(|hu| IntegrityIssue::UnknownSubwalletUtxo(hu.outpoint)),
+    );
+
+    let res = IntegrityReport {
+        issues,
+        repaired: false,
+    };
+    log::debug!("integrity::verify - res={res:?}");
+    Ok(res)
+}
+
+pub(super) fn repair<D: HeritageDatabase + ?Sized>(db: &mut D) -> Result<IntegrityReport> {
+    log::debug!("integrity::repair");
+    let mut report = verify(db)?;
+    let mut unknown_utxos = vec![];
+    for issue in report.issues.iter() {
+        match issue {
+            IntegrityIssue::UsedAccountXPubNotRemoved(account_xpub) => {
+                log::warn!("integrity::repair - Removing {account_xpub} from the unused set");
+                db.delete_unused_account_xpub(account_xpub)?;
+            }
+            IntegrityIssue::UnknownSubwalletUtxo(outpoint) => unknown_utxos.push(*outpoint),
+            IntegrityIssue::MissingCurrentSubwalletConfig
+            | IntegrityIssue::DuplicateSubwalletId(_) => {
+                log::error!("integrity::repair - Cannot repair: {issue}");
+            }
+        }
+    }
+    if !unknown_utxos.is_empty() {
+        log::warn!("integrity::repair - Removing the UTXOs {unknown_utxos:?}");
+        db.delete_utxos(&unknown_utxos)?;
+    }
+    report.repaired = true;
+    Ok(report)
+}
@@ -9,14 +9,15 @@ use bdk::BlockTime;
 
 use crate::{
     account_xpub::AccountXPubId,
-    bitcoin::{FeeRate, OutPoint, Txid},
+    bitcoin::{FeeRate, Network, OutPoint, Txid},
     database::{
         paginate::{ContinuationToken, Paginated},
         HeritageDatabase, TransacHeritageDatabase, TransacHeritageOperation,
     },
     errors::DatabaseError,
     heritage_wallet::{
-        BlockInclusionObjective, HeritageUtxo, HeritageWalletBalance, SubwalletConfigId,
+        heir_rotation::HeirRotation, journal::PendingOperation, BlockInclusionObjective, GapLimit,
+        HeirSpendingQuotas, HeritageUtxo, HeritageWalletBalance, LabelRef, SubwalletConfigId,
         TransactionSummary,
     },
     subwallet_config::SubwalletConfig,
@@ -406,6 +407,125 @@ impl HeritageDatabase for HeritageMemoryDatabase {
         })
     }
 
+    fn set_utxo_label(&mut self, outpoint: &OutPoint, label: &str) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::set_utxo_label - outpoint={outpoint} label={label}");
+        let key = HeritageMonoItemKeyMapper::UtxoLabel(Some(outpoint)).key();
+        self.table
+            .write()
+            .unwrap()
+            .insert(key, Box::new((*outpoint, label.to_owned())));
+        Ok(())
+    }
+
+    fn delete_utxo_label(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::delete_utxo_label - outpoint={outpoint}");
+        let key = HeritageMonoItemKeyMapper::UtxoLabel(Some(outpoint)).key();
+        self.table.write().unwrap().remove(&key);
+        Ok(())
+    }
+
+    fn get_utxo_label(&self, outpoint: &OutPoint) -> Result<Option<String>> {
+        log::debug!("HeritageMemoryDatabase::get_utxo_label - outpoint={outpoint}");
+        let key = HeritageMonoItemKeyMapper::UtxoLabel(Some(outpoint)).key();
+        Ok(self.table.read().unwrap().get(&key).map(|b| {
+            b.downcast_ref::<(OutPoint, String)>()
+                .expect("this is an (OutPoint, String)")
+                .1
+                .clone()
+        }))
+    }
+
+    fn list_utxo_labels(&self) -> Result<Vec<(OutPoint, String)>> {
+        log::debug!("HeritageMemoryDatabase::list_utxo_labels");
+        let key = HeritageMonoItemKeyMapper::UtxoLabel(None).key();
+        let lower_bound = Bound::Included(key.clone() + "0");
+        let upper_bound = Bound::Excluded(key + "{");
+        Ok(self
+            .table
+            .read()
+            .unwrap()
+            .range((lower_bound, upper_bound))
+            .map(|(_, b)| {
+                b.downcast_ref::<(OutPoint, String)>()
+                    .expect("this is an (OutPoint, String)")
+                    .clone()
+            })
+            .collect())
+    }
+
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::set_label - label_ref={label_ref} label={label}");
+        let key = HeritageMonoItemKeyMapper::Label(Some(label_ref)).key();
+        self.table
+            .write()
+            .unwrap()
+            .insert(key, Box::new((label_ref.clone(), label.to_owned())));
+        Ok(())
+    }
+
+    fn delete_label(&mut self, label_ref: &LabelRef) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::delete_label - label_ref={label_ref}");
+        let key = HeritageMonoItemKeyMapper::Label(Some(label_ref)).key();
+        self.table.write().unwrap().remove(&key);
+        Ok(())
+    }
+
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>> {
+        log::debug!("HeritageMemoryDatabase::get_label - label_ref={label_ref}");
+        let key = HeritageMonoItemKeyMapper::Label(Some(label_ref)).key();
+        Ok(self.table.read().unwrap().get(&key).map(|b| {
+            b.downcast_ref::<(LabelRef, String)>()
+                .expect("this is a (LabelRef, String)")
+                .1
+                .clone()
+        }))
+    }
+
+    fn list_labels(&self) -> Result<Vec<(LabelRef, String)>> {
+        log::debug!("HeritageMemoryDatabase::list_labels");
+        let key = HeritageMonoItemKeyMapper::Label(None).key();
+        let lower_bound = Bound::Included(key.clone() + "0");
+        let upper_bound = Bound::Excluded(key + "{");
+        Ok(self
+            .table
+            .read()
+            .unwrap()
+            .range((lower_bound, upper_bound))
+            .map(|(_, b)| {
+                b.downcast_ref::<(LabelRef, String)>()
+                    .expect("this is a (LabelRef, String)")
+                    .clone()
+            })
+            .collect())
+    }
+    fn freeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::freeze_utxo - outpoint={outpoint}");
+        let key = HeritageMonoItemKeyMapper::FrozenUtxo(Some(outpoint)).key();
+        self.table.write().unwrap().insert(key, Box::new(*outpoint));
+        Ok(())
+    }
+
+    fn unfreeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::unfreeze_utxo - outpoint={outpoint}");
+        let key = HeritageMonoItemKeyMapper::FrozenUtxo(Some(outpoint)).key();
+        self.table.write().unwrap().remove(&key);
+        Ok(())
+    }
+
+    fn list_frozen_utxos(&self) -> Result<Vec<OutPoint>> {
+        log::debug!("HeritageMemoryDatabase::list_frozen_utxos");
+        let key = HeritageMonoItemKeyMapper::FrozenUtxo(None).key();
+        let lower_bound = Bound::Included(key.clone() + "0");
+        let upper_bound = Bound::Excluded(key + "{");
+        Ok(self
+            .table
+            .read()
+            .unwrap()
+            .range((lower_bound, upper_bound))
+            .map(|(_, b)| *b.downcast_ref::<OutPoint>().expect("this is an OutPoint"))
+            .collect())
+    }
+
     fn add_transaction_summaries(
         &mut self,
         transaction_summaries: &Vec<TransactionSummary>,
@@ -566,4 +686,119 @@ impl HeritageDatabase for HeritageMemoryDatabase {
             .insert(key, Box::new(new_objective));
         Ok(())
     }
+
+    fn get_gap_limit(&self) -> Result<Option<GapLimit>> {
+        log::debug!("HeritageMemoryDatabase::get_gap_limit");
+        let key = HeritageMonoItemKeyMapper::GapLimit.key();
+        Ok(self.table.read().unwrap().get(&key).map(|b| {
+            b.downcast_ref::<GapLimit>()
+                .expect("this is a GapLimit")
+                .clone()
+        }))
+    }
+
+    fn set_gap_limit(&mut self, new_gap_limit: GapLimit) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::set_gap_limit - new_gap_limit={new_gap_limit:?}");
+        let key = HeritageMonoItemKeyMapper::GapLimit.key();
+        self.table
+            .write()
+            .unwrap()
+            .insert(key, Box::new(new_gap_limit));
+        Ok(())
+    }
+
+    fn get_heir_spending_quotas(&self) -> Result<Option<HeirSpendingQuotas>> {
+        log::debug!("HeritageMemoryDatabase::get_heir_spending_quotas");
+        let key = HeritageMonoItemKeyMapper::HeirSpendingQuotas.key();
+        Ok(self.table.read().unwrap().get(&key).map(|b| {
+            b.downcast_ref::<HeirSpendingQuotas>()
+                .expect("this is an HeirSpendingQuotas")
+                .clone()
+        }))
+    }
+
+    fn set_heir_spending_quotas(&mut self, new_quotas: &HeirSpendingQuotas) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::set_heir_spending_quotas - new_quotas={new_quotas:?}");
+        let key = HeritageMonoItemKeyMapper::HeirSpendingQuotas.key();
+        self.table
+            .write()
+            .unwrap()
+            .insert(key, Box::new(new_quotas.clone()));
+        Ok(())
+    }
+
+    fn get_network(&self) -> Result<Option<Network>> {
+        log::debug!("HeritageMemoryDatabase::get_network");
+        let key = HeritageMonoItemKeyMapper::Network.key();
+        Ok(self
+            .table
+            .read()
+            .unwrap()
+            .get(&key)
+            .map(|b| *b.downcast_ref::<Network>().expect("this is a Network")))
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::set_network - network={network:?}");
+        let key = HeritageMonoItemKeyMapper::Network.key();
+        self.table.write().unwrap().insert(key, Box::new(network));
+        Ok(())
+    }
+
+    fn get_pending_operation(&self) -> Result<Option<PendingOperation>> {
+        log::debug!("HeritageMemoryDatabase::get_pending_operation");
+        let key = HeritageMonoItemKeyMapper::PendingOperation.key();
+        Ok(self.table.read().unwrap().get(&key).map(|b| {
+            b.downcast_ref::<PendingOperation>()
+                .expect("this is a PendingOperation")
+                .clone()
+        }))
+    }
+
+    fn set_pending_operation(&mut self, pending_operation: &PendingOperation) -> Result<()> {
+        log::debug!(
+            "HeritageMemoryDatabase::set_pending_operation - pending_operation={pending_operation:?}"
+        );
+        let key = HeritageMonoItemKeyMapper::PendingOperation.key();
+        self.table
+            .write()
+            .unwrap()
+            .insert(key, Box::new(pending_operation.clone()));
+        Ok(())
+    }
+
+    fn delete_pending_operation(&mut self) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::delete_pending_operation");
+        let key = HeritageMonoItemKeyMapper::PendingOperation.key();
+        self.table.write().unwrap().remove(&key);
+        Ok(())
+    }
+
+    fn add_heir_rotation(&mut self, heir_rotation: &HeirRotation) -> Result<()> {
+        log::debug!("HeritageMemoryDatabase::add_heir_rotation - heir_rotation={heir_rotation:?}");
+        let key = HeritageMonoItemKeyMapper::HeirRotation(Some(heir_rotation)).key();
+        self.table
+            .write()
+            .unwrap()
+            .insert(key, Box::new(heir_rotation.clone()));
+        Ok(())
+    }
+
+    fn list_heir_rotations(&self) -> Result<Vec<HeirRotation>> {
+        log::debug!("HeritageMemoryDatabase::list_heir_rotations");
+        let key = HeritageMonoItemKeyMapper::HeirRotation(None).key();
+        let lower_bound = Bound::Included(key.clone() + "0");
+        let upper_bound = Bound::Excluded(key + "{");
+        Ok(self
+            .table
+            .read()
+            .unwrap()
+            .range((lower_bound, upper_bound))
+            .map(|(_, b)| {
+                b.downcast_ref::<HeirRotation>()
+                    .expect("this is an HeirRotation")
+                    .clone()
+            })
+            .collect())
+    }
 }
@@ -1,5 +1,5 @@
 use ::bdk::BlockTime;
-use core::{any::Any, cell::RefCell};
+use core::any::Any;
 use std::{
     collections::{BTreeMap, HashMap},
     sync::RwLock,
@@ -8,7 +8,7 @@ use std::{
 use crate::{
     account_xpub::AccountXPubId,
     bitcoin::{OutPoint, Txid},
-    heritage_wallet::SubwalletConfigId,
+    heritage_wallet::{heir_rotation::HeirRotation, LabelRef, SubwalletConfigId},
 };
 
 use super::{PartitionableDatabase, Result, SubdatabaseId};
@@ -22,10 +22,18 @@ enum HeritageMonoItemKeyMapper<'a> {
     WalletConfig(Option<SubwalletConfigId>),
     UnusedAccountXPub(Option<AccountXPubId>),
     HeritageUtxo(Option<&'a OutPoint>),
+    UtxoLabel(Option<&'a OutPoint>),
+    Label(Option<&'a LabelRef>),
+    FrozenUtxo(Option<&'a OutPoint>),
     TxSummary(Option<(&'a Txid, Option<&'a BlockTime>)>),
     WalletBalance,
     FeeRate,
     BlockInclusionObjective,
+    GapLimit,
+    HeirSpendingQuotas,
+    Network,
+    PendingOperation,
+    HeirRotation(Option<&'a HeirRotation>),
 }
 
 impl HeritageMonoItemKeyMapper<'_> {
@@ -34,10 +42,18 @@ impl HeritageMonoItemKeyMapper<'_> {
             HeritageMonoItemKeyMapper::WalletConfig(_) => "wc",
             HeritageMonoItemKeyMapper::UnusedAccountXPub(_) => "uaxpubs",
             HeritageMonoItemKeyMapper::HeritageUtxo(_) => "hutxo",
+            HeritageMonoItemKeyMapper::UtxoLabel(_) => "utxolabel",
+            HeritageMonoItemKeyMapper::Label(_) => "label",
+            HeritageMonoItemKeyMapper::FrozenUtxo(_) => "frozenutxo",
             HeritageMonoItemKeyMapper::TxSummary(_) => "txsum",
             HeritageMonoItemKeyMapper::WalletBalance => "balance",
             HeritageMonoItemKeyMapper::FeeRate => "feerate",
             HeritageMonoItemKeyMapper::BlockInclusionObjective => "bio",
+            HeritageMonoItemKeyMapper::GapLimit => "gaplimit",
+            HeritageMonoItemKeyMapper::HeirSpendingQuotas => "heirquotas",
+            HeritageMonoItemKeyMapper::Network => "network",
+            HeritageMonoItemKeyMapper::PendingOperation => "pendingop",
+            HeritageMonoItemKeyMapper::HeirRotation(_) => "heirrotation",
         }
     }
 
@@ -50,7 +66,15 @@ impl HeritageMonoItemKeyMapper<'_> {
             | HeritageMonoItemKeyMapper::UnusedAccountXPub(Some(id)) => {
                 format!("{:0>10}", id)
             }
-            HeritageMonoItemKeyMapper::HeritageUtxo(Some(op)) => op.to_string(),
+            HeritageMonoItemKeyMapper::HeritageUtxo(Some(op))
+            | HeritageMonoItemKeyMapper::UtxoLabel(Some(op))
+            | HeritageMonoItemKeyMapper::FrozenUtxo(Some(op)) => op.to_string(),
+            HeritageMonoItemKeyMapper::Label(Some(label_ref)) => label_ref.to_string(),
+            HeritageMonoItemKeyMapper::HeirRotation(Some(heir_rotation)) => format!(
+                "{:0>20}#{}",
+                heir_rotation.timestamp,
+                heir_rotation.new_heir_config.fingerprint()
+            ),
             HeritageMonoItemKeyMapper::TxSummary(Some((txid, confirmation_time))) => format!(
                 "{:0>10}#{}",
                 confirmation_time
@@ -73,14 +97,14 @@ impl HeritageMonoItemKeyMapper<'_> {
 #[derive(Debug)]
 pub struct HeritageMemoryDatabase {
     table: RwLock<BTreeMap<String, Box<dyn Any + Send + Sync>>>,
-    subdatabases: RefCell<HashMap<SubdatabaseId, HeritageBdkMemoryDatabaseWrapper>>,
+    subdatabases: RwLock<HashMap<SubdatabaseId, HeritageBdkMemoryDatabaseWrapper>>,
 }
 
 impl HeritageMemoryDatabase {
     pub fn new() -> Self {
         Self {
             table: RwLock::new(BTreeMap::new()),
-            subdatabases: RefCell::new(HashMap::new()),
+            subdatabases: RwLock::new(HashMap::new()),
         }
     }
 }
@@ -91,7 +115,8 @@ impl PartitionableDatabase for HeritageMemoryDatabase {
     fn get_subdatabase(&self, subdatabase_id: SubdatabaseId) -> Result<Self::SubDatabase> {
         Ok(self
             .subdatabases
-            .borrow_mut()
+            .write()
+            .unwrap()
             .entry(subdatabase_id)
             .or_insert(HeritageBdkMemoryDatabaseWrapper::new())
             .clone())
@@ -116,12 +141,21 @@ mod tests {
     impl_heritage_test!(get_set_balance);
     impl_heritage_test!(get_set_fee_rate);
     impl_heritage_test!(get_set_block_inclusion_objective);
+    impl_heritage_test!(get_set_gap_limit);
+    impl_heritage_test!(get_set_heir_spending_quotas);
+    impl_heritage_test!(get_set_network);
+    impl_heritage_test!(heir_rotations_management);
     impl_heritage_test!(list_obsolete_subwallet_configs);
+    impl_heritage_test!(pending_operation_management);
     impl_heritage_test!(safe_update_current_subwallet_config);
     impl_heritage_test!(transaction);
     impl_heritage_test!(unused_account_xpub_management);
     impl_heritage_test!(heritage_utxo_management);
+    impl_heritage_test!(utxo_labels_management);
+    impl_heritage_test!(labels_management);
+    impl_heritage_test!(frozen_utxos_management);
     impl_heritage_test!(transaction_summaries_management);
+    impl_heritage_test!(verify_repair_integrity);
 
     macro_rules! impl_bdk_test {
         ($tn: tt) => {
@@ -1,21 +1,35 @@
+//! Database traits used by the [HeritageWallet](crate::HeritageWallet).
+//!
+//! This crate ships the volatile [HeritageMemoryDatabase](memory::HeritageMemoryDatabase)
+//! and, with the `sqlite` feature, the persistent `HeritageSqliteDatabase`.
+//! A redb-backed implementation of [PartitionableDatabase], [HeritageDatabase] and
+//! [TransacHeritageDatabase] is provided by the `btc-heritage-wallet` crate as `HeritageWalletDatabase`
+//! and it is verified against the same `database-tests` suite.
+pub mod integrity;
 pub mod memory;
 pub mod paginate;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
 
 use bdk::{database::BatchDatabase, BlockTime};
 use core::fmt::Display;
 
 use crate::{
     account_xpub::AccountXPub,
-    bitcoin::{FeeRate, OutPoint, Txid},
+    bitcoin::{FeeRate, Network, OutPoint, Txid},
     errors::DatabaseError,
     heritage_wallet::{
-        BlockInclusionObjective, HeritageUtxo, HeritageWalletBalance, SubwalletConfigId,
+        heir_rotation::HeirRotation, journal::PendingOperation, BlockInclusionObjective, GapLimit,
+        HeirSpendingQuotas, HeritageUtxo, HeritageWalletBalance, LabelRef, SubwalletConfigId,
         TransactionSummary,
     },
     subwallet_config::SubwalletConfig,
 };
 
-use self::paginate::{ContinuationToken, Paginated};
+use self::{
+    integrity::IntegrityReport,
+    paginate::{ContinuationToken, Paginated},
+};
 
 type Result<T> = core::result::Result<T, DatabaseError>;
 
@@ -113,6 +127,38 @@ pub trait HeritageDatabase: PartitionableDatabase + TransacHeritageOperation {
         continuation_token: Option<ContinuationToken>,
     ) -> Result<Paginated<HeritageUtxo>>;
 
+    /// Set the label of the UTXO designated by the given [OutPoint], overriding the existing one if any.
+    /// The UTXO does not have to be present in the database.
+    fn set_utxo_label(&mut self, outpoint: &OutPoint, label: &str) -> Result<()>;
+    /// Delete the label of the UTXO designated by the given [OutPoint]. If there is no label,
+    /// it will be processed as a success.
+    fn delete_utxo_label(&mut self, outpoint: &OutPoint) -> Result<()>;
+    /// Retrieve the label of the UTXO designated by the given [OutPoint], if any.
+    fn get_utxo_label(&self, outpoint: &OutPoint) -> Result<Option<String>>;
+    /// Returns the list of all the UTXO labels from the database, along with their [OutPoint].
+    fn list_utxo_labels(&self) -> Result<Vec<(OutPoint, String)>>;
+
+    /// Set the label of the transaction or address designated by the given [LabelRef],
+    /// overriding the existing one if any.
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<()>;
+    /// Delete the label of the transaction or address designated by the given [LabelRef].
+    /// If there is no label, it will be processed as a success.
+    fn delete_label(&mut self, label_ref: &LabelRef) -> Result<()>;
+    /// Retrieve the label of the transaction or address designated by the given [LabelRef], if any.
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>>;
+    /// Returns the list of all the transaction and address labels from the database,
+    /// along with their [LabelRef].
+    fn list_labels(&self) -> Result<Vec<(LabelRef, String)>>;
+
+    /// Freeze the UTXO designated by the given [OutPoint] so that it is never selected
+    /// when creating a new transaction. Freezing an already frozen UTXO is processed as a success.
+    fn freeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()>;
+    /// Unfreeze the UTXO designated by the given [OutPoint]. Unfreezing a UTXO that is not
+    /// frozen is processed as a success.
+    fn unfreeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()>;
+    /// Returns the list of the [OutPoint] of the frozen UTXOs from the database.
+    fn list_frozen_utxos(&self) -> Result<Vec<OutPoint>>;
+
     /// Add new [TransactionSummary] in the database, overriding existing ones if any.
     fn add_transaction_summaries(
         &mut self,
@@ -161,6 +207,44 @@ pub trait HeritageDatabase: PartitionableDatabase + TransacHeritageOperation {
         &mut self,
         new_objective: BlockInclusionObjective,
     ) -> Result<()>;
+
+    /// Retrieve the [GapLimit] of the receiving addresses from the database
+    fn get_gap_limit(&self) -> Result<Option<GapLimit>>;
+    /// Set the [GapLimit] of the receiving addresses in the database
+    fn set_gap_limit(&mut self, new_gap_limit: GapLimit) -> Result<()>;
+
+    /// Retrieve the [HeirSpendingQuotas] of the wallet from the database
+    fn get_heir_spending_quotas(&self) -> Result<Option<HeirSpendingQuotas>>;
+    /// Set the [HeirSpendingQuotas] of the wallet in the database
+    fn set_heir_spending_quotas(&mut self, new_quotas: &HeirSpendingQuotas) -> Result<()>;
+
+    /// Retrieve the [Network] of the wallet from the database
+    fn get_network(&self) -> Result<Option<Network>>;
+    /// Set the [Network] of the wallet in the database
+    fn set_network(&mut self, network: Network) -> Result<()>;
+
+    /// Retrieve the [PendingOperation] journaled in the database, if any
+    fn get_pending_operation(&self) -> Result<Option<PendingOperation>>;
+    /// Journal the [PendingOperation] in the database, overriding the existing one if any
+    fn set_pending_operation(&mut self, pending_operation: &PendingOperation) -> Result<()>;
+    /// Delete the journaled [PendingOperation]. If there is none, it will be processed as a success.
+    fn delete_pending_operation(&mut self) -> Result<()>;
+
+    /// Record the [HeirRotation] in the database
+    fn add_heir_rotation(&mut self, heir_rotation: &HeirRotation) -> Result<()>;
+    /// List the [HeirRotation]s recorded in the database, ordered by timestamp
+    fn list_heir_rotations(&self) -> Result<Vec<HeirRotation>>;
+
+    /// Cross-check the invariants of the database and report the
+    /// [IntegrityIssue](integrity::IntegrityIssue)s found, see the [integrity] module
+    fn verify_integrity(&self) -> Result<IntegrityReport> {
+        integrity::verify(self)
+    }
+    /// Same as [HeritageDatabase::verify_integrity] but also fix the
+    /// [repairable](integrity::IntegrityIssue::is_repairable) issues found
+    fn repair_integrity(&mut self) -> Result<IntegrityReport> {
+        integrity::repair(self)
+    }
 }
 
 pub trait TransacHeritageDatabase: HeritageDatabase {
@@ -187,7 +271,7 @@ pub mod tests {
             get_test_account_xpub, get_test_heritage_config, get_test_subwallet_config,
             TestHeritageConfig,
         },
-        heritage_wallet::TransactionSummaryOwnedIO,
+        heritage_wallet::{CheckedAddress, TransactionSummaryOwnedIO},
     };
 
     use super::*;
@@ -490,6 +574,8 @@ pub mod tests {
                 .try_into()
                 .unwrap(),
             heritage_config: get_test_heritage_config(TestHeritageConfig::BackupWifeBro),
+            keypath_only: false,
+            address_reused: false,
         };
         let heritage_utxo_2 = HeritageUtxo {
             outpoint: OutPoint::from_str(
@@ -505,6 +591,8 @@ pub mod tests {
                 .try_into()
                 .unwrap(),
             heritage_config: get_test_heritage_config(TestHeritageConfig::BackupWifeBro),
+            keypath_only: false,
+            address_reused: false,
         };
         let heritage_utxo_3 = HeritageUtxo {
             outpoint: OutPoint::from_str(
@@ -520,6 +608,8 @@ pub mod tests {
                 .try_into()
                 .unwrap(),
             heritage_config: get_test_heritage_config(TestHeritageConfig::BackupWifeBro),
+            keypath_only: false,
+            address_reused: false,
         };
 
         // Add two UTXO
@@ -592,6 +682,139 @@ pub mod tests {
         assert_eq!(res[0].outpoint, heritage_utxo_3.outpoint);
     }
 
+    pub fn utxo_labels_management<DB: TransacHeritageDatabase>(mut db: DB) {
+        let outpoint_1 = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+        )
+        .unwrap();
+        let outpoint_2 = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+        )
+        .unwrap();
+
+        // At this point, no label
+        let res = db.list_utxo_labels();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_empty());
+        assert!(db.get_utxo_label(&outpoint_1).is_ok_and(|l| l.is_none()));
+
+        // Set labels
+        let res = db.set_utxo_label(&outpoint_1, "Salary");
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.set_utxo_label(&outpoint_2, "Cold storage");
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(db
+            .get_utxo_label(&outpoint_1)
+            .is_ok_and(|l| l.is_some_and(|l| l == "Salary")));
+        assert_eq!(db.list_utxo_labels().unwrap().len(), 2);
+
+        // Override a label
+        let res = db.set_utxo_label(&outpoint_1, "Bonus");
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(db
+            .get_utxo_label(&outpoint_1)
+            .is_ok_and(|l| l.is_some_and(|l| l == "Bonus")));
+        assert_eq!(db.list_utxo_labels().unwrap().len(), 2);
+
+        // Delete a label, twice
+        let res = db.delete_utxo_label(&outpoint_1);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.delete_utxo_label(&outpoint_1);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(db.get_utxo_label(&outpoint_1).is_ok_and(|l| l.is_none()));
+        assert_eq!(
+            db.list_utxo_labels().unwrap(),
+            vec![(outpoint_2, "Cold storage".to_owned())]
+        );
+    }
+
+    pub fn labels_management<DB: TransacHeritageDatabase>(mut db: DB) {
+        let tx_ref = LabelRef::Tx(
+            Txid::from_str("5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456")
+                .unwrap(),
+        );
+        let addr_ref = LabelRef::Address(
+            CheckedAddress::try_from("bcrt1q3q4u6zx7k6c4rwtf9nzhymkvus758eluc06mug").unwrap(),
+        );
+
+        // At this point, no label
+        let res = db.list_labels();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_empty());
+        assert!(db.get_label(&tx_ref).is_ok_and(|l| l.is_none()));
+        assert!(db.get_label(&addr_ref).is_ok_and(|l| l.is_none()));
+
+        // Set labels
+        let res = db.set_label(&tx_ref, "Rent");
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.set_label(&addr_ref, "Landlord");
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(db
+            .get_label(&tx_ref)
+            .is_ok_and(|l| l.is_some_and(|l| l == "Rent")));
+        assert!(db
+            .get_label(&addr_ref)
+            .is_ok_and(|l| l.is_some_and(|l| l == "Landlord")));
+        assert_eq!(db.list_labels().unwrap().len(), 2);
+
+        // Labels are independent from UTXO labels
+        assert!(db.list_utxo_labels().unwrap().is_empty());
+
+        // Override a label
+        let res = db.set_label(&tx_ref, "Rent - January");
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(db
+            .get_label(&tx_ref)
+            .is_ok_and(|l| l.is_some_and(|l| l == "Rent - January")));
+        assert_eq!(db.list_labels().unwrap().len(), 2);
+
+        // Delete a label, twice
+        let res = db.delete_label(&tx_ref);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.delete_label(&tx_ref);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(db.get_label(&tx_ref).is_ok_and(|l| l.is_none()));
+        assert_eq!(
+            db.list_labels().unwrap(),
+            vec![(addr_ref, "Landlord".to_owned())]
+        );
+    }
+
+    pub fn frozen_utxos_management<DB: TransacHeritageDatabase>(mut db: DB) {
+        let outpoint_1 = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+        )
+        .unwrap();
+        let outpoint_2 = OutPoint::from_str(
+            "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:1",
+        )
+        .unwrap();
+
+        // At this point, no frozen UTXO
+        let res = db.list_frozen_utxos();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_empty());
+
+        // Freeze, twice
+        let res = db.freeze_utxo(&outpoint_1);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.freeze_utxo(&outpoint_1);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.freeze_utxo(&outpoint_2);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let frozen = db.list_frozen_utxos().unwrap();
+        assert_eq!(frozen.len(), 2);
+        assert!(frozen.contains(&outpoint_1));
+        assert!(frozen.contains(&outpoint_2));
+
+        // Unfreeze, twice
+        let res = db.unfreeze_utxo(&outpoint_1);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.unfreeze_utxo(&outpoint_1);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert_eq!(db.list_frozen_utxos().unwrap(), vec![outpoint_2]);
+    }
+
     pub fn transaction_summaries_management<DB: TransacHeritageDatabase>(mut db: DB) {
         // At this point, no TransactionSummary
         let res = db.list_transaction_summaries();
@@ -624,6 +847,8 @@ pub mod tests {
             fee: Amount::from_sat(10_000),
             fee_rate: FeeRate::from_sat_per_vb_unchecked(3),
             parent_txids: HashSet::new(),
+            label: None,
+            address_reused: false,
         };
         let txid =
             Txid::from_str("5df6e0e2761359d30a8275058e300fcc0381534545f55cf43e41983f5d4c9456")
@@ -645,6 +870,8 @@ pub mod tests {
             fee: Amount::from_sat(10_000),
             fee_rate: FeeRate::from_sat_per_vb_unchecked(3),
             parent_txids: HashSet::new(),
+            label: None,
+            address_reused: false,
         };
         let txid =
             Txid::from_str("5df6e0e2761359d30a8275058e201fcc0381534545f55cf43e41983f5d4c9456")
@@ -681,6 +908,8 @@ pub mod tests {
                 "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456",
             )
             .unwrap()]),
+            label: None,
+            address_reused: false,
         };
 
         // Add two TransactionSummary
@@ -846,6 +1075,148 @@ pub mod tests {
         assert!(res.unwrap().is_some_and(|bio| bio == new_bio));
     }
 
+    pub fn get_set_gap_limit<DB: TransacHeritageDatabase>(mut db: DB) {
+        // Get gap limit works and is None
+        let res = db.get_gap_limit();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_none());
+
+        let new_gap_limit = GapLimit::from(50);
+        // Insert work
+        let res = db.set_gap_limit(new_gap_limit);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.get_gap_limit();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_some_and(|gl| gl == new_gap_limit));
+
+        let new_gap_limit = GapLimit::from(100);
+        // Update works
+        let res = db.set_gap_limit(new_gap_limit);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.get_gap_limit();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_some_and(|gl| gl == new_gap_limit));
+    }
+
+    pub fn get_set_heir_spending_quotas<DB: TransacHeritageDatabase>(mut db: DB) {
+        use crate::dbtests::{get_test_heritage, TestHeritage};
+        // Get heir spending quotas works and is None
+        let res = db.get_heir_spending_quotas();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_none());
+
+        let wife = get_test_heritage(TestHeritage::Wife).heir_config;
+        let brother = get_test_heritage(TestHeritage::Brother).heir_config;
+        let mut new_quotas = HeirSpendingQuotas::default();
+        new_quotas.set(wife.clone(), Some(70)).unwrap();
+        // Insert work
+        let res = db.set_heir_spending_quotas(&new_quotas);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.get_heir_spending_quotas();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_some_and(|q| q == new_quotas));
+
+        new_quotas.set(wife, None).unwrap();
+        new_quotas.set(brother, Some(30)).unwrap();
+        // Update works
+        let res = db.set_heir_spending_quotas(&new_quotas);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.get_heir_spending_quotas();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_some_and(|q| q == new_quotas));
+    }
+
+    pub fn get_set_network<DB: TransacHeritageDatabase>(mut db: DB) {
+        // Get network works and is None
+        let res = db.get_network();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_none());
+
+        // Insert work
+        let res = db.set_network(Network::Testnet);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.get_network();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert_eq!(res.unwrap(), Some(Network::Testnet));
+
+        // Update works
+        let res = db.set_network(Network::Bitcoin);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.get_network();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert_eq!(res.unwrap(), Some(Network::Bitcoin));
+    }
+
+    pub fn pending_operation_management<DB: TransacHeritageDatabase>(mut db: DB) {
+        use crate::heritage_wallet::journal::SubwalletAddressIndexes;
+
+        // At this point, no PendingOperation
+        let res = db.get_pending_operation();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_none());
+        // Deleting an absent PendingOperation is a success
+        let res = db.delete_pending_operation();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+
+        let pending_operation = PendingOperation::RestoreBackup {
+            address_indexes: vec![SubwalletAddressIndexes {
+                subwallet_id: 0,
+                last_external_index: Some(5),
+                last_change_index: None,
+            }],
+        };
+        let res = db.set_pending_operation(&pending_operation);
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = db.get_pending_operation();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert_eq!(res.unwrap(), Some(pending_operation));
+
+        // Override
+        let pending_operation = PendingOperation::RestoreBackup {
+            address_indexes: vec![],
+        };
+        db.set_pending_operation(&pending_operation).unwrap();
+        assert_eq!(db.get_pending_operation().unwrap(), Some(pending_operation));
+
+        let res = db.delete_pending_operation();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(db.get_pending_operation().unwrap().is_none());
+    }
+
+    pub fn heir_rotations_management<DB: TransacHeritageDatabase>(mut db: DB) {
+        use crate::dbtests::{get_test_heritage, TestHeritage};
+
+        // At this point, no HeirRotation
+        let res = db.list_heir_rotations();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_empty());
+
+        let heir_rotation = |timestamp, old: TestHeritage, new: TestHeritage| HeirRotation {
+            timestamp,
+            old_heir_config: get_test_heritage(old).heir_config,
+            new_heir_config: get_test_heritage(new).heir_config,
+            heritage_config: get_test_heritage_config(TestHeritageConfig::BackupWifeBro),
+        };
+        let heir_rotation1 =
+            heir_rotation(1_700_000_000, TestHeritage::Wife, TestHeritage::Brother);
+        let heir_rotation2 = heir_rotation(1_600_000_000, TestHeritage::Backup, TestHeritage::Wife);
+        // Same timestamp, other heir
+        let heir_rotation3 = heir_rotation(1_600_000_000, TestHeritage::Wife, TestHeritage::Backup);
+        for heir_rotation in [&heir_rotation1, &heir_rotation2, &heir_rotation3] {
+            let res = db.add_heir_rotation(heir_rotation);
+            assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        }
+
+        // Ordered by timestamp
+        let res = db.list_heir_rotations();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let heir_rotations = res.unwrap();
+        assert_eq!(heir_rotations.len(), 3);
+        assert_eq!(heir_rotations[2], heir_rotation1);
+        assert!(heir_rotations[..2].contains(&heir_rotation2));
+        assert!(heir_rotations[..2].contains(&heir_rotation3));
+    }
+
     pub fn list_obsolete_subwallet_configs<DB: TransacHeritageDatabase>(mut db: DB) {
         let subwallet_config0 = get_test_subwallet_config(0, TestHeritageConfig::BackupWifeBro);
         db.put_subwallet_config(SubwalletConfigId::Id(0), &subwallet_config0)
@@ -863,6 +1234,86 @@ pub mod tests {
         let obsolete = obsolete.unwrap();
         assert_eq!(obsolete, vec![subwallet_config0, subwallet_config1]);
     }
+
+    pub fn verify_repair_integrity<DB: TransacHeritageDatabase>(mut db: DB) {
+        use super::integrity::IntegrityIssue;
+
+        // An empty database is consistent
+        let res = db.verify_integrity();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        assert!(res.unwrap().is_ok());
+
+        // No Current SubwalletConfig
+        let subwallet_config0 = get_test_subwallet_config(0, TestHeritageConfig::BackupWifeY2);
+        db.put_subwallet_config(SubwalletConfigId::Id(0), &subwallet_config0)
+            .unwrap();
+        assert_eq!(
+            db.verify_integrity().unwrap().issues,
+            vec![IntegrityIssue::MissingCurrentSubwalletConfig]
+        );
+
+        // The Current SubwalletConfig reuses the SubwalletId 0
+        db.put_subwallet_config(SubwalletConfigId::Current, &subwallet_config0)
+            .unwrap();
+        assert_eq!(
+            db.verify_integrity().unwrap().issues,
+            vec![IntegrityIssue::DuplicateSubwalletId(
+                subwallet_config0.subwallet_id()
+            )]
+        );
+
+        // A new Current SubwalletConfig whose AccountXPub is still in the unused set
+        // and a UTXO that belongs to none of the subwallets
+        let axp1 = get_test_account_xpub(1);
+        db.add_unused_account_xpubs(&vec![axp1.clone(), get_test_account_xpub(2)])
+            .unwrap();
+        let mut transac = db.begin_transac();
+        transac
+            .safe_update_current_subwallet_config(
+                &get_test_subwallet_config(1, TestHeritageConfig::BackupWifeY1),
+                Some(&subwallet_config0),
+            )
+            .unwrap();
+        db.commit_transac(transac).unwrap();
+        let unknown_utxo = HeritageUtxo {
+            outpoint: OutPoint::from_str(
+                "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456:0",
+            )
+            .unwrap(),
+            amount: Amount::from_sat(10_000),
+            confirmation_time: None,
+            address: "bcrt1p30dak2tfa6m7erhayrmmceykrfmqxy6qf6gqzzdphgv6lw9s9ykq4w70ya"
+                .try_into()
+                .unwrap(),
+            heritage_config: get_test_heritage_config(TestHeritageConfig::BackupWifeBro),
+            keypath_only: false,
+            address_reused: false,
+        };
+        db.add_utxos(&vec![unknown_utxo.clone()]).unwrap();
+        let report = db.verify_integrity().unwrap();
+        assert_eq!(
+            report.issues,
+            vec![
+                IntegrityIssue::UsedAccountXPubNotRemoved(axp1.clone()),
+                IntegrityIssue::UnknownSubwalletUtxo(unknown_utxo.outpoint),
+            ]
+        );
+        assert!(report.issues.iter().all(IntegrityIssue::is_repairable));
+        assert!(!report.repaired);
+
+        // Repair
+        let res = db.repair_integrity();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let res = res.unwrap();
+        assert!(res.repaired);
+        assert_eq!(res.issues, report.issues);
+        assert!(db.verify_integrity().unwrap().is_ok());
+        assert_eq!(
+            db.list_unused_account_xpubs().unwrap(),
+            vec![get_test_account_xpub(2)]
+        );
+        assert!(db.list_utxos().unwrap().is_empty());
+    }
 }
 
 #[cfg(any(test, feature = "database-tests"))]
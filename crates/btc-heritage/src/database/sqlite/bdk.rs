@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+
+use bdk::{
+    bitcoin::{OutPoint, Script, ScriptBuf, Transaction, Txid},
+    database::{BatchDatabase, BatchOperations, Database, SyncTime},
+    Error, KeychainKind, LocalUtxo, TransactionDetails,
+};
+
+use super::{from_json, to_json, HeritageSqliteDatabase, KeyMapper, SqliteOperations};
+
+#[derive(Debug)]
+pub struct HeritageSqliteDatabaseBatch {
+    inner: SqliteOperations,
+    prefix: String,
+}
+impl HeritageSqliteDatabaseBatch {
+    fn key(&self, key_mapper: &KeyMapper) -> String {
+        key_mapper.key(&self.prefix)
+    }
+}
+
+impl BatchOperations for HeritageSqliteDatabaseBatch {
+    fn set_script_pubkey(
+        &mut self,
+        script: &Script,
+        keychain: KeychainKind,
+        child: u32,
+    ) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::set_script_pubkey - script={script} keychain={keychain:?} child={child}");
+        let key = self.key(&KeyMapper::Script(Some(script)));
+        self.inner.update_item(&key, &(keychain, child))?;
+
+        let key = self.key(&KeyMapper::Path((Some(keychain), Some(child))));
+        self.inner.update_item(&key, &script.to_bytes())?;
+        Ok(())
+    }
+
+    fn set_utxo(&mut self, utxo: &LocalUtxo) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::set_utxo - utxo={utxo:?}");
+        let key = self.key(&KeyMapper::Utxo(Some(&utxo.outpoint)));
+        self.inner.update_item(&key, utxo)?;
+        Ok(())
+    }
+
+    fn set_raw_tx(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::set_raw_tx - transaction={transaction:?}");
+        let key = self.key(&KeyMapper::RawTx(Some(&transaction.txid())));
+        self.inner.update_item(&key, transaction)?;
+        Ok(())
+    }
+
+    fn set_tx(&mut self, transaction: &TransactionDetails) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::set_tx - transaction={transaction:?}");
+        let key = self.key(&KeyMapper::Transaction(Some(&transaction.txid)));
+
+        // insert the raw_tx if present
+        if let Some(ref tx) = transaction.transaction {
+            self.set_raw_tx(tx)?;
+        }
+        // remove the raw tx from the serialized version
+        let mut transaction = transaction.clone();
+        transaction.transaction = None;
+        self.inner.update_item(&key, &transaction)?;
+        Ok(())
+    }
+
+    fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
+        log::debug!(
+            "HeritageSqliteDatabaseBatch::set_last_index - keychain={keychain:?} value={value}"
+        );
+        let key = self.key(&KeyMapper::LastIndex(keychain));
+        self.inner.update_item(&key, &value)?;
+        Ok(())
+    }
+
+    fn set_sync_time(&mut self, sync_time: SyncTime) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::set_sync_time - sync_time={sync_time:?}");
+        let key = self.key(&KeyMapper::SyncTime);
+        self.inner.update_item(&key, &sync_time)?;
+        Ok(())
+    }
+
+    fn del_script_pubkey_from_path(
+        &mut self,
+        keychain: KeychainKind,
+        child: u32,
+    ) -> Result<Option<ScriptBuf>, Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::del_script_pubkey_from_path - keychain={keychain:?} child={child}");
+        let key = self.key(&KeyMapper::Path((Some(keychain), Some(child))));
+        self.inner.delete_item(&key);
+        Ok(None)
+    }
+
+    fn del_path_from_script_pubkey(
+        &mut self,
+        script: &Script,
+    ) -> Result<Option<(KeychainKind, u32)>, Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::del_path_from_script_pubkey - script={script}");
+        let key = self.key(&KeyMapper::Script(Some(script)));
+        self.inner.delete_item(&key);
+        Ok(None)
+    }
+
+    fn del_utxo(&mut self, outpoint: &OutPoint) -> Result<Option<LocalUtxo>, Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::del_utxo - outpoint={outpoint:?}");
+        let key = self.key(&KeyMapper::Utxo(Some(outpoint)));
+        self.inner.delete_item(&key);
+        Ok(None)
+    }
+
+    fn del_raw_tx(&mut self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::del_raw_tx - txid={txid:?}");
+        let key = self.key(&KeyMapper::RawTx(Some(txid)));
+        self.inner.delete_item(&key);
+        Ok(None)
+    }
+
+    fn del_tx(
+        &mut self,
+        txid: &Txid,
+        include_raw: bool,
+    ) -> Result<Option<TransactionDetails>, Error> {
+        log::debug!(
+            "HeritageSqliteDatabaseBatch::del_tx - txid={txid:?} include_raw={include_raw}"
+        );
+        let key = self.key(&KeyMapper::Transaction(Some(txid)));
+        if include_raw {
+            self.del_raw_tx(txid)?;
+        }
+        self.inner.delete_item(&key);
+        Ok(None)
+    }
+
+    fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::del_last_index - keychain={keychain:?}");
+        let key = self.key(&KeyMapper::LastIndex(keychain));
+        self.inner.delete_item(&key);
+        Ok(None)
+    }
+
+    fn del_sync_time(&mut self) -> Result<Option<SyncTime>, Error> {
+        log::debug!("HeritageSqliteDatabaseBatch::del_sync_time");
+        let key = self.key(&KeyMapper::SyncTime);
+        self.inner.delete_item(&key);
+        Ok(None)
+    }
+}
+
+impl BatchOperations for HeritageSqliteDatabase {
+    fn set_script_pubkey(
+        &mut self,
+        script: &Script,
+        keychain: KeychainKind,
+        child: u32,
+    ) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabase::set_script_pubkey - script={script} keychain={keychain:?} child={child}");
+        let mut operations = self.begin_operations();
+
+        let key = self.key(&KeyMapper::Script(Some(script)));
+        operations.update_item(&key, &(keychain, child))?;
+
+        let key = self.key(&KeyMapper::Path((Some(keychain), Some(child))));
+        operations.update_item(&key, &script.to_bytes())?;
+
+        self.commit_operations(operations)?;
+        Ok(())
+    }
+
+    fn set_utxo(&mut self, utxo: &LocalUtxo) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabase::set_utxo - utxo={utxo:?}");
+        let key = self.key(&KeyMapper::Utxo(Some(&utxo.outpoint)));
+        self.update_item(&key, utxo)?;
+        Ok(())
+    }
+
+    fn set_raw_tx(&mut self, transaction: &Transaction) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabase::set_raw_tx - transaction={transaction:?}");
+        let key = self.key(&KeyMapper::RawTx(Some(&transaction.txid())));
+        self.update_item(&key, transaction)?;
+        Ok(())
+    }
+
+    fn set_tx(&mut self, transaction: &TransactionDetails) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabase::set_tx - transaction={transaction:?}");
+        let key = self.key(&KeyMapper::Transaction(Some(&transaction.txid)));
+
+        // insert the raw_tx if present
+        if let Some(ref tx) = transaction.transaction {
+            self.set_raw_tx(tx)?;
+        }
+
+        // remove the raw tx from the serialized version
+        let mut transaction = transaction.clone();
+        transaction.transaction = None;
+        self.update_item(&key, &transaction)?;
+        Ok(())
+    }
+
+    fn set_last_index(&mut self, keychain: KeychainKind, value: u32) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabase::set_last_index - keychain={keychain:?} value={value}");
+        let key = self.key(&KeyMapper::LastIndex(keychain));
+        self.update_item(&key, &value)?;
+        Ok(())
+    }
+
+    fn set_sync_time(&mut self, sync_time: SyncTime) -> Result<(), Error> {
+        log::debug!("HeritageSqliteDatabase::set_sync_time - sync_time={sync_time:?}");
+        let key = self.key(&KeyMapper::SyncTime);
+        self.update_item(&key, &sync_time)?;
+        Ok(())
+    }
+
+    fn del_script_pubkey_from_path(
+        &mut self,
+        keychain: KeychainKind,
+        child: u32,
+    ) -> Result<Option<ScriptBuf>, Error> {
+        log::debug!("HeritageSqliteDatabase::del_script_pubkey_from_path - keychain={keychain:?} child={child}");
+        let key = self.key(&KeyMapper::Path((Some(keychain), Some(child))));
+        let bytes: Option<Vec<u8>> = self.delete_item(&key)?;
+        Ok(bytes.map(|b| ScriptBuf::from(b)))
+    }
+
+    fn del_path_from_script_pubkey(
+        &mut self,
+        script: &Script,
+    ) -> Result<Option<(KeychainKind, u32)>, Error> {
+        log::debug!("HeritageSqliteDatabase::del_path_from_script_pubkey - script={script}");
+        let key = self.key(&KeyMapper::Script(Some(script)));
+        Ok(self.delete_item(&key)?)
+    }
+
+    fn del_utxo(&mut self, outpoint: &OutPoint) -> Result<Option<LocalUtxo>, Error> {
+        log::debug!("HeritageSqliteDatabase::del_utxo - outpoint={outpoint:?}");
+        let key = self.key(&KeyMapper::Utxo(Some(outpoint)));
+        Ok(self.delete_item(&key)?)
+    }
+
+    fn del_raw_tx(&mut self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        log::debug!("HeritageSqliteDatabase::del_raw_tx - txid={txid:?}");
+        let key = self.key(&KeyMapper::RawTx(Some(txid)));
+        Ok(self.delete_item(&key)?)
+    }
+
+    fn del_tx(
+        &mut self,
+        txid: &Txid,
+        include_raw: bool,
+    ) -> Result<Option<TransactionDetails>, Error> {
+        log::debug!("HeritageSqliteDatabase::del_tx - txid={txid:?} include_raw={include_raw}");
+        let key = self.key(&KeyMapper::Transaction(Some(txid)));
+        let raw_tx = if include_raw {
+            self.del_raw_tx(txid)?
+        } else {
+            None
+        };
+        Ok(self.delete_item(&key)?.map(|mut tx: TransactionDetails| {
+            tx.transaction = raw_tx;
+            tx
+        }))
+    }
+
+    fn del_last_index(&mut self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        log::debug!("HeritageSqliteDatabase::del_last_index - keychain={keychain:?}");
+        let key = self.key(&KeyMapper::LastIndex(keychain));
+        Ok(self.delete_item(&key)?)
+    }
+
+    fn del_sync_time(&mut self) -> Result<Option<SyncTime>, Error> {
+        log::debug!("HeritageSqliteDatabase::del_sync_time");
+        let key = self.key(&KeyMapper::SyncTime);
+        Ok(self.delete_item(&key)?)
+    }
+}
+
+impl Database for HeritageSqliteDatabase {
+    fn check_descriptor_checksum<B: AsRef<[u8]>>(
+        &mut self,
+        keychain: KeychainKind,
+        bytes: B,
+    ) -> Result<(), Error> {
+        let current_checksum = bytes.as_ref().to_vec();
+        let bytes_str = crate::utils::bytes_to_hex_string(&current_checksum);
+        log::debug!(
+            "HeritageSqliteDatabase::check_descriptor_checksum - keychain={keychain:?} bytes={bytes_str}",
+        );
+        let key = self.key(&KeyMapper::DescriptorChecksum(keychain));
+        let recorded_checksum: Option<Vec<u8>> = self.get_item(&key)?;
+        if let Some(recorded_checksum) = recorded_checksum {
+            if current_checksum != recorded_checksum {
+                log::warn!(
+                    "ChecksumMismatch: recorded_checksum={} current_checksum={}",
+                    crate::utils::bytes_to_hex_string(recorded_checksum),
+                    crate::utils::bytes_to_hex_string(current_checksum)
+                );
+                return Err(Error::ChecksumMismatch);
+            }
+        } else {
+            self.put_item(&key, &current_checksum)?;
+        }
+        Ok(())
+    }
+
+    fn iter_script_pubkeys(&self, keychain: Option<KeychainKind>) -> Result<Vec<ScriptBuf>, Error> {
+        log::debug!("HeritageSqliteDatabase::iter_script_pubkeys - keychain={keychain:?}");
+        let prefix = self.key(&KeyMapper::Path((keychain, None)));
+        let bytes: Vec<Vec<u8>> = self.query(&prefix)?;
+        Ok(bytes.into_iter().map(|b| ScriptBuf::from(b)).collect())
+    }
+
+    fn iter_utxos(&self) -> Result<Vec<LocalUtxo>, Error> {
+        log::debug!("HeritageSqliteDatabase::iter_utxos");
+        let prefix = self.key(&KeyMapper::Utxo(None));
+        Ok(self.query(&prefix)?)
+    }
+
+    fn iter_raw_txs(&self) -> Result<Vec<Transaction>, Error> {
+        log::debug!("HeritageSqliteDatabase::iter_raw_txs");
+        let prefix = self.key(&KeyMapper::RawTx(None));
+        Ok(self.query(&prefix)?)
+    }
+
+    fn iter_txs(&self, include_raw: bool) -> Result<Vec<TransactionDetails>, Error> {
+        log::debug!("HeritageSqliteDatabase::iter_txs - include_raw={include_raw}");
+        let prefix = self.key(&KeyMapper::Transaction(None));
+        let mut raw_txs: HashMap<Txid, Transaction> = if include_raw {
+            self.iter_raw_txs()?
+                .into_iter()
+                .map(|tx| (tx.txid(), tx))
+                .collect()
+        } else {
+            Default::default()
+        };
+        let mut result: Vec<TransactionDetails> = self.query(&prefix)?;
+        if include_raw {
+            for tx in result.iter_mut() {
+                tx.transaction = raw_txs.remove(&tx.txid)
+            }
+        }
+        Ok(result)
+    }
+
+    fn get_script_pubkey_from_path(
+        &self,
+        keychain: KeychainKind,
+        child: u32,
+    ) -> Result<Option<ScriptBuf>, Error> {
+        log::debug!("HeritageSqliteDatabase::get_script_pubkey_from_path - keychain={keychain:?} child={child}");
+        let key = self.key(&KeyMapper::Path((Some(keychain), Some(child))));
+        let bytes: Option<Vec<u8>> = self.get_item(&key)?;
+        Ok(bytes.map(|b| ScriptBuf::from(b)))
+    }
+
+    fn get_path_from_script_pubkey(
+        &self,
+        script: &Script,
+    ) -> Result<Option<(KeychainKind, u32)>, Error> {
+        log::debug!("HeritageSqliteDatabase::get_path_from_script_pubkey - script={script}");
+        let key = self.key(&KeyMapper::Script(Some(script)));
+        Ok(self.get_item(&key)?)
+    }
+
+    fn get_utxo(&self, outpoint: &OutPoint) -> Result<Option<LocalUtxo>, Error> {
+        log::debug!("HeritageSqliteDatabase::get_utxo - outpoint={outpoint:?}");
+        let key = self.key(&KeyMapper::Utxo(Some(outpoint)));
+        Ok(self.get_item(&key)?)
+    }
+
+    fn get_raw_tx(&self, txid: &Txid) -> Result<Option<Transaction>, Error> {
+        log::debug!("HeritageSqliteDatabase::get_raw_tx - txid={txid:?}");
+        let key = self.key(&KeyMapper::RawTx(Some(txid)));
+        Ok(self.get_item(&key)?)
+    }
+
+    fn get_tx(&self, txid: &Txid, include_raw: bool) -> Result<Option<TransactionDetails>, Error> {
+        log::debug!("HeritageSqliteDatabase::get_tx - txid={txid:?} include_raw={include_raw}");
+        let key = self.key(&KeyMapper::Transaction(Some(txid)));
+        let raw_tx = if include_raw {
+            self.get_raw_tx(txid)?
+        } else {
+            None
+        };
+        let tx = self.get_item(&key)?;
+        Ok(tx.map(|mut tx: TransactionDetails| {
+            tx.transaction = raw_tx;
+            tx
+        }))
+    }
+
+    fn get_last_index(&self, keychain: KeychainKind) -> Result<Option<u32>, Error> {
+        log::debug!("HeritageSqliteDatabase::get_last_index - keychain={keychain:?}");
+        let key = self.key(&KeyMapper::LastIndex(keychain));
+        Ok(self.get_item(&key)?)
+    }
+
+    fn get_sync_time(&self) -> Result<Option<SyncTime>, Error> {
+        log::debug!("HeritageSqliteDatabase::get_sync_time");
+        let key = self.key(&KeyMapper::SyncTime);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn increment_last_index(&mut self, keychain: KeychainKind) -> Result<u32, Error> {
+        log::debug!("HeritageSqliteDatabase::increment_last_index - keychain={keychain:?}");
+        let key = self.key(&KeyMapper::LastIndex(keychain));
+        let mut conn = self.conn();
+        let txn = conn.transaction().map_err(super::SqliteError::from)?;
+        let new_value = Self::_get(&txn, &key)?
+            .map(|value| from_json::<u32>(&key, &value))
+            .transpose()?
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        Self::_update(&txn, &key, &to_json(&key, &new_value)?)?;
+        txn.commit().map_err(super::SqliteError::from)?;
+        Ok(new_value)
+    }
+}
+
+impl BatchDatabase for HeritageSqliteDatabase {
+    type Batch = HeritageSqliteDatabaseBatch;
+
+    fn begin_batch(&self) -> Self::Batch {
+        Self::Batch {
+            inner: self.begin_operations(),
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    fn commit_batch(&mut self, batch: Self::Batch) -> Result<(), Error> {
+        self.commit_operations(batch.inner)?;
+        Ok(())
+    }
+}
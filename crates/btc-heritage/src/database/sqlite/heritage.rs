@@ -0,0 +1,508 @@
+use std::collections::HashSet;
+
+use bdk::BlockTime;
+
+use crate::{
+    account_xpub::AccountXPub,
+    bitcoin::{FeeRate, Network, OutPoint, Txid},
+    database::{
+        paginate::{ContinuationToken, Paginated},
+        HeritageDatabase, TransacHeritageDatabase, TransacHeritageOperation,
+    },
+    errors::DatabaseError,
+    heritage_wallet::{
+        heir_rotation::HeirRotation, journal::PendingOperation, BlockInclusionObjective, GapLimit,
+        HeirSpendingQuotas, HeritageUtxo, HeritageWalletBalance, LabelRef, SubwalletConfigId,
+        TransactionSummary,
+    },
+    subwallet_config::SubwalletConfig,
+};
+
+use super::{HeritageSqliteDatabase, KeyMapper, Result, SqliteError, SqliteOperations};
+
+#[derive(Debug)]
+pub struct HeritageSqliteDatabaseTransac {
+    inner: SqliteOperations,
+    errors_if_fail: Vec<DatabaseError>,
+    prefix: String,
+}
+impl HeritageSqliteDatabaseTransac {
+    fn key(&self, key_mapper: &KeyMapper) -> String {
+        key_mapper.key(&self.prefix)
+    }
+}
+
+impl TransacHeritageOperation for HeritageSqliteDatabaseTransac {
+    fn put_subwallet_config(
+        &mut self,
+        index: SubwalletConfigId,
+        subwallet_config: &SubwalletConfig,
+    ) -> Result<()> {
+        log::debug!("HeritageSqliteDatabaseTransac::put_subwallet_config - index={index:?} subwallet_config={subwallet_config:?}");
+        let key = self.key(&KeyMapper::SubwalletConfig(Some(index)));
+        self.inner.put_item(&key, subwallet_config)?;
+        self.errors_if_fail
+            .push(DatabaseError::SubwalletConfigAlreadyExist(index));
+        Ok(())
+    }
+
+    fn safe_update_current_subwallet_config(
+        &mut self,
+        new_subwallet_config: &SubwalletConfig,
+        old_subwallet_config: Option<&SubwalletConfig>,
+    ) -> Result<()> {
+        log::debug!("HeritageSqliteDatabaseTransac::safe_update_current_subwallet_config - new_subwallet_config={new_subwallet_config:?} old_subwallet_config={old_subwallet_config:?}");
+        let key = self.key(&KeyMapper::SubwalletConfig(Some(
+            SubwalletConfigId::Current,
+        )));
+        self.inner
+            .compare_and_swap(&key, old_subwallet_config, Some(new_subwallet_config))?;
+        self.errors_if_fail
+            .push(DatabaseError::UnexpectedCurrentSubwalletConfig);
+        Ok(())
+    }
+
+    fn delete_unused_account_xpub(&mut self, account_xpub: &AccountXPub) -> Result<()> {
+        log::debug!("HeritageSqliteDatabaseTransac::delete_unused_account_xpub - account_xpub={account_xpub:?}");
+        let key = self.key(&KeyMapper::UnusedAccountXPub(Some(
+            account_xpub.descriptor_id(),
+        )));
+        self.inner
+            .compare_and_swap(&key, Some(account_xpub), None)?;
+        self.errors_if_fail
+            .push(DatabaseError::AccountXPubInexistant(
+                account_xpub.descriptor_id(),
+            ));
+        Ok(())
+    }
+}
+
+impl TransacHeritageOperation for HeritageSqliteDatabase {
+    fn put_subwallet_config(
+        &mut self,
+        index: SubwalletConfigId,
+        subwallet_config: &SubwalletConfig,
+    ) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::put_subwallet_config - index={index:?} subwallet_config={subwallet_config:?}");
+        let key = self.key(&KeyMapper::SubwalletConfig(Some(index)));
+        self.put_item(&key, subwallet_config).map_err(|e| match e {
+            SqliteError::KeyAlreadyExists(_) => DatabaseError::SubwalletConfigAlreadyExist(index),
+            _ => e.into(),
+        })
+    }
+
+    fn safe_update_current_subwallet_config(
+        &mut self,
+        new_subwallet_config: &SubwalletConfig,
+        old_subwallet_config: Option<&SubwalletConfig>,
+    ) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::safe_update_current_subwallet_config - new_subwallet_config={new_subwallet_config:?} old_subwallet_config={old_subwallet_config:?}");
+        let key = self.key(&KeyMapper::SubwalletConfig(Some(
+            SubwalletConfigId::Current,
+        )));
+        self.compare_and_swap(&key, old_subwallet_config, Some(new_subwallet_config))
+            .map_err(|e| match e {
+                SqliteError::CompareAndSwapError(_) => {
+                    DatabaseError::UnexpectedCurrentSubwalletConfig
+                }
+                _ => e.into(),
+            })
+    }
+
+    fn delete_unused_account_xpub(&mut self, account_xpub: &AccountXPub) -> Result<()> {
+        log::debug!(
+            "HeritageSqliteDatabase::delete_unused_account_xpub - account_xpub={account_xpub:?}"
+        );
+        let key = self.key(&KeyMapper::UnusedAccountXPub(Some(
+            account_xpub.descriptor_id(),
+        )));
+        self.compare_and_swap(&key, Some(account_xpub), None)
+            .map_err(|e| match e {
+                SqliteError::CompareAndSwapError(_) => {
+                    DatabaseError::AccountXPubInexistant(account_xpub.descriptor_id())
+                }
+                _ => e.into(),
+            })
+    }
+}
+
+impl TransacHeritageDatabase for HeritageSqliteDatabase {
+    type Transac = HeritageSqliteDatabaseTransac;
+
+    fn begin_transac(&self) -> Self::Transac {
+        log::debug!("HeritageSqliteDatabase::begin_transac");
+        HeritageSqliteDatabaseTransac {
+            inner: self.begin_operations(),
+            errors_if_fail: vec![],
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    fn commit_transac(&mut self, transac: Self::Transac) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::commit_transac - transac={transac:?}");
+        let HeritageSqliteDatabaseTransac {
+            inner: transac,
+            mut errors_if_fail,
+            ..
+        } = transac;
+        self.commit_operations(transac).map_err(|e| match &e {
+            SqliteError::TransactionFailed { idx, .. } => {
+                log::error!("{e}");
+                errors_if_fail.remove(*idx)
+            }
+            _ => e.into(),
+        })
+    }
+}
+
+impl HeritageDatabase for HeritageSqliteDatabase {
+    fn get_subwallet_config(&self, index: SubwalletConfigId) -> Result<Option<SubwalletConfig>> {
+        log::debug!("HeritageSqliteDatabase::get_subwallet_config - index={index:?}");
+        let key = self.key(&KeyMapper::SubwalletConfig(Some(index)));
+        Ok(self.get_item(&key)?)
+    }
+
+    fn list_obsolete_subwallet_configs(&self) -> Result<Vec<SubwalletConfig>> {
+        log::debug!("HeritageSqliteDatabase::list_obsolete_subwallet_configs");
+        let prefix = self.key(&KeyMapper::SubwalletConfig(None)) + "a";
+        Ok(self.query(&prefix)?)
+    }
+
+    fn get_unused_account_xpub(&self) -> Result<Option<AccountXPub>> {
+        log::debug!("HeritageSqliteDatabase::get_unused_account_xpub");
+        let prefix = self.key(&KeyMapper::UnusedAccountXPub(None));
+        Ok(self.query_page(&prefix, 1, None)?.0.into_iter().next())
+    }
+
+    fn list_unused_account_xpubs(&self) -> Result<Vec<AccountXPub>> {
+        log::debug!("HeritageSqliteDatabase::list_unused_account_xpubs");
+        let prefix = self.key(&KeyMapper::UnusedAccountXPub(None));
+        Ok(self.query(&prefix)?)
+    }
+
+    fn list_used_account_xpubs(&self) -> Result<Vec<AccountXPub>> {
+        log::debug!("HeritageSqliteDatabase::list_used_account_xpubs");
+        let prefix = self.key(&KeyMapper::SubwalletConfig(None));
+        let swcs: Vec<SubwalletConfig> = self.query(&prefix)?;
+        Ok(swcs.into_iter().map(|swc| swc.into_parts().0).collect())
+    }
+
+    fn add_unused_account_xpubs(&mut self, account_xpubs: &Vec<AccountXPub>) -> Result<()> {
+        log::debug!(
+            "HeritageSqliteDatabase::add_unused_account_xpubs - account_xpubs={account_xpubs:?}"
+        );
+
+        // Retrieve the existing and used Account XPubs
+        let used_account_xpubs_index = self
+            .list_used_account_xpubs()?
+            .into_iter()
+            .map(|ad| ad.descriptor_id())
+            .collect::<HashSet<_>>();
+
+        let mut operations = self.begin_operations();
+        for account_xpub in account_xpubs {
+            let id = account_xpub.descriptor_id();
+            if used_account_xpubs_index.contains(&id) {
+                log::warn!("HeritageSqliteDatabase::add_unused_account_xpubs - Ignoring account_xpub because we already used it: {account_xpub:?}");
+                continue;
+            }
+            operations.update_item(
+                &self.key(&KeyMapper::UnusedAccountXPub(Some(id))),
+                account_xpub,
+            )?;
+        }
+        self.commit_operations(operations)?;
+        Ok(())
+    }
+
+    fn add_utxos(&mut self, utxos: &Vec<HeritageUtxo>) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::add_utxos - utxos={utxos:?}");
+        let mut operations = self.begin_operations();
+        for utxo in utxos {
+            operations.update_item(
+                &self.key(&KeyMapper::HeritageUtxo(Some(&utxo.outpoint))),
+                utxo,
+            )?;
+        }
+        self.commit_operations(operations)?;
+        Ok(())
+    }
+
+    fn delete_utxos(&mut self, outpoints: &Vec<OutPoint>) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::delete_utxos - outpoints={outpoints:?}");
+        let mut operations = self.begin_operations();
+        for outpoint in outpoints {
+            operations.delete_item(&self.key(&KeyMapper::HeritageUtxo(Some(outpoint))));
+        }
+        self.commit_operations(operations)?;
+        Ok(())
+    }
+
+    fn list_utxos(&self) -> Result<Vec<HeritageUtxo>> {
+        log::debug!("HeritageSqliteDatabase::list_utxos");
+        let prefix = self.key(&KeyMapper::HeritageUtxo(None));
+        Ok(self.query(&prefix)?)
+    }
+
+    fn paginate_utxos(
+        &self,
+        page_size: usize,
+        continuation_token: Option<ContinuationToken>,
+    ) -> Result<Paginated<HeritageUtxo>> {
+        log::debug!("HeritageSqliteDatabase::paginate_utxos - page_size={page_size} continuation_token={continuation_token:?}");
+        let prefix = self.key(&KeyMapper::HeritageUtxo(None));
+        let (page, next_key) =
+            self.query_page(&prefix, page_size, continuation_token.map(|ct| ct.0))?;
+        Ok(Paginated {
+            page,
+            continuation_token: next_key.map(|s| ContinuationToken(s)),
+        })
+    }
+
+    fn set_utxo_label(&mut self, outpoint: &OutPoint, label: &str) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_utxo_label - outpoint={outpoint} label={label}");
+        let key = self.key(&KeyMapper::UtxoLabel(Some(outpoint)));
+        self.update_item(&key, &(outpoint, label))?;
+        Ok(())
+    }
+
+    fn delete_utxo_label(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::delete_utxo_label - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::UtxoLabel(Some(outpoint)));
+        self.delete_item::<(OutPoint, String)>(&key)?;
+        Ok(())
+    }
+
+    fn get_utxo_label(&self, outpoint: &OutPoint) -> Result<Option<String>> {
+        log::debug!("HeritageSqliteDatabase::get_utxo_label - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::UtxoLabel(Some(outpoint)));
+        Ok(self
+            .get_item::<(OutPoint, String)>(&key)?
+            .map(|(_, label)| label))
+    }
+
+    fn list_utxo_labels(&self) -> Result<Vec<(OutPoint, String)>> {
+        log::debug!("HeritageSqliteDatabase::list_utxo_labels");
+        let prefix = self.key(&KeyMapper::UtxoLabel(None));
+        Ok(self.query(&prefix)?)
+    }
+
+    fn set_label(&mut self, label_ref: &LabelRef, label: &str) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_label - label_ref={label_ref} label={label}");
+        let key = self.key(&KeyMapper::Label(Some(label_ref)));
+        self.update_item(&key, &(label_ref, label))?;
+        Ok(())
+    }
+
+    fn delete_label(&mut self, label_ref: &LabelRef) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::delete_label - label_ref={label_ref}");
+        let key = self.key(&KeyMapper::Label(Some(label_ref)));
+        self.delete_item::<(LabelRef, String)>(&key)?;
+        Ok(())
+    }
+
+    fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>> {
+        log::debug!("HeritageSqliteDatabase::get_label - label_ref={label_ref}");
+        let key = self.key(&KeyMapper::Label(Some(label_ref)));
+        Ok(self
+            .get_item::<(LabelRef, String)>(&key)?
+            .map(|(_, label)| label))
+    }
+
+    fn list_labels(&self) -> Result<Vec<(LabelRef, String)>> {
+        log::debug!("HeritageSqliteDatabase::list_labels");
+        let prefix = self.key(&KeyMapper::Label(None));
+        Ok(self.query(&prefix)?)
+    }
+    fn freeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::freeze_utxo - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::FrozenUtxo(Some(outpoint)));
+        self.update_item(&key, outpoint)?;
+        Ok(())
+    }
+
+    fn unfreeze_utxo(&mut self, outpoint: &OutPoint) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::unfreeze_utxo - outpoint={outpoint}");
+        let key = self.key(&KeyMapper::FrozenUtxo(Some(outpoint)));
+        self.delete_item::<OutPoint>(&key)?;
+        Ok(())
+    }
+
+    fn list_frozen_utxos(&self) -> Result<Vec<OutPoint>> {
+        log::debug!("HeritageSqliteDatabase::list_frozen_utxos");
+        let prefix = self.key(&KeyMapper::FrozenUtxo(None));
+        Ok(self.query(&prefix)?)
+    }
+
+    fn add_transaction_summaries(
+        &mut self,
+        transaction_summaries: &Vec<TransactionSummary>,
+    ) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::add_transaction_summaries - transaction_summaries={transaction_summaries:?}");
+        let mut operations = self.begin_operations();
+        for transaction_summary in transaction_summaries {
+            operations.update_item(
+                &self.key(&KeyMapper::TxSummary(Some((
+                    &transaction_summary.txid,
+                    transaction_summary.confirmation_time.as_ref(),
+                )))),
+                transaction_summary,
+            )?;
+        }
+        self.commit_operations(operations)?;
+        Ok(())
+    }
+
+    fn delete_transaction_summaries(
+        &mut self,
+        key_to_delete: &Vec<(Txid, Option<BlockTime>)>,
+    ) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::delete_transaction_summaries - key_to_delete={key_to_delete:?}");
+        let mut operations = self.begin_operations();
+        for (txid, confirmation_time) in key_to_delete {
+            operations.delete_item(&self.key(&KeyMapper::TxSummary(Some((
+                txid,
+                confirmation_time.as_ref(),
+            )))));
+        }
+        self.commit_operations(operations)?;
+        Ok(())
+    }
+
+    fn list_transaction_summaries(&self) -> Result<Vec<TransactionSummary>> {
+        log::debug!("HeritageSqliteDatabase::list_transaction_summaries");
+        let prefix = self.key(&KeyMapper::TxSummary(None));
+        Ok(self.query_rev(&prefix)?)
+    }
+
+    fn paginate_transaction_summaries(
+        &self,
+        page_size: usize,
+        continuation_token: Option<ContinuationToken>,
+    ) -> Result<Paginated<TransactionSummary>> {
+        log::debug!("HeritageSqliteDatabase::paginate_transaction_summaries - page_size={page_size} continuation_token={continuation_token:?}");
+        let prefix = self.key(&KeyMapper::TxSummary(None));
+        let (page, next_key) =
+            self.query_page_rev(&prefix, page_size, continuation_token.map(|ct| ct.0))?;
+        Ok(Paginated {
+            page,
+            continuation_token: next_key.map(|s| ContinuationToken(s)),
+        })
+    }
+
+    fn get_balance(&self) -> Result<Option<HeritageWalletBalance>> {
+        log::debug!("HeritageSqliteDatabase::get_balance");
+        let key = self.key(&KeyMapper::WalletBalance);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn set_balance(&mut self, new_balance: &HeritageWalletBalance) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_balance - new_balance={new_balance:?}");
+        let key = self.key(&KeyMapper::WalletBalance);
+        self.update_item(&key, new_balance)?;
+        Ok(())
+    }
+
+    fn get_fee_rate(&self) -> Result<Option<FeeRate>> {
+        log::debug!("HeritageSqliteDatabase::get_fee_rate");
+        let key = self.key(&KeyMapper::FeeRate);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn set_fee_rate(&mut self, new_fee_rate: &FeeRate) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_fee_rate - new_fee_rate={new_fee_rate:?}");
+        let key = self.key(&KeyMapper::FeeRate);
+        self.update_item(&key, new_fee_rate)?;
+        Ok(())
+    }
+
+    fn get_block_inclusion_objective(&self) -> Result<Option<BlockInclusionObjective>> {
+        log::debug!("HeritageSqliteDatabase::get_block_inclusion_objective");
+        let key = self.key(&KeyMapper::BlockInclusionObjective);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn set_block_inclusion_objective(
+        &mut self,
+        new_objective: BlockInclusionObjective,
+    ) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_block_inclusion_objective - new_objective={new_objective:?}");
+        let key = self.key(&KeyMapper::BlockInclusionObjective);
+        self.update_item(&key, &new_objective)?;
+        Ok(())
+    }
+
+    fn get_gap_limit(&self) -> Result<Option<GapLimit>> {
+        log::debug!("HeritageSqliteDatabase::get_gap_limit");
+        let key = self.key(&KeyMapper::GapLimit);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn set_gap_limit(&mut self, new_gap_limit: GapLimit) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_gap_limit - new_gap_limit={new_gap_limit:?}");
+        let key = self.key(&KeyMapper::GapLimit);
+        self.update_item(&key, &new_gap_limit)?;
+        Ok(())
+    }
+
+    fn get_heir_spending_quotas(&self) -> Result<Option<HeirSpendingQuotas>> {
+        log::debug!("HeritageSqliteDatabase::get_heir_spending_quotas");
+        let key = self.key(&KeyMapper::HeirSpendingQuotas);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn set_heir_spending_quotas(&mut self, new_quotas: &HeirSpendingQuotas) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_heir_spending_quotas - new_quotas={new_quotas:?}");
+        let key = self.key(&KeyMapper::HeirSpendingQuotas);
+        self.update_item(&key, new_quotas)?;
+        Ok(())
+    }
+
+    fn get_network(&self) -> Result<Option<Network>> {
+        log::debug!("HeritageSqliteDatabase::get_network");
+        let key = self.key(&KeyMapper::Network);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn set_network(&mut self, network: Network) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::set_network - network={network:?}");
+        let key = self.key(&KeyMapper::Network);
+        self.update_item(&key, &network)?;
+        Ok(())
+    }
+
+    fn get_pending_operation(&self) -> Result<Option<PendingOperation>> {
+        log::debug!("HeritageSqliteDatabase::get_pending_operation");
+        let key = self.key(&KeyMapper::PendingOperation);
+        Ok(self.get_item(&key)?)
+    }
+
+    fn set_pending_operation(&mut self, pending_operation: &PendingOperation) -> Result<()> {
+        log::debug!(
+            "HeritageSqliteDatabase::set_pending_operation - pending_operation={pending_operation:?}"
+        );
+        let key = self.key(&KeyMapper::PendingOperation);
+        self.update_item(&key, pending_operation)?;
+        Ok(())
+    }
+
+    fn delete_pending_operation(&mut self) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::delete_pending_operation");
+        let key = self.key(&KeyMapper::PendingOperation);
+        self.delete_item::<PendingOperation>(&key)?;
+        Ok(())
+    }
+
+    fn add_heir_rotation(&mut self, heir_rotation: &HeirRotation) -> Result<()> {
+        log::debug!("HeritageSqliteDatabase::add_heir_rotation - heir_rotation={heir_rotation:?}");
+        let key = self.key(&KeyMapper::HeirRotation(Some(heir_rotation)));
+        self.update_item(&key, heir_rotation)?;
+        Ok(())
+    }
+
+    fn list_heir_rotations(&self) -> Result<Vec<HeirRotation>> {
+        log::debug!("HeritageSqliteDatabase::list_heir_rotations");
+        let prefix = self.key(&KeyMapper::HeirRotation(None));
+        Ok(self.query(&prefix)?)
+    }
+}
@@ -0,0 +1,584 @@
+use core::fmt::Debug;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use ::bdk::{BlockTime, KeychainKind};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    account_xpub::AccountXPubId,
+    bitcoin::{OutPoint, Script, Txid},
+    errors::DatabaseError,
+    heritage_wallet::{heir_rotation::HeirRotation, LabelRef, SubwalletConfigId},
+};
+
+use super::{PartitionableDatabase, Result, SubdatabaseId};
+
+mod bdk;
+mod heritage;
+
+pub use bdk::HeritageSqliteDatabaseBatch;
+pub use heritage::HeritageSqliteDatabaseTransac;
+
+const CREATE_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS heritage (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+) WITHOUT ROWID";
+
+enum KeyMapper<'a> {
+    // HeritageWallet DB related
+    SubwalletConfig(Option<SubwalletConfigId>),
+    UnusedAccountXPub(Option<AccountXPubId>),
+    HeritageUtxo(Option<&'a OutPoint>),
+    UtxoLabel(Option<&'a OutPoint>),
+    Label(Option<&'a LabelRef>),
+    FrozenUtxo(Option<&'a OutPoint>),
+    TxSummary(Option<(&'a Txid, Option<&'a BlockTime>)>),
+    WalletBalance,
+    FeeRate,
+    BlockInclusionObjective,
+    GapLimit,
+    HeirSpendingQuotas,
+    Network,
+    PendingOperation,
+    HeirRotation(Option<&'a HeirRotation>),
+    // bdk::Wallet DB related
+    SyncTime,
+    Path((Option<KeychainKind>, Option<u32>)),
+    Script(Option<&'a Script>),
+    Utxo(Option<&'a OutPoint>),
+    RawTx(Option<&'a Txid>),
+    Transaction(Option<&'a Txid>),
+    LastIndex(KeychainKind),
+    DescriptorChecksum(KeychainKind),
+}
+
+impl KeyMapper<'_> {
+    fn pk(&self) -> &str {
+        match *self {
+            // HeritageWallet DB related
+            KeyMapper::SubwalletConfig(_) => "w",
+            KeyMapper::UnusedAccountXPub(_) => "x",
+            KeyMapper::HeritageUtxo(_) => "h",
+            KeyMapper::UtxoLabel(_) => "n",
+            KeyMapper::Label(_) => "m",
+            KeyMapper::FrozenUtxo(_) => "z",
+            KeyMapper::TxSummary(_) => "y",
+            KeyMapper::WalletBalance => "b",
+            KeyMapper::FeeRate => "f",
+            KeyMapper::BlockInclusionObjective => "o",
+            KeyMapper::GapLimit => "g",
+            KeyMapper::HeirSpendingQuotas => "q",
+            KeyMapper::Network => "e",
+            KeyMapper::PendingOperation => "j",
+            KeyMapper::HeirRotation(_) => "k",
+            // bdk::Wallet DB related
+            KeyMapper::Path(_) => "p",
+            KeyMapper::Script(_) => "s",
+            KeyMapper::Utxo(_) => "u",
+            KeyMapper::RawTx(_) => "r",
+            KeyMapper::Transaction(_) => "t",
+            KeyMapper::LastIndex(_) => "i",
+            KeyMapper::SyncTime => "l",
+            KeyMapper::DescriptorChecksum(_) => "d",
+        }
+    }
+
+    fn sk(&self) -> String {
+        match *self {
+            // HeritageWallet DB related
+            KeyMapper::SubwalletConfig(Some(SubwalletConfigId::Current)) => "c".to_owned(),
+            KeyMapper::SubwalletConfig(Some(SubwalletConfigId::Id(id))) => {
+                // Same trick as the other implementations: "a" is before "c"
+                // so the obsolete configs can be queried with a prefix
+                format!("a{:0>10}", id)
+            }
+            KeyMapper::UnusedAccountXPub(Some(id)) => {
+                format!("{:0>10}", id)
+            }
+            KeyMapper::HeritageUtxo(Some(op))
+            | KeyMapper::UtxoLabel(Some(op))
+            | KeyMapper::FrozenUtxo(Some(op)) => op.to_string(),
+            KeyMapper::Label(Some(label_ref)) => label_ref.to_string(),
+            KeyMapper::HeirRotation(Some(heir_rotation)) => format!(
+                "{:0>20}#{}",
+                heir_rotation.timestamp,
+                heir_rotation.new_heir_config.fingerprint()
+            ),
+            KeyMapper::TxSummary(Some((txid, confirmation_time))) => format!(
+                "{:0>10}#{}",
+                confirmation_time
+                    .as_ref()
+                    .map(|bt| bt.height)
+                    .unwrap_or(u32::MAX),
+                txid.to_string()
+            ),
+            // bdk::Wallet DB related
+            KeyMapper::Path((Some(kk), Some(idx))) => {
+                format!("{}#{idx:0>10}", kk.as_byte() as char)
+            }
+            KeyMapper::Path((Some(kk), None)) => {
+                format!("{}#", kk.as_byte() as char)
+            }
+            KeyMapper::Script(Some(s)) => s.script_hash().to_string(),
+            KeyMapper::Utxo(Some(op)) => op.to_string(),
+            KeyMapper::RawTx(Some(txid)) | KeyMapper::Transaction(Some(txid)) => txid.to_string(),
+            KeyMapper::LastIndex(kk) | KeyMapper::DescriptorChecksum(kk) => {
+                (kk.as_byte() as char).to_string()
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn key(&self, prefix: &str) -> String {
+        let pk = self.pk();
+        let sk = self.sk();
+        format!("{prefix}#{pk}#{sk}")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+enum SqliteError {
+    #[error("The key {0} is already in the database")]
+    KeyAlreadyExists(String),
+    #[error("The key {0} did not have the expected value")]
+    CompareAndSwapError(String),
+    #[error("The database transaction could not be completed, operation #{idx} failed: {reason}")]
+    TransactionFailed { idx: usize, reason: String },
+    #[error("Could not serialize for key {key}: {error}")]
+    SerDeError { key: String, error: String },
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+}
+impl SqliteError {
+    fn serde(k: impl Into<String>, e: serde_json::Error) -> Self {
+        Self::SerDeError {
+            key: k.into(),
+            error: e.to_string(),
+        }
+    }
+}
+impl From<SqliteError> for DatabaseError {
+    fn from(value: SqliteError) -> Self {
+        DatabaseError::Generic(value.to_string())
+    }
+}
+impl From<SqliteError> for ::bdk::Error {
+    fn from(value: SqliteError) -> Self {
+        log::error!("{value:?}");
+        ::bdk::Error::Generic(value.to_string())
+    }
+}
+
+fn to_json<T: Serialize>(key: &str, item: &T) -> core::result::Result<String, SqliteError> {
+    serde_json::to_string(item).map_err(|e| SqliteError::serde(key, e))
+}
+
+fn from_json<T: DeserializeOwned>(key: &str, value: &str) -> core::result::Result<T, SqliteError> {
+    serde_json::from_str(value).map_err(|e| SqliteError::serde(key, e))
+}
+
+enum SqliteOperation {
+    Update(String, String),
+    Delete(String),
+    CompareAndSwap {
+        key: String,
+        old_value: Option<String>,
+        new_value: Option<String>,
+    },
+}
+impl Debug for SqliteOperation {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Update(key, _) => f.debug_tuple("Update").field(key).finish(),
+            Self::Delete(key) => f.debug_tuple("Delete").field(key).finish(),
+            Self::CompareAndSwap { key, .. } => f.debug_tuple("CompareAndSwap").field(key).finish(),
+        }
+    }
+}
+
+/// A list of operations that will be executed in a single SQL transaction
+#[derive(Debug, Default)]
+struct SqliteOperations(Vec<SqliteOperation>);
+impl SqliteOperations {
+    fn put_item<T: Serialize>(
+        &mut self,
+        key: &str,
+        item: &T,
+    ) -> core::result::Result<(), SqliteError> {
+        self.0.push(SqliteOperation::CompareAndSwap {
+            key: key.to_owned(),
+            old_value: None,
+            new_value: Some(to_json(key, item)?),
+        });
+        Ok(())
+    }
+
+    fn update_item<T: Serialize>(
+        &mut self,
+        key: &str,
+        item: &T,
+    ) -> core::result::Result<(), SqliteError> {
+        self.0
+            .push(SqliteOperation::Update(key.to_owned(), to_json(key, item)?));
+        Ok(())
+    }
+
+    fn delete_item(&mut self, key: &str) {
+        self.0.push(SqliteOperation::Delete(key.to_owned()));
+    }
+
+    fn compare_and_swap<T: Serialize>(
+        &mut self,
+        key: &str,
+        old_value: Option<&T>,
+        new_value: Option<&T>,
+    ) -> core::result::Result<(), SqliteError> {
+        let old_value = old_value.map(|v| to_json(key, v)).transpose()?;
+        let new_value = new_value.map(|v| to_json(key, v)).transpose()?;
+        self.0.push(SqliteOperation::CompareAndSwap {
+            key: key.to_owned(),
+            old_value,
+            new_value,
+        });
+        Ok(())
+    }
+}
+
+/// A persistent [HeritageDatabase](super::HeritageDatabase) storing everything
+/// in a single SQLite file.
+///
+/// Items are stored as JSON in a key/value table so the file can be inspected
+/// with the usual SQLite tools. Subdatabases share the same connection and table,
+/// each one using its own key prefix.
+#[derive(Debug, Clone)]
+pub struct HeritageSqliteDatabase {
+    conn: Arc<Mutex<Connection>>,
+    prefix: String,
+}
+
+impl HeritageSqliteDatabase {
+    /// Open the SQLite database at `path`, creating it if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        log::debug!("HeritageSqliteDatabase::open - path={}", path.display());
+        let conn = Connection::open(path).map_err(|e| {
+            DatabaseError::Generic(format!("Cannot open database at {}: {e}", path.display()))
+        })?;
+        Self::from_connection(conn)
+    }
+
+    /// Create a new SQLite database that only lives in memory
+    pub fn open_in_memory() -> Result<Self> {
+        log::debug!("HeritageSqliteDatabase::open_in_memory");
+        let conn = Connection::open_in_memory().map_err(SqliteError::from)?;
+        Self::from_connection(conn)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute(CREATE_TABLE, []).map_err(SqliteError::from)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            prefix: String::new(),
+        })
+    }
+
+    fn key(&self, km: &KeyMapper) -> String {
+        km.key(&self.prefix)
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn
+            .lock()
+            .expect("the SQLite connection mutex is never poisoned")
+    }
+
+    fn begin_operations(&self) -> SqliteOperations {
+        SqliteOperations::default()
+    }
+
+    fn commit_operations(
+        &self,
+        operations: SqliteOperations,
+    ) -> core::result::Result<(), SqliteError> {
+        log::debug!(
+            "HeritageSqliteDatabase::commit_operations - {} ops",
+            operations.0.len()
+        );
+        let mut conn = self.conn();
+        // The transaction is rolled back when dropped without a commit
+        let txn = conn.transaction()?;
+        for (idx, op) in operations.0.into_iter().enumerate() {
+            let res = match &op {
+                SqliteOperation::Update(key, value) => Self::_update(&txn, key, value),
+                SqliteOperation::Delete(key) => Self::_delete(&txn, key).map(|_| ()),
+                SqliteOperation::CompareAndSwap {
+                    key,
+                    old_value,
+                    new_value,
+                } => Self::_compare_and_swap(&txn, key, old_value.as_deref(), new_value.as_deref()),
+            };
+            if let Err(e) = res {
+                log::error!("Operation {op:?} => {e}");
+                return Err(SqliteError::TransactionFailed {
+                    idx,
+                    reason: e.to_string(),
+                });
+            }
+        }
+        txn.commit()?;
+        Ok(())
+    }
+
+    fn get_item<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> core::result::Result<Option<T>, SqliteError> {
+        Self::_get(&self.conn(), key)?
+            .map(|value| from_json(key, &value))
+            .transpose()
+    }
+
+    fn put_item<T: Serialize>(&self, key: &str, item: &T) -> core::result::Result<(), SqliteError> {
+        let value = to_json(key, item)?;
+        Self::_compare_and_swap(&self.conn(), key, None, Some(&value)).map_err(|e| match e {
+            SqliteError::CompareAndSwapError(key) => SqliteError::KeyAlreadyExists(key),
+            _ => e,
+        })
+    }
+
+    fn update_item<T: Serialize>(
+        &self,
+        key: &str,
+        item: &T,
+    ) -> core::result::Result<(), SqliteError> {
+        let value = to_json(key, item)?;
+        Self::_update(&self.conn(), key, &value)
+    }
+
+    fn delete_item<T: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> core::result::Result<Option<T>, SqliteError> {
+        Self::_delete(&self.conn(), key)?
+            .map(|value| from_json(key, &value))
+            .transpose()
+    }
+
+    fn compare_and_swap<T: Serialize>(
+        &self,
+        key: &str,
+        old_value: Option<&T>,
+        new_value: Option<&T>,
+    ) -> core::result::Result<(), SqliteError> {
+        let old_value = old_value.map(|v| to_json(key, v)).transpose()?;
+        let new_value = new_value.map(|v| to_json(key, v)).transpose()?;
+        let mut conn = self.conn();
+        let txn = conn.transaction()?;
+        Self::_compare_and_swap(&txn, key, old_value.as_deref(), new_value.as_deref())?;
+        txn.commit()?;
+        Ok(())
+    }
+
+    /// Returns all the objects in the DB whose key begin with `prefix`
+    fn query<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+    ) -> core::result::Result<Vec<T>, SqliteError> {
+        self._query_inner(prefix, None, None, true).map(|(r, _)| r)
+    }
+
+    /// Like [Self::query] but the DB is tranversed in reverse order
+    fn query_rev<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+    ) -> core::result::Result<Vec<T>, SqliteError> {
+        self._query_inner(prefix, None, None, false).map(|(r, _)| r)
+    }
+
+    /// Returns a page of size `page_size` of the objects in the DB whose key begin with `prefix`,
+    /// with an optional String that represent the next key if it exist.
+    fn query_page<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+        page_size: usize,
+        start_key: Option<String>,
+    ) -> core::result::Result<(Vec<T>, Option<String>), SqliteError> {
+        self._query_inner(prefix, Some(page_size), start_key, true)
+    }
+
+    /// Like [Self::query_page] but the DB is tranversed in reverse order
+    fn query_page_rev<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+        page_size: usize,
+        start_key: Option<String>,
+    ) -> core::result::Result<(Vec<T>, Option<String>), SqliteError> {
+        self._query_inner(prefix, Some(page_size), start_key, false)
+    }
+
+    fn _query_inner<T: DeserializeOwned>(
+        &self,
+        prefix: &str,
+        page_size: Option<usize>,
+        start_key: Option<String>,
+        scan_forward: bool,
+    ) -> core::result::Result<(Vec<T>, Option<String>), SqliteError> {
+        // Keys are compared bytewise, no key with the prefix can be above this one
+        let mut upper_bound = prefix.to_owned();
+        upper_bound.push(char::MAX);
+
+        let (lower_bound, upper_bound) = match (&start_key, scan_forward) {
+            (Some(start_key), true) => (start_key.as_str(), upper_bound.as_str()),
+            (Some(start_key), false) => (prefix, start_key.as_str()),
+            (None, _) => (prefix, upper_bound.as_str()),
+        };
+        let order = if scan_forward { "ASC" } else { "DESC" };
+        // A negative LIMIT means no limit for SQLite
+        let limit = page_size.map(|ps| ps as i64 + 1).unwrap_or(-1);
+
+        let conn = self.conn();
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT key, value FROM heritage WHERE key >= ?1 AND key <= ?2 ORDER BY key {order} LIMIT ?3"
+        ))?;
+        let mut page = stmt
+            .query_map(params![lower_bound, upper_bound, limit], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .map(|row| {
+                let (key, value) = row?;
+                let item = from_json(&key, &value)?;
+                Ok((key, item))
+            })
+            .collect::<core::result::Result<Vec<(String, T)>, SqliteError>>()?;
+
+        let next_key = if page_size.is_some_and(|page_size| page.len() > page_size) {
+            Some(page.pop().unwrap().0)
+        } else {
+            None
+        };
+        Ok((page.into_iter().map(|(_, t)| t).collect(), next_key))
+    }
+
+    fn _get(conn: &Connection, key: &str) -> core::result::Result<Option<String>, SqliteError> {
+        Ok(conn
+            .prepare_cached("SELECT value FROM heritage WHERE key = ?1")?
+            .query_row([key], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn _update(conn: &Connection, key: &str, value: &str) -> core::result::Result<(), SqliteError> {
+        conn.prepare_cached(
+            "INSERT INTO heritage (key, value) VALUES (?1, ?2) \
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+        )?
+        .execute([key, value])?;
+        Ok(())
+    }
+
+    fn _delete(conn: &Connection, key: &str) -> core::result::Result<Option<String>, SqliteError> {
+        Ok(conn
+            .prepare_cached("DELETE FROM heritage WHERE key = ?1 RETURNING value")?
+            .query_row([key], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn _compare_and_swap(
+        conn: &Connection,
+        key: &str,
+        old_value: Option<&str>,
+        new_value: Option<&str>,
+    ) -> core::result::Result<(), SqliteError> {
+        if Self::_get(conn, key)?.as_deref() == old_value {
+            if let Some(v) = new_value {
+                Self::_update(conn, key, v)?;
+            } else {
+                Self::_delete(conn, key)?;
+            }
+            Ok(())
+        } else {
+            Err(SqliteError::CompareAndSwapError(key.to_owned()))
+        }
+    }
+}
+
+impl PartitionableDatabase for HeritageSqliteDatabase {
+    type SubDatabase = Self;
+
+    fn get_subdatabase(&self, subdatabase_id: SubdatabaseId) -> Result<Self::SubDatabase> {
+        Ok(HeritageSqliteDatabase {
+            conn: Arc::clone(&self.conn),
+            prefix: subdatabase_id.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeritageSqliteDatabase, PartitionableDatabase, SubdatabaseId};
+
+    macro_rules! impl_heritage_test {
+        ($tn: tt) => {
+            #[test]
+            fn $tn() {
+                crate::database::tests::$tn(HeritageSqliteDatabase::open_in_memory().unwrap())
+            }
+        };
+    }
+
+    impl_heritage_test!(get_put_subwallet_config);
+    impl_heritage_test!(get_subdatabase);
+    impl_heritage_test!(get_set_balance);
+    impl_heritage_test!(get_set_fee_rate);
+    impl_heritage_test!(get_set_block_inclusion_objective);
+    impl_heritage_test!(get_set_gap_limit);
+    impl_heritage_test!(get_set_heir_spending_quotas);
+    impl_heritage_test!(get_set_network);
+    impl_heritage_test!(heir_rotations_management);
+    impl_heritage_test!(list_obsolete_subwallet_configs);
+    impl_heritage_test!(pending_operation_management);
+    impl_heritage_test!(safe_update_current_subwallet_config);
+    impl_heritage_test!(transaction);
+    impl_heritage_test!(unused_account_xpub_management);
+    impl_heritage_test!(heritage_utxo_management);
+    impl_heritage_test!(utxo_labels_management);
+    impl_heritage_test!(labels_management);
+    impl_heritage_test!(frozen_utxos_management);
+    impl_heritage_test!(transaction_summaries_management);
+    impl_heritage_test!(verify_repair_integrity);
+
+    macro_rules! impl_bdk_test {
+        ($tn: tt) => {
+            #[test]
+            fn $tn() {
+                let heritage_db = HeritageSqliteDatabase::open_in_memory().unwrap();
+                let subdb_index = SubdatabaseId::from("sub");
+                crate::database::bdk_tests::$tn(heritage_db.get_subdatabase(subdb_index).unwrap())
+            }
+        };
+    }
+
+    impl_bdk_test!(test_script_pubkey);
+    impl_bdk_test!(test_batch_script_pubkey);
+    impl_bdk_test!(test_iter_script_pubkey);
+    impl_bdk_test!(test_del_script_pubkey);
+    impl_bdk_test!(test_utxo);
+    impl_bdk_test!(test_raw_tx);
+    impl_bdk_test!(test_batch_raw_tx);
+    impl_bdk_test!(test_tx);
+    impl_bdk_test!(test_batch_tx);
+    impl_bdk_test!(test_list_transaction);
+    impl_bdk_test!(test_last_index);
+    impl_bdk_test!(test_sync_time);
+    impl_bdk_test!(test_iter_raw_txs);
+    impl_bdk_test!(test_del_path_from_script_pubkey);
+    impl_bdk_test!(test_iter_script_pubkeys);
+    impl_bdk_test!(test_del_utxo);
+    impl_bdk_test!(test_del_raw_tx);
+    impl_bdk_test!(test_del_tx);
+    impl_bdk_test!(test_del_last_index);
+    impl_bdk_test!(test_check_descriptor_checksum);
+}
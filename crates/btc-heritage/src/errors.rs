@@ -2,8 +2,9 @@ use thiserror::Error;
 
 use crate::{
     account_xpub::AccountXPubId,
-    bitcoin::{psbt::Psbt, Network},
+    bitcoin::{absolute::LockTime, psbt::Psbt, FeeRate, Network, Sequence, Txid},
     heritage_wallet::SubwalletConfigId,
+    utils::{describe_lock_time, format_timestamp},
 };
 
 pub type Result<T> = core::result::Result<T, Error>;
@@ -14,14 +15,30 @@ pub enum Error {
     InvalidWalletAddressString(String),
     #[error("{0} is not a valid Bitcoin address for the expected network ({1})")]
     InvalidAddressString(String, Network),
+    #[error("{0} is not a valid Bitcoin address")]
+    InvalidAddress(String),
+    #[error("The wallet belongs to the {found} network, not to the {expected} network")]
+    NetworkMismatch { expected: Network, found: Network },
+    #[error("The database does not record the network of the wallet, it must be opened with a known network")]
+    UnknownNetwork,
     #[error("Psbt is not finalizable: {}", serde_json::json!(.0))]
     UnfinalizablePsbt(Psbt),
+    #[error("Psbt inputs cannot be finalized: {}", .0.iter().map(|(index, reason)| format!("input #{index}: {reason}")).collect::<Vec<_>>().join(", "))]
+    UnfinalizablePsbtInputs(Vec<(usize, String)>),
+    #[error("The time locks of the heir transaction are inconsistent: {}", .0.iter().map(|issue| issue.to_string()).collect::<Vec<_>>().join(", "))]
+    InconsistentTimeLocks(Vec<TimeLockIssue>),
     #[error("Trying to call SubwalletConfig::mark_subwallet_firstuse on an already used SubwalletConfig")]
     SubwalletConfigAlreadyMarkedUsed,
     #[error("Trying to set a new HeritageConfig that was already used in this HeritageWallet")]
     HeritageConfigAlreadyUsed,
-    #[error("Heirs can only spend by draining the wallet")]
+    #[error("Heirs must drain the eligible UTXOs to at least one recipient")]
     InvalidSpendingConfigForHeir,
+    #[error("Invalid SpendingConfig: {0}")]
+    InvalidSpendingConfig(&'static str),
+    #[error("The spending quota allows the heir to send at most {max_amount} out of the wallet, the rest must be retained")]
+    HeirSpendingQuotaExceeded { max_amount: crate::bitcoin::Amount },
+    #[error("A spending quota must be between 1 and 100 percent, not {0}")]
+    InvalidSpendingQuota(u8),
     #[error("HeritageWallet does not have a Current SubwalletConfig")]
     MissingCurrentSubwalletConfig,
     #[error("HeritageWallet was never synchronized")]
@@ -32,12 +49,40 @@ pub enum Error {
     InvalidAccountXPub,
     #[error("HeritageConfig is not the expected version: {0}")]
     InvalidHeritageConfigVersion(&'static str),
+    #[error("Invalid HeritageConfig: {0}")]
+    InvalidHeritageConfig(&'static str),
     #[error("{0} cannot be parsed into an HeritageConfigVersion")]
     InvalidHeritageConfigString(String),
     #[error("Invalid DescriptorPublicKey for AccountXPub: {0}")]
     InvalidDescriptorPublicKey(&'static str),
+    #[error("Invalid multisig heir: {0}")]
+    InvalidMultisigHeir(&'static str),
+    #[error("Invalid owner quorum: {0}")]
+    InvalidOwnerQuorum(&'static str),
     #[error("Invalid backup: {0}")]
     InvalidBackup(&'static str),
+    #[error("Invalid migration source: {0}")]
+    InvalidMigrationSource(String),
+    #[error("Invalid continuation token: {0}")]
+    InvalidContinuationToken(String),
+    #[error("Generating {requested} new addresses would exceed the gap limit of {gap_limit} ({unused} unused addresses already)")]
+    GapLimitExceeded {
+        requested: usize,
+        unused: u32,
+        gap_limit: u32,
+    },
+    #[error("Invalid BIP-329 label at line {line}: {reason}")]
+    InvalidBip329Label { line: usize, reason: String },
+    #[error("Invalid export format: {0} (expected csv or json)")]
+    InvalidExportFormat(String),
+    #[error("Invalid payee list: {0}")]
+    InvalidPayeeList(String),
+    #[error("Invalid payee #{entry}: {reason}")]
+    InvalidPayee { entry: usize, reason: String },
+    #[error("Backup encryption error: {0}")]
+    BackupEncryptionError(String),
+    #[error("Failed to decrypt the backup, the passphrase is most likely wrong")]
+    BackupDecryptionFailed,
     #[error("Invalid script fragments to recompose {0} Heritage Config")]
     InvalidScriptFragments(&'static str),
     #[error("Database error: {0}")]
@@ -52,10 +97,44 @@ pub enum Error {
     InvalidUtxoSelectionIncludeExclude(Vec<crate::bitcoin::OutPoint>),
     #[error("Some UTXOs were requested to include that do not exist: {0:?}")]
     UnknownUtxoSelectionInclude(Vec<crate::bitcoin::OutPoint>),
+    #[error("Some UTXOs were requested to include that are frozen: {0:?}")]
+    FrozenUtxoSelectionInclude(Vec<crate::bitcoin::OutPoint>),
+    #[error("Transaction {0} is unknown to the wallet")]
+    UnknownTransaction(Txid),
+    #[error("Transaction {0} is already confirmed and cannot be replaced")]
+    TransactionAlreadyConfirmed(Txid),
+    #[error("The replacement fee rate ({new:?}) must be higher than the original fee rate ({original:?})")]
+    InsufficientReplacementFeeRate { original: FeeRate, new: FeeRate },
+    #[error("The replacement fee ({fee}) must be at least the original fee ({original_fee}) plus the incremental relay fee of the replacement, {min_fee} in total (BIP-125)")]
+    InsufficientReplacementFee {
+        original_fee: crate::bitcoin::Amount,
+        min_fee: crate::bitcoin::Amount,
+        fee: crate::bitcoin::Amount,
+    },
+    #[error("The transaction does not have enough change to pay for the replacement fee")]
+    InsufficientFundsForReplacement,
+    #[error("The current fee rate ({fee_rate:?}) is above the maximum fee rate allowed for a consolidation ({max_fee_rate:?})")]
+    ConsolidationFeeRateTooHigh {
+        fee_rate: FeeRate,
+        max_fee_rate: FeeRate,
+    },
+    #[error("The consolidation fee ({fee}) would exceed the maximum allowed ({max_fee})")]
+    ConsolidationFeeTooHigh {
+        fee: crate::bitcoin::Amount,
+        max_fee: crate::bitcoin::Amount,
+    },
     #[error("Error while interacting with the Blockchain provider: {0}")]
     BlockchainProviderError(String),
+    #[error("The key-path-only change mode can only be changed along with the HeritageConfig")]
+    KeypathChangeRequiresNewHeritageConfig,
+    #[error("The key-path-only change mode is not available when the owner is a quorum of keys")]
+    KeypathChangeWithOwnerQuorum,
+    #[error("No fee rate is known to the wallet, synchronize the fee rate or provide a FeePolicy")]
+    MissingFeeRate,
     #[error("Error during subwallet synchronization: {0}")]
     SyncError(String),
+    #[error("Transaction rejected by the Bitcoin network: {0}")]
+    BroadcastError(#[from] BroadcastError),
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
@@ -71,3 +150,105 @@ pub enum DatabaseError {
     #[error("Generic database error: {0}")]
     Generic(String),
 }
+
+/// An inconsistency between the time locks of an heir transaction and the spend conditions
+/// committed in the scripts of its inputs, see [crate::utils::check_heir_time_locks]
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum TimeLockIssue {
+    #[error("input #{input}: the lock time ({}) is before the maturity of the heritage ({})", describe_lock_time(.lock_time), format_timestamp(*.maturity as u64))]
+    LockTimeBeforeMaturity {
+        input: usize,
+        lock_time: LockTime,
+        maturity: u32,
+    },
+    #[error("input #{input}: the sequence is {:#x} instead of {:#x}, the relative lock of the heritage is not the expected {} blocks", .found.to_consensus_u32(), .expected.to_consensus_u32(), .expected.to_consensus_u32())]
+    SequenceMismatch {
+        input: usize,
+        expected: Sequence,
+        found: Sequence,
+    },
+    #[error("the transaction version is {0}, relative locks require version 2")]
+    VersionTooLow(i32),
+    #[error("the transaction cannot be mined before {}, more than {max_non_final_days} day(s) from now ({})", describe_lock_time(.lock_time), format_timestamp(*.now))]
+    NonFinal {
+        lock_time: LockTime,
+        now: u64,
+        max_non_final_days: u16,
+    },
+}
+
+/// The reason why a transaction was rejected when broadcasting it
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum BroadcastError {
+    #[error("the transaction is not final yet, its timelocks are not satisfied")]
+    NonFinal,
+    #[error("some inputs of the transaction are missing or already spent")]
+    MissingInputs,
+    #[error("the transaction fee is below the minimum relay fee")]
+    InsufficientFee,
+    #[error("the transaction has too many unconfirmed ancestors or descendants in the mempool")]
+    TooLongMempoolChain,
+    #[error("{0}")]
+    Other(String),
+}
+
+impl BroadcastError {
+    /// Map the rejection message of a node (Bitcoin Core RPC, Electrum or Esplora all relay the
+    /// Bitcoin Core reject reason) to a [BroadcastError]
+    pub fn from_reject_message(message: &str) -> Self {
+        let lowercase_message = message.to_lowercase();
+        if lowercase_message.contains("non-final") || lowercase_message.contains("non-bip68-final")
+        {
+            BroadcastError::NonFinal
+        } else if lowercase_message.contains("missingorspent")
+            || lowercase_message.contains("missing-inputs")
+            || lowercase_message.contains("missing inputs")
+        {
+            BroadcastError::MissingInputs
+        } else if lowercase_message.contains("min relay fee not met")
+            || lowercase_message.contains("mempool min fee not met")
+            || lowercase_message.contains("insufficient fee")
+        {
+            BroadcastError::InsufficientFee
+        } else if lowercase_message.contains("too-long-mempool-chain") {
+            BroadcastError::TooLongMempoolChain
+        } else {
+            BroadcastError::Other(message.to_owned())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broadcast_error_from_reject_message() {
+        assert_eq!(
+            BroadcastError::from_reject_message("non-final (code 64)"),
+            BroadcastError::NonFinal
+        );
+        assert_eq!(
+            BroadcastError::from_reject_message("non-BIP68-final"),
+            BroadcastError::NonFinal
+        );
+        assert_eq!(
+            BroadcastError::from_reject_message("bad-txns-inputs-missingorspent"),
+            BroadcastError::MissingInputs
+        );
+        assert_eq!(
+            BroadcastError::from_reject_message(
+                "sendrawtransaction RPC error: {\"code\":-26,\"message\":\"min relay fee not met, 100 < 141\"}"
+            ),
+            BroadcastError::InsufficientFee
+        );
+        assert_eq!(
+            BroadcastError::from_reject_message("too-long-mempool-chain, too many descendants"),
+            BroadcastError::TooLongMempoolChain
+        );
+        assert_eq!(
+            BroadcastError::from_reject_message("scriptpubkey"),
+            BroadcastError::Other("scriptpubkey".to_owned())
+        );
+    }
+}
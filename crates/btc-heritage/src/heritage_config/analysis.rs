@@ -0,0 +1,360 @@
+//! Linting of an [HeritageConfig]: detection of the setups that are valid but risky
+//! for the owner or the heirs, see [HeritageConfig::analyze].
+
+use core::fmt::Display;
+
+use serde::Serialize;
+
+use super::{HeritageConfig, HeritageExplorerTrait};
+use crate::bitcoin::bip32::Fingerprint;
+
+const SEC_IN_A_DAY: u64 = 24 * 60 * 60;
+
+/// Below this number of days, the owner has to renew the [HeritageConfig] more often
+/// than most people reliably do
+pub const RECOMMENDED_MIN_TIME_LOCK_DAYS: u16 = 180;
+
+/// Beyond this depth in the TapTree, the script path of an heir gets noticeably more expensive
+pub const MAX_RECOMMENDED_TREE_DEPTH: u8 = 3;
+
+/// How serious a [ConfigFinding] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingSeverity {
+    /// Worth knowing, but not a problem by itself
+    Info,
+    /// Probably not what the owner wants
+    Warning,
+    /// Defeats the purpose of the [HeritageConfig]
+    Critical,
+}
+
+impl Display for FindingSeverity {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            FindingSeverity::Info => "INFO",
+            FindingSeverity::Warning => "WARNING",
+            FindingSeverity::Critical => "CRITICAL",
+        })
+    }
+}
+
+/// A risky characteristic of an [HeritageConfig]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConfigIssue {
+    /// The first heir can spend after fewer than [RECOMMENDED_MIN_TIME_LOCK_DAYS],
+    /// which is the delay within which the owner must renew the [HeritageConfig]
+    ShortTimeLock {
+        heir_fingerprint: Fingerprint,
+        time_lock_days: u16,
+    },
+    /// The absolute lock of an heir is already in the past: the heir can spend any UTXO
+    /// as soon as its relative lock is satisfied
+    LockTimeInPast {
+        heir_fingerprint: Fingerprint,
+        spendable_timestamp: u64,
+    },
+    /// An heir can spend less than a minimum lock time after the previous one, leaving
+    /// the previous heir almost no time to claim the funds alone
+    OverlappingHeirWindows {
+        heir_fingerprint: Fingerprint,
+        next_heir_fingerprint: Fingerprint,
+        exclusive_window_days: u16,
+    },
+    /// The heir script is deeper than [MAX_RECOMMENDED_TREE_DEPTH] in the TapTree,
+    /// increasing the transaction fee the heir will have to pay
+    DeepTapTree {
+        heir_fingerprint: Fingerprint,
+        tree_depth: u8,
+    },
+    /// The heir has a key with the same [Fingerprint] as the owner: the owner seed
+    /// is probably used for the heir, which makes the heir useless
+    HeirSharesOwnerFingerprint { heir_fingerprint: Fingerprint },
+}
+
+impl ConfigIssue {
+    pub fn severity(&self) -> FindingSeverity {
+        match self {
+            ConfigIssue::ShortTimeLock { .. } | ConfigIssue::OverlappingHeirWindows { .. } => {
+                FindingSeverity::Warning
+            }
+            ConfigIssue::LockTimeInPast { .. } | ConfigIssue::HeirSharesOwnerFingerprint { .. } => {
+                FindingSeverity::Critical
+            }
+            ConfigIssue::DeepTapTree { .. } => FindingSeverity::Info,
+        }
+    }
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ConfigIssue::ShortTimeLock {
+                heir_fingerprint,
+                time_lock_days,
+            } => write!(
+                f,
+                "The heir {heir_fingerprint} can spend after only {time_lock_days} days, \
+                the Heritage configuration will have to be renewed very often"
+            ),
+            ConfigIssue::LockTimeInPast {
+                heir_fingerprint,
+                spendable_timestamp,
+            } => write!(
+                f,
+                "The heir {heir_fingerprint} can spend since timestamp {spendable_timestamp}, \
+                only the relative lock protects the funds"
+            ),
+            ConfigIssue::OverlappingHeirWindows {
+                heir_fingerprint,
+                next_heir_fingerprint,
+                exclusive_window_days,
+            } => write!(
+                f,
+                "The heir {heir_fingerprint} has only {exclusive_window_days} days to spend \
+                before the heir {next_heir_fingerprint} can also spend"
+            ),
+            ConfigIssue::DeepTapTree {
+                heir_fingerprint,
+                tree_depth,
+            } => write!(
+                f,
+                "The heir {heir_fingerprint} is at depth {tree_depth} in the TapTree \
+                and will pay higher transaction fees"
+            ),
+            ConfigIssue::HeirSharesOwnerFingerprint { heir_fingerprint } => write!(
+                f,
+                "The heir {heir_fingerprint} has the same fingerprint as the owner"
+            ),
+        }
+    }
+}
+
+/// A [ConfigIssue] along with its [FindingSeverity], as returned by [HeritageConfig::analyze]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+pub struct ConfigFinding {
+    pub severity: FindingSeverity,
+    #[serde(flatten)]
+    pub issue: ConfigIssue,
+}
+
+impl From<ConfigIssue> for ConfigFinding {
+    fn from(issue: ConfigIssue) -> Self {
+        Self {
+            severity: issue.severity(),
+            issue,
+        }
+    }
+}
+
+impl Display for ConfigFinding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "[{}] {}", self.severity, self.issue)
+    }
+}
+
+impl HeritageConfig {
+    /// Look for the risky setups of this [HeritageConfig], as of the timestamp `now`.
+    ///
+    /// `owner_fingerprints` are the [Fingerprint]s of the owner keys. The [Fingerprint]s of the
+    /// co-owners of the [OwnerQuorum](super::v1::OwnerQuorum), if any, are always included.
+    ///
+    /// The findings are ordered from the most to the least severe.
+    pub fn analyze(&self, owner_fingerprints: &[Fingerprint], now: u64) -> Vec<ConfigFinding> {
+        log::debug!(
+            "HeritageConfig::analyze - owner_fingerprints={owner_fingerprints:?} now={now}"
+        );
+        let mut owner_fingerprints = owner_fingerprints.to_vec();
+        if let Some(owner_quorum) = self.owner_quorum() {
+            owner_fingerprints.extend(
+                owner_quorum
+                    .co_owner_xpubs()
+                    .iter()
+                    .map(|xpub| xpub.descriptor_public_key().master_fingerprint()),
+            );
+        }
+        let (minimum_lock_time_secs, first_time_lock_days) = match &self.0 {
+            super::InnerHeritageConfig::V1(hc) => (
+                hc.minimum_lock_time.as_days().as_seconds(),
+                hc.iter_heritages().next().map(|h| h.time_lock.as_u16()),
+            ),
+        };
+
+        // Heirs are ordered from the lowest maturity to the highest one
+        let heirs = self
+            .iter_heir_configs()
+            .map(|heir_config| {
+                let spendable_timestamp = self
+                    .get_heritage_explorer(heir_config)
+                    .and_then(|he| he.get_spend_conditions().get_spendable_timestamp())
+                    .unwrap_or(0);
+                (heir_config, spendable_timestamp)
+            })
+            .collect::<Vec<_>>();
+        let tree_depths = heir_tree_depths(heirs.len(), self.owner_quorum().is_some());
+
+        let mut issues = Vec::new();
+        if let (Some((heir_config, _)), Some(time_lock_days)) =
+            (heirs.first(), first_time_lock_days)
+        {
+            if time_lock_days < RECOMMENDED_MIN_TIME_LOCK_DAYS {
+                issues.push(ConfigIssue::ShortTimeLock {
+                    heir_fingerprint: heir_config.fingerprint(),
+                    time_lock_days,
+                });
+            }
+        }
+        for (index, (heir_config, spendable_timestamp)) in heirs.iter().enumerate() {
+            let heir_fingerprint = heir_config.fingerprint();
+            if owner_fingerprints
+                .iter()
+                .any(|fingerprint| heir_config.has_fingerprint(*fingerprint))
+            {
+                issues.push(ConfigIssue::HeirSharesOwnerFingerprint { heir_fingerprint });
+            }
+            if *spendable_timestamp <= now {
+                issues.push(ConfigIssue::LockTimeInPast {
+                    heir_fingerprint,
+                    spendable_timestamp: *spendable_timestamp,
+                });
+            }
+            if let Some((next_heir_config, next_spendable_timestamp)) = heirs.get(index + 1) {
+                let exclusive_window_secs =
+                    next_spendable_timestamp.saturating_sub(*spendable_timestamp);
+                if exclusive_window_secs < minimum_lock_time_secs {
+                    issues.push(ConfigIssue::OverlappingHeirWindows {
+                        heir_fingerprint,
+                        next_heir_fingerprint: next_heir_config.fingerprint(),
+                        exclusive_window_days: (exclusive_window_secs / SEC_IN_A_DAY) as u16,
+                    });
+                }
+            }
+            if tree_depths[index] > MAX_RECOMMENDED_TREE_DEPTH {
+                issues.push(ConfigIssue::DeepTapTree {
+                    heir_fingerprint,
+                    tree_depth: tree_depths[index],
+                });
+            }
+        }
+
+        let mut findings = issues
+            .into_iter()
+            .map(ConfigFinding::from)
+            .collect::<Vec<_>>();
+        findings.sort_by(|a, b| b.severity.cmp(&a.severity));
+        log::debug!("HeritageConfig::analyze - findings={findings:?}");
+        findings
+    }
+}
+
+/// Return the depth in the TapTree of each of the `heir_count` heirs, in order of succession.
+///
+/// See [v1::HeritageConfig::descriptor_taptree_miniscript_expression_for_child](super::v1::HeritageConfig::descriptor_taptree_miniscript_expression_for_child)
+/// for the layout of the TapTree. The owner quorum leaf, if any, is at the top of the TapTree.
+fn heir_tree_depths(heir_count: usize, has_owner_quorum: bool) -> Vec<u8> {
+    let offset = has_owner_quorum as u8;
+    (0..heir_count)
+        .map(|index| {
+            let depth = if heir_count == 1 {
+                0
+            } else if index == heir_count - 1 {
+                index as u8
+            } else {
+                index as u8 + 1
+            };
+            depth + offset
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    fn heir_tree_depths_match_descriptors() {
+        for thc in [
+            TestHeritageConfig::BackupWifeY2,
+            TestHeritageConfig::BackupWifeBro,
+        ] {
+            let heritage_config = get_test_heritage_config(thc);
+            let preview = heritage_config.preview_descriptors(&get_test_account_xpub(0));
+            assert_eq!(
+                heir_tree_depths(preview.heirs.len(), false),
+                preview
+                    .heirs
+                    .iter()
+                    .map(|hp| hp.tree_depth)
+                    .collect::<Vec<_>>()
+            );
+        }
+        assert_eq!(heir_tree_depths(1, false), vec![0]);
+        assert_eq!(heir_tree_depths(1, true), vec![1]);
+        assert_eq!(heir_tree_depths(5, false), vec![1, 2, 3, 4, 4]);
+        assert_eq!(heir_tree_depths(2, true), vec![2, 2]);
+    }
+
+    #[test]
+    fn analyze() {
+        let backup_fingerprint = get_test_heritage(TestHeritage::Backup)
+            .heir_config
+            .fingerprint();
+        let wife_fingerprint = get_test_heritage(TestHeritage::Wife)
+            .heir_config
+            .fingerprint();
+
+        // Backup at 365 days and Wife at 400 days, with a minimum lock time of 90 days
+        let heritage_config = get_test_heritage_config(TestHeritageConfig::BackupWifeY2);
+        let findings = heritage_config.analyze(&[], 1_700_000_000);
+        assert_eq!(
+            findings,
+            vec![ConfigFinding::from(ConfigIssue::OverlappingHeirWindows {
+                heir_fingerprint: backup_fingerprint,
+                next_heir_fingerprint: wife_fingerprint,
+                exclusive_window_days: 35,
+            })]
+        );
+        assert_eq!(findings[0].severity, FindingSeverity::Warning);
+
+        // Later, the lock times are in the past, and the owner reuses the Backup seed
+        let findings = heritage_config.analyze(&[backup_fingerprint], 1_800_000_000);
+        assert_eq!(findings.len(), 4);
+        assert!(findings[..3]
+            .iter()
+            .all(|f| f.severity == FindingSeverity::Critical));
+        assert_eq!(findings[3].severity, FindingSeverity::Warning);
+        assert!(findings.contains(&ConfigFinding::from(
+            ConfigIssue::HeirSharesOwnerFingerprint {
+                heir_fingerprint: backup_fingerprint
+            }
+        )));
+        assert!(
+            findings.contains(&ConfigFinding::from(ConfigIssue::LockTimeInPast {
+                heir_fingerprint: wife_fingerprint,
+                spendable_timestamp: 1_700_000_000 + 400 * SEC_IN_A_DAY,
+            }))
+        );
+
+        // Short time lock for the first heir
+        let heritage_config = HeritageConfig::builder_v1()
+            .add_heritage(get_test_heritage(TestHeritage::Backup).time_lock(90))
+            .reference_time(1_700_000_000)
+            .minimum_lock_time(30)
+            .build();
+        assert_eq!(
+            heritage_config.analyze(&[], 1_700_000_000),
+            vec![ConfigFinding::from(ConfigIssue::ShortTimeLock {
+                heir_fingerprint: backup_fingerprint,
+                time_lock_days: 90,
+            })]
+        );
+
+        // No heir, nothing to report
+        assert!(HeritageConfig::builder()
+            .build()
+            .analyze(&[backup_fingerprint], 1_700_000_000)
+            .is_empty());
+    }
+}
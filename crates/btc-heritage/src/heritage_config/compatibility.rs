@@ -0,0 +1,225 @@
+//! Compatibility of the descriptors generated by an [HeritageConfig] with third-party
+//! wallets and signing devices, see [HeritageConfig::compatibility_report].
+//!
+//! The capability matrix of [ExternalSoftware::supports] reflects the latest released
+//! versions we know of. It must be updated when a software gains (or loses) a capability.
+
+use core::fmt::Display;
+
+use serde::Serialize;
+
+use super::{heirtypes::HeirConfig, HeritageConfig};
+
+/// A way of spending the outputs of an Heritage wallet that a third-party
+/// software must support to spend them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendingFeature {
+    /// Single-key spending through the Taproot key-path
+    TaprootKeyPath,
+    /// Spending through a Taproot script-path with a `multi_a` quorum of keys
+    TapscriptMultisig,
+    /// Spending through a Taproot script-path locked by an absolute and a relative
+    /// lock time, which requires a Miniscript-aware signer
+    TapscriptTimelock,
+}
+
+impl Display for SpendingFeature {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            SpendingFeature::TaprootKeyPath => "Taproot key-path",
+            SpendingFeature::TapscriptMultisig => "Tapscript multisig",
+            SpendingFeature::TapscriptTimelock => "Tapscript with time locks",
+        })
+    }
+}
+
+/// A third-party wallet or signing device that may be used to spend from an Heritage wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExternalSoftware {
+    BitcoinCore,
+    Sparrow,
+    Ledger,
+    Coldcard,
+    Trezor,
+    BitBox02,
+    Jade,
+}
+
+impl ExternalSoftware {
+    pub const ALL: [ExternalSoftware; 7] = [
+        ExternalSoftware::BitcoinCore,
+        ExternalSoftware::Sparrow,
+        ExternalSoftware::Ledger,
+        ExternalSoftware::Coldcard,
+        ExternalSoftware::Trezor,
+        ExternalSoftware::BitBox02,
+        ExternalSoftware::Jade,
+    ];
+
+    /// Return `true` if the software can sign for the [SpendingFeature]
+    pub fn supports(self, feature: SpendingFeature) -> bool {
+        match feature {
+            SpendingFeature::TaprootKeyPath => true,
+            SpendingFeature::TapscriptMultisig | SpendingFeature::TapscriptTimelock => match self {
+                // Miniscript in Tapscript since v26
+                ExternalSoftware::BitcoinCore => true,
+                // Miniscript in Tapscript since the Bitcoin app v2.2.0
+                ExternalSoftware::Ledger => true,
+                // Miniscript in Tapscript with the EDGE firmware only
+                ExternalSoftware::Coldcard => true,
+                ExternalSoftware::Sparrow
+                | ExternalSoftware::Trezor
+                | ExternalSoftware::BitBox02
+                | ExternalSoftware::Jade => false,
+            },
+        }
+    }
+
+    /// Return the minimum version or firmware of the software having all the
+    /// capabilities of [ExternalSoftware::supports], if relevant
+    pub fn requirement(self) -> Option<&'static str> {
+        match self {
+            ExternalSoftware::BitcoinCore => Some("v26.0"),
+            ExternalSoftware::Ledger => Some("Bitcoin app v2.2.0"),
+            ExternalSoftware::Coldcard => Some("EDGE firmware"),
+            ExternalSoftware::Sparrow
+            | ExternalSoftware::Trezor
+            | ExternalSoftware::BitBox02
+            | ExternalSoftware::Jade => None,
+        }
+    }
+}
+
+impl Display for ExternalSoftware {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            ExternalSoftware::BitcoinCore => "Bitcoin Core",
+            ExternalSoftware::Sparrow => "Sparrow",
+            ExternalSoftware::Ledger => "Ledger",
+            ExternalSoftware::Coldcard => "Coldcard",
+            ExternalSoftware::Trezor => "Trezor",
+            ExternalSoftware::BitBox02 => "BitBox02",
+            ExternalSoftware::Jade => "Jade",
+        })
+    }
+}
+
+/// The [SpendingFeature]s needed by a spender of an [HeritageConfig] and the
+/// [ExternalSoftware]s that support all of them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SpenderCompatibility {
+    pub required_features: Vec<SpendingFeature>,
+    pub compatible: Vec<ExternalSoftware>,
+    pub incompatible: Vec<ExternalSoftware>,
+}
+
+impl SpenderCompatibility {
+    fn new(required_features: Vec<SpendingFeature>) -> Self {
+        let (compatible, incompatible) = ExternalSoftware::ALL.into_iter().partition(|software| {
+            required_features
+                .iter()
+                .all(|feature| software.supports(*feature))
+        });
+        Self {
+            required_features,
+            compatible,
+            incompatible,
+        }
+    }
+
+    /// Return the [SpenderCompatibility] of an heir using the given [HeirConfig],
+    /// allowing to pick an [HeirConfig] type the heir will be able to use
+    pub fn for_heir_config(heir_config: &HeirConfig) -> Self {
+        Self::new(match heir_config {
+            HeirConfig::SingleHeirPubkey(_) | HeirConfig::HeirXPubkey(_) => {
+                vec![SpendingFeature::TapscriptTimelock]
+            }
+            HeirConfig::MultisigHeirXPubkey(_) => vec![
+                SpendingFeature::TapscriptMultisig,
+                SpendingFeature::TapscriptTimelock,
+            ],
+        })
+    }
+}
+
+/// The compatibility of the owner and each heir of an [HeritageConfig] with third-party
+/// software, see [HeritageConfig::compatibility_report]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CompatibilityReport {
+    pub owner: SpenderCompatibility,
+    /// The heirs, in order of succession
+    pub heirs: Vec<HeirCompatibility>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HeirCompatibility {
+    pub heir_config: HeirConfig,
+    #[serde(flatten)]
+    pub compatibility: SpenderCompatibility,
+}
+
+impl HeritageConfig {
+    /// Report which [ExternalSoftware]s can spend the outputs of the descriptors generated
+    /// by this [HeritageConfig], for the owner and for each heir.
+    ///
+    /// To check existing descriptors, first recover their [HeritageConfig] with
+    /// [FromDescriptorScripts](super::FromDescriptorScripts).
+    pub fn compatibility_report(&self) -> CompatibilityReport {
+        let owner = SpenderCompatibility::new(match self.owner_quorum() {
+            Some(_) => vec![SpendingFeature::TapscriptMultisig],
+            None => vec![SpendingFeature::TaprootKeyPath],
+        });
+        let heirs = self
+            .iter_heir_configs()
+            .map(|heir_config| HeirCompatibility {
+                heir_config: heir_config.clone(),
+                compatibility: SpenderCompatibility::for_heir_config(heir_config),
+            })
+            .collect();
+        CompatibilityReport { owner, heirs }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    #[test]
+    fn compatibility_report() {
+        let report =
+            get_test_heritage_config(TestHeritageConfig::BackupWifeBro).compatibility_report();
+        assert_eq!(
+            report.owner.required_features,
+            vec![SpendingFeature::TaprootKeyPath]
+        );
+        assert_eq!(report.owner.compatible, ExternalSoftware::ALL.to_vec());
+        assert!(report.owner.incompatible.is_empty());
+
+        assert_eq!(report.heirs.len(), 3);
+        for heir in report.heirs.iter() {
+            assert_eq!(
+                heir.compatibility.required_features,
+                vec![SpendingFeature::TapscriptTimelock]
+            );
+            assert!(heir
+                .compatibility
+                .compatible
+                .contains(&ExternalSoftware::Ledger));
+            assert!(heir
+                .compatibility
+                .incompatible
+                .contains(&ExternalSoftware::Trezor));
+            assert_eq!(
+                heir.compatibility.compatible.len() + heir.compatibility.incompatible.len(),
+                ExternalSoftware::ALL.len()
+            );
+        }
+        assert_eq!(
+            report.heirs[0].heir_config,
+            get_test_heritage(TestHeritage::Backup).heir_config
+        );
+    }
+}
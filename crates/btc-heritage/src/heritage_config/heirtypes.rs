@@ -2,13 +2,11 @@ use core::{fmt::Display, hash::Hash, str::FromStr};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    bitcoin::{
-        bip32::{ChildNumber, DerivationPath, Fingerprint},
-        Network,
-    },
+    bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint},
+    bitcoin::key::XOnlyPublicKey,
     errors::Error,
     miniscript::{DescriptorPublicKey, ToPublicKey},
-    utils, AccountXPub,
+    AccountXPub,
 };
 
 #[derive(Debug, Hash, Clone, Serialize, Eq, PartialEq, Ord, PartialOrd)]
@@ -55,25 +53,22 @@ impl TryFrom<DescriptorPublicKey> for SingleHeirPubkey {
             ));
         };
 
-        // If the derivation path is not m/86'/[0,1]'/i'/*, bail
-        let cointype_path_segment = match utils::bitcoin_network_from_env() {
-            Network::Bitcoin => 0,
-            _ => 1,
-        };
+        // If the derivation path is not m/86'/[0,1]'/i'/M/N, bail
+        // A single public key does not carry a network, so both coin types are accepted
         let derivation_path = descriptor
             .full_derivation_path()
             .expect("descriptor has been verified to be an XPub");
         if !(derivation_path.len() == 5
-            && derivation_path[..2]
-                == [
-                    ChildNumber::from_hardened_idx(86).expect("86 is in boundaries"),
-                    ChildNumber::from_hardened_idx(cointype_path_segment)
-                        .expect("0 and 1 are in boundaries"),
-                ]
+            && derivation_path[0]
+                == ChildNumber::from_hardened_idx(86).expect("86 is in boundaries")
+            && [0, 1]
+                .into_iter()
+                .map(|i| ChildNumber::from_hardened_idx(i).expect("0 and 1 are in boundaries"))
+                .any(|cointype| derivation_path[1] == cointype)
             && derivation_path[2].is_hardened()
             && !descriptor.has_wildcard())
         {
-            log::error!("DescriptorPublicKey must have a Derivation Path like m/86'/{cointype_path_segment}'/<account>'/<M>/<N>");
+            log::error!("DescriptorPublicKey must have a Derivation Path like m/86'/[0,1]'/<account>'/<M>/<N>");
             return Err(Error::InvalidDescriptorPublicKey("Wrong derivation path"));
         }
 
@@ -113,11 +108,70 @@ impl Display for SingleHeirPubkey {
     }
 }
 
+/// A k-of-n multisig heir: `threshold` of the `xpubs` must co-sign to spend.
+/// In the TapTree, it translates into a `multi_a` leaf instead of a single key leaf.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+#[serde(try_from = "RawMultisigHeirXPubkey")]
+pub struct MultisigHeirXPubkey {
+    threshold: u8,
+    xpubs: Vec<AccountXPub>,
+}
+
+#[derive(Deserialize)]
+struct RawMultisigHeirXPubkey {
+    threshold: u8,
+    xpubs: Vec<AccountXPub>,
+}
+impl TryFrom<RawMultisigHeirXPubkey> for MultisigHeirXPubkey {
+    type Error = Error;
+
+    fn try_from(value: RawMultisigHeirXPubkey) -> Result<Self, Self::Error> {
+        MultisigHeirXPubkey::new(value.threshold, value.xpubs)
+    }
+}
+
+impl MultisigHeirXPubkey {
+    /// Create a new [MultisigHeirXPubkey] requiring `threshold` signatures among the `xpubs`
+    ///
+    /// # Errors
+    /// Returns an error if there is less than 2 `xpubs`, if the `threshold` is not
+    /// between 1 and the number of `xpubs` or if the same `xpub` is present more than once
+    pub fn new(threshold: u8, xpubs: Vec<AccountXPub>) -> Result<Self, Error> {
+        if xpubs.len() < 2 {
+            return Err(Error::InvalidMultisigHeir(
+                "a multisig heir must have at least 2 keys",
+            ));
+        }
+        if threshold == 0 || threshold as usize > xpubs.len() {
+            return Err(Error::InvalidMultisigHeir(
+                "the threshold must be between 1 and the number of keys",
+            ));
+        }
+        if xpubs
+            .iter()
+            .enumerate()
+            .any(|(i, xpub)| xpubs[i + 1..].contains(xpub))
+        {
+            return Err(Error::InvalidMultisigHeir(
+                "the same key cannot be present more than once",
+            ));
+        }
+        Ok(Self { threshold, xpubs })
+    }
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+    pub fn xpubs(&self) -> &[AccountXPub] {
+        &self.xpubs
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Clone)]
 #[serde(tag = "type", content = "value", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum HeirConfig {
     SingleHeirPubkey(SingleHeirPubkey),
     HeirXPubkey(AccountXPub),
+    MultisigHeirXPubkey(MultisigHeirXPubkey),
     // SingleHeirPubKeyHash(KeyHash),
 }
 
@@ -133,6 +187,10 @@ impl core::hash::Hash for HeirConfig {
                 "hxp".hash(state);
                 hxp.hash(state)
             }
+            HeirConfig::MultisigHeirXPubkey(mhxp) => {
+                "mhxp".hash(state);
+                mhxp.hash(state)
+            }
         };
     }
 }
@@ -144,10 +202,22 @@ impl HeirConfig {
             HeirConfig::HeirXPubkey(xpub) => match xpub_child_index {
                 Some(index) => format!("v:pk({})", xpub.child_descriptor_public_key(index)),
                 None => format!("v:pk({})", xpub),
-            }, // HeritageMode::SingleHeirPubKeyHash(pubkeyhash) => {
-               //     let s: String = (*pubkeyhash).into();
-               //     format!("vc:expr_raw_pkh({s})")
-               // }
+            },
+            HeirConfig::MultisigHeirXPubkey(mxpub) => {
+                let keys = mxpub
+                    .xpubs
+                    .iter()
+                    .map(|xpub| match xpub_child_index {
+                        Some(index) => xpub.child_descriptor_public_key(index).to_string(),
+                        None => xpub.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!("v:multi_a({},{keys})", mxpub.threshold)
+            } // HeritageMode::SingleHeirPubKeyHash(pubkeyhash) => {
+              //     let s: String = (*pubkeyhash).into();
+              //     format!("vc:expr_raw_pkh({s})")
+              // }
         }
     }
     pub fn concrete_script_segment<'a>(
@@ -157,32 +227,18 @@ impl HeirConfig {
         match self {
             HeirConfig::SingleHeirPubkey(xpub) => format!("v:pk({xpub})"),
             HeirConfig::HeirXPubkey(xpub) => {
-                let fingerprint = xpub.descriptor_public_key().master_fingerprint();
-                let derivation_path = xpub
-                    .descriptor_public_key()
-                    .full_derivation_path()
-                    .expect("account Xpub has a derivation path");
-                let mut origins = origins.filter_map(|(f, d)| {
-                    if *f == fingerprint && d[..3] == derivation_path[..] {
-                        let chain = d[3];
-                        let address_index = d[4];
-                        Some(
-                            xpub.child_descriptor_public_key(chain.into())
-                                .at_derivation_index(address_index.into())
-                                .unwrap()
-                                .to_x_only_pubkey(),
-                        )
-                    } else {
-                        None
-                    }
-                });
-                let key = origins
-                    .next()
-                    .expect("Caller should gave us an origin that covers the Xpub");
-                if origins.next().is_some() {
-                    panic!("Having multiple origins candidates is unexpected");
-                }
-                format!("v:pk({key})")
+                let origins = origins.collect::<Vec<_>>();
+                format!("v:pk({})", concrete_xpub_key(xpub, &origins))
+            }
+            HeirConfig::MultisigHeirXPubkey(mxpub) => {
+                let origins = origins.collect::<Vec<_>>();
+                let keys = mxpub
+                    .xpubs
+                    .iter()
+                    .map(|xpub| concrete_xpub_key(xpub, &origins).to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                format!("v:multi_a({},{keys})", mxpub.threshold)
             } // HeritageMode::SingleHeirPubKeyHash(pubkeyhash) => {
               //     let s: String = (*pubkeyhash).into();
               //     format!("vc:expr_raw_pkh({s})")
@@ -190,21 +246,102 @@ impl HeirConfig {
         }
     }
 
+    /// Returns the [Fingerprint] of the heir.
+    /// For a [HeirConfig::MultisigHeirXPubkey], it is the [Fingerprint] of the first key,
+    /// use [HeirConfig::has_fingerprint] to check if a [Fingerprint] is part of the heir.
     pub fn fingerprint(&self) -> Fingerprint {
         match self {
             HeirConfig::SingleHeirPubkey(xpub) => xpub.0.master_fingerprint(),
             HeirConfig::HeirXPubkey(xpub) => xpub.descriptor_public_key().master_fingerprint(),
+            HeirConfig::MultisigHeirXPubkey(mxpub) => {
+                mxpub.xpubs[0].descriptor_public_key().master_fingerprint()
+            }
+        }
+    }
+
+    /// Verify if the given [Fingerprint] is one of the keys of the heir
+    pub fn has_fingerprint(&self, fingerprint: Fingerprint) -> bool {
+        match self {
+            HeirConfig::MultisigHeirXPubkey(mxpub) => mxpub
+                .xpubs
+                .iter()
+                .any(|xpub| xpub.descriptor_public_key().master_fingerprint() == fingerprint),
+            _ => self.fingerprint() == fingerprint,
         }
     }
 }
 
+/// Find the origin corresponding to `xpub` and derive the [XOnlyPublicKey] it points to
+///
+/// # Panics
+/// Panics if there is not exactly one origin covering the `xpub`
+fn concrete_xpub_key(
+    xpub: &AccountXPub,
+    origins: &[(&Fingerprint, &DerivationPath)],
+) -> XOnlyPublicKey {
+    let fingerprint = xpub.descriptor_public_key().master_fingerprint();
+    let derivation_path = xpub
+        .descriptor_public_key()
+        .full_derivation_path()
+        .expect("account Xpub has a derivation path");
+    let mut origins = origins.iter().filter_map(|(f, d)| {
+        if **f == fingerprint && d[..3] == derivation_path[..] {
+            let chain = d[3];
+            let address_index = d[4];
+            Some(
+                xpub.child_descriptor_public_key(chain.into())
+                    .at_derivation_index(address_index.into())
+                    .unwrap()
+                    .to_x_only_pubkey(),
+            )
+        } else {
+            None
+        }
+    });
+    let key = origins
+        .next()
+        .expect("Caller should gave us an origin that covers the Xpub");
+    if origins.next().is_some() {
+        panic!("Having multiple origins candidates is unexpected");
+    }
+    key
+}
+
 /// Extract an HeirConfig key from the key fragment of a script
 fn re_heirconfig_key() -> &'static regex::Regex {
     static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
     RE.get_or_init(|| regex::Regex::new(r"^v:pk\((?<key>.+?)\)$").unwrap())
 }
+/// Extract the threshold and keys of a multisig HeirConfig from the fragment of a script
+fn re_multisig_heirconfig_keys() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        regex::Regex::new(r"^v:multi_a\((?<threshold>[0-9]+),(?<keys>.+)\)$").unwrap()
+    })
+}
 impl super::FromDescriptorScripts for HeirConfig {
     fn from_descriptor_scripts(script_fragment: &str) -> crate::errors::Result<Self> {
+        if let Some(caps) = re_multisig_heirconfig_keys().captures(script_fragment) {
+            let threshold: u8 = caps["threshold"].parse().map_err(|e| {
+                log::info!("Failed to parse multisig threshold: {e}");
+                Error::InvalidScriptFragments("heir in")
+            })?;
+            let xpubs = caps["keys"]
+                .split(',')
+                .map(AccountXPub::try_from)
+                .collect::<crate::errors::Result<Vec<_>>>()
+                .map_err(|e| {
+                    log::info!("{e}");
+                    Error::InvalidScriptFragments("heir in")
+                })?;
+            return Ok(HeirConfig::MultisigHeirXPubkey(
+                MultisigHeirXPubkey::new(threshold, xpubs).map_err(|e| {
+                    log::info!("{e}");
+                    Error::InvalidScriptFragments("heir in")
+                })?,
+            ));
+        }
+
         let key = &re_heirconfig_key()
             .captures(script_fragment)
             .ok_or(Error::InvalidScriptFragments("heir in"))?["key"];
@@ -252,8 +389,10 @@ mod tests {
         assert!(SingleHeirPubkey::try_from("[99ccb69a/86'/1'/1751476594'/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b").is_err());
         // Derivation path too long
         assert!(SingleHeirPubkey::try_from("[99ccb69a/86'/1'/1751476594'/0/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b").is_err());
-        // Network wrong
-        assert!(SingleHeirPubkey::try_from("[99ccb69a/86'/0'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b").is_err());
+        // Mainnet coin type
+        assert!(SingleHeirPubkey::try_from("[99ccb69a/86'/0'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b").is_ok());
+        // Coin type wrong
+        assert!(SingleHeirPubkey::try_from("[99ccb69a/86'/2'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b").is_err());
         // Usage not hardened
         assert!(SingleHeirPubkey::try_from("[99ccb69a/86/1'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b").is_err());
         // Incorrect usage
@@ -268,7 +407,7 @@ mod tests {
         let hc1 = hc1.unwrap();
         assert_eq!(hc1, HeirConfig::SingleHeirPubkey(SingleHeirPubkey::try_from("[99ccb69a/86'/1'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b").unwrap()));
         assert_eq!(hc1.descriptor_segment(None), h1_script_fragment);
-        assert!(HeirConfig::from_descriptor_scripts("v:pk([99ccb69a/86'/0'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b)").is_err());
+        assert!(HeirConfig::from_descriptor_scripts("v:pk([99ccb69a/86'/2'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b)").is_err());
         assert!(HeirConfig::from_descriptor_scripts("pk([99ccb69a/86'/1'/1751476594'/0/0]02ee39732e7f49cf4c9bd9b3faec01ed6f62a668fef33fbec0f2708e4cebf5bc9b)").is_err());
 
         let h2_script_fragment = "v:pk([f0d79bf6/86'/1'/1751476594']tpubDDFibSiSkFTfnLc4cG5X2wwkLjatiWbxb3T6PNbaCuv9uQpeq4i2sRrk7EKFgd56TTTHXpKDrW4JEDfsueAfLYC9CTPAung761RWMcWE3aP/*)";
@@ -279,4 +418,45 @@ mod tests {
         assert_eq!(hc2.descriptor_segment(None), h2_script_fragment);
         assert!(HeirConfig::from_descriptor_scripts("v:pk([f0d79bf6/86'/0'/1751476594']tpubDDFibSiSkFTfnLc4cG5X2wwkLjatiWbxb3T6PNbaCuv9uQpeq4i2sRrk7EKFgd56TTTHXpKDrW4JEDfsueAfLYC9CTPAung761RWMcWE3aP/*)").is_err());
     }
+
+    #[test]
+    fn multisig_heir_xpubkey() {
+        let xpub1 = AccountXPub::try_from("[f0d79bf6/86'/1'/1751476594']tpubDDFibSiSkFTfnLc4cG5X2wwkLjatiWbxb3T6PNbaCuv9uQpeq4i2sRrk7EKFgd56TTTHXpKDrW4JEDfsueAfLYC9CTPAung761RWMcWE3aP/*").unwrap();
+        let xpub2 = crate::tests::get_test_account_xpub(0);
+
+        assert!(MultisigHeirXPubkey::new(1, vec![xpub1.clone(), xpub2.clone()]).is_ok());
+        assert!(MultisigHeirXPubkey::new(2, vec![xpub1.clone(), xpub2.clone()]).is_ok());
+        // Not enough keys
+        assert!(MultisigHeirXPubkey::new(1, vec![xpub1.clone()]).is_err());
+        // Invalid thresholds
+        assert!(MultisigHeirXPubkey::new(0, vec![xpub1.clone(), xpub2.clone()]).is_err());
+        assert!(MultisigHeirXPubkey::new(3, vec![xpub1.clone(), xpub2.clone()]).is_err());
+        // Duplicated key
+        assert!(MultisigHeirXPubkey::new(2, vec![xpub1.clone(), xpub1.clone()]).is_err());
+
+        let hc = HeirConfig::MultisigHeirXPubkey(
+            MultisigHeirXPubkey::new(2, vec![xpub1.clone(), xpub2.clone()]).unwrap(),
+        );
+        assert!(hc.has_fingerprint(xpub1.descriptor_public_key().master_fingerprint()));
+        assert!(hc.has_fingerprint(xpub2.descriptor_public_key().master_fingerprint()));
+
+        // Serde validates the multisig
+        let json = serde_json::to_string(&hc).unwrap();
+        assert_eq!(serde_json::from_str::<HeirConfig>(&json).unwrap(), hc);
+        assert!(serde_json::from_str::<HeirConfig>(&format!(
+            r#"{{"type":"MULTISIG_HEIR_X_PUBKEY","value":{{"threshold":3,"xpubs":["{xpub1}","{xpub2}"]}}}}"#
+        ))
+        .is_err());
+
+        // Script fragments
+        let script_fragment = format!("v:multi_a(2,{xpub1},{xpub2})");
+        assert_eq!(hc.descriptor_segment(None), script_fragment);
+        assert_eq!(
+            HeirConfig::from_descriptor_scripts(&script_fragment).unwrap(),
+            hc
+        );
+        assert!(
+            HeirConfig::from_descriptor_scripts(&format!("v:multi_a(3,{xpub1},{xpub2})")).is_err()
+        );
+    }
 }
@@ -4,14 +4,18 @@ use serde::{Deserialize, Serialize};
 
 use self::heirtypes::HeirConfig;
 use crate::{
+    account_xpub::AccountXPub,
     bitcoin::{
         bip32::{DerivationPath, Fingerprint},
         ScriptBuf,
     },
     errors::{Error, Result},
-    miniscript::{DefiniteDescriptorKey, Miniscript, Tap},
+    miniscript::{DefiniteDescriptorKey, Descriptor, DescriptorPublicKey, Miniscript, Tap},
+    subwallet_config::SubwalletConfig,
 };
 
+pub mod analysis;
+pub mod compatibility;
 pub mod heirtypes;
 pub mod v1;
 
@@ -43,6 +47,29 @@ impl SpendConditions {
     }
 }
 
+/// Preview of what an [HeritageConfig] would produce for a given [AccountXPub],
+/// see [HeritageConfig::preview_descriptors]
+#[derive(Debug, Clone, Serialize)]
+pub struct HeritageConfigPreview {
+    pub external_descriptor: Descriptor<DescriptorPublicKey>,
+    pub change_descriptor: Descriptor<DescriptorPublicKey>,
+    /// The heirs, in order of succession
+    pub heirs: Vec<HeirPreview>,
+}
+
+/// Spending conditions and position in the TapTree of an heir in an [HeritageConfigPreview]
+#[derive(Debug, Clone, Serialize)]
+pub struct HeirPreview {
+    pub heir_config: HeirConfig,
+    /// Depth of the heir script in the TapTree. The deeper, the more expensive
+    /// the transaction fee will be for the heir
+    pub tree_depth: u8,
+    /// The heir cannot spend before this timestamp (absolute lock)
+    pub spendable_timestamp: Option<u64>,
+    /// The heir cannot spend an UTXO before it has this number of confirmations (relative lock)
+    pub relative_block_lock: Option<u16>,
+}
+
 #[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(transparent)]
 pub struct HeritageConfig(InnerHeritageConfig);
@@ -110,7 +137,7 @@ impl HeritageConfig {
     /// Returns the miniscript expression representing the TapTree generated
     /// by this [HeritageConfig], if any.
     /// If present, the index will be used to derive a child for every xpub present in this [HeritageConfig],
-    /// i.e. for every [HeirConfig::HeirXPubkey]. For other HeirConfig, it has no effect.
+    /// i.e. for every [HeirConfig::HeirXPubkey] and [HeirConfig::MultisigHeirXPubkey]. For other HeirConfig, it has no effect.
     /// The only case where this returns [None] is if there is no heir in this [HeritageConfig].
     pub fn descriptor_taptree_miniscript_expression_for_child(
         &self,
@@ -123,6 +150,32 @@ impl HeritageConfig {
         }
     }
 
+    /// Returns the [v1::OwnerQuorum] of the [HeritageConfig], if the owner spends with a quorum of keys
+    pub fn owner_quorum(&self) -> Option<&v1::OwnerQuorum> {
+        match &self.0 {
+            InnerHeritageConfig::V1(hc) => hc.owner_quorum(),
+        }
+    }
+
+    /// Return a copy of this [HeritageConfig] where `new_heir_config` replaces
+    /// `old_heir_config`, e.g. because the heir lost the device holding their key.
+    /// The time lock and the spending quota of the heir are unchanged.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidHeritageConfig] if `old_heir_config` is not in the
+    /// [HeritageConfig] or if `new_heir_config` already is.
+    pub fn with_replaced_heir(
+        &self,
+        old_heir_config: &HeirConfig,
+        new_heir_config: HeirConfig,
+    ) -> Result<Self> {
+        match &self.0 {
+            InnerHeritageConfig::V1(hc) => hc
+                .with_replaced_heir(old_heir_config, new_heir_config)
+                .map(|hc| HeritageConfig(InnerHeritageConfig::V1(hc))),
+        }
+    }
+
     /// Returns an iterator over references to the [HeirConfig]s present in the [HeritageConfig].
     ///
     /// For a V1 HeritageConfig, the order is guaranteed to be from the lowest maturity to the highest one.
@@ -132,6 +185,52 @@ impl HeritageConfig {
         }
     }
 
+    /// Returns the descriptors that would be generated for a subwallet using this
+    /// [HeritageConfig] with the given [AccountXPub], along with the TapTree layout and
+    /// the spending conditions of each heir.
+    ///
+    /// Nothing is committed anywhere, so it can be used to show a summary to the user
+    /// before actually updating the [HeritageConfig] of a wallet.
+    pub fn preview_descriptors(&self, account_xpub: &AccountXPub) -> HeritageConfigPreview {
+        log::debug!("HeritageConfig::preview_descriptors - account_xpub={account_xpub}");
+        let (external_descriptor, change_descriptor) = SubwalletConfig::create_descriptors(
+            account_xpub,
+            self,
+            SubwalletConfig::DEFAULT_EXTERNAL_INDEX,
+            SubwalletConfig::DEFAULT_CHANGE_INDEX,
+        );
+        // The TapTree leaves are in the same order as the heirs, after the owner quorum leaf if any
+        let tree_depths = match &external_descriptor {
+            Descriptor::Tr(tr) => tr
+                .iter_scripts()
+                .skip(self.owner_quorum().is_some() as usize)
+                .map(|(depth, _)| depth)
+                .collect(),
+            _ => vec![],
+        };
+        let heirs = self
+            .iter_heir_configs()
+            .zip(tree_depths)
+            .map(|(heir_config, tree_depth)| {
+                let spend_conditions = self
+                    .get_heritage_explorer(heir_config)
+                    .expect("heir_config comes from the HeritageConfig")
+                    .get_spend_conditions();
+                HeirPreview {
+                    heir_config: heir_config.clone(),
+                    tree_depth,
+                    spendable_timestamp: spend_conditions.get_spendable_timestamp(),
+                    relative_block_lock: spend_conditions.get_relative_block_lock(),
+                }
+            })
+            .collect();
+        HeritageConfigPreview {
+            external_descriptor,
+            change_descriptor,
+            heirs,
+        }
+    }
+
     /// Returns a type with [HeritageExplorer] for the given [HeirConfig] if it can be found in the [HeritageConfig].
     /// If no Heritage could be matched, the function returns [None].
     pub fn get_heritage_explorer(&self, heir_config: &HeirConfig) -> Option<HeritageExplorer> {
@@ -262,6 +361,7 @@ mod tests {
 
     use crate::tests::get_test_heritage;
     use crate::tests::TestHeritage;
+    use crate::tests::*;
 
     use super::HeritageConfig;
     use super::InnerHeritageConfig;
@@ -275,6 +375,117 @@ mod tests {
         };
     }
 
+    #[test]
+    fn preview_descriptors() {
+        let preview = get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+            .preview_descriptors(&get_test_account_xpub(0));
+        assert_eq!(
+            preview.external_descriptor.to_string(),
+            get_default_test_subwallet_config_expected_external_descriptor(
+                TestHeritageConfig::BackupWifeY2
+            )
+        );
+        assert_eq!(
+            preview.change_descriptor.to_string(),
+            get_default_test_subwallet_config_expected_change_descriptor(
+                TestHeritageConfig::BackupWifeY2
+            )
+        );
+        assert_eq!(preview.heirs.len(), 2);
+        assert_eq!(
+            preview.heirs[0].heir_config,
+            get_test_heritage(TestHeritage::Backup).heir_config
+        );
+        assert_eq!(preview.heirs[0].tree_depth, 1);
+        assert_eq!(preview.heirs[0].spendable_timestamp, Some(1731536000));
+        assert_eq!(preview.heirs[0].relative_block_lock, Some(12960));
+        assert_eq!(
+            preview.heirs[1].heir_config,
+            get_test_heritage(TestHeritage::Wife).heir_config
+        );
+        assert_eq!(preview.heirs[1].tree_depth, 1);
+        assert_eq!(preview.heirs[1].spendable_timestamp, Some(1734560000));
+        assert_eq!(preview.heirs[1].relative_block_lock, Some(25920));
+
+        // Three heirs: the last two are deeper in the TapTree
+        let preview = get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+            .preview_descriptors(&get_test_account_xpub(2));
+        assert_eq!(
+            preview
+                .heirs
+                .iter()
+                .map(|hp| hp.tree_depth)
+                .collect::<Vec<_>>(),
+            vec![1, 2, 2]
+        );
+
+        // No heir
+        let preview = HeritageConfig::builder()
+            .build()
+            .preview_descriptors(&get_test_account_xpub(0));
+        assert!(preview.heirs.is_empty());
+    }
+
+    #[test]
+    fn with_replaced_heir() {
+        let heritage_config = get_test_heritage_config(TestHeritageConfig::BackupWifeY2);
+        let wife = get_test_heritage(TestHeritage::Wife);
+        let brother = get_test_heritage(TestHeritage::Brother);
+
+        let replaced = heritage_config
+            .with_replaced_heir(&wife.heir_config, brother.heir_config.clone())
+            .unwrap();
+        assert_eq!(
+            replaced,
+            HeritageConfig::builder_v1()
+                .add_heritage(get_test_heritage(TestHeritage::Backup))
+                .add_heritage(brother.clone().time_lock(wife.time_lock.as_u16()))
+                .reference_time(1700000000)
+                .minimum_lock_time(90)
+                .build()
+        );
+
+        // The heir to replace must be present
+        assert!(heritage_config
+            .with_replaced_heir(&brother.heir_config, wife.heir_config.clone())
+            .is_err());
+        // The new heir must not be present
+        assert!(heritage_config
+            .with_replaced_heir(
+                &get_test_heritage(TestHeritage::Backup).heir_config,
+                wife.heir_config.clone()
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn builder_try_build() {
+        assert!(HeritageConfig::builder_v1()
+            .add_heritage(get_test_heritage(TestHeritage::Backup))
+            .add_heritage(get_test_heritage(TestHeritage::Wife))
+            .minimum_lock_time(90)
+            .try_build()
+            .is_ok());
+        // Same heir twice
+        assert!(HeritageConfig::builder_v1()
+            .add_heritage(get_test_heritage(TestHeritage::Backup))
+            .add_heritage(get_test_heritage(TestHeritage::Backup).time_lock(900))
+            .try_build()
+            .is_err());
+        // Same time lock twice
+        assert!(HeritageConfig::builder_v1()
+            .add_heritage(get_test_heritage(TestHeritage::Backup).time_lock(900))
+            .add_heritage(get_test_heritage(TestHeritage::Wife).time_lock(900))
+            .try_build()
+            .is_err());
+        // Minimum lock time too short
+        assert!(HeritageConfig::builder_v1()
+            .add_heritage(get_test_heritage(TestHeritage::Backup))
+            .minimum_lock_time(9)
+            .try_build()
+            .is_err());
+    }
+
     #[test]
     fn heritage_config_hash_eq() {
         let reference = HeritageConfig::builder_v1()
@@ -4,6 +4,7 @@ use serde::{Deserialize, Serialize};
 
 use super::{heirtypes::HeirConfig, SpendConditions};
 use crate::{
+    account_xpub::AccountXPub,
     bitcoin::{
         absolute::LOCK_TIME_THRESHOLD,
         bip32::{DerivationPath, Fingerprint},
@@ -228,7 +229,127 @@ impl Heritages {
     }
 }
 
-#[derive(Debug, Clone, Hash, Serialize, Deserialize, PartialEq, Eq)]
+/// A k-of-n quorum of keys for the owner: `threshold` signatures among the [AccountXPub]
+/// of the subwallet and the `co_owner_xpubs` are required for the owner to spend.
+///
+/// The key-path of the descriptors is then made unspendable and the owner spends
+/// through a `multi_a` leaf, placed at the top of the TapTree, without any time lock.
+#[derive(Debug, Hash, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "RawOwnerQuorum")]
+pub struct OwnerQuorum {
+    threshold: u8,
+    co_owner_xpubs: Vec<AccountXPub>,
+}
+
+#[derive(Deserialize)]
+struct RawOwnerQuorum {
+    threshold: u8,
+    co_owner_xpubs: Vec<AccountXPub>,
+}
+impl TryFrom<RawOwnerQuorum> for OwnerQuorum {
+    type Error = Error;
+
+    fn try_from(value: RawOwnerQuorum) -> Result<Self, Self::Error> {
+        OwnerQuorum::new(value.threshold, value.co_owner_xpubs)
+    }
+}
+
+impl OwnerQuorum {
+    /// Create a new [OwnerQuorum] requiring `threshold` signatures among the [AccountXPub]
+    /// of the subwallet and the `co_owner_xpubs`
+    ///
+    /// # Errors
+    /// Returns an error if there is no `co_owner_xpubs`, if the `threshold` is not
+    /// between 1 and the total number of keys or if the same `xpub` is present more than once
+    pub fn new(threshold: u8, co_owner_xpubs: Vec<AccountXPub>) -> Result<Self, Error> {
+        if co_owner_xpubs.is_empty() {
+            return Err(Error::InvalidOwnerQuorum(
+                "an owner quorum must have at least 1 co-owner key",
+            ));
+        }
+        if threshold == 0 || threshold as usize > co_owner_xpubs.len() + 1 {
+            return Err(Error::InvalidOwnerQuorum(
+                "the threshold must be between 1 and the number of keys",
+            ));
+        }
+        if co_owner_xpubs
+            .iter()
+            .enumerate()
+            .any(|(i, xpub)| co_owner_xpubs[i + 1..].contains(xpub))
+        {
+            return Err(Error::InvalidOwnerQuorum(
+                "the same key cannot be present more than once",
+            ));
+        }
+        Ok(Self {
+            threshold,
+            co_owner_xpubs,
+        })
+    }
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+    pub fn co_owner_xpubs(&self) -> &[AccountXPub] {
+        &self.co_owner_xpubs
+    }
+
+    /// Returns the miniscript expression of the owner leaf, `account_xpub` being the first key.
+    /// If present, the index will be used to derive a child for every xpub.
+    pub fn descriptor_segment(&self, account_xpub: &AccountXPub, index: Option<u32>) -> String {
+        let keys = core::iter::once(account_xpub)
+            .chain(self.co_owner_xpubs.iter())
+            .map(|xpub| match index {
+                Some(index) => xpub.child_descriptor_public_key(index).to_string(),
+                None => xpub.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!("multi_a({},{keys})", self.threshold)
+    }
+
+    /// Recover the [AccountXPub] of the subwallet and the [OwnerQuorum] from the
+    /// owner leaf of a descriptor
+    ///
+    /// # Errors
+    /// Returns an error if the fragment is not an owner quorum leaf
+    pub(crate) fn from_descriptor_fragment(
+        fragment: &str,
+    ) -> crate::errors::Result<(AccountXPub, Self)> {
+        let caps = re_owner_quorum_fragment()
+            .captures(fragment)
+            .ok_or(Error::InvalidScriptFragments("owner quorum in"))?;
+        let threshold: u8 = caps["threshold"].parse().map_err(|e| {
+            log::info!("Failed to parse owner quorum threshold: {e}");
+            Error::InvalidScriptFragments("owner quorum in")
+        })?;
+        let mut xpubs = caps["keys"]
+            .split(',')
+            .map(AccountXPub::try_from)
+            .collect::<crate::errors::Result<Vec<_>>>()
+            .map_err(|e| {
+                log::info!("{e}");
+                Error::InvalidScriptFragments("owner quorum in")
+            })?;
+        let account_xpub = xpubs.remove(0);
+        if xpubs.contains(&account_xpub) {
+            log::info!("The account xpub is also a co-owner key");
+            return Err(Error::InvalidScriptFragments("owner quorum in"));
+        }
+        let owner_quorum = OwnerQuorum::new(threshold, xpubs).map_err(|e| {
+            log::info!("{e}");
+            Error::InvalidScriptFragments("owner quorum in")
+        })?;
+        Ok((account_xpub, owner_quorum))
+    }
+}
+
+/// Extract the threshold and keys of an owner quorum from the fragment of a script
+fn re_owner_quorum_fragment() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| regex::Regex::new(r"^multi_a\((?<threshold>[0-9]+),(?<keys>.+)\)$").unwrap())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct HeritageConfig {
     /// The deduplicated, ordered list of [Heritage] for this [HeritageConfig].
     heritages: Heritages,
@@ -239,6 +360,22 @@ pub struct HeritageConfig {
     /// It exist in case an old address with an old absolute locktime is used
     #[serde(default)]
     pub minimum_lock_time: MinimumLockTime,
+    /// If present, the owner spends with a quorum of keys instead of the key-path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    owner_quorum: Option<OwnerQuorum>,
+}
+
+impl core::hash::Hash for HeritageConfig {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.heritages.hash(state);
+        self.reference_timestamp.hash(state);
+        self.minimum_lock_time.hash(state);
+        // Only hashed when present so that the Hash of the existing HeritageConfig does not change
+        if let Some(owner_quorum) = &self.owner_quorum {
+            "oq".hash(state);
+            owner_quorum.hash(state);
+        }
+    }
 }
 
 impl HeritageConfig {
@@ -254,6 +391,60 @@ impl HeritageConfig {
     pub(crate) fn builder() -> HeritageConfigBuilder {
         HeritageConfigBuilder::default()
     }
+
+    pub fn owner_quorum(&self) -> Option<&OwnerQuorum> {
+        self.owner_quorum.as_ref()
+    }
+
+    /// Return a copy of this [HeritageConfig] where `new_heir_config` takes the place of
+    /// `old_heir_config`, with the same time lock and spending quota. Everything else,
+    /// including the reference timestamp, is kept so the maturity dates do not change.
+    ///
+    /// # Errors
+    /// Returns an error if `old_heir_config` is not in the [HeritageConfig] or if
+    /// `new_heir_config` already is.
+    pub fn with_replaced_heir(
+        &self,
+        old_heir_config: &HeirConfig,
+        new_heir_config: HeirConfig,
+    ) -> crate::errors::Result<HeritageConfig> {
+        if !self
+            .iter_heritages()
+            .any(|h| h.heir_config == *old_heir_config)
+        {
+            return Err(Error::InvalidHeritageConfig(
+                "the heir to replace is not in the HeritageConfig",
+            ));
+        }
+        if self
+            .iter_heritages()
+            .any(|h| h.heir_config == new_heir_config)
+        {
+            return Err(Error::InvalidHeritageConfig(
+                "the new heir is already in the HeritageConfig",
+            ));
+        }
+        let heritages = self
+            .iter_heritages()
+            .map(|h| {
+                if h.heir_config == *old_heir_config {
+                    Heritage {
+                        heir_config: new_heir_config.clone(),
+                        ..h.clone()
+                    }
+                } else {
+                    h.clone()
+                }
+            })
+            .collect();
+        let mut heritages = Heritages(heritages);
+        heritages.normalize();
+        Ok(HeritageConfig {
+            heritages,
+            ..self.clone()
+        })
+    }
+
     pub fn descriptor_taptree_miniscript_expression_for_child(
         &self,
         index: Option<u32>,
@@ -432,9 +623,19 @@ fn re_v1_fragment() -> &'static regex::Regex {
 
 impl super::FromDescriptorScripts for HeritageConfig {
     fn from_descriptor_scripts(scripts: &str) -> crate::errors::Result<Self> {
-        let script_fragments = fragment_scripts(scripts);
+        let mut script_fragments = fragment_scripts(scripts);
+        let mut builder = HeritageConfigBuilder::default();
+        // If present, the owner quorum leaf is always the first one
+        if script_fragments
+            .first()
+            .is_some_and(|fragment| fragment.starts_with("multi_a("))
+        {
+            let (_, owner_quorum) = OwnerQuorum::from_descriptor_fragment(script_fragments[0])?;
+            builder = builder.owner_quorum(owner_quorum);
+            script_fragments.remove(0);
+        }
         if script_fragments.len() == 0 {
-            return Ok(HeritageConfigBuilder::default().build_v1());
+            return Ok(builder.build_v1());
         }
         let mut heritage_parts = script_fragments
             .into_iter()
@@ -501,7 +702,7 @@ impl super::FromDescriptorScripts for HeritageConfig {
             })
             .collect::<crate::errors::Result<Vec<_>>>()?;
 
-        Ok(HeritageConfigBuilder::default()
+        Ok(builder
             .reference_time(reference_time)
             .minimum_lock_time(min_lock_time_days)
             .expand_heritages(heritages)
@@ -517,6 +718,7 @@ pub struct HeritageConfigBuilder {
     // This is the number of days we want to enforce before an heir can consumme an input
     // It exist in case an old address with an old absolute locktime is used
     minimum_lock_time: MinimumLockTime,
+    owner_quorum: Option<OwnerQuorum>,
 }
 
 impl HeritageConfigBuilder {
@@ -537,9 +739,44 @@ impl HeritageConfigBuilder {
         self.minimum_lock_time = MinimumLockTime(Days(minimum_lock_time));
         self
     }
+    /// Require a quorum of keys for the owner to spend, see [OwnerQuorum]
+    pub fn owner_quorum(mut self, owner_quorum: OwnerQuorum) -> Self {
+        self.owner_quorum = Some(owner_quorum);
+        self
+    }
     pub fn build(self) -> super::HeritageConfig {
         super::HeritageConfig(super::InnerHeritageConfig::V1(self.build_v1()))
     }
+    /// Same as [HeritageConfigBuilder::build] but verifies that the [HeritageConfig] would be
+    /// valid, instead of silently deduplicating the [Heritage]s or producing a
+    /// [HeritageConfig] that cannot generate descriptors.
+    ///
+    /// # Errors
+    /// Returns an error if:
+    /// - two [Heritage]s have the same [HeirConfig] or the same time lock
+    /// - the minimum lock time is less than 10 days
+    pub fn try_build(self) -> crate::errors::Result<super::HeritageConfig> {
+        let mut heir_configs = HashSet::new();
+        let mut time_locks = HashSet::new();
+        for heritage in self.heritages.iter() {
+            if !heir_configs.insert(&heritage.heir_config) {
+                return Err(crate::errors::Error::InvalidHeritageConfig(
+                    "the same heir is present more than once",
+                ));
+            }
+            if !time_locks.insert(heritage.time_lock.0) {
+                return Err(crate::errors::Error::InvalidHeritageConfig(
+                    "multiple heirs have the same time lock",
+                ));
+            }
+        }
+        if self.minimum_lock_time.0 .0 < 10 {
+            return Err(crate::errors::Error::InvalidHeritageConfig(
+                "the minimum lock time cannot be less than 10 days",
+            ));
+        }
+        Ok(self.build())
+    }
     pub fn build_v1(self) -> HeritageConfig {
         // Create Heritages from the Vec of Heritage and normalize it
         let mut heritages = Heritages(self.heritages);
@@ -548,6 +785,7 @@ impl HeritageConfigBuilder {
             heritages,
             reference_timestamp: self.reference_timestamp,
             minimum_lock_time: self.minimum_lock_time,
+            owner_quorum: self.owner_quorum,
         }
     }
 }
@@ -578,8 +816,7 @@ impl<'a> super::HeritageExplorerTrait for HeritageExplorer<'a> {
     fn has_fingerprint(&self, fingerprint: Fingerprint) -> bool {
         self.heritage_config.heritages.0[self.heritage_index]
             .get_heir_config()
-            .fingerprint()
-            == fingerprint
+            .has_fingerprint(fingerprint)
     }
 
     fn get_miniscript_expression<'b>(
@@ -941,6 +1178,80 @@ mod tests {
         );
     }
 
+    #[test]
+    fn multisig_heir() {
+        use crate::{
+            account_xpub::AccountXPub,
+            bitcoin::bip32::ChildNumber,
+            heritage_config::{
+                heirtypes::{HeirConfig, MultisigHeirXPubkey},
+                HeritageExplorerTrait,
+            },
+            miniscript::ToPublicKey,
+            subwallet_config::SubwalletConfig,
+        };
+        let HeirConfig::HeirXPubkey(backup_xpub) =
+            get_test_heritage(TestHeritage::Backup).heir_config
+        else {
+            unreachable!("Backup is an HeirXPubkey")
+        };
+        let other_xpub = get_test_account_xpub(1);
+        let multisig_heir_config = HeirConfig::MultisigHeirXPubkey(
+            MultisigHeirXPubkey::new(2, vec![backup_xpub.clone(), other_xpub.clone()]).unwrap(),
+        );
+        let hc = HeritageConfigV1::builder()
+            .add_heritage(super::Heritage::new(multisig_heir_config.clone()).time_lock(365))
+            .add_heritage(get_test_heritage(TestHeritage::Wife).time_lock(400))
+            .reference_time(1700000000)
+            .minimum_lock_time(90)
+            .build();
+
+        let fragment = hc
+            .descriptor_taptree_miniscript_expression_for_child(None)
+            .unwrap();
+        assert!(fragment.starts_with(&format!(
+            "{{and_v(v:multi_a(2,{backup_xpub},{other_xpub}),and_v(v:older(12960),after(1731536000))),"
+        )));
+        // Stable when restored from the script fragments
+        let restored_hc = HeritageConfigV1::from_descriptor_scripts(&fragment).unwrap();
+        assert_eq!(
+            fragment,
+            restored_hc
+                .descriptor_taptree_miniscript_expression_for_child(None)
+                .unwrap()
+        );
+        // Produces a valid descriptor
+        SubwalletConfig::create_descriptors(&get_test_account_xpub(0), &hc, 0, 1);
+
+        // The explorer must retain every key of the multisig heir
+        let he = hc.get_heritage_explorer(&multisig_heir_config).unwrap();
+        let fingerprint = |xpub: &AccountXPub| xpub.descriptor_public_key().master_fingerprint();
+        assert!(he.has_fingerprint(fingerprint(&backup_xpub)));
+        assert!(he.has_fingerprint(fingerprint(&other_xpub)));
+        let paths = [&backup_xpub, &other_xpub].map(|xpub| {
+            xpub.descriptor_public_key()
+                .full_derivation_path()
+                .unwrap()
+                .extend([ChildNumber::from(0), ChildNumber::from(3)])
+        });
+        let fingerprints = [fingerprint(&backup_xpub), fingerprint(&other_xpub)];
+        let origins = fingerprints.iter().zip(paths.iter());
+        let concrete_key = |xpub: &AccountXPub| {
+            xpub.child_descriptor_public_key(0)
+                .at_derivation_index(3)
+                .unwrap()
+                .to_x_only_pubkey()
+        };
+        assert_eq!(
+            he.get_miniscript_expression(origins),
+            format!(
+                "and_v(v:multi_a(2,{},{}),and_v(v:older(12960),after(1731536000)))",
+                concrete_key(&backup_xpub),
+                concrete_key(&other_xpub)
+            )
+        );
+    }
+
     #[test]
     fn fragment_scripts() {
         // Test empty fragment
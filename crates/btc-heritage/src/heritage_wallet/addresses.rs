@@ -0,0 +1,371 @@
+//! Lazy and paginated listing of the addresses of an [HeritageWallet].
+//!
+//! The addresses are produced in the order of [HeritageWallet::list_wallet_addresses]: the
+//! external addresses of the current subwallet, from the most recent, then its change
+//! addresses, then the external and change addresses of the previous subwallets.
+//!
+//! [HeritageWallet::list_wallet_addresses_filtered] restricts the listing to a subwallet,
+//! a keychain and a range of indexes, reading only the matching addresses.
+
+use core::ops::{Bound, RangeBounds};
+use std::collections::{HashMap, VecDeque};
+
+use bdk::{database::Database, KeychainKind};
+
+use crate::{
+    bitcoin::{
+        bip32::{ChildNumber, DerivationPath, Fingerprint},
+        Address, Network,
+    },
+    database::{
+        paginate::{ContinuationToken, Paginated},
+        PartitionableDatabase, SubdatabaseId, TransacHeritageDatabase,
+    },
+    errors::{DatabaseError, Error, Result},
+    subwallet_config::{SubwalletConfig, SubwalletId},
+};
+
+use super::{HeritageWallet, SubwalletConfigId, WalletAddress};
+
+/// The addresses of one keychain of one subwallet, iterated from the highest index
+struct AddressSegment<S> {
+    subdatabase: S,
+    fingerprint: Fingerprint,
+    subwallet_id: SubwalletId,
+    keychain: KeychainKind,
+    derivation_path: DerivationPath,
+    next_index: Option<u32>,
+    /// The lowest index to produce
+    min_index: u32,
+}
+
+/// The criteria of [HeritageWallet::list_wallet_addresses_filtered]
+#[derive(Debug, Clone, Copy)]
+struct AddressFilter {
+    subwallet_id: Option<SubwalletId>,
+    keychain: Option<KeychainKind>,
+    min_index: u32,
+    max_index: u32,
+}
+
+impl AddressFilter {
+    const ALL: AddressFilter = AddressFilter {
+        subwallet_id: None,
+        keychain: None,
+        min_index: 0,
+        max_index: u32::MAX,
+    };
+
+    /// Return [None] if no index can be in `index_range`
+    fn new(
+        subwallet_id: Option<SubwalletId>,
+        keychain: Option<KeychainKind>,
+        index_range: impl RangeBounds<u32>,
+    ) -> Option<Self> {
+        let min_index = match index_range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+        let max_index = match index_range.end_bound() {
+            Bound::Included(end) => *end,
+            Bound::Excluded(end) => end.checked_sub(1)?,
+            Bound::Unbounded => u32::MAX,
+        };
+        (min_index <= max_index).then_some(AddressFilter {
+            subwallet_id,
+            keychain,
+            min_index,
+            max_index,
+        })
+    }
+}
+
+/// The position of an address in the iteration, serialized in a [ContinuationToken]
+/// as `<subwallet_id>:<keychain>:<index>` with `0` for the external keychain and
+/// `1` for the change keychain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AddressPosition {
+    subwallet_id: SubwalletId,
+    keychain: KeychainKind,
+    index: u32,
+}
+
+impl From<AddressPosition> for ContinuationToken {
+    fn from(value: AddressPosition) -> Self {
+        let keychain = match value.keychain {
+            KeychainKind::External => 0,
+            KeychainKind::Internal => 1,
+        };
+        ContinuationToken(format!("{}:{keychain}:{}", value.subwallet_id, value.index))
+    }
+}
+
+impl TryFrom<&ContinuationToken> for AddressPosition {
+    type Error = Error;
+
+    fn try_from(value: &ContinuationToken) -> Result<Self> {
+        let invalid = || Error::InvalidContinuationToken(value.0.clone());
+        let mut parts = value.0.split(':');
+        let (Some(subwallet_id), Some(keychain), Some(index), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        Ok(AddressPosition {
+            subwallet_id: subwallet_id.parse().map_err(|_| invalid())?,
+            keychain: match keychain {
+                "0" => KeychainKind::External,
+                "1" => KeychainKind::Internal,
+                _ => return Err(invalid()),
+            },
+            index: index.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A lazy iterator over the [WalletAddress]es of an [HeritageWallet],
+/// see [HeritageWallet::iter_wallet_addresses]
+pub struct WalletAddressIter<D: TransacHeritageDatabase> {
+    network: Network,
+    address_labels: HashMap<Address, String>,
+    segments: VecDeque<AddressSegment<<D as PartitionableDatabase>::SubDatabase>>,
+}
+
+impl<D: TransacHeritageDatabase> WalletAddressIter<D> {
+    /// Return the position of the next address, if any
+    fn next_position(&self) -> Option<AddressPosition> {
+        self.segments.front().and_then(|segment| {
+            segment.next_index.map(|index| AddressPosition {
+                subwallet_id: segment.subwallet_id,
+                keychain: segment.keychain,
+                index,
+            })
+        })
+    }
+
+    /// Skip the addresses preceding `position`
+    fn seek(&mut self, position: AddressPosition) -> Result<()> {
+        while let Some(segment) = self.segments.front_mut() {
+            if segment.subwallet_id == position.subwallet_id
+                && segment.keychain == position.keychain
+            {
+                segment.next_index = segment.next_index.map(|index| index.min(position.index));
+                return Ok(());
+            }
+            self.segments.pop_front();
+        }
+        Err(Error::InvalidContinuationToken(
+            ContinuationToken::from(position).0,
+        ))
+    }
+
+    fn next_address(&mut self) -> Option<Result<WalletAddress>> {
+        loop {
+            let segment = self.segments.front_mut()?;
+            let Some(index) = segment.next_index else {
+                self.segments.pop_front();
+                continue;
+            };
+            let min_index = segment.min_index;
+            segment.next_index = index
+                .checked_sub(1)
+                .filter(|next_index| *next_index >= min_index);
+            let script_pubkey = match segment
+                .subdatabase
+                .get_script_pubkey_from_path(segment.keychain, index)
+            {
+                Ok(Some(script_pubkey)) => script_pubkey,
+                Ok(None) => {
+                    let e = format!(
+                        "Missing script pubkey {:?}/{index} of subwallet {}",
+                        segment.keychain, segment.subwallet_id
+                    );
+                    return Some(Err(DatabaseError::Generic(e).into()));
+                }
+                Err(e) => return Some(Err(DatabaseError::Generic(e.to_string()).into())),
+            };
+            let address = Address::from_script(script_pubkey.as_script(), self.network)
+                .expect("script should always be valid from the correct network inside the DB");
+            let origin = (
+                segment.fingerprint,
+                segment.derivation_path.child(ChildNumber::Normal { index }),
+            );
+            if segment.next_index.is_none() {
+                self.segments.pop_front();
+            }
+            let label = self.address_labels.remove(&address);
+            return Some(Ok(WalletAddress {
+                origin,
+                address,
+                label,
+            }));
+        }
+    }
+}
+
+impl<D: TransacHeritageDatabase> Iterator for WalletAddressIter<D> {
+    type Item = Result<WalletAddress>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_address()
+    }
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Return an iterator over the [WalletAddress]es of the wallet, in the order of
+    /// [HeritageWallet::list_wallet_addresses]. The addresses are only read from the
+    /// database when the iterator reaches them.
+    pub fn iter_wallet_addresses(&self) -> Result<WalletAddressIter<D>> {
+        log::debug!("HeritageWallet::iter_wallet_addresses");
+        self.iter_wallet_addresses_with_filter(Some(AddressFilter::ALL))
+    }
+
+    /// Return the [WalletAddress]es of the wallet matching every criteria, in the order of
+    /// [HeritageWallet::list_wallet_addresses]:
+    /// - `subwallet_id`: only the addresses of this subwallet, see [SubwalletConfig::subwallet_id]
+    /// - `keychain`: only the external ([KeychainKind::External]) or change
+    /// ([KeychainKind::Internal]) addresses
+    /// - `index_range`: only the addresses whose derivation index is in this range
+    ///
+    /// Only the matching addresses are read from the database.
+    pub fn list_wallet_addresses_filtered(
+        &self,
+        subwallet_id: Option<SubwalletId>,
+        keychain: Option<KeychainKind>,
+        index_range: impl RangeBounds<u32>,
+    ) -> Result<Vec<WalletAddress>> {
+        log::debug!(
+            "HeritageWallet::list_wallet_addresses_filtered - subwallet_id={subwallet_id:?} \
+            keychain={keychain:?} index_range=({:?}, {:?})",
+            index_range.start_bound(),
+            index_range.end_bound()
+        );
+        self.iter_wallet_addresses_with_filter(AddressFilter::new(
+            subwallet_id,
+            keychain,
+            index_range,
+        ))?
+        .collect()
+    }
+
+    /// A [None] `filter` produces no address
+    fn iter_wallet_addresses_with_filter(
+        &self,
+        filter: Option<AddressFilter>,
+    ) -> Result<WalletAddressIter<D>> {
+        let mut segments = VecDeque::new();
+        // No fingerprint means no AccountXPub, so no address either
+        if let (Some(filter), Some(fingerprint)) = (filter, self.fingerprint()?) {
+            let mut subwallet_configs = self.database.borrow().list_obsolete_subwallet_configs()?;
+            subwallet_configs.extend(
+                self.database
+                    .borrow()
+                    .get_subwallet_config(SubwalletConfigId::Current)?,
+            );
+            for swc in subwallet_configs.iter().rev().filter(|swc| {
+                filter
+                    .subwallet_id
+                    .map_or(true, |id| id == swc.subwallet_id())
+            }) {
+                segments.extend(self.address_segments(fingerprint, swc, filter)?);
+            }
+        }
+        Ok(WalletAddressIter {
+            network: self.network,
+            address_labels: self.address_labels()?,
+            segments,
+        })
+    }
+
+    /// Return a page of at most `page_size` [WalletAddress]es of the wallet, in the order
+    /// of [HeritageWallet::list_wallet_addresses], starting at `continuation_token` or at
+    /// the first address if [None].
+    ///
+    /// The [ContinuationToken] designates an address, so new addresses generated between
+    /// two calls do not shift the pages.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidContinuationToken] if `continuation_token` does not designate
+    /// an address of the wallet.
+    pub fn list_wallet_addresses_paginated(
+        &self,
+        page_size: usize,
+        continuation_token: Option<ContinuationToken>,
+    ) -> Result<Paginated<WalletAddress>> {
+        log::debug!(
+            "HeritageWallet::list_wallet_addresses_paginated - \
+            page_size={page_size} continuation_token={continuation_token:?}"
+        );
+        let mut iter = self.iter_wallet_addresses()?;
+        if let Some(continuation_token) = &continuation_token {
+            iter.seek(AddressPosition::try_from(continuation_token)?)?;
+        }
+        let page = iter.by_ref().take(page_size).collect::<Result<Vec<_>>>()?;
+        Ok(Paginated {
+            page,
+            continuation_token: iter.next_position().map(ContinuationToken::from),
+        })
+    }
+
+    /// Return the [AddressSegment]s of the subwallet matching the [AddressFilter],
+    /// external keychain first, skipping the keychains without any matching address
+    fn address_segments(
+        &self,
+        fingerprint: Fingerprint,
+        subwallet_config: &SubwalletConfig,
+        filter: AddressFilter,
+    ) -> Result<Vec<AddressSegment<<D as PartitionableDatabase>::SubDatabase>>> {
+        // Retrieve the derivation path of the account xpub
+        let axpub_dp = subwallet_config
+            .account_xpub()
+            .descriptor_public_key()
+            .full_derivation_path()
+            .expect("DerivationPath is present for an Account Xpub");
+        let ext_dp = axpub_dp.child(ChildNumber::Normal { index: 0 });
+        // Key-path-only change addresses are derived on their own branch
+        let change_dp = axpub_dp.child(ChildNumber::Normal {
+            index: if subwallet_config.has_keypath_change() {
+                SubwalletConfig::DEFAULT_KEYPATH_CHANGE_INDEX
+            } else {
+                1
+            },
+        });
+
+        let subwallet_id = subwallet_config.subwallet_id();
+        [
+            (KeychainKind::External, ext_dp),
+            (KeychainKind::Internal, change_dp),
+        ]
+        .into_iter()
+        .filter(|(keychain, _)| filter.keychain.map_or(true, |kc| kc == *keychain))
+        .filter_map(|(keychain, derivation_path)| {
+            let subdatabase = match self
+                .database
+                .borrow()
+                .get_subdatabase(SubdatabaseId::from(subwallet_id))
+            {
+                Ok(subdatabase) => subdatabase,
+                Err(e) => return Some(Err(e.into())),
+            };
+            match subdatabase.get_last_index(keychain) {
+                Ok(last_index) => last_index
+                    .map(|last_index| last_index.min(filter.max_index))
+                    .filter(|first_index| *first_index >= filter.min_index)
+                    .map(|first_index| {
+                        Ok(AddressSegment {
+                            subdatabase,
+                            fingerprint,
+                            subwallet_id,
+                            keychain,
+                            derivation_path,
+                            next_index: Some(first_index),
+                            min_index: filter.min_index,
+                        })
+                    }),
+                Err(e) => Some(Err(DatabaseError::Generic(e.to_string()).into())),
+            }
+        })
+        .collect()
+    }
+}
@@ -0,0 +1,257 @@
+//! An executor-agnostic async facade of the [HeritageWallet].
+//!
+//! The [HeritageWallet] and its database are synchronous and the blockchain synchronization
+//! blocks on network I/O. [AsyncHeritageWallet] moves the wallet to a dedicated worker thread
+//! and runs every operation there, in the order they are submitted, returning a [Future]
+//! that completes with the result. No async runtime is required: the futures can be awaited
+//! from any executor without `spawn_blocking`.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+use std::{
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use crate::{
+    bitcoin::{psbt::Psbt, Address},
+    database::TransacHeritageDatabase,
+    errors::Result,
+    HeirConfig, HeritageConfig,
+};
+
+use super::{
+    CreatePsbtOptions, HeritageWallet, HeritageWalletBalance, SpendingConfig, TransactionSummary,
+};
+
+type Job<D> = Box<dyn FnOnce(&HeritageWallet<D>) + Send>;
+
+struct ReplyState<T> {
+    value: Option<std::thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+/// The [Future] of an operation submitted to an [AsyncHeritageWallet]
+pub struct WalletTask<T> {
+    state: Arc<Mutex<ReplyState<T>>>,
+}
+
+impl<T> Future for WalletTask<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().expect("wallet task state lock poisoned");
+        match state.value.take() {
+            Some(Ok(value)) => Poll::Ready(value),
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// An async facade of an [HeritageWallet], see the [module documentation](self)
+pub struct AsyncHeritageWallet<D: TransacHeritageDatabase + 'static> {
+    sender: Option<mpsc::Sender<Job<D>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<D: TransacHeritageDatabase + 'static> AsyncHeritageWallet<D> {
+    /// Move the [HeritageWallet] to a new worker thread
+    pub fn new(wallet: HeritageWallet<D>) -> Self
+    where
+        D: Send,
+    {
+        Self::with_builder(move || wallet)
+    }
+
+    /// Create the [HeritageWallet] with `builder` on a new worker thread, allowing
+    /// to use a database that cannot be sent across threads
+    pub fn with_builder<B>(builder: B) -> Self
+    where
+        B: FnOnce() -> HeritageWallet<D> + Send + 'static,
+    {
+        log::debug!("AsyncHeritageWallet::with_builder");
+        let (sender, receiver) = mpsc::channel::<Job<D>>();
+        let worker = std::thread::Builder::new()
+            .name("heritage-wallet".to_owned())
+            .spawn(move || {
+                let wallet = builder();
+                for job in receiver {
+                    job(&wallet);
+                }
+                log::debug!("AsyncHeritageWallet - worker stopped");
+            })
+            .expect("failed to spawn the HeritageWallet worker thread");
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Run `f` with the [HeritageWallet] on the worker thread, after every previously
+    /// submitted operation, and return its result
+    ///
+    /// # Panics
+    /// The returned [WalletTask] panics when polled if `f` panicked. The worker thread
+    /// survives the panic and keeps serving the other operations.
+    pub fn call<F, R>(&self, f: F) -> WalletTask<R>
+    where
+        F: FnOnce(&HeritageWallet<D>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(ReplyState {
+            value: None,
+            waker: None,
+        }));
+        let reply = state.clone();
+        let job: Job<D> = Box::new(move |wallet| {
+            let value = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(wallet)));
+            let mut state = reply.lock().expect("wallet task state lock poisoned");
+            state.value = Some(value);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        self.sender
+            .as_ref()
+            .expect("sender is only taken on drop")
+            .send(job)
+            .expect("the HeritageWallet worker thread should be running");
+        WalletTask { state }
+    }
+
+    /// See [HeritageWallet::get_balance]
+    pub async fn get_balance(&self) -> Result<HeritageWalletBalance> {
+        self.call(|wallet| wallet.get_balance()).await
+    }
+
+    /// See [HeritageWallet::get_new_address]
+    pub async fn get_new_address(&self) -> Result<Address> {
+        self.call(|wallet| wallet.get_new_address()).await
+    }
+
+    /// See [HeritageWallet::list_transaction_summaries]
+    pub async fn list_transaction_summaries(&self) -> Result<Vec<TransactionSummary>> {
+        self.call(|wallet| wallet.list_transaction_summaries())
+            .await
+    }
+
+    /// See [HeritageWallet::update_heritage_config]
+    pub async fn update_heritage_config(&self, new_heritage_config: HeritageConfig) -> Result<()> {
+        self.call(move |wallet| wallet.update_heritage_config(new_heritage_config))
+            .await
+    }
+
+    /// See [HeritageWallet::create_owner_psbt]
+    pub async fn create_owner_psbt(
+        &self,
+        spending_config: SpendingConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<(Psbt, TransactionSummary)> {
+        self.call(move |wallet| wallet.create_owner_psbt(spending_config, options))
+            .await
+    }
+
+    /// See [HeritageWallet::create_heir_psbt]
+    pub async fn create_heir_psbt(
+        &self,
+        heir_config: HeirConfig,
+        spending_config: SpendingConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<(Psbt, TransactionSummary)> {
+        self.call(move |wallet| wallet.create_heir_psbt(heir_config, spending_config, options))
+            .await
+    }
+}
+
+#[cfg(any(feature = "online", test))]
+impl<D: TransacHeritageDatabase + 'static> AsyncHeritageWallet<D> {
+    /// See [HeritageWallet::sync]
+    pub async fn sync<T>(&self, blockchain_factory: T) -> Result<()>
+    where
+        T: bdk::blockchain::BlockchainFactory + Send + 'static,
+    {
+        self.call(move |wallet| wallet.sync(&blockchain_factory))
+            .await
+    }
+
+    /// See [HeritageWallet::sync_fee_rate]
+    pub async fn sync_fee_rate<F>(&self, fee_estimator: F) -> Result<crate::bitcoin::FeeRate>
+    where
+        F: super::online::fee_estimator::FeeEstimator + Send + 'static,
+    {
+        self.call(move |wallet| wallet.sync_fee_rate(&fee_estimator))
+            .await
+    }
+}
+
+impl<D: TransacHeritageDatabase + 'static> Drop for AsyncHeritageWallet<D> {
+    /// Wait for the submitted operations to complete and stop the worker thread
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                log::error!("AsyncHeritageWallet - the worker thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Wake;
+
+    use super::*;
+    use crate::{bitcoin::Network, database::memory::HeritageMemoryDatabase, errors::Error};
+
+    struct ThreadWaker(std::thread::Thread);
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn async_heritage_wallet() {
+        let wallet = AsyncHeritageWallet::new(
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap(),
+        );
+        assert_eq!(
+            block_on(wallet.get_balance()).unwrap(),
+            HeritageWalletBalance::default()
+        );
+        // Without HeritageConfig, the wallet cannot produce addresses
+        assert!(matches!(
+            block_on(wallet.get_new_address()),
+            Err(Error::MissingCurrentSubwalletConfig)
+        ));
+        // Operations are executed in order on the worker thread
+        let first = wallet.call(|_| std::thread::current().name().map(str::to_owned));
+        let second = wallet.call(|wallet| wallet.get_balance().is_ok());
+        assert!(block_on(second));
+        assert_eq!(block_on(first).as_deref(), Some("heritage-wallet"));
+        // A panicking operation does not stop the worker thread
+        let task = wallet.call(|_| panic!("operation failure"));
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| block_on(task))).is_err());
+        assert!(block_on(wallet.get_balance()).is_ok());
+    }
+}
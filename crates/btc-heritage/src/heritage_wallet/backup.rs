@@ -1,10 +1,27 @@
-use std::collections::HashSet;
+use core::str::FromStr;
+use std::collections::{BTreeMap, HashSet};
+
+#[cfg(feature = "backup-encryption")]
+mod encrypted;
+#[cfg(feature = "backup-encryption")]
+pub use encrypted::{Argon2idParams, EncryptedHeritageWalletBackup};
 
 use crate::errors::Error;
-use crate::miniscript::{Descriptor, DescriptorPublicKey};
+use crate::miniscript::{descriptor::Tr, Descriptor, DescriptorPublicKey};
+use crate::subwallet_config::SubwalletConfig;
 
 use crate::bitcoin::bip32::Fingerprint;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Version of the [HeritageWalletBackup] serialization format produced by this crate
+///
+/// - `0`: unversioned bare list of [SubwalletDescriptorBackup] (before versioning)
+/// - `1`: `{"version": 1, "subwallets": [...]}`
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Number of addresses to watch beyond the last used index when exporting descriptors,
+/// matching the default keypool size of Bitcoin Core
+const CORE_DESCRIPTOR_LOOKAHEAD: u32 = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(any(test, feature = "database-tests"), derive(Eq, PartialEq))]
@@ -17,6 +34,10 @@ pub struct SubwalletDescriptorBackup {
     pub last_external_index: Option<u32>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub last_change_index: Option<u32>,
+    /// The key-path-only descriptor receiving the change outputs, if the subwallet uses one.
+    /// In that case, `last_change_index` refers to this descriptor.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keypath_change_descriptor: Option<Descriptor<DescriptorPublicKey>>,
 }
 impl SubwalletDescriptorBackup {
     /// Return the [Fingerprint] of this [SubwalletDescriptorBackup]
@@ -31,20 +52,184 @@ impl SubwalletDescriptorBackup {
         let Descriptor::Tr(tr_change) = &self.change_descriptor else {
             return Err(Error::InvalidBackup("change descriptor not Tr"));
         };
-        let fingerprint = tr_ext.internal_key().master_fingerprint();
-        if fingerprint != tr_change.internal_key().master_fingerprint() {
+        let owner_fingerprint = |tr: &Tr<DescriptorPublicKey>| {
+            if tr.internal_key().to_string() == SubwalletConfig::UNSPENDABLE_INTERNAL_KEY {
+                // The owner is a quorum, the key of the wallet is the first key of the owner leaf
+                tr.iter_scripts()
+                    .next()
+                    .and_then(|(_, ms)| ms.iter_pk().next())
+                    .map(|pk| pk.master_fingerprint())
+                    .ok_or(Error::InvalidBackup("owner quorum leaf is missing"))
+            } else {
+                Ok(tr.internal_key().master_fingerprint())
+            }
+        };
+        let fingerprint = owner_fingerprint(tr_ext)?;
+        if fingerprint != owner_fingerprint(tr_change)? {
             return Err(Error::InvalidBackup(
                 "ext and change descriptors have different keys",
             ));
         }
         Ok(fingerprint)
     }
+
+    /// Return the [CoreDescriptorImport] of the external and change descriptors of
+    /// this [SubwalletDescriptorBackup], in that order. If the subwallet uses a
+    /// `keypath_change_descriptor`, it replaces the change descriptor.
+    ///
+    /// `active` should only be `true` for the current subwallet, as Bitcoin Core only
+    /// accepts one active descriptor per output type and keychain.
+    pub fn core_descriptors(&self, active: bool) -> [CoreDescriptorImport; 2] {
+        let timestamp = self
+            .first_use_ts
+            .map(CoreImportTimestamp::Time)
+            .unwrap_or(CoreImportTimestamp::Now);
+        let import = |descriptor: &Descriptor<DescriptorPublicKey>,
+                      internal: bool,
+                      last_index: Option<u32>| {
+            CoreDescriptorImport {
+                desc: descriptor.to_string(),
+                timestamp,
+                active,
+                internal,
+                range: (
+                    0,
+                    last_index.map(|i| i + 1).unwrap_or(0) + CORE_DESCRIPTOR_LOOKAHEAD,
+                ),
+                next_index: active.then(|| last_index.map(|i| i + 1).unwrap_or(0)),
+            }
+        };
+        [
+            import(&self.external_descriptor, false, self.last_external_index),
+            import(
+                self.keypath_change_descriptor
+                    .as_ref()
+                    .unwrap_or(&self.change_descriptor),
+                true,
+                self.last_change_index,
+            ),
+        ]
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(transparent)]
+/// The `timestamp` of a descriptor import request of Bitcoin Core
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoreImportTimestamp {
+    /// Bitcoin Core will not rescan the blockchain for this descriptor
+    Now,
+    /// Bitcoin Core will rescan the blockchain from this UNIX timestamp
+    Time(u64),
+}
+impl Serialize for CoreImportTimestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            CoreImportTimestamp::Now => serializer.serialize_str("now"),
+            CoreImportTimestamp::Time(ts) => serializer.serialize_u64(*ts),
+        }
+    }
+}
+
+/// One element of the JSON array expected by the `importdescriptors` RPC of Bitcoin Core
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CoreDescriptorImport {
+    /// The descriptor, including its checksum
+    pub desc: String,
+    pub timestamp: CoreImportTimestamp,
+    pub active: bool,
+    pub internal: bool,
+    pub range: (u32, u32),
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_index: Option<u32>,
+}
+
+/// Export of a wallet in the generic "wallet export" JSON format, understood by
+/// tools like Sparrow to create a watch-only wallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalletExport {
+    /// Arbitrary label for the wallet
+    pub label: String,
+    /// Earliest block to rescan when looking for the wallet's transactions
+    pub blockheight: u32,
+    /// The external descriptor of the wallet, including its checksum
+    pub descriptor: String,
+}
+
+/// Backup of the descriptors of every subwallet of an [HeritageWallet](super::HeritageWallet)
+///
+/// It is serialized along with the [BACKUP_VERSION] of the format. Backups produced by
+/// older versions of the crate are migrated when deserialized, backups produced by newer
+/// versions are rejected.
+#[derive(Debug, Clone)]
 #[cfg_attr(any(test, feature = "database-tests"), derive(Eq, PartialEq))]
 pub struct HeritageWalletBackup(pub(super) Vec<SubwalletDescriptorBackup>);
+
+#[derive(Serialize)]
+struct VersionedBackupRef<'a> {
+    version: u32,
+    subwallets: &'a [SubwalletDescriptorBackup],
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyVersionBackup {
+    Unversioned(Vec<serde_json::Value>),
+    Versioned {
+        version: u32,
+        subwallets: Vec<serde_json::Value>,
+    },
+}
+
+impl Serialize for HeritageWalletBackup {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        VersionedBackupRef {
+            version: BACKUP_VERSION,
+            subwallets: &self.0,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for HeritageWalletBackup {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (version, subwallets) = match AnyVersionBackup::deserialize(deserializer)? {
+            AnyVersionBackup::Unversioned(subwallets) => (0, subwallets),
+            AnyVersionBackup::Versioned {
+                version,
+                subwallets,
+            } => (version, subwallets),
+        };
+        if version > BACKUP_VERSION {
+            return Err(serde::de::Error::custom(format!(
+                "backup format version {version} is not supported by this version \
+                of the library (max supported: {BACKUP_VERSION}), please upgrade"
+            )));
+        }
+        migrate_subwallet_backups(version, subwallets)
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<Vec<_>, _>>()
+            .map(HeritageWalletBackup)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Upgrade the serialized [SubwalletDescriptorBackup] of a backup from the format `version`
+/// to the current [BACKUP_VERSION], one version at a time
+fn migrate_subwallet_backups(
+    version: u32,
+    mut subwallets: Vec<serde_json::Value>,
+) -> Vec<serde_json::Value> {
+    for from_version in version..BACKUP_VERSION {
+        log::debug!("migrate_subwallet_backups - from_version={from_version}");
+        subwallets = match from_version {
+            // The content of the SubwalletDescriptorBackup did not change from 0 to 1,
+            // only the envelope did
+            0 => subwallets,
+            _ => unreachable!("every version below BACKUP_VERSION has a migration"),
+        };
+    }
+    subwallets
+}
 impl IntoIterator for HeritageWalletBackup {
     type Item = SubwalletDescriptorBackup;
     type IntoIter = <Vec<SubwalletDescriptorBackup> as IntoIterator>::IntoIter;
@@ -71,4 +256,166 @@ impl HeritageWalletBackup {
         }
         Ok(h_fingerprint.into_iter().next())
     }
+
+    /// Return the external and change descriptors of every subwallet of this
+    /// [HeritageWalletBackup], one per line, in a format suitable for a plain text
+    /// (or paper) backup that [HeritageWalletBackup::from_descriptors] can read back.
+    pub fn to_descriptors(&self) -> String {
+        self.0
+            .iter()
+            .flat_map(|sdb| [&sdb.external_descriptor, &sdb.change_descriptor])
+            .map(|desc| desc.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Reconstruct a best-effort [HeritageWalletBackup] from a list of Heritage descriptors,
+    /// one per line. Empty lines and lines starting with `#` are ignored.
+    ///
+    /// Descriptors are grouped by subwallet and the missing external or change descriptor of
+    /// a subwallet is inferred from the other one. Subwallets are ordered by account index,
+    /// the last one becoming the current subwallet when the backup is restored.
+    ///
+    /// The first use time and the address indexes are unknown, so every subwallet is
+    /// considered used and its addresses will be discovered by the synchronization.
+    ///
+    /// # Error
+    /// Return an error if a line is not a valid Heritage descriptor
+    pub fn from_descriptors(descriptors: &str) -> Result<Self, Error> {
+        // Subwallets indexed by their external descriptor (without checksum)
+        let mut subwallets: BTreeMap<String, (Option<String>, Option<String>)> = BTreeMap::new();
+        for line in descriptors.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let descriptor = Descriptor::<DescriptorPublicKey>::from_str(line)
+                .map_err(|_| Error::InvalidBackup("invalid descriptor"))?;
+            // Alternate display prevents the checksum to go in
+            let descriptor = format!("{descriptor:#}");
+            let external = descriptor.replace("/1/*", "/0/*");
+            let entry = subwallets.entry(external.clone()).or_default();
+            if descriptor == external {
+                entry.0 = Some(descriptor);
+            } else {
+                entry.1 = Some(descriptor);
+            }
+        }
+
+        let mut subwallets = subwallets
+            .into_iter()
+            .map(|(external, (_, change))| {
+                let change = change.unwrap_or_else(|| external.replace("/0/*", "/1/*"));
+                let sdb = SubwalletDescriptorBackup {
+                    external_descriptor: Descriptor::from_str(&external)
+                        .map_err(|_| Error::InvalidBackup("invalid descriptor"))?,
+                    change_descriptor: Descriptor::from_str(&change)
+                        .map_err(|_| Error::InvalidBackup("invalid descriptor"))?,
+                    first_use_ts: Some(0),
+                    last_external_index: None,
+                    last_change_index: None,
+                    keypath_change_descriptor: None,
+                };
+                // Ensure it is an Heritage descriptor and retrieve the account index
+                let subwallet_id = SubwalletConfig::try_from(&sdb)?.subwallet_id();
+                Ok((subwallet_id, sdb))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        subwallets.sort_by_key(|(subwallet_id, _)| *subwallet_id);
+
+        let backup = HeritageWalletBackup(subwallets.into_iter().map(|(_, sdb)| sdb).collect());
+        backup.fingerprint()?;
+        Ok(backup)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::*;
+
+    fn subwallet_backup_json() -> serde_json::Value {
+        serde_json::json!({
+            "external_descriptor": get_default_test_subwallet_config_expected_external_descriptor(
+                TestHeritageConfig::BackupWifeBro,
+            ),
+            "change_descriptor": get_default_test_subwallet_config_expected_change_descriptor(
+                TestHeritageConfig::BackupWifeBro,
+            ),
+            "first_use_ts": 1_700_000_000,
+            "last_external_index": 3,
+        })
+    }
+
+    #[test]
+    fn from_descriptors() {
+        let descriptors = [
+            TestHeritageConfig::BackupWifeBro,
+            TestHeritageConfig::BackupWifeY2,
+            TestHeritageConfig::BackupWifeY1,
+        ];
+        // Only the external descriptors, in disorder, with some noise
+        let text = descriptors
+            .iter()
+            .map(|thc| get_default_test_subwallet_config_expected_external_descriptor(*thc))
+            .collect::<Vec<_>>()
+            .join("\n\n# Comment\n");
+        let backup = HeritageWalletBackup::from_descriptors(&text).unwrap();
+
+        // Ordered by account index, with the change descriptors recovered
+        assert_eq!(backup.0.len(), 3);
+        for (sdb, thc) in backup.0.iter().zip([
+            TestHeritageConfig::BackupWifeY2,
+            TestHeritageConfig::BackupWifeY1,
+            TestHeritageConfig::BackupWifeBro,
+        ]) {
+            assert_eq!(
+                sdb.external_descriptor.to_string(),
+                get_default_test_subwallet_config_expected_external_descriptor(thc)
+            );
+            assert_eq!(
+                sdb.change_descriptor.to_string(),
+                get_default_test_subwallet_config_expected_change_descriptor(thc)
+            );
+            assert!(sdb.first_use_ts.is_some());
+        }
+
+        // Roundtrip through the text format
+        assert_eq!(
+            HeritageWalletBackup::from_descriptors(&backup.to_descriptors()).unwrap(),
+            backup
+        );
+
+        // Invalid descriptor
+        assert!(HeritageWalletBackup::from_descriptors("tr(invalid)").is_err());
+    }
+
+    #[test]
+    fn deserialize_unversioned_backup() {
+        let json = serde_json::json!([subwallet_backup_json()]);
+        let backup: HeritageWalletBackup = serde_json::from_value(json).unwrap();
+        assert_eq!(backup.0.len(), 1);
+        assert_eq!(backup.0[0].first_use_ts, Some(1_700_000_000));
+        assert_eq!(backup.0[0].last_external_index, Some(3));
+        assert_eq!(backup.0[0].last_change_index, None);
+    }
+
+    #[test]
+    fn serialization_roundtrip() {
+        let json = serde_json::json!({
+            "version": BACKUP_VERSION,
+            "subwallets": [subwallet_backup_json()]
+        });
+        let backup: HeritageWalletBackup = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&backup).unwrap(), json);
+    }
+
+    #[test]
+    fn deserialize_future_backup() {
+        let json = serde_json::json!({
+            "version": BACKUP_VERSION + 1,
+            "subwallets": [subwallet_backup_json()]
+        });
+        let err = serde_json::from_value::<HeritageWalletBackup>(json).unwrap_err();
+        assert!(err.to_string().contains("is not supported"), "{err}");
+    }
 }
@@ -0,0 +1,221 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
+    AeadCore, ChaCha20Poly1305, Key,
+};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use super::HeritageWalletBackup;
+use crate::{
+    bitcoin::hex::{DisplayHex, FromHex},
+    errors::{Error, Result},
+};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+// The KDF parameters are read from the backup, which may come from untrusted storage.
+// They are bounded so that a crafted backup cannot exhaust the memory or the CPU.
+// The bounds are well above the default parameters used to encrypt.
+const MAX_M_COST: u32 = 256 * 1024; // KiB, i.e. 256 MiB
+const MAX_T_COST: u32 = 16;
+const MAX_P_COST: u32 = 16;
+
+/// Parameters of the Argon2id key derivation used to derive the encryption key from the passphrase
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2idParams {
+    /// Memory cost, in KiB
+    pub m_cost: u32,
+    /// Number of iterations
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+    /// Hex-encoded salt
+    pub salt: String,
+}
+
+/// A [HeritageWalletBackup] encrypted with ChaCha20-Poly1305, using a key derived from a
+/// passphrase with Argon2id.
+///
+/// Unlike a [HeritageWalletBackup], it does not reveal the descriptors of the wallet and
+/// the keys of the heirs, so it can safely be stored on untrusted storage.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncryptedHeritageWalletBackup {
+    kdf: Argon2idParams,
+    /// Hex-encoded nonce
+    nonce: String,
+    /// Hex-encoded ciphertext of the JSON serialization of the [HeritageWalletBackup]
+    ciphertext: String,
+}
+
+impl EncryptedHeritageWalletBackup {
+    /// Encrypt a [HeritageWalletBackup] using the given passphrase
+    pub fn encrypt(backup: &HeritageWalletBackup, passphrase: &str) -> Result<Self> {
+        log::debug!("EncryptedHeritageWalletBackup::encrypt");
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let kdf = Argon2idParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+            salt: salt.as_slice().to_lower_hex_string(),
+        };
+        let cipher = Self::cipher(&kdf, passphrase)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext =
+            serde_json::to_vec(backup).map_err(|e| Error::BackupEncryptionError(e.to_string()))?;
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|e| Error::BackupEncryptionError(e.to_string()))?;
+        Ok(Self {
+            kdf,
+            nonce: nonce.as_slice().to_lower_hex_string(),
+            ciphertext: ciphertext.as_slice().to_lower_hex_string(),
+        })
+    }
+
+    /// Decrypt the [HeritageWalletBackup] using the given passphrase
+    ///
+    /// # Errors
+    /// Returns [Error::BackupDecryptionFailed] if the passphrase is wrong or if the
+    /// ciphertext was tampered with.
+    pub fn decrypt(&self, passphrase: &str) -> Result<HeritageWalletBackup> {
+        log::debug!("EncryptedHeritageWalletBackup::decrypt");
+        let cipher = Self::cipher(&self.kdf, passphrase)?;
+        let nonce: [u8; NONCE_LEN] = Vec::<u8>::from_hex(&self.nonce)
+            .ok()
+            .and_then(|nonce| nonce.try_into().ok())
+            .ok_or(Error::InvalidBackup("invalid nonce"))?;
+        let ciphertext = Vec::<u8>::from_hex(&self.ciphertext)
+            .map_err(|_| Error::InvalidBackup("invalid ciphertext"))?;
+        let plaintext = cipher
+            .decrypt(&nonce.into(), ciphertext.as_slice())
+            .map_err(|_| Error::BackupDecryptionFailed)?;
+        serde_json::from_slice(&plaintext)
+            .map_err(|_| Error::InvalidBackup("decrypted content is not a backup"))
+    }
+
+    fn cipher(kdf: &Argon2idParams, passphrase: &str) -> Result<ChaCha20Poly1305> {
+        let salt = Vec::<u8>::from_hex(&kdf.salt)
+            .ok()
+            .filter(|salt| salt.len() == SALT_LEN)
+            .ok_or(Error::InvalidBackup("invalid salt"))?;
+        if kdf.m_cost > MAX_M_COST || kdf.t_cost > MAX_T_COST || kdf.p_cost > MAX_P_COST {
+            log::error!(
+                "Argon2id parameters m_cost={} t_cost={} p_cost={} exceed the maximums \
+                m_cost={MAX_M_COST} t_cost={MAX_T_COST} p_cost={MAX_P_COST}",
+                kdf.m_cost,
+                kdf.t_cost,
+                kdf.p_cost
+            );
+            return Err(Error::InvalidBackup(
+                "key derivation parameters are too costly",
+            ));
+        }
+        let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(KEY_LEN))
+            .map_err(|e| Error::BackupEncryptionError(e.to_string()))?;
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut_slice())
+            .map_err(|e| Error::BackupEncryptionError(e.to_string()))?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(key.as_slice())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::str::FromStr;
+
+    use super::*;
+    use crate::{
+        heritage_wallet::backup::SubwalletDescriptorBackup,
+        miniscript::{Descriptor, DescriptorPublicKey},
+        tests::*,
+    };
+
+    fn get_backup() -> HeritageWalletBackup {
+        HeritageWalletBackup(vec![SubwalletDescriptorBackup {
+            external_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                get_default_test_subwallet_config_expected_external_descriptor(
+                    TestHeritageConfig::BackupWifeBro,
+                ),
+            )
+            .unwrap(),
+            change_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                get_default_test_subwallet_config_expected_change_descriptor(
+                    TestHeritageConfig::BackupWifeBro,
+                ),
+            )
+            .unwrap(),
+            first_use_ts: Some(1_700_000_000),
+            last_external_index: Some(3),
+            last_change_index: None,
+            keypath_change_descriptor: None,
+        }])
+    }
+
+    #[test]
+    fn encrypt_decrypt() {
+        let backup = get_backup();
+        let encrypted = EncryptedHeritageWalletBackup::encrypt(&backup, "passphrase").unwrap();
+        // The descriptors do not appear in the serialization
+        let serialized = serde_json::to_string(&encrypted).unwrap();
+        assert!(!serialized.contains("tpub"));
+        // Roundtrip through the serialization
+        let encrypted: EncryptedHeritageWalletBackup = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(encrypted.decrypt("passphrase").unwrap(), backup);
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let encrypted =
+            EncryptedHeritageWalletBackup::encrypt(&get_backup(), "passphrase").unwrap();
+        assert!(matches!(
+            encrypted.decrypt("wrong passphrase"),
+            Err(Error::BackupDecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn costly_kdf_parameters() {
+        let encrypted =
+            EncryptedHeritageWalletBackup::encrypt(&get_backup(), "passphrase").unwrap();
+        for kdf in [
+            Argon2idParams {
+                m_cost: 4 * 1024 * 1024,
+                ..encrypted.kdf.clone()
+            },
+            Argon2idParams {
+                t_cost: 1_000_000,
+                ..encrypted.kdf.clone()
+            },
+            Argon2idParams {
+                p_cost: 1024,
+                ..encrypted.kdf.clone()
+            },
+        ] {
+            let crafted = EncryptedHeritageWalletBackup {
+                kdf,
+                ..encrypted.clone()
+            };
+            assert!(matches!(
+                crafted.decrypt("passphrase"),
+                Err(Error::InvalidBackup(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tampered_ciphertext() {
+        let mut encrypted =
+            EncryptedHeritageWalletBackup::encrypt(&get_backup(), "passphrase").unwrap();
+        let mut ciphertext = Vec::<u8>::from_hex(&encrypted.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        encrypted.ciphertext = ciphertext.as_slice().to_lower_hex_string();
+        assert!(matches!(
+            encrypted.decrypt("passphrase"),
+            Err(Error::BackupDecryptionFailed)
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+//! Batch payments of many recipients from an [HeritageWallet], e.g. salaries paid from a
+//! treasury, see [HeritageWallet::plan_batch_payment].
+//!
+//! The payees are given as a [PayeeList], parsed from CSV lines `address,amount_sat,label`
+//! (the header line and the label are optional) or from a JSON array of [Payee].
+
+use std::collections::HashSet;
+
+use bdk::wallet::IsDust;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::{psbt::Psbt, Amount, Network, OutPoint, Weight},
+    database::TransacHeritageDatabase,
+    errors::{Error, Result},
+    utils::string_to_address_for_network,
+};
+
+use super::{
+    export::{parse_csv_line, ExportFormat},
+    get_expected_tx_weight, CreatePsbtOptions, HeritageWallet, Recipient, SpendingConfig,
+    TransactionSummary, UtxoSelection,
+};
+
+/// The maximum weight of a transaction relayed by Bitcoin Core (`MAX_STANDARD_TX_WEIGHT`)
+const MAX_STANDARD_TX_WEIGHT: Weight = Weight::from_wu(400_000);
+
+/// The default maximum number of payees of a single transaction of a batch payment
+pub const DEFAULT_MAX_PAYEES_PER_BATCH: usize = 250;
+
+/// A recipient of a batch payment
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Payee {
+    pub address: String,
+    pub amount_sat: u64,
+    /// A free text for the bookkeeping of the payment, e.g. the name of the employee
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// The list of the [Payee]s of a batch payment
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PayeeList(pub Vec<Payee>);
+
+impl PayeeList {
+    /// Parse a [PayeeList] in the given [ExportFormat]
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPayeeList] if the document cannot be parsed
+    /// and [Error::InvalidPayee] if a CSV line is not a valid [Payee]
+    pub fn parse(s: &str, format: ExportFormat) -> Result<Self> {
+        match format {
+            ExportFormat::Csv => Self::from_csv(s),
+            ExportFormat::Json => Self::from_json(s),
+        }
+    }
+
+    /// Parse CSV lines `address,amount_sat,label`. The label is optional, the blank lines
+    /// are skipped and so is the first line if it is the header, i.e. starts with `address`.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPayee] if a line is not a valid [Payee]
+    pub fn from_csv(s: &str) -> Result<Self> {
+        let mut lines = s.lines().filter(|line| !line.trim().is_empty()).peekable();
+        if lines
+            .peek()
+            .is_some_and(|line| line.trim().to_ascii_lowercase().starts_with("address"))
+        {
+            lines.next();
+        }
+        Ok(Self(
+            lines
+                .enumerate()
+                .map(|(i, line)| {
+                    let invalid = |reason: String| Error::InvalidPayee {
+                        entry: i + 1,
+                        reason,
+                    };
+                    let mut fields = parse_csv_line(line).into_iter();
+                    let (Some(address), Some(amount_sat)) = (fields.next(), fields.next()) else {
+                        return Err(invalid("expected address,amount_sat[,label]".to_owned()));
+                    };
+                    let amount_sat = amount_sat
+                        .trim()
+                        .parse()
+                        .map_err(|e| invalid(format!("invalid amount {amount_sat}: {e}")))?;
+                    let label = fields.next().filter(|label| !label.is_empty());
+                    if fields.next().is_some() {
+                        return Err(invalid("too many fields".to_owned()));
+                    }
+                    Ok(Payee {
+                        address: address.trim().to_owned(),
+                        amount_sat,
+                        label,
+                    })
+                })
+                .collect::<Result<_>>()?,
+        ))
+    }
+
+    /// Parse a JSON array of [Payee]
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPayeeList] if `s` is not a JSON array of [Payee]
+    pub fn from_json(s: &str) -> Result<Self> {
+        Ok(Self(
+            serde_json::from_str(s).map_err(|e| Error::InvalidPayeeList(e.to_string()))?,
+        ))
+    }
+
+    /// Validate every [Payee] for the `network` and return the corresponding [Recipient]s
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPayee] for the first [Payee] whose address is not valid for
+    /// the `network` or whose amount is zero, dust or more than the 21 million bitcoins
+    pub fn to_recipients(&self, network: Network) -> Result<Vec<Recipient>> {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, payee)| {
+                let invalid = |reason: String| Error::InvalidPayee {
+                    entry: i + 1,
+                    reason,
+                };
+                let address = string_to_address_for_network(&payee.address, network)
+                    .map_err(|e| invalid(e.to_string()))?;
+                let amount = Amount::from_sat(payee.amount_sat);
+                if amount > Amount::MAX_MONEY {
+                    return Err(invalid(format!(
+                        "{amount} is more than 21 million bitcoins"
+                    )));
+                }
+                if payee.amount_sat.is_dust(&address.script_pubkey()) {
+                    return Err(invalid(format!("{amount} is dust for {address}")));
+                }
+                Ok(Recipient(address, amount))
+            })
+            .collect()
+    }
+}
+
+/// One transaction of a batch payment, see [HeritageWallet::plan_batch_payment]
+#[derive(Debug, Clone)]
+pub struct PaymentBatch {
+    /// The payees of the transaction, in the order of the [PayeeList]
+    pub payees: Vec<Payee>,
+    /// The [Psbt] to sign and broadcast
+    pub psbt: Psbt,
+    /// The summary of the transaction, including its fee
+    pub tx_summary: TransactionSummary,
+}
+
+/// Return `utxo_selection` restricted to the UTXOs that are not in `spent`
+fn excluding(utxo_selection: &UtxoSelection, spent: &HashSet<OutPoint>) -> UtxoSelection {
+    let unspent = |include: &Vec<OutPoint>| {
+        include
+            .iter()
+            .filter(|op| !spent.contains(op))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    match utxo_selection {
+        UtxoSelection::IncludePrevious => UtxoSelection::Exclude(spent.clone()),
+        UtxoSelection::Include(include) => UtxoSelection::IncludeExclude {
+            include: unspent(include),
+            exclude: spent.clone(),
+        },
+        UtxoSelection::Exclude(exclude) => {
+            UtxoSelection::Exclude(exclude.union(spent).cloned().collect())
+        }
+        UtxoSelection::IncludeExclude { include, exclude } => UtxoSelection::IncludeExclude {
+            include: unspent(include),
+            exclude: exclude.union(spent).cloned().collect(),
+        },
+        UtxoSelection::UseOnly(use_only) => {
+            UtxoSelection::UseOnly(use_only.difference(spent).cloned().collect())
+        }
+    }
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Create the transactions paying every [Payee] of `payees`, with at most
+    /// `max_payees_per_batch` payees per transaction (see [DEFAULT_MAX_PAYEES_PER_BATCH]).
+    /// A batch is split further if its transaction would not be standard, i.e. heavier
+    /// than 400,000 weight units.
+    ///
+    /// The transactions do not spend the same UTXOs, so they can be broadcasted in any
+    /// order, and each one has its own change output. The `options` are used to create
+    /// every [Psbt]. All the payees are validated before any [Psbt] is created.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidPayee] if a [Payee] is invalid (see [PayeeList::to_recipients]),
+    /// [Error::InvalidPayeeList] if the list is empty or `max_payees_per_batch` is zero,
+    /// and the errors of [HeritageWallet::create_owner_psbt], e.g. if the funds are insufficient
+    pub fn plan_batch_payment(
+        &self,
+        payees: &PayeeList,
+        max_payees_per_batch: usize,
+        options: CreatePsbtOptions,
+    ) -> Result<Vec<PaymentBatch>> {
+        log::debug!(
+            "HeritageWallet::plan_batch_payment - payees.len()={} \
+            max_payees_per_batch={max_payees_per_batch} options={options:?}",
+            payees.0.len()
+        );
+        if payees.0.is_empty() {
+            return Err(Error::InvalidPayeeList("no payee".to_owned()));
+        }
+        if max_payees_per_batch == 0 {
+            return Err(Error::InvalidPayeeList(
+                "the maximum number of payees per batch cannot be zero".to_owned(),
+            ));
+        }
+        let recipients = payees.to_recipients(self.network)?;
+
+        let mut spent = HashSet::new();
+        let mut batches: Vec<PaymentBatch> = vec![];
+        let mut start = 0;
+        while start < recipients.len() {
+            let mut end = recipients.len().min(start + max_payees_per_batch);
+            loop {
+                let (psbt, tx_summary) = self.create_owner_psbt(
+                    SpendingConfig::Recipients {
+                        recipients: recipients[start..end].to_vec(),
+                        change_to: None,
+                    },
+                    CreatePsbtOptions {
+                        utxo_selection: excluding(&options.utxo_selection, &spent),
+                        ..options.clone()
+                    },
+                )?;
+                let weight = get_expected_tx_weight(&psbt);
+                if weight <= MAX_STANDARD_TX_WEIGHT {
+                    spent.extend(psbt.unsigned_tx.input.iter().map(|i| i.previous_output));
+                    batches.push(PaymentBatch {
+                        payees: payees.0[start..end].to_vec(),
+                        psbt,
+                        tx_summary,
+                    });
+                    break;
+                }
+                if end - start == 1 {
+                    log::error!(
+                        "HeritageWallet::plan_batch_payment - Payee #{} alone needs a \
+                        transaction of {weight} weight units",
+                        start + 1
+                    );
+                    return Err(Error::PsbtCreationError(format!(
+                        "the transaction paying payee #{} would not be standard ({weight} weight units)",
+                        start + 1
+                    )));
+                }
+                log::info!(
+                    "HeritageWallet::plan_batch_payment - The transaction of {} payees \
+                    would not be standard ({weight} weight units), splitting it",
+                    end - start
+                );
+                end = start + (end - start) / 2;
+            }
+            start = end;
+        }
+        log::info!(
+            "HeritageWallet::plan_batch_payment - {} payees in {} transaction(s)",
+            recipients.len(),
+            batches.len()
+        );
+        Ok(batches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payee_list_parsing() {
+        let csv = "address,amount_sat,label\r\n\
+            bcrt1qxyz,100000,Alice\r\n\
+            \r\n\
+            bcrt1qabc,200000,\"Bob, Jr.\"\r\n\
+            bcrt1qdef, 300000\r\n";
+        let payees = PayeeList::from_csv(csv).unwrap();
+        assert_eq!(payees.0.len(), 3);
+        assert_eq!(payees.0[1].label.as_deref(), Some("Bob, Jr."));
+        assert_eq!(payees.0[2].amount_sat, 300_000);
+        assert_eq!(payees.0[2].label, None);
+        // Without header
+        assert_eq!(
+            PayeeList::from_csv(&csv[csv.find('\n').unwrap() + 1..]).unwrap(),
+            payees
+        );
+        assert_eq!(
+            PayeeList::parse(
+                &serde_json::to_string(&payees.0).unwrap(),
+                ExportFormat::Json
+            )
+            .unwrap(),
+            payees
+        );
+
+        assert!(matches!(
+            PayeeList::from_csv("bcrt1qxyz,one bitcoin"),
+            Err(Error::InvalidPayee { entry: 1, .. })
+        ));
+        assert!(matches!(
+            PayeeList::from_csv("bcrt1qxyz,1000\nbcrt1qabc"),
+            Err(Error::InvalidPayee { entry: 2, .. })
+        ));
+        assert!(matches!(
+            PayeeList::from_json("{}"),
+            Err(Error::InvalidPayeeList(_))
+        ));
+    }
+
+    #[test]
+    fn utxo_selection_excluding() {
+        let op = |vout| OutPoint {
+            vout,
+            ..OutPoint::null()
+        };
+        let spent = HashSet::from([op(0)]);
+        assert!(matches!(
+            excluding(&UtxoSelection::IncludePrevious, &spent),
+            UtxoSelection::Exclude(exclude) if exclude == spent
+        ));
+        assert!(matches!(
+            excluding(&UtxoSelection::Include(vec![op(0), op(1)]), &spent),
+            UtxoSelection::IncludeExclude { include, exclude } if include == vec![op(1)] && exclude == spent
+        ));
+        assert!(matches!(
+            excluding(&UtxoSelection::UseOnly(HashSet::from([op(0), op(1)])), &spent),
+            UtxoSelection::UseOnly(use_only) if use_only == HashSet::from([op(1)])
+        ));
+    }
+}
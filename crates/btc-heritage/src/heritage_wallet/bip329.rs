@@ -0,0 +1,164 @@
+//! [BIP-329](https://github.com/bitcoin/bips/blob/master/bip-0329.mediawiki) wallet labels,
+//! used to exchange the labels of a [HeritageWallet](super::HeritageWallet) with other wallets.
+//!
+//! The export is a JSON Lines document where each line is a [Bip329Label]. The `tx` and `addr`
+//! labels map to the [LabelRef] labels of the wallet, the `output` labels map to the UTXO labels
+//! and their `spendable` attribute to the frozen state of the UTXO. The `input`, `pubkey` and `xpub`
+//! labels have no equivalent in a [HeritageWallet](super::HeritageWallet) and are ignored on import.
+
+use core::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::{Network, OutPoint, Txid},
+    errors::Error,
+    utils::string_to_address_for_network,
+};
+
+use super::{CheckedAddress, LabelRef};
+
+/// The item a [Bip329Label] applies to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", content = "ref", rename_all = "lowercase")]
+pub enum Bip329Ref {
+    Tx(Txid),
+    /// Kept as a string so that a label for another network does not prevent the whole import
+    Addr(String),
+    Pubkey(String),
+    Input(OutPoint),
+    Output(OutPoint),
+    Xpub(String),
+}
+
+/// A single BIP-329 label record
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Bip329Label {
+    #[serde(flatten)]
+    pub label_ref: Bip329Ref,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Abbreviated output descriptor of the keystore the label applies to,
+    /// e.g. `tr([73c5da0a/86'/0'/0'])`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Only meaningful for `output` labels: `false` if the UTXO is frozen
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Bip329Label {
+    /// Return the [LabelRef] of this label, if it is a `tx` or `addr` label
+    /// for an address valid on the `network`
+    pub fn as_label_ref(&self, network: Network) -> Option<LabelRef> {
+        match &self.label_ref {
+            Bip329Ref::Tx(txid) => Some(LabelRef::Tx(*txid)),
+            Bip329Ref::Addr(address) => string_to_address_for_network(address, network)
+                .ok()
+                .map(|address| LabelRef::Address(CheckedAddress::from(address))),
+            _ => None,
+        }
+    }
+}
+
+impl From<(LabelRef, String)> for Bip329Label {
+    fn from((label_ref, label): (LabelRef, String)) -> Self {
+        Self {
+            label_ref: match label_ref {
+                LabelRef::Address(address) => Bip329Ref::Addr(address.to_string()),
+                LabelRef::Tx(txid) => Bip329Ref::Tx(txid),
+            },
+            label: Some(label),
+            origin: None,
+            spendable: None,
+        }
+    }
+}
+
+/// A list of [Bip329Label], serialized as JSON Lines
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bip329Labels(pub Vec<Bip329Label>);
+
+impl Display for Bip329Labels {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for label in &self.0 {
+            writeln!(
+                f,
+                "{}",
+                serde_json::to_string(label).expect("Bip329Label serialization cannot fail")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Bip329Labels {
+    type Err = Error;
+
+    /// Parse a JSON Lines document. Blank lines are skipped.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(
+            s.lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| {
+                    serde_json::from_str(line).map_err(|e| Error::InvalidBip329Label {
+                        line: i + 1,
+                        reason: e.to_string(),
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Examples from the BIP
+    const BIP329_EXAMPLE: &str = r#"{"type":"tx","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd","label":"Transaction","origin":"wpkh([d34db33f/84'/0'/0'])"}
+{"type":"addr","ref":"bc1q34aq5drpuwy3wgl9lhup9892qp6svr8ldzyy7c","label":"Address"}
+
+{"type":"pubkey","ref":"0283409659355b6d1cc3c32decd5d561abaac86c37a353b52895a5e6c196d6f448","label":"Public Key"}
+{"type":"input","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:0","label":"Input"}
+{"type":"output","ref":"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd:1","label":"Output","spendable":false}
+{"type":"xpub","ref":"xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8","label":"Extended Public Key"}
+"#;
+
+    #[test]
+    fn parse_and_serialize() {
+        let labels = Bip329Labels::from_str(BIP329_EXAMPLE).unwrap();
+        assert_eq!(labels.0.len(), 6);
+        assert_eq!(
+            labels.0[0].label_ref,
+            Bip329Ref::Tx(
+                Txid::from_str("f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd")
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            labels.0[0].origin.as_deref(),
+            Some("wpkh([d34db33f/84'/0'/0'])")
+        );
+        assert_eq!(labels.0[4].spendable, Some(false));
+        assert!(matches!(labels.0[5].label_ref, Bip329Ref::Xpub(_)));
+        // Mainnet address is not valid for the test network
+        assert!(labels.0[1].as_label_ref(Network::Regtest).is_none());
+        assert!(labels.0[1].as_label_ref(Network::Bitcoin).is_some());
+
+        // Round-trip
+        let serialized = labels.to_string();
+        assert_eq!(serialized.lines().count(), 6);
+        assert_eq!(Bip329Labels::from_str(&serialized).unwrap(), labels);
+    }
+
+    #[test]
+    fn parse_error_reports_line() {
+        let err = Bip329Labels::from_str(
+            "{\"type\":\"tx\",\"ref\":\"f91d0a8a78462bc59398f2c5d7a84fcff491c26ba54c4833478b202796c8aafd\"}\n{\"type\":\"unknown\",\"ref\":\"\"}",
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidBip329Label { line: 2, .. }));
+    }
+}
@@ -0,0 +1,131 @@
+//! Winding down of the inheritance of an [HeritageWallet], for owners who no longer want
+//! on-chain inheritance conditions, see [HeritageWallet::plan_dissolution].
+
+use std::collections::HashSet;
+
+use bdk::KeychainKind;
+
+use crate::{
+    bitcoin::{psbt::Psbt, Address},
+    database::TransacHeritageDatabase,
+    errors::{Error, Result},
+    heritage_config::HeritageConfig,
+};
+
+use super::{
+    CreatePsbtOptions, HeritageWallet, SpendingConfig, SubwalletConfigId, TransactionSummary,
+    UtxoSelection,
+};
+
+/// Where [HeritageWallet::plan_dissolution] sweeps the funds
+#[derive(Debug, Clone)]
+pub enum DissolutionDestination {
+    /// A new address of the wallet, protected by the owner key(s) only
+    Wallet,
+    /// An address outside of the wallet
+    Address(Address),
+}
+
+/// The result of [HeritageWallet::plan_dissolution]
+#[derive(Debug, Clone)]
+pub struct DissolutionPlan {
+    /// The [HeritageConfig] without heir now used by the wallet
+    pub heritage_config: HeritageConfig,
+    /// The self-transfer, or the transfer to the external address, sweeping the funds
+    /// with its summary. [None] if there is nothing to sweep.
+    pub sweep: Option<(Psbt, TransactionSummary)>,
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Terminate the inheritance of the wallet: a [HeritageConfig] without any heir becomes
+    /// the current one, so the new addresses are only protected by the owner key(s), and a
+    /// [Psbt] sweeping the funds to `destination` is built for the owner to sign and broadcast.
+    /// If the current [HeritageConfig] has an owner quorum, it is kept.
+    ///
+    /// With [DissolutionDestination::Wallet], only the UTXOs that heirs could eventually spend
+    /// are swept, with [DissolutionDestination::Address] all of them are.
+    /// The `options` are used to create the [Psbt], except for [CreatePsbtOptions::utxo_selection]
+    /// that is replaced by the UTXOs to sweep. Frozen UTXOs are never swept.
+    ///
+    /// The heirs can still spend the UTXOs that are not swept, or if the sweep is not
+    /// broadcasted before their time locks expire.
+    ///
+    /// # Errors
+    /// Returns [Error::MissingCurrentSubwalletConfig] if the wallet has no [HeritageConfig]
+    pub fn plan_dissolution(
+        &self,
+        destination: DissolutionDestination,
+        options: CreatePsbtOptions,
+    ) -> Result<DissolutionPlan> {
+        log::debug!(
+            "HeritageWallet::plan_dissolution - destination={destination:?} options={options:?}"
+        );
+        let current_heritage_config = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .ok_or(Error::MissingCurrentSubwalletConfig)?
+            .heritage_config()
+            .clone();
+        let heritage_config = if current_heritage_config.iter_heir_configs().next().is_none() {
+            log::info!("HeritageWallet::plan_dissolution - Already without heir");
+            current_heritage_config
+        } else {
+            let builder = HeritageConfig::builder();
+            let heritage_config = match current_heritage_config.owner_quorum() {
+                Some(owner_quorum) => builder.owner_quorum(owner_quorum.clone()),
+                None => builder,
+            }
+            .build();
+            self.update_heritage_config(heritage_config.clone())?;
+            log::info!("HeritageWallet::plan_dissolution - HeritageConfig without heir set");
+            heritage_config
+        };
+
+        let frozen_utxos = self
+            .database
+            .borrow()
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let swept = self
+            .database
+            .borrow()
+            .list_utxos()?
+            .into_iter()
+            .filter(|hu| {
+                !frozen_utxos.contains(&hu.outpoint)
+                    && match destination {
+                        DissolutionDestination::Wallet => {
+                            !hu.keypath_only
+                                && hu.heritage_config.iter_heir_configs().next().is_some()
+                        }
+                        DissolutionDestination::Address(_) => true,
+                    }
+            })
+            .map(|hu| hu.outpoint)
+            .collect::<HashSet<_>>();
+        log::debug!("HeritageWallet::plan_dissolution - swept={swept:?}");
+        let sweep = if swept.is_empty() {
+            log::info!("HeritageWallet::plan_dissolution - Nothing to sweep");
+            None
+        } else {
+            let drain_addr = match destination {
+                DissolutionDestination::Wallet => {
+                    self.internal_get_new_address(KeychainKind::External)?
+                        .address
+                }
+                DissolutionDestination::Address(address) => address,
+            };
+            let options = CreatePsbtOptions {
+                utxo_selection: UtxoSelection::UseOnly(swept),
+                ..options
+            };
+            Some(self.create_owner_psbt(SpendingConfig::DrainTo(drain_addr), options)?)
+        };
+        Ok(DissolutionPlan {
+            heritage_config,
+            sweep,
+        })
+    }
+}
@@ -0,0 +1,339 @@
+//! Accounting exports of the transactions and UTXOs of a [HeritageWallet](super::HeritageWallet).
+//!
+//! Every export is available as CSV, with a header line and one record per line, or as a JSON
+//! array of records. Amounts are given both in satoshis and in BTC (as a string with exactly 8
+//! decimals, so that no precision is lost). No fiat value is involved. Fields are always present,
+//! possibly `null` in JSON or empty in CSV, so that the layout of an export never changes.
+
+use core::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{bitcoin::Txid, errors::Error, subwallet_config::SubwalletId};
+
+use super::{HeritageUtxo, TransactionSummary};
+
+/// The format of an export
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ExportFormat::Csv => write!(f, "csv"),
+            ExportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(Error::InvalidExportFormat(s.to_owned())),
+        }
+    }
+}
+
+/// A record that can be exported as a CSV line
+pub(super) trait CsvRecord {
+    const HEADER: &'static [&'static str];
+    /// The fields of the record, in the order of [CsvRecord::HEADER]
+    fn csv_fields(&self) -> Vec<String>;
+}
+
+/// Export `records` in the given [ExportFormat]
+pub(super) fn export<T: Serialize + CsvRecord>(records: &[T], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => {
+            let mut csv = csv_line(T::HEADER.iter().map(|h| h.to_string()));
+            for record in records {
+                csv.push_str(&csv_line(record.csv_fields().into_iter()));
+            }
+            csv
+        }
+        ExportFormat::Json => {
+            serde_json::to_string_pretty(records).expect("export records serialization cannot fail")
+        }
+    }
+}
+
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let mut line = fields
+        .map(|field| {
+            // RFC 4180: fields containing separators, quotes or line breaks are quoted
+            // and their quotes doubled
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+/// Split a CSV line into its fields, the reverse of [csv_line].
+/// A quoted field cannot span several lines.
+pub(super) fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = line.trim_end_matches(['\r', '\n']).chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    in_quotes = false;
+                }
+            }
+            '"' if field.is_empty() => in_quotes = true,
+            ',' if !in_quotes => fields.push(core::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn opt_to_string<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_default()
+}
+
+/// Format an amount of satoshis as BTC with exactly 8 decimals, e.g. `-0.00012000`
+pub fn format_btc(sats: i64) -> String {
+    let sign = if sats < 0 { "-" } else { "" };
+    let sats = sats.unsigned_abs();
+    format!("{sign}{}.{:08}", sats / 100_000_000, sats % 100_000_000)
+}
+
+/// One transaction of a [HeritageWallet::export_transaction_summaries](super::HeritageWallet::export_transaction_summaries)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TransactionExport {
+    pub txid: Txid,
+    /// Height of the block containing the transaction, `None` if unconfirmed
+    pub block_height: Option<u32>,
+    /// Unix timestamp of the block containing the transaction, `None` if unconfirmed
+    pub block_timestamp: Option<u64>,
+    /// The subwallets owning at least one of the inputs or outputs of the transaction
+    pub subwallet_ids: Vec<SubwalletId>,
+    /// Sum of the owned outputs
+    pub received_sat: u64,
+    pub received_btc: String,
+    /// Sum of the owned inputs
+    pub sent_sat: u64,
+    pub sent_btc: String,
+    /// Fee of the transaction, paid by the wallet only if `sent_sat` is not zero
+    pub fee_sat: u64,
+    pub fee_btc: String,
+    /// Variation of the wallet balance, i.e. `received_sat - sent_sat`
+    pub net_sat: i64,
+    pub net_btc: String,
+    pub label: Option<String>,
+}
+
+impl TransactionExport {
+    pub(super) fn new(tx_summary: TransactionSummary, subwallet_ids: Vec<SubwalletId>) -> Self {
+        let received_sat = tx_summary
+            .owned_outputs
+            .iter()
+            .map(|io| io.amount.to_sat())
+            .sum::<u64>();
+        let sent_sat = tx_summary
+            .owned_inputs
+            .iter()
+            .map(|io| io.amount.to_sat())
+            .sum::<u64>();
+        let fee_sat = tx_summary.fee.to_sat();
+        let net_sat = received_sat as i64 - sent_sat as i64;
+        Self {
+            txid: tx_summary.txid,
+            block_height: tx_summary.confirmation_time.as_ref().map(|bt| bt.height),
+            block_timestamp: tx_summary.confirmation_time.as_ref().map(|bt| bt.timestamp),
+            subwallet_ids,
+            received_sat,
+            received_btc: format_btc(received_sat as i64),
+            sent_sat,
+            sent_btc: format_btc(sent_sat as i64),
+            fee_sat,
+            fee_btc: format_btc(fee_sat as i64),
+            net_sat,
+            net_btc: format_btc(net_sat),
+            label: tx_summary.label,
+        }
+    }
+}
+
+impl CsvRecord for TransactionExport {
+    const HEADER: &'static [&'static str] = &[
+        "txid",
+        "block_height",
+        "block_timestamp",
+        "subwallet_ids",
+        "received_sat",
+        "received_btc",
+        "sent_sat",
+        "sent_btc",
+        "fee_sat",
+        "fee_btc",
+        "net_sat",
+        "net_btc",
+        "label",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.txid.to_string(),
+            opt_to_string(&self.block_height),
+            opt_to_string(&self.block_timestamp),
+            self.subwallet_ids
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(";"),
+            self.received_sat.to_string(),
+            self.received_btc.clone(),
+            self.sent_sat.to_string(),
+            self.sent_btc.clone(),
+            self.fee_sat.to_string(),
+            self.fee_btc.clone(),
+            self.net_sat.to_string(),
+            self.net_btc.clone(),
+            opt_to_string(&self.label),
+        ]
+    }
+}
+
+/// One UTXO of a [HeritageWallet::export_utxos](super::HeritageWallet::export_utxos)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UtxoExport {
+    pub txid: Txid,
+    pub vout: u32,
+    pub address: String,
+    /// The subwallet owning the UTXO
+    pub subwallet_id: Option<SubwalletId>,
+    /// `true` if the UTXO is a key-path-only change output, that the heirs cannot spend
+    pub keypath_only: bool,
+    /// Height of the block containing the UTXO, `None` if unconfirmed
+    pub block_height: Option<u32>,
+    /// Unix timestamp of the block containing the UTXO, `None` if unconfirmed
+    pub block_timestamp: Option<u64>,
+    pub amount_sat: u64,
+    pub amount_btc: String,
+    pub frozen: bool,
+    pub label: Option<String>,
+}
+
+impl UtxoExport {
+    pub(super) fn new(
+        heritage_utxo: HeritageUtxo,
+        subwallet_id: Option<SubwalletId>,
+        frozen: bool,
+        label: Option<String>,
+    ) -> Self {
+        let amount_sat = heritage_utxo.amount.to_sat();
+        Self {
+            txid: heritage_utxo.outpoint.txid,
+            vout: heritage_utxo.outpoint.vout,
+            address: heritage_utxo.address.to_string(),
+            subwallet_id,
+            keypath_only: heritage_utxo.keypath_only,
+            block_height: heritage_utxo.confirmation_time.as_ref().map(|bt| bt.height),
+            block_timestamp: heritage_utxo
+                .confirmation_time
+                .as_ref()
+                .map(|bt| bt.timestamp),
+            amount_sat,
+            amount_btc: format_btc(amount_sat as i64),
+            frozen,
+            label,
+        }
+    }
+}
+
+impl CsvRecord for UtxoExport {
+    const HEADER: &'static [&'static str] = &[
+        "txid",
+        "vout",
+        "address",
+        "subwallet_id",
+        "keypath_only",
+        "block_height",
+        "block_timestamp",
+        "amount_sat",
+        "amount_btc",
+        "frozen",
+        "label",
+    ];
+
+    fn csv_fields(&self) -> Vec<String> {
+        vec![
+            self.txid.to_string(),
+            self.vout.to_string(),
+            self.address.clone(),
+            opt_to_string(&self.subwallet_id),
+            self.keypath_only.to_string(),
+            opt_to_string(&self.block_height),
+            opt_to_string(&self.block_timestamp),
+            self.amount_sat.to_string(),
+            self.amount_btc.clone(),
+            self.frozen.to_string(),
+            opt_to_string(&self.label),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn btc_formatting() {
+        assert_eq!(format_btc(0), "0.00000000");
+        assert_eq!(format_btc(1), "0.00000001");
+        assert_eq!(format_btc(100_000_000), "1.00000000");
+        assert_eq!(format_btc(123_456_789), "1.23456789");
+        assert_eq!(format_btc(-12_000), "-0.00012000");
+    }
+
+    #[test]
+    fn csv_escaping() {
+        assert_eq!(
+            csv_line(
+                ["plain", "with,comma", "with \"quotes\"", "multi\nline"]
+                    .into_iter()
+                    .map(String::from)
+            ),
+            "plain,\"with,comma\",\"with \"\"quotes\"\"\",\"multi\nline\"\r\n"
+        );
+        let fields = ["plain", "with,comma", "with \"quotes\"", ""].map(String::from);
+        assert_eq!(
+            parse_csv_line(&csv_line(fields.clone().into_iter())),
+            fields.to_vec()
+        );
+    }
+
+    #[test]
+    fn export_format_parsing() {
+        assert_eq!(ExportFormat::from_str("CSV").unwrap(), ExportFormat::Csv);
+        assert_eq!(ExportFormat::from_str("json").unwrap(), ExportFormat::Json);
+        assert!(matches!(
+            ExportFormat::from_str("xml"),
+            Err(Error::InvalidExportFormat(_))
+        ));
+        assert_eq!(ExportFormat::Json.to_string(), "json");
+    }
+}
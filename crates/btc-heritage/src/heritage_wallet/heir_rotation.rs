@@ -0,0 +1,131 @@
+//! Replacement of the key of an heir of an [HeritageWallet], e.g. because the heir
+//! lost the device holding it, see [HeritageWallet::rotate_heir_key].
+
+use std::collections::HashSet;
+
+use bdk::KeychainKind;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::psbt::Psbt,
+    database::TransacHeritageDatabase,
+    errors::{Error, Result},
+    heritage_config::HeritageConfig,
+    utils::timestamp_now,
+    HeirConfig,
+};
+
+use super::{
+    CreatePsbtOptions, HeritageWallet, SpendingConfig, SubwalletConfigId, TransactionSummary,
+    UtxoSelection,
+};
+
+/// The record of an heir key rotation kept in the database as an audit trail,
+/// see [HeritageWallet::list_heir_rotations]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeirRotation {
+    /// When the rotation happened
+    pub timestamp: u64,
+    /// The replaced [HeirConfig]
+    pub old_heir_config: HeirConfig,
+    /// The [HeirConfig] replacing it
+    pub new_heir_config: HeirConfig,
+    /// The [HeritageConfig] of the wallet after the rotation
+    pub heritage_config: HeritageConfig,
+}
+
+/// The result of [HeritageWallet::rotate_heir_key]
+#[derive(Debug, Clone)]
+pub struct HeirKeyRotation {
+    /// The [HeirRotation] recorded in the database
+    pub rotation: HeirRotation,
+    /// The self-transfer moving the UTXOs still spendable with the old heir key under
+    /// the new [HeritageConfig], with its summary. [None] if there is no such UTXO.
+    pub refresh: Option<(Psbt, TransactionSummary)>,
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Replace `old_heir_config` by `new_heir_config` in the current [HeritageConfig],
+    /// see [HeritageConfig::with_replaced_heir], set the result as the new [HeritageConfig]
+    /// of the wallet and record the rotation in the database.
+    ///
+    /// The UTXOs of the subwallets whose [HeritageConfig] includes `old_heir_config` remain
+    /// spendable with the old heir key until they are moved. A self-transfer [Psbt] moving
+    /// them to a new address of the new subwallet is returned for the owner to sign and
+    /// broadcast. The `options` are used to create it, except for
+    /// [CreatePsbtOptions::utxo_selection] that is replaced by the UTXOs to move.
+    /// Frozen UTXOs are never moved.
+    ///
+    /// # Errors
+    /// Returns [Error::MissingCurrentSubwalletConfig] if the wallet has no [HeritageConfig]
+    /// yet and [Error::InvalidHeritageConfig] if `old_heir_config` is not an heir of the
+    /// current [HeritageConfig] or if `new_heir_config` already is.
+    pub fn rotate_heir_key(
+        &self,
+        old_heir_config: &HeirConfig,
+        new_heir_config: HeirConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<HeirKeyRotation> {
+        log::debug!(
+            "HeritageWallet::rotate_heir_key - old_heir_config={old_heir_config:?} \
+            new_heir_config={new_heir_config:?} options={options:?}"
+        );
+        let new_heritage_config = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .ok_or(Error::MissingCurrentSubwalletConfig)?
+            .heritage_config()
+            .with_replaced_heir(old_heir_config, new_heir_config.clone())?;
+        self.update_heritage_config(new_heritage_config.clone())?;
+
+        let rotation = HeirRotation {
+            timestamp: timestamp_now(),
+            old_heir_config: old_heir_config.clone(),
+            new_heir_config,
+            heritage_config: new_heritage_config,
+        };
+        self.database.borrow_mut().add_heir_rotation(&rotation)?;
+        log::info!("HeritageWallet::rotate_heir_key - Rotation recorded");
+
+        let frozen_utxos = self
+            .database
+            .borrow()
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let moved = self
+            .database
+            .borrow()
+            .list_utxos()?
+            .into_iter()
+            .filter(|hu| {
+                hu.heritage_config
+                    .iter_heir_configs()
+                    .any(|hc| hc == old_heir_config)
+                    && !frozen_utxos.contains(&hu.outpoint)
+            })
+            .map(|hu| hu.outpoint)
+            .collect::<HashSet<_>>();
+        log::debug!("HeritageWallet::rotate_heir_key - moved={moved:?}");
+        let refresh = if moved.is_empty() {
+            log::info!("HeritageWallet::rotate_heir_key - No UTXO to move");
+            None
+        } else {
+            // An external address, because the change may be key-path-only
+            let drain_addr = self.internal_get_new_address(KeychainKind::External)?;
+            let options = CreatePsbtOptions {
+                utxo_selection: UtxoSelection::UseOnly(moved),
+                ..options
+            };
+            Some(self.create_owner_psbt(SpendingConfig::DrainTo(drain_addr.address), options)?)
+        };
+        Ok(HeirKeyRotation { rotation, refresh })
+    }
+
+    /// List the [HeirRotation]s of the wallet, from the oldest to the newest
+    pub fn list_heir_rotations(&self) -> Result<Vec<HeirRotation>> {
+        log::debug!("HeritageWallet::list_heir_rotations");
+        Ok(self.database.borrow().list_heir_rotations()?)
+    }
+}
@@ -0,0 +1,137 @@
+//! Journaling of the multi-step operations of an [HeritageWallet] that cannot be
+//! committed in a single database transaction.
+//!
+//! Before such an operation writes anything, it records itself as a [PendingOperation]
+//! in the database and it removes it once completed. If the process is interrupted
+//! in-between, the next [HeritageWallet] opening the database finds the
+//! [PendingOperation] and completes or rolls it back,
+//! see [HeritageWallet::resume_pending_operation].
+
+use bdk::{database::Database, wallet::AddressIndex, KeychainKind};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::TransacHeritageDatabase,
+    errors::{DatabaseError, Error, Result},
+    subwallet_config::{SubwalletConfig, SubwalletId},
+};
+
+use super::{HeritageWallet, SubwalletConfigId};
+
+/// The last address indexes to restore for a subwallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubwalletAddressIndexes {
+    pub subwallet_id: SubwalletId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_external_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_change_index: Option<u32>,
+}
+
+/// A multi-step operation of an [HeritageWallet] that was started but not completed yet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum PendingOperation {
+    /// [HeritageWallet::restore_backup]: the [SubwalletConfig]s are written in a single
+    /// transaction, then the address indexes of each subwallet are reset one by one
+    RestoreBackup {
+        address_indexes: Vec<SubwalletAddressIndexes>,
+    },
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Complete or roll back the [PendingOperation] left in the database by an interrupted
+    /// process, if any, and return it. This is done automatically when the [HeritageWallet]
+    /// is created.
+    ///
+    /// For [PendingOperation::RestoreBackup], the address indexes are reset if the
+    /// [SubwalletConfig]s were written, else there is nothing to roll back and the
+    /// [PendingOperation] is simply discarded.
+    pub fn resume_pending_operation(&self) -> Result<Option<PendingOperation>> {
+        log::debug!("HeritageWallet::resume_pending_operation");
+        let Some(pending_operation) = self.database.borrow().get_pending_operation()? else {
+            return Ok(None);
+        };
+        log::warn!(
+            "HeritageWallet::resume_pending_operation - Found pending_operation={pending_operation:?}"
+        );
+        match &pending_operation {
+            PendingOperation::RestoreBackup { address_indexes } => {
+                let mut subwallet_configs =
+                    self.database.borrow().list_obsolete_subwallet_configs()?;
+                subwallet_configs.extend(
+                    self.database
+                        .borrow()
+                        .get_subwallet_config(SubwalletConfigId::Current)?,
+                );
+                let to_resume = address_indexes
+                    .iter()
+                    .map(|indexes| {
+                        subwallet_configs
+                            .iter()
+                            .find(|swc| swc.subwallet_id() == indexes.subwallet_id)
+                            .map(|swc| (swc, indexes))
+                    })
+                    .collect::<Option<Vec<_>>>();
+                match to_resume {
+                    Some(to_resume) => {
+                        log::info!("HeritageWallet::resume_pending_operation - Resuming");
+                        for (swc, indexes) in to_resume {
+                            self.reset_address_indexes(swc, indexes)?;
+                        }
+                    }
+                    None => {
+                        log::info!("HeritageWallet::resume_pending_operation - Rolling back");
+                    }
+                }
+            }
+        }
+        self.database.borrow_mut().delete_pending_operation()?;
+        Ok(Some(pending_operation))
+    }
+
+    /// Reset the last address indexes of the subwallet of `subwallet_config`, unless
+    /// they are already at or beyond the ones of `indexes`
+    pub(super) fn reset_address_indexes(
+        &self,
+        subwallet_config: &SubwalletConfig,
+        indexes: &SubwalletAddressIndexes,
+    ) -> Result<()> {
+        let Some(max_index) = Ord::max(indexes.last_external_index, indexes.last_change_index)
+        else {
+            return Ok(());
+        };
+        let sw = self.get_subwallet(subwallet_config)?;
+        // We must ensure addresses are cached up to the max index we are setting
+        // or it may become a fucking mess as BDK we never be able to cache
+        // the previous address if the user get a new address before syncing.
+        sw.ensure_addresses_cached(max_index + 1)
+            .map_err(|e| Error::FailedToResetAddressIndex(e.to_string()))?;
+        for (keychain, last_index) in [
+            (KeychainKind::External, indexes.last_external_index),
+            (KeychainKind::Internal, indexes.last_change_index),
+        ] {
+            let Some(last_index) = last_index else {
+                continue;
+            };
+            let current_last_index = sw
+                .database()
+                .get_last_index(keychain)
+                .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+            if current_last_index.is_some_and(|current| current >= last_index) {
+                continue;
+            }
+            log::info!(
+                "HeritageWallet::reset_address_indexes - \
+                SubwalletConfigId({}) reset {keychain:?} index {last_index}",
+                subwallet_config.subwallet_id()
+            );
+            match keychain {
+                KeychainKind::External => sw.get_address(AddressIndex::Reset(last_index)),
+                KeychainKind::Internal => sw.get_internal_address(AddressIndex::Reset(last_index)),
+            }
+            .map_err(|e| Error::FailedToResetAddressIndex(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
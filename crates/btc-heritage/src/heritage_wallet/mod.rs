@@ -1,69 +1,181 @@
+pub mod addresses;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub mod asynchronous;
 pub mod backup;
+pub mod batch;
+pub mod bip329;
+pub mod dissolution;
+pub mod export;
+pub mod heir_rotation;
+pub mod journal;
 #[cfg(any(feature = "online", test))]
 pub mod online;
+pub mod prune;
+pub mod simulation;
 mod types;
 
-use core::cell::RefCell;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
 
 use crate::{
     account_xpub::AccountXPub,
     bitcoin::{
         absolute::LockTime,
-        bip32::Fingerprint,
+        bip32::{ChildNumber, DerivationPath, Fingerprint},
+        opcodes::all::{OP_CLTV, OP_CSV},
         psbt::{Input, Output, Psbt},
-        Address, Amount, FeeRate, OutPoint, Script, Sequence, TxOut, Weight,
+        script::Instruction,
+        secp256k1::rand::{thread_rng, Rng},
+        taproot::TapLeafHash,
+        Address, Amount, FeeRate, Network, OutPoint, Script, ScriptBuf, Sequence, SignedAmount,
+        TxOut, Txid, Weight, Witness,
     },
     database::{
-        PartitionableDatabase, SubdatabaseId, TransacHeritageDatabase, TransacHeritageOperation,
+        integrity::IntegrityReport, PartitionableDatabase, SubdatabaseId, TransacHeritageDatabase,
+        TransacHeritageOperation,
     },
     errors::{DatabaseError, Error, Result},
     heritage_config::{HeritageConfig, HeritageExplorer, HeritageExplorerTrait},
-    miniscript::{Miniscript, Tap},
+    miniscript::{psbt::PsbtExt, Miniscript, Tap},
     subwallet_config::SubwalletConfig,
-    utils::bitcoin_network_from_env,
     HeirConfig,
 };
 
-use backup::{HeritageWalletBackup, SubwalletDescriptorBackup};
+use backup::{CoreDescriptorImport, HeritageWalletBackup, SubwalletDescriptorBackup, WalletExport};
 use bdk::{
     database::Database,
-    wallet::{AddressIndex, AddressInfo, IsDust},
+    wallet::{
+        coin_selection::OldestFirstCoinSelection, tx_builder::TxOrdering, AddressIndex,
+        AddressInfo, IsDust,
+    },
     BlockTime, FeeRate as BdkFeeRate, KeychainKind, LocalUtxo, Wallet,
 };
+use bip329::{Bip329Label, Bip329Labels, Bip329Ref};
+use export::{ExportFormat, TransactionExport, UtxoExport};
+use journal::{PendingOperation, SubwalletAddressIndexes};
 
 pub use types::*;
 
+/// The default incremental relay fee rate of Bitcoin Core, used for the BIP-125 rule 4
+const INCREMENTAL_RELAY_FEE_RATE: FeeRate = FeeRate::from_sat_per_vb_unchecked(1);
+
 #[derive(Debug, Clone)]
 enum Spender {
     Owner,
     Heir(HeirConfig),
 }
 
+/// The lock protecting the database of an [HeritageWallet], with the interface of a
+/// [RefCell](core::cell::RefCell).
+///
+/// Any number of threads can [DatabaseLock::borrow] the database at the same time while
+/// [DatabaseLock::borrow_mut] gives an exclusive access, waiting for the other borrows
+/// to be released. A thread must not request a mutable borrow while it holds another
+/// borrow: it would deadlock.
+/// A panic while the database is borrowed does not prevent further borrows: the database
+/// is responsible for its own consistency.
+#[derive(Debug)]
+struct DatabaseLock<D>(RwLock<D>);
+
+impl<D> DatabaseLock<D> {
+    fn new(database: D) -> Self {
+        Self(RwLock::new(database))
+    }
+
+    fn borrow(&self) -> RwLockReadGuard<'_, D> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn borrow_mut(&self) -> RwLockWriteGuard<'_, D> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// An Heritage wallet.
+///
+/// The [HeritageWallet] is [Send] and [Sync] when its database is, so it can be shared
+/// across threads, e.g. in an [Arc](std::sync::Arc). Each database access is atomic and
+/// the operations updating several records rely on the database transactions, see
+/// [TransacHeritageDatabase], to detect concurrent modifications. The synchronizations
+/// with the blockchain are serialized.
 pub struct HeritageWallet<D: TransacHeritageDatabase> {
-    database: RefCell<D>,
+    database: DatabaseLock<D>,
+    network: Network,
+    #[cfg(any(feature = "online", test))]
+    sync_lock: std::sync::Mutex<()>,
 }
 
 impl<D: TransacHeritageDatabase> HeritageWallet<D> {
-    pub fn new(database: D) -> Self {
+    /// Open the [HeritageWallet] stored in `database`, on the [Network] recorded in it.
+    ///
+    /// A [PendingOperation] left by an interrupted process is completed or rolled back,
+    /// see [HeritageWallet::resume_pending_operation].
+    ///
+    /// # Errors
+    /// Returns [Error::UnknownNetwork] if `database` does not record a [Network], e.g.
+    /// because it was created by a previous version. Such a database must be opened with
+    /// [HeritageWallet::new_with_network].
+    pub fn new(database: D) -> Result<Self> {
         log::debug!("HeritageWallet::new");
-        Self {
-            database: RefCell::new(database),
+        let network = database.get_network()?.ok_or(Error::UnknownNetwork)?;
+        let wallet = Self {
+            database: DatabaseLock::new(database),
+            network,
+            #[cfg(any(feature = "online", test))]
+            sync_lock: std::sync::Mutex::new(()),
+        };
+        wallet.resume_pending_operation()?;
+        Ok(wallet)
+    }
+
+    /// Create or open the [HeritageWallet] stored in `database` for the `network`.
+    /// The `network` is recorded in `database` if it was not already.
+    ///
+    /// Like [HeritageWallet::new], a [PendingOperation] left by an interrupted process
+    /// is completed or rolled back.
+    ///
+    /// # Errors
+    /// Returns [Error::NetworkMismatch] if `database` records another [Network]
+    pub fn new_with_network(mut database: D, network: Network) -> Result<Self> {
+        log::debug!("HeritageWallet::new_with_network - network={network}");
+        match database.get_network()? {
+            Some(found) if found != network => {
+                return Err(Error::NetworkMismatch {
+                    expected: network,
+                    found,
+                })
+            }
+            Some(_) => (),
+            None => database.set_network(network)?,
         }
+        let wallet = Self {
+            database: DatabaseLock::new(database),
+            network,
+            #[cfg(any(feature = "online", test))]
+            sync_lock: std::sync::Mutex::new(()),
+        };
+        wallet.resume_pending_operation()?;
+        Ok(wallet)
+    }
+
+    /// Return the [Network] of the [HeritageWallet]
+    pub fn network(&self) -> Network {
+        self.network
     }
 
     pub fn generate_backup(&self) -> Result<HeritageWalletBackup> {
         log::debug!("HeritageWallet::generate_backup");
-        Ok(HeritageWalletBackup(
+        let mut subwallet_configs = self.database.borrow().list_obsolete_subwallet_configs()?;
+        subwallet_configs.extend(
             self.database
                 .borrow()
-                .list_obsolete_subwallet_configs()?
+                .get_subwallet_config(SubwalletConfigId::Current)?,
+        );
+        Ok(HeritageWalletBackup(
+            subwallet_configs
                 .into_iter()
-                .chain(
-                    self.database
-                        .borrow()
-                        .get_subwallet_config(SubwalletConfigId::Current)?,
-                )
                 .map(|swc| {
                     let sw = self.get_subwallet(&swc)?;
                     let last_external_index = sw
@@ -81,12 +193,88 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
                         first_use_ts: swc.subwallet_firstuse_time(),
                         last_external_index,
                         last_change_index,
+                        keypath_change_descriptor: swc.keypath_change_descriptor().cloned(),
                     })
                 })
                 .collect::<Result<_>>()?,
         ))
     }
 
+    /// Generate a [HeritageWalletBackup] encrypted with the given passphrase.
+    /// See [HeritageWallet::generate_backup]
+    #[cfg(feature = "backup-encryption")]
+    pub fn generate_encrypted_backup(
+        &self,
+        passphrase: &str,
+    ) -> Result<backup::EncryptedHeritageWalletBackup> {
+        log::debug!("HeritageWallet::generate_encrypted_backup");
+        backup::EncryptedHeritageWalletBackup::encrypt(&self.generate_backup()?, passphrase)
+    }
+
+    /// Export the descriptors of every subwallet in the format expected by the
+    /// `importdescriptors` RPC of Bitcoin Core, so that the [HeritageWallet] can be
+    /// watched independently in a Bitcoin Core watch-only wallet.
+    ///
+    /// Only the descriptors of the current subwallet are marked as active.
+    pub fn export_core_descriptors(&self) -> Result<Vec<CoreDescriptorImport>> {
+        log::debug!("HeritageWallet::export_core_descriptors");
+        let has_current = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .is_some();
+        // generate_backup lists the obsolete subwallets first and the current one last
+        let backup = self.generate_backup()?;
+        let subwallet_count = backup.0.len();
+        Ok(backup
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, sdb)| sdb.core_descriptors(has_current && i + 1 == subwallet_count))
+            .collect())
+    }
+
+    /// Export the current subwallet in the generic "wallet export" JSON format so that tools
+    /// like Sparrow can be used as a secondary watch-only viewer.
+    ///
+    /// The `blockheight` is the height of the oldest transaction known by the current
+    /// subwallet, or `0` if there is none.
+    ///
+    /// # Errors
+    /// Returns an error if there is no current subwallet
+    pub fn export_wallet(&self, label: &str) -> Result<WalletExport> {
+        log::debug!("HeritageWallet::export_wallet - label={label}");
+        let subwalletconfig = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .ok_or(Error::MissingCurrentSubwalletConfig)?;
+        let blockheight = self
+            .get_subwallet(&subwalletconfig)?
+            .list_transactions(false)
+            .map_err(|e| DatabaseError::Generic(e.to_string()))?
+            .into_iter()
+            .filter_map(|tx| tx.confirmation_time.map(|bt| bt.height))
+            .min()
+            .unwrap_or(0);
+        Ok(WalletExport {
+            label: label.to_owned(),
+            blockheight,
+            descriptor: subwalletconfig.ext_descriptor().to_string(),
+        })
+    }
+
+    /// Decrypt an [EncryptedHeritageWalletBackup](backup::EncryptedHeritageWalletBackup)
+    /// with the given passphrase and restore it. See [HeritageWallet::restore_backup]
+    #[cfg(feature = "backup-encryption")]
+    pub fn restore_backup_encrypted(
+        &self,
+        encrypted_backup: &backup::EncryptedHeritageWalletBackup,
+        passphrase: &str,
+    ) -> Result<()> {
+        log::debug!("HeritageWallet::restore_backup_encrypted");
+        self.restore_backup(encrypted_backup.decrypt(passphrase)?)
+    }
+
     pub fn restore_backup(&self, backup: HeritageWalletBackup) -> Result<()> {
         log::debug!("HeritageWallet::restore_backup - backup={backup:?}");
         if backup.0.len() == 0 {
@@ -106,6 +294,14 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             .into_iter()
             .map(|swc_backup| Ok((SubwalletConfig::try_from(&swc_backup)?, swc_backup)))
             .collect::<Result<Vec<_>>>()?;
+        if swc_and_backups
+            .iter()
+            .any(|(swc, _)| !swc.account_xpub().is_valid_for_network(self.network))
+        {
+            return Err(Error::InvalidBackup(
+                "the descriptors are not for the network of the wallet",
+            ));
+        }
 
         log::info!("HeritageWallet::restore_backup - All SubwalletConfig(s) created");
         // Ensure they are sorted by ID
@@ -118,6 +314,14 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             .0
             .subwallet_id();
         log::debug!("HeritageWallet::restore_backup - last_id={last_id}");
+        let address_indexes = swc_and_backups
+            .iter()
+            .map(|(swc, swc_backup)| SubwalletAddressIndexes {
+                subwallet_id: swc.subwallet_id(),
+                last_external_index: swc_backup.last_external_index,
+                last_change_index: swc_backup.last_change_index,
+            })
+            .collect::<Vec<_>>();
         let mut transaction = self.database.borrow().begin_transac();
         for (swc, _) in swc_and_backups.iter() {
             let swc_id = swc.subwallet_id();
@@ -132,134 +336,173 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             );
             transaction.put_subwallet_config(swc_id, swc)?;
         }
-        self.database.borrow_mut().commit_transac(transaction)?;
+        // The address indexes are reset outside of the transaction: journal the operation
+        // so it can be resumed if the process is interrupted
+        self.database
+            .borrow_mut()
+            .set_pending_operation(&PendingOperation::RestoreBackup {
+                address_indexes: address_indexes.clone(),
+            })?;
+        if let Err(e) = self.database.borrow_mut().commit_transac(transaction) {
+            self.database.borrow_mut().delete_pending_operation()?;
+            return Err(e.into());
+        }
         log::info!("HeritageWallet::restore_backup - All SubwalletConfig(s) written to DB");
 
-        for (swc, swc_backup) in swc_and_backups.into_iter() {
-            if let Some(max_index) =
-                Ord::max(swc_backup.last_external_index, swc_backup.last_change_index)
-            {
-                let sw = self.get_subwallet(&swc)?;
-                // We must ensure addresses are cached up to the max index we are setting
-                // or it may become a fucking mess as BDK we never be able to cache
-                // the previous address if the user get a new address before syncing.
-                sw.ensure_addresses_cached(max_index + 1)
-                    .map_err(|e| Error::FailedToResetAddressIndex(e.to_string()))?;
-                if let Some(last_external_index) = swc_backup.last_external_index {
-                    log::info!(
-                        "HeritageWallet::restore_backup - \
-                    SubwalletConfigId({}) reset external index {last_external_index}",
-                        swc.subwallet_id()
-                    );
-                    sw.get_address(AddressIndex::Reset(last_external_index))
-                        .map_err(|e| Error::FailedToResetAddressIndex(e.to_string()))?;
-                }
-                if let Some(last_change_index) = swc_backup.last_change_index {
-                    log::info!(
-                        "HeritageWallet::restore_backup - \
-                    SubwalletConfigId({}) reset change index {last_change_index}",
-                        swc.subwallet_id()
-                    );
-                    sw.get_internal_address(AddressIndex::Reset(last_change_index))
-                        .map_err(|e| Error::FailedToResetAddressIndex(e.to_string()))?;
-                }
-            }
+        for ((swc, _), indexes) in swc_and_backups.iter().zip(address_indexes.iter()) {
+            self.reset_address_indexes(swc, indexes)?;
         }
+        self.database.borrow_mut().delete_pending_operation()?;
         log::info!("HeritageWallet::restore_backup - Done");
         Ok(())
     }
 
+    /// Return every [WalletAddress] of the wallet: the external addresses of the current
+    /// subwallet, from the most recent, then its change addresses, then the external and
+    /// change addresses of the previous subwallets.
+    ///
+    /// See [HeritageWallet::iter_wallet_addresses] and [HeritageWallet::list_wallet_addresses_paginated]
+    /// to avoid loading every address at once.
     pub fn list_wallet_addresses(&self) -> Result<Vec<WalletAddress>> {
         log::debug!("HeritageWallet::list_wallet_addresses");
-        let Some(fingerprint) = self.fingerprint()? else {
-            // No fingerprint means no AccountXPub, so no address either
-            return Ok(vec![]);
-        };
+        self.iter_wallet_addresses()?.collect()
+    }
 
-        let intermediate_results = self
+    /// Return the [TransactionSummary] of the wallet, from the most recent to the oldest,
+    /// along with their labels
+    pub fn list_transaction_summaries(&self) -> Result<Vec<TransactionSummary>> {
+        log::debug!("HeritageWallet::list_transaction_summaries");
+        let mut tx_labels = self
             .database
             .borrow()
-            .list_obsolete_subwallet_configs()?
+            .list_labels()?
             .into_iter()
-            .chain(
-                self.database
-                    .borrow()
-                    .get_subwallet_config(SubwalletConfigId::Current)?,
-            )
-            // Map each subwallet config to a WalletAddress iterator
-            .map(|swc| {
-                // Retrieve the derivation path of the account xpub
-                let axpub_dp = swc
-                    .account_xpub()
-                    .descriptor_public_key()
-                    .full_derivation_path()
-                    .expect("DerivationPath is present for an Account Xpub");
-                let mut axpub_dpi = axpub_dp.normal_children();
-
-                // Construct the external and change DerivationPath
-                let (ext_dp, change_dp) = (axpub_dpi.next().unwrap(), axpub_dpi.next().unwrap());
+            .filter_map(|(label_ref, label)| match label_ref {
+                LabelRef::Tx(txid) => Some((txid, label)),
+                LabelRef::Address(_) => None,
+            })
+            .collect::<HashMap<_, _>>();
+        Ok(self
+            .database
+            .borrow()
+            .list_transaction_summaries()?
+            .into_iter()
+            .map(|mut tx_summary| {
+                tx_summary.label = tx_labels.remove(&tx_summary.txid);
+                tx_summary
+            })
+            .collect())
+    }
 
-                // Open the Subwallet DB
-                let sw = self.get_subwallet(&swc)?;
+    /// Return the addresses of the wallet that received more than one payment, as recorded
+    /// during the last synchronization, so that the owner can deliberately consolidate them
+    pub fn list_reused_addresses(&self) -> Result<Vec<ReusedAddress>> {
+        log::debug!("HeritageWallet::list_reused_addresses");
+        let tx_summaries = self.database.borrow().list_transaction_summaries()?;
+        let res = reused_addresses(tx_summaries.iter())
+            .into_iter()
+            .map(|(address, txids)| ReusedAddress { address, txids })
+            .collect::<Vec<_>>();
+        log::debug!("HeritageWallet::list_reused_addresses - res={res:?}");
+        Ok(res)
+    }
 
-                // Retrieve the last external index
-                let last_external_index = sw
-                    .database()
-                    .get_last_index(KeychainKind::External)
-                    .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+    /// Return the transaction history of the wallet, from the most recent to the oldest
+    /// transaction, each [TransactionSummary] being annotated with the resulting balance,
+    /// whether it refreshed the timelocks of the funds it moved and which heirs gained or
+    /// lost a claim on the wallet funds because of it.
+    ///
+    /// Transactions confirmed in the same block are not ordered, so the running balance may
+    /// be transiently inaccurate between them.
+    pub fn get_history(&self) -> Result<Vec<HistoryEntry>> {
+        log::debug!("HeritageWallet::get_history");
+        let subwallets = self.get_all_subwallets()?;
+        // The HeritageConfig protecting an owned address, None for the key-path-only change
+        let heritage_config_of = |address: &CheckedAddress| -> Result<Option<&HeritageConfig>> {
+            Ok(
+                Self::find_script_owner(&subwallets, &address.script_pubkey())?.and_then(
+                    |(swc, keychain)| {
+                        let keypath_only =
+                            keychain == KeychainKind::Internal && swc.has_keypath_change();
+                        (!keypath_only).then(|| swc.heritage_config())
+                    },
+                ),
+            )
+        };
+        let heirs_of = |owned_ios: &[TransactionSummaryOwnedIO]| -> Result<BTreeSet<HeirConfig>> {
+            let mut heirs = BTreeSet::new();
+            for owned_io in owned_ios {
+                if let Some(heritage_config) = heritage_config_of(&owned_io.address)? {
+                    heirs.extend(heritage_config.iter_heir_configs().cloned());
+                }
+            }
+            Ok(heirs)
+        };
 
-                // Retrieve the last change index
-                let last_change_index = sw
-                    .database()
-                    .get_last_index(KeychainKind::Internal)
-                    .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+        let mut tx_summaries = self.list_transaction_summaries()?;
+        // Compute the running balance from the oldest transaction
+        tx_summaries.reverse();
+        let mut running_balance_sat = 0i64;
+        let mut history = Vec::with_capacity(tx_summaries.len());
+        for transaction_summary in tx_summaries {
+            let amount_sat = |owned_ios: &[TransactionSummaryOwnedIO]| {
+                owned_ios
+                    .iter()
+                    .map(|io| io.amount.to_sat() as i64)
+                    .sum::<i64>()
+            };
+            let balance_delta = SignedAmount::from_sat(
+                amount_sat(&transaction_summary.owned_outputs)
+                    - amount_sat(&transaction_summary.owned_inputs),
+            );
+            running_balance_sat += balance_delta.to_sat();
 
-                // For each (index, keychain, derivation_path)
-                let wallet_addresses = [
-                    (last_change_index, KeychainKind::Internal, change_dp),
-                    (last_external_index, KeychainKind::External, ext_dp),
-                ]
-                .into_iter()
-                // Filtermap, if last index is present, find all address up to that index
-                // Else, do nothing
-                .filter_map(|(last_index, kc, dp)| {
-                    last_index.map(|last_index| {
-                        let wallet_addresses = sw
-                            .database()
-                            .iter_script_pubkeys(Some(kc))
-                            .map_err(|e| DatabaseError::Generic(e.to_string()))?
-                            .into_iter()
-                            .zip(dp.normal_children())
-                            .take((last_index + 1) as usize)
-                            .map(|(sb, dp)| WalletAddress {
-                                origin: (fingerprint, dp),
-                                address: Address::from_script(
-                                    sb.as_script(),
-                                    *bitcoin_network_from_env(),
-                                )
-                                .expect(
-                                    "script should always be valid from the \
-                                correct network inside the DB",
-                                ),
-                            })
-                            .collect::<Vec<_>>();
-                        Ok(wallet_addresses)
-                    })
+            let input_heirs = heirs_of(&transaction_summary.owned_inputs)?;
+            let output_heirs = heirs_of(&transaction_summary.owned_outputs)?;
+            let refreshed_timelocks = !transaction_summary.owned_inputs.is_empty()
+                && transaction_summary
+                    .owned_outputs
+                    .iter()
+                    .map(|io| heritage_config_of(&io.address))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .any(|hc| hc.is_some());
+            let heir_eligibility_changes = output_heirs
+                .difference(&input_heirs)
+                .map(|heir_config| HeirEligibilityChange {
+                    heir_config: heir_config.clone(),
+                    change: EligibilityChange::Gained,
                 })
-                .collect::<Result<Vec<_>>>()?;
-                Ok(wallet_addresses)
-            })
-            .collect::<Result<Vec<_>>>()?;
+                .chain(input_heirs.difference(&output_heirs).map(|heir_config| {
+                    HeirEligibilityChange {
+                        heir_config: heir_config.clone(),
+                        change: EligibilityChange::Lost,
+                    }
+                }))
+                .collect();
+
+            history.push(HistoryEntry {
+                transaction_summary,
+                balance_delta,
+                running_balance: Amount::from_sat(running_balance_sat.max(0) as u64),
+                refreshed_timelocks,
+                heir_eligibility_changes,
+            });
+        }
+        history.reverse();
+        Ok(history)
+    }
 
-        // At this point we got a Vec<Vec<Vec<WalletAddress>>>
-        // We flatten and reverse it
-        // Reverse so that the result vec starts with external addresses of the current config,
-        // then change addresses of the current config, then external of the previous configs, etc...
-        Ok(intermediate_results
+    fn address_labels(&self) -> Result<HashMap<Address, String>> {
+        Ok(self
+            .database
+            .borrow()
+            .list_labels()?
             .into_iter()
-            .flatten()
-            .flatten()
-            .rev()
+            .filter_map(|(label_ref, label)| match label_ref {
+                LabelRef::Address(address) => Some(((*address).clone(), label)),
+                LabelRef::Tx(_) => None,
+            })
             .collect())
     }
 
@@ -268,6 +511,19 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
         self.database.borrow()
     }
 
+    /// Verify the integrity of the internal database and, if `repair` is `true`, fix the
+    /// repairable issues, see [HeritageDatabase::verify_integrity](crate::database::HeritageDatabase::verify_integrity)
+    pub fn verify_database_integrity(&self, repair: bool) -> Result<IntegrityReport> {
+        log::debug!("HeritageWallet::verify_database_integrity - repair={repair}");
+        let res = if repair {
+            self.database.borrow_mut().repair_integrity()?
+        } else {
+            self.database.borrow().verify_integrity()?
+        };
+        log::debug!("HeritageWallet::verify_database_integrity - res={res:?}");
+        Ok(res)
+    }
+
     pub fn list_used_account_xpubs(&self) -> Result<Vec<AccountXPub>> {
         log::debug!("HeritageWallet::list_used_account_xpubs");
         let res = self.database.borrow().list_used_account_xpubs()?;
@@ -282,6 +538,53 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
         Ok(res)
     }
 
+    /// Return an [AccountXPubAudit] for every used and unused account xpub of the wallet,
+    /// the used ones first, ordered by account index.
+    ///
+    /// It allows to detect account xpubs of mixed origins and stale devices before they
+    /// cause descriptor mismatches.
+    pub fn audit_account_xpubs(&self) -> Result<Vec<AccountXPubAudit>> {
+        log::debug!("HeritageWallet::audit_account_xpubs");
+        let wallet_fingerprint = self.fingerprint()?;
+        let db = self.database.borrow();
+        let current_subwallet_config = db.get_subwallet_config(SubwalletConfigId::Current)?;
+        let current_subwallet_id = current_subwallet_config
+            .as_ref()
+            .map(|swc| swc.subwallet_id());
+        let mut subwallet_configs = db.list_obsolete_subwallet_configs()?;
+        subwallet_configs.extend(current_subwallet_config);
+        subwallet_configs.sort_by_key(|swc| swc.subwallet_id());
+
+        let audit = |account_xpub: AccountXPub, subwallet_config: Option<&SubwalletConfig>| {
+            let fingerprint = account_xpub.descriptor_public_key().master_fingerprint();
+            let derivation_path = account_xpub
+                .descriptor_public_key()
+                .full_derivation_path()
+                .expect("multipath extended keys not supported");
+            let subwallet_id = subwallet_config.map(|swc| swc.subwallet_id());
+            AccountXPubAudit {
+                account_xpub,
+                fingerprint,
+                derivation_path,
+                subwallet_id,
+                firstuse_time: subwallet_config.and_then(|swc| swc.subwallet_firstuse_time()),
+                is_current: subwallet_id.is_some() && subwallet_id == current_subwallet_id,
+                matches_wallet_fingerprint: wallet_fingerprint == Some(fingerprint),
+            }
+        };
+        let mut res = subwallet_configs
+            .iter()
+            .map(|swc| audit(swc.account_xpub().clone(), Some(swc)))
+            .collect::<Vec<_>>();
+        res.extend(
+            db.list_unused_account_xpubs()?
+                .into_iter()
+                .map(|account_xpub| audit(account_xpub, None)),
+        );
+        log::debug!("HeritageWallet::audit_account_xpubs - res={res:?}");
+        Ok(res)
+    }
+
     /// Returns the fingerprint of the Heritage Wallet master key
     /// if the wallet already has Account Xpubs
     /// Else return None
@@ -343,6 +646,75 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
         Ok(None)
     }
 
+    /// Verify that `address` belongs to one of the subwallets of this [HeritageWallet], either
+    /// as a receiving or a change address, and return its [AddressOrigin]. Return [None] if
+    /// the address does not belong to the wallet.
+    ///
+    /// Only the addresses already derived by the subwallets (i.e. the addresses generated so far
+    /// and the lookahead window of the synchronization) can be found.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if there are problems with the database.
+    pub fn verify_address(&self, address: &Address) -> Result<Option<AddressOrigin>> {
+        log::debug!("HeritageWallet::verify_address - address={address}");
+        let script = address.script_pubkey();
+        let current_subwalletconfig = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?;
+        let obsolete_subwalletconfigs = self.database.borrow().list_obsolete_subwallet_configs()?;
+        // Look into the current subwallet first, then from the newest obsolete subwallet to the oldest
+        let subwalletconfigs = current_subwalletconfig
+            .into_iter()
+            .map(|swc| (swc, true))
+            .chain(
+                obsolete_subwalletconfigs
+                    .into_iter()
+                    .rev()
+                    .map(|swc| (swc, false)),
+            );
+        for (swc, is_current) in subwalletconfigs {
+            let Some((keychain, index)) = self
+                .get_subwallet(&swc)?
+                .database()
+                .get_path_from_script_pubkey(&script)
+                .map_err(|e| DatabaseError::Generic(e.to_string()))?
+            else {
+                continue;
+            };
+            let axpub_dpk = swc.account_xpub().descriptor_public_key();
+            let branch = match keychain {
+                KeychainKind::External => 0,
+                KeychainKind::Internal if swc.has_keypath_change() => {
+                    SubwalletConfig::DEFAULT_KEYPATH_CHANGE_INDEX
+                }
+                KeychainKind::Internal => 1,
+            };
+            let derivation_path = axpub_dpk
+                .full_derivation_path()
+                .expect("DerivationPath is present for an Account Xpub")
+                .child(ChildNumber::Normal { index: branch })
+                .child(ChildNumber::Normal { index });
+            let label =
+                self.get_label(&LabelRef::Address(CheckedAddress::from(address.clone())))?;
+            let address_origin = AddressOrigin {
+                wallet_address: WalletAddress {
+                    origin: (axpub_dpk.master_fingerprint(), derivation_path),
+                    address: address.clone(),
+                    label,
+                },
+                is_change: keychain == KeychainKind::Internal,
+                subwallet_id: swc.subwallet_id(),
+                is_current,
+            };
+            log::debug!("HeritageWallet::verify_address - address_origin={address_origin:?}");
+            return Ok(Some(address_origin));
+        }
+        log::debug!("HeritageWallet::verify_address - address not found");
+        Ok(None)
+    }
+
     /// Verify if a ScriptPubKey belong to the wallet of current [HeritageConfig] of this
     /// [HeritageWallet].
     ///
@@ -443,6 +815,16 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
                 return Err(Error::InvalidAccountXPub);
             }
         }
+        if account_xpubs
+            .iter()
+            .any(|axpub| !axpub.is_valid_for_network(self.network))
+        {
+            log::error!(
+                "Cannot add Account Xpubs that are not for the {} network",
+                self.network
+            );
+            return Err(Error::InvalidAccountXPub);
+        }
         log::debug!("HeritageWallet::append_account_xpubs - account_xpubs={account_xpubs:?}");
         self.database
             .borrow_mut()
@@ -450,10 +832,66 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             .map_err(Into::into)
     }
 
+    /// Set a new [HeritageConfig] for the wallet, keeping the key-path-only change mode of the
+    /// current subwallet, see [HeritageWallet::update_heritage_config_with_keypath_change].
+    /// The key-path-only change mode is dropped if the new [HeritageConfig] has an owner quorum.
     pub fn update_heritage_config(&self, new_heritage_config: HeritageConfig) -> Result<()> {
+        let keypath_change = new_heritage_config.owner_quorum().is_none()
+            && self
+                .database
+                .borrow()
+                .get_subwallet_config(SubwalletConfigId::Current)?
+                .is_some_and(|swc| swc.has_keypath_change());
+        self.update_heritage_config_with_keypath_change(new_heritage_config, keypath_change)
+    }
+
+    /// Set a new [HeritageConfig] for the wallet. If `keypath_change` is `true`, the change
+    /// outputs of the new subwallet go to a key-path-only descriptor: they are not protected
+    /// by the heirs until refreshed, see [HeritageWallet::create_keypath_refresh_psbt].
+    ///
+    /// # Errors
+    /// Returns [Error::KeypathChangeRequiresNewHeritageConfig] if only `keypath_change` changes
+    /// while the current subwallet has already been used.
+    /// Returns [Error::KeypathChangeWithOwnerQuorum] if `keypath_change` is `true` while the new
+    /// [HeritageConfig] has an owner quorum, and [Error::InvalidOwnerQuorum] if a co-owner key
+    /// belongs to the wallet.
+    pub fn update_heritage_config_with_keypath_change(
+        &self,
+        new_heritage_config: HeritageConfig,
+        keypath_change: bool,
+    ) -> Result<()> {
         log::debug!(
-            "HeritageWallet::update_heritage_config - new_heritage_config={new_heritage_config:?}"
+            "HeritageWallet::update_heritage_config - new_heritage_config={new_heritage_config:?} \
+            keypath_change={keypath_change}"
         );
+        if let Some(owner_quorum) = new_heritage_config.owner_quorum() {
+            // The key-path is unspendable when the owner is a quorum of keys
+            if keypath_change {
+                log::error!("Cannot use the keypath change mode with an owner quorum");
+                return Err(Error::KeypathChangeWithOwnerQuorum);
+            }
+            if let Some(fingerprint) = self.fingerprint()? {
+                if owner_quorum
+                    .co_owner_xpubs()
+                    .iter()
+                    .any(|xpub| xpub.descriptor_public_key().master_fingerprint() == fingerprint)
+                {
+                    log::error!("A co-owner key of the owner quorum belongs to the wallet");
+                    return Err(Error::InvalidOwnerQuorum(
+                        "a co-owner key cannot belong to the wallet",
+                    ));
+                }
+            }
+        }
+        let build_subwallet_config =
+            |account_xpub: AccountXPub, heritage_config: HeritageConfig| {
+                let subwallet_config = SubwalletConfig::new(account_xpub, heritage_config);
+                if keypath_change {
+                    subwallet_config.with_keypath_change()
+                } else {
+                    subwallet_config
+                }
+            };
         log::info!("HeritageWallet::update_heritage_config - Called for an HeritageConfig update");
 
         // If we previously saw this HeritageConfig, bail
@@ -473,7 +911,7 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             .get_subwallet_config(SubwalletConfigId::Current)?
         else {
             log::debug!("HeritageWallet::update_heritage_config - No Current SubwalletConfig");
-            return self.create_new_subwallet_config(new_heritage_config, None);
+            return self.create_new_subwallet_config(new_heritage_config, None, keypath_change);
         };
         log::debug!(
             "HeritageWallet::update_heritage_config - current_subwallet_config={current_subwallet_config:?}"
@@ -483,7 +921,8 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             "HeritageWallet::update_heritage_config - current_heritage_config={current_heritage_config:?}"
         );
         // If the new_heritage_config is the same as the existing one, do nothing
-        if new_heritage_config == *current_heritage_config {
+        let same_keypath_change = current_subwallet_config.has_keypath_change() == keypath_change;
+        if new_heritage_config == *current_heritage_config && same_keypath_change {
             log::debug!(
                 "HeritageWallet::update_heritage_config - new_heritage_config == current_heritage_config."
             );
@@ -498,7 +937,7 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             log::debug!(
                 "HeritageWallet::update_heritage_config - current_subwallet_config.subwallet_firstuse_time().is_none()"
             );
-            let new_subwallet_config = SubwalletConfig::new(
+            let new_subwallet_config = build_subwallet_config(
                 current_subwallet_config.account_xpub().clone(),
                 new_heritage_config,
             );
@@ -514,12 +953,20 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
                     Some(&old_subwallet_config),
                 )
                 .map_err(Into::into)
+        } else if new_heritage_config == *current_heritage_config {
+            // A used subwallet cannot be replaced by a subwallet with the same HeritageConfig
+            log::error!("Cannot change the keypath change mode without a new HeritageConfig");
+            Err(Error::KeypathChangeRequiresNewHeritageConfig)
         } else {
             // If it has been used, call the full update procedue
             log::debug!(
                 "HeritageWallet::update_heritage_config - current_subwallet_config.subwallet_firstuse_time().is_some()"
             );
-            self.create_new_subwallet_config(new_heritage_config, Some(current_subwallet_config))
+            self.create_new_subwallet_config(
+                new_heritage_config,
+                Some(current_subwallet_config),
+                keypath_change,
+            )
         }
     }
 
@@ -558,1887 +1005,4974 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
         Ok(res)
     }
 
-    pub fn get_new_address(&self) -> Result<Address> {
-        log::info!("HeritageWallet::get_new_address - Called for a new Bitcoin address");
-        let address = self
-            .internal_get_new_address(KeychainKind::External)?
-            .address;
-        log::info!("HeritageWallet::get_new_address - address={address}");
-        Ok(address)
+    /// Returns the [BalanceDetails] of the wallet, see [HeritageWallet::get_balance_details_at]
+    pub fn get_balance_details(&self) -> Result<BalanceDetails> {
+        self.get_balance_details_at(crate::utils::timestamp_now())
     }
 
-    pub fn get_block_inclusion_objective(&self) -> Result<BlockInclusionObjective> {
-        Ok(self
-            .database
-            .borrow()
-            .get_block_inclusion_objective()?
-            .unwrap_or_default())
+    /// Returns the [BalanceDetails] of the wallet at the given `timestamp`: the balance of
+    /// each subwallet and the funds bucketed by the moment an heir becomes able to spend them.
+    ///
+    /// It is computed from the [HeritageUtxo]s of the last synchronization and, like
+    /// [HeritageUtxo::estimate_heir_spending_timestamp], MAY rely on estimations.
+    pub fn get_balance_details_at(&self, timestamp: u64) -> Result<BalanceDetails> {
+        log::debug!("HeritageWallet::get_balance_details_at - timestamp={timestamp}");
+        const SEC_IN_A_DAY: u64 = 24 * 60 * 60;
+        let db = self.database.borrow();
+        let current_subwallet_config = db.get_subwallet_config(SubwalletConfigId::Current)?;
+        let current_subwallet_id = current_subwallet_config
+            .as_ref()
+            .map(|swc| swc.subwallet_id());
+        let mut subwallet_configs = db.list_obsolete_subwallet_configs()?;
+        subwallet_configs.extend(current_subwallet_config);
+        subwallet_configs.sort_by_key(|swc| swc.subwallet_id());
+        let utxos = db.list_utxos()?;
+
+        let subwallets = subwallet_configs
+            .iter()
+            .map(|swc| {
+                let mut balance = SubwalletBalance {
+                    subwallet_id: swc.subwallet_id(),
+                    is_current: Some(swc.subwallet_id()) == current_subwallet_id,
+                    confirmed: Amount::ZERO,
+                    unconfirmed: Amount::ZERO,
+                };
+                // The HeritageConfig identifies the subwallet owning an HeritageUtxo
+                for hu in utxos
+                    .iter()
+                    .filter(|hu| &hu.heritage_config == swc.heritage_config())
+                {
+                    if hu.confirmation_time.is_some() {
+                        balance.confirmed += hu.amount;
+                    } else {
+                        balance.unconfirmed += hu.amount;
+                    }
+                }
+                balance
+            })
+            .collect();
+
+        let mut heir_eligibility = HeirEligibilityBuckets::default();
+        for hu in utxos.iter() {
+            let earliest_heir_ts = hu
+                .heritage_config
+                .iter_heir_configs()
+                .filter_map(|heir_config| hu.estimate_heir_spending_timestamp(heir_config))
+                .min();
+            let bucket = match earliest_heir_ts {
+                Some(ts) if ts <= timestamp => &mut heir_eligibility.eligible_now,
+                Some(ts) if ts <= timestamp + 30 * SEC_IN_A_DAY => {
+                    &mut heir_eligibility.eligible_within_30_days
+                }
+                Some(ts) if ts <= timestamp + 90 * SEC_IN_A_DAY => {
+                    &mut heir_eligibility.eligible_within_90_days
+                }
+                Some(ts) if ts <= timestamp + 365 * SEC_IN_A_DAY => {
+                    &mut heir_eligibility.eligible_within_365_days
+                }
+                _ => &mut heir_eligibility.not_eligible_within_365_days,
+            };
+            *bucket += hu.amount;
+        }
+
+        let res = BalanceDetails {
+            owner_spendable: utxos.iter().map(|hu| hu.amount).sum(),
+            subwallets,
+            heir_eligibility,
+        };
+        log::debug!("HeritageWallet::get_balance_details_at - res={res:?}");
+        Ok(res)
     }
 
-    pub fn set_block_inclusion_objective(&self, new_bio: BlockInclusionObjective) -> Result<()> {
-        self.database
-            .borrow_mut()
-            .set_block_inclusion_objective(new_bio)
-            .map_err(|e| DatabaseError::Generic(e.to_string()).into())
+    /// Returns the consolidated [InheritanceSchedule] of the wallet: for every UTXO and every heir
+    /// of the [HeritageConfig] of the subwallet owning it, when the heir will be able to spend it.
+    ///
+    /// Beware that the timestamps MAY be estimations based on the average Bitcoin network blocktime.
+    pub fn get_inheritance_schedule(&self) -> Result<InheritanceSchedule> {
+        log::debug!("HeritageWallet::get_inheritance_schedule");
+        let res = InheritanceSchedule::from_heritage_utxos(self.database.borrow().list_utxos()?);
+        log::debug!("HeritageWallet::get_inheritance_schedule - res={res:?}");
+        Ok(res)
     }
 
-    pub fn create_owner_psbt(
+    /// Identify the UTXOs that will become spendable by an heir within `horizon` seconds and
+    /// build a self-transfer [Psbt] to the current subwallet spending them, which resets their
+    /// relative timelocks and moves them to the current [HeritageConfig].
+    ///
+    /// The `options` are used to create the [Psbt], except for [CreatePsbtOptions::utxo_selection]
+    /// that is replaced by the UTXOs to refresh. Frozen UTXOs are never refreshed.
+    ///
+    /// Returns [None] if there is nothing to refresh.
+    pub fn plan_renewal(
         &self,
-        spending_config: SpendingConfig,
+        horizon: u64,
         options: CreatePsbtOptions,
-    ) -> Result<(Psbt, TransactionSummary)> {
-        log::debug!(
-            "HeritageWallet::create_owner_psbt - spending_config={spending_config:?} \
-            options={options:?}"
-        );
-        self.create_psbt(Spender::Owner, spending_config, options)
+    ) -> Result<Option<RenewalPlan>> {
+        log::debug!("HeritageWallet::plan_renewal - horizon={horizon} options={options:?}");
+        let present = match &options.assume_blocktime {
+            Some(block_time) => block_time.timestamp,
+            None => crate::utils::timestamp_now(),
+        };
+        let deadline = present + horizon;
+        let frozen_utxos = self
+            .database
+            .borrow()
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        // The events are sorted, so the first event of each UTXO is its earliest one
+        let mut seen = HashSet::new();
+        let refreshed = self
+            .get_inheritance_schedule()?
+            .events
+            .into_iter()
+            .filter(|event| seen.insert(event.outpoint))
+            .filter(|event| {
+                event.estimated_timestamp <= deadline && !frozen_utxos.contains(&event.outpoint)
+            })
+            .collect::<Vec<_>>();
+        log::debug!("HeritageWallet::plan_renewal - refreshed={refreshed:?}");
+        if refreshed.is_empty() {
+            log::info!("HeritageWallet::plan_renewal - Nothing to refresh");
+            return Ok(None);
+        }
+
+        let drain_addr = self.internal_get_new_address(KeychainKind::Internal)?;
+        let options = CreatePsbtOptions {
+            utxo_selection: UtxoSelection::UseOnly(
+                refreshed.iter().map(|event| event.outpoint).collect(),
+            ),
+            ..options
+        };
+        let (psbt, tx_summary) =
+            self.create_owner_psbt(SpendingConfig::DrainTo(drain_addr.address), options)?;
+        Ok(Some(RenewalPlan {
+            refreshed,
+            psbt,
+            tx_summary,
+        }))
     }
 
-    pub fn create_heir_psbt(
+    /// Build a self-transfer [Psbt] moving every key-path-only change UTXO (see
+    /// [HeritageWallet::update_heritage_config_with_keypath_change]) to a new external address
+    /// of the current subwallet, putting them back under the protection of the heirs.
+    ///
+    /// The `options` are used to create the [Psbt], except for [CreatePsbtOptions::utxo_selection]
+    /// that is replaced by the UTXOs to refresh. Frozen UTXOs are never refreshed.
+    ///
+    /// Returns [None] if there is nothing to refresh.
+    pub fn create_keypath_refresh_psbt(
         &self,
-        heir_config: HeirConfig,
-        spending_config: SpendingConfig,
         options: CreatePsbtOptions,
-    ) -> Result<(Psbt, TransactionSummary)> {
-        log::debug!(
-            "HeritageWallet::create_heir_psbt - heir_config={heir_config:?} \
-        spending_config={spending_config:?} options={options:?}"
-        );
-        self.create_psbt(Spender::Heir(heir_config), spending_config, options)
+    ) -> Result<Option<(Psbt, TransactionSummary)>> {
+        log::debug!("HeritageWallet::create_keypath_refresh_psbt - options={options:?}");
+        let frozen_utxos = self
+            .database
+            .borrow()
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let refreshed = self
+            .database
+            .borrow()
+            .list_utxos()?
+            .into_iter()
+            .filter(|hu| hu.keypath_only && !frozen_utxos.contains(&hu.outpoint))
+            .map(|hu| hu.outpoint)
+            .collect::<HashSet<_>>();
+        log::debug!("HeritageWallet::create_keypath_refresh_psbt - refreshed={refreshed:?}");
+        if refreshed.is_empty() {
+            log::info!("HeritageWallet::create_keypath_refresh_psbt - Nothing to refresh");
+            return Ok(None);
+        }
+
+        let drain_addr = self.internal_get_new_address(KeychainKind::External)?;
+        let options = CreatePsbtOptions {
+            utxo_selection: UtxoSelection::UseOnly(refreshed),
+            ..options
+        };
+        self.create_owner_psbt(SpendingConfig::DrainTo(drain_addr.address), options)
+            .map(Some)
     }
 
-    fn create_psbt(
+    /// Build a self-transfer [Psbt] sweeping UTXOs of the obsolete subwallets into the current
+    /// subwallet, so that the wallet ends up with at most `target_utxo_count` UTXOs.
+    ///
+    /// The smallest UTXOs are swept first, the oldest first in case of equality. Unconfirmed and
+    /// frozen UTXOs are never swept. The consolidation is meant to be done when fees are low: it is
+    /// refused if the fee rate is above `max_fee_rate` or if the fee would exceed `max_fee_percent`
+    /// of the consolidated value.
+    ///
+    /// The `options` are used to create the [Psbt], except for [CreatePsbtOptions::utxo_selection]
+    /// that is replaced by the UTXOs to sweep.
+    ///
+    /// Returns [None] if there is nothing to consolidate.
+    ///
+    /// # Errors
+    /// Returns [Error::ConsolidationFeeRateTooHigh] or [Error::ConsolidationFeeTooHigh] if fees
+    /// are too high for the consolidation to be worth it.
+    pub fn create_consolidation_psbt(
         &self,
-        spender: Spender,
-        spending_config: SpendingConfig,
+        max_fee_rate: FeeRate,
+        target_utxo_count: usize,
+        max_fee_percent: u8,
         options: CreatePsbtOptions,
-    ) -> Result<(Psbt, TransactionSummary)> {
+    ) -> Result<Option<(Psbt, TransactionSummary)>> {
         log::debug!(
-            "HeritageWallet::create_psbt - spender={spender:?} \
-            spending_config={spending_config:?} options={options:?}"
+            "HeritageWallet::create_consolidation_psbt - max_fee_rate={max_fee_rate:?} \
+            target_utxo_count={target_utxo_count} max_fee_percent={max_fee_percent} \
+            options={options:?}"
         );
-        // When the owner is spending, we want to drain all the obsolete wallets.
-        // The current wallet is used only if draining old wallets is not enough to fullfil the requested paiement
-        // In the likely case where draining old wallets is enough to fullfil the requested paiement, the remainder is send as change in the current wallet
+        let fee_rate = match &options.fee_policy {
+            Some(FeePolicy::FeeRate(fee_rate)) => Some(*fee_rate),
+            Some(FeePolicy::Absolute(_)) => None,
+            None => Some(
+                self.database
+                    .borrow()
+                    .get_fee_rate()?
+                    .ok_or(Error::MissingFeeRate)?,
+            ),
+        };
+        if let Some(fee_rate) = fee_rate {
+            if fee_rate > max_fee_rate {
+                log::error!(
+                    "HeritageWallet::create_consolidation_psbt - fee_rate={fee_rate:?} is too high"
+                );
+                return Err(Error::ConsolidationFeeRateTooHigh {
+                    fee_rate,
+                    max_fee_rate,
+                });
+            }
+        }
 
-        // When an heir is spending, we want to take eligible UTXO from the old wallets, taking the closest to loss of exclusivity first
-        // We cannot overshot, as we would not now what to do with the change (assuming that, if the heir is spending, to owner is likely dead or otherwise impaired)
-        // For now, the wisest course of action is probably to only allow Heir to drain wallets, meaning they receive every eligible UTXO
+        let current_heritage_config = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .ok_or(Error::MissingCurrentSubwalletConfig)?
+            .heritage_config()
+            .clone();
+        let frozen_utxos = self
+            .database
+            .borrow()
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let utxos = self.database.borrow().list_utxos()?;
+        // The consolidation itself creates one UTXO
+        let sweep_count = (utxos.len() + 1).saturating_sub(target_utxo_count.max(1));
+        let mut candidates = utxos
+            .into_iter()
+            .filter(|hu| {
+                hu.heritage_config != current_heritage_config
+                    && hu.confirmation_time.is_some()
+                    && !frozen_utxos.contains(&hu.outpoint)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|hu| {
+            (
+                hu.amount,
+                hu.confirmation_time.as_ref().map(|bt| bt.height),
+                hu.outpoint,
+            )
+        });
+        candidates.truncate(sweep_count);
+        log::debug!("HeritageWallet::create_consolidation_psbt - candidates={candidates:?}");
+        if candidates.is_empty() {
+            log::info!("HeritageWallet::create_consolidation_psbt - Nothing to consolidate");
+            return Ok(None);
+        }
 
-        // Walk over every spender-eligible UTXO in all subwallets, ordered by loss of exclusivity
-        // Eligible UTXO = UTXO that the spender can use given the current block_height (owner is typically always eligible, heirs must wait certain dates)
-        // Loss of exlusivity = block_height at which another new spender will be able to spend, for the owner it is typically the first Heir, for an Heir the next-in-line Heir
+        let drain_addr = self.internal_get_new_address(KeychainKind::Internal)?;
+        let options = CreatePsbtOptions {
+            utxo_selection: UtxoSelection::UseOnly(
+                candidates.iter().map(|hu| hu.outpoint).collect(),
+            ),
+            ..options
+        };
+        let (psbt, tx_summary) =
+            self.create_owner_psbt(SpendingConfig::DrainTo(drain_addr.address), options)?;
+
+        let consolidated_amount = candidates.iter().map(|hu| hu.amount).sum::<Amount>();
+        let max_fee = Amount::from_sat(consolidated_amount.to_sat() * max_fee_percent as u64 / 100);
+        if tx_summary.fee > max_fee {
+            log::error!(
+                "HeritageWallet::create_consolidation_psbt - fee={} is too high",
+                tx_summary.fee
+            );
+            return Err(Error::ConsolidationFeeTooHigh {
+                fee: tx_summary.fee,
+                max_fee,
+            });
+        }
+        Ok(Some((psbt, tx_summary)))
+    }
 
-        // As soon as enough UTXO has been gathered to satisfy the spending_config, stop walking over the subwallets
-        // Each subwallet will yield a PSBT, we just have to merge them to produce the expected PSBT of this function
+    /// Tell if the wallet should use a new [HeritageConfig] and/or renew its UTXOs
+    /// according to the given [RotationPolicy], see [HeritageWallet::check_rotation_due_at]
+    pub fn check_rotation_due(&self, policy: &RotationPolicy) -> Result<RotationStatus> {
+        self.check_rotation_due_at(policy, crate::utils::timestamp_now())
+    }
 
-        let heir_spending = if let Spender::Heir(_) = spender {
-            true
-        } else {
-            false
-        };
+    /// Tell if, at the given `timestamp`, the wallet should use a new [HeritageConfig]
+    /// and/or renew its UTXOs according to the given [RotationPolicy]:
+    /// - a new [HeritageConfig] is due if there is no current one, if the current subwallet is older
+    /// than [RotationPolicy::max_config_age_days] or if an heir of the current [HeritageConfig] will
+    /// reach its absolute lock within the renewal window, as renewing the UTXOs cannot push it back
+    /// - a renewal is due if at least [RotationPolicy::renewal_threshold_percent] of the funds
+    /// will become spendable by an heir within the renewal window
+    pub fn check_rotation_due_at(
+        &self,
+        policy: &RotationPolicy,
+        timestamp: u64,
+    ) -> Result<RotationStatus> {
+        log::debug!(
+            "HeritageWallet::check_rotation_due_at - policy={policy:?} timestamp={timestamp}"
+        );
+        const SEC_IN_A_DAY: u64 = 24 * 60 * 60;
+        let deadline = timestamp + policy.renewal_window_days as u64 * SEC_IN_A_DAY;
 
-        let default_sequence = if options.disable_rbf {
-            Sequence::ENABLE_LOCKTIME_NO_RBF
-        } else {
-            Sequence::ENABLE_RBF_NO_LOCKTIME
+        let heritage_config_update_due = match self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+        {
+            Some(current_subwallet_config) => {
+                let too_old = policy.max_config_age_days.is_some_and(|max_age| {
+                    current_subwallet_config
+                        .subwallet_firstuse_time()
+                        .is_some_and(|firstuse_ts| {
+                            firstuse_ts + max_age as u64 * SEC_IN_A_DAY <= timestamp
+                        })
+                });
+                let heritage_config = current_subwallet_config.heritage_config();
+                let absolute_lock_close = heritage_config.iter_heir_configs().any(|heir_config| {
+                    heritage_config
+                        .get_heritage_explorer(heir_config)
+                        .and_then(|he| he.get_spend_conditions().get_spendable_timestamp())
+                        .is_some_and(|ts| ts <= deadline)
+                });
+                too_old || absolute_lock_close
+            }
+            None => true,
         };
 
-        // For now, we only accept SpendingConfig::DrainTo if it is an Heir spender
-        if heir_spending {
-            let SpendingConfig::DrainTo(_) = spending_config else {
-                log::error!("An Heir can only use SpendingConfig::DrainTo(...)");
-                return Err(Error::InvalidSpendingConfigForHeir);
-            };
+        let utxos = self.database.borrow().list_utxos()?;
+        let total_amount = utxos.iter().map(|hu| hu.amount).sum::<Amount>();
+        let maturing_amount = utxos
+            .iter()
+            .filter(|hu| {
+                hu.heritage_config.iter_heir_configs().any(|heir_config| {
+                    hu.estimate_heir_spending_timestamp(heir_config)
+                        .is_some_and(|ts| ts <= deadline)
+                })
+            })
+            .map(|hu| hu.amount)
+            .sum::<Amount>();
+        let renewal_due = maturing_amount > Amount::ZERO
+            && maturing_amount.to_sat() * 100
+                >= total_amount.to_sat() * policy.renewal_threshold_percent as u64;
+
+        let res = RotationStatus {
+            heritage_config_update_due,
+            renewal_due,
         };
+        log::debug!("HeritageWallet::check_rotation_due_at - res={res:?}");
+        Ok(res)
+    }
 
-        // We do this now so if it fails we don't bother to go further
+    pub fn get_new_address(&self) -> Result<Address> {
+        log::info!("HeritageWallet::get_new_address - Called for a new Bitcoin address");
+        let address = self
+            .internal_get_new_address(KeychainKind::External)?
+            .address;
+        log::info!("HeritageWallet::get_new_address - address={address}");
+        Ok(address)
+    }
+
+    /// Generate `count` new receiving addresses at once, e.g. for a merchant to pre-assign them.
+    ///
+    /// Unlike [HeritageWallet::get_new_address], this refuses to leave more than
+    /// [GapLimit] consecutive unused addresses in the current subwallet, so that a wallet
+    /// restored from the descriptors will find every payment.
+    ///
+    /// # Errors
+    /// Returns [Error::GapLimitExceeded] if the addresses cannot be generated without
+    /// exceeding the [GapLimit].
+    pub fn get_new_addresses(&self, count: usize) -> Result<Vec<Address>> {
+        log::debug!("HeritageWallet::get_new_addresses - count={count}");
         let current_subwallet_config = self
             .database
             .borrow()
             .get_subwallet_config(SubwalletConfigId::Current)?
             .ok_or(Error::MissingCurrentSubwalletConfig)?;
-        log::debug!(
-            "HeritageWallet::create_psbt - current_subwallet_config={current_subwallet_config:?}"
-        );
-        let current_subwallet = self.get_subwallet(&current_subwallet_config)?;
+        let gap_limit = u32::from(self.get_gap_limit()?);
+        let unused =
+            Self::count_unused_external_addresses(&self.get_subwallet(&current_subwallet_config)?)?;
+        if unused as usize + count > gap_limit as usize {
+            return Err(Error::GapLimitExceeded {
+                requested: count,
+                unused,
+                gap_limit,
+            });
+        }
+        let addresses = (0..count)
+            .map(|_| {
+                Ok(self
+                    .internal_get_new_address(KeychainKind::External)?
+                    .address)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        log::info!("HeritageWallet::get_new_addresses - addresses={addresses:?}");
+        Ok(addresses)
+    }
 
-        // Logging the UTXO selection strategy
-        match &options.utxo_selection {
-            UtxoSelection::IncludePrevious => {
-                log::info!("HeritageWallet::create_psbt - Using the default UTXO selection");
-            }
-            UtxoSelection::Include(vec) => {
-                log::info!(
-                    "HeritageWallet::create_psbt - Using the default UTXO selection \
-                and ensuring the inclusion of the given UTXOs"
-                );
-                log::debug!("HeritageWallet::create_psbt - Include={vec:?}");
-            }
-            UtxoSelection::Exclude(vec) => {
-                log::info!(
-                    "HeritageWallet::create_psbt - Using the default UTXO selection \
-                but ensuring the exclusion of the given UTXOs"
-                );
-                log::debug!("HeritageWallet::create_psbt - Exclude={vec:?}");
-            }
-            UtxoSelection::IncludeExclude { include, exclude } => {
-                log::info!(
-                    "HeritageWallet::create_psbt - Using the default UTXO selection \
-                but ensuring the inclusion and exclusion of the given UTXOs"
-                );
-                log::debug!("HeritageWallet::create_psbt - Include={include:?}");
-                log::debug!("HeritageWallet::create_psbt - Exclude={exclude:?}");
-                let anomalies = include
-                    .iter()
-                    .filter(|&e| exclude.contains(e))
-                    .cloned()
-                    .collect::<Vec<_>>();
-                if anomalies.len() > 0 {
-                    return Err(Error::InvalidUtxoSelectionIncludeExclude(anomalies));
+    /// Count the receiving addresses generated after the last one that received funds
+    fn count_unused_external_addresses(
+        subwallet: &Wallet<<D as PartitionableDatabase>::SubDatabase>,
+    ) -> Result<u32> {
+        let Some(last_index) = subwallet
+            .database()
+            .get_last_index(KeychainKind::External)
+            .map_err(|e| DatabaseError::Generic(e.to_string()))?
+        else {
+            return Ok(0);
+        };
+        let mut last_used_index = None;
+        for tx in subwallet
+            .list_transactions(true)
+            .map_err(|e| DatabaseError::Generic(e.to_string()))?
+            .into_iter()
+            .filter_map(|td| td.transaction)
+        {
+            for txout in tx.output {
+                if let Some((KeychainKind::External, index)) = subwallet
+                    .database()
+                    .get_path_from_script_pubkey(&txout.script_pubkey)
+                    .map_err(|e| DatabaseError::Generic(e.to_string()))?
+                {
+                    last_used_index = last_used_index.max(Some(index));
                 }
             }
-            UtxoSelection::UseOnly(vec) => {
-                log::info!("HeritageWallet::create_psbt - Using only the given UTXOs");
-                log::debug!("HeritageWallet::create_psbt - UseOnly={vec:?}");
-            }
         }
+        Ok(match last_used_index {
+            Some(last_used_index) => last_index.saturating_sub(last_used_index),
+            None => last_index + 1,
+        })
+    }
 
-        // Gather all the UTXO of the obsolete wallet configs
-        log::debug!("HeritageWallet::create_psbt - Listing obsolete subwallet_configs");
-        let obsolete_subwallet_configs =
-            self.database.borrow().list_obsolete_subwallet_configs()?;
+    pub fn get_gap_limit(&self) -> Result<GapLimit> {
+        Ok(self.database.borrow().get_gap_limit()?.unwrap_or_default())
+    }
 
-        // Here we compute what will be the "present" for this PSBT creation
-        // If we got it as a paramter, just use it
-        // Else we create a fake BlockTime with the last synchronization height and the current timestamp
-        let block_time = match options.assume_blocktime {
-            Some(block_time) => block_time,
-            None => {
-                let mut bt = self.get_sync_time()?.ok_or(Error::UnsyncedWallet)?;
-                bt.timestamp = crate::utils::timestamp_now();
-                bt
-            }
+    pub fn set_gap_limit(&self, new_gap_limit: GapLimit) -> Result<()> {
+        log::debug!("HeritageWallet::set_gap_limit - new_gap_limit={new_gap_limit}");
+        Ok(self.database.borrow_mut().set_gap_limit(new_gap_limit)?)
+    }
+
+    pub fn get_block_inclusion_objective(&self) -> Result<BlockInclusionObjective> {
+        Ok(self
+            .database
+            .borrow()
+            .get_block_inclusion_objective()?
+            .unwrap_or_default())
+    }
+
+    pub fn set_block_inclusion_objective(&self, new_bio: BlockInclusionObjective) -> Result<()> {
+        self.database
+            .borrow_mut()
+            .set_block_inclusion_objective(new_bio)
+            .map_err(|e| DatabaseError::Generic(e.to_string()).into())
+    }
+
+    /// Set the label of the UTXO designated by `outpoint`, or remove it if `label` is [None]
+    pub fn set_utxo_label(&self, outpoint: &OutPoint, label: Option<&str>) -> Result<()> {
+        log::debug!("HeritageWallet::set_utxo_label - outpoint={outpoint} label={label:?}");
+        let mut db = self.database.borrow_mut();
+        match label {
+            Some(label) => db.set_utxo_label(outpoint, label)?,
+            None => db.delete_utxo_label(outpoint)?,
         };
+        Ok(())
+    }
 
-        log::debug!("HeritageWallet::create_psbt - Creating foreing_utxos list");
-        // We want to build 3 different informations
-        // - We want the "global" Locktime to apply the transaction, essentially the maximum locktime out of all the inputs
-        // - We want to keep track of all the Sequence for all the OutPoint of the inputs
-        // - We want to construct the foreign UTXOs vector
-        let (mut final_lock, mut seq_index, foreign_utxos) = obsolete_subwallet_configs
+    pub fn get_utxo_label(&self, outpoint: &OutPoint) -> Result<Option<String>> {
+        log::debug!("HeritageWallet::get_utxo_label - outpoint={outpoint}");
+        Ok(self.database.borrow().get_utxo_label(outpoint)?)
+    }
+
+    pub fn list_utxo_labels(&self) -> Result<Vec<(OutPoint, String)>> {
+        log::debug!("HeritageWallet::list_utxo_labels");
+        Ok(self.database.borrow().list_utxo_labels()?)
+    }
+
+    /// Set the label of the transaction or address designated by `label_ref`,
+    /// or remove it if `label` is [None]
+    pub fn set_label(&self, label_ref: &LabelRef, label: Option<&str>) -> Result<()> {
+        log::debug!("HeritageWallet::set_label - label_ref={label_ref} label={label:?}");
+        let mut db = self.database.borrow_mut();
+        match label {
+            Some(label) => db.set_label(label_ref, label)?,
+            None => db.delete_label(label_ref)?,
+        };
+        Ok(())
+    }
+
+    pub fn get_label(&self, label_ref: &LabelRef) -> Result<Option<String>> {
+        log::debug!("HeritageWallet::get_label - label_ref={label_ref}");
+        Ok(self.database.borrow().get_label(label_ref)?)
+    }
+
+    pub fn list_labels(&self) -> Result<Vec<(LabelRef, String)>> {
+        log::debug!("HeritageWallet::list_labels");
+        Ok(self.database.borrow().list_labels()?)
+    }
+
+    /// Freeze the UTXO designated by `outpoint`. A frozen UTXO is never used
+    /// by [HeritageWallet::create_owner_psbt] nor [HeritageWallet::create_heir_psbt]
+    pub fn freeze_utxo(&self, outpoint: &OutPoint) -> Result<()> {
+        log::info!("HeritageWallet::freeze_utxo - outpoint={outpoint}");
+        Ok(self.database.borrow_mut().freeze_utxo(outpoint)?)
+    }
+
+    pub fn unfreeze_utxo(&self, outpoint: &OutPoint) -> Result<()> {
+        log::info!("HeritageWallet::unfreeze_utxo - outpoint={outpoint}");
+        Ok(self.database.borrow_mut().unfreeze_utxo(outpoint)?)
+    }
+
+    pub fn list_frozen_utxos(&self) -> Result<Vec<OutPoint>> {
+        log::debug!("HeritageWallet::list_frozen_utxos");
+        Ok(self.database.borrow().list_frozen_utxos()?)
+    }
+
+    /// Export the transaction, address and UTXO labels of the wallet as [BIP-329](bip329) labels.
+    ///
+    /// Labels of addresses belonging to the wallet, including the addresses of obsolete
+    /// subwallets, carry the origin of their subwallet account (e.g. `tr([73c5da0a/86'/0'/0'])`).
+    /// Frozen UTXOs are exported as `output` labels with `"spendable": false`, even without a label.
+    pub fn export_bip329_labels(&self) -> Result<Bip329Labels> {
+        log::debug!("HeritageWallet::export_bip329_labels");
+        let address_origins = self
+            .list_wallet_addresses()?
             .into_iter()
-            .filter_map(|subwallet_config| {
-                self.get_conditions_and_utxos_for_subwallet(
-                    &subwallet_config,
-                    &spender,
-                    &block_time,
-                    true,
+            .map(|wallet_address| {
+                let (fingerprint, derivation_path) = wallet_address.origin;
+                let children: &[ChildNumber] = derivation_path.as_ref();
+                // Strip the change and index levels to get the account derivation path
+                let account_derivation_path =
+                    DerivationPath::from(&children[..children.len().saturating_sub(2)]);
+                (
+                    wallet_address.address,
+                    format!("tr([{fingerprint}/{account_derivation_path}])"),
                 )
-                .unwrap_or_else(|e| {
-                    log::error!(
-                        "HeritageWallet::create_psbt - Failed to call \
-                        get_conditions_and_utxos_for_subwallet for \
-                        subwallet_config {subwallet_config:?}: {e:#}"
-                    );
-                    None
-                })
-                // Remove all the UTXO that must be excluded per the UTXO Selection strategy
-                .map(|(o_locktime, o_sequence, mut utxos)| {
-                    match &options.utxo_selection {
-                        UtxoSelection::IncludePrevious | UtxoSelection::Include(_) => (),
-                        UtxoSelection::Exclude(exclude)
-                        | UtxoSelection::IncludeExclude { exclude, .. } => {
-                            utxos.retain(|(o, _)| !exclude.contains(&o.outpoint))
-                        }
-                        UtxoSelection::UseOnly(include_exclusive) => {
-                            utxos.retain(|(o, _)| include_exclusive.contains(&o.outpoint))
-                        }
-                    };
-                    (o_locktime, o_sequence, utxos)
-                })
             })
-            // It is possible that the previous step is yelding an empty Vec of UTXO, in that case remove it.
-            .filter(|(_, _, utxos)| utxos.len() > 0)
-            // Now we can fold and compute our final result
-            .fold(
-                (
-                    LockTime::from_consensus(bdk::bitcoin::absolute::LOCK_TIME_THRESHOLD),
-                    HashMap::new(),
-                    Vec::new(),
-                ),
-                |(mut final_lock, mut seq_index, mut foreign_utxos),
-                 (o_locktime, o_sequence, utxos)| {
-                    // Take the more restrictive locktime
-                    if o_locktime
-                        .is_some_and(|candidate_lock| final_lock.is_implied_by(candidate_lock))
-                    {
-                        final_lock = o_locktime.unwrap();
-                    }
-                    // Process the utxos
-                    for (utxo, o_foreign_utxo) in utxos {
-                        let outpoint = utxo.outpoint;
-                        seq_index.insert(outpoint, o_sequence.unwrap_or(default_sequence));
-                        let foreign_utxo =
-                            o_foreign_utxo.expect("We ask to include the foreign_utxos");
-                        foreign_utxos.push(foreign_utxo);
-                    }
-                    (final_lock, seq_index, foreign_utxos)
-                },
-            );
+            .collect::<HashMap<_, _>>();
 
-        // Create the TX builder
-        log::debug!("HeritageWallet::create_psbt - wallet.build_tx()");
-        let mut tx_builder = current_subwallet.build_tx();
+        let mut labels = self
+            .list_labels()?
+            .into_iter()
+            .map(|(label_ref, label)| {
+                let origin = match &label_ref {
+                    LabelRef::Address(address) => address_origins.get(&**address).cloned(),
+                    LabelRef::Tx(_) => None,
+                };
+                Bip329Label {
+                    origin,
+                    ..Bip329Label::from((label_ref, label))
+                }
+            })
+            .collect::<Vec<_>>();
 
-        // Assume block height
-        log::debug!(
-            "HeritageWallet::create_psbt - tx_builder.current_height({})",
-            block_time.height
-        );
-        tx_builder.current_height(block_time.height);
+        let frozen_utxos = self
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let mut outputs = self
+            .list_utxo_labels()?
+            .into_iter()
+            .map(|(outpoint, label)| (outpoint, Some(label)))
+            .collect::<BTreeMap<_, _>>();
+        for outpoint in frozen_utxos.iter() {
+            outputs.entry(*outpoint).or_insert(None);
+        }
+        labels.extend(outputs.into_iter().map(|(outpoint, label)| Bip329Label {
+            label_ref: Bip329Ref::Output(outpoint),
+            label,
+            origin: None,
+            spendable: Some(!frozen_utxos.contains(&outpoint)),
+        }));
 
-        let drain_script = match &spending_config {
-            SpendingConfig::DrainTo(addr) => {
-                log::debug!(
-                    "HeritageWallet::create_psbt - tx_builder.drain_wallet().drain_to({addr:?})"
-                );
-                tx_builder.drain_wallet().drain_to(addr.script_pubkey());
-                addr.script_pubkey()
-            }
-            SpendingConfig::Recipients(recipients) => {
-                log::debug!(
-                    "HeritageWallet::create_psbt - tx_builder.set_recipients({recipients:?})"
-                );
-                // Convert the recipients address to scripts
-                let recipients = recipients
+        Ok(Bip329Labels(labels))
+    }
+
+    /// Export the [TransactionSummary] of the wallet, from the most recent to the oldest,
+    /// in the given [ExportFormat]. See [TransactionExport] for the exported fields.
+    pub fn export_transaction_summaries(&self, format: ExportFormat) -> Result<String> {
+        log::debug!("HeritageWallet::export_transaction_summaries - format={format}");
+        let subwallets = self.get_all_subwallets()?;
+        let records = self
+            .list_transaction_summaries()?
+            .into_iter()
+            .map(|tx_summary| {
+                let mut subwallet_ids = BTreeSet::new();
+                for owned_io in tx_summary
+                    .owned_inputs
                     .iter()
-                    .map(|Recipient(addr, amount)| (addr.script_pubkey(), amount.to_sat()))
-                    .collect::<Vec<_>>();
-                tx_builder.set_recipients(recipients);
-                let drain_addr = self.internal_get_new_address(KeychainKind::Internal)?;
-                tx_builder.drain_to(drain_addr.script_pubkey());
-                drain_addr.script_pubkey()
-            }
-        };
+                    .chain(tx_summary.owned_outputs.iter())
+                {
+                    if let Some((swc, _)) =
+                        Self::find_script_owner(&subwallets, &owned_io.address.script_pubkey())?
+                    {
+                        subwallet_ids.insert(swc.subwallet_id());
+                    }
+                }
+                Ok(TransactionExport::new(
+                    tx_summary,
+                    subwallet_ids.into_iter().collect(),
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(export::export(&records, format))
+    }
 
-        // Keep a set of the OutPoint corresponding to already minimized PsbtInputs to filter them out of the final minimization
-        let already_minimized_psbt_input_by_outpoint = foreign_utxos
-            .iter()
-            .map(|(op, _, _)| *op)
+    /// Export the UTXOs of the wallet in the given [ExportFormat], along with their labels
+    /// and frozen state. See [UtxoExport] for the exported fields.
+    pub fn export_utxos(&self, format: ExportFormat) -> Result<String> {
+        log::debug!("HeritageWallet::export_utxos - format={format}");
+        let subwallets = self.get_all_subwallets()?;
+        let frozen_utxos = self
+            .list_frozen_utxos()?
+            .into_iter()
             .collect::<HashSet<_>>();
+        let mut utxo_labels = self
+            .list_utxo_labels()?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let mut utxos = self.database.borrow().list_utxos()?;
+        utxos.sort_by_key(|hu| hu.outpoint);
+        let records = utxos
+            .into_iter()
+            .map(|hu| {
+                let subwallet_id =
+                    Self::find_script_owner(&subwallets, &hu.address.script_pubkey())?
+                        .map(|(swc, _)| swc.subwallet_id());
+                let frozen = frozen_utxos.contains(&hu.outpoint);
+                let label = utxo_labels.remove(&hu.outpoint);
+                Ok(UtxoExport::new(hu, subwallet_id, frozen, label))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(export::export(&records, format))
+    }
 
-        // Include all the foreign_utxos
-        log::debug!("HeritageWallet::create_psbt - tx_builder.add_foreign_utxo - foreing_utxos={foreign_utxos:?}");
-        for (outpoint, psbt_input, satisfaction_weight) in foreign_utxos {
-            tx_builder
-                .add_foreign_utxo(outpoint, psbt_input, satisfaction_weight)
-                .expect("Parameters are under our control and correct");
+    /// Import [BIP-329](bip329) labels, overriding the existing labels of the same items.
+    ///
+    /// `tx` and `addr` labels become transaction and address labels, `output` labels become
+    /// UTXO labels and their `spendable` attribute freezes or unfreezes the UTXO.
+    /// Other label types, and addresses that are not valid for the current network, are skipped.
+    ///
+    /// Returns the number of labels that were imported.
+    pub fn import_bip329_labels(&self, labels: &Bip329Labels) -> Result<usize> {
+        log::debug!(
+            "HeritageWallet::import_bip329_labels - labels.len()={}",
+            labels.0.len()
+        );
+        let mut imported = 0;
+        for label in labels.0.iter() {
+            match (&label.label_ref, label.as_label_ref(self.network)) {
+                (_, Some(label_ref)) => {
+                    if let Some(label) = &label.label {
+                        self.set_label(&label_ref, Some(label))?;
+                        imported += 1;
+                    }
+                }
+                (Bip329Ref::Output(outpoint), None) => {
+                    if let Some(label) = &label.label {
+                        self.set_utxo_label(outpoint, Some(label))?;
+                    }
+                    match label.spendable {
+                        Some(true) => self.unfreeze_utxo(outpoint)?,
+                        Some(false) => self.freeze_utxo(outpoint)?,
+                        None => (),
+                    }
+                    if label.label.is_some() || label.spendable.is_some() {
+                        imported += 1;
+                    }
+                }
+                (label_ref, None) => {
+                    log::warn!(
+                        "HeritageWallet::import_bip329_labels - Skipping unsupported label {label_ref:?}"
+                    );
+                }
+            }
         }
+        log::info!("HeritageWallet::import_bip329_labels - imported={imported}");
+        Ok(imported)
+    }
 
-        // Process the utxo_selection option
-        match options.utxo_selection {
-            UtxoSelection::IncludePrevious => (),
-            UtxoSelection::Include(include) => {
-                tx_builder.add_utxos(&include).map_err(|e| match e {
-                    bdk::Error::UnknownUtxo => Error::UnknownUtxoSelectionInclude(include),
-                    _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
-                })?;
-            }
-            UtxoSelection::Exclude(exclude) => {
-                tx_builder.unspendable(exclude.into_iter().collect());
-            }
-            UtxoSelection::IncludeExclude { include, exclude } => {
-                tx_builder.add_utxos(&include).map_err(|e| match e {
-                    bdk::Error::UnknownUtxo => Error::UnknownUtxoSelectionInclude(include),
-                    _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
-                })?;
-                tx_builder.unspendable(exclude.into_iter().collect());
+    pub fn create_owner_psbt(
+        &self,
+        spending_config: SpendingConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<(Psbt, TransactionSummary)> {
+        log::debug!(
+            "HeritageWallet::create_owner_psbt - spending_config={spending_config:?} \
+            options={options:?}"
+        );
+        let selection_fallbacks = match (&spending_config, &options.utxo_selection) {
+            (
+                SpendingConfig::Recipients { recipients, .. },
+                UtxoSelection::IncludePrevious | UtxoSelection::Exclude(_),
+            ) if !recipients.iter().any(Recipient::is_max) => {
+                self.coin_selection_fallbacks(options.coin_selection, &options.utxo_selection)?
             }
-            UtxoSelection::UseOnly(include) => {
-                let include = include.into_iter().collect::<Vec<_>>();
-                tx_builder.add_utxos(&include).map_err(|e| match e {
-                    bdk::Error::UnknownUtxo => Error::UnknownUtxoSelectionInclude(include),
-                    _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
-                })?;
-                tx_builder.manually_selected_only();
+            _ => vec![],
+        };
+        for utxo_selection in selection_fallbacks {
+            log::debug!(
+                "HeritageWallet::create_owner_psbt - Trying utxo_selection={utxo_selection:?}"
+            );
+            match self.create_psbt(
+                Spender::Owner,
+                spending_config.clone(),
+                CreatePsbtOptions {
+                    utxo_selection,
+                    ..options.clone()
+                },
+            ) {
+                Ok(result) => return Ok(result),
+                Err(e) => log::info!(
+                    "HeritageWallet::create_owner_psbt - Coin selection policy {:?} \
+                    could not be satisfied, trying the next option: {e}",
+                    options.coin_selection
+                ),
             }
+        }
+        self.create_psbt(Spender::Owner, spending_config, options)
+    }
+
+    /// Compute the maximum amount the owner can send with `spending_config` and `options`,
+    /// i.e. what the [Recipient::max] of a [SpendingConfig::Recipients], or the `drain_to`
+    /// address of the other [SpendingConfig]s, receives once the other recipients and the fee
+    /// are paid. The transaction is built to get the exact weight of its inputs and outputs,
+    /// but nothing is stored.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidSpendingConfig] if `spending_config` is a
+    /// [SpendingConfig::Recipients] without [Recipient::max], and the errors of
+    /// [HeritageWallet::create_owner_psbt], e.g. if the funds cannot pay the other recipients
+    pub fn estimate_max_spendable(
+        &self,
+        spending_config: SpendingConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<Amount> {
+        log::debug!(
+            "HeritageWallet::estimate_max_spendable - spending_config={spending_config:?} \
+            options={options:?}"
+        );
+        let max_script = match &spending_config {
+            SpendingConfig::DrainTo(drain_to)
+            | SpendingConfig::DrainToWithChange { drain_to, .. } => drain_to.script_pubkey(),
+            SpendingConfig::Recipients { recipients, .. } => recipients
+                .iter()
+                .find(|r| r.is_max())
+                .map(|Recipient(addr, _)| addr.script_pubkey())
+                .ok_or(Error::InvalidSpendingConfig(
+                    "no recipient receives the maximum amount",
+                ))?,
         };
+        let (psbt, _) = self.create_owner_psbt(spending_config, options)?;
+        let max_spendable = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|o| o.script_pubkey == max_script)
+            .map(|o| o.value)
+            .max()
+            // The drain output is removed if the fee leaves only dust
+            .unwrap_or(0);
+        log::info!("HeritageWallet::estimate_max_spendable - max_spendable={max_spendable} sat");
+        Ok(Amount::from_sat(max_spendable))
+    }
 
-        // Set FeeRate
-        let fee_rate = match options.fee_policy {
-            Some(fee_policy) => match fee_policy {
-                FeePolicy::Absolute(amount) => {
-                    let amount = amount.to_sat();
-                    log::debug!(
-                        "HeritageWallet::create_psbt - tx_builder.fee_absolute - amount={amount}"
-                    );
-                    tx_builder.fee_absolute(amount);
-                    None
-                },
-                FeePolicy::FeeRate(fee_rate) => Some(fee_rate),
-            },
-            None => {
-                Some(self.database.borrow().get_fee_rate()?.unwrap_or_else(||{
-                    log::warn!("HeritageWallet::create_psbt - No FeeRate in the database. Maybe call sync_fee_rate");
-                    FeeRate::BROADCAST_MIN
-                }))
+    /// Compute the [FeePreview] of the owner transaction for `spending_config` at `fee_rate`,
+    /// i.e. its exact expected size, its fee and the inputs selected in each subwallet.
+    /// The transaction is built as [HeritageWallet::create_owner_psbt] would with `options`,
+    /// except for [CreatePsbtOptions::fee_policy] that is replaced by `fee_rate`.
+    ///
+    /// Unlike [HeritageWallet::create_owner_psbt], nothing is stored: no change address is
+    /// derived and the current subwallet is not marked as used, so it can be called as often
+    /// as needed, e.g. each time the user changes an amount.
+    ///
+    /// # Errors
+    /// Returns the errors of [HeritageWallet::create_owner_psbt], e.g. if the funds are insufficient
+    pub fn estimate_fee(
+        &self,
+        spending_config: SpendingConfig,
+        fee_rate: FeeRate,
+        options: CreatePsbtOptions,
+    ) -> Result<FeePreview> {
+        log::debug!(
+            "HeritageWallet::estimate_fee - spending_config={spending_config:?} \
+            fee_rate={fee_rate:?} options={options:?}"
+        );
+        let current_subwallet_config = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .ok_or(Error::MissingCurrentSubwalletConfig)?;
+        // Deriving a new change address would store it, instead the change goes to the first
+        // change address of the current subwallet, whose script has the same weight
+        let spending_config = match spending_config {
+            SpendingConfig::Recipients {
+                recipients,
+                change_to: None,
+            } if !recipients.iter().any(Recipient::is_max) => {
+                let change_to = self
+                    .get_subwallet(&current_subwallet_config)?
+                    .get_internal_address(AddressIndex::Peek(0))
+                    .map_err(|e| Error::Unknown(e.to_string()))?
+                    .address;
+                SpendingConfig::Recipients {
+                    recipients,
+                    change_to: Some(change_to),
+                }
             }
+            spending_config => spending_config,
         };
-        // Map the Option<bitcoin::FeeRate> to a Option<BdkFeerate>
-        let fee_rate = fee_rate.map(|fee_rate| {
-            let fee_rate = BdkFeeRate::from_sat_per_kwu(fee_rate.to_sat_per_kwu() as f32);
-            log::debug!(
-                "HeritageWallet::create_psbt - tx_builder.fee_rate - fee_rate={fee_rate:?}"
-            );
-            tx_builder.fee_rate(fee_rate);
-            fee_rate
-        });
+        let options = CreatePsbtOptions {
+            fee_policy: Some(FeePolicy::FeeRate(fee_rate)),
+            ..options
+        };
+        let (psbt, tx_summary) = self.create_owner_psbt(spending_config, options)?;
+
+        // The HeritageConfig identifies the subwallet owning an HeritageUtxo
+        let db = self.database.borrow();
+        let mut subwallet_configs = db.list_obsolete_subwallet_configs()?;
+        subwallet_configs.push(current_subwallet_config);
+        subwallet_configs.sort_by_key(|swc| swc.subwallet_id());
+        let inputs = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect::<HashSet<_>>();
+        let input_utxos = db
+            .list_utxos()?
+            .into_iter()
+            .filter(|hu| inputs.contains(&hu.outpoint))
+            .collect::<Vec<_>>();
+        let subwallet_inputs = subwallet_configs
+            .iter()
+            .map(|swc| {
+                (
+                    swc.subwallet_id(),
+                    input_utxos
+                        .iter()
+                        .filter(|hu| &hu.heritage_config == swc.heritage_config())
+                        .count(),
+                )
+            })
+            .filter(|(_, count)| *count > 0)
+            .collect();
 
-        // Extract the HeritageExplorer if we are with an Heir spending and if any
-        let heritage_explorer = if let Spender::Heir(heir_config) = &spender {
-            current_subwallet_config
-                .heritage_config()
-                .get_heritage_explorer(heir_config)
-        } else {
-            None
+        let fee_preview = FeePreview {
+            vsize: get_expected_tx_weight(&psbt).to_vbytes_ceil(),
+            fee: tx_summary.fee,
+            subwallet_inputs,
         };
+        log::debug!("HeritageWallet::estimate_fee - fee_preview={fee_preview:?}");
+        Ok(fee_preview)
+    }
 
-        // Policy for the PSBT
-        let policy_index = if let Some(he) = &heritage_explorer {
-            he.get_miniscript_index() + 1
-        } else {
-            0
+    /// Compute the [UtxoSelection] to try, in order, before the default behavior
+    /// in order to honor the given [CoinSelectionPolicy]
+    fn coin_selection_fallbacks(
+        &self,
+        coin_selection: CoinSelectionPolicy,
+        utxo_selection: &UtxoSelection,
+    ) -> Result<Vec<UtxoSelection>> {
+        if let CoinSelectionPolicy::ConsolidateObsolete | CoinSelectionPolicy::OldestFirst =
+            coin_selection
+        {
+            return Ok(vec![]);
+        }
+        let excluded = match utxo_selection {
+            UtxoSelection::Exclude(exclude) => exclude.clone(),
+            _ => HashSet::new(),
         };
+        let frozen_utxos = self
+            .database
+            .borrow()
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
 
-        log::debug!("HeritageWallet::create_psbt - policy_index={policy_index}");
-        let keychains = [KeychainKind::External, KeychainKind::Internal];
-        for kc in keychains {
-            if let Some(pol) = current_subwallet.policies(kc).map_err(|e| match e {
-                bdk::Error::InvalidPolicyPathError(e) => Error::FailToExtractPolicy(e),
-                _ => {
-                    log::error!(
-                        "Unknown error while extracting policies from the current SubwalletConfig: {e:#}"
-                    );
-                    panic!(
-                        "Unknown error while extracting policies from the current SubwalletConfig: {e:#}"
-                    )
-                }
-            })? {
-                let bt = BTreeMap::from([(pol.id, vec![policy_index])]);
-                log::debug!(
-                    "HeritageWallet::create_psbt - tx_builder.policy_path - bt={bt:?}, kc={kc:?}"
-                );
-                tx_builder.policy_path(bt, kc);
+        // The UTXOs of each obsolete subwallet, the most recent subwallet first
+        let mut obsolete_subwallet_configs =
+            self.database.borrow().list_obsolete_subwallet_configs()?;
+        obsolete_subwallet_configs.sort_by_key(|swc| core::cmp::Reverse(swc.subwallet_id()));
+        let mut obsolete_utxos = Vec::with_capacity(obsolete_subwallet_configs.len());
+        for subwallet_config in obsolete_subwallet_configs.iter() {
+            let utxos = self
+                .get_subwallet(subwallet_config)?
+                .list_unspent()
+                .map_err(|e| DatabaseError::Generic(e.to_string()))?
+                .into_iter()
+                .map(|utxo| utxo.outpoint)
+                .filter(|op| !excluded.contains(op) && !frozen_utxos.contains(op))
+                .collect::<HashSet<_>>();
+            if utxos.len() > 0 {
+                obsolete_utxos.push(utxos);
             }
         }
 
-        // If the owner is spending, we let the bdk::Wallet do its work
-        // but if an Heir is spending, we manually select the UTXOs we want in.
-        if heir_spending {
-            // Manual selection only
-            log::debug!("HeritageWallet::create_psbt - tx_builder.manually_selected_only()");
-            tx_builder.manually_selected_only();
-
-            if let Some((o_locktime, o_sequence, utxos)) = self
-                .get_conditions_and_utxos_for_subwallet(
-                    &current_subwallet_config,
-                    &spender,
-                    &block_time,
-                    false,
-                )?
-            {
-                // Take the more restrictive locktime
-                if o_locktime.is_some_and(|candidate_lock| final_lock.is_implied_by(candidate_lock))
-                {
-                    final_lock = o_locktime.unwrap();
-                }
-                // Process the utxos
-                for (utxo, _) in utxos {
-                    let outpoint = utxo.outpoint;
-                    seq_index.insert(outpoint, o_sequence.unwrap_or(default_sequence));
-                    tx_builder.add_utxo(outpoint).map_err(|e| match e {
-                        bdk::Error::UnknownUtxo => {
-                            log::error!("Unexpected UnknownUtxo error: {e:#}");
-                            panic!("Unexpected UnknownUtxo error: {e:#}")
-                        }
-                        _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
-                    })?;
-                }
-            }
+        // Without obsolete UTXOs, the default behavior already satisfies every policy
+        if obsolete_utxos.len() == 0 {
+            return Ok(vec![]);
         }
 
-        if !options.disable_rbf {
-            // Enable RBF
-            tx_builder.enable_rbf();
+        // First, try with the current subwallet only
+        let mut fallbacks = vec![UtxoSelection::Exclude(
+            obsolete_utxos
+                .iter()
+                .flatten()
+                .cloned()
+                .chain(excluded.into_iter())
+                .collect(),
+        )];
+        // Then with each obsolete subwallet alone
+        if let CoinSelectionPolicy::PrivacyPreferSingleSubwallet = coin_selection {
+            fallbacks.extend(obsolete_utxos.into_iter().map(UtxoSelection::UseOnly));
         }
+        log::debug!("HeritageWallet::coin_selection_fallbacks - fallbacks={fallbacks:?}");
+        Ok(fallbacks)
+    }
 
-        // Create the PSBT
-        log::debug!("HeritageWallet::create_psbt - tx_builder.finish()");
-        let (mut psbt, _) = tx_builder.finish().map_err(|e| match e {
-            bdk::Error::InvalidPolicyPathError(e) => Error::FailToExtractPolicy(e),
-            bdk::Error::UnknownUtxo
-            | bdk::Error::FeeRateTooLow { .. }
-            | bdk::Error::FeeTooLow { .. }
-            | bdk::Error::ScriptDoesntHaveAddressForm
-            | bdk::Error::InsufficientFunds { .. }
-            | bdk::Error::NoRecipients
-            | bdk::Error::NoUtxosSelected
-            | bdk::Error::OutputBelowDustLimit(_)
-            | bdk::Error::BnBTotalTriesExceeded
-            | bdk::Error::BnBNoExactMatch
-            | bdk::Error::FeeRateUnavailable
-            | bdk::Error::SpendingPolicyRequired(_)
-            | bdk::Error::TransactionNotFound
-            | bdk::Error::Psbt(_)
-            | bdk::Error::Miniscript(_)
-            | bdk::Error::MiniscriptPsbt(_)
-            | bdk::Error::InvalidOutpoint(_)
-            | bdk::Error::InvalidNetwork { .. }
-            | bdk::Error::Descriptor(_)
-            | bdk::Error::ChecksumMismatch => Error::PsbtCreationError(e.to_string()),
-            _ => {
-                log::error!("Unknown error while creating PSBT: {e:#}");
-                Error::Unknown(e.to_string())
-            }
-        })?;
+    /// Create a [Psbt] for an heir, spending every UTXO the heir is currently eligible to.
+    ///
+    /// If the heir has a spending quota (see [HeritageWallet::set_heir_spending_quota]), the
+    /// outputs that do not belong to the wallet cannot receive more than this share of the
+    /// spent funds: the rest must go back to the wallet, e.g. using a
+    /// [SpendingConfig::DrainToWithChange] with a `change_to` address of the wallet.
+    /// Note that this is purely advisory: the quota is not part of the descriptors and
+    /// other software will not enforce it.
+    ///
+    /// # Errors
+    /// Returns [Error::HeirSpendingQuotaExceeded] if the heir would send more than its quota
+    /// out of the wallet
+    pub fn create_heir_psbt(
+        &self,
+        heir_config: HeirConfig,
+        spending_config: SpendingConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<(Psbt, TransactionSummary)> {
+        log::debug!(
+            "HeritageWallet::create_heir_psbt - heir_config={heir_config:?} \
+        spending_config={spending_config:?} options={options:?}"
+        );
+        let spending_quota = self.get_heir_spending_quota(&heir_config)?;
 
-        // Post-process the PSBT
-        // We want to:
-        // - Minimize the new Inputs, if any
-        // - Override the Locktime, if this is an Heir spending
-        // - Override all the individual nSequence, if this is an Heir spending
-        for (psbt_input, tx_input) in psbt
-            .inputs
-            .iter_mut()
-            .zip(psbt.unsigned_tx.input.iter_mut())
-        {
-            // Sequence of the TX Input
-            if heir_spending {
-                if let Some(seq) = seq_index.get(&tx_input.previous_output) {
-                    log::debug!("HeritageWallet::create_psbt - For tx_input={tx_input:?} override tx_input.sequence={seq:?}");
-                    tx_input.sequence = *seq;
-                }
-            }
+        let (psbt, tx_summary) =
+            self.create_psbt(Spender::Heir(heir_config), spending_config, options)?;
 
-            // Minimization of the PsbtInput, if necessary
-            if !already_minimized_psbt_input_by_outpoint.contains(&tx_input.previous_output) {
-                minimize_psbt_input_for_spender(psbt_input, heritage_explorer.as_ref());
-            }
-        }
-        // Override the Locktime and ensure TX v2
-        if heir_spending {
+        if let Some(spending_quota) = spending_quota {
+            let spent_amount = tx_summary
+                .owned_inputs
+                .iter()
+                .map(|o| o.amount)
+                .sum::<Amount>();
+            let max_amount = Amount::from_sat(spent_amount.to_sat() * spending_quota as u64 / 100);
+            // Everything that does not go back to the wallet is considered spent by the heir
+            let owned_amount = tx_summary
+                .owned_outputs
+                .iter()
+                .map(|o| o.amount)
+                .sum::<Amount>();
+            let sent_amount =
+                Amount::from_sat(psbt.unsigned_tx.output.iter().map(|o| o.value).sum::<u64>())
+                    - owned_amount;
             log::debug!(
-                "HeritageWallet::create_psbt - Override psbt.unsigned_tx.lock_time={final_lock:?}"
+                "HeritageWallet::create_heir_psbt - spending_quota={spending_quota} \
+                sent_amount={sent_amount} max_amount={max_amount}"
             );
-            psbt.unsigned_tx.lock_time = final_lock;
-            psbt.unsigned_tx.version = 2;
+            if sent_amount > max_amount {
+                log::error!(
+                    "HeritageWallet::create_heir_psbt - the heir would send more than \
+                    its quota allows ({max_amount})"
+                );
+                return Err(Error::HeirSpendingQuotaExceeded { max_amount });
+            }
         }
+        Ok((psbt, tx_summary))
+    }
 
-        // If there is a fee rate, adjust the fee because BDK computes it with laaaaaarge margin
-        // As we are only using TapRoot inputs, we can do a lot better without too much difficulties
-        // We just have to find the "change" output
-        if let Some(fee_rate) = fee_rate {
-            let adjustable_output_index = if let Some(adjustable_output_index) = psbt
-                .unsigned_tx
-                .output
-                .iter()
-                .position(|o| o.script_pubkey == drain_script)
+    /// Retrieve the spending quota of the heir, in percent of the funds it spends.
+    /// See [HeritageWallet::create_heir_psbt] for how it is enforced
+    pub fn get_heir_spending_quota(&self, heir_config: &HeirConfig) -> Result<Option<u8>> {
+        Ok(self
+            .database
+            .borrow()
+            .get_heir_spending_quotas()?
+            .and_then(|quotas| quotas.get(heir_config)))
+    }
+
+    /// Set the spending quota of the heir, in percent of the funds it spends, or remove it
+    /// if `quota` is [None]. The quota is a policy of the wallet: it does not change the
+    /// [HeritageConfig] nor the descriptors.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidSpendingQuota] if `quota` is not between 1 and 100 percent
+    pub fn set_heir_spending_quota(
+        &self,
+        heir_config: HeirConfig,
+        quota: Option<u8>,
+    ) -> Result<()> {
+        log::debug!(
+            "HeritageWallet::set_heir_spending_quota - heir_config={heir_config:?} quota={quota:?}"
+        );
+        let mut quotas = self
+            .database
+            .borrow()
+            .get_heir_spending_quotas()?
+            .unwrap_or_default();
+        quotas.set(heir_config, quota)?;
+        Ok(self
+            .database
+            .borrow_mut()
+            .set_heir_spending_quotas(&quotas)?)
+    }
+
+    /// Create a [Psbt] replacing the unconfirmed owner transaction `txid` (BIP-125) in order to bump its fee.
+    ///
+    /// The replacement spends the exact same inputs and pays the exact same outputs as the original
+    /// transaction, signals RBF on every input and uses `new_fee_rate` to compute its fee.
+    /// The fee increase is deducted from the change output of the original transaction or,
+    /// if there is none, from its only output (typically when the original transaction drained the wallet).
+    ///
+    /// The owned outputs of the replacement carry the key origins of their descriptor, like the
+    /// ones of the original transaction.
+    ///
+    /// # Errors
+    /// Return an error if the transaction is unknown or already confirmed, if `new_fee_rate` is not
+    /// higher than the fee rate of the original transaction, if the fee increase does not respect
+    /// the BIP-125 rules or if the output paying for the fee increase is not large enough.
+    pub fn create_replacement_psbt(
+        &self,
+        txid: Txid,
+        new_fee_rate: FeeRate,
+    ) -> Result<(Psbt, TransactionSummary)> {
+        log::debug!(
+            "HeritageWallet::create_replacement_psbt - txid={txid} new_fee_rate={new_fee_rate:?}"
+        );
+
+        // The original transaction may involve any subwallet
+        let mut subwallet_configs = self.database.borrow().list_obsolete_subwallet_configs()?;
+        subwallet_configs.extend(
+            self.database
+                .borrow()
+                .get_subwallet_config(SubwalletConfigId::Current)?,
+        );
+        let subwallets = subwallet_configs
+            .into_iter()
+            .map(|swc| self.get_subwallet(&swc))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Retrieve the original transaction
+        let mut original_tx_details = None;
+        for subwallet in subwallets.iter() {
+            if let Some(tx_details) = subwallet
+                .get_tx(&txid, true)
+                .map_err(|e| DatabaseError::Generic(e.to_string()))?
+                .filter(|tx_details| tx_details.transaction.is_some())
             {
-                adjustable_output_index
-            } else {
-                log::info!(
-                    "HeritageWallet::create_psbt - This psbt does not have change, adding one"
+                original_tx_details = Some(tx_details);
+                break;
+            }
+        }
+        let original_tx_details = original_tx_details.ok_or(Error::UnknownTransaction(txid))?;
+        if original_tx_details.confirmation_time.is_some() {
+            log::error!("Cannot replace an already confirmed transaction");
+            return Err(Error::TransactionAlreadyConfirmed(txid));
+        }
+        let mut unsigned_tx = original_tx_details
+            .transaction
+            .expect("filtered on transaction.is_some()");
+
+        // Re-create the PsbtInputs from the UTXOs spent by the original transaction
+        let mut psbt_inputs = Vec::with_capacity(unsigned_tx.input.len());
+        for tx_input in unsigned_tx.input.iter_mut() {
+            let outpoint = tx_input.previous_output;
+            let mut o_psbt_input = None;
+            for subwallet in subwallets.iter() {
+                let o_utxo = subwallet
+                    .database()
+                    .get_utxo(&outpoint)
+                    .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+                if let Some(utxo) = o_utxo {
+                    let psbt_input =
+                        subwallet
+                            .get_psbt_input(utxo, None, true)
+                            .map_err(|e| match e {
+                                bdk::Error::MiniscriptPsbt(_) => {
+                                    Error::PsbtCreationError(e.to_string())
+                                }
+                                _ => Error::from(DatabaseError::Generic(e.to_string())),
+                            })?;
+                    o_psbt_input = Some(psbt_input);
+                    break;
+                }
+            }
+            let Some(mut psbt_input) = o_psbt_input else {
+                log::error!(
+                    "HeritageWallet::create_replacement_psbt - Input {outpoint} is not owned by the wallet"
                 );
-                // We are in the remote possibility where we try to send exactly the right amount
-                // so that there is no need to change
-                // In that case just add the output, process the psbt, and see what happen
-                let drain_output = TxOut {
-                    value: 0,
-                    script_pubkey: drain_script.clone(),
-                };
-                psbt.unsigned_tx.output.push(drain_output);
-                psbt.outputs.push(Output::default());
-                let adjustable_output_index = psbt.unsigned_tx.output.len() - 1;
-                adjustable_output_index
+                return Err(Error::PsbtCreationError(format!(
+                    "input {outpoint} is not owned by the wallet"
+                )));
             };
+            minimize_psbt_input_for_spender(&mut psbt_input, None);
+            psbt_inputs.push(psbt_input);
 
-            log::debug!("HeritageWallet::create_psbt - adjust_with_real_fee(psbt, {fee_rate:?}, {adjustable_output_index})");
-            let adjustment = adjust_with_real_fee(&mut psbt, &fee_rate, adjustable_output_index);
-            log::info!("HeritageWallet::create_psbt - Fee adjustment: {adjustment}");
+            // Remove any signature and signal RBF
+            tx_input.script_sig = ScriptBuf::new();
+            tx_input.witness = Witness::new();
+            tx_input.sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        }
 
-            // If the resulting amount is below dust treshold, just pop the output (and therefor give that amount to the miners)
-            if psbt.unsigned_tx.output[adjustable_output_index]
-                .value
-                .is_dust(&drain_script)
-            {
-                // In that case, the adjustment is 0 because the only way we are here
-                // is that we where in the case "remote possibility where we try to send exactly the right amount"
-                // and we added the output drain and it happens to be too small so we just go back to the old fee.
-                psbt.unsigned_tx.output.remove(adjustable_output_index);
-                psbt.outputs.remove(adjustable_output_index);
+        // Find the owned outputs and their descriptor, so that signers recognize them
+        let mut owned_output_descriptors = Vec::new();
+        for (i, tx_output) in unsigned_tx.output.iter().enumerate() {
+            for subwallet in subwallets.iter() {
+                if let Some((keychain, child)) = subwallet
+                    .database()
+                    .get_path_from_script_pubkey(&tx_output.script_pubkey)
+                    .map_err(|e| DatabaseError::Generic(e.to_string()))?
+                {
+                    let descriptor = subwallet
+                        .get_descriptor_for_keychain(keychain)
+                        .at_derivation_index(child)
+                        .map_err(|e| Error::PsbtCreationError(e.to_string()))?;
+                    owned_output_descriptors.push((i, keychain, descriptor));
+                    break;
+                }
             }
         }
-
-        // Our PSBT only contains owned inputs
-        // Adding all inputs into the owned_inputs Vec
-        let owned_inputs = psbt
-            .inputs
+        // The output paying for the fee increase: the change if any, else the only output
+        let change_output_index = owned_output_descriptors
             .iter()
-            .zip(psbt.unsigned_tx.input.iter())
-            .map(|(pi, ti)| {
-                let utxo = pi.witness_utxo.as_ref().expect("we only deal with Taproot");
-                TransactionSummaryOwnedIO {
-                    outpoint: ti.previous_output,
-                    address: (&utxo.script_pubkey)
-                        .try_into()
-                        .expect("comes from the PSBT"),
-                    amount: Amount::from_sat(utxo.value),
-                }
-            })
-            .collect::<Vec<_>>();
-        // Compute the future TxId
-        let txid = psbt.unsigned_tx.txid();
-        // Creating the owned_outputs Vec
-        let owned_outputs = (0u32..)
-            .zip(psbt.unsigned_tx.output.iter())
-            .filter(|&(_, o)| self.is_mine(o.script_pubkey.as_script()).unwrap_or(false))
-            .map(|(i, o)| TransactionSummaryOwnedIO {
-                outpoint: OutPoint { txid, vout: i },
-                address: (&o.script_pubkey).try_into().expect("comes from the PSBT"),
-                amount: Amount::from_sat(o.value),
-            })
-            .collect::<Vec<_>>();
+            .find(|(_, keychain, _)| *keychain == KeychainKind::Internal)
+            .map(|(i, _, _)| *i);
+        let adjustable_output_index = match change_output_index {
+            Some(i) => i,
+            None if unsigned_tx.output.len() == 1 => 0,
+            None => {
+                log::error!("Cannot find an output to pay for the fee increase");
+                return Err(Error::PsbtCreationError(
+                    "the transaction does not have a change output".to_owned(),
+                ));
+            }
+        };
+        log::debug!(
+            "HeritageWallet::create_replacement_psbt - adjustable_output_index={adjustable_output_index}"
+        );
 
-        // Create the parent_ids
-        let parent_txids = psbt
-            .unsigned_tx
-            .input
-            .iter()
-            .map(|i| i.previous_output.txid)
-            .collect();
+        let mut psbt =
+            Psbt::from_unsigned_tx(unsigned_tx).expect("script_sig and witness have been removed");
+        psbt.inputs = psbt_inputs;
+        for (i, _, descriptor) in owned_output_descriptors {
+            psbt.update_output_with_descriptor(i, &descriptor)
+                .map_err(|e| Error::PsbtCreationError(e.to_string()))?;
+        }
 
-        let fee = psbt.fee().expect("our psbt is fresh and sound");
-        let fee_rate = fee_rate
-            .map(|bdk_fee_rate| {
-                FeeRate::from_sat_per_vb_unchecked(bdk_fee_rate.as_sat_per_vb() as u64)
-            })
-            .unwrap_or_else(|| fee / get_expected_tx_weight(&psbt));
-        // Create the TransactionSummary
-        let tx_summary = TransactionSummary {
-            txid,
-            confirmation_time: None,
-            owned_inputs,
-            owned_outputs,
-            fee,
-            fee_rate,
-            parent_txids,
-        };
+        // The new fee rate must be higher than the original one
+        let original_fee = psbt.fee().expect("the PSBT is valid");
+        let original_fee_rate = original_fee / get_expected_tx_weight(&psbt);
+        log::debug!(
+            "HeritageWallet::create_replacement_psbt - original_fee_rate={original_fee_rate:?}"
+        );
+        if new_fee_rate <= original_fee_rate {
+            log::error!("The replacement fee rate must be higher than the original one");
+            return Err(Error::InsufficientReplacementFeeRate {
+                original: original_fee_rate,
+                new: new_fee_rate,
+            });
+        }
 
-        log::debug!("HeritageWallet::create_psbt - psbt={psbt:?}");
-        log::debug!("HeritageWallet::create_psbt - tx_summary={tx_summary:?}");
-        Ok((psbt, tx_summary))
-    }
+        let bdk_fee_rate = BdkFeeRate::from_sat_per_kwu(new_fee_rate.to_sat_per_kwu() as f32);
+        log::debug!("HeritageWallet::create_replacement_psbt - adjust_with_real_fee(psbt, {bdk_fee_rate:?}, {adjustable_output_index})");
+        let adjustment = adjust_with_real_fee(&mut psbt, &bdk_fee_rate, adjustable_output_index);
+        log::info!("HeritageWallet::create_replacement_psbt - Fee adjustment: {adjustment}");
+        // If the fee did not increase, the adjustable output was too small.
+        // If it became dust, we cannot keep it either.
+        let adjustable_output = &psbt.unsigned_tx.output[adjustable_output_index];
+        if adjustment >= 0
+            || adjustable_output
+                .value
+                .is_dust(&adjustable_output.script_pubkey)
+        {
+            // The only way out is to give the whole change to the miners
+            if change_output_index.is_none() {
+                log::error!("The adjustable output cannot pay for the fee increase");
+                return Err(Error::InsufficientFundsForReplacement);
+            }
+            log::info!("HeritageWallet::create_replacement_psbt - Removing the change output");
+            psbt.unsigned_tx.output.remove(adjustable_output_index);
+            psbt.outputs.remove(adjustable_output_index);
+            let expected_fee = bdk_fee_rate.fee_wu(get_expected_tx_weight(&psbt));
+            if psbt.fee().expect("the PSBT is valid").to_sat() < expected_fee {
+                log::error!("The transaction inputs cannot pay for the fee increase");
+                return Err(Error::InsufficientFundsForReplacement);
+            }
+        }
 
-    fn get_conditions_and_utxos_for_subwallet(
-        &self,
-        subwallet_config: &SubwalletConfig,
-        spender: &Spender,
-        assume_blocktime: &BlockTime,
-        include_foreign_utxo: bool,
-    ) -> Result<
-        Option<(
-            Option<LockTime>,
-            Option<Sequence>,
-            Vec<(LocalUtxo, Option<(OutPoint, Input, usize)>)>,
-        )>,
-    > {
+        // BIP-125 rules 3 and 4: the replacement must pay at least the fee of the original
+        // transaction plus its own relay fee at the incremental relay fee rate
+        let fee = psbt.fee().expect("the PSBT is valid");
+        let min_fee = original_fee + INCREMENTAL_RELAY_FEE_RATE * get_expected_tx_weight(&psbt);
+        if fee < min_fee {
+            log::error!("The replacement fee ({fee}) must be at least {min_fee}");
+            return Err(Error::InsufficientReplacementFee {
+                original_fee,
+                min_fee,
+                fee,
+            });
+        }
+
+        let tx_summary = self.create_transaction_summary(&psbt, Some(new_fee_rate));
+
+        log::debug!("HeritageWallet::create_replacement_psbt - psbt={psbt:?}");
+        log::debug!("HeritageWallet::create_replacement_psbt - tx_summary={tx_summary:?}");
+        Ok((psbt, tx_summary))
+    }
+
+    fn create_psbt(
+        &self,
+        spender: Spender,
+        spending_config: SpendingConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<(Psbt, TransactionSummary)> {
         log::debug!(
-            "HeritageWallet::filter_subwallet_utxos - subwallet_config={subwallet_config:?} spender={spender:?} assume_blocktime={assume_blocktime:?}"
+            "HeritageWallet::create_psbt - spender={spender:?} \
+            spending_config={spending_config:?} options={options:?}"
         );
+        // When the owner is spending, we want to drain all the obsolete wallets.
+        // The current wallet is used only if draining old wallets is not enough to fullfil the requested paiement
+        // In the likely case where draining old wallets is enough to fullfil the requested paiement, the remainder is send as change in the current wallet
 
-        let heritage_explorer = if let Spender::Heir(heir_config) = &spender {
-            let he = subwallet_config
-                .heritage_config()
-                .get_heritage_explorer(heir_config);
-            if he.is_none() {
-                // If None, the Heir is not in this wallet
-                // So skip it
-                return Ok(None);
-            }
-            he
-        } else {
-            None
-        };
+        // When an heir is spending, we want to take eligible UTXO from the old wallets, taking the closest to loss of exclusivity first
+        // We cannot overshot, as we would not now what to do with the change (assuming that, if the heir is spending, to owner is likely dead or otherwise impaired)
+        // For now, the wisest course of action is probably to only allow Heir to drain wallets, meaning they receive every eligible UTXO
 
-        let spend_condition = heritage_explorer
-            .as_ref()
-            .map(|he| he.get_spend_conditions());
+        // Walk over every spender-eligible UTXO in all subwallets, ordered by loss of exclusivity
+        // Eligible UTXO = UTXO that the spender can use given the current block_height (owner is typically always eligible, heirs must wait certain dates)
+        // Loss of exlusivity = block_height at which another new spender will be able to spend, for the owner it is typically the first Heir, for an Heir the next-in-line Heir
 
-        // At this point we KNOW that there is either a Some(spend_condition) or this is the owner spending
-        // If it is a Some (so an Heir is spending), verify that the timestamp is compatible with they spending
-        if let Some(sc) = &spend_condition {
-            // If too soon, the Heir cannot yet spend this wallet
-            // So skip it and return a None
-            if !sc.can_spend_at(assume_blocktime.timestamp) {
-                return Ok(None);
-            }
-        }
+        // As soon as enough UTXO has been gathered to satisfy the spending_config, stop walking over the subwallets
+        // Each subwallet will yield a PSBT, we just have to merge them to produce the expected PSBT of this function
 
-        let (o_lock_time, o_sequence) = match &spend_condition {
-            Some(sc) => (
-                sc.get_spendable_timestamp().map(|ts| {
-                    LockTime::from_time(ts as u32)
-                        .expect("comes from HeritageConfig which check the value")
-                }),
-                sc.get_relative_block_lock()
-                    .map(|h| Sequence::from_height(h)),
-            ),
-            None => (None, None),
+        let heir_spending = if let Spender::Heir(_) = spender {
+            true
+        } else {
+            false
         };
 
-        let subwallet = self.get_subwallet(&subwallet_config)?;
-        let unspent = subwallet
-            .list_unspent()
-            .map_err(|e| DatabaseError::Generic(e.to_string()))?;
-
-        // Manually select UTXOs
-        let utxos_and_inputs = unspent
-        .into_iter()
-        // Filter to keep only UTXO spendable by the Spender
-        .filter(|utxo| {
-            // We want to get the confirmation time of the TX and verify that it is compatible
-            // with the HeritageConfig for the Heir, i.e. that the Heir will be able to spend the UTXO
-            // Their is two requirements:
-            //   1. timestamp.now() must be greater than the heritagedate for the heir
-            //   2. for each TX, blockheight < current_block_height + min_lock
-            if let Spender::Heir(_) = spender {
-                // There is a spend_condition_tester, use it to filter TX
-                let tx = match subwallet.get_tx(&utxo.outpoint.txid, false) {
-                    Ok(Some(tx)) => tx,
-                    Ok(None) => {
-                        log::error!("HeritageWallet::create_psbt_for_subwallet - Database inconsistent for Subwallet ID={} No tx with in database for {utxo:?}", subwallet_config.subwallet_id());
-                        return false;
-                    }
-                    Err(e) => {
-                        log::error!("HeritageWallet::create_psbt_for_subwallet - Failed to retrieve tx for Subwallet ID={} and {utxo:?}: {e:#}", subwallet_config.subwallet_id());
-                        return false;
-                    }
-                };
-                let Some(tx_confirmation_time) = tx.confirmation_time else {
-                    return false;
-                };
+        let default_sequence = if options.disable_rbf {
+            Sequence::ENABLE_LOCKTIME_NO_RBF
+        } else {
+            Sequence::ENABLE_RBF_NO_LOCKTIME
+        };
 
-                // If the TX is not old enough, skip it
-                if tx_confirmation_time.height
-                    + spend_condition
-                        .as_ref()
-                        .expect("this is some when Spender::Heir")
-                        .get_relative_block_lock().unwrap_or(0)
-                        as u32
-                    > assume_blocktime.height
-                {
-                    return false;
+        // An Heir always drains the eligible UTXOs, so SpendingConfig::Recipients
+        // must have at least one recipient to receive what remains, and no change address
+        if heir_spending {
+            if let SpendingConfig::Recipients {
+                recipients,
+                change_to,
+            } = &spending_config
+            {
+                if recipients.is_empty() {
+                    log::error!("An Heir cannot use SpendingConfig::Recipients without recipients");
+                    return Err(Error::InvalidSpendingConfigForHeir);
                 }
-            }
-            true
-        })
-        .map(|utxo|{
-            if include_foreign_utxo {
-                let outpoint = utxo.outpoint;
-                let mut input = subwallet.get_psbt_input(utxo.clone(), None, true).map_err(|e| match e {
-                    bdk::Error::UnknownUtxo => {
-                        log::error!("Unexpected UnknownUtxo error: {e:#}");
-                        panic!("Unexpected UnknownUtxo error: {e:#}")
-                    }
-                    bdk::Error::MiniscriptPsbt(_) => Error::PsbtCreationError(e.to_string()),
-                    _ => DatabaseError::Generic(e.to_string()).into(),
-                })?;
-                minimize_psbt_input_for_spender(&mut input, heritage_explorer.as_ref());
-                let satisfaction_weight = subwallet
-                .get_descriptor_for_keychain(utxo.keychain)
-                .max_weight_to_satisfy()
-                .expect("our descriptors can always be satisfied");
-                Ok((utxo, Some((outpoint, input, satisfaction_weight))))
-            } else {
-                Ok((utxo, None))
-            }
-        }).filter_map(|r: Result<_>|{
-            match r {
-                Ok(v) => Some(v),
-                Err(e) => {
-                    log::error!("HeritageWallet::create_psbt_for_subwallet - Failed to create PsbtInput for Subwallet ID={}: {e:#}", subwallet_config.subwallet_id());
-                    None
+                if change_to.is_some() {
+                    log::error!(
+                        "An Heir cannot use SpendingConfig::Recipients with a change address"
+                    );
+                    return Err(Error::InvalidSpendingConfigForHeir);
                 }
             }
-        })
-        .collect::<Vec<_>>();
-
-        // If there are no applicable UTXO, don't bother sending a result
-        let result = if utxos_and_inputs.len() == 0 {
-            None
-        } else {
-            Some((o_lock_time, o_sequence, utxos_and_inputs))
         };
-        log::debug!("HeritageWallet::filter_subwallet_utxos - result={result:?}");
-        Ok(result)
-    }
 
-    fn create_new_subwallet_config(
-        &self,
-        heritage_config: HeritageConfig,
-        old_subwallet_config: Option<SubwalletConfig>,
-    ) -> Result<()> {
-        log::debug!(
-            "HeritageWallet::create_new_subwallet_config - old_subwallet_config={old_subwallet_config:?}"
-        );
-        // If different, then we need to archive the old subwallet_config and create a new one
-        // With a new AccountXPub
-        let new_account_xpub = self
-            .database
-            .borrow()
-            .get_unused_account_xpub()?
-            .ok_or(Error::MissingUnusedAccountXPub)?;
-        log::debug!(
-            "HeritageWallet::update_heritage_config - new_account_xpub={new_account_xpub:?}"
-        );
-        let mut transaction = self.database.borrow().begin_transac();
-        transaction.delete_unused_account_xpub(&new_account_xpub)?;
-        let new_subwallet_config = SubwalletConfig::new(new_account_xpub, heritage_config);
-        log::info!("HeritageWallet::update_heritage_config - Creating a new SubwalletConfig for the new HeritageConfig");
-        log::debug!(
-            "HeritageWallet::update_heritage_config - new_subwallet_config={new_subwallet_config:?}"
-        );
-        transaction.safe_update_current_subwallet_config(
-            &new_subwallet_config,
-            old_subwallet_config.as_ref(),
-        )?;
-        if let Some(old_subwallet_config) = old_subwallet_config {
-            transaction.put_subwallet_config(
-                SubwalletConfigId::Id(old_subwallet_config.subwallet_id()),
-                &old_subwallet_config,
-            )?;
+        // At most one recipient receives the remainder, and then there is no change
+        if let SpendingConfig::Recipients {
+            recipients,
+            change_to,
+        } = &spending_config
+        {
+            let max_count = recipients.iter().filter(|r| r.is_max()).count();
+            if max_count > 1 {
+                return Err(Error::InvalidSpendingConfig(
+                    "only one recipient can receive the maximum amount",
+                ));
+            }
+            if max_count == 1 && change_to.is_some() {
+                return Err(Error::InvalidSpendingConfig(
+                    "there is no change when a recipient receives the maximum amount",
+                ));
+            }
+            if max_count == 1 && heir_spending && !recipients.last().is_some_and(Recipient::is_max)
+            {
+                return Err(Error::InvalidSpendingConfig(
+                    "only the last recipient of an heir can receive the maximum amount",
+                ));
+            }
         }
-        self.database.borrow_mut().commit_transac(transaction)?;
-        Ok(())
-    }
 
-    fn get_subwallet(
-        &self,
-        subwalletconfig: &SubwalletConfig,
-    ) -> Result<Wallet<<D as PartitionableDatabase>::SubDatabase>> {
-        log::debug!("HeritageWallet::get_subwallet - Opening subwallet database");
-        let subdatabase = self
-            .database
-            .borrow()
-            .get_subdatabase(SubdatabaseId::from(subwalletconfig.subwallet_id()))?;
-        log::debug!("HeritageWallet::get_subwallet - Creating subwallet");
-        Ok(subwalletconfig.get_subwallet(subdatabase))
-    }
-
-    fn internal_get_new_address(&self, keychain_kind: KeychainKind) -> Result<AddressInfo> {
-        log::debug!("HeritageWallet::internal_get_new_address - keychain_kind={keychain_kind:?}");
+        // Never send to an address of another network
+        let addresses = match &spending_config {
+            SpendingConfig::DrainTo(addr) => vec![addr],
+            SpendingConfig::Recipients {
+                recipients,
+                change_to,
+            } => recipients
+                .iter()
+                .map(|Recipient(addr, _)| addr)
+                .chain(change_to.iter())
+                .collect(),
+            SpendingConfig::DrainToWithChange {
+                drain_to,
+                change_to,
+                ..
+            } => vec![drain_to, change_to],
+        };
+        if let Some(addr) = addresses
+            .into_iter()
+            .find(|addr| !addr.as_unchecked().is_valid_for_network(self.network))
+        {
+            log::error!("{addr} is not an address of the {} network", self.network);
+            return Err(Error::InvalidAddressString(addr.to_string(), self.network));
+        }
 
+        // We do this now so if it fails we don't bother to go further
         let current_subwallet_config = self
             .database
             .borrow()
             .get_subwallet_config(SubwalletConfigId::Current)?
             .ok_or(Error::MissingCurrentSubwalletConfig)?;
-        log::debug!("HeritageWallet::internal_get_new_address - current_subwallet_config={current_subwallet_config:?}");
-
-        // Verify if the subwallet_config has not already been used, just override it
-        if current_subwallet_config.subwallet_firstuse_time().is_none() {
-            log::debug!(
-                "HeritageWallet::internal_get_new_address - current_subwallet_config was never used"
-            );
-            let mut new_current_subwallet_config = current_subwallet_config.clone();
-            new_current_subwallet_config.mark_subwallet_firstuse()?;
-            log::info!("HeritageWallet::internal_get_new_address - Marking previously unused SubwalletConfig as used");
-            log::debug!(
-                "HeritageWallet::internal_get_new_address - new_current_subwallet_config={new_current_subwallet_config:?}"
-            );
-            self.database
-                .borrow_mut()
-                .safe_update_current_subwallet_config(
-                    &new_current_subwallet_config,
-                    Some(&current_subwallet_config),
-                )?;
-        }
-        log::debug!("HeritageWallet::internal_get_new_address - get_subwallet");
-        let subwallet = self.get_subwallet(&current_subwallet_config)?;
+        log::debug!(
+            "HeritageWallet::create_psbt - current_subwallet_config={current_subwallet_config:?}"
+        );
+        let current_subwallet = self.get_subwallet(&current_subwallet_config)?;
 
-        let address = match keychain_kind {
-            KeychainKind::External => {
-                log::debug!("HeritageWallet::internal_get_new_address - subwallet.get_address");
-                subwallet
-                    .get_address(AddressIndex::New)
-                    .map_err(|e| match e {
-                        bdk::Error::ScriptDoesntHaveAddressForm => Error::Unknown(
-                            "ScriptDoesntHaveAddressForm: Invalid script retrieved from database"
-                                .to_owned(),
-                        ),
-                        _ => DatabaseError::Generic(e.to_string()).into(),
-                    })?
+        // Logging the UTXO selection strategy
+        match &options.utxo_selection {
+            UtxoSelection::IncludePrevious => {
+                log::info!("HeritageWallet::create_psbt - Using the default UTXO selection");
             }
-            KeychainKind::Internal => {
+            UtxoSelection::Include(vec) => {
+                log::info!(
+                    "HeritageWallet::create_psbt - Using the default UTXO selection \
+                and ensuring the inclusion of the given UTXOs"
+                );
+                log::debug!("HeritageWallet::create_psbt - Include={vec:?}");
+            }
+            UtxoSelection::Exclude(vec) => {
+                log::info!(
+                    "HeritageWallet::create_psbt - Using the default UTXO selection \
+                but ensuring the exclusion of the given UTXOs"
+                );
+                log::debug!("HeritageWallet::create_psbt - Exclude={vec:?}");
+            }
+            UtxoSelection::IncludeExclude { include, exclude } => {
+                log::info!(
+                    "HeritageWallet::create_psbt - Using the default UTXO selection \
+                but ensuring the inclusion and exclusion of the given UTXOs"
+                );
+                log::debug!("HeritageWallet::create_psbt - Include={include:?}");
+                log::debug!("HeritageWallet::create_psbt - Exclude={exclude:?}");
+                let anomalies = include
+                    .iter()
+                    .filter(|&e| exclude.contains(e))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if anomalies.len() > 0 {
+                    return Err(Error::InvalidUtxoSelectionIncludeExclude(anomalies));
+                }
+            }
+            UtxoSelection::UseOnly(vec) => {
+                log::info!("HeritageWallet::create_psbt - Using only the given UTXOs");
+                log::debug!("HeritageWallet::create_psbt - UseOnly={vec:?}");
+            }
+        }
+
+        // Frozen UTXOs are never spent, whoever the spender is
+        let frozen_utxos = self
+            .database
+            .borrow()
+            .list_frozen_utxos()?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        log::debug!("HeritageWallet::create_psbt - frozen_utxos={frozen_utxos:?}");
+        let frozen_included = match &options.utxo_selection {
+            UtxoSelection::IncludePrevious | UtxoSelection::Exclude(_) => vec![],
+            UtxoSelection::Include(include) | UtxoSelection::IncludeExclude { include, .. } => {
+                include
+                    .iter()
+                    .filter(|op| frozen_utxos.contains(op))
+                    .cloned()
+                    .collect()
+            }
+            UtxoSelection::UseOnly(include) => include
+                .iter()
+                .filter(|op| frozen_utxos.contains(op))
+                .cloned()
+                .collect(),
+        };
+        if frozen_included.len() > 0 {
+            return Err(Error::FrozenUtxoSelectionInclude(frozen_included));
+        }
+
+        // Gather all the UTXO of the obsolete wallet configs
+        log::debug!("HeritageWallet::create_psbt - Listing obsolete subwallet_configs");
+        let obsolete_subwallet_configs =
+            self.database.borrow().list_obsolete_subwallet_configs()?;
+
+        // Here we compute what will be the "present" for this PSBT creation
+        // If we got it as a paramter, just use it
+        // Else we create a fake BlockTime with the last synchronization height and the current timestamp
+        let block_time = match options.assume_blocktime {
+            Some(block_time) => block_time,
+            None => {
+                let mut bt = self.get_sync_time()?.ok_or(Error::UnsyncedWallet)?;
+                bt.timestamp = crate::utils::timestamp_now();
+                bt
+            }
+        };
+
+        log::debug!("HeritageWallet::create_psbt - Creating foreing_utxos list");
+        // We want to build 3 different informations
+        // - We want the "global" Locktime to apply the transaction, essentially the maximum locktime out of all the inputs
+        // - We want to keep track of all the Sequence for all the OutPoint of the inputs
+        // - We want to construct the foreign UTXOs vector
+        let (mut final_lock, mut seq_index, foreign_utxos) = obsolete_subwallet_configs
+            .into_iter()
+            .filter_map(|subwallet_config| {
+                self.get_conditions_and_utxos_for_subwallet(
+                    &subwallet_config,
+                    &spender,
+                    &block_time,
+                    true,
+                )
+                .unwrap_or_else(|e| {
+                    log::error!(
+                        "HeritageWallet::create_psbt - Failed to call \
+                        get_conditions_and_utxos_for_subwallet for \
+                        subwallet_config {subwallet_config:?}: {e:#}"
+                    );
+                    None
+                })
+                // Remove all the UTXO that must be excluded per the UTXO Selection strategy
+                .map(|(o_locktime, o_sequence, mut utxos)| {
+                    utxos.retain(|(o, _)| !frozen_utxos.contains(&o.outpoint));
+                    match &options.utxo_selection {
+                        UtxoSelection::IncludePrevious | UtxoSelection::Include(_) => (),
+                        UtxoSelection::Exclude(exclude)
+                        | UtxoSelection::IncludeExclude { exclude, .. } => {
+                            utxos.retain(|(o, _)| !exclude.contains(&o.outpoint))
+                        }
+                        UtxoSelection::UseOnly(include_exclusive) => {
+                            utxos.retain(|(o, _)| include_exclusive.contains(&o.outpoint))
+                        }
+                    };
+                    (o_locktime, o_sequence, utxos)
+                })
+            })
+            // It is possible that the previous step is yelding an empty Vec of UTXO, in that case remove it.
+            .filter(|(_, _, utxos)| utxos.len() > 0)
+            // Now we can fold and compute our final result
+            .fold(
+                (
+                    LockTime::from_consensus(bdk::bitcoin::absolute::LOCK_TIME_THRESHOLD),
+                    HashMap::new(),
+                    Vec::new(),
+                ),
+                |(mut final_lock, mut seq_index, mut foreign_utxos),
+                 (o_locktime, o_sequence, utxos)| {
+                    // Take the more restrictive locktime
+                    if o_locktime
+                        .is_some_and(|candidate_lock| final_lock.is_implied_by(candidate_lock))
+                    {
+                        final_lock = o_locktime.unwrap();
+                    }
+                    // Process the utxos
+                    for (utxo, o_foreign_utxo) in utxos {
+                        let outpoint = utxo.outpoint;
+                        seq_index.insert(outpoint, o_sequence.unwrap_or(default_sequence));
+                        let foreign_utxo =
+                            o_foreign_utxo.expect("We ask to include the foreign_utxos");
+                        foreign_utxos.push(foreign_utxo);
+                    }
+                    (final_lock, seq_index, foreign_utxos)
+                },
+            );
+
+        // Create the TX builder
+        log::debug!("HeritageWallet::create_psbt - wallet.build_tx()");
+        let mut tx_builder = current_subwallet.build_tx();
+
+        // Assume block height
+        log::debug!(
+            "HeritageWallet::create_psbt - tx_builder.current_height({})",
+            block_time.height
+        );
+        tx_builder.current_height(block_time.height);
+
+        // Minimum amount the drain output must reach, used when an Heir spends to Recipients
+        let mut heir_drain_minimum = None;
+        let drain_script = match &spending_config {
+            SpendingConfig::DrainTo(addr) => {
                 log::debug!(
-                    "HeritageWallet::internal_get_new_address - subwallet.get_internal_address"
+                    "HeritageWallet::create_psbt - tx_builder.drain_wallet().drain_to({addr:?})"
                 );
-                subwallet
-                    .get_internal_address(AddressIndex::New)
-                    .map_err(|e| match e {
-                        bdk::Error::ScriptDoesntHaveAddressForm => Error::Unknown(
-                            "ScriptDoesntHaveAddressForm: Invalid script retrieved from database"
-                                .to_owned(),
-                        ),
-                        _ => DatabaseError::Generic(e.to_string()).into(),
-                    })?
+                tx_builder.drain_wallet().drain_to(addr.script_pubkey());
+                addr.script_pubkey()
+            }
+            SpendingConfig::Recipients {
+                recipients,
+                change_to,
+            } => {
+                log::debug!(
+                    "HeritageWallet::create_psbt - tx_builder.set_recipients({recipients:?})"
+                );
+                let max_recipient = recipients.iter().position(Recipient::is_max);
+                // Convert the recipients address to scripts
+                let mut recipients = recipients
+                    .iter()
+                    .map(|Recipient(addr, amount)| (addr.script_pubkey(), amount.to_sat()))
+                    .collect::<Vec<_>>();
+                if heir_spending {
+                    // There must be no change going back to the owner: every eligible UTXO is spent
+                    // and the last recipient receives the remainder instead of its fixed amount
+                    let (last_script, last_amount) =
+                        recipients.pop().expect("verified to not be empty");
+                    log::debug!(
+                        "HeritageWallet::create_psbt - tx_builder.drain_wallet().drain_to({last_script:?})"
+                    );
+                    tx_builder
+                        .set_recipients(recipients)
+                        .drain_wallet()
+                        .drain_to(last_script.clone());
+                    // A Recipient::max is verified to be the last one and has no minimum
+                    heir_drain_minimum = Some(last_amount).filter(|_| max_recipient.is_none());
+                    last_script
+                } else if let Some(max_recipient) = max_recipient {
+                    // Every spendable UTXO is spent and the remainder goes to the Recipient::max
+                    let (max_script, _) = recipients.remove(max_recipient);
+                    log::debug!(
+                        "HeritageWallet::create_psbt - tx_builder.drain_wallet().drain_to({max_script:?})"
+                    );
+                    tx_builder
+                        .set_recipients(recipients)
+                        .drain_wallet()
+                        .drain_to(max_script.clone());
+                    max_script
+                } else {
+                    tx_builder.set_recipients(recipients);
+                    let drain_script = match change_to {
+                        Some(change_to) => change_to.script_pubkey(),
+                        None => self
+                            .internal_get_new_address(KeychainKind::Internal)?
+                            .script_pubkey(),
+                    };
+                    log::debug!(
+                        "HeritageWallet::create_psbt - tx_builder.drain_to({drain_script:?})"
+                    );
+                    tx_builder.drain_to(drain_script.clone());
+                    drain_script
+                }
+            }
+            SpendingConfig::DrainToWithChange {
+                drain_to,
+                change_amount,
+                change_to,
+            } => {
+                log::debug!(
+                    "HeritageWallet::create_psbt - tx_builder.add_recipient({change_to:?}, \
+                    {change_amount:?}).drain_wallet().drain_to({drain_to:?})"
+                );
+                // The change amount is a regular recipient, everything else goes to drain_to
+                tx_builder
+                    .add_recipient(change_to.script_pubkey(), change_amount.to_sat())
+                    .drain_wallet()
+                    .drain_to(drain_to.script_pubkey());
+                drain_to.script_pubkey()
+            }
+        };
+
+        // Keep a set of the OutPoint corresponding to already minimized PsbtInputs to filter them out of the final minimization
+        let already_minimized_psbt_input_by_outpoint = foreign_utxos
+            .iter()
+            .map(|(op, _, _)| *op)
+            .collect::<HashSet<_>>();
+
+        // Include all the foreign_utxos
+        log::debug!("HeritageWallet::create_psbt - tx_builder.add_foreign_utxo - foreing_utxos={foreign_utxos:?}");
+        for (outpoint, psbt_input, satisfaction_weight) in foreign_utxos {
+            tx_builder
+                .add_foreign_utxo(outpoint, psbt_input, satisfaction_weight)
+                .expect("Parameters are under our control and correct");
+        }
+
+        // Process the utxo_selection option
+        // The UTXOs of the obsolete subwallets are already included as foreign UTXOs,
+        // only the UTXOs of the current subwallet must be given to the tx_builder
+        let current_only = |include: Vec<OutPoint>| {
+            include
+                .into_iter()
+                .filter(|op| !already_minimized_psbt_input_by_outpoint.contains(op))
+                .collect::<Vec<_>>()
+        };
+        match options.utxo_selection {
+            UtxoSelection::IncludePrevious => (),
+            UtxoSelection::Include(include) => {
+                let include = current_only(include);
+                tx_builder.add_utxos(&include).map_err(|e| match e {
+                    bdk::Error::UnknownUtxo => Error::UnknownUtxoSelectionInclude(include),
+                    _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
+                })?;
+            }
+            UtxoSelection::Exclude(exclude) => {
+                tx_builder.unspendable(exclude.into_iter().collect());
+            }
+            UtxoSelection::IncludeExclude { include, exclude } => {
+                let include = current_only(include);
+                tx_builder.add_utxos(&include).map_err(|e| match e {
+                    bdk::Error::UnknownUtxo => Error::UnknownUtxoSelectionInclude(include),
+                    _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
+                })?;
+                tx_builder.unspendable(exclude.into_iter().collect());
+            }
+            UtxoSelection::UseOnly(include) => {
+                let include = current_only(include.into_iter().collect());
+                tx_builder.add_utxos(&include).map_err(|e| match e {
+                    bdk::Error::UnknownUtxo => Error::UnknownUtxoSelectionInclude(include),
+                    _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
+                })?;
+                tx_builder.manually_selected_only();
             }
         };
-        log::debug!("HeritageWallet::internal_get_new_address - address={address:?}");
-        Ok(address)
+        // Frozen UTXOs of the current subwallet must be marked as unspendable
+        // Note that it must happen after the UtxoSelection processing as TxBuilder::unspendable
+        // replaces the set of unspendable UTXOs
+        for outpoint in frozen_utxos.iter() {
+            tx_builder.add_unspendable(*outpoint);
+        }
+
+        // Set FeeRate
+        let fee_rate = match options.fee_policy {
+            Some(fee_policy) => match fee_policy {
+                FeePolicy::Absolute(amount) => {
+                    let amount = amount.to_sat();
+                    log::debug!(
+                        "HeritageWallet::create_psbt - tx_builder.fee_absolute - amount={amount}"
+                    );
+                    tx_builder.fee_absolute(amount);
+                    None
+                }
+                FeePolicy::FeeRate(fee_rate) => Some(fee_rate),
+            },
+            None => Some(
+                self.database
+                    .borrow()
+                    .get_fee_rate()?
+                    .ok_or(Error::MissingFeeRate)?,
+            ),
+        };
+        // Map the Option<bitcoin::FeeRate> to a Option<BdkFeerate>
+        let fee_rate = fee_rate.map(|fee_rate| {
+            let fee_rate = BdkFeeRate::from_sat_per_kwu(fee_rate.to_sat_per_kwu() as f32);
+            log::debug!(
+                "HeritageWallet::create_psbt - tx_builder.fee_rate - fee_rate={fee_rate:?}"
+            );
+            tx_builder.fee_rate(fee_rate);
+            fee_rate
+        });
+
+        // Extract the HeritageExplorer if we are with an Heir spending and if any
+        let heritage_explorer = if let Spender::Heir(heir_config) = &spender {
+            current_subwallet_config
+                .heritage_config()
+                .get_heritage_explorer(heir_config)
+        } else {
+            None
+        };
+
+        // Policy for the PSBT
+        // With an owner quorum, the key-path is unspendable and the owner leaf comes before the heirs
+        let owner_quorum_offset = current_subwallet_config
+            .heritage_config()
+            .owner_quorum()
+            .is_some() as usize;
+        let policy_index = if let Some(he) = &heritage_explorer {
+            he.get_miniscript_index() + 1 + owner_quorum_offset
+        } else {
+            owner_quorum_offset
+        };
+
+        log::debug!("HeritageWallet::create_psbt - policy_index={policy_index}");
+        let keychains = [KeychainKind::External, KeychainKind::Internal];
+        for kc in keychains {
+            if let Some(pol) = current_subwallet.policies(kc).map_err(|e| match e {
+                bdk::Error::InvalidPolicyPathError(e) => Error::FailToExtractPolicy(e),
+                _ => {
+                    log::error!(
+                        "Unknown error while extracting policies from the current SubwalletConfig: {e:#}"
+                    );
+                    panic!(
+                        "Unknown error while extracting policies from the current SubwalletConfig: {e:#}"
+                    )
+                }
+            })? {
+                let bt = BTreeMap::from([(pol.id, vec![policy_index])]);
+                log::debug!(
+                    "HeritageWallet::create_psbt - tx_builder.policy_path - bt={bt:?}, kc={kc:?}"
+                );
+                tx_builder.policy_path(bt, kc);
+            }
+        }
+
+        // If the owner is spending, we let the bdk::Wallet do its work
+        // but if an Heir is spending, we manually select the UTXOs we want in.
+        if heir_spending {
+            // Manual selection only
+            log::debug!("HeritageWallet::create_psbt - tx_builder.manually_selected_only()");
+            tx_builder.manually_selected_only();
+
+            if let Some((o_locktime, o_sequence, utxos)) = self
+                .get_conditions_and_utxos_for_subwallet(
+                    &current_subwallet_config,
+                    &spender,
+                    &block_time,
+                    false,
+                )?
+            {
+                // Take the more restrictive locktime
+                if o_locktime.is_some_and(|candidate_lock| final_lock.is_implied_by(candidate_lock))
+                {
+                    final_lock = o_locktime.unwrap();
+                }
+                // Process the utxos
+                for (utxo, _) in utxos {
+                    let outpoint = utxo.outpoint;
+                    if frozen_utxos.contains(&outpoint) {
+                        continue;
+                    }
+                    seq_index.insert(outpoint, o_sequence.unwrap_or(default_sequence));
+                    tx_builder.add_utxo(outpoint).map_err(|e| match e {
+                        bdk::Error::UnknownUtxo => {
+                            log::error!("Unexpected UnknownUtxo error: {e:#}");
+                            panic!("Unexpected UnknownUtxo error: {e:#}")
+                        }
+                        _ => Error::DatabaseError(DatabaseError::Generic(e.to_string())),
+                    })?;
+                }
+            }
+        }
+
+        if !options.disable_rbf {
+            // Enable RBF
+            tx_builder.enable_rbf();
+        }
+
+        // Set the ordering of the inputs and outputs
+        let tx_ordering = match options.output_ordering {
+            OutputOrdering::Shuffle => TxOrdering::Shuffle,
+            OutputOrdering::Bip69 => TxOrdering::Bip69Lexicographic,
+        };
+        log::debug!("HeritageWallet::create_psbt - tx_builder.ordering({tx_ordering:?})");
+        tx_builder.ordering(tx_ordering);
+
+        // Create the PSBT
+        log::debug!("HeritageWallet::create_psbt - tx_builder.finish()");
+        let tx_builder_result = match options.coin_selection {
+            CoinSelectionPolicy::OldestFirst => {
+                log::debug!(
+                    "HeritageWallet::create_psbt - tx_builder.coin_selection(OldestFirstCoinSelection)"
+                );
+                tx_builder.coin_selection(OldestFirstCoinSelection).finish()
+            }
+            _ => tx_builder.finish(),
+        };
+        let (mut psbt, _) = tx_builder_result.map_err(|e| match e {
+            bdk::Error::InvalidPolicyPathError(e) => Error::FailToExtractPolicy(e),
+            bdk::Error::UnknownUtxo
+            | bdk::Error::FeeRateTooLow { .. }
+            | bdk::Error::FeeTooLow { .. }
+            | bdk::Error::ScriptDoesntHaveAddressForm
+            | bdk::Error::InsufficientFunds { .. }
+            | bdk::Error::NoRecipients
+            | bdk::Error::NoUtxosSelected
+            | bdk::Error::OutputBelowDustLimit(_)
+            | bdk::Error::BnBTotalTriesExceeded
+            | bdk::Error::BnBNoExactMatch
+            | bdk::Error::FeeRateUnavailable
+            | bdk::Error::SpendingPolicyRequired(_)
+            | bdk::Error::TransactionNotFound
+            | bdk::Error::Psbt(_)
+            | bdk::Error::Miniscript(_)
+            | bdk::Error::MiniscriptPsbt(_)
+            | bdk::Error::InvalidOutpoint(_)
+            | bdk::Error::InvalidNetwork { .. }
+            | bdk::Error::Descriptor(_)
+            | bdk::Error::ChecksumMismatch => Error::PsbtCreationError(e.to_string()),
+            _ => {
+                log::error!("Unknown error while creating PSBT: {e:#}");
+                Error::Unknown(e.to_string())
+            }
+        })?;
+
+        // Post-process the PSBT
+        // We want to:
+        // - Minimize the new Inputs, if any
+        // - Override the Locktime, if this is an Heir spending
+        // - Override all the individual nSequence, if this is an Heir spending
+        for (psbt_input, tx_input) in psbt
+            .inputs
+            .iter_mut()
+            .zip(psbt.unsigned_tx.input.iter_mut())
+        {
+            // Sequence of the TX Input
+            if heir_spending {
+                if let Some(seq) = seq_index.get(&tx_input.previous_output) {
+                    log::debug!("HeritageWallet::create_psbt - For tx_input={tx_input:?} override tx_input.sequence={seq:?}");
+                    tx_input.sequence = *seq;
+                }
+            }
+
+            // Minimization of the PsbtInput, if necessary
+            if !already_minimized_psbt_input_by_outpoint.contains(&tx_input.previous_output) {
+                minimize_psbt_input_for_spender(psbt_input, heritage_explorer.as_ref());
+            }
+        }
+        // Override the Locktime and ensure TX v2
+        if heir_spending {
+            log::debug!(
+                "HeritageWallet::create_psbt - Override psbt.unsigned_tx.lock_time={final_lock:?}"
+            );
+            psbt.unsigned_tx.lock_time = final_lock;
+            psbt.unsigned_tx.version = 2;
+        } else {
+            let lock_time = options.lock_time_policy.lock_time(block_time.height);
+            log::debug!(
+                "HeritageWallet::create_psbt - Override psbt.unsigned_tx.lock_time={lock_time:?}"
+            );
+            psbt.unsigned_tx.lock_time = lock_time;
+        }
+
+        // If there is a fee rate, adjust the fee because BDK computes it with laaaaaarge margin
+        // As we are only using TapRoot inputs, we can do a lot better without too much difficulties
+        // We just have to find the "change" output
+        if let Some(fee_rate) = fee_rate {
+            let adjustable_output_index = if let Some(adjustable_output_index) = psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .position(|o| o.script_pubkey == drain_script)
+            {
+                adjustable_output_index
+            } else {
+                log::info!(
+                    "HeritageWallet::create_psbt - This psbt does not have change, adding one"
+                );
+                // We are in the remote possibility where we try to send exactly the right amount
+                // so that there is no need to change
+                // In that case just add the output, process the psbt, and see what happen
+                let drain_output = TxOut {
+                    value: 0,
+                    script_pubkey: drain_script.clone(),
+                };
+                // Insert it at a random position, like the other outputs, else it would
+                // always be the last one. BIP-69 outputs are sorted afterward anyway.
+                let adjustable_output_index = match options.output_ordering {
+                    OutputOrdering::Shuffle => {
+                        thread_rng().gen_range(0..=psbt.unsigned_tx.output.len())
+                    }
+                    OutputOrdering::Bip69 => psbt.unsigned_tx.output.len(),
+                };
+                psbt.unsigned_tx
+                    .output
+                    .insert(adjustable_output_index, drain_output);
+                psbt.outputs
+                    .insert(adjustable_output_index, Output::default());
+                adjustable_output_index
+            };
+
+            log::debug!("HeritageWallet::create_psbt - adjust_with_real_fee(psbt, {fee_rate:?}, {adjustable_output_index})");
+            let adjustment = adjust_with_real_fee(&mut psbt, &fee_rate, adjustable_output_index);
+            log::info!("HeritageWallet::create_psbt - Fee adjustment: {adjustment}");
+
+            // If the resulting amount is below dust treshold, just pop the output (and therefor give that amount to the miners)
+            if psbt.unsigned_tx.output[adjustable_output_index]
+                .value
+                .is_dust(&drain_script)
+            {
+                // In that case, the adjustment is 0 because the only way we are here
+                // is that we where in the case "remote possibility where we try to send exactly the right amount"
+                // and we added the output drain and it happens to be too small so we just go back to the old fee.
+                psbt.unsigned_tx.output.remove(adjustable_output_index);
+                psbt.outputs.remove(adjustable_output_index);
+            }
+        }
+        // The fee adjustment may have changed the amount of an output, so the BIP-69 order
+        // must be restored
+        if options.output_ordering == OutputOrdering::Bip69 {
+            sort_outputs_bip69(&mut psbt);
+        }
+
+        // Verify that the last recipient of an Heir receives at least the requested amount
+        if let Some(minimum) = heir_drain_minimum {
+            let drained = psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .find(|o| o.script_pubkey == drain_script)
+                .map(|o| o.value)
+                .unwrap_or(0);
+            if drained < minimum {
+                log::error!(
+                    "HeritageWallet::create_psbt - The last recipient would receive {drained} sat \
+                    instead of at least {minimum} sat"
+                );
+                return Err(Error::PsbtCreationError(format!(
+                    "Insufficient funds: {drained} sat available for the last recipient, \
+                    {minimum} sat needed"
+                )));
+            }
+        }
+
+        let fee_rate = fee_rate.map(|bdk_fee_rate| {
+            FeeRate::from_sat_per_vb_unchecked(bdk_fee_rate.as_sat_per_vb() as u64)
+        });
+        let tx_summary = self.create_transaction_summary(&psbt, fee_rate);
+
+        log::debug!("HeritageWallet::create_psbt - psbt={psbt:?}");
+        log::debug!("HeritageWallet::create_psbt - tx_summary={tx_summary:?}");
+        Ok((psbt, tx_summary))
+    }
+
+    /// Create the [TransactionSummary] of a freshly created [Psbt] that only contains owned inputs.
+    /// If `fee_rate` is [None], it is computed using the expected weight of the final transaction.
+    fn create_transaction_summary(
+        &self,
+        psbt: &Psbt,
+        fee_rate: Option<FeeRate>,
+    ) -> TransactionSummary {
+        // Our PSBT only contains owned inputs
+        // Adding all inputs into the owned_inputs Vec
+        let owned_inputs = psbt
+            .inputs
+            .iter()
+            .zip(psbt.unsigned_tx.input.iter())
+            .map(|(pi, ti)| {
+                let utxo = pi.witness_utxo.as_ref().expect("we only deal with Taproot");
+                TransactionSummaryOwnedIO {
+                    outpoint: ti.previous_output,
+                    address: CheckedAddress::from_script(
+                        utxo.script_pubkey.as_script(),
+                        self.network,
+                    )
+                    .expect("comes from the PSBT"),
+                    amount: Amount::from_sat(utxo.value),
+                }
+            })
+            .collect::<Vec<_>>();
+        // Compute the future TxId
+        let txid = psbt.unsigned_tx.txid();
+        // Creating the owned_outputs Vec
+        let owned_outputs = (0u32..)
+            .zip(psbt.unsigned_tx.output.iter())
+            .filter(|&(_, o)| self.is_mine(o.script_pubkey.as_script()).unwrap_or(false))
+            .map(|(i, o)| TransactionSummaryOwnedIO {
+                outpoint: OutPoint { txid, vout: i },
+                address: CheckedAddress::from_script(o.script_pubkey.as_script(), self.network)
+                    .expect("comes from the PSBT"),
+                amount: Amount::from_sat(o.value),
+            })
+            .collect::<Vec<_>>();
+
+        // Create the parent_ids
+        let parent_txids = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|i| i.previous_output.txid)
+            .collect();
+
+        let fee = psbt.fee().expect("our psbt is fresh and sound");
+        let fee_rate = fee_rate.unwrap_or_else(|| fee / get_expected_tx_weight(psbt));
+        // Create the TransactionSummary
+        TransactionSummary {
+            txid,
+            confirmation_time: None,
+            owned_inputs,
+            owned_outputs,
+            fee,
+            fee_rate,
+            parent_txids,
+            label: None,
+            address_reused: false,
+        }
+    }
+
+    fn get_conditions_and_utxos_for_subwallet(
+        &self,
+        subwallet_config: &SubwalletConfig,
+        spender: &Spender,
+        assume_blocktime: &BlockTime,
+        include_foreign_utxo: bool,
+    ) -> Result<
+        Option<(
+            Option<LockTime>,
+            Option<Sequence>,
+            Vec<(LocalUtxo, Option<(OutPoint, Input, usize)>)>,
+        )>,
+    > {
+        log::debug!(
+            "HeritageWallet::filter_subwallet_utxos - subwallet_config={subwallet_config:?} spender={spender:?} assume_blocktime={assume_blocktime:?}"
+        );
+
+        let heritage_explorer = if let Spender::Heir(heir_config) = &spender {
+            let he = subwallet_config
+                .heritage_config()
+                .get_heritage_explorer(heir_config);
+            if he.is_none() {
+                // If None, the Heir is not in this wallet
+                // So skip it
+                return Ok(None);
+            }
+            he
+        } else {
+            None
+        };
+
+        let spend_condition = heritage_explorer
+            .as_ref()
+            .map(|he| he.get_spend_conditions());
+
+        // At this point we KNOW that there is either a Some(spend_condition) or this is the owner spending
+        // If it is a Some (so an Heir is spending), verify that the timestamp is compatible with they spending
+        if let Some(sc) = &spend_condition {
+            // If too soon, the Heir cannot yet spend this wallet
+            // So skip it and return a None
+            if !sc.can_spend_at(assume_blocktime.timestamp) {
+                return Ok(None);
+            }
+        }
+
+        let (o_lock_time, o_sequence) = match &spend_condition {
+            Some(sc) => (
+                sc.get_spendable_timestamp().map(|ts| {
+                    LockTime::from_time(ts as u32)
+                        .expect("comes from HeritageConfig which check the value")
+                }),
+                sc.get_relative_block_lock()
+                    .map(|h| Sequence::from_height(h)),
+            ),
+            None => (None, None),
+        };
+
+        let subwallet = self.get_subwallet(&subwallet_config)?;
+        let unspent = subwallet
+            .list_unspent()
+            .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+
+        // Manually select UTXOs
+        let utxos_and_inputs = unspent
+        .into_iter()
+        // Filter to keep only UTXO spendable by the Spender
+        .filter(|utxo| {
+            // We want to get the confirmation time of the TX and verify that it is compatible
+            // with the HeritageConfig for the Heir, i.e. that the Heir will be able to spend the UTXO
+            // Their is two requirements:
+            //   1. timestamp.now() must be greater than the heritagedate for the heir
+            //   2. for each TX, blockheight < current_block_height + min_lock
+            if let Spender::Heir(_) = spender {
+                // Key-path-only change outputs cannot be spent by an Heir
+                if subwallet_config.has_keypath_change() && utxo.keychain == KeychainKind::Internal {
+                    return false;
+                }
+                // There is a spend_condition_tester, use it to filter TX
+                let tx = match subwallet.get_tx(&utxo.outpoint.txid, false) {
+                    Ok(Some(tx)) => tx,
+                    Ok(None) => {
+                        log::error!("HeritageWallet::create_psbt_for_subwallet - Database inconsistent for Subwallet ID={} No tx with in database for {utxo:?}", subwallet_config.subwallet_id());
+                        return false;
+                    }
+                    Err(e) => {
+                        log::error!("HeritageWallet::create_psbt_for_subwallet - Failed to retrieve tx for Subwallet ID={} and {utxo:?}: {e:#}", subwallet_config.subwallet_id());
+                        return false;
+                    }
+                };
+                let Some(tx_confirmation_time) = tx.confirmation_time else {
+                    return false;
+                };
+
+                // If the TX is not old enough, skip it
+                if tx_confirmation_time.height
+                    + spend_condition
+                        .as_ref()
+                        .expect("this is some when Spender::Heir")
+                        .get_relative_block_lock().unwrap_or(0)
+                        as u32
+                    > assume_blocktime.height
+                {
+                    return false;
+                }
+            }
+            true
+        })
+        .map(|utxo|{
+            if include_foreign_utxo {
+                let outpoint = utxo.outpoint;
+                let mut input = subwallet.get_psbt_input(utxo.clone(), None, true).map_err(|e| match e {
+                    bdk::Error::UnknownUtxo => {
+                        log::error!("Unexpected UnknownUtxo error: {e:#}");
+                        panic!("Unexpected UnknownUtxo error: {e:#}")
+                    }
+                    bdk::Error::MiniscriptPsbt(_) => Error::PsbtCreationError(e.to_string()),
+                    _ => DatabaseError::Generic(e.to_string()).into(),
+                })?;
+                minimize_psbt_input_for_spender(&mut input, heritage_explorer.as_ref());
+                let satisfaction_weight = subwallet
+                .get_descriptor_for_keychain(utxo.keychain)
+                .max_weight_to_satisfy()
+                .expect("our descriptors can always be satisfied");
+                Ok((utxo, Some((outpoint, input, satisfaction_weight))))
+            } else {
+                Ok((utxo, None))
+            }
+        }).filter_map(|r: Result<_>|{
+            match r {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    log::error!("HeritageWallet::create_psbt_for_subwallet - Failed to create PsbtInput for Subwallet ID={}: {e:#}", subwallet_config.subwallet_id());
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+        // If there are no applicable UTXO, don't bother sending a result
+        let result = if utxos_and_inputs.len() == 0 {
+            None
+        } else {
+            Some((o_lock_time, o_sequence, utxos_and_inputs))
+        };
+        log::debug!("HeritageWallet::filter_subwallet_utxos - result={result:?}");
+        Ok(result)
+    }
+
+    fn create_new_subwallet_config(
+        &self,
+        heritage_config: HeritageConfig,
+        old_subwallet_config: Option<SubwalletConfig>,
+        keypath_change: bool,
+    ) -> Result<()> {
+        log::debug!(
+            "HeritageWallet::create_new_subwallet_config - old_subwallet_config={old_subwallet_config:?}"
+        );
+        // If different, then we need to archive the old subwallet_config and create a new one
+        // With a new AccountXPub
+        let new_account_xpub = self
+            .database
+            .borrow()
+            .get_unused_account_xpub()?
+            .ok_or(Error::MissingUnusedAccountXPub)?;
+        log::debug!(
+            "HeritageWallet::update_heritage_config - new_account_xpub={new_account_xpub:?}"
+        );
+        let mut transaction = self.database.borrow().begin_transac();
+        transaction.delete_unused_account_xpub(&new_account_xpub)?;
+        let mut new_subwallet_config = SubwalletConfig::new(new_account_xpub, heritage_config);
+        if keypath_change {
+            new_subwallet_config = new_subwallet_config.with_keypath_change();
+        }
+        log::info!("HeritageWallet::update_heritage_config - Creating a new SubwalletConfig for the new HeritageConfig");
+        log::debug!(
+            "HeritageWallet::update_heritage_config - new_subwallet_config={new_subwallet_config:?}"
+        );
+        transaction.safe_update_current_subwallet_config(
+            &new_subwallet_config,
+            old_subwallet_config.as_ref(),
+        )?;
+        if let Some(old_subwallet_config) = old_subwallet_config {
+            transaction.put_subwallet_config(
+                SubwalletConfigId::Id(old_subwallet_config.subwallet_id()),
+                &old_subwallet_config,
+            )?;
+        }
+        self.database.borrow_mut().commit_transac(transaction)?;
+        Ok(())
+    }
+
+    /// Open the subwallets of every [SubwalletConfig], obsolete ones first and the current one last
+    fn get_all_subwallets(
+        &self,
+    ) -> Result<
+        Vec<(
+            Wallet<<D as PartitionableDatabase>::SubDatabase>,
+            SubwalletConfig,
+        )>,
+    > {
+        let mut subwallet_configs = self.database.borrow().list_obsolete_subwallet_configs()?;
+        subwallet_configs.extend(
+            self.database
+                .borrow()
+                .get_subwallet_config(SubwalletConfigId::Current)?,
+        );
+        subwallet_configs
+            .into_iter()
+            .map(|swc| Ok((self.get_subwallet(&swc)?, swc)))
+            .collect()
+    }
+
+    /// Find the [SubwalletConfig] and the [KeychainKind] of the subwallet owning `script`, if any
+    fn find_script_owner<'a>(
+        subwallets: &'a [(
+            Wallet<<D as PartitionableDatabase>::SubDatabase>,
+            SubwalletConfig,
+        )],
+        script: &Script,
+    ) -> Result<Option<(&'a SubwalletConfig, KeychainKind)>> {
+        for (subwallet, swc) in subwallets {
+            if let Some((keychain, _)) = subwallet
+                .database()
+                .get_path_from_script_pubkey(script)
+                .map_err(|e| DatabaseError::Generic(e.to_string()))?
+            {
+                return Ok(Some((swc, keychain)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_subwallet(
+        &self,
+        subwalletconfig: &SubwalletConfig,
+    ) -> Result<Wallet<<D as PartitionableDatabase>::SubDatabase>> {
+        log::debug!("HeritageWallet::get_subwallet - Opening subwallet database");
+        let subdatabase = self
+            .database
+            .borrow()
+            .get_subdatabase(SubdatabaseId::from(subwalletconfig.subwallet_id()))?;
+        log::debug!("HeritageWallet::get_subwallet - Creating subwallet");
+        Ok(subwalletconfig.get_subwallet(subdatabase, self.network))
+    }
+
+    fn internal_get_new_address(&self, keychain_kind: KeychainKind) -> Result<AddressInfo> {
+        log::debug!("HeritageWallet::internal_get_new_address - keychain_kind={keychain_kind:?}");
+
+        let current_subwallet_config = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .ok_or(Error::MissingCurrentSubwalletConfig)?;
+        log::debug!("HeritageWallet::internal_get_new_address - current_subwallet_config={current_subwallet_config:?}");
+
+        // Verify if the subwallet_config has not already been used, just override it
+        if current_subwallet_config.subwallet_firstuse_time().is_none() {
+            log::debug!(
+                "HeritageWallet::internal_get_new_address - current_subwallet_config was never used"
+            );
+            let mut new_current_subwallet_config = current_subwallet_config.clone();
+            new_current_subwallet_config.mark_subwallet_firstuse()?;
+            log::info!("HeritageWallet::internal_get_new_address - Marking previously unused SubwalletConfig as used");
+            log::debug!(
+                "HeritageWallet::internal_get_new_address - new_current_subwallet_config={new_current_subwallet_config:?}"
+            );
+            self.database
+                .borrow_mut()
+                .safe_update_current_subwallet_config(
+                    &new_current_subwallet_config,
+                    Some(&current_subwallet_config),
+                )?;
+        }
+        log::debug!("HeritageWallet::internal_get_new_address - get_subwallet");
+        let subwallet = self.get_subwallet(&current_subwallet_config)?;
+
+        let address = match keychain_kind {
+            KeychainKind::External => {
+                log::debug!("HeritageWallet::internal_get_new_address - subwallet.get_address");
+                subwallet
+                    .get_address(AddressIndex::New)
+                    .map_err(|e| match e {
+                        bdk::Error::ScriptDoesntHaveAddressForm => Error::Unknown(
+                            "ScriptDoesntHaveAddressForm: Invalid script retrieved from database"
+                                .to_owned(),
+                        ),
+                        _ => DatabaseError::Generic(e.to_string()).into(),
+                    })?
+            }
+            KeychainKind::Internal => {
+                log::debug!(
+                    "HeritageWallet::internal_get_new_address - subwallet.get_internal_address"
+                );
+                subwallet
+                    .get_internal_address(AddressIndex::New)
+                    .map_err(|e| match e {
+                        bdk::Error::ScriptDoesntHaveAddressForm => Error::Unknown(
+                            "ScriptDoesntHaveAddressForm: Invalid script retrieved from database"
+                                .to_owned(),
+                        ),
+                        _ => DatabaseError::Generic(e.to_string()).into(),
+                    })?
+            }
+        };
+        log::debug!("HeritageWallet::internal_get_new_address - address={address:?}");
+        Ok(address)
+    }
+}
+
+/// Take a mutable reference to a [Input] and make sure that it does not contains superfluous
+/// informations that are not needed by the intended spender.
+/// The idea is to remove from the [Input] all the scripts and key paths that are
+/// not relevant for the spender.
+/// It also enhence privacy, because Heirs will not be able to know who are
+/// the other Heirs from the Psbt. At most, an Heir can infere how many Heirs were
+/// before him by looking at the number of merkle_branches necessary for them to
+/// create their transaction because of the way we create the MAST
+/// (see comments in [HeritageConfig]::descriptor_segment())
+///
+/// This function SHOULD become largelly useless once [bdk] starts using miniscript v11.0
+/// because it introduces a clean way to generate a minimized Psbt from the get-go, as well
+/// as a feature to better compute the weight of the future transaction (currently, [bdk] uses
+/// the worst-case weight, meaning eveybody pays the fee as if they were the last Heir, not ideal)
+fn minimize_psbt_input_for_spender(
+    psbt_input: &mut Input,
+    heritage_explorer: Option<&HeritageExplorer>,
+) {
+    log::debug!("minimize_psbt_for_spender - heritage_explorer={heritage_explorer:?}");
+    match heritage_explorer {
+        // This is the owner spending with a quorum of keys
+        None if psbt_input
+            .tap_internal_key
+            .is_some_and(|k| k.to_string() == SubwalletConfig::UNSPENDABLE_INTERNAL_KEY) =>
+        {
+            // Keeps only the owner quorum leaf, the only script without any time lock
+            psbt_input.tap_scripts.retain(|_, (s, _)| {
+                !s.instructions().any(|instruction| {
+                    matches!(instruction, Ok(Instruction::Op(op)) if op == OP_CLTV || op == OP_CSV)
+                })
+            });
+            let owner_leaf_hashes = psbt_input
+                .tap_scripts
+                .values()
+                .map(|(s, leaf_version)| TapLeafHash::from_script(s, *leaf_version))
+                .collect::<Vec<_>>();
+            // Then remove every Key that is not part of the owner quorum leaf
+            psbt_input.tap_key_origins.retain(|_, (leaf_hashes, _)| {
+                leaf_hashes.retain(|leaf_hash| owner_leaf_hashes.contains(leaf_hash));
+                !leaf_hashes.is_empty()
+            });
+        }
+        // This is the owner spending
+        None => {
+            // With the owner it is simple: simply clean the scripts
+            psbt_input.tap_scripts.clear();
+            // Then remove every Key that is not the tap_internal_key
+            psbt_input.tap_key_origins.retain(|k, _| {
+                *k == psbt_input
+                    .tap_internal_key
+                    .expect("this is a taproot input")
+            })
+        }
+        // This is an Heir spending
+        Some(heritage_explorer) => {
+            // Keeps only the relevant keys
+            psbt_input
+                .tap_key_origins
+                .retain(|_, (_, (fingerprint, _))| heritage_explorer.has_fingerprint(*fingerprint));
+            // Create a Vec of the retained origins
+            let origins = psbt_input
+                .tap_key_origins
+                .iter()
+                .map(|(_, (_, (fingerprint, derivation_path)))| (fingerprint, derivation_path));
+            // Use the origins to ask for a concrete Script
+            let script = heritage_explorer.get_script(origins);
+            // Keeps only the relevant script
+            psbt_input.tap_scripts.retain(|_, (s, _)| *s == script);
+        }
+    }
+}
+
+/// Sort the outputs of `psbt` by amount, then by script, as specified by BIP-69
+fn sort_outputs_bip69(psbt: &mut Psbt) {
+    let mut outputs = core::mem::take(&mut psbt.unsigned_tx.output)
+        .into_iter()
+        .zip(core::mem::take(&mut psbt.outputs))
+        .collect::<Vec<_>>();
+    outputs.sort_by(|(a, _), (b, _)| {
+        a.value
+            .cmp(&b.value)
+            .then_with(|| a.script_pubkey.cmp(&b.script_pubkey))
+    });
+    (psbt.unsigned_tx.output, psbt.outputs) = outputs.into_iter().unzip();
+}
+
+/// Take a mutable reference to a [Psbt] and compute the exact expected weight of the final transaction.
+/// It is possible to do so relatively easily since:
+/// 1. the [Psbt] is for a Taproot SegWit TX
+/// 2. We already minimized each [Input] down to a single spend-path so that we can easily see if it is
+/// a key-path or script-path and in the later case, we know what will be the size of the control block
+/// by infering the depth of the script in the MAST.
+///
+/// After that, we use the given [FeeRate] to compute the Fee and adjust the amount of the
+/// [bdk::bitcoin::psbt::Output] pointed by `adjustable_output_index`.
+///
+/// This function MAY become largelly useless once [bdk] starts using miniscript v11.0
+/// because it introduces a clean way to generate a minimized Psbt from the get-go, as well
+/// as a feature to better compute the weight of the future transaction (currently, [bdk] uses
+/// the worst-case weight, meaning eveybody pays the fee as if they were the last Heir, not ideal)
+///
+/// But on the other hand, bdk is really doing kind-of a shitty job computing the WU of the future TX:
+/// 1. It uses a wrong [bdk::wallet::coin_selection::TXIN_BASE_WEIGHT] because it forget that the absence of signature
+/// must still be signaled by setting sig_length to 0, which consumme 1 byte / 4 WU. The weight of the TX is thus
+/// underestimated by 4WU/input
+/// 2. On the other hand, it uses the obsolete [Tr::max_satisfaction_weight] which compensate that
+/// 3. On the other other hand, it always assume a witness_stack size of the worst-case scenario, meaning it uses the size
+/// the witness stack would have by spending the deepest/longest script in the MAST. In case of a Key-path spend
+/// it ugely overestimates the TX weight, in case of script-path spend, it depends on the script we actually intend to
+/// use but tend to overestimate also.
+///
+/// All in all, that makes the TX weight often wrong and thus the Fee too.
+///
+/// # Returns
+/// Return an i64 telling the amount of adjustment made to the adjustable_output
+///
+/// # Panics
+/// Panics if the [Psbt] contains [Input] that are not Taproot, or if the Psbt Inputs where
+/// not minimized (see [minimize_psbt_input_for_spender])
+fn adjust_with_real_fee(
+    psbt: &mut Psbt,
+    fee_rate: &BdkFeeRate,
+    adjustable_output_index: usize,
+) -> i64 {
+    log::debug!(
+        "adjust_with_real_fee - psbt={psbt:?} fee_rate={fee_rate:?} \
+        adjustable_output_index={adjustable_output_index}"
+    );
+
+    let expected_weight = get_expected_tx_weight(&psbt);
+    log::debug!("adjust_with_real_fee - expected_weight={expected_weight:#}");
+    log::debug!(
+        "adjust_with_real_fee - expected_weight={} vB (ceil)",
+        expected_weight.to_vbytes_ceil()
+    );
+
+    // Compute the new_fee using the expected_weight and the fee_rate
+    let new_fee = fee_rate.fee_wu(expected_weight);
+    let current_fee = psbt
+        .fee()
+        .expect("the PSBT is assumed to be valid")
+        .to_sat();
+
+    log::debug!("adjust_with_real_fee - current_fee={current_fee} sat; new_fee={new_fee} sat");
+    // Compute the change we need to the adjustable_output to get the correct fee
+    // In case we have to lower the amount, we could endup having a amount <0
+    // or a Dust amount. In either case, warn and do nothing
+    if new_fee > current_fee {
+        // We need to lower the amount in adjustable_output to increase the fee
+        let adjustment = new_fee - current_fee;
+        if let Some(new_amount) = psbt.unsigned_tx.output[adjustable_output_index]
+            .value
+            .checked_sub(adjustment)
+        {
+            if new_amount.is_dust(&psbt.unsigned_tx.output[adjustable_output_index].script_pubkey)
+                && new_amount != 0
+            {
+                log::warn!(
+                    "adjust_with_real_fee - current_fee={current_fee} is lower than the \
+                new_fee={new_fee} but the resulting new_amount {new_amount} for the \
+                adjustable_output would be dust. Do nothing."
+                );
+                0
+            } else {
+                psbt.unsigned_tx.output[adjustable_output_index].value = new_amount;
+                -(adjustment as i64)
+            }
+        } else {
+            log::warn!(
+                "adjust_with_real_fee - current_fee={current_fee} is lower than the \
+            new_fee={new_fee} but the resulting new_amount for the adjustable_output \
+            would be negative. Do nothing."
+            );
+            0
+        }
+    } else {
+        let adjustment = current_fee - new_fee;
+        psbt.unsigned_tx.output[adjustable_output_index].value += adjustment;
+        adjustment as i64
+    }
+}
+
+/// List the owned addresses receiving payments from more than one of the `tx_summaries`,
+/// with the [Txid]s of those payments, in the order of `tx_summaries`
+fn reused_addresses<'a>(
+    tx_summaries: impl Iterator<Item = &'a TransactionSummary>,
+) -> Vec<(CheckedAddress, Vec<Txid>)> {
+    let mut payments: Vec<(&CheckedAddress, Vec<Txid>)> = Vec::new();
+    let mut index: HashMap<&CheckedAddress, usize> = HashMap::new();
+    for tx_summary in tx_summaries {
+        for owned_output in tx_summary.owned_outputs.iter() {
+            let i = *index.entry(&owned_output.address).or_insert_with(|| {
+                payments.push((&owned_output.address, Vec::new()));
+                payments.len() - 1
+            });
+            let txids = &mut payments[i].1;
+            // Several outputs of the same transaction count as a single payment
+            if !txids.contains(&tx_summary.txid) {
+                txids.push(tx_summary.txid);
+            }
+        }
+    }
+    payments
+        .into_iter()
+        .filter(|(_, txids)| txids.len() > 1)
+        .map(|(address, txids)| (address.clone(), txids))
+        .collect()
+}
+
+pub fn get_expected_tx_weight(psbt: &Psbt) -> Weight {
+    log::debug!("get_expected_tx_weight - psbt={psbt}");
+    // Put some barriers so we do not misuses this
+    // Ensure this is a pure Taproot Psbt
+    assert!(psbt
+        .inputs
+        .iter()
+        .all(|input| input.tap_internal_key.is_some() && input.tap_merkle_root.is_some()));
+
+    // The [Weight] is the addition of:
+    // - The TX weight (without any script_sig or witness at this point)
+    // - The 2 additionnal WU coming from the segwit format (marker + flag)
+    // - Expected witness and sig size for each input
+
+    // TX weight given by rust-bitcoin implementation, nice
+    let mut expected_weight = psbt.unsigned_tx.weight();
+    // Fixed addition of the 2 additionnal WU coming from the segwit format
+    expected_weight += Weight::from_wu(2);
+    // Expected witness and sig size for each input
+    expected_weight += psbt
+        .inputs
+        .iter()
+        .map(|input| {
+            match input.tap_scripts.len() {
+                // Code here is copied from <rust-miniscript 10.0.0>/src/descriptor/tr.rs
+                // {} impl Tr<Pk>::max_weight_to_satisfy(&self)
+                0 => {
+                    // key spend path
+                    // item: varint(sig+sigHash) + <sig(64)+sigHash(1)>
+                    let item_sig_size = 1 + 65;
+                    // 1 stack item
+                    // let stack_varint_diff = varint_len(1) - varint_len(0); // Always 0
+
+                    item_sig_size
+                }
+                1 => {
+                    // Script spend
+                    let (ctr_block, (script, _)) = input.tap_scripts.first_key_value().unwrap();
+                    let miniscript: Miniscript<_, Tap> = Miniscript::parse(script).unwrap();
+                    let script_size = miniscript.script_size();
+                    let max_sat_elems = miniscript
+                        .max_satisfaction_witness_elements()
+                        .expect("Our Miniscript are satisfyable");
+                    let max_sat_size = miniscript
+                        .max_satisfaction_size()
+                        .expect("Our Miniscript are satisfyable");
+                    let control_block_size = ctr_block.size();
+
+                    // stack varint difference (+1 for ctrl block, witness script already included)
+                    let stack_varint_diff = varint_len(max_sat_elems + 1) - varint_len(0);
+
+                    stack_varint_diff +
+                        // size of elements to satisfy script
+                        max_sat_size +
+                        // second to last element: script
+                        varint_len(script_size) +
+                        script_size +
+                        // last element: control block
+                        varint_len(control_block_size) +
+                        control_block_size
+                }
+                _ => panic!("Psbt input is not minimized"),
+            }
+        })
+        .fold(Weight::ZERO, |acc, iw| {
+            acc + Weight::from_witness_data_size(iw as u64)
+        });
+    expected_weight
+}
+
+// Helper function to calculate witness size
+// copied from <rust-miniscript 10.0.0>/src/utils.rs
+fn varint_len(n: usize) -> usize {
+    bdk::bitcoin::VarInt(n as u64).len()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use core::{cell::RefCell, str::FromStr};
+    use std::collections::{hash_map::RandomState, BTreeSet, HashMap, HashSet};
+
+    use bdk::{
+        blockchain::{
+            noop_progress, Blockchain, BlockchainFactory, Capability, GetBlockHash, GetHeight,
+            GetTx, Progress, WalletSync,
+        },
+        database::{BatchDatabase, BatchOperations, SyncTime},
+        Balance, BlockTime, Error, FeeRate, KeychainKind, LocalUtxo, TransactionDetails,
+    };
+
+    use crate::{
+        bitcoin::{
+            absolute::LockTime,
+            bip32::{DerivationPath, Fingerprint},
+            secp256k1::XOnlyPublicKey,
+            taproot::TapNodeHash,
+            Amount, BlockHash, Network, OutPoint, Sequence, Transaction, Txid,
+        },
+        database::{
+            memory::HeritageMemoryDatabase, HeritageDatabase, PartitionableDatabase, SubdatabaseId,
+            TransacHeritageOperation,
+        },
+        heritage_config::v1::OwnerQuorum,
+        heritage_wallet::{
+            backup::{
+                CoreImportTimestamp, HeritageWalletBackup, SubwalletDescriptorBackup, WalletExport,
+            },
+            bip329::{Bip329Label, Bip329Labels, Bip329Ref},
+            get_expected_tx_weight,
+            online::{fee_estimator::FeeEstimator, SyncStrategy},
+            BlockInclusionObjective, CheckedAddress, CoinSelectionPolicy, CreatePsbtOptions,
+            EligibilityChange, GapLimit, HeritageWallet, HeritageWalletBalance, LabelRef,
+            LockTimePolicy, OutputOrdering, Recipient, RotationPolicy, RotationStatus,
+            SpendingConfig, SubwalletConfigId, UtxoSelection, WalletAddress,
+            INCREMENTAL_RELAY_FEE_RATE,
+        },
+        miniscript::{Descriptor, DescriptorPublicKey},
+        subwallet_config::SubwalletConfig,
+        tests::*,
+        utils::extract_tx,
+        HeirConfig, HeritageConfig,
+    };
+
+    use super::FeePolicy;
+
+    #[derive(Debug, Clone)]
+    pub struct FakeBlockchain {
+        pub current_height: BlockTime,
+        pub transactions: Vec<TransactionDetails>,
+    }
+
+    impl GetHeight for FakeBlockchain {
+        fn get_height(&self) -> Result<u32, Error> {
+            Ok(self.current_height.height)
+        }
+    }
+    impl GetTx for FakeBlockchain {
+        fn get_tx(&self, _txid: &Txid) -> Result<Option<Transaction>, Error> {
+            Err(Error::Generic("Unimplemented".to_owned()))
+        }
+    }
+    impl GetBlockHash for FakeBlockchain {
+        fn get_block_hash(&self, _height: u64) -> Result<BlockHash, Error> {
+            Err(Error::Generic("Unimplemented".to_owned()))
+        }
+    }
+    impl WalletSync for FakeBlockchain {
+        fn wallet_setup<D: BatchDatabase>(
+            &self,
+            database: &RefCell<D>,
+            _progress_update: Box<dyn Progress>,
+        ) -> Result<(), Error> {
+            for tx_details in &self.transactions {
+                database.borrow_mut().set_tx(tx_details)?;
+                database.borrow_mut().set_utxo(&LocalUtxo {
+                    txout: tx_details.transaction.as_ref().unwrap().output[0].clone(),
+                    outpoint: OutPoint {
+                        txid: tx_details.txid.clone(),
+                        vout: 0,
+                    },
+                    keychain: KeychainKind::External,
+                    is_spent: false,
+                })?;
+            }
+            database.borrow_mut().set_sync_time(SyncTime {
+                block_time: self.current_height.clone(),
+            })?;
+            Ok(())
+        }
+    }
+    impl Blockchain for FakeBlockchain {
+        fn get_capabilities(&self) -> HashSet<Capability> {
+            [
+                Capability::FullHistory,
+                Capability::AccurateFees,
+                Capability::GetAnyTx,
+            ]
+            .into()
+        }
+
+        fn broadcast(&self, _tx: &Transaction) -> Result<(), Error> {
+            Err(Error::Generic("Unimplemented".to_owned()))
+        }
+
+        fn estimate_fee(&self, _target: usize) -> Result<FeeRate, Error> {
+            Ok(FeeRate::from_sat_per_vb(10.0))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct FakeBlockchainFactory {
+        pub current_height: BlockTime,
+    }
+    impl BlockchainFactory for FakeBlockchainFactory {
+        type Inner = FakeBlockchain;
+
+        fn build(
+            &self,
+            wallet_name: &str,
+            _override_skip_blocks: Option<u32>,
+        ) -> Result<Self::Inner, bdk::Error> {
+            let mut hashtable: HashMap<String, Vec<TransactionDetails>> = HashMap::new();
+            // Wallet TestHeritageConfig::BackupWifeY2
+            hashtable.insert("7y7nqca9j84snf2h".to_owned(), vec![
+                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"51208bdbdb2969eeb7ec8efd20f7bc64961a760313404e900109a1ba19afb8b0292c"}]},"txid":"344dbc396e3c6945f46a67faab275141bb0fdd63f8a46362ba27e4753400d9c2","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":842520,"timestamp":1715552000}}"#).unwrap(),
+                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"51204eff92d75cc954964dfe21755cf3063abbe19aa0e6fcacf429af996fc0f65beb"}]},"txid":"d2f3bd44fb6ad0c32833ea943d718e806245e632302f25720811fea167c13507","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":904440,"timestamp":1752704000}}"#).unwrap(),
+            ]);
+            // Wallet TestHeritageConfig::BackupWifeY1
+            hashtable.insert("0hqx0prur5t9us5w".to_owned(), vec![
+                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"5120a6d2ae7fb6a453f32d1d32ffdfc31f3303a7704bcf16ff4ebabe0e26686ec687"}]},"txid":"2f0a77d510db56dda3b43692d4658a92f523193a3b854d2387681f2fd0f5d920","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":895080,"timestamp":1747088000}}"#).unwrap(),
+                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"5120f0c155ea564bd2fc6d45f654a68d2734b762a947d93c4cf6e2fdda0260ee89d9"}]},"txid":"3854db1cb2253a270e49a093a6ddb92fa79efd8b295568e08448e4de678fc08b","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":897960,"timestamp":1748816000}}"#).unwrap(),
+            ]);
+            // Wallet TestHeritageConfig::BackupWifeBro
+            hashtable.insert("9lwn0wm9mh7ydv64".to_owned(), vec![
+                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"5120d1756b2e88a51fc63f156ef0ba3a3cfd126206bfb96347f1b4ccb15f9b75e14f"}]},"txid":"6ed1563a936196211f2f76447c478533df8f3efc43933f4c3405b9a760b31204","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":923160,"timestamp":1763936000}}"#).unwrap(),
+            ]);
+
+            Ok(FakeBlockchain {
+                current_height: self.current_height.clone(),
+                transactions: hashtable
+                    .get(wallet_name)
+                    .map(Clone::clone)
+                    .unwrap_or_default(),
+            })
+        }
+    }
+
+    fn setup_wallet() -> HeritageWallet<HeritageMemoryDatabase> {
+        setup_wallet_with_current_subwallet_config(get_default_test_subwallet_config(
+            TestHeritageConfig::BackupWifeBro,
+        ))
+    }
+
+    fn setup_wallet_with_current_subwallet_config(
+        current_subwallet_config: SubwalletConfig,
+    ) -> HeritageWallet<HeritageMemoryDatabase> {
+        setup_wallet_with_subwallet_configs(
+            [
+                get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY2),
+                get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY1),
+            ],
+            current_subwallet_config,
+        )
+    }
+
+    fn setup_wallet_with_subwallet_configs(
+        obsolete_subwallet_configs: [SubwalletConfig; 2],
+        current_subwallet_config: SubwalletConfig,
+    ) -> HeritageWallet<HeritageMemoryDatabase> {
+        let mut db = HeritageMemoryDatabase::new();
+
+        // Account descriptors
+        let unused_axps = (3..10)
+            .into_iter()
+            .map(|i| get_test_account_xpub(i))
+            .collect();
+        db.add_unused_account_xpubs(&unused_axps).unwrap();
+
+        // Wallet subconfigs
+        for (i, swc) in obsolete_subwallet_configs.iter().enumerate() {
+            db.put_subwallet_config(
+                SubwalletConfigId::Id(i as crate::subwallet_config::SubwalletId),
+                swc,
+            )
+            .unwrap();
+        }
+        db.put_subwallet_config(SubwalletConfigId::Current, &current_subwallet_config)
+            .unwrap();
+
+        let wallet = HeritageWallet::new_with_network(db, Network::Regtest).unwrap();
+        wallet
+            .sync(&FakeBlockchainFactory {
+                current_height: get_present(),
+            })
+            .unwrap();
+
+        wallet
+    }
+
+    fn get_present() -> BlockTime {
+        get_blocktime_for_timestamp(
+            get_absolute_inheritance_timestamp(
+                TestHeritageConfig::BackupWifeY1,
+                TestHeritage::Backup,
+            ) + 86400 * 15, // 15 days after backup can inherit, therefore wife cannot yet.
+        )
+    }
+
+    #[test]
+    fn get_balance() {
+        let wallet = setup_wallet();
+
+        let expected_balance = HeritageWalletBalance::new(
+            Balance {
+                confirmed: 100_000_000,
+                ..Default::default()
+            },
+            Balance {
+                confirmed: 400_000_000,
+                ..Default::default()
+            },
+        );
+        assert_eq!(wallet.get_balance().unwrap(), expected_balance);
+    }
+
+    #[test]
+    fn fingerprint() {
+        // Test on an empty wallet
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        // An empty wallet does not have a fingerprint
+        assert!(wallet.fingerprint().is_ok_and(|f| f.is_none()));
+
+        // Test on an non-empty wallet
+        let wallet = setup_wallet();
+        // A non-empty wallet does have a fingerprint
+        assert_eq!(
+            wallet.fingerprint().unwrap(),
+            Some(
+                get_test_account_xpub(0)
+                    .descriptor_public_key()
+                    .master_fingerprint()
+            )
+        );
+    }
+
+    #[test]
+    fn list_used_account_xpubs() {
+        let wallet = setup_wallet();
+        let expected = (0..3)
+            .into_iter()
+            .map(|i| get_test_account_xpub(i))
+            .collect::<Vec<_>>();
+        assert_eq!(wallet.list_used_account_xpubs().unwrap(), expected)
+    }
+
+    #[test]
+    fn generate_backup() {
+        let wallet = setup_wallet();
+        // To have a last_external_index on the last backup
+        let _ = wallet.get_new_address().unwrap();
+        // We expect the values set in the tests mod of lib.rs
+        let expected = HeritageWalletBackup(vec![
+            SubwalletDescriptorBackup {
+                external_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                    get_default_test_subwallet_config_expected_external_descriptor(
+                        TestHeritageConfig::BackupWifeY2,
+                    ),
+                )
+                .unwrap(),
+                change_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                    get_default_test_subwallet_config_expected_change_descriptor(
+                        TestHeritageConfig::BackupWifeY2,
+                    ),
+                )
+                .unwrap(),
+                first_use_ts: get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY2)
+                    .subwallet_firstuse_time(),
+                last_external_index: None,
+                last_change_index: None,
+                keypath_change_descriptor: None,
+            },
+            SubwalletDescriptorBackup {
+                external_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                    get_default_test_subwallet_config_expected_external_descriptor(
+                        TestHeritageConfig::BackupWifeY1,
+                    ),
+                )
+                .unwrap(),
+                change_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                    get_default_test_subwallet_config_expected_change_descriptor(
+                        TestHeritageConfig::BackupWifeY1,
+                    ),
+                )
+                .unwrap(),
+                first_use_ts: get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY1)
+                    .subwallet_firstuse_time(),
+                last_external_index: None,
+                last_change_index: None,
+                keypath_change_descriptor: None,
+            },
+            SubwalletDescriptorBackup {
+                external_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                    get_default_test_subwallet_config_expected_external_descriptor(
+                        TestHeritageConfig::BackupWifeBro,
+                    ),
+                )
+                .unwrap(),
+                change_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
+                    get_default_test_subwallet_config_expected_change_descriptor(
+                        TestHeritageConfig::BackupWifeBro,
+                    ),
+                )
+                .unwrap(),
+                first_use_ts: get_default_test_subwallet_config(TestHeritageConfig::BackupWifeBro)
+                    .subwallet_firstuse_time(),
+                last_external_index: Some(0),
+                last_change_index: None,
+                keypath_change_descriptor: None,
+            },
+        ]);
+        assert_eq!(wallet.generate_backup().unwrap(), expected)
+    }
+
+    #[test]
+    fn export_core_descriptors() {
+        let wallet = setup_wallet();
+        // To have a last_external_index on the current subwallet
+        let _ = wallet.get_new_address().unwrap();
+        let backup = wallet.generate_backup().unwrap();
+        let exports = wallet.export_core_descriptors().unwrap();
+
+        // External and change descriptors for each subwallet
+        assert_eq!(exports.len(), 2 * backup.0.len());
+        for (sdb, exports) in backup.0.iter().zip(exports.chunks(2)) {
+            assert_eq!(exports[0].desc, sdb.external_descriptor.to_string());
+            assert!(!exports[0].internal);
+            assert_eq!(exports[1].desc, sdb.change_descriptor.to_string());
+            assert!(exports[1].internal);
+            for export in exports {
+                assert_eq!(
+                    export.timestamp,
+                    CoreImportTimestamp::Time(sdb.first_use_ts.unwrap())
+                );
+            }
+        }
+        // Only the current subwallet is active
+        let (obsolete, current) = exports.split_at(exports.len() - 2);
+        assert!(obsolete
+            .iter()
+            .all(|export| !export.active && export.next_index.is_none()));
+        assert!(current.iter().all(|export| export.active));
+        assert_eq!(current[0].next_index, Some(1));
+        assert_eq!(current[0].range, (0, 1001));
+        assert_eq!(current[1].next_index, Some(0));
+        assert_eq!(current[1].range, (0, 1000));
+
+        // The JSON is what Bitcoin Core expects
+        let json = serde_json::to_value(&current[1]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "desc": backup.0.last().unwrap().change_descriptor.to_string(),
+                "timestamp": backup.0.last().unwrap().first_use_ts.unwrap(),
+                "active": true,
+                "internal": true,
+                "range": [0, 1000],
+                "next_index": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn export_wallet() {
+        let wallet = setup_wallet();
+        let export = wallet.export_wallet("Heritage").unwrap();
+        assert_eq!(
+            export,
+            WalletExport {
+                label: "Heritage".to_owned(),
+                // The only transaction of the current subwallet in the FakeBlockchain
+                blockheight: 923160,
+                descriptor: get_default_test_subwallet_config_expected_external_descriptor(
+                    TestHeritageConfig::BackupWifeBro
+                )
+                .to_owned(),
+            }
+        );
+        // The JSON has the expected fields
+        let json = serde_json::to_value(&export).unwrap();
+        assert_eq!(json["label"], "Heritage");
+        assert_eq!(json["blockheight"], 923160);
+        assert!(json["descriptor"].is_string());
+
+        // No current subwallet
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        assert!(matches!(
+            wallet.export_wallet("Heritage"),
+            Err(crate::errors::Error::MissingCurrentSubwalletConfig)
+        ));
+    }
+
+    #[test]
+    fn restore_backup() {
+        let wallet = setup_wallet();
+        // To have a last_external_index on the last backup
+        let _ = wallet.get_new_address().unwrap();
+
+        // We expect that if we backup and then restore (i.e. duplicates) the wallet,
+        // we will have effectively the same wallet (same balance, same addresses, etc...)
+
+        let new_wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        let backup = wallet.generate_backup().unwrap();
+
+        // Restoration goes ok
+        let r = new_wallet.restore_backup(backup);
+        assert!(r.is_ok(), "{}", r.err().unwrap());
+        // Nothing is left in the journal
+        assert!(new_wallet
+            .database()
+            .get_pending_operation()
+            .unwrap()
+            .is_none());
+
+        // New address from both wallet are the same
+        assert_eq!(
+            wallet.get_new_address().unwrap(),
+            new_wallet.get_new_address().unwrap()
+        );
+
+        // Sync
+        new_wallet
+            .sync(&FakeBlockchainFactory {
+                current_height: get_present(),
+            })
+            .unwrap();
+
+        // Balance from both wallet are the same
+        assert_eq!(
+            wallet.get_balance().unwrap(),
+            new_wallet.get_balance().unwrap()
+        );
+
+        // Trying to restore another time should fail
+        assert!(new_wallet
+            .restore_backup(wallet.generate_backup().unwrap())
+            .is_err());
+        assert!(new_wallet
+            .database()
+            .get_pending_operation()
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn resume_pending_operation() {
+        use journal::{PendingOperation, SubwalletAddressIndexes};
+
+        let swc = get_default_test_subwallet_config(TestHeritageConfig::BackupWifeBro);
+        let pending_operation = PendingOperation::RestoreBackup {
+            address_indexes: vec![SubwalletAddressIndexes {
+                subwallet_id: swc.subwallet_id(),
+                last_external_index: Some(5),
+                last_change_index: None,
+            }],
+        };
+
+        // Interrupted before the SubwalletConfigs were written: rolled back
+        let mut db = HeritageMemoryDatabase::new();
+        db.set_pending_operation(&pending_operation).unwrap();
+        let wallet = HeritageWallet::new_with_network(db, Network::Regtest).unwrap();
+        assert!(wallet.database().get_pending_operation().unwrap().is_none());
+        assert!(wallet.get_current_heritage_config().unwrap().is_none());
+
+        // Interrupted after the SubwalletConfigs were written: resumed
+        let mut db = HeritageMemoryDatabase::new();
+        db.put_subwallet_config(SubwalletConfigId::Current, &swc)
+            .unwrap();
+        db.set_pending_operation(&pending_operation).unwrap();
+        let wallet = HeritageWallet::new_with_network(db, Network::Regtest).unwrap();
+        assert!(wallet.database().get_pending_operation().unwrap().is_none());
+        let subdatabase = wallet
+            .database()
+            .get_subdatabase(SubdatabaseId::from(swc.subwallet_id()))
+            .unwrap();
+        assert_eq!(
+            subdatabase.get_last_index(KeychainKind::External).unwrap(),
+            Some(5)
+        );
+        assert_eq!(
+            subdatabase.get_last_index(KeychainKind::Internal).unwrap(),
+            None
+        );
+
+        // Address indexes are never moved backward
+        wallet
+            .database
+            .borrow_mut()
+            .set_pending_operation(&PendingOperation::RestoreBackup {
+                address_indexes: vec![SubwalletAddressIndexes {
+                    subwallet_id: swc.subwallet_id(),
+                    last_external_index: Some(2),
+                    last_change_index: Some(1),
+                }],
+            })
+            .unwrap();
+        assert!(wallet.resume_pending_operation().unwrap().is_some());
+        assert_eq!(
+            subdatabase.get_last_index(KeychainKind::External).unwrap(),
+            Some(5)
+        );
+        assert_eq!(
+            subdatabase.get_last_index(KeychainKind::Internal).unwrap(),
+            Some(1)
+        );
+        assert!(wallet.resume_pending_operation().unwrap().is_none());
+    }
+
+    #[test]
+    fn sync_with_strategy() {
+        let wallet = setup_wallet();
+        let blockchain_factory = FakeBlockchainFactory {
+            current_height: get_present(),
+        };
+
+        // A freshly restored wallet synchronized with CurrentOnly only knows the current subwallet
+        let new_wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        new_wallet
+            .restore_backup(wallet.generate_backup().unwrap())
+            .unwrap();
+        new_wallet
+            .sync_with_strategy(
+                &blockchain_factory,
+                SyncStrategy::CurrentOnly,
+                noop_progress(),
+            )
+            .unwrap();
+        let balance = new_wallet.get_balance().unwrap();
+        assert_eq!(
+            balance.uptodate_balance(),
+            wallet.get_balance().unwrap().uptodate_balance()
+        );
+        assert_eq!(*balance.obsolete_balance(), Balance::default());
+
+        // Smart does not skip obsolete subwallets that were never synchronized
+        new_wallet
+            .sync_with_strategy(
+                &blockchain_factory,
+                SyncStrategy::Smart {
+                    inactivity_blocks: 0,
+                },
+                noop_progress(),
+            )
+            .unwrap();
+        assert_eq!(
+            new_wallet.get_balance().unwrap(),
+            wallet.get_balance().unwrap()
+        );
+
+        // CurrentOnly keeps the locally known state of the obsolete subwallets
+        new_wallet
+            .sync_with_strategy(
+                &blockchain_factory,
+                SyncStrategy::CurrentOnly,
+                noop_progress(),
+            )
+            .unwrap();
+        assert_eq!(
+            new_wallet.get_balance().unwrap(),
+            wallet.get_balance().unwrap()
+        );
+        assert_eq!(
+            new_wallet.database().list_utxos().unwrap().len(),
+            wallet.database().list_utxos().unwrap().len()
+        );
+        assert_eq!(
+            new_wallet
+                .database()
+                .list_transaction_summaries()
+                .unwrap()
+                .len(),
+            wallet
+                .database()
+                .list_transaction_summaries()
+                .unwrap()
+                .len()
+        );
+    }
+
+    #[test]
+    fn list_wallet_addresses_paginated() {
+        let wallet = setup_wallet();
+        for _ in 0..3 {
+            wallet.get_new_address().unwrap();
+        }
+        let all_addresses = wallet.list_wallet_addresses().unwrap();
+        assert!(all_addresses.len() > 5);
+        assert_eq!(
+            wallet
+                .iter_wallet_addresses()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            all_addresses
+        );
+
+        // Walk every page
+        let mut paginated_addresses = vec![];
+        let mut continuation_token = None;
+        loop {
+            let page = wallet
+                .list_wallet_addresses_paginated(2, continuation_token)
+                .unwrap();
+            assert!(page.len() <= 2);
+            paginated_addresses.extend(page.page.iter().cloned());
+            if page.is_last_page() {
+                break;
+            }
+            continuation_token = page.continuation_token;
+        }
+        assert_eq!(paginated_addresses, all_addresses);
+
+        // New addresses do not shift the next pages
+        let first_page = wallet.list_wallet_addresses_paginated(2, None).unwrap();
+        wallet.get_new_address().unwrap();
+        let second_page = wallet
+            .list_wallet_addresses_paginated(2, first_page.continuation_token)
+            .unwrap();
+        assert_eq!(second_page.page, all_addresses[2..4]);
+
+        assert!(matches!(
+            wallet.list_wallet_addresses_paginated(
+                2,
+                Some(crate::database::paginate::ContinuationToken(
+                    "garbage".to_owned()
+                ))
+            ),
+            Err(crate::errors::Error::InvalidContinuationToken(_))
+        ));
+    }
+
+    #[test]
+    fn list_wallet_addresses_filtered() {
+        let wallet = setup_wallet();
+        for _ in 0..5 {
+            wallet.get_new_address().unwrap();
+        }
+        wallet
+            .internal_get_new_address(KeychainKind::Internal)
+            .unwrap();
+        let all_addresses = wallet.list_wallet_addresses().unwrap();
+        // (subwallet_id, keychain, index) of a WalletAddress
+        let position = |wa: &WalletAddress| {
+            let dp = wa.origin().1.to_u32_vec();
+            (
+                dp[2] - (1 << 31),
+                if dp[3] == 0 {
+                    KeychainKind::External
+                } else {
+                    KeychainKind::Internal
+                },
+                dp[4],
+            )
+        };
+        let expected = |subwallet_id: Option<crate::subwallet_config::SubwalletId>,
+                        keychain: Option<KeychainKind>,
+                        range: core::ops::RangeInclusive<u32>| {
+            all_addresses
+                .iter()
+                .filter(|wa| {
+                    let (swid, kc, index) = position(wa);
+                    subwallet_id.map_or(true, |id| id == swid)
+                        && keychain.map_or(true, |k| k == kc)
+                        && range.contains(&index)
+                })
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        let current_id =
+            get_default_test_subwallet_config(TestHeritageConfig::BackupWifeBro).subwallet_id();
+        assert_eq!(
+            wallet
+                .list_wallet_addresses_filtered(None, None, ..)
+                .unwrap(),
+            all_addresses
+        );
+        let res = wallet
+            .list_wallet_addresses_filtered(Some(current_id), Some(KeychainKind::External), 2..5)
+            .unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(
+            res,
+            expected(Some(current_id), Some(KeychainKind::External), 2..=4)
+        );
+        assert_eq!(
+            wallet
+                .list_wallet_addresses_filtered(None, Some(KeychainKind::Internal), ..)
+                .unwrap(),
+            expected(None, Some(KeychainKind::Internal), 0..=u32::MAX)
+        );
+        assert_eq!(
+            wallet
+                .list_wallet_addresses_filtered(Some(current_id), None, 1..=1)
+                .unwrap(),
+            expected(Some(current_id), None, 1..=1)
+        );
+        // Empty range or unknown subwallet
+        assert!(wallet
+            .list_wallet_addresses_filtered(None, None, 3..3)
+            .unwrap()
+            .is_empty());
+        assert!(wallet
+            .list_wallet_addresses_filtered(Some(42), None, ..)
+            .unwrap()
+            .is_empty());
     }
-}
 
-/// Take a mutable reference to a [Input] and make sure that it does not contains superfluous
-/// informations that are not needed by the intended spender.
-/// The idea is to remove from the [Input] all the scripts and key paths that are
-/// not relevant for the spender.
-/// It also enhence privacy, because Heirs will not be able to know who are
-/// the other Heirs from the Psbt. At most, an Heir can infere how many Heirs were
-/// before him by looking at the number of merkle_branches necessary for them to
-/// create their transaction because of the way we create the MAST
-/// (see comments in [HeritageConfig]::descriptor_segment())
-///
-/// This function SHOULD become largelly useless once [bdk] starts using miniscript v11.0
-/// because it introduces a clean way to generate a minimized Psbt from the get-go, as well
-/// as a feature to better compute the weight of the future transaction (currently, [bdk] uses
-/// the worst-case weight, meaning eveybody pays the fee as if they were the last Heir, not ideal)
-fn minimize_psbt_input_for_spender(
-    psbt_input: &mut Input,
-    heritage_explorer: Option<&HeritageExplorer>,
-) {
-    log::debug!("minimize_psbt_for_spender - heritage_explorer={heritage_explorer:?}");
-    match heritage_explorer {
-        // This is the owner spending
-        None => {
-            // With the owner it is simple: simply clean the scripts
-            psbt_input.tap_scripts.clear();
-            // Then remove every Key that is not the tap_internal_key
-            psbt_input.tap_key_origins.retain(|k, _| {
-                *k == psbt_input
-                    .tap_internal_key
-                    .expect("this is a taproot input")
+    #[test]
+    fn list_wallet_addresses() {
+        // Empty wallet
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        // Add AccountXPubs
+        wallet
+            .append_account_xpubs((0..3).into_iter().map(|i| get_test_account_xpub(i)))
+            .unwrap();
+        // Test the expected sequence of addresses
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .unwrap();
+        wallet.get_new_address().unwrap();
+        wallet.get_new_address().unwrap();
+
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .unwrap();
+        wallet.get_new_address().unwrap();
+        wallet.get_new_address().unwrap();
+
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .unwrap();
+        wallet.get_new_address().unwrap();
+        wallet.get_new_address().unwrap();
+
+        let results = wallet.list_wallet_addresses().unwrap();
+
+        // Expected addresses, in order
+        let expected_addresses = vec![
+            get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeBro,
+                1,
+            ),
+            get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeBro,
+                0,
+            ),
+            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY1, 1),
+            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY1, 0),
+            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY2, 1),
+            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY2, 0),
+        ];
+
+        assert_eq!(
+            results
+                .iter()
+                .map(|wa| wa.address().to_string())
+                .collect::<Vec<_>>(),
+            expected_addresses
+        );
+
+        // The fingerprint should be the same for every WalletAddress origin
+        let fingerprint = get_test_account_xpub(0)
+            .descriptor_public_key()
+            .master_fingerprint();
+        assert!(results.iter().all(|wa| wa.origin().0 == fingerprint));
+
+        // Expected derivation paths, in order
+        let expected_derivation_paths = (0..3)
+            .map(|a_i| {
+                let axpub = get_test_account_xpub(a_i);
+                let dp = axpub
+                    .descriptor_public_key()
+                    .full_derivation_path()
+                    .unwrap();
+                dp.normal_children()
+                    .take(1)
+                    .map(|cdp| cdp.normal_children().take(2).collect::<Vec<_>>())
+                    .flatten()
+                    .collect::<Vec<_>>()
             })
-        }
-        // This is an Heir spending
-        Some(heritage_explorer) => {
-            // Keeps only the relevant keys
-            psbt_input
-                .tap_key_origins
-                .retain(|_, (_, (fingerprint, _))| heritage_explorer.has_fingerprint(*fingerprint));
-            // Create a Vec of the retained origins
-            let origins = psbt_input
-                .tap_key_origins
+            .flatten()
+            .rev()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results
                 .iter()
-                .map(|(_, (_, (fingerprint, derivation_path)))| (fingerprint, derivation_path));
-            // Use the origins to ask for a concrete Script
-            let script = heritage_explorer.get_script(origins);
-            // Keeps only the relevant script
-            psbt_input.tap_scripts.retain(|_, (s, _)| *s == script);
-        }
+                .map(|wa| wa.origin().1.clone())
+                .collect::<Vec<_>>(),
+            expected_derivation_paths
+        );
     }
-}
 
-/// Take a mutable reference to a [Psbt] and compute the exact expected weight of the final transaction.
-/// It is possible to do so relatively easily since:
-/// 1. the [Psbt] is for a Taproot SegWit TX
-/// 2. We already minimized each [Input] down to a single spend-path so that we can easily see if it is
-/// a key-path or script-path and in the later case, we know what will be the size of the control block
-/// by infering the depth of the script in the MAST.
-///
-/// After that, we use the given [FeeRate] to compute the Fee and adjust the amount of the
-/// [bdk::bitcoin::psbt::Output] pointed by `adjustable_output_index`.
-///
-/// This function MAY become largelly useless once [bdk] starts using miniscript v11.0
-/// because it introduces a clean way to generate a minimized Psbt from the get-go, as well
-/// as a feature to better compute the weight of the future transaction (currently, [bdk] uses
-/// the worst-case weight, meaning eveybody pays the fee as if they were the last Heir, not ideal)
-///
-/// But on the other hand, bdk is really doing kind-of a shitty job computing the WU of the future TX:
-/// 1. It uses a wrong [bdk::wallet::coin_selection::TXIN_BASE_WEIGHT] because it forget that the absence of signature
-/// must still be signaled by setting sig_length to 0, which consumme 1 byte / 4 WU. The weight of the TX is thus
-/// underestimated by 4WU/input
-/// 2. On the other hand, it uses the obsolete [Tr::max_satisfaction_weight] which compensate that
-/// 3. On the other other hand, it always assume a witness_stack size of the worst-case scenario, meaning it uses the size
-/// the witness stack would have by spending the deepest/longest script in the MAST. In case of a Key-path spend
-/// it ugely overestimates the TX weight, in case of script-path spend, it depends on the script we actually intend to
-/// use but tend to overestimate also.
-///
-/// All in all, that makes the TX weight often wrong and thus the Fee too.
-///
-/// # Returns
-/// Return an i64 telling the amount of adjustment made to the adjustable_output
-///
-/// # Panics
-/// Panics if the [Psbt] contains [Input] that are not Taproot, or if the Psbt Inputs where
-/// not minimized (see [minimize_psbt_input_for_spender])
-fn adjust_with_real_fee(
-    psbt: &mut Psbt,
-    fee_rate: &BdkFeeRate,
-    adjustable_output_index: usize,
-) -> i64 {
-    log::debug!(
-        "adjust_with_real_fee - psbt={psbt:?} fee_rate={fee_rate:?} \
-        adjustable_output_index={adjustable_output_index}"
-    );
+    #[test]
+    fn list_unused_account_xpubs() {
+        let wallet = setup_wallet();
+        let expected = (3..10)
+            .into_iter()
+            .map(|i| get_test_account_xpub(i))
+            .collect::<Vec<_>>();
+        assert_eq!(wallet.list_unused_account_xpubs().unwrap(), expected)
+    }
 
-    let expected_weight = get_expected_tx_weight(&psbt);
-    log::debug!("adjust_with_real_fee - expected_weight={expected_weight:#}");
-    log::debug!(
-        "adjust_with_real_fee - expected_weight={} vB (ceil)",
-        expected_weight.to_vbytes_ceil()
-    );
+    #[test]
+    fn get_balance_details() {
+        let wallet = setup_wallet();
+        let utxos = wallet.database().list_utxos().unwrap();
+        let total = utxos.iter().map(|hu| hu.amount).sum::<Amount>();
+        let sum_buckets = |b: &HeirEligibilityBuckets| {
+            b.eligible_now
+                + b.eligible_within_30_days
+                + b.eligible_within_90_days
+                + b.eligible_within_365_days
+                + b.not_eligible_within_365_days
+        };
 
-    // Compute the new_fee using the expected_weight and the fee_rate
-    let new_fee = fee_rate.fee_wu(expected_weight);
-    let current_fee = psbt
-        .fee()
-        .expect("the PSBT is assumed to be valid")
-        .to_sat();
+        let now = get_present().timestamp;
+        let details = wallet.get_balance_details_at(now).unwrap();
+        assert_eq!(details.owner_spendable, total);
+        assert_eq!(sum_buckets(&details.heir_eligibility), total);
+        assert_eq!(
+            details
+                .subwallets
+                .iter()
+                .map(|swb| swb.confirmed + swb.unconfirmed)
+                .sum::<Amount>(),
+            total
+        );
+        assert_eq!(
+            details
+                .subwallets
+                .iter()
+                .map(|swb| (swb.subwallet_id, swb.is_current))
+                .collect::<Vec<_>>(),
+            vec![(0, false), (1, false), (2, true)]
+        );
 
-    log::debug!("adjust_with_real_fee - current_fee={current_fee} sat; new_fee={new_fee} sat");
-    // Compute the change we need to the adjustable_output to get the correct fee
-    // In case we have to lower the amount, we could endup having a amount <0
-    // or a Dust amount. In either case, warn and do nothing
-    if new_fee > current_fee {
-        // We need to lower the amount in adjustable_output to increase the fee
-        let adjustment = new_fee - current_fee;
-        if let Some(new_amount) = psbt.unsigned_tx.output[adjustable_output_index]
-            .value
-            .checked_sub(adjustment)
-        {
-            if new_amount.is_dust(&psbt.unsigned_tx.output[adjustable_output_index].script_pubkey)
-                && new_amount != 0
-            {
-                log::warn!(
-                    "adjust_with_real_fee - current_fee={current_fee} is lower than the \
-                new_fee={new_fee} but the resulting new_amount {new_amount} for the \
-                adjustable_output would be dust. Do nothing."
-                );
-                0
-            } else {
-                psbt.unsigned_tx.output[adjustable_output_index].value = new_amount;
-                -(adjustment as i64)
-            }
-        } else {
-            log::warn!(
-                "adjust_with_real_fee - current_fee={current_fee} is lower than the \
-            new_fee={new_fee} but the resulting new_amount for the adjustable_output \
-            would be negative. Do nothing."
+        // Very far in the future, every heir can spend everything
+        let details = wallet.get_balance_details_at(u32::MAX as u64).unwrap();
+        assert_eq!(details.heir_eligibility.eligible_now, total);
+        assert_eq!(sum_buckets(&details.heir_eligibility), total);
+    }
+
+    #[test]
+    fn audit_account_xpubs() {
+        let wallet = setup_wallet();
+        let audits = wallet.audit_account_xpubs().unwrap();
+        assert_eq!(audits.len(), 10);
+        for (i, audit) in audits.iter().enumerate() {
+            assert_eq!(audit.account_xpub, get_test_account_xpub(i as u32));
+            assert_eq!(
+                audit.fingerprint,
+                Fingerprint::from_str("9c7088e3").unwrap()
             );
-            0
+            assert_eq!(
+                audit.derivation_path,
+                DerivationPath::from_str(&format!("m/86'/1'/{i}'")).unwrap()
+            );
+            assert!(audit.matches_wallet_fingerprint);
+            assert_eq!(audit.is_current, i == 2);
+            if i < 3 {
+                assert_eq!(audit.subwallet_id, Some(i as u32));
+            } else {
+                assert_eq!(audit.subwallet_id, None);
+                assert_eq!(audit.firstuse_time, None);
+            }
         }
-    } else {
-        let adjustment = current_fee - new_fee;
-        psbt.unsigned_tx.output[adjustable_output_index].value += adjustment;
-        adjustment as i64
+        assert_eq!(
+            audits[0].firstuse_time,
+            get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY2)
+                .subwallet_firstuse_time()
+        );
     }
-}
 
-pub fn get_expected_tx_weight(psbt: &Psbt) -> Weight {
-    log::debug!("get_expected_tx_weight - psbt={psbt}");
-    // Put some barriers so we do not misuses this
-    // Ensure this is a pure Taproot Psbt
-    assert!(psbt
-        .inputs
-        .iter()
-        .all(|input| input.tap_internal_key.is_some() && input.tap_merkle_root.is_some()));
+    #[test]
+    fn append_account_xpubs() {
+        let wallet = setup_wallet();
+        let initial_used = wallet.list_used_account_xpubs().unwrap();
+        let mut initial_unused = wallet.list_unused_account_xpubs().unwrap();
+        let to_add = (0..15).into_iter().map(|i| get_test_account_xpub(i));
+        let expected_add = (10..15)
+            .into_iter()
+            .map(|i| get_test_account_xpub(i))
+            .collect::<Vec<_>>();
 
-    // The [Weight] is the addition of:
-    // - The TX weight (without any script_sig or witness at this point)
-    // - The 2 additionnal WU coming from the segwit format (marker + flag)
-    // - Expected witness and sig size for each input
+        // Append the descriptors
+        assert!(wallet.append_account_xpubs(to_add).is_ok());
 
-    // TX weight given by rust-bitcoin implementation, nice
-    let mut expected_weight = psbt.unsigned_tx.weight();
-    // Fixed addition of the 2 additionnal WU coming from the segwit format
-    expected_weight += Weight::from_wu(2);
-    // Expected witness and sig size for each input
-    expected_weight += psbt
-        .inputs
-        .iter()
-        .map(|input| {
-            match input.tap_scripts.len() {
-                // Code here is copied from <rust-miniscript 10.0.0>/src/descriptor/tr.rs
-                // {} impl Tr<Pk>::max_weight_to_satisfy(&self)
-                0 => {
-                    // key spend path
-                    // item: varint(sig+sigHash) + <sig(64)+sigHash(1)>
-                    let item_sig_size = 1 + 65;
-                    // 1 stack item
-                    // let stack_varint_diff = varint_len(1) - varint_len(0); // Always 0
+        // Used ADs do not change
+        assert_eq!(wallet.list_used_account_xpubs().unwrap(), initial_used);
 
-                    item_sig_size
-                }
-                1 => {
-                    // Script spend
-                    let (ctr_block, (script, _)) = input.tap_scripts.first_key_value().unwrap();
-                    let miniscript: Miniscript<_, Tap> = Miniscript::parse(script).unwrap();
-                    let script_size = miniscript.script_size();
-                    let max_sat_elems = miniscript
-                        .max_satisfaction_witness_elements()
-                        .expect("Our Miniscript are satisfyable");
-                    let max_sat_size = miniscript
-                        .max_satisfaction_size()
-                        .expect("Our Miniscript are satisfyable");
-                    let control_block_size = ctr_block.size();
+        // Unused ADs change and is now initial_unused + expected_add
+        initial_unused.extend(expected_add);
+        assert_eq!(wallet.list_unused_account_xpubs().unwrap(), initial_unused);
 
-                    // stack varint difference (+1 for ctrl block, witness script already included)
-                    let stack_varint_diff = varint_len(max_sat_elems + 1) - varint_len(0);
+        // Cannot add an Account XPub with a different fingerprint
+        assert!(wallet
+            .append_account_xpubs([get_bad_account_xpub()])
+            .is_err_and(|e| match e {
+                crate::errors::Error::InvalidAccountXPub => true,
+                _ => false,
+            }));
+    }
 
-                    stack_varint_diff +
-                        // size of elements to satisfy script
-                        max_sat_size +
-                        // second to last element: script
-                        varint_len(script_size) +
-                        script_size +
-                        // last element: control block
-                        varint_len(control_block_size) +
-                        control_block_size
-                }
-                _ => panic!("Psbt input is not minimized"),
-            }
-        })
-        .fold(Weight::ZERO, |acc, iw| {
-            acc + Weight::from_witness_data_size(iw as u64)
-        });
-    expected_weight
-}
+    #[test]
+    fn get_current_heritage_config() {
+        let wallet = setup_wallet();
+        assert_eq!(
+            wallet.get_current_heritage_config().unwrap().unwrap(),
+            get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+        );
+    }
 
-// Helper function to calculate witness size
-// copied from <rust-miniscript 10.0.0>/src/utils.rs
-fn varint_len(n: usize) -> usize {
-    bdk::bitcoin::VarInt(n as u64).len()
-}
+    #[test]
+    fn list_obsolete_heritage_configs() {
+        let wallet = setup_wallet();
+        let expected = vec![
+            get_test_heritage_config(TestHeritageConfig::BackupWifeY2),
+            get_test_heritage_config(TestHeritageConfig::BackupWifeY1),
+        ];
+        assert_eq!(wallet.list_obsolete_heritage_configs().unwrap(), expected);
+    }
 
-#[cfg(test)]
-mod tests {
+    #[test]
+    fn update_heritage_config_with_keypath_change() {
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        wallet
+            .append_account_xpubs((0..5).into_iter().map(|i| get_test_account_xpub(i)))
+            .unwrap();
+        let current_has_keypath_change = || {
+            wallet
+                .database()
+                .get_subwallet_config(SubwalletConfigId::Current)
+                .unwrap()
+                .unwrap()
+                .has_keypath_change()
+        };
 
-    use core::{cell::RefCell, str::FromStr};
-    use std::collections::{hash_map::RandomState, HashMap, HashSet};
+        assert!(wallet
+            .update_heritage_config_with_keypath_change(
+                get_test_heritage_config(TestHeritageConfig::BackupWifeY2),
+                true
+            )
+            .is_ok());
+        assert!(current_has_keypath_change());
+        // The external addresses are the usual ones
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeY2,
+                0
+            )));
 
-    use bdk::{
-        blockchain::{
-            Blockchain, BlockchainFactory, Capability, GetBlockHash, GetHeight, GetTx, Progress,
-            WalletSync,
-        },
-        database::{BatchDatabase, SyncTime},
-        Balance, BlockTime, Error, FeeRate, KeychainKind, LocalUtxo, TransactionDetails,
-    };
+        // The subwallet has been used, the mode cannot change without a new HeritageConfig
+        assert!(matches!(
+            wallet.update_heritage_config_with_keypath_change(
+                get_test_heritage_config(TestHeritageConfig::BackupWifeY2),
+                false
+            ),
+            Err(crate::errors::Error::KeypathChangeRequiresNewHeritageConfig)
+        ));
 
-    use crate::{
-        bitcoin::{
-            absolute::LockTime,
-            bip32::{DerivationPath, Fingerprint},
-            secp256k1::XOnlyPublicKey,
-            taproot::TapNodeHash,
-            Amount, BlockHash, OutPoint, Sequence, Transaction, Txid,
-        },
-        database::{memory::HeritageMemoryDatabase, HeritageDatabase, TransacHeritageOperation},
-        heritage_wallet::{
-            backup::{HeritageWalletBackup, SubwalletDescriptorBackup},
-            get_expected_tx_weight, BlockInclusionObjective, CreatePsbtOptions, HeritageWallet,
-            HeritageWalletBalance, Recipient, SpendingConfig, SubwalletConfigId, UtxoSelection,
-        },
-        miniscript::{Descriptor, DescriptorPublicKey},
-        tests::*,
-        utils::{extract_tx, string_to_address},
-        HeritageConfig,
-    };
+        // The mode is kept by default
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .is_ok());
+        assert!(current_has_keypath_change());
+        // The subwallet is unused, so the mode can change without a new HeritageConfig
+        assert!(wallet
+            .update_heritage_config_with_keypath_change(
+                get_test_heritage_config(TestHeritageConfig::BackupWifeY1),
+                false
+            )
+            .is_ok());
+        assert!(!current_has_keypath_change());
+        assert_eq!(wallet.list_used_account_xpubs().unwrap().len(), 2);
 
-    use super::FeePolicy;
+        // The backup records the keypath change descriptor
+        let backup = wallet.generate_backup().unwrap();
+        assert_eq!(backup.0.len(), 2);
+        assert!(backup.0[0].keypath_change_descriptor.is_some());
+        assert!(backup.0[1].keypath_change_descriptor.is_none());
 
-    #[derive(Debug, Clone)]
-    pub struct FakeBlockchain {
-        pub current_height: BlockTime,
-        pub transactions: Vec<TransactionDetails>,
+        // No key-path-only UTXO, nothing to refresh
+        assert!(wallet
+            .create_keypath_refresh_psbt(Default::default())
+            .unwrap()
+            .is_none());
     }
 
-    impl GetHeight for FakeBlockchain {
-        fn get_height(&self) -> Result<u32, Error> {
-            Ok(self.current_height.height)
-        }
-    }
-    impl GetTx for FakeBlockchain {
-        fn get_tx(&self, _txid: &Txid) -> Result<Option<Transaction>, Error> {
-            Err(Error::Generic("Unimplemented".to_owned()))
-        }
-    }
-    impl GetBlockHash for FakeBlockchain {
-        fn get_block_hash(&self, _height: u64) -> Result<BlockHash, Error> {
-            Err(Error::Generic("Unimplemented".to_owned()))
-        }
-    }
-    impl WalletSync for FakeBlockchain {
-        fn wallet_setup<D: BatchDatabase>(
-            &self,
-            database: &RefCell<D>,
-            _progress_update: Box<dyn Progress>,
-        ) -> Result<(), Error> {
-            for tx_details in &self.transactions {
-                database.borrow_mut().set_tx(tx_details)?;
-                database.borrow_mut().set_utxo(&LocalUtxo {
-                    txout: tx_details.transaction.as_ref().unwrap().output[0].clone(),
-                    outpoint: OutPoint {
-                        txid: tx_details.txid.clone(),
-                        vout: 0,
-                    },
-                    keychain: KeychainKind::External,
-                    is_spent: false,
-                })?;
-            }
-            database.borrow_mut().set_sync_time(SyncTime {
-                block_time: self.current_height.clone(),
-            })?;
-            Ok(())
-        }
+    #[test]
+    fn update_heritage_config() {
+        // Test on an empty wallet
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        wallet
+            .append_account_xpubs((0..5).into_iter().map(|i| get_test_account_xpub(i)))
+            .unwrap();
+        // No current wallet
+        assert!(wallet.get_current_heritage_config().unwrap().is_none());
+        // Update is ok
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .is_ok());
+        // Current wallet is now the new one
+        assert_eq!(
+            wallet.get_current_heritage_config().unwrap().unwrap(),
+            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+        );
+        // An account descriptor has been consumed
+        let used_axps = wallet.list_used_account_xpubs().unwrap();
+        assert!(used_axps.len() == 1 && used_axps[0] == get_test_account_xpub(0));
+        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
+        assert!(unused_axps.len() == 4 && unused_axps[0] == get_test_account_xpub(1));
+        // Now generate an address
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeY2,
+                0
+            )));
+
+        // As it as been used, replacing it should cause the creation of an obsolete HeritageConfig
+        // Update is ok
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .is_ok());
+        // Current wallet is now the new one
+        assert_eq!(
+            wallet.get_current_heritage_config().unwrap().unwrap(),
+            get_test_heritage_config(TestHeritageConfig::BackupWifeY1)
+        );
+        // An account descriptor has been consumed
+        let used_axps = wallet.list_used_account_xpubs().unwrap();
+        assert!(used_axps.len() == 2 && used_axps[1] == get_test_account_xpub(1));
+        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
+        assert!(unused_axps.len() == 3 && unused_axps[0] == get_test_account_xpub(2));
+        // Obsolete HeritageConfig
+        let obsolete_heritage_config = wallet.list_obsolete_heritage_configs().unwrap();
+        assert_eq!(obsolete_heritage_config.len(), 1);
+        assert_eq!(
+            obsolete_heritage_config[0],
+            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+        );
+
+        // As it as NOT been used, replacing it should NOT cause the creation of an obsolete HeritageConfig
+        // Update is ok
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .is_ok());
+        // Current wallet is now the new one
+        assert_eq!(
+            wallet.get_current_heritage_config().unwrap().unwrap(),
+            get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+        );
+        // An account descriptor has NOT been consumed, i.e. used_axps.len == 2
+        let used_axps = wallet.list_used_account_xpubs().unwrap();
+        assert!(used_axps.len() == 2);
+        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
+        assert!(unused_axps.len() == 3 && unused_axps[0] == get_test_account_xpub(2));
+        // Obsolete HeritageConfig is still len==1 and contains only BackupWifeY2
+        let obsolete_heritage_config = wallet.list_obsolete_heritage_configs().unwrap();
+        assert_eq!(obsolete_heritage_config.len(), 1);
+        assert_eq!(
+            obsolete_heritage_config[0],
+            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+        );
+
+        // Now generate an address
+        assert!(wallet.get_new_address().is_ok());
+        // Replace with the same HeritageConfig
+        // Update is ok
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .is_ok());
+        // Current wallet is now the new one
+        assert_eq!(
+            wallet.get_current_heritage_config().unwrap().unwrap(),
+            get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+        );
+        // An account descriptor has NOT been consumed, i.e. used_axps.len == 2
+        let used_axps = wallet.list_used_account_xpubs().unwrap();
+        assert!(used_axps.len() == 2);
+        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
+        assert!(unused_axps.len() == 3 && unused_axps[0] == get_test_account_xpub(2));
+        // Obsolete HeritageConfig is still len==1 and contains only BackupWifeY2
+        let obsolete_heritage_config = wallet.list_obsolete_heritage_configs().unwrap();
+        assert_eq!(obsolete_heritage_config.len(), 1);
+        assert_eq!(
+            obsolete_heritage_config[0],
+            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+        );
+
+        // Test on wallet with only one AD
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        wallet
+            .append_account_xpubs((0..1).into_iter().map(|i| get_test_account_xpub(i)))
+            .unwrap();
+        // Update is ok
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .is_ok());
+        // Now generate an address
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeY2,
+                0
+            )));
+        // Update fail with new HeritageConfig because no more ADs
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .is_err());
+        // Update succeed with the same HeritageConfig
+        assert!(wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .is_ok());
     }
-    impl Blockchain for FakeBlockchain {
-        fn get_capabilities(&self) -> HashSet<Capability> {
-            [
-                Capability::FullHistory,
-                Capability::AccurateFees,
-                Capability::GetAnyTx,
-            ]
-            .into()
-        }
 
-        fn broadcast(&self, _tx: &Transaction) -> Result<(), Error> {
-            Err(Error::Generic("Unimplemented".to_owned()))
-        }
+    #[test]
+    fn get_new_address() {
+        // Test on an empty wallet
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        // Add AccountXPubs
+        wallet
+            .append_account_xpubs((0..3).into_iter().map(|i| get_test_account_xpub(i)))
+            .unwrap();
+        // Test the expected sequence of addresses
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .unwrap();
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeY2,
+                0
+            )));
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .unwrap();
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeY2,
+                1,
+            )));
 
-        fn estimate_fee(&self, _target: usize) -> Result<FeeRate, Error> {
-            Ok(FeeRate::from_sat_per_vb(10.0))
-        }
-    }
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .unwrap();
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeY1,
+                0
+            )));
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .unwrap();
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeY1,
+                1,
+            )));
 
-    #[derive(Debug, Clone)]
-    pub struct FakeBlockchainFactory {
-        pub current_height: BlockTime,
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .unwrap();
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeBro,
+                0
+            )));
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .unwrap();
+        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
+            == get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeBro,
+                1,
+            )));
     }
-    impl BlockchainFactory for FakeBlockchainFactory {
-        type Inner = FakeBlockchain;
 
-        fn build(
-            &self,
-            wallet_name: &str,
-            _override_skip_blocks: Option<u32>,
-        ) -> Result<Self::Inner, bdk::Error> {
-            let mut hashtable: HashMap<String, Vec<TransactionDetails>> = HashMap::new();
-            // Wallet TestHeritageConfig::BackupWifeY2
-            hashtable.insert("7y7nqca9j84snf2h".to_owned(), vec![
-                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"51208bdbdb2969eeb7ec8efd20f7bc64961a760313404e900109a1ba19afb8b0292c"}]},"txid":"344dbc396e3c6945f46a67faab275141bb0fdd63f8a46362ba27e4753400d9c2","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":842520,"timestamp":1715552000}}"#).unwrap(),
-                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"51204eff92d75cc954964dfe21755cf3063abbe19aa0e6fcacf429af996fc0f65beb"}]},"txid":"d2f3bd44fb6ad0c32833ea943d718e806245e632302f25720811fea167c13507","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":904440,"timestamp":1752704000}}"#).unwrap(),
-            ]);
-            // Wallet TestHeritageConfig::BackupWifeY1
-            hashtable.insert("0hqx0prur5t9us5w".to_owned(), vec![
-                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"5120a6d2ae7fb6a453f32d1d32ffdfc31f3303a7704bcf16ff4ebabe0e26686ec687"}]},"txid":"2f0a77d510db56dda3b43692d4658a92f523193a3b854d2387681f2fd0f5d920","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":895080,"timestamp":1747088000}}"#).unwrap(),
-                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"5120f0c155ea564bd2fc6d45f654a68d2734b762a947d93c4cf6e2fdda0260ee89d9"}]},"txid":"3854db1cb2253a270e49a093a6ddb92fa79efd8b295568e08448e4de678fc08b","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":897960,"timestamp":1748816000}}"#).unwrap(),
-            ]);
-            // Wallet TestHeritageConfig::BackupWifeBro
-            hashtable.insert("9lwn0wm9mh7ydv64".to_owned(), vec![
-                serde_json::from_str(r#"{"transaction":{"version":1,"lock_time":0,"input":[{"previous_output":"0000000000000000000000000000000000000000000000000000000000000000:4294967295","script_sig":"","sequence":4294967295,"witness":[]}],"output":[{"value":100000000,"script_pubkey":"5120d1756b2e88a51fc63f156ef0ba3a3cfd126206bfb96347f1b4ccb15f9b75e14f"}]},"txid":"6ed1563a936196211f2f76447c478533df8f3efc43933f4c3405b9a760b31204","received":100000000,"sent":0,"fee":0,"confirmation_time":{"height":923160,"timestamp":1763936000}}"#).unwrap(),
-            ]);
+    #[test]
+    fn get_set_block_inclusion_objective() {
+        // Test on an empty wallet
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        assert_eq!(
+            wallet.get_block_inclusion_objective().unwrap(),
+            BlockInclusionObjective::default()
+        );
+        let new_bio = BlockInclusionObjective::from(50u16);
+        assert!(wallet.set_block_inclusion_objective(new_bio).is_ok());
+        assert_eq!(wallet.get_block_inclusion_objective().unwrap(), new_bio);
 
-            Ok(FakeBlockchain {
-                current_height: self.current_height.clone(),
-                transactions: hashtable
-                    .get(wallet_name)
-                    .map(Clone::clone)
-                    .unwrap_or_default(),
-            })
-        }
+        let new_bio = BlockInclusionObjective::from(30u16);
+        assert!(wallet.set_block_inclusion_objective(new_bio).is_ok());
+        assert_eq!(wallet.get_block_inclusion_objective().unwrap(), new_bio);
     }
 
-    fn setup_wallet() -> HeritageWallet<HeritageMemoryDatabase> {
-        let mut db = HeritageMemoryDatabase::new();
+    #[test]
+    fn wallet_first_use_time() {
+        let wallet = setup_wallet();
+        // Create a brand new HeritageConfig (which does not really make sense but we don't care for this test)
+        wallet
+            .update_heritage_config(
+                HeritageConfig::builder()
+                    .add_heritage(get_test_heritage(TestHeritage::Backup).time_lock(180))
+                    .reference_time(get_present().timestamp)
+                    .minimum_lock_time(10)
+                    .build(),
+            )
+            .unwrap();
 
-        // Account descriptors
-        let unused_axps = (3..10)
-            .into_iter()
-            .map(|i| get_test_account_xpub(i))
-            .collect();
-        db.add_unused_account_xpubs(&unused_axps).unwrap();
+        // At this stage, the current wallet is unused
+        assert!(wallet
+            .database()
+            .get_subwallet_config(SubwalletConfigId::Current)
+            .unwrap()
+            .unwrap()
+            .subwallet_firstuse_time()
+            .is_none());
 
-        // Wallet subconfigs
-        db.put_subwallet_config(
-            SubwalletConfigId::Id(0),
-            &get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY2),
-        )
-        .unwrap();
-        db.put_subwallet_config(
-            SubwalletConfigId::Id(1),
-            &get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY1),
-        )
-        .unwrap();
-        db.put_subwallet_config(
-            SubwalletConfigId::Current,
-            &get_default_test_subwallet_config(TestHeritageConfig::BackupWifeBro),
-        )
-        .unwrap();
+        wallet.get_new_address().unwrap();
+
+        // At this stage, the current wallet is used
+        assert!(wallet
+            .database()
+            .get_subwallet_config(SubwalletConfigId::Current)
+            .unwrap()
+            .unwrap()
+            .subwallet_firstuse_time()
+            .is_some());
 
-        let wallet = HeritageWallet::new(db);
+        // Create a brand new HeritageConfig (which does not really make sense but we don't care for this test)
         wallet
-            .sync(&FakeBlockchainFactory {
-                current_height: get_present(),
-            })
+            .update_heritage_config(
+                HeritageConfig::builder()
+                    .add_heritage(get_test_heritage(TestHeritage::Backup).time_lock(360))
+                    .reference_time(get_present().timestamp)
+                    .minimum_lock_time(30)
+                    .build(),
+            )
             .unwrap();
+        // // Sync
+        // wallet
+        //     .sync(FakeBlockchainFactory {
+        //         current_height: get_present(),
+        //     })
+        //     .unwrap();
+
+        // At this stage, the current wallet is unused
+        assert!(wallet
+            .database()
+            .get_subwallet_config(SubwalletConfigId::Current)
+            .unwrap()
+            .unwrap()
+            .subwallet_firstuse_time()
+            .is_none());
 
         wallet
-    }
+            .create_owner_psbt(
+                SpendingConfig::Recipients {
+                    recipients: vec![Recipient(
+                        string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                        Amount::from_sat(10_000),
+                    )],
+                    change_to: None,
+                },
+                Default::default(),
+            )
+            .unwrap();
 
-    fn get_present() -> BlockTime {
-        get_blocktime_for_timestamp(
-            get_absolute_inheritance_timestamp(
-                TestHeritageConfig::BackupWifeY1,
-                TestHeritage::Backup,
-            ) + 86400 * 15, // 15 days after backup can inherit, therefore wife cannot yet.
-        )
+        // At this stage, the current wallet is used
+        assert!(wallet
+            .database()
+            .get_subwallet_config(SubwalletConfigId::Current)
+            .unwrap()
+            .unwrap()
+            .subwallet_firstuse_time()
+            .is_some());
     }
 
     #[test]
-    fn get_balance() {
+    fn list_heritage_utxos() {
         let wallet = setup_wallet();
+        let res = wallet.database().list_utxos();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let hus = res.unwrap();
+        assert_eq!(hus.len(), 5);
 
-        let expected_balance = HeritageWalletBalance::new(
-            Balance {
-                confirmed: 100_000_000,
-                ..Default::default()
-            },
-            Balance {
-                confirmed: 400_000_000,
-                ..Default::default()
-            },
-        );
-        assert_eq!(wallet.get_balance().unwrap(), expected_balance);
+        // Control 2 UTXOs
+        // For this UTXO, the expected timestamps are the absolute timelocks expiration for each Heir
+        assert!(hus.iter().any(|hu| {
+            hu.confirmation_time.as_ref().unwrap().height == 897960
+                && hu.amount.to_sat() == 100_000_000
+                && hu.estimate_heir_spending_timestamp(
+                    get_test_heritage(TestHeritage::Backup).get_heir_config(),
+                ) == Some(1763072000)
+                && hu.estimate_heir_spending_timestamp(
+                    get_test_heritage(TestHeritage::Wife).get_heir_config(),
+                ) == Some(1766096000)
+                && hu.estimate_heir_spending_timestamp(
+                    get_test_heritage(TestHeritage::Brother).get_heir_config(),
+                ) == None
+        }));
+
+        // For this UTXO, the expected timestamps are estimates based on the 90/180 days relative
+        // lock after the transaction is confirmed (transaction came in long after the absoltue locktime expiration)
+        assert!(hus.iter().any(|hu| {
+            hu.confirmation_time.as_ref().unwrap().height == 904440
+                && hu.amount.to_sat() == 100_000_000
+                && hu.estimate_heir_spending_timestamp(
+                    get_test_heritage(TestHeritage::Backup).get_heir_config(),
+                ) == Some(1760480000)
+                && hu.estimate_heir_spending_timestamp(
+                    get_test_heritage(TestHeritage::Wife).get_heir_config(),
+                ) == Some(1768256000)
+                && hu.estimate_heir_spending_timestamp(
+                    get_test_heritage(TestHeritage::Brother).get_heir_config(),
+                ) == None
+        }));
     }
 
     #[test]
-    fn fingerprint() {
-        // Test on an empty wallet
-        let wallet = HeritageWallet::new(HeritageMemoryDatabase::new());
-        // An empty wallet does not have a fingerprint
-        assert!(wallet.fingerprint().is_ok_and(|f| f.is_none()));
-
-        // Test on an non-empty wallet
+    fn get_inheritance_schedule() {
         let wallet = setup_wallet();
-        // A non-empty wallet does have a fingerprint
+        let schedule = wallet.get_inheritance_schedule().unwrap();
+        let hus = wallet.database().list_utxos().unwrap();
+        // One event per heir per UTXO
         assert_eq!(
-            wallet.fingerprint().unwrap(),
-            Some(
-                get_test_account_xpub(0)
-                    .descriptor_public_key()
-                    .master_fingerprint()
-            )
+            schedule.events.len(),
+            hus.iter()
+                .map(|hu| hu.heritage_config.iter_heir_configs().count())
+                .sum::<usize>()
+        );
+        // Sorted by estimated timestamp
+        assert!(schedule
+            .events
+            .windows(2)
+            .all(|w| w[0].estimated_timestamp <= w[1].estimated_timestamp));
+        // Consistent with the HeritageUtxo estimations
+        assert!(schedule.events.iter().all(|event| {
+            let hu = hus.iter().find(|hu| hu.outpoint == event.outpoint).unwrap();
+            hu.amount == event.amount
+                && hu.estimate_heir_spending_timestamp(&event.heir_config)
+                    == Some(event.estimated_timestamp)
+                && event.estimated_timestamp >= event.spendable_timestamp
+        }));
+        // Control one event
+        let backup_heir_config = get_test_heritage(TestHeritage::Backup).get_heir_config();
+        let event = schedule
+            .events
+            .iter()
+            .find(|event| {
+                event.spendable_height == Some(897960 + 12960)
+                    && event.heir_config == *backup_heir_config
+            })
+            .unwrap();
+        assert_eq!(event.spendable_timestamp, 1763072000);
+        assert_eq!(event.estimated_timestamp, 1763072000);
+
+        // Next expiration
+        assert_eq!(schedule.next_expiration_after(0), schedule.events.first());
+        assert_eq!(
+            schedule
+                .next_expiration_after(1763072000 - 1)
+                .unwrap()
+                .estimated_timestamp,
+            1763072000
         );
+        assert!(schedule.next_expiration_after(u64::MAX).is_none());
     }
 
     #[test]
-    fn list_used_account_xpubs() {
+    fn simulate() {
         let wallet = setup_wallet();
-        let expected = (0..3)
+        let backup_heir_config = get_test_heritage(TestHeritage::Backup)
+            .get_heir_config()
+            .clone();
+        let total = wallet
+            .database()
+            .list_utxos()
+            .unwrap()
             .into_iter()
-            .map(|i| get_test_account_xpub(i))
-            .collect::<Vec<_>>();
-        assert_eq!(wallet.list_used_account_xpubs().unwrap(), expected)
-    }
+            .map(|hu| hu.amount)
+            .sum::<Amount>();
+
+        // In the far past, no heir can spend anything
+        let simulation = wallet.simulate(get_blocktime_for_timestamp(1_700_000_001));
+        let balance = simulation.get_balance().unwrap();
+        assert_eq!(balance.total, total);
+        assert_eq!(balance.owner_exclusive, total);
+        assert_eq!(balance.heir_spendable, Amount::ZERO);
+        let eligibilities = simulation.list_heir_eligibilities().unwrap();
+        assert!(!eligibilities.is_empty());
+        assert!(eligibilities
+            .iter()
+            .all(|e| e.spendable == Amount::ZERO && e.next_maturity_ts.is_some()));
+        assert!(simulation.next_expiration().unwrap().is_some());
+        assert!(simulation
+            .create_heir_psbt(
+                backup_heir_config.clone(),
+                SpendingConfig::DrainTo(string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap()),
+                CreatePsbtOptions::default(),
+            )
+            .is_err());
 
-    #[test]
-    fn generate_backup() {
-        let wallet = setup_wallet();
-        // To have a last_external_index on the last backup
-        let _ = wallet.get_new_address().unwrap();
-        // We expect the values set in the tests mod of lib.rs
-        let expected = HeritageWalletBackup(vec![
-            SubwalletDescriptorBackup {
-                external_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
-                    get_default_test_subwallet_config_expected_external_descriptor(
-                        TestHeritageConfig::BackupWifeY2,
-                    ),
-                )
-                .unwrap(),
-                change_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
-                    get_default_test_subwallet_config_expected_change_descriptor(
-                        TestHeritageConfig::BackupWifeY2,
-                    ),
-                )
-                .unwrap(),
-                first_use_ts: get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY2)
-                    .subwallet_firstuse_time(),
-                last_external_index: None,
-                last_change_index: None,
-            },
-            SubwalletDescriptorBackup {
-                external_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
-                    get_default_test_subwallet_config_expected_external_descriptor(
-                        TestHeritageConfig::BackupWifeY1,
-                    ),
-                )
-                .unwrap(),
-                change_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
-                    get_default_test_subwallet_config_expected_change_descriptor(
-                        TestHeritageConfig::BackupWifeY1,
-                    ),
-                )
-                .unwrap(),
-                first_use_ts: get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY1)
-                    .subwallet_firstuse_time(),
-                last_external_index: None,
-                last_change_index: None,
-            },
-            SubwalletDescriptorBackup {
-                external_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
-                    get_default_test_subwallet_config_expected_external_descriptor(
-                        TestHeritageConfig::BackupWifeBro,
-                    ),
-                )
-                .unwrap(),
-                change_descriptor: Descriptor::<DescriptorPublicKey>::from_str(
-                    get_default_test_subwallet_config_expected_change_descriptor(
-                        TestHeritageConfig::BackupWifeBro,
-                    ),
-                )
-                .unwrap(),
-                first_use_ts: get_default_test_subwallet_config(TestHeritageConfig::BackupWifeBro)
-                    .subwallet_firstuse_time(),
-                last_external_index: Some(0),
-                last_change_index: None,
-            },
-        ]);
-        assert_eq!(wallet.generate_backup().unwrap(), expected)
+        // At present, the backup heir can spend some of the funds
+        let simulation = wallet.simulate(get_present());
+        let balance = simulation.get_balance().unwrap();
+        assert_eq!(balance.total, total);
+        assert!(balance.heir_spendable > Amount::ZERO);
+        assert_eq!(
+            balance.owner_exclusive + balance.heir_spendable,
+            balance.total
+        );
+        let backup_eligibility = simulation
+            .list_heir_eligibilities()
+            .unwrap()
+            .into_iter()
+            .find(|e| e.heir_config == backup_heir_config)
+            .unwrap();
+        assert!(backup_eligibility.spendable > Amount::ZERO);
+        let (psbt, _) = simulation
+            .create_heir_psbt(
+                backup_heir_config,
+                SpendingConfig::DrainTo(string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap()),
+                CreatePsbtOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            Amount::from_sat(
+                psbt.inputs
+                    .iter()
+                    .map(|i| i.witness_utxo.as_ref().unwrap().value)
+                    .sum::<u64>()
+            ),
+            backup_eligibility.spendable
+        );
+
+        // In the far future, every UTXO is spendable by an heir
+        let simulation = wallet.simulate(get_blocktime_for_timestamp(4_000_000_000));
+        assert_eq!(simulation.get_balance().unwrap().heir_spendable, total);
+        assert!(simulation.next_expiration().unwrap().is_none());
     }
 
     #[test]
-    fn restore_backup() {
+    fn create_consolidation_psbt() {
         let wallet = setup_wallet();
-        // To have a last_external_index on the last backup
-        let _ = wallet.get_new_address().unwrap();
+        let max_fee_rate = crate::bitcoin::FeeRate::from_sat_per_vb_unchecked(20);
 
-        // We expect that if we backup and then restore (i.e. duplicates) the wallet,
-        // we will have effectively the same wallet (same balance, same addresses, etc...)
-
-        let new_wallet = HeritageWallet::new(HeritageMemoryDatabase::new());
-        let backup = wallet.generate_backup().unwrap();
-
-        // Restoration goes ok
-        let r = new_wallet.restore_backup(backup);
-        assert!(r.is_ok(), "{}", r.err().unwrap());
+        // Already at the target
+        assert!(wallet
+            .create_consolidation_psbt(max_fee_rate, 5, 1, Default::default())
+            .unwrap()
+            .is_none());
 
-        // New address from both wallet are the same
+        // Sweep one UTXO: all have the same amount so the oldest is chosen
+        let (psbt, tx_summary) = wallet
+            .create_consolidation_psbt(max_fee_rate, 4, 1, Default::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 1);
         assert_eq!(
-            wallet.get_new_address().unwrap(),
-            new_wallet.get_new_address().unwrap()
+            psbt.unsigned_tx.input[0].previous_output.txid.to_string(),
+            "344dbc396e3c6945f46a67faab275141bb0fdd63f8a46362ba27e4753400d9c2"
         );
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(tx_summary.owned_outputs.len(), 1);
 
-        // Sync
-        new_wallet
-            .sync(&FakeBlockchainFactory {
-                current_height: get_present(),
-            })
+        // Only the UTXOs of obsolete subwallets are swept
+        let (psbt, _) = wallet
+            .create_consolidation_psbt(max_fee_rate, 1, 1, Default::default())
+            .unwrap()
             .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 4);
+        assert!(psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .all(|i| i.previous_output.txid.to_string()
+                != "6ed1563a936196211f2f76447c478533df8f3efc43933f4c3405b9a760b31204"));
 
-        // Balance from both wallet are the same
+        // Fees are too high
+        assert!(matches!(
+            wallet.create_consolidation_psbt(
+                crate::bitcoin::FeeRate::from_sat_per_vb_unchecked(5),
+                1,
+                1,
+                Default::default()
+            ),
+            Err(crate::errors::Error::ConsolidationFeeRateTooHigh { .. })
+        ));
+        assert!(matches!(
+            wallet.create_consolidation_psbt(max_fee_rate, 1, 0, Default::default()),
+            Err(crate::errors::Error::ConsolidationFeeTooHigh { .. })
+        ));
+    }
+
+    #[test]
+    fn plan_renewal() {
+        let wallet = setup_wallet();
+        let present = get_present();
+        let horizon = 30 * 24 * 3600;
+
+        // Expected UTXOs, computed from the inheritance schedule
+        let schedule = wallet.get_inheritance_schedule().unwrap();
+        let expected_outpoints = wallet
+            .database()
+            .list_utxos()
+            .unwrap()
+            .into_iter()
+            .map(|hu| hu.outpoint)
+            .filter(|op| {
+                schedule
+                    .events
+                    .iter()
+                    .filter(|event| event.outpoint == *op)
+                    .map(|event| event.estimated_timestamp)
+                    .min()
+                    .is_some_and(|ts| ts <= present.timestamp + horizon)
+            })
+            .collect::<HashSet<_>>();
+        assert!(!expected_outpoints.is_empty());
+
+        let plan = wallet
+            .plan_renewal(
+                horizon,
+                CreatePsbtOptions {
+                    assume_blocktime: Some(present),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            plan.refreshed
+                .iter()
+                .map(|event| event.outpoint)
+                .collect::<HashSet<_>>(),
+            expected_outpoints
+        );
+        assert_eq!(
+            plan.psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|i| i.previous_output)
+                .collect::<HashSet<_>>(),
+            expected_outpoints
+        );
+        // A single output, going back to the wallet
+        assert_eq!(plan.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(plan.tx_summary.owned_outputs.len(), 1);
         assert_eq!(
-            wallet.get_balance().unwrap(),
-            new_wallet.get_balance().unwrap()
+            plan.tx_summary
+                .owned_inputs
+                .iter()
+                .map(|o| o.amount)
+                .sum::<Amount>(),
+            plan.tx_summary.owned_outputs[0].amount + plan.tx_summary.fee
         );
 
-        // Trying to restore another time should fail
-        assert!(new_wallet
-            .restore_backup(wallet.generate_backup().unwrap())
-            .is_err());
+        // Nothing to refresh in the far past
+        assert!(wallet
+            .plan_renewal(
+                0,
+                CreatePsbtOptions {
+                    assume_blocktime: Some(get_blocktime_for_timestamp(1_700_000_001)),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .is_none());
     }
 
     #[test]
-    fn list_wallet_addresses() {
-        // Empty wallet
-        let wallet = HeritageWallet::new(HeritageMemoryDatabase::new());
-        // Add AccountXPubs
-        wallet
-            .append_account_xpubs((0..3).into_iter().map(|i| get_test_account_xpub(i)))
-            .unwrap();
-        // Test the expected sequence of addresses
-        wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
-            .unwrap();
-        wallet.get_new_address().unwrap();
-        wallet.get_new_address().unwrap();
+    fn rotate_heir_key() {
+        let wallet = setup_wallet();
+        let present = get_present();
+        let options = CreatePsbtOptions {
+            assume_blocktime: Some(present),
+            ..Default::default()
+        };
+        let wife = get_test_heritage(TestHeritage::Wife).heir_config;
+        let brother = get_test_heritage(TestHeritage::Brother).heir_config;
+        let new_wife = HeirConfig::HeirXPubkey(get_test_account_xpub(9));
 
-        wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
-            .unwrap();
-        wallet.get_new_address().unwrap();
-        wallet.get_new_address().unwrap();
+        // The new heir must not be an heir already
+        assert!(wallet
+            .rotate_heir_key(&wife, brother.clone(), options.clone())
+            .is_err());
+        // The old heir must be an heir of the current HeritageConfig
+        assert!(wallet
+            .rotate_heir_key(
+                &new_wife,
+                HeirConfig::HeirXPubkey(get_test_account_xpub(8)),
+                options.clone()
+            )
+            .is_err());
+        assert!(wallet.list_heir_rotations().unwrap().is_empty());
 
-        wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+        let all_outpoints = wallet
+            .database()
+            .list_utxos()
+            .unwrap()
+            .into_iter()
+            .map(|hu| hu.outpoint)
+            .collect::<HashSet<_>>();
+        let rotation = wallet
+            .rotate_heir_key(&wife, new_wife.clone(), options.clone())
+            .unwrap();
+        let expected_heritage_config = get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+            .with_replaced_heir(&wife, new_wife.clone())
             .unwrap();
-        wallet.get_new_address().unwrap();
-        wallet.get_new_address().unwrap();
-
-        let results = wallet.list_wallet_addresses().unwrap();
-
-        // Expected addresses, in order
-        let expected_addresses = vec![
-            get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeBro,
-                1,
-            ),
-            get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeBro,
-                0,
-            ),
-            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY1, 1),
-            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY1, 0),
-            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY2, 1),
-            get_default_test_subwallet_config_expected_address(TestHeritageConfig::BackupWifeY2, 0),
-        ];
-
         assert_eq!(
-            results
-                .iter()
-                .map(|wa| wa.address().to_string())
-                .collect::<Vec<_>>(),
-            expected_addresses
+            wallet.get_current_heritage_config().unwrap(),
+            Some(expected_heritage_config.clone())
+        );
+        assert_eq!(rotation.rotation.old_heir_config, wife);
+        assert_eq!(rotation.rotation.new_heir_config, new_wife);
+        assert_eq!(rotation.rotation.heritage_config, expected_heritage_config);
+        assert_eq!(
+            wallet.list_heir_rotations().unwrap(),
+            vec![rotation.rotation]
         );
 
-        // The fingerprint should be the same for every WalletAddress origin
-        let fingerprint = get_test_account_xpub(0)
-            .descriptor_public_key()
-            .master_fingerprint();
-        assert!(results.iter().all(|wa| wa.origin().0 == fingerprint));
-
-        // Expected derivation paths, in order
-        let expected_derivation_paths = (0..3)
-            .map(|a_i| {
-                let axpub = get_test_account_xpub(a_i);
-                let dp = axpub
-                    .descriptor_public_key()
-                    .full_derivation_path()
-                    .unwrap();
-                dp.normal_children()
-                    .take(1)
-                    .map(|cdp| cdp.normal_children().take(2).collect::<Vec<_>>())
-                    .flatten()
-                    .collect::<Vec<_>>()
-            })
-            .flatten()
-            .rev()
-            .collect::<Vec<_>>();
+        // Every UTXO was spendable by the wife and must be moved
+        let (psbt, tx_summary) = rotation.refresh.unwrap();
         assert_eq!(
-            results
+            psbt.unsigned_tx
+                .input
                 .iter()
-                .map(|wa| wa.origin().1.clone())
-                .collect::<Vec<_>>(),
-            expected_derivation_paths
+                .map(|i| i.previous_output)
+                .collect::<HashSet<_>>(),
+            all_outpoints
         );
-    }
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(tx_summary.owned_outputs.len(), 1);
 
-    #[test]
-    fn list_unused_account_xpubs() {
-        let wallet = setup_wallet();
-        let expected = (3..10)
-            .into_iter()
-            .map(|i| get_test_account_xpub(i))
-            .collect::<Vec<_>>();
-        assert_eq!(wallet.list_unused_account_xpubs().unwrap(), expected)
+        // The old key is not an heir anymore
+        assert!(wallet.rotate_heir_key(&wife, brother, options).is_err());
     }
 
     #[test]
-    fn append_account_xpubs() {
+    fn plan_dissolution() {
+        use dissolution::DissolutionDestination;
+        let options = CreatePsbtOptions {
+            assume_blocktime: Some(get_present()),
+            ..Default::default()
+        };
+
         let wallet = setup_wallet();
-        let initial_used = wallet.list_used_account_xpubs().unwrap();
-        let mut initial_unused = wallet.list_unused_account_xpubs().unwrap();
-        let to_add = (0..15).into_iter().map(|i| get_test_account_xpub(i));
-        let expected_add = (10..15)
+        let all_outpoints = wallet
+            .database()
+            .list_utxos()
+            .unwrap()
             .into_iter()
-            .map(|i| get_test_account_xpub(i))
-            .collect::<Vec<_>>();
-
-        // Append the descriptors
-        assert!(wallet.append_account_xpubs(to_add).is_ok());
-
-        // Used ADs do not change
-        assert_eq!(wallet.list_used_account_xpubs().unwrap(), initial_used);
-
-        // Unused ADs change and is now initial_unused + expected_add
-        initial_unused.extend(expected_add);
-        assert_eq!(wallet.list_unused_account_xpubs().unwrap(), initial_unused);
-
-        // Cannot add an Account XPub with a different fingerprint
+            .map(|hu| hu.outpoint)
+            .collect::<HashSet<_>>();
+        let plan = wallet
+            .plan_dissolution(DissolutionDestination::Wallet, options.clone())
+            .unwrap();
+        // The new HeritageConfig has no heir and the previous one is obsolete
+        assert_eq!(plan.heritage_config.iter_heir_configs().count(), 0);
+        assert_eq!(
+            wallet.get_current_heritage_config().unwrap(),
+            Some(plan.heritage_config.clone())
+        );
         assert!(wallet
-            .append_account_xpubs([get_bad_account_xpub()])
-            .is_err_and(|e| match e {
-                crate::errors::Error::InvalidAccountXPub => true,
-                _ => false,
-            }));
-    }
+            .list_obsolete_heritage_configs()
+            .unwrap()
+            .contains(&get_test_heritage_config(TestHeritageConfig::BackupWifeBro)));
+        // Every UTXO is swept to a single key-path-only output of the wallet
+        let (psbt, tx_summary) = plan.sweep.unwrap();
+        assert_eq!(
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .map(|i| i.previous_output)
+                .collect::<HashSet<_>>(),
+            all_outpoints
+        );
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(tx_summary.owned_outputs.len(), 1);
+        let new_subwallet_config = wallet
+            .database()
+            .get_subwallet_config(SubwalletConfigId::Current)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            new_subwallet_config.ext_descriptor(),
+            Descriptor::Tr(tr) if tr.taptree().is_none()
+        ));
+
+        // Planning again keeps the HeritageConfig without heir
+        let plan = wallet
+            .plan_dissolution(DissolutionDestination::Wallet, options.clone())
+            .unwrap();
+        assert_eq!(
+            wallet.get_current_heritage_config().unwrap(),
+            Some(plan.heritage_config)
+        );
 
-    #[test]
-    fn get_current_heritage_config() {
+        // Sweeping to an external address
         let wallet = setup_wallet();
+        let external_address = string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let plan = wallet
+            .plan_dissolution(
+                DissolutionDestination::Address(external_address.clone()),
+                options,
+            )
+            .unwrap();
+        let (psbt, tx_summary) = plan.sweep.unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), all_outpoints.len());
+        assert_eq!(psbt.unsigned_tx.output.len(), 1);
         assert_eq!(
-            wallet.get_current_heritage_config().unwrap().unwrap(),
-            get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+            psbt.unsigned_tx.output[0].script_pubkey,
+            external_address.script_pubkey()
         );
+        assert!(tx_summary.owned_outputs.is_empty());
     }
 
     #[test]
-    fn list_obsolete_heritage_configs() {
+    fn plan_batch_payment() {
+        use batch::{Payee, PayeeList};
         let wallet = setup_wallet();
-        let expected = vec![
-            get_test_heritage_config(TestHeritageConfig::BackupWifeY2),
-            get_test_heritage_config(TestHeritageConfig::BackupWifeY1),
-        ];
-        assert_eq!(wallet.list_obsolete_heritage_configs().unwrap(), expected);
+        let payee = |address: &str, label: &str| Payee {
+            address: address.to_owned(),
+            amount_sat: 1_000_000,
+            label: Some(label.to_owned()),
+        };
+        let payees = PayeeList(vec![
+            payee(TR_EXTERNAL_RECIPIENT_ADDR, "Alice"),
+            payee(WPKH_EXTERNAL_RECIPIENT_ADDR, "Bob"),
+        ]);
+
+        // Everything in a single transaction
+        let batches = wallet
+            .plan_batch_payment(&payees, 10, CreatePsbtOptions::default())
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].payees, payees.0);
+
+        // One transaction per payee, without common input
+        let batches = wallet
+            .plan_batch_payment(&payees, 1, CreatePsbtOptions::default())
+            .unwrap();
+        assert_eq!(batches.len(), 2);
+        let mut inputs = HashSet::new();
+        for (batch, payee) in batches.iter().zip(payees.0.iter()) {
+            assert_eq!(batch.payees, vec![payee.clone()]);
+            let script_pubkey = string_to_address(&payee.address).unwrap().script_pubkey();
+            assert!(batch
+                .psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .any(|o| o.script_pubkey == script_pubkey && o.value == payee.amount_sat));
+            for txin in batch.psbt.unsigned_tx.input.iter() {
+                assert!(inputs.insert(txin.previous_output));
+            }
+        }
+
+        // Every payee is validated first
+        let invalid_payees = PayeeList(vec![
+            payee(TR_EXTERNAL_RECIPIENT_ADDR, "Alice"),
+            Payee {
+                amount_sat: 100,
+                ..payee(WPKH_EXTERNAL_RECIPIENT_ADDR, "Bob")
+            },
+        ]);
+        assert!(matches!(
+            wallet.plan_batch_payment(&invalid_payees, 1, CreatePsbtOptions::default()),
+            Err(crate::errors::Error::InvalidPayee { entry: 2, .. })
+        ));
+        assert!(matches!(
+            wallet.plan_batch_payment(&PayeeList::default(), 1, CreatePsbtOptions::default()),
+            Err(crate::errors::Error::InvalidPayeeList(_))
+        ));
     }
 
     #[test]
-    fn update_heritage_config() {
-        // Test on an empty wallet
-        let wallet = HeritageWallet::new(HeritageMemoryDatabase::new());
-        wallet
-            .append_account_xpubs((0..5).into_iter().map(|i| get_test_account_xpub(i)))
-            .unwrap();
-        // No current wallet
-        assert!(wallet.get_current_heritage_config().unwrap().is_none());
-        // Update is ok
-        assert!(wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
-            .is_ok());
-        // Current wallet is now the new one
-        assert_eq!(
-            wallet.get_current_heritage_config().unwrap().unwrap(),
-            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
-        );
-        // An account descriptor has been consumed
-        let used_axps = wallet.list_used_account_xpubs().unwrap();
-        assert!(used_axps.len() == 1 && used_axps[0] == get_test_account_xpub(0));
-        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
-        assert!(unused_axps.len() == 4 && unused_axps[0] == get_test_account_xpub(1));
-        // Now generate an address
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeY2,
-                0
-            )));
+    fn check_rotation_due() {
+        let wallet = setup_wallet();
+        let policy = RotationPolicy {
+            max_config_age_days: None,
+            renewal_window_days: 30,
+            renewal_threshold_percent: 50,
+        };
 
-        // As it as been used, replacing it should cause the creation of an obsolete HeritageConfig
-        // Update is ok
-        assert!(wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
-            .is_ok());
-        // Current wallet is now the new one
+        // Long before any heir can spend, nothing is due
         assert_eq!(
-            wallet.get_current_heritage_config().unwrap().unwrap(),
-            get_test_heritage_config(TestHeritageConfig::BackupWifeY1)
+            wallet.check_rotation_due_at(&policy, 1700000000).unwrap(),
+            RotationStatus {
+                heritage_config_update_due: false,
+                renewal_due: false,
+            }
         );
-        // An account descriptor has been consumed
-        let used_axps = wallet.list_used_account_xpubs().unwrap();
-        assert!(used_axps.len() == 2 && used_axps[1] == get_test_account_xpub(1));
-        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
-        assert!(unused_axps.len() == 3 && unused_axps[0] == get_test_account_xpub(2));
-        // Obsolete HeritageConfig
-        let obsolete_heritage_config = wallet.list_obsolete_heritage_configs().unwrap();
-        assert_eq!(obsolete_heritage_config.len(), 1);
+        // At present, heirs can spend 4 of the 5 UTXOs
         assert_eq!(
-            obsolete_heritage_config[0],
-            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+            wallet
+                .check_rotation_due_at(&policy, get_present().timestamp)
+                .unwrap(),
+            RotationStatus {
+                heritage_config_update_due: false,
+                renewal_due: true,
+            }
+        );
+        // Requiring every fund to mature is not met
+        assert!(
+            !wallet
+                .check_rotation_due_at(
+                    &RotationPolicy {
+                        renewal_threshold_percent: 100,
+                        ..policy
+                    },
+                    get_present().timestamp
+                )
+                .unwrap()
+                .renewal_due
         );
+        // Config age
+        assert!(
+            wallet
+                .check_rotation_due_at(
+                    &RotationPolicy {
+                        max_config_age_days: Some(10),
+                        ..policy
+                    },
+                    1763072000 + 10 * 24 * 3600
+                )
+                .unwrap()
+                .heritage_config_update_due
+        );
+        // The absolute lock of the first heir of the current HeritageConfig is close
+        assert!(
+            wallet
+                .check_rotation_due_at(
+                    &policy,
+                    get_absolute_inheritance_timestamp(
+                        TestHeritageConfig::BackupWifeBro,
+                        TestHeritage::Backup
+                    ) - 29 * 24 * 3600
+                )
+                .unwrap()
+                .heritage_config_update_due
+        );
+    }
+
+    #[test]
+    fn list_transaction_summaries() {
+        let wallet = setup_wallet();
+        let res = wallet.database().list_transaction_summaries();
+        assert!(res.is_ok(), "{:#}", res.unwrap_err());
+        let tx_sums = res.unwrap();
+        assert_eq!(tx_sums.len(), 5);
+        assert!(tx_sums.iter().all(|txs| txs.owned_outputs.len() == 1
+            && txs.owned_outputs[0].amount == Amount::from_btc(1.0).unwrap()
+            && txs.owned_inputs.len() == 0));
+    }
 
-        // As it as NOT been used, replacing it should NOT cause the creation of an obsolete HeritageConfig
-        // Update is ok
+    #[test]
+    fn reused_addresses() {
+        let wallet = setup_wallet();
+        assert!(wallet.list_reused_addresses().unwrap().is_empty());
         assert!(wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
-            .is_ok());
-        // Current wallet is now the new one
+            .database()
+            .list_utxos()
+            .unwrap()
+            .iter()
+            .all(|hu| !hu.address_reused));
+        let mut tx_sums = wallet.database().list_transaction_summaries().unwrap();
+        assert!(tx_sums.iter().all(|txs| !txs.address_reused));
+
+        // Make the second transaction pay to the address of the first one, twice
+        let reused_address = tx_sums[0].owned_outputs[0].address.clone();
+        tx_sums[1].owned_outputs[0].address = reused_address.clone();
+        let mut extra_output = tx_sums[1].owned_outputs[0].clone();
+        extra_output.outpoint.vout += 1;
+        tx_sums[1].owned_outputs.push(extra_output);
         assert_eq!(
-            wallet.get_current_heritage_config().unwrap().unwrap(),
-            get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+            super::reused_addresses(tx_sums.iter()),
+            vec![(reused_address, vec![tx_sums[0].txid, tx_sums[1].txid])]
         );
-        // An account descriptor has NOT been consumed, i.e. used_axps.len == 2
-        let used_axps = wallet.list_used_account_xpubs().unwrap();
-        assert!(used_axps.len() == 2);
-        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
-        assert!(unused_axps.len() == 3 && unused_axps[0] == get_test_account_xpub(2));
-        // Obsolete HeritageConfig is still len==1 and contains only BackupWifeY2
-        let obsolete_heritage_config = wallet.list_obsolete_heritage_configs().unwrap();
-        assert_eq!(obsolete_heritage_config.len(), 1);
+    }
+
+    #[test]
+    fn transaction_and_address_labels() {
+        let wallet = setup_wallet();
+        let new_address = wallet.get_new_address().unwrap();
+        let tx_sums = wallet.list_transaction_summaries().unwrap();
+        assert!(tx_sums.iter().all(|txs| txs.label.is_none()));
+        let wallet_addresses = wallet.list_wallet_addresses().unwrap();
+        assert!(wallet_addresses.len() > 1);
+        assert!(wallet_addresses.iter().all(|wa| wa.label().is_none()));
+        // The newest address of the current subwallet is listed first
+        assert_eq!(wallet_addresses[0].address(), &new_address);
+
+        // Label a transaction, a wallet address and an external address
+        let tx_ref = LabelRef::Tx(tx_sums[0].txid);
+        let addr_ref = LabelRef::Address(CheckedAddress::from(new_address));
+        let ext_addr_ref =
+            LabelRef::Address(CheckedAddress::try_from(TR_EXTERNAL_RECIPIENT_ADDR).unwrap());
+        wallet.set_label(&tx_ref, Some("Salary")).unwrap();
+        wallet.set_label(&addr_ref, Some("Employer")).unwrap();
+        wallet.set_label(&ext_addr_ref, Some("Exchange")).unwrap();
+        assert_eq!(wallet.list_labels().unwrap().len(), 3);
         assert_eq!(
-            obsolete_heritage_config[0],
-            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+            wallet.get_label(&ext_addr_ref).unwrap(),
+            Some("Exchange".to_owned())
         );
 
-        // Now generate an address
-        assert!(wallet.get_new_address().is_ok());
-        // Replace with the same HeritageConfig
-        // Update is ok
-        assert!(wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
-            .is_ok());
-        // Current wallet is now the new one
+        // Labels are surfaced in the listings
+        let tx_sums = wallet.list_transaction_summaries().unwrap();
+        assert_eq!(tx_sums[0].label.as_deref(), Some("Salary"));
+        assert!(tx_sums[1..].iter().all(|txs| txs.label.is_none()));
+        let wallet_addresses = wallet.list_wallet_addresses().unwrap();
+        assert_eq!(wallet_addresses[0].label(), Some("Employer"));
+        assert!(wallet_addresses[1..].iter().all(|wa| wa.label().is_none()));
+
+        // A labeled WalletAddress survives a serialization round-trip, an unlabeled one
+        // is still serialized as a plain string
+        let json = serde_json::to_string(&wallet_addresses[0]).unwrap();
+        assert!(json.contains("\"label\":\"Employer\""));
         assert_eq!(
-            wallet.get_current_heritage_config().unwrap().unwrap(),
-            get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+            serde_json::from_str::<WalletAddress>(&json).unwrap(),
+            wallet_addresses[0]
         );
-        // An account descriptor has NOT been consumed, i.e. used_axps.len == 2
-        let used_axps = wallet.list_used_account_xpubs().unwrap();
-        assert!(used_axps.len() == 2);
-        let unused_axps = wallet.list_unused_account_xpubs().unwrap();
-        assert!(unused_axps.len() == 3 && unused_axps[0] == get_test_account_xpub(2));
-        // Obsolete HeritageConfig is still len==1 and contains only BackupWifeY2
-        let obsolete_heritage_config = wallet.list_obsolete_heritage_configs().unwrap();
-        assert_eq!(obsolete_heritage_config.len(), 1);
+        let json = serde_json::to_string(&wallet_addresses[1]).unwrap();
+        assert_eq!(json, format!("\"{}\"", wallet_addresses[1]));
         assert_eq!(
-            obsolete_heritage_config[0],
-            get_test_heritage_config(TestHeritageConfig::BackupWifeY2)
+            serde_json::from_str::<WalletAddress>(&json).unwrap(),
+            wallet_addresses[1]
         );
 
-        // Test on wallet with only one AD
-        let wallet = HeritageWallet::new(HeritageMemoryDatabase::new());
-        wallet
-            .append_account_xpubs((0..1).into_iter().map(|i| get_test_account_xpub(i)))
-            .unwrap();
-        // Update is ok
-        assert!(wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
-            .is_ok());
-        // Now generate an address
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeY2,
-                0
-            )));
-        // Update fail with new HeritageConfig because no more ADs
+        // Remove a label
+        wallet.set_label(&tx_ref, None).unwrap();
+        assert!(wallet.get_label(&tx_ref).unwrap().is_none());
         assert!(wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
-            .is_err());
-        // Update succeed with the same HeritageConfig
-        assert!(wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
-            .is_ok());
+            .list_transaction_summaries()
+            .unwrap()
+            .iter()
+            .all(|txs| txs.label.is_none()));
     }
 
     #[test]
-    fn get_new_address() {
-        // Test on an empty wallet
-        let wallet = HeritageWallet::new(HeritageMemoryDatabase::new());
-        // Add AccountXPubs
+    fn bip329_labels_export_import() {
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
         wallet
             .append_account_xpubs((0..3).into_iter().map(|i| get_test_account_xpub(i)))
             .unwrap();
-        // Test the expected sequence of addresses
         wallet
             .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
             .unwrap();
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeY2,
-                0
-            )));
+        let obsolete_address = wallet.get_new_address().unwrap();
         wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
             .unwrap();
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeY2,
-                1,
-            )));
+        let current_address = wallet.get_new_address().unwrap();
 
+        let txid =
+            Txid::from_str("344dbc396e3c6945f46a67faab275141bb0fdd63f8a46362ba27e4753400d9c2")
+                .unwrap();
+        let labeled_outpoint = OutPoint { txid, vout: 0 };
+        let frozen_outpoint = OutPoint { txid, vout: 1 };
         wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .set_label(&LabelRef::Tx(txid), Some("Salary"))
             .unwrap();
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeY1,
-                0
-            )));
         wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .set_label(
+                &LabelRef::Address(CheckedAddress::from(obsolete_address.clone())),
+                Some("Old address"),
+            )
             .unwrap();
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeY1,
-                1,
-            )));
-
         wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .set_label(
+                &LabelRef::Address(CheckedAddress::from(current_address.clone())),
+                Some("New address"),
+            )
             .unwrap();
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeBro,
-                0
-            )));
         wallet
-            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .set_label(
+                &LabelRef::Address(CheckedAddress::try_from(TR_EXTERNAL_RECIPIENT_ADDR).unwrap()),
+                Some("Exchange"),
+            )
             .unwrap();
-        assert!(wallet.get_new_address().is_ok_and(|addr| addr.to_string()
-            == get_default_test_subwallet_config_expected_address(
-                TestHeritageConfig::BackupWifeBro,
-                1,
-            )));
-    }
+        wallet
+            .set_utxo_label(&labeled_outpoint, Some("Cold storage"))
+            .unwrap();
+        wallet.freeze_utxo(&frozen_outpoint).unwrap();
 
-    #[test]
-    fn get_set_block_inclusion_objective() {
-        // Test on an empty wallet
-        let wallet = HeritageWallet::new(HeritageMemoryDatabase::new());
+        let export = wallet.export_bip329_labels().unwrap();
+        assert_eq!(export.0.len(), 6);
+        let origin_of = |address: &str| {
+            export
+                .0
+                .iter()
+                .find(|l| l.label_ref == Bip329Ref::Addr(address.to_owned()))
+                .unwrap()
+                .origin
+                .clone()
+        };
+        // Both the obsolete and the current subwallet addresses carry their account origin
+        let obsolete_origin = origin_of(&obsolete_address.to_string()).unwrap();
+        let current_origin = origin_of(&current_address.to_string()).unwrap();
+        assert!(obsolete_origin.starts_with("tr(["));
+        assert!(current_origin.starts_with("tr(["));
+        assert_ne!(obsolete_origin, current_origin);
+        // External addresses have no origin
+        assert!(origin_of(TR_EXTERNAL_RECIPIENT_ADDR).is_none());
+        assert!(export.0.contains(&Bip329Label {
+            label_ref: Bip329Ref::Output(labeled_outpoint),
+            label: Some("Cold storage".to_owned()),
+            origin: None,
+            spendable: Some(true),
+        }));
+        assert!(export.0.contains(&Bip329Label {
+            label_ref: Bip329Ref::Output(frozen_outpoint),
+            label: None,
+            origin: None,
+            spendable: Some(false),
+        }));
+
+        // Import the JSONL export in a fresh wallet, along with an unsupported label
+        let jsonl = format!(
+            "{export}{{\"type\":\"xpub\",\"ref\":\"{}\",\"label\":\"Unsupported\"}}\n",
+            get_test_account_xpub(0)
+        );
+        let imported_labels = Bip329Labels::from_str(&jsonl).unwrap();
+        assert_eq!(imported_labels.0.len(), 7);
+        let other_wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
         assert_eq!(
-            wallet.get_block_inclusion_objective().unwrap(),
-            BlockInclusionObjective::default()
+            other_wallet.import_bip329_labels(&imported_labels).unwrap(),
+            6
+        );
+        assert_eq!(
+            other_wallet.list_labels().unwrap(),
+            wallet.list_labels().unwrap()
+        );
+        assert_eq!(
+            other_wallet.list_utxo_labels().unwrap(),
+            wallet.list_utxo_labels().unwrap()
+        );
+        assert_eq!(
+            other_wallet.list_frozen_utxos().unwrap(),
+            vec![frozen_outpoint]
         );
-        let new_bio = BlockInclusionObjective::from(50u16);
-        assert!(wallet.set_block_inclusion_objective(new_bio).is_ok());
-        assert_eq!(wallet.get_block_inclusion_objective().unwrap(), new_bio);
-
-        let new_bio = BlockInclusionObjective::from(30u16);
-        assert!(wallet.set_block_inclusion_objective(new_bio).is_ok());
-        assert_eq!(wallet.get_block_inclusion_objective().unwrap(), new_bio);
     }
 
     #[test]
-    fn wallet_first_use_time() {
-        let wallet = setup_wallet();
-        // Create a brand new HeritageConfig (which does not really make sense but we don't care for this test)
+    fn verify_address() {
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
         wallet
-            .update_heritage_config(
-                HeritageConfig::builder()
-                    .add_heritage(get_test_heritage(TestHeritage::Backup).time_lock(180))
-                    .reference_time(get_present().timestamp)
-                    .minimum_lock_time(10)
-                    .build(),
-            )
+            .append_account_xpubs((0..3).into_iter().map(|i| get_test_account_xpub(i)))
             .unwrap();
-
-        // At this stage, the current wallet is unused
+        // No subwallet at all
         assert!(wallet
-            .database()
-            .get_subwallet_config(SubwalletConfigId::Current)
+            .verify_address(&string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap())
             .unwrap()
-            .unwrap()
-            .subwallet_firstuse_time()
             .is_none());
 
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY2))
+            .unwrap();
+        let obsolete_address = wallet.get_new_address().unwrap();
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeY1))
+            .unwrap();
         wallet.get_new_address().unwrap();
-
-        // At this stage, the current wallet is used
-        assert!(wallet
-            .database()
-            .get_subwallet_config(SubwalletConfigId::Current)
-            .unwrap()
-            .unwrap()
-            .subwallet_firstuse_time()
-            .is_some());
-
-        // Create a brand new HeritageConfig (which does not really make sense but we don't care for this test)
+        let current_address = wallet.get_new_address().unwrap();
         wallet
-            .update_heritage_config(
-                HeritageConfig::builder()
-                    .add_heritage(get_test_heritage(TestHeritage::Backup).time_lock(360))
-                    .reference_time(get_present().timestamp)
-                    .minimum_lock_time(30)
-                    .build(),
+            .set_label(
+                &LabelRef::Address(CheckedAddress::from(current_address.clone())),
+                Some("Invoice #42"),
             )
             .unwrap();
-        // // Sync
-        // wallet
-        //     .sync(FakeBlockchainFactory {
-        //         current_height: get_present(),
-        //     })
-        //     .unwrap();
 
-        // At this stage, the current wallet is unused
+        let obsolete_origin = wallet.verify_address(&obsolete_address).unwrap().unwrap();
+        assert!(!obsolete_origin.is_current);
+        assert!(!obsolete_origin.is_change);
+        assert_eq!(obsolete_origin.wallet_address.address(), &obsolete_address);
+        assert!(obsolete_origin
+            .wallet_address
+            .origin()
+            .1
+            .to_string()
+            .ends_with("/0/0"));
+
+        let current_origin = wallet.verify_address(&current_address).unwrap().unwrap();
+        assert!(current_origin.is_current);
+        assert!(!current_origin.is_change);
+        assert_ne!(current_origin.subwallet_id, obsolete_origin.subwallet_id);
+        assert!(current_origin
+            .wallet_address
+            .origin()
+            .1
+            .to_string()
+            .ends_with("/0/1"));
+        assert_eq!(current_origin.wallet_address.label(), Some("Invoice #42"));
+
+        // Every listed address verifies with the same origin
+        for wallet_address in wallet.list_wallet_addresses().unwrap() {
+            let address_origin = wallet
+                .verify_address(wallet_address.address())
+                .unwrap()
+                .unwrap();
+            assert_eq!(address_origin.wallet_address, wallet_address);
+        }
+
+        // Foreign address
         assert!(wallet
-            .database()
-            .get_subwallet_config(SubwalletConfigId::Current)
+            .verify_address(&string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap())
             .unwrap()
-            .unwrap()
-            .subwallet_firstuse_time()
             .is_none());
+    }
 
-        wallet
-            .create_owner_psbt(
-                SpendingConfig::Recipients(vec![Recipient(
-                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                    Amount::from_sat(10_000),
-                )]),
-                Default::default(),
-            )
-            .unwrap();
+    #[test]
+    fn wallet_network() {
+        // Without a recorded network, the wallet cannot be opened
+        assert!(matches!(
+            HeritageWallet::new(HeritageMemoryDatabase::new()),
+            Err(crate::errors::Error::UnknownNetwork)
+        ));
 
-        // At this stage, the current wallet is used
-        assert!(wallet
-            .database()
-            .get_subwallet_config(SubwalletConfigId::Current)
-            .unwrap()
-            .unwrap()
-            .subwallet_firstuse_time()
-            .is_some());
+        let mut db = HeritageMemoryDatabase::new();
+        db.set_network(Network::Testnet).unwrap();
+        let wallet = HeritageWallet::new(db).unwrap();
+        assert_eq!(wallet.network(), Network::Testnet);
+
+        let mut db = HeritageMemoryDatabase::new();
+        db.set_network(Network::Testnet).unwrap();
+        assert!(matches!(
+            HeritageWallet::new_with_network(db, Network::Bitcoin),
+            Err(crate::errors::Error::NetworkMismatch {
+                expected: Network::Bitcoin,
+                found: Network::Testnet
+            })
+        ));
+
+        // The network is recorded in the database
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Bitcoin)
+                .unwrap();
+        assert_eq!(
+            wallet.database.borrow().get_network().unwrap(),
+            Some(Network::Bitcoin)
+        );
+        // Test network AccountXPubs are refused
+        assert!(matches!(
+            wallet.append_account_xpubs([get_test_account_xpub(0)]),
+            Err(crate::errors::Error::InvalidAccountXPub)
+        ));
     }
 
     #[test]
-    fn list_heritage_utxos() {
-        let wallet = setup_wallet();
-        let res = wallet.database().list_utxos();
-        assert!(res.is_ok(), "{:#}", res.unwrap_err());
-        let hus = res.unwrap();
-        assert_eq!(hus.len(), 5);
+    fn get_new_addresses() {
+        let wallet =
+            HeritageWallet::new_with_network(HeritageMemoryDatabase::new(), Network::Regtest)
+                .unwrap();
+        wallet
+            .append_account_xpubs((0..3).into_iter().map(|i| get_test_account_xpub(i)))
+            .unwrap();
+        // No subwallet yet
+        assert!(matches!(
+            wallet.get_new_addresses(1),
+            Err(crate::errors::Error::MissingCurrentSubwalletConfig)
+        ));
+        wallet
+            .update_heritage_config(get_test_heritage_config(TestHeritageConfig::BackupWifeBro))
+            .unwrap();
+        assert_eq!(wallet.get_gap_limit().unwrap(), GapLimit::default());
+        wallet.set_gap_limit(GapLimit::from(5)).unwrap();
+        assert_eq!(wallet.get_gap_limit().unwrap(), GapLimit::from(5));
 
-        // Control 2 UTXOs
-        // For this UTXO, the expected timestamps are the absolute timelocks expiration for each Heir
-        assert!(hus.iter().any(|hu| {
-            hu.confirmation_time.as_ref().unwrap().height == 897960
-                && hu.amount.to_sat() == 100_000_000
-                && hu.estimate_heir_spending_timestamp(
-                    get_test_heritage(TestHeritage::Backup).get_heir_config(),
-                ) == Some(1763072000)
-                && hu.estimate_heir_spending_timestamp(
-                    get_test_heritage(TestHeritage::Wife).get_heir_config(),
-                ) == Some(1766096000)
-                && hu.estimate_heir_spending_timestamp(
-                    get_test_heritage(TestHeritage::Brother).get_heir_config(),
-                ) == None
-        }));
+        let addresses = wallet.get_new_addresses(3).unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(
+            addresses[0].to_string(),
+            get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeBro,
+                0
+            )
+        );
+        assert_eq!(
+            addresses[1].to_string(),
+            get_default_test_subwallet_config_expected_address(
+                TestHeritageConfig::BackupWifeBro,
+                1
+            )
+        );
+        assert_ne!(addresses[2], addresses[1]);
+
+        // 3 unused addresses, 3 more would exceed the gap limit
+        assert!(matches!(
+            wallet.get_new_addresses(3),
+            Err(crate::errors::Error::GapLimitExceeded {
+                requested: 3,
+                unused: 3,
+                gap_limit: 5
+            })
+        ));
+        assert_eq!(wallet.get_new_addresses(2).unwrap().len(), 2);
+        assert!(wallet.get_new_addresses(1).is_err());
 
-        // For this UTXO, the expected timestamps are estimates based on the 90/180 days relative
-        // lock after the transaction is confirmed (transaction came in long after the absoltue locktime expiration)
-        assert!(hus.iter().any(|hu| {
-            hu.confirmation_time.as_ref().unwrap().height == 904440
-                && hu.amount.to_sat() == 100_000_000
-                && hu.estimate_heir_spending_timestamp(
-                    get_test_heritage(TestHeritage::Backup).get_heir_config(),
-                ) == Some(1760480000)
-                && hu.estimate_heir_spending_timestamp(
-                    get_test_heritage(TestHeritage::Wife).get_heir_config(),
-                ) == Some(1768256000)
-                && hu.estimate_heir_spending_timestamp(
-                    get_test_heritage(TestHeritage::Brother).get_heir_config(),
-                ) == None
-        }));
+        // Raising the gap limit allows more addresses
+        wallet.set_gap_limit(GapLimit::from(10)).unwrap();
+        assert_eq!(wallet.get_new_addresses(5).unwrap().len(), 5);
     }
 
     #[test]
-    fn list_transaction_summaries() {
+    fn get_history() {
         let wallet = setup_wallet();
-        let res = wallet.database().list_transaction_summaries();
-        assert!(res.is_ok(), "{:#}", res.unwrap_err());
-        let tx_sums = res.unwrap();
-        assert_eq!(tx_sums.len(), 5);
-        assert!(tx_sums.iter().all(|txs| txs.owned_outputs.len() == 1
-            && txs.owned_outputs[0].amount == Amount::from_btc(1.0).unwrap()
-            && txs.owned_inputs.len() == 0));
+        let history = wallet.get_history().unwrap();
+        assert_eq!(history.len(), 5);
+        // Newest first, the running balance of the newest is the wallet balance
+        assert_eq!(history[0].running_balance, Amount::from_btc(5.0).unwrap());
+        assert_eq!(history[4].running_balance, Amount::from_btc(1.0).unwrap());
+        for (i, entry) in history.iter().enumerate() {
+            assert_eq!(
+                entry.running_balance,
+                Amount::from_btc(5.0 - i as f64).unwrap()
+            );
+            assert_eq!(
+                entry.balance_delta,
+                Amount::from_btc(1.0).unwrap().to_signed().unwrap()
+            );
+            // Receiving only, no timelock refreshed and heirs only gain funds
+            assert!(!entry.refreshed_timelocks);
+            assert!(!entry.heir_eligibility_changes.is_empty());
+            assert!(entry
+                .heir_eligibility_changes
+                .iter()
+                .all(|hec| hec.change == EligibilityChange::Gained));
+        }
+        // The heirs are the ones of the HeritageConfig of the receiving subwallet
+        let current_heirs = get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+            .iter_heir_configs()
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            history[0]
+                .heir_eligibility_changes
+                .iter()
+                .map(|hec| hec.heir_config.clone())
+                .collect::<BTreeSet<_>>(),
+            current_heirs
+        );
+        // The summaries are the ones of list_transaction_summaries
+        assert_eq!(
+            history
+                .into_iter()
+                .map(|entry| entry.transaction_summary)
+                .collect::<Vec<_>>(),
+            wallet.list_transaction_summaries().unwrap()
+        );
+    }
+
+    #[test]
+    fn export_transaction_summaries_and_utxos() {
+        let wallet = setup_wallet();
+        let first_txid = wallet.list_transaction_summaries().unwrap()[0].txid;
+        wallet
+            .set_label(&LabelRef::Tx(first_txid), Some("Salary, March"))
+            .unwrap();
+
+        // CSV: header + one line per transaction
+        let csv = wallet
+            .export_transaction_summaries(export::ExportFormat::Csv)
+            .unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("txid,block_height,block_timestamp,subwallet_ids,"));
+        assert!(lines[1].starts_with(&first_txid.to_string()));
+        assert!(lines[1].contains(",100000000,1.00000000,0,0.00000000,"));
+        assert!(lines[1].ends_with(",\"Salary, March\""));
+
+        // JSON: stable records with amounts in sats and BTC
+        let json = wallet
+            .export_transaction_summaries(export::ExportFormat::Json)
+            .unwrap();
+        let records: Vec<export::TransactionExport> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records.len(), 5);
+        assert_eq!(records[0].txid, first_txid);
+        assert_eq!(records[0].label.as_deref(), Some("Salary, March"));
+        assert!(records.iter().all(|r| r.net_sat == 100_000_000
+            && r.net_btc == "1.00000000"
+            && r.subwallet_ids.len() == 1
+            && r.block_height.is_some()));
+        // The oldest transactions were received by the obsolete subwallets
+        let subwallet_ids = records
+            .iter()
+            .map(|r| r.subwallet_ids[0])
+            .collect::<HashSet<_>>();
+        assert_eq!(subwallet_ids.len(), 3);
+
+        // UTXOs
+        let utxos = wallet.database.borrow().list_utxos().unwrap();
+        let frozen = utxos[0].outpoint;
+        wallet.freeze_utxo(&frozen).unwrap();
+        let json = wallet.export_utxos(export::ExportFormat::Json).unwrap();
+        let records: Vec<export::UtxoExport> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records.len(), utxos.len());
+        assert!(records
+            .iter()
+            .all(|r| r.subwallet_id.is_some()
+                && r.amount_btc == export::format_btc(r.amount_sat as i64)));
+        assert_eq!(
+            records
+                .iter()
+                .filter(|r| r.frozen)
+                .map(|r| OutPoint {
+                    txid: r.txid,
+                    vout: r.vout
+                })
+                .collect::<Vec<_>>(),
+            vec![frozen]
+        );
+        let csv = wallet.export_utxos(export::ExportFormat::Csv).unwrap();
+        assert_eq!(csv.lines().count(), utxos.len() + 1);
     }
 
     #[test]
@@ -2446,20 +5980,23 @@ mod tests {
         let wallet = setup_wallet();
         let (psbt, tx_sum) = wallet
             .create_owner_psbt(
-                SpendingConfig::Recipients(vec![
-                    Recipient::from((
-                        string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                        Amount::from_btc(0.1).unwrap(),
-                    )),
-                    Recipient::from((
-                        string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                        Amount::from_btc(0.2).unwrap(),
-                    )),
-                    Recipient::from((
-                        string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                        Amount::from_btc(0.3).unwrap(),
-                    )),
-                ]),
+                SpendingConfig::Recipients {
+                    recipients: vec![
+                        Recipient::from((
+                            string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                            Amount::from_btc(0.1).unwrap(),
+                        )),
+                        Recipient::from((
+                            string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                            Amount::from_btc(0.2).unwrap(),
+                        )),
+                        Recipient::from((
+                            string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                            Amount::from_btc(0.3).unwrap(),
+                        )),
+                    ],
+                    change_to: None,
+                },
                 Default::default(),
             )
             .unwrap();
@@ -2624,20 +6161,23 @@ mod tests {
     #[test]
     fn create_owner_psbt_select_utxos() {
         let wallet = setup_wallet();
-        let spending_config = SpendingConfig::Recipients(vec![
-            Recipient::from((
-                string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                Amount::from_btc(0.1).unwrap(),
-            )),
-            Recipient::from((
-                string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                Amount::from_btc(0.2).unwrap(),
-            )),
-            Recipient::from((
-                string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                Amount::from_btc(0.3).unwrap(),
-            )),
-        ]);
+        let spending_config = SpendingConfig::Recipients {
+            recipients: vec![
+                Recipient::from((
+                    string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.1).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.2).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.3).unwrap(),
+                )),
+            ],
+            change_to: None,
+        };
 
         let outpoint_10 = OutPoint::from_str(
             "344dbc396e3c6945f46a67faab275141bb0fdd63f8a46362ba27e4753400d9c2:0",
@@ -2817,47 +6357,571 @@ mod tests {
             expected_values.remove(&input.previous_output);
         }
         assert!(expected_values.is_empty());
+
+        // The "UseOnly" behavior, mixing obsolete and current UTXOs
+        let options = CreatePsbtOptions {
+            utxo_selection: UtxoSelection::UseOnly(HashSet::from_iter(vec![
+                outpoint_11,
+                outpoint_30,
+            ])),
+            ..Default::default()
+        };
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), options)
+            .unwrap();
+        // This PSBT has exactly 2 inputs, corresponding to the UseOnly ones
+        assert_eq!(psbt.unsigned_tx.input.len(), 2);
+        let mut expected_values: HashSet<OutPoint> =
+            HashSet::from_iter(vec![outpoint_11, outpoint_30]);
+        // The inputs are expected
+        for input in psbt.unsigned_tx.input {
+            expected_values.remove(&input.previous_output);
+        }
+        assert!(expected_values.is_empty());
+
+        // The "UseOnly" behavior, with only obsolete UTXOs
+        let options = CreatePsbtOptions {
+            utxo_selection: UtxoSelection::UseOnly(HashSet::from_iter(vec![outpoint_21])),
+            ..Default::default()
+        };
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), options)
+            .unwrap();
+        // This PSBT has exactly 1 input, the UseOnly one
+        assert_eq!(psbt.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.unsigned_tx.input[0].previous_output, outpoint_21);
+
+        // The "Include" behavior with an obsolete UTXO is the same as the "normal" behavior
+        let options = CreatePsbtOptions {
+            utxo_selection: UtxoSelection::Include(vec![outpoint_10]),
+            ..Default::default()
+        };
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), options)
+            .unwrap();
+        let mut expected_values: HashSet<OutPoint> =
+            HashSet::from_iter(vec![outpoint_10, outpoint_11, outpoint_20, outpoint_21]);
+        for input in psbt.unsigned_tx.input {
+            expected_values.remove(&input.previous_output);
+        }
+        assert!(expected_values.is_empty());
+    }
+
+    #[test]
+    fn create_owner_psbt_frozen_utxos() {
+        let wallet = setup_wallet();
+        let spending_config = SpendingConfig::Recipients {
+            recipients: vec![
+                Recipient::from((
+                    string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.1).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.2).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.3).unwrap(),
+                )),
+            ],
+            change_to: None,
+        };
+
+        let outpoint_10 = OutPoint::from_str(
+            "344dbc396e3c6945f46a67faab275141bb0fdd63f8a46362ba27e4753400d9c2:0",
+        )
+        .unwrap();
+        let outpoint_11 = OutPoint::from_str(
+            "d2f3bd44fb6ad0c32833ea943d718e806245e632302f25720811fea167c13507:0",
+        )
+        .unwrap();
+        let outpoint_20 = OutPoint::from_str(
+            "2f0a77d510db56dda3b43692d4658a92f523193a3b854d2387681f2fd0f5d920:0",
+        )
+        .unwrap();
+        let outpoint_21 = OutPoint::from_str(
+            "3854db1cb2253a270e49a093a6ddb92fa79efd8b295568e08448e4de678fc08b:0",
+        )
+        .unwrap();
+        let outpoint_30 = OutPoint::from_str(
+            "6ed1563a936196211f2f76447c478533df8f3efc43933f4c3405b9a760b31204:0",
+        )
+        .unwrap();
+
+        // Labels are stored and retrieved
+        wallet.set_utxo_label(&outpoint_30, Some("Keep")).unwrap();
+        assert_eq!(
+            wallet.get_utxo_label(&outpoint_30).unwrap(),
+            Some("Keep".to_owned())
+        );
+        wallet.set_utxo_label(&outpoint_30, None).unwrap();
+        assert!(wallet.get_utxo_label(&outpoint_30).unwrap().is_none());
+
+        // Frozen obsolete UTXOs are not used
+        wallet.freeze_utxo(&outpoint_10).unwrap();
+        wallet.freeze_utxo(&outpoint_20).unwrap();
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), CreatePsbtOptions::default())
+            .unwrap();
+        // This PSBT has 2 inputs, corresponding to the 2 obsolete transaction that are not frozen
+        let mut expected_values: HashSet<OutPoint> =
+            HashSet::from_iter(vec![outpoint_11, outpoint_21]);
+        for input in psbt.unsigned_tx.input {
+            assert!(expected_values.remove(&input.previous_output));
+        }
+        assert!(expected_values.is_empty());
+
+        // Including a frozen UTXO is an error
+        let options = CreatePsbtOptions {
+            utxo_selection: UtxoSelection::Include(vec![outpoint_10, outpoint_30]),
+            ..Default::default()
+        };
+        assert!(wallet
+            .create_owner_psbt(spending_config.clone(), options)
+            .is_err_and(|err| match err {
+                crate::errors::Error::FrozenUtxoSelectionInclude(vec) => {
+                    vec == vec![outpoint_10]
+                }
+                _ => false,
+            }));
+
+        // With all the obsolete UTXOs frozen, the current one is used
+        wallet.freeze_utxo(&outpoint_11).unwrap();
+        wallet.freeze_utxo(&outpoint_21).unwrap();
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), CreatePsbtOptions::default())
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 1);
+        assert_eq!(psbt.unsigned_tx.input[0].previous_output, outpoint_30);
+
+        // With every UTXO frozen, nothing can be spent
+        wallet.freeze_utxo(&outpoint_30).unwrap();
+        assert!(wallet
+            .create_owner_psbt(spending_config.clone(), CreatePsbtOptions::default())
+            .is_err());
+
+        // Unfreezing makes the UTXOs usable again
+        for op in wallet.list_frozen_utxos().unwrap() {
+            wallet.unfreeze_utxo(&op).unwrap();
+        }
+        assert!(wallet.list_frozen_utxos().unwrap().is_empty());
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), CreatePsbtOptions::default())
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 4);
+    }
+
+    #[test]
+    fn create_owner_psbt_coin_selection() {
+        let wallet = setup_wallet();
+        let recipients = |btc: f64| SpendingConfig::Recipients {
+            recipients: vec![Recipient::from((
+                string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                Amount::from_btc(btc).unwrap(),
+            ))],
+            change_to: None,
+        };
+        let input_txids = |psbt: &crate::bitcoin::psbt::Psbt| {
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .map(|i| i.previous_output.txid.to_string())
+                .collect::<HashSet<_>>()
+        };
+        let current_txid = "6ed1563a936196211f2f76447c478533df8f3efc43933f4c3405b9a760b31204";
+        let y1_txids = HashSet::from([
+            "2f0a77d510db56dda3b43692d4658a92f523193a3b854d2387681f2fd0f5d920".to_owned(),
+            "3854db1cb2253a270e49a093a6ddb92fa79efd8b295568e08448e4de678fc08b".to_owned(),
+        ]);
+
+        // Default behavior drains the obsolete subwallets
+        let (psbt, _) = wallet
+            .create_owner_psbt(recipients(0.1), Default::default())
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 4);
+        assert!(!input_txids(&psbt).contains(current_txid));
+        let (psbt, _) = wallet
+            .create_owner_psbt(
+                recipients(0.1),
+                CreatePsbtOptions {
+                    coin_selection: CoinSelectionPolicy::OldestFirst,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 4);
+
+        // MinimizeFee only uses the current subwallet when possible
+        let options = CreatePsbtOptions {
+            coin_selection: CoinSelectionPolicy::MinimizeFee,
+            ..Default::default()
+        };
+        let (psbt, _) = wallet
+            .create_owner_psbt(recipients(0.1), options.clone())
+            .unwrap();
+        assert_eq!(input_txids(&psbt), HashSet::from([current_txid.to_owned()]));
+        // Fallback to the default behavior
+        let (psbt, _) = wallet.create_owner_psbt(recipients(1.5), options).unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 4);
+
+        // PrivacyPreferSingleSubwallet uses the most recent obsolete subwallet able to pay
+        let options = CreatePsbtOptions {
+            coin_selection: CoinSelectionPolicy::PrivacyPreferSingleSubwallet,
+            ..Default::default()
+        };
+        let (psbt, _) = wallet
+            .create_owner_psbt(recipients(0.1), options.clone())
+            .unwrap();
+        assert_eq!(input_txids(&psbt), HashSet::from([current_txid.to_owned()]));
+        let (psbt, _) = wallet
+            .create_owner_psbt(recipients(1.5), options.clone())
+            .unwrap();
+        assert_eq!(input_txids(&psbt), y1_txids);
+        // No single subwallet can pay, fallback to the default behavior
+        let (psbt, _) = wallet.create_owner_psbt(recipients(2.5), options).unwrap();
+        assert_eq!(psbt.unsigned_tx.input.len(), 4);
+    }
+
+    #[test]
+    fn create_owner_psbt_disable_rbf() {
+        let wallet = setup_wallet();
+        let spending_config = SpendingConfig::Recipients {
+            recipients: vec![
+                Recipient::from((
+                    string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.1).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.2).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.3).unwrap(),
+                )),
+            ],
+            change_to: None,
+        };
+
+        // The "normal" behavior
+        let options = CreatePsbtOptions {
+            disable_rbf: false,
+            ..Default::default()
+        };
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), options)
+            .unwrap();
+        // The transaction is RBF
+        assert!(psbt.unsigned_tx.input.iter().any(|i| i.sequence.is_rbf()));
+
+        // The "disable RBF" behavior
+        let options = CreatePsbtOptions {
+            disable_rbf: true,
+            ..Default::default()
+        };
+        let (psbt, _) = wallet
+            .create_owner_psbt(spending_config.clone(), options)
+            .unwrap();
+        // The transaction is RBF
+        assert!(psbt.unsigned_tx.input.iter().all(|i| !i.sequence.is_rbf()));
+    }
+
+    #[test]
+    fn create_owner_psbt_lock_time_policy() {
+        let wallet = setup_wallet();
+        let height = wallet.get_sync_time().unwrap().unwrap().height;
+        let spending_config =
+            SpendingConfig::DrainTo(string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap());
+        let lock_time = |lock_time_policy| {
+            let options = CreatePsbtOptions {
+                lock_time_policy,
+                ..Default::default()
+            };
+            let (psbt, _) = wallet
+                .create_owner_psbt(spending_config.clone(), options)
+                .unwrap();
+            psbt.unsigned_tx.lock_time.to_consensus_u32()
+        };
+
+        // Anti-fee-sniping by default
+        assert_eq!(lock_time(LockTimePolicy::default()), height);
+        assert_eq!(lock_time(LockTimePolicy::None), 0);
+        for _ in 0..20 {
+            let core_lock_time = lock_time(LockTimePolicy::BitcoinCore);
+            assert!(height - 99 <= core_lock_time && core_lock_time <= height);
+        }
+    }
+
+    #[test]
+    fn create_owner_psbt_output_ordering() {
+        let wallet = setup_wallet();
+        let spending_config = SpendingConfig::Recipients {
+            recipients: vec![
+                Recipient::from((
+                    string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.3).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.1).unwrap(),
+                )),
+                Recipient::from((
+                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                    Amount::from_btc(0.2).unwrap(),
+                )),
+            ],
+            change_to: None,
+        };
+        let change_position = |output_ordering| {
+            let options = CreatePsbtOptions {
+                output_ordering,
+                ..Default::default()
+            };
+            let (psbt, tx_sum) = wallet
+                .create_owner_psbt(spending_config.clone(), options)
+                .unwrap();
+            assert_eq!(psbt.unsigned_tx.output.len(), psbt.outputs.len());
+            // The recipients are external so the only owned output is the change
+            assert_eq!(tx_sum.owned_outputs.len(), 1);
+            let change_script = tx_sum.owned_outputs[0].address.script_pubkey();
+            let position = psbt
+                .unsigned_tx
+                .output
+                .iter()
+                .position(|o| o.script_pubkey == change_script)
+                .unwrap();
+            (psbt, position)
+        };
+
+        // BIP-69: sorted by amount then script, even after the fee adjustment
+        let (psbt, _) = change_position(OutputOrdering::Bip69);
+        assert!(psbt
+            .unsigned_tx
+            .output
+            .windows(2)
+            .all(|w| (w[0].value, &w[0].script_pubkey) <= (w[1].value, &w[1].script_pubkey)));
+
+        // Shuffle: the change is not always at the same position
+        let positions = (0..20)
+            .map(|_| change_position(OutputOrdering::Shuffle).1)
+            .collect::<HashSet<_>>();
+        assert!(positions.len() > 1);
+    }
+
+    #[test]
+    fn create_owner_psbt_change_to() {
+        let wallet = setup_wallet();
+        let recipient = string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let change_to = string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let spending_config = |change_to| SpendingConfig::Recipients {
+            recipients: vec![Recipient::from((
+                recipient.clone(),
+                Amount::from_btc(0.1).unwrap(),
+            ))],
+            change_to,
+        };
+
+        // The "normal" behavior, the change goes to a new internal address
+        let (psbt, tx_sum) = wallet
+            .create_owner_psbt(spending_config(None), CreatePsbtOptions::default())
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+        assert_eq!(tx_sum.owned_outputs.len(), 1);
+
+        // The change goes to the given address
+        let (psbt, tx_sum) = wallet
+            .create_owner_psbt(
+                spending_config(Some(change_to.clone())),
+                CreatePsbtOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+        assert!(tx_sum.owned_outputs.is_empty());
+        let change_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == change_to.script_pubkey())
+            .expect("the change goes to change_to");
+        assert!(change_output.value > Amount::from_btc(0.1).unwrap().to_sat());
+
+        // Never send the change to an address of another network
+        let other_network =
+            crate::bitcoin::Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq")
+                .unwrap()
+                .assume_checked();
+        assert!(wallet
+            .create_owner_psbt(
+                spending_config(Some(other_network)),
+                CreatePsbtOptions::default()
+            )
+            .is_err_and(|e| matches!(e, crate::errors::Error::InvalidAddressString(..))));
     }
 
     #[test]
-    fn create_owner_psbt_disable_rbf() {
+    fn estimate_fee() {
         let wallet = setup_wallet();
-        let spending_config = SpendingConfig::Recipients(vec![
-            Recipient::from((
-                string_to_address(PKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                Amount::from_btc(0.1).unwrap(),
-            )),
-            Recipient::from((
-                string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                Amount::from_btc(0.2).unwrap(),
-            )),
-            Recipient::from((
+        let fee_rate = crate::bitcoin::FeeRate::from_sat_per_vb_unchecked(20);
+        let spending_config = SpendingConfig::Recipients {
+            recipients: vec![Recipient::from((
                 string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                Amount::from_btc(0.3).unwrap(),
-            )),
-        ]);
+                Amount::from_btc(0.1).unwrap(),
+            ))],
+            change_to: None,
+        };
 
-        // The "normal" behavior
+        let fee_preview = wallet
+            .estimate_fee(spending_config.clone(), fee_rate, Default::default())
+            .unwrap();
+        // Same result each time
+        assert_eq!(
+            wallet
+                .estimate_fee(spending_config.clone(), fee_rate, Default::default())
+                .unwrap(),
+            fee_preview
+        );
+        // The fee is the one of the real transaction
+        let (psbt, tx_sum) = wallet
+            .create_owner_psbt(
+                spending_config.clone(),
+                CreatePsbtOptions {
+                    fee_policy: Some(FeePolicy::FeeRate(fee_rate)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(fee_preview.fee, tx_sum.fee);
+        assert_eq!(
+            fee_preview.vsize,
+            get_expected_tx_weight(&psbt).to_vbytes_ceil()
+        );
+        assert!(fee_preview.fee.to_sat() >= 20 * (fee_preview.vsize - 1));
+        assert_eq!(
+            fee_preview
+                .subwallet_inputs
+                .iter()
+                .map(|(_, count)| count)
+                .sum::<usize>(),
+            psbt.unsigned_tx.input.len()
+        );
+
+        // Draining spends every UTXO
+        let fee_preview = wallet
+            .estimate_fee(
+                SpendingConfig::DrainTo(string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap()),
+                fee_rate,
+                Default::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            fee_preview
+                .subwallet_inputs
+                .iter()
+                .map(|(_, count)| count)
+                .sum::<usize>(),
+            wallet.database.borrow().list_utxos().unwrap().len()
+        );
+
+        // The options are honored, only the fee policy is replaced
         let options = CreatePsbtOptions {
-            disable_rbf: false,
+            fee_policy: Some(FeePolicy::Absolute(Amount::from_sat(100))),
+            coin_selection: CoinSelectionPolicy::MinimizeFee,
             ..Default::default()
         };
-        let (psbt, _) = wallet
-            .create_owner_psbt(spending_config.clone(), options)
+        let fee_preview = wallet
+            .estimate_fee(spending_config.clone(), fee_rate, options.clone())
             .unwrap();
-        // The transaction is RBF
-        assert!(psbt.unsigned_tx.input.iter().any(|i| i.sequence.is_rbf()));
+        let (psbt, tx_sum) = wallet
+            .create_owner_psbt(
+                spending_config,
+                CreatePsbtOptions {
+                    fee_policy: Some(FeePolicy::FeeRate(fee_rate)),
+                    ..options
+                },
+            )
+            .unwrap();
+        assert_eq!(fee_preview.fee, tx_sum.fee);
+        assert_eq!(
+            fee_preview.vsize,
+            get_expected_tx_weight(&psbt).to_vbytes_ceil()
+        );
+        // Only the UTXO of the current subwallet is spent
+        assert_eq!(psbt.unsigned_tx.input.len(), 1);
+        assert_eq!(fee_preview.subwallet_inputs.len(), 1);
+    }
 
-        // The "disable RBF" behavior
-        let options = CreatePsbtOptions {
-            disable_rbf: true,
-            ..Default::default()
+    #[test]
+    fn create_owner_psbt_max_recipient() {
+        let wallet = setup_wallet();
+        let recipient = string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let max_recipient = string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let spending_config = SpendingConfig::Recipients {
+            recipients: vec![
+                Recipient::max(max_recipient.clone()),
+                Recipient::from((recipient.clone(), Amount::from_btc(0.1).unwrap())),
+            ],
+            change_to: None,
         };
-        let (psbt, _) = wallet
-            .create_owner_psbt(spending_config.clone(), options)
+
+        let max_spendable = wallet
+            .estimate_max_spendable(spending_config.clone(), CreatePsbtOptions::default())
             .unwrap();
-        // The transaction is RBF
-        assert!(psbt.unsigned_tx.input.iter().all(|i| !i.sequence.is_rbf()));
+        let (psbt, tx_sum) = wallet
+            .create_owner_psbt(spending_config, CreatePsbtOptions::default())
+            .unwrap();
+        // Every UTXO is spent, without change
+        assert_eq!(psbt.unsigned_tx.output.len(), 2);
+        assert!(tx_sum.owned_outputs.is_empty());
+        let max_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == max_recipient.script_pubkey())
+            .unwrap();
+        assert_eq!(max_output.value, max_spendable.to_sat());
+        assert_eq!(
+            tx_sum.owned_inputs.iter().map(|o| o.amount).sum::<Amount>(),
+            max_spendable + Amount::from_btc(0.1).unwrap() + tx_sum.fee
+        );
+        // Without the other recipient, the maximum is the drain amount
+        let drain_max = wallet
+            .estimate_max_spendable(
+                SpendingConfig::DrainTo(max_recipient.clone()),
+                CreatePsbtOptions::default(),
+            )
+            .unwrap();
+        assert!(drain_max > max_spendable + Amount::from_btc(0.1).unwrap());
+
+        // Invalid uses of Recipient::max
+        let invalid_spending_configs = [
+            SpendingConfig::Recipients {
+                recipients: vec![
+                    Recipient::max(max_recipient.clone()),
+                    Recipient::max(recipient.clone()),
+                ],
+                change_to: None,
+            },
+            SpendingConfig::Recipients {
+                recipients: vec![Recipient::max(max_recipient.clone())],
+                change_to: Some(recipient.clone()),
+            },
+        ];
+        for spending_config in invalid_spending_configs {
+            assert!(wallet
+                .create_owner_psbt(spending_config, CreatePsbtOptions::default())
+                .is_err_and(|e| matches!(e, crate::errors::Error::InvalidSpendingConfig(_))));
+        }
+        // Nothing to estimate without Recipient::max
+        assert!(wallet
+            .estimate_max_spendable(
+                SpendingConfig::Recipients {
+                    recipients: vec![Recipient::from((recipient, Amount::from_btc(0.1).unwrap()))],
+                    change_to: None,
+                },
+                CreatePsbtOptions::default()
+            )
+            .is_err_and(|e| matches!(e, crate::errors::Error::InvalidSpendingConfig(_))));
     }
 
     #[test]
@@ -2993,14 +7057,17 @@ mod tests {
         let heir_config = get_test_heritage(TestHeritage::Backup)
             .get_heir_config()
             .clone();
-        // Heirs MUST use the Drain SpendingConfig
+        // Heirs cannot send more than what is eligible, fee included
         assert!(wallet
             .create_heir_psbt(
                 heir_config.clone(),
-                SpendingConfig::Recipients(vec![Recipient::from((
-                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                    Amount::from_btc(1.0).unwrap(),
-                ))]),
+                SpendingConfig::Recipients {
+                    recipients: vec![Recipient::from((
+                        string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                        Amount::from_btc(1.0).unwrap(),
+                    ))],
+                    change_to: None
+                },
                 CreatePsbtOptions {
                     assume_blocktime: Some(get_present()),
                     ..Default::default()
@@ -3167,14 +7234,17 @@ mod tests {
         let heir_config = get_test_heritage(TestHeritage::Wife)
             .get_heir_config()
             .clone();
-        // Heirs MUST use the Drain SpendingConfig
+        // Heirs cannot send more than what is eligible, fee included
         assert!(wallet
             .create_heir_psbt(
                 heir_config.clone(),
-                SpendingConfig::Recipients(vec![Recipient::from((
-                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                    Amount::from_btc(1.0).unwrap(),
-                ))]),
+                SpendingConfig::Recipients {
+                    recipients: vec![Recipient::from((
+                        string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                        Amount::from_btc(1.0).unwrap(),
+                    ))],
+                    change_to: None
+                },
                 CreatePsbtOptions {
                     assume_blocktime: Some(get_present()),
                     ..Default::default()
@@ -3235,68 +7305,325 @@ mod tests {
                 )
                 .unwrap(),
             )
-            .is_some_and(|(tap_leaf_hash, (key_fingerprint, derivation_path))| {
-                !tap_leaf_hash.is_empty()
-                    && *key_fingerprint == Fingerprint::from_str("c907dcb9").unwrap()
-                    && *derivation_path
-                        == DerivationPath::from_str(
-                            expected_values
-                                .get(&input.tap_internal_key.unwrap())
-                                .unwrap()[2],
-                        )
-                        .unwrap()
-            })
-            && input.tap_key_origins.len() == 1));
-        // There is one script and its the one expected for the heir
-        assert!(psbt.inputs.iter().all(|input| input.tap_scripts.len() == 1
-            && input.tap_scripts.first_key_value().is_some_and(|(_, v)| {
-                v.0.to_hex_string()
-                    == expected_values
-                        .get(&input.tap_internal_key.unwrap())
-                        .unwrap()[3]
-            })));
-        // 1 output
-        assert_eq!(psbt.outputs.len(), 1);
+            .is_some_and(|(tap_leaf_hash, (key_fingerprint, derivation_path))| {
+                !tap_leaf_hash.is_empty()
+                    && *key_fingerprint == Fingerprint::from_str("c907dcb9").unwrap()
+                    && *derivation_path
+                        == DerivationPath::from_str(
+                            expected_values
+                                .get(&input.tap_internal_key.unwrap())
+                                .unwrap()[2],
+                        )
+                        .unwrap()
+            })
+            && input.tap_key_origins.len() == 1));
+        // There is one script and its the one expected for the heir
+        assert!(psbt.inputs.iter().all(|input| input.tap_scripts.len() == 1
+            && input.tap_scripts.first_key_value().is_some_and(|(_, v)| {
+                v.0.to_hex_string()
+                    == expected_values
+                        .get(&input.tap_internal_key.unwrap())
+                        .unwrap()[3]
+            })));
+        // 1 output
+        assert_eq!(psbt.outputs.len(), 1);
+
+        // TX is v2
+        assert_eq!(psbt.unsigned_tx.version, 2);
+        // TX has the expected Locktime
+        assert_eq!(
+            psbt.unsigned_tx.lock_time,
+            LockTime::from_time(get_absolute_inheritance_timestamp(
+                TestHeritageConfig::BackupWifeY2,
+                TestHeritage::Wife,
+            ) as u32)
+            .unwrap()
+        );
+        // TX inputs have the expected sequence
+        assert!(psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .all(|input| input.sequence.enables_absolute_lock_time() && input.sequence.0 == 25920));
+        // The transaction is RBF
+        assert!(psbt.unsigned_tx.input.iter().any(|i| i.sequence.is_rbf()));
+
+        // PSBT is exactly the expected one
+        assert_eq!(psbt, get_test_unsigned_psbt(TestPsbt::WifePresent));
+
+        assert_eq!(tx_sum.confirmation_time, None);
+        // Receive nothing, heir is draining
+        assert_eq!(
+            tx_sum
+                .owned_outputs
+                .iter()
+                .map(|o| o.amount)
+                .sum::<Amount>(),
+            Amount::from_btc(0.0).unwrap()
+        );
+        // Uses only the eligible UTXO
+        assert_eq!(
+            tx_sum.owned_inputs.iter().map(|o| o.amount).sum::<Amount>(),
+            Amount::from_btc(1.0).unwrap()
+        );
+        assert_eq!(tx_sum.fee, Amount::from_btc(0.00001390).unwrap());
+    }
+
+    #[test]
+    fn create_wife_heir_psbt_with_change() {
+        let wallet = setup_wallet();
+        let heir_config = get_test_heritage(TestHeritage::Wife)
+            .get_heir_config()
+            .clone();
+        let drain_to = string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let change_to = string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let (psbt, tx_sum) = wallet
+            .create_heir_psbt(
+                heir_config,
+                SpendingConfig::DrainToWithChange {
+                    drain_to: drain_to.clone(),
+                    change_amount: Amount::from_btc(0.3).unwrap(),
+                    change_to: change_to.clone(),
+                },
+                CreatePsbtOptions {
+                    assume_blocktime: Some(get_present()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // Uses only the eligible UTXO, exactly like a DrainTo
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(
+            tx_sum.owned_inputs.iter().map(|o| o.amount).sum::<Amount>(),
+            Amount::from_btc(1.0).unwrap()
+        );
+        // 2 outputs: the change and the drain
+        assert_eq!(psbt.outputs.len(), 2);
+        let change_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == change_to.script_pubkey())
+            .unwrap();
+        assert_eq!(change_output.value, Amount::from_btc(0.3).unwrap().to_sat());
+        let drain_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == drain_to.script_pubkey())
+            .unwrap();
+        assert_eq!(
+            drain_output.value + tx_sum.fee.to_sat(),
+            Amount::from_btc(0.7).unwrap().to_sat()
+        );
+        assert_eq!(psbt.fee().unwrap(), tx_sum.fee);
+        // The transaction is still an Heir transaction
+        assert_eq!(psbt.unsigned_tx.version, 2);
+        assert_eq!(
+            psbt.unsigned_tx.lock_time,
+            LockTime::from_time(get_absolute_inheritance_timestamp(
+                TestHeritageConfig::BackupWifeY2,
+                TestHeritage::Wife,
+            ) as u32)
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn create_heir_psbt_spending_quota() {
+        let wallet = setup_wallet();
+        let heir_config = get_test_heritage(TestHeritage::Wife)
+            .get_heir_config()
+            .clone();
+        let drain_to = string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let external_change_to = string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let wallet_change_to = wallet.get_new_address().unwrap();
+        let options = CreatePsbtOptions {
+            assume_blocktime: Some(get_present()),
+            ..Default::default()
+        };
+
+        // A quota must be between 1 and 100 percent
+        assert!(matches!(
+            wallet.set_heir_spending_quota(heir_config.clone(), Some(0)),
+            Err(crate::errors::Error::InvalidSpendingQuota(0))
+        ));
+        assert!(matches!(
+            wallet.set_heir_spending_quota(heir_config.clone(), Some(101)),
+            Err(crate::errors::Error::InvalidSpendingQuota(101))
+        ));
+        // The Wife can only send 70% of the 1 BTC she is eligible to out of the wallet
+        wallet
+            .set_heir_spending_quota(heir_config.clone(), Some(70))
+            .unwrap();
+        assert_eq!(
+            wallet.get_heir_spending_quota(&heir_config).unwrap(),
+            Some(70)
+        );
+
+        // Draining everything is refused
+        assert!(matches!(
+            wallet.create_heir_psbt(
+                heir_config.clone(),
+                SpendingConfig::DrainTo(drain_to.clone()),
+                options.clone(),
+            ),
+            Err(crate::errors::Error::HeirSpendingQuotaExceeded { max_amount })
+                if max_amount == Amount::from_btc(0.7).unwrap()
+        ));
+        // Sending the change out of the wallet is refused
+        assert!(matches!(
+            wallet.create_heir_psbt(
+                heir_config.clone(),
+                SpendingConfig::DrainToWithChange {
+                    drain_to: drain_to.clone(),
+                    change_amount: Amount::from_btc(0.3).unwrap(),
+                    change_to: external_change_to,
+                },
+                options.clone(),
+            ),
+            Err(crate::errors::Error::HeirSpendingQuotaExceeded { .. })
+        ));
+        // Not sending enough back to the wallet is refused
+        assert!(matches!(
+            wallet.create_heir_psbt(
+                heir_config.clone(),
+                SpendingConfig::DrainToWithChange {
+                    drain_to: drain_to.clone(),
+                    change_amount: Amount::from_btc(0.2).unwrap(),
+                    change_to: wallet_change_to.clone(),
+                },
+                options.clone(),
+            ),
+            Err(crate::errors::Error::HeirSpendingQuotaExceeded { .. })
+        ));
+        // A change larger than the eligible funds is an error, not a panic
+        assert!(wallet
+            .create_heir_psbt(
+                heir_config.clone(),
+                SpendingConfig::DrainToWithChange {
+                    drain_to: drain_to.clone(),
+                    change_amount: Amount::from_btc(2.0).unwrap(),
+                    change_to: wallet_change_to.clone(),
+                },
+                options.clone(),
+            )
+            .is_err());
+        // Sending the rest back to the wallet is accepted
+        assert!(wallet
+            .create_heir_psbt(
+                heir_config.clone(),
+                SpendingConfig::DrainToWithChange {
+                    drain_to: drain_to.clone(),
+                    change_amount: Amount::from_btc(0.3).unwrap(),
+                    change_to: wallet_change_to,
+                },
+                options.clone(),
+            )
+            .is_ok());
+
+        // Without a quota, the heir can drain everything
+        wallet
+            .set_heir_spending_quota(heir_config.clone(), None)
+            .unwrap();
+        assert_eq!(wallet.get_heir_spending_quota(&heir_config).unwrap(), None);
+        assert!(wallet
+            .create_heir_psbt(heir_config, SpendingConfig::DrainTo(drain_to), options)
+            .is_ok());
+    }
+
+    #[test]
+    fn create_wife_heir_psbt_recipients() {
+        let wallet = setup_wallet();
+        let heir_config = get_test_heritage(TestHeritage::Wife)
+            .get_heir_config()
+            .clone();
+        let first_recipient = string_to_address(WPKH_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        let last_recipient = string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap();
+        // An Heir needs at least one recipient
+        assert!(wallet
+            .create_heir_psbt(
+                heir_config.clone(),
+                SpendingConfig::Recipients {
+                    recipients: vec![],
+                    change_to: None
+                },
+                CreatePsbtOptions {
+                    assume_blocktime: Some(get_present()),
+                    ..Default::default()
+                }
+            )
+            .is_err_and(|e| matches!(e, crate::errors::Error::InvalidSpendingConfigForHeir)));
+        // An Heir has no change
+        assert!(wallet
+            .create_heir_psbt(
+                heir_config.clone(),
+                SpendingConfig::Recipients {
+                    recipients: vec![Recipient::from((
+                        last_recipient.clone(),
+                        Amount::from_btc(0.5).unwrap()
+                    ))],
+                    change_to: Some(first_recipient.clone()),
+                },
+                CreatePsbtOptions {
+                    assume_blocktime: Some(get_present()),
+                    ..Default::default()
+                }
+            )
+            .is_err_and(|e| matches!(e, crate::errors::Error::InvalidSpendingConfigForHeir)));
 
-        // TX is v2
-        assert_eq!(psbt.unsigned_tx.version, 2);
-        // TX has the expected Locktime
+        let (psbt, tx_sum) = wallet
+            .create_heir_psbt(
+                heir_config,
+                SpendingConfig::Recipients {
+                    recipients: vec![
+                        Recipient::from((first_recipient.clone(), Amount::from_btc(0.3).unwrap())),
+                        Recipient::from((last_recipient.clone(), Amount::from_btc(0.5).unwrap())),
+                    ],
+                    change_to: None,
+                },
+                CreatePsbtOptions {
+                    assume_blocktime: Some(get_present()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+        // Uses only the eligible UTXO
+        assert_eq!(psbt.inputs.len(), 1);
         assert_eq!(
-            psbt.unsigned_tx.lock_time,
-            LockTime::from_time(get_absolute_inheritance_timestamp(
-                TestHeritageConfig::BackupWifeY2,
-                TestHeritage::Wife,
-            ) as u32)
-            .unwrap()
+            tx_sum.owned_inputs.iter().map(|o| o.amount).sum::<Amount>(),
+            Amount::from_btc(1.0).unwrap()
         );
-        // TX inputs have the expected sequence
+        // Exactly one output per recipient and no change for the owner
+        assert_eq!(psbt.outputs.len(), 2);
+        assert!(tx_sum.owned_outputs.is_empty());
         assert!(psbt
             .unsigned_tx
-            .input
+            .output
             .iter()
-            .all(|input| input.sequence.enables_absolute_lock_time() && input.sequence.0 == 25920));
-        // The transaction is RBF
-        assert!(psbt.unsigned_tx.input.iter().any(|i| i.sequence.is_rbf()));
-
-        // PSBT is exactly the expected one
-        assert_eq!(psbt, get_test_unsigned_psbt(TestPsbt::WifePresent));
-
-        assert_eq!(tx_sum.confirmation_time, None);
-        // Receive nothing, heir is draining
-        assert_eq!(
-            tx_sum
-                .owned_outputs
-                .iter()
-                .map(|o| o.amount)
-                .sum::<Amount>(),
-            Amount::from_btc(0.0).unwrap()
-        );
-        // Uses only the eligible UTXO
+            .all(|o| !wallet.is_mine(&o.script_pubkey).unwrap()));
+        // The first recipient receives its amount, the last one receives the remainder
+        let first_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == first_recipient.script_pubkey())
+            .unwrap();
+        assert_eq!(first_output.value, Amount::from_btc(0.3).unwrap().to_sat());
+        let last_output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .find(|o| o.script_pubkey == last_recipient.script_pubkey())
+            .unwrap();
         assert_eq!(
-            tx_sum.owned_inputs.iter().map(|o| o.amount).sum::<Amount>(),
-            Amount::from_btc(1.0).unwrap()
+            last_output.value + tx_sum.fee.to_sat(),
+            Amount::from_btc(0.7).unwrap().to_sat()
         );
-        assert_eq!(tx_sum.fee, Amount::from_btc(0.00001390).unwrap());
+        // The transaction is still an Heir transaction
+        assert_eq!(psbt.unsigned_tx.version, 2);
     }
 
     #[test]
@@ -3305,14 +7632,17 @@ mod tests {
         let heir_config = get_test_heritage(TestHeritage::Brother)
             .get_heir_config()
             .clone();
-        // Heirs MUST use the Drain SpendingConfig
+        // Heirs cannot send more than what is eligible, fee included
         assert!(wallet
             .create_heir_psbt(
                 heir_config.clone(),
-                SpendingConfig::Recipients(vec![Recipient::from((
-                    string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
-                    Amount::from_btc(1.0).unwrap(),
-                ))]),
+                SpendingConfig::Recipients {
+                    recipients: vec![Recipient::from((
+                        string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                        Amount::from_btc(1.0).unwrap(),
+                    ))],
+                    change_to: None
+                },
                 CreatePsbtOptions {
                     assume_blocktime: Some(get_present()),
                     ..Default::default()
@@ -3832,6 +8162,60 @@ mod tests {
         let tx = extract_tx(signed_psbt).unwrap();
         assert_eq!(tx.weight(), get_expected_tx_weight(&unsigned_psbt));
     }
+    #[test]
+    fn owner_quorum_psbt_minimization() {
+        let heritage_config = HeritageConfig::builder_v1()
+            .add_heritage(get_test_heritage(TestHeritage::Backup))
+            .add_heritage(get_test_heritage(TestHeritage::Wife))
+            .reference_time(1700000000)
+            .minimum_lock_time(90)
+            .owner_quorum(
+                OwnerQuorum::new(
+                    2,
+                    vec![get_test_account_xpub(10), get_test_account_xpub(11)],
+                )
+                .unwrap(),
+            )
+            .build();
+        let swc = SubwalletConfig::new(get_test_account_xpub(0), heritage_config);
+        let wallet = bdk::wallet::get_funded_wallet(&swc.ext_descriptor().to_string()).0;
+
+        // The owner quorum leaf comes right after the unspendable key-path
+        let mut tx_builder = wallet.build_tx();
+        tx_builder
+            .drain_wallet()
+            .drain_to(
+                string_to_address(TR_EXTERNAL_RECIPIENT_ADDR)
+                    .unwrap()
+                    .script_pubkey(),
+            )
+            .policy_path(
+                std::collections::BTreeMap::from([(
+                    wallet.policies(KeychainKind::External).unwrap().unwrap().id,
+                    vec![1],
+                )]),
+                KeychainKind::External,
+            );
+        let mut psbt = tx_builder.finish().unwrap().0;
+        assert_eq!(psbt.inputs.len(), 1);
+        assert_eq!(psbt.inputs[0].tap_scripts.len(), 3);
+
+        super::minimize_psbt_input_for_spender(&mut psbt.inputs[0], None);
+        // Only the owner leaf and its 3 keys remain
+        assert_eq!(psbt.inputs[0].tap_scripts.len(), 1);
+        assert_eq!(psbt.inputs[0].tap_key_origins.len(), 3);
+        assert!(psbt.inputs[0]
+            .tap_key_origins
+            .values()
+            .all(|(leaf_hashes, _)| leaf_hashes.len() == 1));
+
+        // The witness is larger than a key-path spend: 2 signatures, the script and the control block
+        assert!(
+            get_expected_tx_weight(&psbt)
+                > psbt.unsigned_tx.weight() + crate::bitcoin::Weight::from_wu(2 + 1 + 66 + 66)
+        );
+    }
+
     #[test]
     fn tx_weight_prediction() {
         _tx_weight_prediction(TestPsbt::OwnerRecipients);
@@ -4052,4 +8436,411 @@ mod tests {
 
         assert_eq!(tx_sum.fee, fee_amount);
     }
+
+    #[test]
+    fn plan_migration() {
+        use online::migration::MigrationSource;
+        let wallet = setup_wallet();
+        let blockchain_factory = FakeBlockchainFactory {
+            current_height: get_present(),
+        };
+
+        // Invalid descriptor
+        assert!(matches!(
+            wallet.plan_migration(
+                &MigrationSource {
+                    descriptor: "tr(not_a_key)".to_owned(),
+                    change_descriptor: None,
+                },
+                None,
+                &blockchain_factory,
+                None,
+            ),
+            Err(crate::errors::Error::InvalidMigrationSource(_))
+        ));
+
+        // No funds to migrate, nothing changes in the wallet
+        let addresses_count = wallet.list_wallet_addresses().unwrap().len();
+        let empty_source = MigrationSource {
+            descriptor: format!("tr({})", get_test_account_xpub_str(15)),
+            change_descriptor: None,
+        };
+        assert!(matches!(
+            wallet.plan_migration(&empty_source, None, &blockchain_factory, None),
+            Err(crate::errors::Error::InvalidMigrationSource(_))
+        ));
+        assert_eq!(
+            wallet.list_wallet_addresses().unwrap().len(),
+            addresses_count
+        );
+
+        // Use the descriptors of the first subwallet as a foreign watch-only wallet
+        let swc = get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY2);
+        let source = MigrationSource {
+            descriptor: swc.ext_descriptor().to_string(),
+            change_descriptor: Some(swc.internal_descriptor().to_string()),
+        };
+        let plan = wallet
+            .plan_migration(&source, None, &blockchain_factory, None)
+            .unwrap();
+        assert_eq!(
+            plan.heritage_config,
+            get_test_heritage_config(TestHeritageConfig::BackupWifeBro)
+        );
+        assert_eq!(
+            wallet.list_wallet_addresses().unwrap().len(),
+            addresses_count + 1
+        );
+        assert!(!plan.signed);
+        assert_eq!(plan.amount, Amount::from_btc(2.0).unwrap());
+        assert!(plan.fee > Amount::ZERO);
+        assert_eq!(plan.psbt.inputs.len(), 2);
+        assert_eq!(plan.psbt.unsigned_tx.output.len(), 1);
+        assert_eq!(
+            plan.psbt.unsigned_tx.output[0].script_pubkey,
+            plan.receive_address.script_pubkey()
+        );
+        assert_eq!(
+            Amount::from_sat(plan.psbt.unsigned_tx.output[0].value) + plan.fee,
+            plan.amount
+        );
+    }
+
+    #[test]
+    fn shared_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<HeritageWallet<HeritageMemoryDatabase>>();
+
+        let wallet = std::sync::Arc::new(setup_wallet());
+        let expected_balance = wallet.get_balance().unwrap();
+        let addresses_count = wallet.list_wallet_addresses().unwrap().len();
+        let handles = (0..4)
+            .map(|_| {
+                let wallet = wallet.clone();
+                std::thread::spawn(move || {
+                    assert_eq!(wallet.get_balance().unwrap(), expected_balance);
+                    wallet.get_new_address().unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let addresses = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<HashSet<_>>();
+        // Every thread got its own address
+        assert_eq!(addresses.len(), 4);
+        assert_eq!(
+            wallet.list_wallet_addresses().unwrap().len(),
+            addresses_count + 4
+        );
+    }
+
+    #[test]
+    fn reads_while_another_thread_writes() {
+        // The methods going through every subwallet must not hold several read guards at
+        // the same time, or they could deadlock with a writer waiting in between
+        let wallet = std::sync::Arc::new(setup_wallet());
+        let writer = {
+            let wallet = wallet.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    wallet
+                        .sync(&FakeBlockchainFactory {
+                            current_height: get_present(),
+                        })
+                        .unwrap();
+                }
+            })
+        };
+        let txid =
+            Txid::from_str("6ed1563a936196211f2f76447c478533df8f3efc43933f4c3405b9a760b31204")
+                .unwrap();
+        let new_fee_rate = bdk::bitcoin::FeeRate::from_sat_per_vb(20).unwrap();
+        for _ in 0..50 {
+            assert_eq!(wallet.generate_backup().unwrap().0.len(), 3);
+            assert_eq!(wallet.get_all_subwallets().unwrap().len(), 3);
+            assert!(wallet.resume_pending_operation().unwrap().is_none());
+            assert!(matches!(
+                wallet.create_replacement_psbt(txid, new_fee_rate),
+                Err(crate::errors::Error::TransactionAlreadyConfirmed(t)) if t == txid
+            ));
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn prune() {
+        use prune::PruneOptions;
+        const SEC_IN_A_DAY: u64 = 24 * 60 * 60;
+        let wallet = setup_wallet();
+        let present = get_present().timestamp;
+        let options = PruneOptions {
+            retention_secs: 30 * SEC_IN_A_DAY,
+            dry_run: false,
+        };
+
+        // Obsolete subwallets with funds are never pruned
+        assert!(wallet.prune_at(options, present).unwrap().is_empty());
+
+        // Simulate that the funds of the first obsolete subwallet were spent
+        let swc = get_default_test_subwallet_config(TestHeritageConfig::BackupWifeY2);
+        let mut subdatabase = wallet
+            .database()
+            .get_subdatabase(SubdatabaseId::from(swc.subwallet_id()))
+            .unwrap();
+        for mut local_utxo in subdatabase.iter_utxos().unwrap() {
+            local_utxo.is_spent = true;
+            subdatabase.set_utxo(&local_utxo).unwrap();
+        }
+        let outpoints = wallet
+            .database()
+            .list_utxos()
+            .unwrap()
+            .into_iter()
+            .filter(|hu| hu.heritage_config == *swc.heritage_config())
+            .map(|hu| hu.outpoint)
+            .collect::<Vec<_>>();
+        wallet
+            .database
+            .borrow_mut()
+            .delete_utxos(&outpoints)
+            .unwrap();
+        let tx_summaries = wallet.list_transaction_summaries().unwrap();
+        let addresses = wallet.list_wallet_addresses().unwrap();
+
+        // The retention period is not over yet
+        let last_activity_ts = 1752704000;
+        assert!(wallet
+            .prune_at(
+                PruneOptions {
+                    retention_secs: present - last_activity_ts + 1,
+                    ..options
+                },
+                present
+            )
+            .unwrap()
+            .is_empty());
+
+        // A dry run does not remove anything
+        let dry_run = PruneOptions {
+            dry_run: true,
+            ..options
+        };
+        let report = wallet.prune_at(dry_run, present).unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].subwallet_id, swc.subwallet_id());
+        assert_eq!(report[0].raw_transactions, 2);
+        assert!(report[0].script_pubkeys > 0);
+        assert_eq!(wallet.prune_at(dry_run, present).unwrap(), report);
+
+        assert_eq!(wallet.prune_at(options, present).unwrap(), report);
+        let report = wallet.prune_at(options, present).unwrap();
+        assert_eq!(report[0].raw_transactions, 0);
+        assert_eq!(report[0].script_pubkeys, 0);
+        assert_eq!(wallet.list_wallet_addresses().unwrap(), addresses);
+
+        // The TransactionSummaries of the pruned subwallet survive a synchronization
+        wallet
+            .sync_with_strategy(
+                &FakeBlockchainFactory {
+                    current_height: get_present(),
+                },
+                SyncStrategy::CurrentOnly,
+                noop_progress(),
+            )
+            .unwrap();
+        assert_eq!(wallet.list_transaction_summaries().unwrap(), tx_summaries);
+    }
+
+    #[test]
+    fn create_owner_missing_fee_rate() {
+        let mut db = HeritageMemoryDatabase::new();
+        db.put_subwallet_config(
+            SubwalletConfigId::Current,
+            &get_default_test_subwallet_config(TestHeritageConfig::BackupWifeBro),
+        )
+        .unwrap();
+        // Never synced, so no FeeRate in the database
+        let wallet = HeritageWallet::new_with_network(db, Network::Regtest).unwrap();
+        assert!(matches!(
+            wallet.create_owner_psbt(
+                SpendingConfig::DrainTo(string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap()),
+                Default::default(),
+            ),
+            Err(crate::errors::Error::MissingFeeRate)
+        ));
+    }
+
+    #[test]
+    fn sync_fee_rate() {
+        struct FixedFeeEstimator(u64);
+        impl FeeEstimator for FixedFeeEstimator {
+            fn estimate_fee_rate(
+                &self,
+                _block_inclusion_objective: BlockInclusionObjective,
+            ) -> crate::errors::Result<bdk::bitcoin::FeeRate> {
+                Ok(bdk::bitcoin::FeeRate::from_sat_per_vb_unchecked(self.0))
+            }
+        }
+
+        let wallet = setup_wallet();
+        let (_, tx_sum_10) = wallet
+            .create_owner_psbt(
+                SpendingConfig::DrainTo(string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap()),
+                Default::default(),
+            )
+            .unwrap();
+
+        let fee_rate = wallet.sync_fee_rate(&FixedFeeEstimator(20)).unwrap();
+        assert_eq!(
+            fee_rate,
+            bdk::bitcoin::FeeRate::from_sat_per_vb_unchecked(20)
+        );
+        assert_eq!(wallet.database().get_fee_rate().unwrap(), Some(fee_rate));
+        let (_, tx_sum_20) = wallet
+            .create_owner_psbt(
+                SpendingConfig::DrainTo(string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap()),
+                Default::default(),
+            )
+            .unwrap();
+        assert!(tx_sum_20.fee > tx_sum_10.fee);
+
+        // A zero estimation is refused and the previous FeeRate is kept
+        assert!(wallet.sync_fee_rate(&FixedFeeEstimator(0)).is_err());
+        assert_eq!(wallet.database().get_fee_rate().unwrap(), Some(fee_rate));
+    }
+
+    #[test]
+    fn create_replacement_psbt() {
+        let wallet = setup_wallet();
+        let (psbt, tx_sum) = wallet
+            .create_owner_psbt(
+                SpendingConfig::Recipients {
+                    recipients: vec![Recipient::from((
+                        string_to_address(TR_EXTERNAL_RECIPIENT_ADDR).unwrap(),
+                        Amount::from_btc(0.1).unwrap(),
+                    ))],
+                    change_to: None,
+                },
+                Default::default(),
+            )
+            .unwrap();
+        let txid = psbt.unsigned_tx.txid();
+        let new_fee_rate = bdk::bitcoin::FeeRate::from_sat_per_vb(20).unwrap();
+
+        // The transaction is not known yet
+        assert!(matches!(
+            wallet.create_replacement_psbt(txid, new_fee_rate),
+            Err(crate::errors::Error::UnknownTransaction(t)) if t == txid
+        ));
+
+        // Simulate the broadcast and synchronization of the transaction
+        let current_swc = wallet
+            .database()
+            .get_subwallet_config(SubwalletConfigId::Current)
+            .unwrap()
+            .unwrap();
+        let mut subdb = wallet
+            .database()
+            .get_subdatabase(SubdatabaseId::from(current_swc.subwallet_id()))
+            .unwrap();
+        let mut tx_details = TransactionDetails {
+            transaction: Some(psbt.unsigned_tx.clone()),
+            txid,
+            received: 0,
+            sent: 0,
+            fee: Some(tx_sum.fee.to_sat()),
+            confirmation_time: None,
+        };
+        subdb.set_tx(&tx_details).unwrap();
+
+        // The new fee rate must be higher than the original one
+        assert!(matches!(
+            wallet
+                .create_replacement_psbt(txid, bdk::bitcoin::FeeRate::from_sat_per_vb(5).unwrap()),
+            Err(crate::errors::Error::InsufficientReplacementFeeRate { .. })
+        ));
+        // A slightly higher fee rate does not pay for the relay of the replacement (BIP-125 rule 4)
+        assert!(matches!(
+            wallet.create_replacement_psbt(
+                txid,
+                bdk::bitcoin::FeeRate::from_sat_per_kwu(tx_sum.fee_rate.to_sat_per_kwu() + 50)
+            ),
+            Err(crate::errors::Error::InsufficientReplacementFee { original_fee, .. })
+                if original_fee == tx_sum.fee
+        ));
+
+        let (new_psbt, new_tx_sum) = wallet.create_replacement_psbt(txid, new_fee_rate).unwrap();
+        // Same inputs, all signaling RBF
+        assert_eq!(
+            new_psbt
+                .unsigned_tx
+                .input
+                .iter()
+                .map(|i| i.previous_output)
+                .collect::<Vec<_>>(),
+            psbt.unsigned_tx
+                .input
+                .iter()
+                .map(|i| i.previous_output)
+                .collect::<Vec<_>>()
+        );
+        assert!(new_psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .all(|i| i.sequence.is_rbf()));
+        assert_eq!(new_psbt.inputs.len(), psbt.inputs.len());
+        // Same outputs, only the change is lower
+        assert_eq!(
+            new_psbt.unsigned_tx.output.len(),
+            psbt.unsigned_tx.output.len()
+        );
+        let change_vout = tx_sum.owned_outputs[0].outpoint.vout as usize;
+        for (i, (new_o, o)) in new_psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .zip(psbt.unsigned_tx.output.iter())
+            .enumerate()
+        {
+            assert_eq!(new_o.script_pubkey, o.script_pubkey);
+            // The owned outputs keep their key origins
+            assert_eq!(
+                new_psbt.outputs[i].tap_internal_key,
+                psbt.outputs[i].tap_internal_key
+            );
+            assert_eq!(
+                new_psbt.outputs[i].tap_key_origins,
+                psbt.outputs[i].tap_key_origins
+            );
+            assert_eq!(
+                new_psbt.outputs[i].tap_key_origins.is_empty(),
+                i != change_vout
+            );
+            if i == change_vout {
+                assert_eq!(
+                    o.value - new_o.value,
+                    (new_tx_sum.fee - tx_sum.fee).to_sat()
+                );
+            } else {
+                assert_eq!(new_o.value, o.value);
+            }
+        }
+        // The fee is higher and coherent with the PSBT
+        assert!(
+            new_tx_sum.fee
+                >= tx_sum.fee + INCREMENTAL_RELAY_FEE_RATE * get_expected_tx_weight(&new_psbt)
+        );
+        assert_eq!(new_psbt.fee().unwrap(), new_tx_sum.fee);
+        assert_eq!(new_tx_sum.fee_rate, new_fee_rate);
+
+        // A confirmed transaction cannot be replaced
+        tx_details.confirmation_time = Some(get_present());
+        subdb.set_tx(&tx_details).unwrap();
+        assert!(matches!(
+            wallet.create_replacement_psbt(txid, new_fee_rate),
+            Err(crate::errors::Error::TransactionAlreadyConfirmed(t)) if t == txid
+        ));
+    }
 }
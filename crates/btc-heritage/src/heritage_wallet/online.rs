@@ -1,26 +1,106 @@
 use std::collections::{HashMap, HashSet};
 
 use bdk::{
-    blockchain::{log_progress, Blockchain, BlockchainFactory},
+    blockchain::{log_progress, Blockchain, BlockchainFactory, Capability, GetHeight, Progress},
     database::Database,
-    Balance, SyncOptions,
+    Balance, KeychainKind, SyncOptions,
 };
 
+use self::fee_estimator::{BlockchainFeeEstimator, FeeEstimator};
 use super::{
     HeritageUtxo, HeritageWallet, HeritageWalletBalance, SubwalletConfigId, TransactionSummary,
 };
 use crate::{
-    bitcoin::{Amount, FeeRate, OutPoint, Txid},
+    bitcoin::{Amount, FeeRate, OutPoint, Transaction, Txid},
     database::TransacHeritageDatabase,
-    errors::{DatabaseError, Error, Result},
-    heritage_wallet::TransactionSummaryOwnedIO,
-    subwallet_config::SubwalletConfig,
+    errors::{BroadcastError, DatabaseError, Error, Result},
+    heritage_wallet::{CheckedAddress, TransactionSummaryOwnedIO},
+    subwallet_config::{SubwalletConfig, SubwalletId},
     utils::sort_transactions_with_parents,
 };
 
+#[cfg(feature = "compact-filters")]
+pub mod compact_filters;
+#[cfg(feature = "esplora-async")]
+pub mod esplora;
+pub mod fee_estimator;
+pub mod migration;
+
+/// Wraps a [Progress] hook to report the progress of the synchronization of one subwallet
+/// as a slice of the progress of the whole [HeritageWallet] synchronization
+#[derive(Debug)]
+struct SubwalletProgress<P: Progress> {
+    inner: P,
+    subwallet_id: SubwalletId,
+    offset: f32,
+    span: f32,
+}
+
+impl<P: Progress> Progress for SubwalletProgress<P> {
+    fn update(
+        &self,
+        progress: f32,
+        message: Option<String>,
+    ) -> core::result::Result<(), bdk::Error> {
+        self.inner.update(
+            self.offset + progress * self.span / 100.0,
+            message.map(|m| format!("Subwallet {}: {m}", self.subwallet_id)),
+        )
+    }
+}
+
+/// Strategy used by [HeritageWallet::sync_with_strategy] to decide which subwallets are
+/// synchronized with the blockchain
+///
+/// Whatever the strategy, the current subwallet is always synchronized and the
+/// subwallets that are not synchronized still contribute their locally known
+/// transactions to the [HeritageWallet] state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncStrategy {
+    /// Synchronize every subwallet
+    #[default]
+    Full,
+    /// Only synchronize the current subwallet
+    CurrentOnly,
+    /// Skip the obsolete subwallets that have a zero balance and no activity
+    /// during the last `inactivity_blocks` blocks
+    Smart { inactivity_blocks: u32 },
+}
+
 impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Synchronize the [HeritageWallet] using the given [BlockchainFactory], logging the
+    /// progress of the synchronization
     pub fn sync<T: BlockchainFactory>(&self, blockchain_factory: &T) -> Result<()> {
-        log::debug!("HeritageWallet::sync");
+        self.sync_with_progress(blockchain_factory, log_progress())
+    }
+
+    /// Synchronize the [HeritageWallet] using the given [BlockchainFactory], reporting the
+    /// progress of the synchronization to the given [Progress] hook.
+    ///
+    /// The progress goes from 0.0 to 100.0 for the whole wallet, each subwallet
+    /// being allocated an equal share of it.
+    pub fn sync_with_progress<T: BlockchainFactory, P: Progress + Clone>(
+        &self,
+        blockchain_factory: &T,
+        progress: P,
+    ) -> Result<()> {
+        self.sync_with_strategy(blockchain_factory, SyncStrategy::Full, progress)
+    }
+
+    /// Synchronize the [HeritageWallet] using the given [BlockchainFactory] and [SyncStrategy],
+    /// reporting the progress of the synchronization to the given [Progress] hook.
+    pub fn sync_with_strategy<T: BlockchainFactory, P: Progress + Clone>(
+        &self,
+        blockchain_factory: &T,
+        strategy: SyncStrategy,
+        progress: P,
+    ) -> Result<()> {
+        log::debug!("HeritageWallet::sync_with_strategy - strategy={strategy:?}");
+        // Concurrent synchronizations would compute the updates from the same initial state
+        let _sync_guard = self
+            .sync_lock
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
         // This cache will serve to build the TransactionSummary list
         // /!\ It is crucial that it is filled from oldest to newest so that we can
         // use it in one-pass. Each time we search this cache for an owned-Outpoint
@@ -32,6 +112,8 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
         let mut utxos_to_delete = vec![];
         // Manage the TransactionSummary updates
         let mut txsum_to_add = HashMap::new();
+        // The transactions whose raw transaction was pruned, see HeritageWallet::prune
+        let mut pruned_txids = HashSet::new();
         // Start obsolete_balance at zero
         let mut obsolete_balance = Balance::default();
         // Walk over every subwallets and sync them
@@ -41,35 +123,78 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             swc.subwallet_firstuse_time()
                 .expect("obsolete subwallet have always been used")
         });
+        let current_subwallet_config = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?;
+        // Each subwallet gets an equal share of the progress
+        let progress_span = 100.0
+            / (subwalletconfigs.len() + current_subwallet_config.is_some() as usize).max(1) as f32;
+        let mut progress_offset = 0.0;
+        // Only retrieve the blockchain height if the strategy needs it
+        let current_height = match strategy {
+            SyncStrategy::Smart { .. } => Some(
+                blockchain_factory
+                    .build("unimportant", None)
+                    .map_err(|e| Error::BlockchainProviderError(e.to_string()))?
+                    .get_height()
+                    .map_err(|e| Error::BlockchainProviderError(e.to_string()))?,
+            ),
+            _ => None,
+        };
         for subwalletconfig in subwalletconfigs {
-            // Extract the HeritageConfig of this wallet
+            let skip_blockchain_sync = match strategy {
+                SyncStrategy::Full => false,
+                SyncStrategy::CurrentOnly => true,
+                SyncStrategy::Smart { inactivity_blocks } => self.is_subwallet_inactive(
+                    &subwalletconfig,
+                    &existing_utxos,
+                    current_height.expect("retrieved for the Smart strategy"),
+                    inactivity_blocks,
+                )?,
+            };
+            let subwallet_progress = SubwalletProgress {
+                inner: progress.clone(),
+                subwallet_id: subwalletconfig.subwallet_id(),
+                offset: progress_offset,
+                span: progress_span,
+            };
+            progress_offset += progress_span;
             self.sync_subwallet(
                 subwalletconfig,
                 blockchain_factory,
+                skip_blockchain_sync,
+                subwallet_progress,
                 &mut tx_owned_io_cache,
                 &mut obsolete_balance,
                 &mut existing_utxos,
                 &mut utxos_to_add,
                 &mut utxos_to_delete,
                 &mut txsum_to_add,
+                &mut pruned_txids,
             )?;
         }
 
-        let uptodate_balance = if let Some(current_subwallet_config) = self
-            .database
-            .borrow()
-            .get_subwallet_config(SubwalletConfigId::Current)?
-        {
+        let uptodate_balance = if let Some(current_subwallet_config) = current_subwallet_config {
             let mut balance = Balance::default();
+            let subwallet_progress = SubwalletProgress {
+                inner: progress.clone(),
+                subwallet_id: current_subwallet_config.subwallet_id(),
+                offset: progress_offset,
+                span: progress_span,
+            };
             self.sync_subwallet(
                 current_subwallet_config,
                 blockchain_factory,
+                false,
+                subwallet_progress,
                 &mut tx_owned_io_cache,
                 &mut balance,
                 &mut existing_utxos,
                 &mut utxos_to_add,
                 &mut utxos_to_delete,
                 &mut txsum_to_add,
+                &mut pruned_txids,
             )?;
             balance
         } else {
@@ -79,11 +204,44 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
 
         // Update the balance
         let new_balance = HeritageWalletBalance::new(uptodate_balance, obsolete_balance);
-        log::info!("HeritageWallet::sync - new_balance={new_balance:?}");
+        log::info!("HeritageWallet::sync_with_strategy - new_balance={new_balance:?}");
         self.database.borrow_mut().set_balance(&new_balance)?;
 
+        // Flag the HeritageUtxos and TransactionSummaries paying to an address
+        // that received more than one payment
+        let reused_addresses = super::reused_addresses(txsum_to_add.values())
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect::<HashSet<_>>();
+        if !reused_addresses.is_empty() {
+            log::warn!(
+                "HeritageWallet::sync_with_strategy - {} reused address(es)",
+                reused_addresses.len()
+            );
+        }
+        for txsum in txsum_to_add.values_mut() {
+            txsum.address_reused = txsum
+                .owned_outputs
+                .iter()
+                .any(|o| reused_addresses.contains(&o.address));
+        }
+        for hu in utxos_to_add.iter_mut() {
+            hu.address_reused = reused_addresses.contains(&hu.address);
+        }
+        // The HeritageUtxos we keep must be replaced if their flag changed
+        for hu in existing_utxos
+            .iter()
+            .filter(|hu| hu.address_reused != reused_addresses.contains(&hu.address))
+        {
+            utxos_to_delete.push(hu.outpoint);
+            utxos_to_add.push(HeritageUtxo {
+                address_reused: !hu.address_reused,
+                ..hu.clone()
+            });
+        }
+
         log::info!(
-            "HeritageWallet::sync - utxos - remove={} add={}",
+            "HeritageWallet::sync_with_strategy - utxos - remove={} add={}",
             utxos_to_delete.len(),
             utxos_to_add.len()
         );
@@ -96,9 +254,12 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
         let existing_txsum = self.database().list_transaction_summaries()?;
 
         // Compute the list of existing to_delete/to_add by partitioning on the presence of the TxId in txsum_to_add
-        let (existing_txsum, mut existing_txsum_to_delete): (Vec<_>, Vec<_>) = existing_txsum
-            .into_iter()
-            .partition(|txsum| txsum_to_add.contains_key(&txsum.txid));
+        // The existing TransactionSummaries of pruned transactions are kept as they are: if another
+        // subwallet took part in the transaction, it would compute an incomplete one
+        let (existing_txsum, mut existing_txsum_to_delete): (Vec<_>, Vec<_>) =
+            existing_txsum.into_iter().partition(|txsum| {
+                txsum_to_add.contains_key(&txsum.txid) || pruned_txids.contains(&txsum.txid)
+            });
 
         // Transform the existing TxSum into a hashmap
         let existing_txsum = existing_txsum
@@ -114,6 +275,10 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
                 if !existing_txsum.contains_key(&txid) {
                     Some(txsum)
                 }
+                // If it was pruned, keep the one we have
+                else if pruned_txids.contains(&txid) {
+                    None
+                }
                 // If we have it but it is different, we need to delete the one we have
                 // Because the Database representation uses a combination of TxId and Confirmation
                 // time as the key, to garantee the correct ordering of the TxSummaries
@@ -129,7 +294,7 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             })
             .collect::<Vec<_>>();
         log::info!(
-            "HeritageWallet::sync - tx_summaries - remove={} add={}",
+            "HeritageWallet::sync_with_strategy - tx_summaries - remove={} add={}",
             existing_txsum_to_delete.len(),
             txsum_to_add.len(),
         );
@@ -143,36 +308,86 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             .borrow_mut()
             .add_transaction_summaries(&txsum_to_add)?;
 
-        // Sync FeeRate
-        let fee_rate = self.sync_fee_rate(blockchain_factory)?;
-        log::info!("HeritageWallet::sync - fee_rate={fee_rate:?}");
+        // Sync FeeRate, unless the backend cannot provide real estimations (e.g. Compact Filters)
+        // in which case the previously stored FeeRate, if any, is left untouched
+        let fee_blockchain = blockchain_factory
+            .build("unimportant", None)
+            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?;
+        if fee_blockchain
+            .get_capabilities()
+            .contains(&Capability::AccurateFees)
+        {
+            let fee_rate = self.sync_fee_rate(&BlockchainFeeEstimator(fee_blockchain))?;
+            log::info!("HeritageWallet::sync_with_strategy - fee_rate={fee_rate:?}");
+        } else {
+            log::warn!(
+                "HeritageWallet::sync_with_strategy - blockchain provider cannot estimate fees, \
+                FeeRate not synchronized"
+            );
+        }
 
+        progress
+            .update(100.0, Some("Synchronization done".to_owned()))
+            .map_err(|e| Error::SyncError(e.to_string()))?;
         Ok(())
     }
 
-    fn sync_subwallet<T: BlockchainFactory>(
+    fn sync_subwallet<T: BlockchainFactory, P: Progress>(
         &self,
         subwalletconfig: SubwalletConfig,
         blockchain_factory: &T,
+        skip_blockchain_sync: bool,
+        progress: SubwalletProgress<P>,
         tx_owned_io_cache: &mut HashMap<OutPoint, TransactionSummaryOwnedIO>,
         balance_acc: &mut Balance,
         existing_utxos: &mut Vec<HeritageUtxo>,
         utxos_to_add: &mut Vec<HeritageUtxo>,
         utxos_to_delete: &mut Vec<OutPoint>,
         txsum_to_add: &mut HashMap<Txid, TransactionSummary>,
+        pruned_txids: &mut HashSet<Txid>,
     ) -> Result<()> {
         log::debug!("sync_subwallet - {subwalletconfig:?}");
         // Use the wallet first use time to limit the range of the (first) sync
         // If there is no first use, there is no need to sync either
         if subwalletconfig.subwallet_firstuse_time().is_some() {
             let subwallet = self.get_subwallet(&subwalletconfig)?;
-            let sync_options = SyncOptions {
-                progress: Some(Box::new(log_progress())),
-            };
-
-            blockchain_factory
-                .sync_wallet(&subwallet, None, sync_options)
-                .map_err(|e| Error::SyncError(e.to_string()))?;
+            if skip_blockchain_sync {
+                // The subwallet local state is still used so that its HeritageUtxos
+                // and TransactionSummaries are kept
+                log::info!(
+                    "Skipping blockchain sync of SubwalletConfig Id={}, using its local state",
+                    subwalletconfig.subwallet_id()
+                );
+                progress
+                    .update(100.0, Some("Skipped by the sync strategy".to_owned()))
+                    .map_err(|e| Error::SyncError(e.to_string()))?;
+            } else {
+                // Make sure the sync looks at least GapLimit addresses ahead of the last
+                // generated one, whichever the keychain
+                let last_index = [KeychainKind::External, KeychainKind::Internal]
+                    .into_iter()
+                    .map(|kc| {
+                        subwallet
+                            .database()
+                            .get_last_index(kc)
+                            .map_err(|e| DatabaseError::Generic(e.to_string()))
+                    })
+                    .collect::<core::result::Result<Vec<_>, _>>()?
+                    .into_iter()
+                    .flatten()
+                    .max();
+                let lookahead =
+                    last_index.map(|i| i + 1).unwrap_or(0) + u32::from(self.get_gap_limit()?);
+                subwallet
+                    .ensure_addresses_cached(lookahead)
+                    .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+                let sync_options = SyncOptions {
+                    progress: Some(Box::new(progress)),
+                };
+                blockchain_factory
+                    .sync_wallet(&subwallet, None, sync_options)
+                    .map_err(|e| Error::SyncError(e.to_string()))?;
+            }
 
             // Update the balance
             *balance_acc = balance_acc.clone()
@@ -228,11 +443,15 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
                         confirmation_time: block_time,
                         address: crate::bitcoin::Address::from_script(
                             subwallet_utxo.txout.script_pubkey.as_script(),
-                            *crate::utils::bitcoin_network_from_env(),
+                            self.network(),
                         )
                         .expect("script should always be valid")
                         .into(),
                         heritage_config: subwallet_heritage_config.clone(),
+                        keypath_only: subwalletconfig.has_keypath_change()
+                            && subwallet_utxo.keychain == KeychainKind::Internal,
+                        // Set once every subwallet is synchronized
+                        address_reused: false,
                     });
                 }
             }
@@ -255,6 +474,14 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
             let mut subwallet_txs = subwallet
                 .list_transactions(true)
                 .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+            // The TransactionSummaries of pruned transactions cannot be recomputed
+            subwallet_txs.retain(|tx_details| {
+                let pruned = tx_details.transaction.is_none();
+                if pruned {
+                    pruned_txids.insert(tx_details.txid);
+                }
+                !pruned
+            });
             // Sort them to ensure with process them from oldest to newest
             sort_transactions_with_parents(
                 &mut subwallet_txs,
@@ -308,7 +535,11 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
                         };
                         let tsoio = TransactionSummaryOwnedIO {
                             outpoint,
-                            address: (&o.script_pubkey).try_into().expect("comes from DB"),
+                            address: CheckedAddress::from_script(
+                                o.script_pubkey.as_script(),
+                                self.network(),
+                            )
+                            .expect("comes from DB"),
                             amount: Amount::from_sat(o.value),
                         };
                         tx_owned_io_cache.insert(outpoint, tsoio.clone());
@@ -350,32 +581,103 @@ impl<D: TransacHeritageDatabase> HeritageWallet<D> {
                         fee: fee_info.map(|fi| fi.0).unwrap_or(Amount::ZERO),
                         fee_rate: fee_info.map(|fi| fi.1).unwrap_or(FeeRate::ZERO),
                         parent_txids,
+                        label: None,
+                        // Set once every subwallet is synchronized
+                        address_reused: false,
                     });
             }
         } else {
             log::info!(
                 "Skipping sync of SubwalletConfig Id={} because it was never used",
                 subwalletconfig.subwallet_id()
-            )
+            );
+            progress
+                .update(100.0, Some("Skipped because never used".to_owned()))
+                .map_err(|e| Error::SyncError(e.to_string()))?;
         }
         Ok(())
     }
 
-    fn sync_fee_rate<T: BlockchainFactory>(&self, blockchain_factory: &T) -> Result<FeeRate> {
+    /// Returns `true` if the subwallet owns no [HeritageUtxo] and has not seen any
+    /// transaction during the last `inactivity_blocks` blocks, according to its local state
+    fn is_subwallet_inactive(
+        &self,
+        subwalletconfig: &SubwalletConfig,
+        existing_utxos: &[HeritageUtxo],
+        current_height: u32,
+        inactivity_blocks: u32,
+    ) -> Result<bool> {
+        let subwallet_heritage_config = subwalletconfig.heritage_config();
+        if existing_utxos
+            .iter()
+            .any(|hu| hu.heritage_config == *subwallet_heritage_config)
+        {
+            return Ok(false);
+        }
+        let subwallet_txs = self
+            .get_subwallet(subwalletconfig)?
+            .list_transactions(false)
+            .map_err(|e| DatabaseError::Generic(e.to_string()))?;
+        // A subwallet without any known transaction was most likely never synchronized
+        // (e.g. restored from a backup), so it is not considered inactive
+        if subwallet_txs.is_empty() {
+            return Ok(false);
+        }
+        let mut last_activity_height = 0;
+        for tx in subwallet_txs {
+            match tx.confirmation_time {
+                Some(bt) => last_activity_height = last_activity_height.max(bt.height),
+                // Unconfirmed transactions mean the subwallet is active
+                None => return Ok(false),
+            }
+        }
+        let inactive = current_height.saturating_sub(last_activity_height) > inactivity_blocks;
+        log::debug!(
+            "HeritageWallet::is_subwallet_inactive - subwallet_id={} last_activity_height={last_activity_height} inactive={inactive}",
+            subwalletconfig.subwallet_id()
+        );
+        Ok(inactive)
+    }
+
+    /// Broadcast the given [Transaction] using the given [BlockchainFactory].
+    ///
+    /// # Errors
+    /// If the transaction is rejected, the reason is mapped to a [BroadcastError] when possible
+    pub fn broadcast<T: BlockchainFactory>(
+        &self,
+        blockchain_factory: &T,
+        tx: &Transaction,
+    ) -> Result<Txid> {
+        let txid = tx.txid();
+        log::debug!("HeritageWallet::broadcast - txid={txid}");
+        blockchain_factory
+            .build("unimportant", None)
+            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?
+            .broadcast(tx)
+            .map_err(|e| {
+                log::error!("HeritageWallet::broadcast - Transaction {txid} rejected: {e}");
+                BroadcastError::from_reject_message(&e.to_string())
+            })?;
+        Ok(txid)
+    }
+
+    /// Retrieve a fee estimation for the [BlockInclusionObjective](crate::BlockInclusionObjective)
+    /// of the wallet from the given [FeeEstimator] and store it in the database
+    pub fn sync_fee_rate<F: FeeEstimator + ?Sized>(&self, fee_estimator: &F) -> Result<FeeRate> {
         log::debug!("HeritageWallet::sync_fee_rate");
         let block_inclusion_objective = self.get_block_inclusion_objective()?;
         log::debug!(
             "HeritageWallet::sync_fee_rate - block_inclusion_objective={block_inclusion_objective}"
         );
 
-        // The RPC method "estimatesmartfee" returns a result in BTC/kvB
-        let bdk_fee_rate = blockchain_factory
-            .build("unimportant", None)
-            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?
-            .estimate_fee(block_inclusion_objective.0 as usize)
-            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?;
+        let fee_rate = fee_estimator.estimate_fee_rate(block_inclusion_objective)?;
+        // Never store an estimation that would produce unrelayable transactions
+        if fee_rate < FeeRate::BROADCAST_MIN {
+            return Err(Error::BlockchainProviderError(format!(
+                "No usable fee estimation available for a block inclusion objective of {block_inclusion_objective}"
+            )));
+        }
 
-        let fee_rate = FeeRate::from_sat_per_vb_unchecked(bdk_fee_rate.as_sat_per_vb() as u64);
         self.database.borrow_mut().set_fee_rate(&fee_rate)?;
         Ok(fee_rate)
     }
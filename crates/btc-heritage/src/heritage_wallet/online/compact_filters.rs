@@ -0,0 +1,65 @@
+//! Compact Block Filters (BIP157/158) synchronization backend.
+//!
+//! Using this backend, the [HeritageWallet](crate::HeritageWallet) discovers its
+//! subwallets transactions by downloading block filters from Bitcoin P2P peers and matching
+//! them locally, so the descriptors and addresses of the wallet are never revealed to a
+//! third-party server.
+
+use std::path::PathBuf;
+
+use bdk::blockchain::{
+    compact_filters::{
+        BitcoinPeerConfig, CompactFiltersBlockchain, CompactFiltersBlockchainConfig,
+    },
+    BlockchainFactory, ConfigurableBlockchain,
+};
+
+use crate::bitcoin::Network;
+
+/// A [BlockchainFactory] creating [CompactFiltersBlockchain] for each subwallet of
+/// a [HeritageWallet](crate::HeritageWallet).
+///
+/// Headers and filters are stored in a separate directory for each subwallet,
+/// under `storage_dir`. They cannot be shared: once a subwallet has scanned a bundle
+/// of filters, BDK prunes it from the store and would skip it when scanning for the
+/// next subwallet.
+///
+/// Note that BIP157/158 peers do not provide fee estimations, so the fee rate is not
+/// updated by [HeritageWallet::sync](crate::HeritageWallet::sync) with this backend.
+/// Use [HeritageWallet::sync_fee_rate](crate::HeritageWallet::sync_fee_rate) with another
+/// [FeeEstimator](super::fee_estimator::FeeEstimator) instead.
+#[derive(Debug, Clone)]
+pub struct CompactFiltersBlockchainFactory {
+    /// The list of peers to connect to. It is recommended to use a single trusted peer
+    pub peers: Vec<BitcoinPeerConfig>,
+    /// The network we are using
+    pub network: Network,
+    /// The directory under which headers and filters are stored
+    pub storage_dir: PathBuf,
+    /// Default number of blocks to skip which will be inherited by blockchain unless overridden
+    pub default_skip_blocks: Option<usize>,
+}
+
+impl BlockchainFactory for CompactFiltersBlockchainFactory {
+    type Inner = CompactFiltersBlockchain;
+
+    fn build(
+        &self,
+        wallet_name: &str,
+        override_skip_blocks: Option<u32>,
+    ) -> Result<Self::Inner, bdk::Error> {
+        log::debug!("CompactFiltersBlockchainFactory::build - wallet_name={wallet_name}");
+        CompactFiltersBlockchain::from_config(&CompactFiltersBlockchainConfig {
+            peers: self.peers.clone(),
+            network: self.network,
+            storage_dir: self
+                .storage_dir
+                .join(wallet_name)
+                .to_string_lossy()
+                .into_owned(),
+            skip_blocks: override_skip_blocks
+                .map(|skip_blocks| skip_blocks as usize)
+                .or(self.default_skip_blocks),
+        })
+    }
+}
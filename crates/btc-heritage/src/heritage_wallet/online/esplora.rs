@@ -0,0 +1,51 @@
+//! Async counterparts of the [HeritageWallet] online operations for Esplora backends.
+//!
+//! The subwallets synchronization relies on the blocking [bdk::blockchain::Blockchain]
+//! interface, so an Esplora endpoint can be used for it through
+//! [EsploraBlockchain](bdk::blockchain::esplora::EsploraBlockchain) (`esplora` feature).
+//! This module only covers the operations that can be carried out without touching the
+//! subwallets: fee estimation and transaction broadcast.
+
+use esplora_client::AsyncClient;
+
+use super::super::HeritageWallet;
+use crate::{
+    bitcoin::{FeeRate, Transaction, Txid},
+    database::TransacHeritageDatabase,
+    errors::{BroadcastError, Error, Result},
+};
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Retrieve a fee estimation for the [BlockInclusionObjective](crate::BlockInclusionObjective)
+    /// of the wallet from an Esplora endpoint and store it in the database
+    pub async fn sync_fee_rate_async(&self, client: &AsyncClient) -> Result<FeeRate> {
+        log::debug!("HeritageWallet::sync_fee_rate_async");
+        let block_inclusion_objective = self.get_block_inclusion_objective()?;
+        log::debug!(
+            "HeritageWallet::sync_fee_rate_async - block_inclusion_objective={block_inclusion_objective}"
+        );
+
+        // Esplora returns a map of confirmation target to fee estimations in sat/vB
+        let estimates = client
+            .get_fee_estimates()
+            .await
+            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?;
+        let sat_per_vb =
+            esplora_client::convert_fee_rate(block_inclusion_objective.0 as usize, estimates)
+                .map_err(|e| Error::BlockchainProviderError(e.to_string()))?;
+
+        let fee_rate = FeeRate::from_sat_per_vb_unchecked(sat_per_vb as u64);
+        self.database.borrow_mut().set_fee_rate(&fee_rate)?;
+        Ok(fee_rate)
+    }
+}
+
+/// Broadcast a [Transaction] using an Esplora endpoint
+pub async fn broadcast_async(client: &AsyncClient, tx: &Transaction) -> Result<Txid> {
+    log::debug!("esplora::broadcast_async - txid={}", tx.txid());
+    client
+        .broadcast(tx)
+        .await
+        .map_err(|e| BroadcastError::from_reject_message(&e.to_string()))?;
+    Ok(tx.txid())
+}
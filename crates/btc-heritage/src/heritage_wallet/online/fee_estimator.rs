@@ -0,0 +1,218 @@
+//! Fee estimation providers usable with [HeritageWallet::sync_fee_rate](super::super::HeritageWallet::sync_fee_rate).
+//!
+//! The [HeritageWallet](super::super::HeritageWallet) only stores a single [FeeRate], computed
+//! for its [BlockInclusionObjective]. Where this estimation comes from is chosen at runtime by
+//! passing one of the [FeeEstimator] implementations of this module (or a custom one).
+
+use bdk::blockchain::Blockchain;
+
+use crate::{
+    bitcoin::FeeRate,
+    errors::{Error, Result},
+    BlockInclusionObjective,
+};
+
+/// A source of fee estimations
+pub trait FeeEstimator {
+    /// Return the [FeeRate] a transaction should pay in order to be included in the
+    /// blockchain within the given [BlockInclusionObjective]
+    ///
+    /// # Errors
+    /// Returns an error if the provider cannot be reached or has no estimation to offer
+    /// for the requested objective.
+    fn estimate_fee_rate(
+        &self,
+        block_inclusion_objective: BlockInclusionObjective,
+    ) -> Result<FeeRate>;
+}
+
+impl<T: FeeEstimator + ?Sized> FeeEstimator for &T {
+    fn estimate_fee_rate(
+        &self,
+        block_inclusion_objective: BlockInclusionObjective,
+    ) -> Result<FeeRate> {
+        (**self).estimate_fee_rate(block_inclusion_objective)
+    }
+}
+
+/// A [FeeEstimator] relying on a BDK [Blockchain]
+///
+/// For Bitcoin Core, this uses the RPC method `estimatesmartfee`. For Electrum, this uses the
+/// method `blockchain.estimatefee`.
+#[derive(Debug, Clone)]
+pub struct BlockchainFeeEstimator<B: Blockchain>(pub B);
+
+impl<B: Blockchain> FeeEstimator for BlockchainFeeEstimator<B> {
+    fn estimate_fee_rate(
+        &self,
+        block_inclusion_objective: BlockInclusionObjective,
+    ) -> Result<FeeRate> {
+        log::debug!(
+            "BlockchainFeeEstimator::estimate_fee_rate - block_inclusion_objective={block_inclusion_objective}"
+        );
+        // The RPC method "estimatesmartfee" and the Electrum method "blockchain.estimatefee"
+        // both return a result in BTC/kvB, BDK takes care of the conversion
+        let bdk_fee_rate = self
+            .0
+            .estimate_fee(block_inclusion_objective.0 as usize)
+            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?;
+        // Electrum servers answer "-1" to "blockchain.estimatefee" when they do not have
+        // enough data to produce an estimation for the requested target
+        if bdk_fee_rate.as_sat_per_vb() <= 0.0 {
+            return Err(Error::BlockchainProviderError(format!(
+                "No fee estimation available for a block inclusion objective of {block_inclusion_objective}"
+            )));
+        }
+        Ok(FeeRate::from_sat_per_vb_unchecked(
+            bdk_fee_rate.as_sat_per_vb() as u64,
+        ))
+    }
+}
+
+/// A [FeeEstimator] relying on the `/api/v1/fees/recommended` endpoint of a
+/// [mempool.space](https://mempool.space) instance
+///
+/// The endpoint only provides a handful of recommendations, so the [BlockInclusionObjective]
+/// is mapped to the closest one:
+/// - `1` block: `fastestFee`
+/// - up to `3` blocks: `halfHourFee`
+/// - up to `6` blocks: `hourFee`
+/// - more than `6` blocks: `economyFee`
+#[cfg(feature = "mempool-space")]
+#[derive(Debug, Clone)]
+pub struct MempoolSpaceFeeEstimator {
+    base_url: String,
+}
+
+#[cfg(feature = "mempool-space")]
+impl Default for MempoolSpaceFeeEstimator {
+    /// Use the public mainnet instance at `https://mempool.space`
+    fn default() -> Self {
+        Self::new("https://mempool.space")
+    }
+}
+
+#[cfg(feature = "mempool-space")]
+impl MempoolSpaceFeeEstimator {
+    /// Create a [MempoolSpaceFeeEstimator] for the instance at `base_url`,
+    /// e.g. `https://mempool.space/testnet`
+    pub fn new(base_url: impl Into<String>) -> Self {
+        let mut base_url: String = base_url.into();
+        while base_url.ends_with('/') {
+            base_url.pop();
+        }
+        Self { base_url }
+    }
+
+    /// Create a [MempoolSpaceFeeEstimator] for the public instance of the `network`,
+    /// or [None] if there is none (i.e. for [crate::BitcoinNetwork::Regtest])
+    pub fn for_network(network: crate::BitcoinNetwork) -> Option<Self> {
+        network.mempool_space_url().map(Self::new)
+    }
+
+    fn select_recommendation(
+        recommended: &serde_json::Value,
+        block_inclusion_objective: BlockInclusionObjective,
+    ) -> Result<f64> {
+        let key = match block_inclusion_objective.0 {
+            1 => "fastestFee",
+            2..=3 => "halfHourFee",
+            4..=6 => "hourFee",
+            _ => "economyFee",
+        };
+        recommended
+            .get(key)
+            .and_then(serde_json::Value::as_f64)
+            .ok_or_else(|| {
+                Error::BlockchainProviderError(format!(
+                    "Invalid mempool.space fee recommendations: missing {key}"
+                ))
+            })
+    }
+}
+
+#[cfg(feature = "mempool-space")]
+impl FeeEstimator for MempoolSpaceFeeEstimator {
+    fn estimate_fee_rate(
+        &self,
+        block_inclusion_objective: BlockInclusionObjective,
+    ) -> Result<FeeRate> {
+        log::debug!(
+            "MempoolSpaceFeeEstimator::estimate_fee_rate - block_inclusion_objective={block_inclusion_objective}"
+        );
+        let url = format!("{}/api/v1/fees/recommended", self.base_url);
+        let response = minreq::get(&url)
+            .send()
+            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?;
+        if response.status_code != 200 {
+            return Err(Error::BlockchainProviderError(format!(
+                "{url} answered with HTTP status {}",
+                response.status_code
+            )));
+        }
+        let recommended: serde_json::Value = serde_json::from_slice(response.as_bytes())
+            .map_err(|e| Error::BlockchainProviderError(e.to_string()))?;
+        let sat_per_vb = Self::select_recommendation(&recommended, block_inclusion_objective)?;
+        log::debug!("MempoolSpaceFeeEstimator::estimate_fee_rate - sat_per_vb={sat_per_vb}");
+        Ok(FeeRate::from_sat_per_vb_unchecked(sat_per_vb.ceil() as u64))
+    }
+}
+
+#[cfg(all(test, feature = "mempool-space"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mempool_space_select_recommendation() {
+        let recommended = serde_json::json!({
+            "fastestFee": 30,
+            "halfHourFee": 20,
+            "hourFee": 10,
+            "economyFee": 5,
+            "minimumFee": 1
+        });
+        for (bio, expected) in [
+            (1, 30.0),
+            (2, 20.0),
+            (3, 20.0),
+            (6, 10.0),
+            (7, 5.0),
+            (1008, 5.0),
+        ] {
+            assert_eq!(
+                MempoolSpaceFeeEstimator::select_recommendation(
+                    &recommended,
+                    BlockInclusionObjective::from(bio)
+                )
+                .unwrap(),
+                expected
+            );
+        }
+
+        let incomplete = serde_json::json!({"fastestFee": 30});
+        assert!(MempoolSpaceFeeEstimator::select_recommendation(
+            &incomplete,
+            BlockInclusionObjective::from(6u16)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn mempool_space_base_url() {
+        assert_eq!(
+            MempoolSpaceFeeEstimator::new("https://mempool.space/testnet/").base_url,
+            "https://mempool.space/testnet"
+        );
+        assert_eq!(
+            MempoolSpaceFeeEstimator::default().base_url,
+            "https://mempool.space"
+        );
+        assert_eq!(
+            MempoolSpaceFeeEstimator::for_network(crate::BitcoinNetwork::Signet)
+                .unwrap()
+                .base_url,
+            "https://mempool.space/signet"
+        );
+        assert!(MempoolSpaceFeeEstimator::for_network(crate::BitcoinNetwork::Regtest).is_none());
+    }
+}
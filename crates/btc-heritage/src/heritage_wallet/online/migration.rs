@@ -0,0 +1,165 @@
+//! Migration of the funds of an existing, non-Heritage, descriptor wallet (e.g. a BDK or
+//! Bitcoin Core taproot wallet) into an [HeritageWallet], see [HeritageWallet::plan_migration].
+
+use std::collections::BTreeMap;
+
+use bdk::{
+    blockchain::BlockchainFactory, database::MemoryDatabase, FeeRate as BdkFeeRate, KeychainKind,
+    SignOptions, SyncOptions, Wallet,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::{psbt::Psbt, Address, Amount, FeeRate},
+    database::TransacHeritageDatabase,
+    errors::{Error, Result},
+    heritage_wallet::{HeritageWallet, SubwalletConfigId},
+    HeritageConfig,
+};
+
+/// The descriptors of the wallet to migrate. They may contain private keys
+/// (xprv), in which case the sweep [Psbt] is signed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationSource {
+    pub descriptor: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_descriptor: Option<String>,
+}
+
+/// The result of the stages of [HeritageWallet::plan_migration]
+#[derive(Debug, Clone)]
+pub struct MigrationPlan {
+    /// The [HeritageConfig] of the [HeritageWallet] receiving the funds
+    pub heritage_config: HeritageConfig,
+    /// The new address of the [HeritageWallet] receiving the funds
+    pub receive_address: Address,
+    /// The [Psbt] sweeping every UTXO of the source wallet to `receive_address`
+    pub psbt: Psbt,
+    /// The amount swept, fee included
+    pub amount: Amount,
+    pub fee: Amount,
+    /// `true` if the source descriptors contained the private keys and the [Psbt] is
+    /// ready to be broadcasted
+    pub signed: bool,
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Plan the migration of the funds of the wallet described by `source` into this
+    /// [HeritageWallet], in three stages:
+    /// 1. if `heritage_config` is provided and is not the current one, it is set on the wallet,
+    /// see [HeritageWallet::update_heritage_config]
+    /// 2. a new address of the wallet is generated to receive the funds
+    /// 3. a [Psbt] sweeping every UTXO of the source wallet to this address is created and,
+    /// if the source descriptors contain the private keys, signed
+    ///
+    /// The source wallet is synchronized using the `blockchain_factory` and the fee rate
+    /// is `fee_rate` or, if [None], the last one of this wallet.
+    ///
+    /// Nothing is broadcasted: it is up to the caller to sign the [Psbt], if needed, and to broadcast it.
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidMigrationSource] if the source descriptors are invalid or
+    /// if the source wallet has no funds, in which case nothing is changed in this wallet.
+    /// Returns [Error::MissingCurrentSubwalletConfig] if `heritage_config` is [None] and
+    /// the wallet has no current [HeritageConfig].
+    pub fn plan_migration<T: BlockchainFactory>(
+        &self,
+        source: &MigrationSource,
+        heritage_config: Option<HeritageConfig>,
+        blockchain_factory: &T,
+        fee_rate: Option<FeeRate>,
+    ) -> Result<MigrationPlan> {
+        log::debug!(
+            "HeritageWallet::plan_migration - source={source:?} heritage_config={heritage_config:?} \
+            fee_rate={fee_rate:?}"
+        );
+        let fee_rate = match fee_rate {
+            Some(fee_rate) => fee_rate,
+            None => self
+                .database
+                .borrow()
+                .get_fee_rate()?
+                .ok_or(Error::MissingFeeRate)?,
+        };
+
+        // Validate the source before changing anything in this wallet
+        let source_wallet = Wallet::new(
+            source.descriptor.as_str(),
+            source.change_descriptor.as_deref(),
+            self.network(),
+            MemoryDatabase::new(),
+        )
+        .map_err(|e| Error::InvalidMigrationSource(e.to_string()))?;
+        blockchain_factory
+            .sync_wallet(&source_wallet, None, SyncOptions::default())
+            .map_err(|e| Error::SyncError(e.to_string()))?;
+        let source_balance = source_wallet
+            .get_balance()
+            .map_err(|e| Error::InvalidMigrationSource(e.to_string()))?
+            .get_total();
+        log::info!("HeritageWallet::plan_migration - source_balance={source_balance}");
+        if source_balance == 0 {
+            return Err(Error::InvalidMigrationSource(
+                "the source wallet has no funds".to_owned(),
+            ));
+        }
+
+        // Stage 1: the HeritageConfig
+        let current_heritage_config = self
+            .database
+            .borrow()
+            .get_subwallet_config(SubwalletConfigId::Current)?
+            .map(|swc| swc.heritage_config().clone());
+        let heritage_config = match (heritage_config, current_heritage_config) {
+            (Some(new), Some(current)) if new == current => current,
+            (Some(new), _) => {
+                log::info!("HeritageWallet::plan_migration - Setting the new HeritageConfig");
+                self.update_heritage_config(new.clone())?;
+                new
+            }
+            (None, Some(current)) => current,
+            (None, None) => return Err(Error::MissingCurrentSubwalletConfig),
+        };
+
+        // Stage 2: the receive address
+        let receive_address = self.get_new_address()?;
+
+        // Stage 3: the sweep PSBT
+        let mut tx_builder = source_wallet.build_tx();
+        // If the source descriptors have a script tree, spend through the key-path
+        for kc in [KeychainKind::External, KeychainKind::Internal] {
+            if let Some(pol) = source_wallet
+                .policies(kc)
+                .map_err(|e| Error::InvalidMigrationSource(e.to_string()))?
+            {
+                if pol.requires_path() {
+                    tx_builder.policy_path(BTreeMap::from([(pol.id, vec![0])]), kc);
+                }
+            }
+        }
+        tx_builder
+            .drain_wallet()
+            .drain_to(receive_address.script_pubkey())
+            .fee_rate(BdkFeeRate::from_sat_per_kwu(
+                fee_rate.to_sat_per_kwu() as f32
+            ))
+            .enable_rbf();
+        let (mut psbt, tx_details) = tx_builder
+            .finish()
+            .map_err(|e| Error::PsbtCreationError(e.to_string()))?;
+        let signed = source_wallet
+            .sign(&mut psbt, SignOptions::default())
+            .map_err(|e| Error::PsbtCreationError(e.to_string()))?;
+
+        let res = MigrationPlan {
+            heritage_config,
+            receive_address,
+            psbt,
+            amount: Amount::from_sat(tx_details.sent),
+            fee: Amount::from_sat(tx_details.fee.unwrap_or_default()),
+            signed,
+        };
+        log::debug!("HeritageWallet::plan_migration - res={res:?}");
+        Ok(res)
+    }
+}
@@ -0,0 +1,169 @@
+//! Pruning of the data of the obsolete subwallets of an [HeritageWallet] that will most
+//! likely never be used again, see [HeritageWallet::prune].
+
+use bdk::{
+    database::{BatchDatabase, BatchOperations, Database},
+    KeychainKind,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{PartitionableDatabase, SubdatabaseId, TransacHeritageDatabase},
+    errors::{DatabaseError, Result},
+    subwallet_config::{SubwalletConfig, SubwalletId},
+    utils::timestamp_now,
+};
+
+use super::HeritageWallet;
+
+/// The options of [HeritageWallet::prune]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PruneOptions {
+    /// The minimum age, in seconds, of the last transaction of an obsolete subwallet
+    /// for its data to be pruned
+    pub retention_secs: u64,
+    /// Only report what would be pruned
+    pub dry_run: bool,
+}
+
+impl Default for PruneOptions {
+    fn default() -> Self {
+        Self {
+            retention_secs: 365 * 24 * 60 * 60,
+            dry_run: false,
+        }
+    }
+}
+
+/// What [HeritageWallet::prune] removed from the database of an obsolete subwallet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrunedSubwallet {
+    pub subwallet_id: SubwalletId,
+    /// The number of raw transactions removed
+    pub raw_transactions: usize,
+    /// The number of cached script pubkeys, beyond the last generated address, removed
+    pub script_pubkeys: usize,
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Prune the data of the obsolete subwallets that have a zero balance and whose last
+    /// transaction is older than [PruneOptions::retention_secs]: the raw transactions and
+    /// the cached script pubkeys beyond the last generated addresses are removed.
+    ///
+    /// The [SubwalletConfig]s, the [TransactionSummary](super::TransactionSummary)s and the
+    /// generated addresses are kept, so the wallet history, its addresses and its backup
+    /// are unchanged. A subsequent synchronization of a pruned subwallet with the blockchain
+    /// retrieves the data again.
+    pub fn prune(&self, options: PruneOptions) -> Result<Vec<PrunedSubwallet>> {
+        self.prune_at(options, timestamp_now())
+    }
+
+    /// Same as [HeritageWallet::prune] but considering `timestamp` as the present
+    pub fn prune_at(&self, options: PruneOptions, timestamp: u64) -> Result<Vec<PrunedSubwallet>> {
+        log::debug!("HeritageWallet::prune_at - options={options:?} timestamp={timestamp}");
+        let heritage_utxos = self.database.borrow().list_utxos()?;
+        let mut res = vec![];
+        for swc in self.database.borrow().list_obsolete_subwallet_configs()? {
+            if heritage_utxos
+                .iter()
+                .any(|hu| hu.heritage_config == *swc.heritage_config())
+            {
+                continue;
+            }
+            if let Some(pruned) = self.prune_subwallet(&swc, options, timestamp)? {
+                res.push(pruned);
+            }
+        }
+        log::debug!("HeritageWallet::prune_at - res={res:?}");
+        Ok(res)
+    }
+
+    fn prune_subwallet(
+        &self,
+        subwallet_config: &SubwalletConfig,
+        options: PruneOptions,
+        timestamp: u64,
+    ) -> Result<Option<PrunedSubwallet>> {
+        let subwallet_id = subwallet_config.subwallet_id();
+        let mut subdatabase: <D as PartitionableDatabase>::SubDatabase = self
+            .database
+            .borrow()
+            .get_subdatabase(SubdatabaseId::from(subwallet_id))?;
+        let db_error = |e: bdk::Error| DatabaseError::Generic(e.to_string());
+
+        if subdatabase
+            .iter_utxos()
+            .map_err(db_error)?
+            .iter()
+            .any(|lu| !lu.is_spent)
+        {
+            return Ok(None);
+        }
+        let txs = subdatabase.iter_txs(false).map_err(db_error)?;
+        // Without any transaction, the subwallet was most likely never synchronized
+        if txs.is_empty() {
+            return Ok(None);
+        }
+        let mut last_activity_ts = 0;
+        for tx in txs.iter() {
+            match &tx.confirmation_time {
+                Some(bt) => last_activity_ts = last_activity_ts.max(bt.timestamp),
+                None => return Ok(None),
+            }
+        }
+        if timestamp.saturating_sub(last_activity_ts) < options.retention_secs {
+            return Ok(None);
+        }
+
+        let mut batch = subdatabase.begin_batch();
+        let mut raw_transactions = 0;
+        for tx in txs.iter() {
+            if subdatabase
+                .get_raw_tx(&tx.txid)
+                .map_err(db_error)?
+                .is_some()
+            {
+                raw_transactions += 1;
+                batch.del_raw_tx(&tx.txid).map_err(db_error)?;
+            }
+        }
+        let mut script_pubkeys = 0;
+        for keychain in [KeychainKind::External, KeychainKind::Internal] {
+            let last_index = subdatabase.get_last_index(keychain).map_err(db_error)?;
+            for script_pubkey in subdatabase
+                .iter_script_pubkeys(Some(keychain))
+                .map_err(db_error)?
+            {
+                let Some((_, child)) = subdatabase
+                    .get_path_from_script_pubkey(&script_pubkey)
+                    .map_err(db_error)?
+                else {
+                    continue;
+                };
+                if last_index.is_some_and(|last_index| child <= last_index) {
+                    continue;
+                }
+                script_pubkeys += 1;
+                batch
+                    .del_script_pubkey_from_path(keychain, child)
+                    .map_err(db_error)?;
+                batch
+                    .del_path_from_script_pubkey(&script_pubkey)
+                    .map_err(db_error)?;
+            }
+        }
+        log::info!(
+            "HeritageWallet::prune_subwallet - subwallet_id={subwallet_id} \
+            raw_transactions={raw_transactions} script_pubkeys={script_pubkeys} dry_run={}",
+            options.dry_run
+        );
+        if !options.dry_run {
+            subdatabase.commit_batch(batch).map_err(db_error)?;
+        }
+        Ok(Some(PrunedSubwallet {
+            subwallet_id,
+            raw_transactions,
+            script_pubkeys,
+        }))
+    }
+}
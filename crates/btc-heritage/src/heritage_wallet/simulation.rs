@@ -0,0 +1,169 @@
+//! Time-travel simulation of an [HeritageWallet], answering questions like "what happens
+//! if the owner stops renewing the wallet and the chain reaches 2027".
+//!
+//! [HeritageWallet::simulate] returns an [HeritageWalletSimulation] in which the balances,
+//! the eligibility of the heirs and the heir PSBTs are computed as if the blockchain was at
+//! the given [BlockTime], assuming no new transaction.
+
+use std::collections::{BTreeMap, HashSet};
+
+use bdk::BlockTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::{psbt::Psbt, Amount},
+    database::TransacHeritageDatabase,
+    errors::Result,
+    HeirConfig,
+};
+
+use super::{
+    CreatePsbtOptions, HeritageWallet, InheritanceEvent, InheritanceSchedule, SpendingConfig,
+    TransactionSummary,
+};
+
+/// The balance of an [HeritageWallet] at the simulated [BlockTime]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SimulatedBalance {
+    /// The total amount of the UTXOs of the wallet
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub total: Amount,
+    /// The amount that only the owner can spend
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub owner_exclusive: Amount,
+    /// The amount that at least one heir can spend
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub heir_spendable: Amount,
+}
+
+/// The funds an heir can claim at the simulated [BlockTime]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SimulatedHeirEligibility {
+    pub heir_config: HeirConfig,
+    /// The amount the heir can spend
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub spendable: Amount,
+    /// The amount the heir will only be able to spend later
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub pending: Amount,
+    /// The estimated timestamp at which some of the pending amount will become spendable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_maturity_ts: Option<u64>,
+}
+
+/// A read-only view of an [HeritageWallet] as if the blockchain was at a given [BlockTime],
+/// see [HeritageWallet::simulate]
+pub struct HeritageWalletSimulation<'a, D: TransacHeritageDatabase> {
+    wallet: &'a HeritageWallet<D>,
+    at: BlockTime,
+}
+
+impl<D: TransacHeritageDatabase> HeritageWallet<D> {
+    /// Return a read-only view of the wallet as if the blockchain was at `at`
+    /// and the wallet had no new transaction until then.
+    ///
+    /// The estimations rely on the last synchronization of the wallet.
+    pub fn simulate(&self, at: BlockTime) -> HeritageWalletSimulation<'_, D> {
+        log::debug!("HeritageWallet::simulate - at={at:?}");
+        HeritageWalletSimulation { wallet: self, at }
+    }
+}
+
+impl<D: TransacHeritageDatabase> HeritageWalletSimulation<'_, D> {
+    /// Return the simulated [BlockTime]
+    pub fn at(&self) -> &BlockTime {
+        &self.at
+    }
+
+    /// Return `true` if the heir of `event` can spend its UTXO at the simulated [BlockTime].
+    /// Unconfirmed UTXOs are never spendable by an heir.
+    fn is_spendable(&self, event: &InheritanceEvent) -> bool {
+        event.spendable_timestamp <= self.at.timestamp
+            && event
+                .spendable_height
+                .is_some_and(|height| height <= self.at.height)
+    }
+
+    /// Return the [SimulatedBalance] of the wallet
+    pub fn get_balance(&self) -> Result<SimulatedBalance> {
+        log::debug!("HeritageWalletSimulation::get_balance");
+        let heritage_utxos = self.wallet.database().list_utxos()?;
+        let total = heritage_utxos.iter().map(|hu| hu.amount).sum::<Amount>();
+        let heir_spendable_outpoints = InheritanceSchedule::from_heritage_utxos(heritage_utxos)
+            .events
+            .into_iter()
+            .filter(|event| self.is_spendable(event))
+            .map(|event| (event.outpoint, event.amount))
+            .collect::<HashSet<_>>();
+        let heir_spendable = heir_spendable_outpoints
+            .into_iter()
+            .map(|(_, amount)| amount)
+            .sum::<Amount>();
+        let res = SimulatedBalance {
+            total,
+            owner_exclusive: total - heir_spendable,
+            heir_spendable,
+        };
+        log::debug!("HeritageWalletSimulation::get_balance - res={res:?}");
+        Ok(res)
+    }
+
+    /// Return, for every heir of the wallet, the funds it can claim and the funds
+    /// it will be able to claim later, ordered by [HeirConfig]
+    pub fn list_heir_eligibilities(&self) -> Result<Vec<SimulatedHeirEligibility>> {
+        log::debug!("HeritageWalletSimulation::list_heir_eligibilities");
+        let mut eligibilities: BTreeMap<HeirConfig, SimulatedHeirEligibility> = BTreeMap::new();
+        for event in self.wallet.get_inheritance_schedule()?.events {
+            let spendable = self.is_spendable(&event);
+            let eligibility = eligibilities
+                .entry(event.heir_config.clone())
+                .or_insert_with(|| SimulatedHeirEligibility {
+                    heir_config: event.heir_config,
+                    spendable: Amount::ZERO,
+                    pending: Amount::ZERO,
+                    next_maturity_ts: None,
+                });
+            if spendable {
+                eligibility.spendable += event.amount;
+            } else {
+                eligibility.pending += event.amount;
+                // The events are sorted by estimated timestamp
+                if eligibility.next_maturity_ts.is_none() {
+                    eligibility.next_maturity_ts = Some(event.estimated_timestamp);
+                }
+            }
+        }
+        let res = eligibilities.into_values().collect::<Vec<_>>();
+        log::debug!("HeritageWalletSimulation::list_heir_eligibilities - res={res:?}");
+        Ok(res)
+    }
+
+    /// Return the next [InheritanceEvent] after the simulated [BlockTime], if any
+    pub fn next_expiration(&self) -> Result<Option<InheritanceEvent>> {
+        Ok(self
+            .wallet
+            .get_inheritance_schedule()?
+            .next_expiration_after(self.at.timestamp)
+            .cloned())
+    }
+
+    /// Create the [Psbt] the heir could create at the simulated [BlockTime],
+    /// see [HeritageWallet::create_heir_psbt].
+    ///
+    /// [CreatePsbtOptions::assume_blocktime] is replaced by the simulated [BlockTime].
+    pub fn create_heir_psbt(
+        &self,
+        heir_config: HeirConfig,
+        spending_config: SpendingConfig,
+        options: CreatePsbtOptions,
+    ) -> Result<(Psbt, TransactionSummary)> {
+        self.wallet.create_heir_psbt(
+            heir_config,
+            spending_config,
+            CreatePsbtOptions {
+                assume_blocktime: Some(self.at.clone()),
+                ..options
+            },
+        )
+    }
+}
@@ -2,21 +2,24 @@ use core::{fmt::Display, ops::Deref, str::FromStr};
 use std::collections::HashSet;
 
 use bdk::{
-    bitcoin::{FeeRate, Script, ScriptBuf},
+    bitcoin::{FeeRate, Script},
     Balance, BlockTime,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
     bitcoin::{
+        absolute::LockTime,
         address::NetworkChecked,
         bip32::{DerivationPath, Fingerprint},
-        Address, Amount, OutPoint, Txid,
+        psbt::Psbt,
+        secp256k1::rand::{thread_rng, Rng},
+        Address, Amount, Network, OutPoint, SignedAmount, Txid,
     },
     errors::Error,
     heritage_config::HeritageExplorerTrait,
     subwallet_config::SubwalletId,
-    utils::string_to_address,
+    utils::string_to_address_assume_checked,
     HeirConfig, HeritageConfig,
 };
 
@@ -58,17 +61,30 @@ impl HeritageWalletBalance {
 
 #[derive(Debug, Clone)]
 pub struct Recipient(pub(crate) Address, pub(crate) Amount);
+impl Recipient {
+    /// Create a [Recipient] receiving everything that remains once the other recipients
+    /// and the fee are paid, i.e. the "send all" amount. Its amount is [Amount::MAX].
+    pub fn max(address: Address) -> Self {
+        Self(address, Amount::MAX)
+    }
+    /// Return `true` if the [Recipient] was created by [Recipient::max]
+    pub fn is_max(&self) -> bool {
+        self.1 == Amount::MAX
+    }
+}
 impl From<(Address, Amount)> for Recipient {
     fn from(value: (Address, Amount)) -> Self {
         Self(value.0, value.1)
     }
 }
+/// The network of the address is not verified, the [HeritageWallet](super::HeritageWallet)
+/// refuses to create a PSBT paying an address of another network
 impl TryFrom<(&str, Amount)> for Recipient {
     type Error = Error;
 
     fn try_from(value: (&str, Amount)) -> Result<Self, Self::Error> {
         let (addr_str, amount) = value;
-        let addr = crate::utils::string_to_address(addr_str)?;
+        let addr = string_to_address_assume_checked(addr_str)?;
         Ok(Self(addr, amount))
     }
 }
@@ -84,11 +100,36 @@ impl TryFrom<(String, Amount)> for Recipient {
 #[derive(Debug, Clone)]
 pub enum SpendingConfig {
     DrainTo(Address),
-    Recipients(Vec<Recipient>),
+    /// Send the given amounts to the recipients. When an heir is spending, every eligible
+    /// UTXO is spent and the last recipient receives the remainder, its amount being a minimum
+    ///
+    /// The change of the owner goes to `change_to`, e.g. another wallet of the owner, or to
+    /// a new internal address of the current subwallet if it is [None]. An heir has no change
+    /// and cannot give a `change_to` address.
+    ///
+    /// At most one recipient can be a [Recipient::max], it then receives every spendable UTXO
+    /// minus the other amounts and the fee, and there is no change. For an heir, it must be
+    /// the last recipient.
+    Recipients {
+        recipients: Vec<Recipient>,
+        change_to: Option<Address>,
+    },
+    /// Like [SpendingConfig::DrainTo], every spendable UTXO is spent, but `change_amount`
+    /// is sent to `change_to` and only the remainder goes to `drain_to`.
+    ///
+    /// No UTXO is left unspent: the change only stays in the wallet if `change_to` is
+    /// an address of the wallet.
+    DrainToWithChange {
+        drain_to: Address,
+        change_amount: Amount,
+        change_to: Address,
+    },
 }
 impl SpendingConfig {
+    /// The network of the address is not verified, the [HeritageWallet](super::HeritageWallet)
+    /// refuses to create a PSBT paying an address of another network
     pub fn drain_to_address_str(addr: &str) -> crate::errors::Result<SpendingConfig> {
-        Ok(SpendingConfig::DrainTo(crate::utils::string_to_address(
+        Ok(SpendingConfig::DrainTo(string_to_address_assume_checked(
             addr,
         )?))
     }
@@ -98,19 +139,23 @@ impl SpendingConfig {
 }
 impl From<Vec<(Address, Amount)>> for SpendingConfig {
     fn from(value: Vec<(Address, Amount)>) -> Self {
-        SpendingConfig::Recipients(value.into_iter().map(|e| Recipient::from(e)).collect())
+        SpendingConfig::Recipients {
+            recipients: value.into_iter().map(|e| Recipient::from(e)).collect(),
+            change_to: None,
+        }
     }
 }
 impl TryFrom<Vec<(String, Amount)>> for SpendingConfig {
     type Error = Error;
 
     fn try_from(value: Vec<(String, Amount)>) -> Result<Self, Self::Error> {
-        Ok(SpendingConfig::Recipients(
-            value
+        Ok(SpendingConfig::Recipients {
+            recipients: value
                 .into_iter()
                 .map(|e| Recipient::try_from(e))
                 .collect::<Result<_, _>>()?,
-        ))
+            change_to: None,
+        })
     }
 }
 
@@ -124,6 +169,8 @@ pub enum FeePolicy {
 }
 
 /// The UTXO selection mode
+///
+/// The given UTXOs can belong to the current subwallet or to any obsolete subwallet.
 #[derive(Debug, Clone, Default)]
 pub enum UtxoSelection {
     /// Default behavior,
@@ -144,6 +191,81 @@ pub enum UtxoSelection {
     UseOnly(HashSet<OutPoint>),
 }
 
+/// The strategy used to choose the UTXOs funding an owner transaction
+///
+/// Only [SpendingConfig::Recipients] without a [Recipient::max] leaves room for a choice:
+/// every other [SpendingConfig] spends all the available UTXOs.
+/// Heirs always spend every UTXO they are eligible to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CoinSelectionPolicy {
+    /// Default behavior,
+    /// spend every UTXO of the obsolete subwallets, plus the UTXOs of the current subwallet
+    /// needed to match the requested amount, selected using Branch and Bound.
+    /// This moves the funds to the current [HeritageConfig] as soon as possible.
+    #[default]
+    ConsolidateObsolete,
+    /// Like [CoinSelectionPolicy::ConsolidateObsolete], but the UTXOs of the current subwallet
+    /// are selected oldest first, i.e. the ones with the closest inheritance maturity first
+    OldestFirst,
+    /// Only use UTXOs of the current subwallet, selected using Branch and Bound, in order to
+    /// minimize the number of inputs. Fallback to [CoinSelectionPolicy::ConsolidateObsolete]
+    /// if the current subwallet cannot fund the transaction.
+    MinimizeFee,
+    /// Avoid linking the subwallets together by funding the transaction with the UTXOs of a
+    /// single subwallet, trying the current subwallet first, then the obsolete subwallets
+    /// from the most recent. Fallback to [CoinSelectionPolicy::ConsolidateObsolete]
+    /// if no single subwallet can fund the transaction.
+    PrivacyPreferSingleSubwallet,
+}
+
+/// The order of the inputs and outputs of a new transaction, so that the change cannot be
+/// told apart by its position
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputOrdering {
+    /// Default behavior,
+    /// the inputs and outputs are randomly shuffled
+    #[default]
+    Shuffle,
+    /// The inputs and outputs are sorted as specified by BIP-69
+    Bip69,
+}
+
+/// The nLockTime of the transactions of the owner
+///
+/// Heir transactions always use the lock time required by their spend conditions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LockTimePolicy {
+    /// Default behavior,
+    /// the lock time is the current block height, to discourage fee sniping
+    #[default]
+    AntiFeeSniping,
+    /// Like the wallet of Bitcoin Core: the current block height but, one time out of ten,
+    /// a random number of blocks up to 99 earlier, so that the transactions relayed late
+    /// do not stand out
+    BitcoinCore,
+    /// No lock time
+    None,
+}
+
+impl LockTimePolicy {
+    /// The lock time of a transaction created at the block `height`
+    pub(crate) fn lock_time(self, height: u32) -> LockTime {
+        let height = match self {
+            LockTimePolicy::AntiFeeSniping => height,
+            LockTimePolicy::BitcoinCore => {
+                let mut rng = thread_rng();
+                if rng.gen_ratio(1, 10) {
+                    height.saturating_sub(rng.gen_range(0..100))
+                } else {
+                    height
+                }
+            }
+            LockTimePolicy::None => 0,
+        };
+        LockTime::from_height(height).unwrap_or(LockTime::ZERO)
+    }
+}
+
 /// Options used to customize the behavior of [super::HeritageWallet::create_psbt]
 #[derive(Debug, Clone, Default)]
 pub struct CreatePsbtOptions {
@@ -154,11 +276,23 @@ pub struct CreatePsbtOptions {
     pub assume_blocktime: Option<BlockTime>,
     /// The UTXO Selection algorithm, see [UtxoSelection], defaults to [UtxoSelection::IncludePrevious]
     pub utxo_selection: UtxoSelection,
+    /// The coin selection strategy, see [CoinSelectionPolicy], defaults to
+    /// [CoinSelectionPolicy::ConsolidateObsolete].
+    /// It is ignored when [UtxoSelection::UseOnly] is used and the fallbacks of
+    /// [CoinSelectionPolicy::MinimizeFee] and [CoinSelectionPolicy::PrivacyPreferSingleSubwallet]
+    /// are not available when UTXOs are forcibly included.
+    pub coin_selection: CoinSelectionPolicy,
     /// Signal the the Transaction should not have the Replace By Fee opt-in flag
     /// Defaults to false, meaning the transaction will allow RBF.
     /// Note that since BitcoinCore v28, full-RBF is the node default configuration, so this
     /// parameter will likely have no impact whatsoever
     pub disable_rbf: bool,
+    /// The lock time of the transaction when the owner is spending, see [LockTimePolicy],
+    /// defaults to [LockTimePolicy::AntiFeeSniping]
+    pub lock_time_policy: LockTimePolicy,
+    /// The order of the inputs and outputs, see [OutputOrdering],
+    /// defaults to [OutputOrdering::Shuffle]
+    pub output_ordering: OutputOrdering,
 }
 
 /// An [HeritageWallet] configuration used to query the appropriate [crate::bitcoin::FeeRate]
@@ -197,18 +331,103 @@ impl Display for BlockInclusionObjective {
     }
 }
 
+/// The maximum number of consecutive unused receiving addresses of a subwallet.
+///
+/// [HeritageWallet::get_new_addresses](super::HeritageWallet::get_new_addresses) refuses to
+/// go beyond it, and the synchronization always looks that many addresses ahead of the last
+/// generated one so that handed-out addresses are never missed.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(any(test, feature = "database-tests"), derive(Eq, PartialEq))]
+#[serde(transparent)]
+pub struct GapLimit(pub(crate) u32);
+impl Default for GapLimit {
+    /// The usual gap limit of BIP-44 wallets, so that a backup can be restored in any wallet
+    fn default() -> Self {
+        Self(20)
+    }
+}
+impl From<u32> for GapLimit {
+    /// Create a [GapLimit] from a [u32]
+    ///
+    /// # Panics
+    /// Panics if the value is 0
+    fn from(value: u32) -> Self {
+        assert!(value >= 1);
+        Self(value)
+    }
+}
+impl From<GapLimit> for u32 {
+    fn from(value: GapLimit) -> Self {
+        value.0
+    }
+}
+impl Display for GapLimit {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The spending quotas of the heirs, in percent of the funds they are eligible to.
+///
+/// They are a policy of the wallet, not part of the [HeritageConfig]: they are NOT enforced
+/// by consensus and do not appear in the descriptors, they are only advisory limits enforced
+/// by [HeritageWallet::create_heir_psbt](super::HeritageWallet::create_heir_psbt).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(any(test, feature = "database-tests"), derive(Eq, PartialEq))]
+#[serde(transparent)]
+pub struct HeirSpendingQuotas(Vec<(HeirConfig, u8)>);
+impl HeirSpendingQuotas {
+    /// Return the spending quota of the heir, if any
+    pub fn get(&self, heir_config: &HeirConfig) -> Option<u8> {
+        self.0
+            .iter()
+            .find(|(hc, _)| hc == heir_config)
+            .map(|(_, quota)| *quota)
+    }
+
+    /// Set the spending quota of the heir, or remove it if `quota` is [None]
+    ///
+    /// # Errors
+    /// Returns [Error::InvalidSpendingQuota] if `quota` is not between 1 and 100 percent
+    pub fn set(&mut self, heir_config: HeirConfig, quota: Option<u8>) -> Result<(), Error> {
+        if let Some(quota) = quota.filter(|quota| *quota == 0 || *quota > 100) {
+            return Err(Error::InvalidSpendingQuota(quota));
+        }
+        self.0.retain(|(hc, _)| *hc != heir_config);
+        if let Some(quota) = quota {
+            self.0.push((heir_config, quota));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum SubwalletConfigId {
     Current,
     Id(SubwalletId),
 }
 
-/// Wrapper around an [Address<NetworkChecked>] that automatically check the address
-/// using the `BITCOIN_NETWORK` environment variable.
-/// If the environment variable is absent, assume [crate::bitcoin::Network::Bitcoin]
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+/// Wrapper around an [Address<NetworkChecked>] of the wallet network, created with
+/// [CheckedAddress::from_script].
+///
+/// When converted from a string, e.g. when deserialized, the address is assumed to have been
+/// checked before being stored and its network is deduced from its prefix. As the prefix of
+/// testnet and signet addresses is the same, two [CheckedAddress] are equal if they have the
+/// same script pubkey.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(into = "String", try_from = "String")]
 pub struct CheckedAddress(Address<NetworkChecked>);
+impl PartialEq for CheckedAddress {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.payload == other.0.payload
+    }
+}
+impl Eq for CheckedAddress {}
+impl core::hash::Hash for CheckedAddress {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        self.0.payload.hash(state)
+    }
+}
 impl Deref for CheckedAddress {
     type Target = Address<NetworkChecked>;
 
@@ -227,25 +446,18 @@ impl TryFrom<String> for CheckedAddress {
         Self::try_from(value.as_str())
     }
 }
-impl TryFrom<&Script> for CheckedAddress {
-    type Error = Error;
-    fn try_from(value: &Script) -> Result<Self, Error> {
-        Ok(Self::from(
-            Address::from_script(value, *crate::utils::bitcoin_network_from_env())
-                .map_err(|e| Error::Unknown(format!("Invalid script: {e}")))?,
-        ))
-    }
-}
-impl TryFrom<&ScriptBuf> for CheckedAddress {
-    type Error = Error;
-    fn try_from(value: &ScriptBuf) -> Result<Self, Error> {
-        Self::try_from(value.as_script())
+impl CheckedAddress {
+    /// Create the [CheckedAddress] of `script` for the `network`
+    pub fn from_script(script: &Script, network: Network) -> Result<Self, Error> {
+        Ok(Self::from(Address::from_script(script, network).map_err(
+            |e| Error::Unknown(format!("Invalid script: {e}")),
+        )?))
     }
 }
 impl TryFrom<&str> for CheckedAddress {
     type Error = Error;
     fn try_from(value: &str) -> Result<Self, Error> {
-        Ok(Self(string_to_address(value)?))
+        Ok(Self(string_to_address_assume_checked(value)?))
     }
 }
 
@@ -276,13 +488,24 @@ pub struct HeritageUtxo {
     pub address: CheckedAddress,
     /// The [HeritageConfig] of the subwallet that owns this UTXO
     pub heritage_config: HeritageConfig,
+    /// `true` if this UTXO is a key-path-only change output, that the heirs cannot spend
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub keypath_only: bool,
+    /// `true` if the address of this UTXO received more than one payment,
+    /// see [HeritageWallet::list_reused_addresses](super::HeritageWallet::list_reused_addresses)
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub address_reused: bool,
 }
 impl HeritageUtxo {
     /// Returns the timestamp at which the given [HeirConfig] will be able to spend this [HeritageUtxo].
-    /// If the heir is not present the the [HeritageConfig], the function returns [None].
+    /// If the heir is not present the the [HeritageConfig] or if the UTXO is
+    /// [keypath_only](HeritageUtxo::keypath_only), the function returns [None].
     ///
     /// Beware that this MAY be an estimation based on the average Bitcoin network blocktime.
     pub fn estimate_heir_spending_timestamp(&self, heir_config: &HeirConfig) -> Option<u64> {
+        if self.keypath_only {
+            return None;
+        }
         self.heritage_config
             .get_heritage_explorer(heir_config)
             .map(|explo| {
@@ -308,6 +531,197 @@ impl HeritageUtxo {
     }
 }
 
+/// An event of the [InheritanceSchedule]: the moment an heir becomes able to spend an [HeritageUtxo]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InheritanceEvent {
+    /// The heir concerned by this event
+    pub heir_config: HeirConfig,
+    /// [OutPoint] of the UTXO the heir will be able to spend
+    pub outpoint: OutPoint,
+    /// [Amount] of the UTXO the heir will be able to spend
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub amount: Amount,
+    /// The heir cannot spend before this timestamp (absolute lock)
+    pub spendable_timestamp: u64,
+    /// The heir cannot spend before this block height (relative lock).
+    /// Can be None if the UTXO is for a unconfirmed TX
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable_height: Option<u32>,
+    /// The estimated timestamp at which both locks will be satisfied,
+    /// see [HeritageUtxo::estimate_heir_spending_timestamp]
+    pub estimated_timestamp: u64,
+}
+
+/// The full inheritance timeline of an [super::HeritageWallet], for every heir and every UTXO
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InheritanceSchedule {
+    /// The [InheritanceEvent]s, sorted by estimated timestamp
+    pub events: Vec<InheritanceEvent>,
+}
+impl InheritanceSchedule {
+    /// Build the [InheritanceSchedule] of a set of [HeritageUtxo]s, for every heir of
+    /// their [HeritageConfig]. Key-path-only UTXOs are ignored as they cannot be inherited.
+    pub fn from_heritage_utxos(heritage_utxos: impl IntoIterator<Item = HeritageUtxo>) -> Self {
+        let mut events = heritage_utxos
+            .into_iter()
+            // Key-path-only UTXOs cannot be inherited
+            .filter(|heritage_utxo| !heritage_utxo.keypath_only)
+            .flat_map(|heritage_utxo| {
+                heritage_utxo
+                    .heritage_config
+                    .iter_heir_configs()
+                    .map(|heir_config| {
+                        let spend_conditions = heritage_utxo
+                            .heritage_config
+                            .get_heritage_explorer(heir_config)
+                            .expect("heir_config comes from the HeritageConfig")
+                            .get_spend_conditions();
+                        InheritanceEvent {
+                            heir_config: heir_config.clone(),
+                            outpoint: heritage_utxo.outpoint,
+                            amount: heritage_utxo.amount,
+                            spendable_timestamp: spend_conditions
+                                .get_spendable_timestamp()
+                                .expect("an Heir always have a timelock"),
+                            spendable_height: heritage_utxo
+                                .confirmation_time
+                                .as_ref()
+                                .zip(spend_conditions.get_relative_block_lock())
+                                .map(|(bt, rel_lock)| bt.height + rel_lock as u32),
+                            estimated_timestamp: heritage_utxo
+                                .estimate_heir_spending_timestamp(heir_config)
+                                .expect("heir_config comes from the HeritageConfig"),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        events.sort_by_key(|event| (event.estimated_timestamp, event.outpoint));
+        Self { events }
+    }
+    /// Returns the [InheritanceSchedule] restricted to the events of the given heir
+    pub fn for_heir(&self, heir_config: &HeirConfig) -> Self {
+        Self {
+            events: self
+                .events
+                .iter()
+                .filter(|event| event.heir_config == *heir_config)
+                .cloned()
+                .collect(),
+        }
+    }
+    /// Returns the next [InheritanceEvent], i.e. the next time the owner will lose
+    /// the exclusivity over some of the funds, if any.
+    pub fn next_expiration(&self) -> Option<&InheritanceEvent> {
+        self.next_expiration_after(crate::utils::timestamp_now())
+    }
+    /// Returns the first [InheritanceEvent] with an estimated timestamp
+    /// strictly greater than the given `timestamp`, if any.
+    pub fn next_expiration_after(&self, timestamp: u64) -> Option<&InheritanceEvent> {
+        self.events
+            .iter()
+            .find(|event| event.estimated_timestamp > timestamp)
+    }
+}
+
+/// Policy used by [super::HeritageWallet::check_rotation_due] to decide when the wallet
+/// should use a new [HeritageConfig] and/or renew its UTXOs
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RotationPolicy {
+    /// Use a new [HeritageConfig] when the current subwallet has been in use for this number of days, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_config_age_days: Option<u16>,
+    /// Number of days ahead to look at for UTXOs becoming spendable by an heir
+    pub renewal_window_days: u16,
+    /// Renew the UTXOs when at least this percentage of the funds will become spendable
+    /// by an heir within the renewal window
+    pub renewal_threshold_percent: u8,
+}
+impl Default for RotationPolicy {
+    /// We arbitrarly choose to rotate the [HeritageConfig] every year and
+    /// to renew as soon as any fund will be spendable by an heir within 30 days
+    fn default() -> Self {
+        Self {
+            max_config_age_days: Some(365),
+            renewal_window_days: 30,
+            renewal_threshold_percent: 1,
+        }
+    }
+}
+
+/// The result of [super::HeritageWallet::check_rotation_due]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RotationStatus {
+    /// A new [HeritageConfig] should be set with [super::HeritageWallet::update_heritage_config]
+    pub heritage_config_update_due: bool,
+    /// The UTXOs should be renewed, see [super::HeritageWallet::plan_renewal]
+    pub renewal_due: bool,
+}
+
+/// The balance of one subwallet, see [BalanceDetails]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SubwalletBalance {
+    pub subwallet_id: SubwalletId,
+    /// `true` if this is the subwallet of the current [HeritageConfig]
+    pub is_current: bool,
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub confirmed: Amount,
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub unconfirmed: Amount,
+}
+
+/// The funds of the wallet bucketed by the moment an heir becomes able to spend them.
+/// Each UTXO is counted in a single bucket, according to its earliest heir.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HeirEligibilityBuckets {
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub eligible_now: Amount,
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub eligible_within_30_days: Amount,
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub eligible_within_90_days: Amount,
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub eligible_within_365_days: Amount,
+    /// The funds no heir can spend within 365 days, including the key-path-only ones
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub not_eligible_within_365_days: Amount,
+}
+
+/// The detailed balance of the wallet, see [super::HeritageWallet::get_balance_details]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BalanceDetails {
+    /// The funds the owner can spend now, i.e. every UTXO of the wallet
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub owner_spendable: Amount,
+    /// The balances of the subwallets, ordered by [SubwalletId]
+    pub subwallets: Vec<SubwalletBalance>,
+    pub heir_eligibility: HeirEligibilityBuckets,
+}
+
+/// The fee of a new transaction of the owner, see [super::HeritageWallet::estimate_fee]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeePreview {
+    /// The expected virtual size of the signed transaction, in vbytes
+    pub vsize: u64,
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub fee: Amount,
+    /// The number of inputs spent from each subwallet, ordered by [SubwalletId].
+    /// The subwallets without input are omitted.
+    pub subwallet_inputs: Vec<(SubwalletId, usize)>,
+}
+
+/// A self-transfer renewing the UTXOs that are about to become spendable by heirs,
+/// see [super::HeritageWallet::plan_renewal]
+#[derive(Debug, Clone)]
+pub struct RenewalPlan {
+    /// For each refreshed UTXO, its earliest [InheritanceEvent], i.e. the one the renewal prevents
+    pub refreshed: Vec<InheritanceEvent>,
+    /// The self-transfer [Psbt] to sign and broadcast
+    pub psbt: Psbt,
+    /// The summary of the self-transfer, including its fee
+    pub tx_summary: TransactionSummary,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TransactionSummaryOwnedIO {
     pub outpoint: OutPoint,
@@ -316,6 +730,60 @@ pub struct TransactionSummaryOwnedIO {
     pub amount: Amount,
 }
 
+/// Whether an heir gained or lost a claim on some of the funds of the wallet
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EligibilityChange {
+    Gained,
+    Lost,
+}
+
+/// A change of eligibility of an heir caused by a transaction
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HeirEligibilityChange {
+    pub heir_config: HeirConfig,
+    pub change: EligibilityChange,
+}
+
+/// A [TransactionSummary] annotated by [HeritageWallet::get_history](super::HeritageWallet::get_history)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub transaction_summary: TransactionSummary,
+    /// The variation of the wallet balance caused by the transaction, fee included
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub balance_delta: SignedAmount,
+    /// The balance of the wallet right after the transaction
+    #[serde(with = "crate::bitcoin::amount::serde::as_sat")]
+    pub running_balance: Amount,
+    /// `true` if the transaction moved existing funds of the wallet to new heritage-protected
+    /// outputs, restarting their timelocks
+    pub refreshed_timelocks: bool,
+    /// The heirs that gained or lost a claim on the funds of the wallet with this transaction
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub heir_eligibility_changes: Vec<HeirEligibilityChange>,
+}
+
+/// The wallet item a label is attached to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", content = "ref")]
+pub enum LabelRef {
+    /// An address of the wallet, or an external address it sent funds to
+    #[serde(rename = "addr")]
+    Address(CheckedAddress),
+    /// A transaction of the wallet
+    #[serde(rename = "tx")]
+    Tx(Txid),
+}
+impl Display for LabelRef {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LabelRef::Address(address) => write!(f, "addr:{address}"),
+            LabelRef::Tx(txid) => write!(f, "tx:{txid}"),
+        }
+    }
+}
+
 /// A wallet transaction
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TransactionSummary {
@@ -336,6 +804,13 @@ pub struct TransactionSummary {
     pub fee_rate: FeeRate,
     /// The previous [Txid] of the same block on which this transaction depends. For ordering purposes
     pub parent_txids: HashSet<Txid>,
+    /// The user label of the transaction, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// `true` if an owned output of this transaction pays to an address that received
+    /// more than one payment, see [HeritageWallet::list_reused_addresses](super::HeritageWallet::list_reused_addresses)
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub address_reused: bool,
 }
 
 // /// A descriptors backup to export an HeritageWallet configuration
@@ -349,13 +824,57 @@ pub struct TransactionSummary {
 //     pub last_change_index: Option<u32>,
 // }
 
-/// A [Address<NetworkChecked>] with [(Fingerprint, DerivationPath)] informations
+/// The result of a successful [HeritageWallet::verify_address](super::HeritageWallet::verify_address)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(any(test, feature = "database-tests"), derive(Eq, PartialEq))]
-#[serde(into = "String", try_from = "String")]
+pub struct AddressOrigin {
+    /// The verified address, with its full derivation path and its label if any
+    pub wallet_address: WalletAddress,
+    /// `true` if the address is a change address
+    pub is_change: bool,
+    /// The [SubwalletId] of the subwallet the address belongs to
+    pub subwallet_id: SubwalletId,
+    /// `true` if the address belongs to the subwallet of the current [HeritageConfig]
+    pub is_current: bool,
+}
+
+/// The audit of an [AccountXPub](crate::AccountXPub) of an [HeritageWallet](super::HeritageWallet),
+/// see [HeritageWallet::audit_account_xpubs](super::HeritageWallet::audit_account_xpubs)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AccountXPubAudit {
+    pub account_xpub: crate::AccountXPub,
+    pub fingerprint: Fingerprint,
+    pub derivation_path: DerivationPath,
+    /// The [SubwalletId] of the subwallet using the account xpub, [None] if it is unused
+    pub subwallet_id: Option<SubwalletId>,
+    /// The first time the subwallet using the account xpub received funds, if any
+    pub firstuse_time: Option<u64>,
+    /// `true` if the subwallet using the account xpub is the current one
+    pub is_current: bool,
+    /// `false` if the [Fingerprint] of the account xpub is not the one of the wallet,
+    /// i.e. the account xpub was not created by the device currently holding the wallet keys
+    pub matches_wallet_fingerprint: bool,
+}
+
+/// An address of the wallet that received more than one payment,
+/// see [HeritageWallet::list_reused_addresses](super::HeritageWallet::list_reused_addresses)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReusedAddress {
+    pub address: CheckedAddress,
+    /// The transactions paying to the address
+    pub txids: Vec<Txid>,
+}
+
+/// A [Address<NetworkChecked>] with [(Fingerprint, DerivationPath)] informations
+///
+/// It serializes as its [Display] string `[<fingerprint>/<derivation_path>]<address>`
+/// or, if it has a label, as `{"address": "<Display string>", "label": "<label>"}`
+#[derive(Debug, Clone)]
+#[cfg_attr(any(test, feature = "database-tests"), derive(Eq, PartialEq))]
 pub struct WalletAddress {
     pub(crate) origin: (Fingerprint, DerivationPath),
     pub(crate) address: Address<NetworkChecked>,
+    pub(crate) label: Option<String>,
 }
 impl WalletAddress {
     pub fn origin(&self) -> &(Fingerprint, DerivationPath) {
@@ -364,6 +883,40 @@ impl WalletAddress {
     pub fn address(&self) -> &Address {
         &self.address
     }
+    /// The user label of the address, if any
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+}
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SerdeWalletAddress {
+    Bare(String),
+    Labeled { address: String, label: String },
+}
+impl Serialize for WalletAddress {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match &self.label {
+            Some(label) => SerdeWalletAddress::Labeled {
+                address: self.to_string(),
+                label: label.clone(),
+            },
+            None => SerdeWalletAddress::Bare(self.to_string()),
+        }
+        .serialize(serializer)
+    }
+}
+impl<'de> Deserialize<'de> for WalletAddress {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (address, label) = match SerdeWalletAddress::deserialize(deserializer)? {
+            SerdeWalletAddress::Bare(address) => (address, None),
+            SerdeWalletAddress::Labeled { address, label } => (address, Some(label)),
+        };
+        let mut wallet_address =
+            WalletAddress::try_from(address.as_str()).map_err(serde::de::Error::custom)?;
+        wallet_address.label = label;
+        Ok(wallet_address)
+    }
 }
 impl Deref for WalletAddress {
     type Target = Address<NetworkChecked>;
@@ -377,6 +930,7 @@ impl From<((Fingerprint, DerivationPath), Address<NetworkChecked>)> for WalletAd
         Self {
             origin: value.0,
             address: value.1,
+            label: None,
         }
     }
 }
@@ -415,7 +969,8 @@ impl TryFrom<&str> for WalletAddress {
             log::error!("Could not parse derivation_path: {derivation_path_str} ({e})");
             error_c()
         })?;
-        let address = string_to_address(address_str).map_err(|e| {
+        // The address was derived by the wallet, for its network
+        let address = string_to_address_assume_checked(address_str).map_err(|e| {
             log::error!("Could not parse address: {address_str} ({e})");
             error_c()
         })?;
@@ -423,6 +978,7 @@ impl TryFrom<&str> for WalletAddress {
         Ok(Self {
             origin: (fingerprint, derivation_path),
             address,
+            label: None,
         })
     }
 }
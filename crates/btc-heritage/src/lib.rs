@@ -3,6 +3,7 @@ pub mod database;
 pub mod errors;
 pub mod heritage_config;
 pub mod heritage_wallet;
+pub mod network;
 pub mod subwallet_config;
 pub mod utils;
 
@@ -13,12 +14,15 @@ pub use heritage_wallet::{
     backup::{HeritageWalletBackup, SubwalletDescriptorBackup},
     BlockInclusionObjective, HeritageWallet, HeritageWalletBalance, Recipient, SpendingConfig,
 };
+pub use network::BitcoinNetwork;
 
 pub use bdk::bitcoin;
 pub use bdk::miniscript;
 
 #[cfg(feature = "online")]
 pub use bdk::{bitcoincore_rpc, electrum_client};
+#[cfg(feature = "esplora-async")]
+pub use esplora_client;
 
 // Publicly exposed BDK types
 pub mod bdk_types {
@@ -31,8 +35,14 @@ pub mod bdk_types {
     pub use bdk::blockchain::{
         electrum::ElectrumBlockchain,
         rpc::{Auth, RpcBlockchainFactory},
-        BlockchainFactory,
+        Blockchain, BlockchainFactory, Progress,
     };
+
+    #[cfg(feature = "compact-filters")]
+    pub use bdk::blockchain::compact_filters::BitcoinPeerConfig;
+
+    #[cfg(feature = "esplora")]
+    pub use bdk::blockchain::esplora::EsploraBlockchain;
 }
 
 #[cfg(test)]
@@ -58,6 +68,11 @@ mod tests {
     pub const TR_EXTERNAL_RECIPIENT_ADDR: &'static str =
         "bcrt1pj74kr57y4t5d4nxf8qz2rytac86k2cawpeh2eq2plnlkmc0yxngs0kyqyn";
 
+    /// Parse `s` into a Regtest [Address](crate::bitcoin::Address)
+    pub fn string_to_address(s: &str) -> Result<crate::bitcoin::Address, crate::errors::Error> {
+        crate::utils::string_to_address_for_network(s, crate::bitcoin::Network::Regtest)
+    }
+
     const EXPECTED_VALUES: [[&str; 4]; 3] = [
         [
             "tr([9c7088e3/86'/1'/0']tpubDD2pKf3K2M2oukBVyGLVBKhqMV2MC5jQ3ABYNY17tFUgkq8Y2M65yBmeZHiz9gwrYfYkCZqipP9pL5NGwkSSsS2dijy7Nus1DLJLr6FQyWv/0/*,\
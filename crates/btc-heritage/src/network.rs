@@ -0,0 +1,171 @@
+//! The Bitcoin networks on which an Heritage wallet can operate.
+//!
+//! [BitcoinNetwork] is a superset of [Network]: it also knows about Testnet4, which shares
+//! the address and extended key formats of Testnet (and so maps to [Network::Testnet]) but
+//! is a different chain, with its own backends and endpoints.
+
+use core::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::bitcoin::Network;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub enum BitcoinNetwork {
+    Bitcoin,
+    Testnet,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl BitcoinNetwork {
+    pub const ALL: [BitcoinNetwork; 5] = [
+        BitcoinNetwork::Bitcoin,
+        BitcoinNetwork::Testnet,
+        BitcoinNetwork::Testnet4,
+        BitcoinNetwork::Signet,
+        BitcoinNetwork::Regtest,
+    ];
+
+    /// Return the [Network] used for the keys, descriptors and addresses
+    pub fn network(self) -> Network {
+        match self {
+            BitcoinNetwork::Bitcoin => Network::Bitcoin,
+            BitcoinNetwork::Testnet | BitcoinNetwork::Testnet4 => Network::Testnet,
+            BitcoinNetwork::Signet => Network::Signet,
+            BitcoinNetwork::Regtest => Network::Regtest,
+        }
+    }
+
+    /// Return the name of the chain for Bitcoin Core (`-chain=<name>`)
+    pub fn core_chain(self) -> &'static str {
+        match self {
+            BitcoinNetwork::Bitcoin => "main",
+            BitcoinNetwork::Testnet => "test",
+            BitcoinNetwork::Testnet4 => "testnet4",
+            BitcoinNetwork::Signet => "signet",
+            BitcoinNetwork::Regtest => "regtest",
+        }
+    }
+
+    /// Return the default RPC port of Bitcoin Core
+    pub fn default_rpc_port(self) -> u16 {
+        match self {
+            BitcoinNetwork::Bitcoin => 8332,
+            BitcoinNetwork::Testnet => 18332,
+            BitcoinNetwork::Testnet4 => 48332,
+            BitcoinNetwork::Signet => 38332,
+            BitcoinNetwork::Regtest => 18443,
+        }
+    }
+
+    /// Return the base URL of the public [mempool.space](https://mempool.space) instance
+    /// of the network, or [None] for [BitcoinNetwork::Regtest]
+    pub fn mempool_space_url(self) -> Option<&'static str> {
+        match self {
+            BitcoinNetwork::Bitcoin => Some("https://mempool.space"),
+            BitcoinNetwork::Testnet => Some("https://mempool.space/testnet"),
+            BitcoinNetwork::Testnet4 => Some("https://mempool.space/testnet4"),
+            BitcoinNetwork::Signet => Some("https://mempool.space/signet"),
+            BitcoinNetwork::Regtest => None,
+        }
+    }
+
+    /// Return the URL of the public Esplora API of the network, served by
+    /// [mempool.space](https://mempool.space), or [None] for [BitcoinNetwork::Regtest]
+    pub fn esplora_url(self) -> Option<String> {
+        self.mempool_space_url().map(|url| format!("{url}/api"))
+    }
+}
+
+impl From<Network> for BitcoinNetwork {
+    fn from(value: Network) -> Self {
+        match value {
+            Network::Bitcoin => BitcoinNetwork::Bitcoin,
+            Network::Testnet => BitcoinNetwork::Testnet,
+            Network::Signet => BitcoinNetwork::Signet,
+            _ => BitcoinNetwork::Regtest,
+        }
+    }
+}
+
+impl From<BitcoinNetwork> for Network {
+    fn from(value: BitcoinNetwork) -> Self {
+        value.network()
+    }
+}
+
+impl Display for BitcoinNetwork {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            BitcoinNetwork::Bitcoin => "bitcoin",
+            BitcoinNetwork::Testnet => "testnet",
+            BitcoinNetwork::Testnet4 => "testnet4",
+            BitcoinNetwork::Signet => "signet",
+            BitcoinNetwork::Regtest => "regtest",
+        })
+    }
+}
+
+impl FromStr for BitcoinNetwork {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bitcoin" | "mainnet" | "main" => Ok(BitcoinNetwork::Bitcoin),
+            "testnet" | "testnet3" | "test" => Ok(BitcoinNetwork::Testnet),
+            "testnet4" => Ok(BitcoinNetwork::Testnet4),
+            "signet" => Ok(BitcoinNetwork::Signet),
+            "regtest" => Ok(BitcoinNetwork::Regtest),
+            _ => Err(format!("{s} is not a known Bitcoin network")),
+        }
+    }
+}
+
+impl From<BitcoinNetwork> for String {
+    fn from(value: BitcoinNetwork) -> Self {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for BitcoinNetwork {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        BitcoinNetwork::from_str(&value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bitcoin_network() {
+        for bn in BitcoinNetwork::ALL {
+            assert_eq!(BitcoinNetwork::from_str(&bn.to_string()), Ok(bn));
+            assert_eq!(
+                serde_json::from_value::<BitcoinNetwork>(serde_json::to_value(bn).unwrap())
+                    .unwrap(),
+                bn
+            );
+        }
+        assert_eq!(BitcoinNetwork::Testnet4.network(), Network::Testnet);
+        assert_eq!(
+            BitcoinNetwork::from(Network::Signet),
+            BitcoinNetwork::Signet
+        );
+        assert_eq!(
+            BitcoinNetwork::from_str("mainnet"),
+            Ok(BitcoinNetwork::Bitcoin)
+        );
+        assert!(BitcoinNetwork::from_str("testnet5").is_err());
+        assert_eq!(
+            BitcoinNetwork::Testnet4.esplora_url().as_deref(),
+            Some("https://mempool.space/testnet4/api")
+        );
+        assert!(BitcoinNetwork::Regtest.esplora_url().is_none());
+    }
+}
@@ -2,8 +2,9 @@ use core::str::FromStr;
 
 use crate::{
     account_xpub::AccountXPub,
+    bitcoin::Network,
     errors::{Error, Result},
-    heritage_config::{FromDescriptorScripts, HeritageConfig},
+    heritage_config::{v1::OwnerQuorum, FromDescriptorScripts, HeritageConfig},
     miniscript::{Descriptor, DescriptorPublicKey},
     utils, SubwalletDescriptorBackup,
 };
@@ -33,6 +34,10 @@ pub struct SubwalletConfig {
     heritage_config: HeritageConfig,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     subwallet_firstuse_time: Option<SubwalletFirstUseTime>,
+    /// When present, the change outputs of the subwallet go to this key-path-only
+    /// descriptor instead of `change_descriptor`, i.e. they are not protected by the heirs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keypath_change_descriptor: Option<Descriptor<DescriptorPublicKey>>,
 }
 
 impl SubwalletConfig {
@@ -43,6 +48,12 @@ impl SubwalletConfig {
     // the ext_index and change_index values
     pub const DEFAULT_EXTERNAL_INDEX: u32 = 0;
     pub const DEFAULT_CHANGE_INDEX: u32 = 1;
+    pub const DEFAULT_KEYPATH_CHANGE_INDEX: u32 = 2;
+    /// The internal key used when the owner is an [OwnerQuorum].
+    /// It is the "H" point of BIP-341, for which nobody knows the private key,
+    /// so the key-path of the descriptors is unspendable.
+    pub const UNSPENDABLE_INTERNAL_KEY: &str =
+        "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
 
     pub fn new(account_xpub: AccountXPub, heritage_config: HeritageConfig) -> Self {
         log::debug!(
@@ -86,9 +97,33 @@ impl SubwalletConfig {
             subwallet_firstuse_time: None,
             account_xpub,
             heritage_config,
+            keypath_change_descriptor: None,
         }
     }
 
+    /// Send the change outputs of the subwallet to a key-path-only descriptor
+    /// derived at [SubwalletConfig::DEFAULT_KEYPATH_CHANGE_INDEX], without any heir script path.
+    ///
+    /// The heritage `change_descriptor` is kept in the [SubwalletConfig] but no longer used.
+    pub fn with_keypath_change(mut self) -> Self {
+        let keypath_change_descriptor =
+            Self::create_keypath_descriptor(&self.account_xpub, Self::DEFAULT_KEYPATH_CHANGE_INDEX);
+        log::debug!(
+            "SubwalletConfig::with_keypath_change - keypath_change_descriptor={keypath_change_descriptor}"
+        );
+        self.keypath_change_descriptor = Some(keypath_change_descriptor);
+        self
+    }
+
+    pub fn create_keypath_descriptor(
+        account_xpub: &AccountXPub,
+        index: u32,
+    ) -> Descriptor<DescriptorPublicKey> {
+        let descriptor_public_key = account_xpub.child_descriptor_public_key(index);
+        Descriptor::<DescriptorPublicKey>::from_str(&format!("tr({descriptor_public_key})"))
+            .expect("we produce valid descriptor strings")
+    }
+
     pub fn create_descriptors(
         account_xpub: &AccountXPub,
         heritage_config: &HeritageConfig,
@@ -99,12 +134,27 @@ impl SubwalletConfig {
         Descriptor<DescriptorPublicKey>,
     ) {
         let mut descriptor_iterator = [external_index, change_index].into_iter().map(|index| {
-            let descriptor_public_key = account_xpub.child_descriptor_public_key(index);
             let descriptor_taptree_miniscript_expression =
                 heritage_config.descriptor_taptree_miniscript_expression_for_child(Some(index));
-            let descriptor_string = match &descriptor_taptree_miniscript_expression {
-                Some(script_paths) => format!("tr({descriptor_public_key},{script_paths})"),
-                None => format!("tr({descriptor_public_key})"),
+            let descriptor_string = match heritage_config.owner_quorum() {
+                // The owner quorum leaf is always the first leaf, at the top of the TapTree
+                Some(owner_quorum) => {
+                    let unspendable_key = Self::UNSPENDABLE_INTERNAL_KEY;
+                    let owner_leaf = owner_quorum.descriptor_segment(account_xpub, Some(index));
+                    match &descriptor_taptree_miniscript_expression {
+                        Some(script_paths) => {
+                            format!("tr({unspendable_key},{{{owner_leaf},{script_paths}}})")
+                        }
+                        None => format!("tr({unspendable_key},{owner_leaf})"),
+                    }
+                }
+                None => {
+                    let descriptor_public_key = account_xpub.child_descriptor_public_key(index);
+                    match &descriptor_taptree_miniscript_expression {
+                        Some(script_paths) => format!("tr({descriptor_public_key},{script_paths})"),
+                        None => format!("tr({descriptor_public_key})"),
+                    }
+                }
             };
             Descriptor::<DescriptorPublicKey>::from_str(&descriptor_string)
                 .expect("we produce valid descriptor strings")
@@ -115,11 +165,16 @@ impl SubwalletConfig {
         )
     }
 
-    pub fn get_subwallet<DB: BatchDatabase>(&self, subdatabase: DB) -> Wallet<DB> {
+    /// Create the [Wallet] of the subwallet for the `network`, backed by `subdatabase`
+    pub fn get_subwallet<DB: BatchDatabase>(
+        &self,
+        subdatabase: DB,
+        network: Network,
+    ) -> Wallet<DB> {
         Wallet::new(
             self.ext_descriptor.clone(),
-            Some(self.change_descriptor.clone()),
-            *utils::bitcoin_network_from_env(),
+            Some(self.internal_descriptor().clone()),
+            network,
             subdatabase,
         )
         .expect("failed because descriptors checksums are inconsistent with previous DB values")
@@ -158,6 +213,23 @@ impl SubwalletConfig {
         &self.change_descriptor
     }
 
+    pub fn keypath_change_descriptor(&self) -> Option<&Descriptor<DescriptorPublicKey>> {
+        self.keypath_change_descriptor.as_ref()
+    }
+
+    /// Returns `true` if the change outputs of the subwallet are key-path-only
+    pub fn has_keypath_change(&self) -> bool {
+        self.keypath_change_descriptor.is_some()
+    }
+
+    /// The descriptor actually used for the change outputs of the subwallet, i.e. the
+    /// `keypath_change_descriptor` if any, else the `change_descriptor`
+    pub fn internal_descriptor(&self) -> &Descriptor<DescriptorPublicKey> {
+        self.keypath_change_descriptor
+            .as_ref()
+            .unwrap_or(&self.change_descriptor)
+    }
+
     /// Consume the [SubwalletConfig] in order to retrieve its
     /// [AccountXPub] and [HeritageConfig] without having to clone anything
     pub fn into_parts(self) -> (AccountXPub, HeritageConfig) {
@@ -222,12 +294,49 @@ impl TryFrom<&SubwalletDescriptorBackup> for SubwalletConfig {
             .captures(&desc)
             .ok_or(Error::InvalidBackup("descriptors are not Tr"))?;
 
-        let account_xpub = AccountXPub::try_from(&capts["key"])?;
         let scripts = capts
             .name("scripts")
             .map(|cap| cap.as_str())
             .unwrap_or_default();
         let heritage_config = HeritageConfig::from_descriptor_scripts(scripts)?;
+        let account_xpub = if &capts["key"] == Self::UNSPENDABLE_INTERNAL_KEY {
+            // The owner is a quorum, the AccountXPub is the first key of the owner leaf
+            if heritage_config.owner_quorum().is_none() || sdb.keypath_change_descriptor.is_some() {
+                return Err(Error::InvalidBackup(
+                    "unspendable internal key without a valid owner quorum",
+                ));
+            }
+            let owner_leaf = scripts
+                .trim_start_matches('{')
+                .split_once(')')
+                .map(|(owner_leaf, _)| format!("{owner_leaf})"))
+                .unwrap_or_default();
+            OwnerQuorum::from_descriptor_fragment(&owner_leaf)?.0
+        } else if heritage_config.owner_quorum().is_some() {
+            return Err(Error::InvalidBackup(
+                "owner quorum with a spendable internal key",
+            ));
+        } else {
+            AccountXPub::try_from(&capts["key"])?
+        };
+
+        // The key-path-only change descriptor, if any, must be a bare key of the same AccountXPub
+        if let Some(keypath_change_descriptor) = &sdb.keypath_change_descriptor {
+            let kp_desc = format!("{keypath_change_descriptor:#}");
+            let kp_desc = re_account_xpub().replace_all(&kp_desc, "${key}/*");
+            let kp_capts = re_descriptor()
+                .captures(&kp_desc)
+                .ok_or(Error::InvalidBackup("keypath change descriptor is not Tr"))?;
+            if kp_capts.name("scripts").is_some()
+                || AccountXPub::try_from(&kp_capts["key"])? != account_xpub
+            {
+                log::error!("external and keypath change descriptor are not compatible");
+                log::error!("keypath change: {kp_desc}");
+                return Err(Error::InvalidBackup(
+                    "external and keypath change descriptor are not compatible",
+                ));
+            }
+        }
 
         Ok(Self {
             ext_descriptor: sdb.external_descriptor.clone(),
@@ -235,6 +344,7 @@ impl TryFrom<&SubwalletDescriptorBackup> for SubwalletConfig {
             account_xpub,
             heritage_config,
             subwallet_firstuse_time: sdb.first_use_ts.map(|ts| SubwalletFirstUseTime(ts)),
+            keypath_change_descriptor: sdb.keypath_change_descriptor.clone(),
         })
     }
 }
@@ -251,7 +361,7 @@ mod tests {
     use core::str::FromStr;
     use std::collections::BTreeMap;
 
-    use crate::{tests::*, utils::string_to_address};
+    use crate::tests::*;
 
     use super::*;
 
@@ -501,6 +611,7 @@ mod tests {
             first_use_ts: Some(1720879341),
             last_external_index: None,
             last_change_index: None,
+            keypath_change_descriptor: None,
         };
         assert!(SubwalletConfig::try_from(&invalid_backup).is_err());
 
@@ -511,6 +622,7 @@ mod tests {
             first_use_ts: Some(1720879341),
             last_external_index: None,
             last_change_index: None,
+            keypath_change_descriptor: None,
         };
         assert!(SubwalletConfig::try_from(&invalid_backup).is_err());
 
@@ -521,6 +633,7 @@ mod tests {
             first_use_ts: Some(1720879341),
             last_external_index: None,
             last_change_index: None,
+            keypath_change_descriptor: None,
         };
         assert!(SubwalletConfig::try_from(&invalid_backup).is_err());
 
@@ -531,6 +644,7 @@ mod tests {
             first_use_ts: Some(1720879341),
             last_external_index: None,
             last_change_index: None,
+            keypath_change_descriptor: None,
         };
         assert!(SubwalletConfig::try_from(&invalid_backup).is_err());
 
@@ -541,6 +655,7 @@ mod tests {
             first_use_ts: Some(1720879341),
             last_external_index: None,
             last_change_index: None,
+            keypath_change_descriptor: None,
         };
         assert!(SubwalletConfig::try_from(&invalid_backup).is_err());
 
@@ -551,6 +666,7 @@ mod tests {
             first_use_ts: Some(1720879341),
             last_external_index: None,
             last_change_index: None,
+            keypath_change_descriptor: None,
         };
         let swc = SubwalletConfig::try_from(&valid_backup);
         assert!(swc.is_ok(), "{}", swc.err().unwrap());
@@ -567,6 +683,7 @@ mod tests {
             first_use_ts: Some(1706600000),
             last_external_index: None,
             last_change_index: None,
+            keypath_change_descriptor: None,
         };
         let swc = SubwalletConfig::try_from(&valid_backup);
         assert!(swc.is_ok(), "{}", swc.err().unwrap());
@@ -583,6 +700,7 @@ mod tests {
                     first_use_ts: Some(1706600000),
                     last_external_index: None,
                     last_change_index: None,
+                    keypath_change_descriptor: None,
                 };
         let swc = SubwalletConfig::try_from(&valid_backup);
         assert!(swc.is_ok(), "{}", swc.err().unwrap());
@@ -592,4 +710,142 @@ mod tests {
         assert_eq!(swc.account_xpub().descriptor_id(), 1);
         assert_eq!(swc.heritage_config().iter_heir_configs().count(), 0);
     }
+
+    #[test]
+    fn keypath_change() {
+        let swc = get_default_test_subwallet_config(TestHeritageConfig::BackupWifeBro);
+        assert!(!swc.has_keypath_change());
+        assert_eq!(swc.internal_descriptor(), swc.change_descriptor());
+
+        let swc = swc.with_keypath_change();
+        assert!(swc.has_keypath_change());
+        let keypath_change_descriptor = swc.keypath_change_descriptor().unwrap();
+        assert_eq!(swc.internal_descriptor(), keypath_change_descriptor);
+        assert_eq!(
+            keypath_change_descriptor,
+            &SubwalletConfig::create_keypath_descriptor(
+                swc.account_xpub(),
+                SubwalletConfig::DEFAULT_KEYPATH_CHANGE_INDEX
+            )
+        );
+        // No script path at all
+        let Descriptor::Tr(tr) = keypath_change_descriptor else {
+            panic!("must be Tr")
+        };
+        assert!(tr.tap_tree().is_none());
+
+        // Backup round-trip
+        let mut backup = SubwalletDescriptorBackup {
+            external_descriptor: swc.ext_descriptor().clone(),
+            change_descriptor: swc.change_descriptor().clone(),
+            first_use_ts: swc.subwallet_firstuse_time(),
+            last_external_index: None,
+            last_change_index: None,
+            keypath_change_descriptor: Some(keypath_change_descriptor.clone()),
+        };
+        assert_eq!(SubwalletConfig::try_from(&backup).unwrap(), swc);
+
+        // Invalid because the keypath change descriptor has scripts
+        backup.keypath_change_descriptor = Some(swc.change_descriptor().clone());
+        assert!(SubwalletConfig::try_from(&backup).is_err());
+        // Invalid because the keypath change descriptor uses another key
+        backup.keypath_change_descriptor = Some(SubwalletConfig::create_keypath_descriptor(
+            &get_test_account_xpub(5),
+            SubwalletConfig::DEFAULT_KEYPATH_CHANGE_INDEX,
+        ));
+        assert!(SubwalletConfig::try_from(&backup).is_err());
+    }
+
+    #[test]
+    fn owner_quorum() {
+        let owner_quorum = OwnerQuorum::new(
+            2,
+            vec![get_test_account_xpub(10), get_test_account_xpub(11)],
+        )
+        .unwrap();
+        let heritage_config = HeritageConfig::builder_v1()
+            .add_heritage(get_test_heritage(TestHeritage::Backup))
+            .add_heritage(get_test_heritage(TestHeritage::Wife))
+            .reference_time(1700000000)
+            .minimum_lock_time(90)
+            .owner_quorum(owner_quorum.clone())
+            .build();
+        let swc = SubwalletConfig::new(get_test_account_xpub(0), heritage_config);
+
+        // The key-path is unspendable and the owner leaf is the first one, without time locks
+        let Descriptor::Tr(tr) = swc.ext_descriptor() else {
+            panic!("must be Tr")
+        };
+        assert_eq!(
+            tr.internal_key().to_string(),
+            SubwalletConfig::UNSPENDABLE_INTERNAL_KEY
+        );
+        let leaves = tr.iter_scripts().collect::<Vec<_>>();
+        assert_eq!(leaves.len(), 3);
+        assert_eq!(leaves[0].0, 1);
+        assert_eq!(
+            leaves[0].1.to_string(),
+            owner_quorum.descriptor_segment(
+                &get_test_account_xpub(0),
+                Some(SubwalletConfig::DEFAULT_EXTERNAL_INDEX)
+            )
+        );
+        assert_eq!(leaves[0].1.iter_pk().count(), 3);
+
+        // Backup round-trip
+        let mut backup = SubwalletDescriptorBackup {
+            external_descriptor: swc.ext_descriptor().clone(),
+            change_descriptor: swc.change_descriptor().clone(),
+            first_use_ts: None,
+            last_external_index: None,
+            last_change_index: None,
+            keypath_change_descriptor: None,
+        };
+        assert_eq!(
+            backup.fingerprint().unwrap(),
+            Fingerprint::from_str("9c7088e3").unwrap()
+        );
+        assert_eq!(SubwalletConfig::try_from(&backup).unwrap(), swc);
+
+        // Invalid because the key-path is unspendable
+        backup.keypath_change_descriptor = Some(SubwalletConfig::create_keypath_descriptor(
+            swc.account_xpub(),
+            SubwalletConfig::DEFAULT_KEYPATH_CHANGE_INDEX,
+        ));
+        assert!(SubwalletConfig::try_from(&backup).is_err());
+
+        // Without heirs, the owner leaf is the only one
+        let heritage_config = HeritageConfig::builder_v1()
+            .owner_quorum(owner_quorum)
+            .build();
+        let swc = SubwalletConfig::new(get_test_account_xpub(0), heritage_config);
+        let Descriptor::Tr(tr) = swc.ext_descriptor() else {
+            panic!("must be Tr")
+        };
+        assert_eq!(tr.iter_scripts().count(), 1);
+        let backup = SubwalletDescriptorBackup {
+            external_descriptor: swc.ext_descriptor().clone(),
+            change_descriptor: swc.change_descriptor().clone(),
+            first_use_ts: None,
+            last_external_index: None,
+            last_change_index: None,
+            keypath_change_descriptor: None,
+        };
+        let restored_swc = SubwalletConfig::try_from(&backup).unwrap();
+        assert_eq!(restored_swc.account_xpub(), swc.account_xpub());
+        assert_eq!(
+            restored_swc.heritage_config().owner_quorum(),
+            swc.heritage_config().owner_quorum()
+        );
+
+        // Invalid quorums
+        assert!(OwnerQuorum::new(1, vec![]).is_err());
+        assert!(OwnerQuorum::new(0, vec![get_test_account_xpub(10)]).is_err());
+        assert!(OwnerQuorum::new(3, vec![get_test_account_xpub(10)]).is_err());
+        assert!(OwnerQuorum::new(
+            2,
+            vec![get_test_account_xpub(10), get_test_account_xpub(10)]
+        )
+        .is_err());
+    }
 }
@@ -1,14 +1,20 @@
 use core::{cmp::Ordering, fmt::Write, str::FromStr};
-use std::{
-    collections::{HashMap, HashSet},
-    sync::OnceLock,
-};
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     bitcoin::{
-        psbt::PartiallySignedTransaction, secp256k1::Secp256k1, Address, Network, Transaction,
+        absolute::LockTime,
+        opcodes::{
+            all::{OP_CLTV, OP_CSV},
+            Class, ClassifyContext,
+        },
+        psbt::{Input, PartiallySignedTransaction},
+        script::{read_scriptint, Instruction},
+        secp256k1::Secp256k1,
+        taproot::TapLeafHash,
+        Address, Network, Script, Sequence, Transaction,
     },
-    errors::Error,
+    errors::{Error, TimeLockIssue},
     miniscript::psbt::PsbtExt,
 };
 
@@ -19,6 +25,10 @@ use serde_json::json;
 /// The Bitcoin network targets 10 minutes
 pub const AVERAGE_BLOCK_TIME_SEC: u32 = 60 * 10;
 
+/// The default maximum number of days an heir transaction may remain non-final when it is
+/// finalized, see [finalize_heir_psbt]
+pub const DEFAULT_MAX_NON_FINAL_DAYS: u16 = 1;
+
 pub fn bytes_to_hex_string<B: AsRef<[u8]>>(bytes: B) -> String {
     let bytes = bytes.as_ref();
     let mut s = String::with_capacity(2 * bytes.len());
@@ -28,50 +38,66 @@ pub fn bytes_to_hex_string<B: AsRef<[u8]>>(bytes: B) -> String {
     s
 }
 
-pub fn bitcoin_network_from_env() -> &'static Network {
-    static BITCOIN_NETWORK: OnceLock<Network> = OnceLock::new();
-    BITCOIN_NETWORK.get_or_init(|| {
-        #[cfg(not(any(test, feature = "database-tests", feature = "psbt-tests")))]
-        let bitcoin_network = match std::env::var("BITCOIN_NETWORK") {
-            Ok(bitcoin_network) => match bitcoin_network.as_str() {
-                "bitcoin" => Network::Bitcoin,
-                "testnet" => Network::Testnet,
-                "regtest" => Network::Regtest,
-                "signet" => Network::Signet,
-                _ => {
-                    log::warn!(
-                        "environment variable `BITCOIN_NETWORK` is set to unknown value: \
-                        \"{bitcoin_network}\". Using Network::Testnet."
-                    );
-                    Network::Testnet
-                }
-            },
-            Err(_) => {
-                log::warn!(
-                    "environment variable `BITCOIN_NETWORK` is not set. Using Network::Bitcoin."
-                );
-                Network::Bitcoin
-            }
-        };
-        #[cfg(any(test, feature = "database-tests", feature = "psbt-tests"))]
-        let bitcoin_network = Network::Regtest;
-
-        log::info!("BITCOIN_NETWORK={bitcoin_network:?}");
-        bitcoin_network
-    })
+/// Parse `s` into an [Address] of the network deduced from its prefix, without verifying it
+/// against an expected [Network], like `Address::assume_checked`.
+///
+/// Only for addresses whose network was or will be verified elsewhere, e.g. a stored
+/// [CheckedAddress](crate::heritage_wallet::CheckedAddress) or a recipient of a
+/// [HeritageWallet](crate::HeritageWallet) PSBT. Use [string_to_address_for_network] otherwise.
+pub(crate) fn string_to_address_assume_checked(s: &str) -> Result<Address, Error> {
+    Ok(Address::from_str(s)
+        .map_err(|e| {
+            log::error!("Could not parse {s}: {e:#}");
+            Error::InvalidAddress(s.to_owned())
+        })?
+        .assume_checked())
 }
 
-pub fn string_to_address(s: &str) -> Result<Address, Error> {
+/// Parse `s` into an [Address], verifying that it is valid for `network`
+pub fn string_to_address_for_network(s: &str, network: Network) -> Result<Address, Error> {
     Ok(Address::from_str(s)
         .map_err(|e| {
             log::error!("Could not parse {s}: {e:#}");
-            Error::InvalidAddressString(s.to_owned(), *bitcoin_network_from_env())
+            Error::InvalidAddressString(s.to_owned(), network)
         })?
-        .require_network(*bitcoin_network_from_env())
-        .map_err(|_| Error::InvalidAddressString(s.to_owned(), *bitcoin_network_from_env()))?)
+        .require_network(network)
+        .map_err(|_| Error::InvalidAddressString(s.to_owned(), network))?)
+}
+
+/// Format `timestamp`, a number of seconds since UNIX_EPOCH, as an UTC date and time,
+/// e.g. `2024-05-12 22:13:20 UTC`
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (days, secs) = ((timestamp / 86400) as i64, timestamp % 86400);
+    // Civil date from the number of days since 1970-01-01,
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Describe `lock_time` in a human readable way: a date for time-based lock times,
+/// a block height otherwise
+pub fn describe_lock_time(lock_time: &LockTime) -> String {
+    match lock_time {
+        LockTime::Blocks(height) => format!("block {height}"),
+        LockTime::Seconds(time) => format_timestamp(time.to_consensus_u32() as u64),
+    }
 }
 
 /// Returns the current timestamp, as the number of seconds since UNIX_EPOCH
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
 pub fn timestamp_now() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -79,6 +105,12 @@ pub fn timestamp_now() -> u64 {
         .as_secs()
 }
 
+/// [std::time::SystemTime::now] panics on wasm32-unknown-unknown, use the clock of the JS environment
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn timestamp_now() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
+
 pub fn extract_tx(psbt: PartiallySignedTransaction) -> Result<Transaction, Error> {
     log::debug!("extract_tx - psbt: {}", json!(psbt));
     let psbt = psbt.finalize(&Secp256k1::new()).map_err(|(psbt, errors)| {
@@ -117,6 +149,203 @@ pub fn extract_tx(psbt: PartiallySignedTransaction) -> Result<Transaction, Error
     Ok(raw_tx)
 }
 
+/// Finalize the given [PartiallySignedTransaction] and extract the signed [Transaction].
+///
+/// It handles both the key-path spends of the owner and the script-path spends of the heirs,
+/// in which case the witness stack is built from the script signatures and the leaf script
+/// of the input. Inputs that are already finalized are left untouched.
+///
+/// The time locks of the heir inputs are verified first, see [check_heir_time_locks].
+///
+/// # Errors
+/// Returns [Error::UnfinalizablePsbtInputs] listing every input that cannot be finalized and why,
+/// typically because of missing signatures, and [Error::InconsistentTimeLocks] if the time locks
+/// of the transaction do not match the spend conditions of its heir inputs.
+pub fn finalize_psbt(mut psbt: PartiallySignedTransaction) -> Result<Transaction, Error> {
+    log::debug!("finalize_psbt - psbt: {}", json!(psbt));
+    check_heir_time_locks(&psbt)?;
+    let tx_inputs_len = psbt.unsigned_tx.input.len();
+    let psbt_inputs_len = psbt.inputs.len();
+    if tx_inputs_len != psbt_inputs_len {
+        log::error!(
+            "Malformed PSBT, {} unsigned tx inputs and {} psbt inputs.",
+            tx_inputs_len,
+            psbt_inputs_len
+        );
+        return Err(Error::UnfinalizablePsbt(psbt));
+    }
+
+    let secp = Secp256k1::verification_only();
+    let mut errors = Vec::new();
+    for index in 0..psbt_inputs_len {
+        let input = &psbt.inputs[index];
+        if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+            continue;
+        }
+        let has_signature = input.tap_key_sig.is_some()
+            || !input.tap_script_sigs.is_empty()
+            || !input.partial_sigs.is_empty();
+        if let Err(e) = psbt.finalize_inp_mut(&secp, index) {
+            let reason = if has_signature {
+                e.to_string()
+            } else {
+                "missing signature".to_owned()
+            };
+            log::error!("finalize_psbt - input #{index} cannot be finalized: {reason}");
+            errors.push((index, reason));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(Error::UnfinalizablePsbtInputs(errors));
+    }
+
+    let raw_tx = psbt.extract_tx();
+    log::debug!("finalize_psbt - raw_tx: {}", json!(raw_tx));
+    Ok(raw_tx)
+}
+
+/// Return the heir leaf script `psbt_in` is spent through, if it is spent by an heir
+fn heir_leaf_script(psbt_in: &Input) -> Option<&Script> {
+    if psbt_in.tap_key_sig.is_some() {
+        return None;
+    }
+    if let Some(witness) = &psbt_in.final_script_witness {
+        // A key path spend only has the signature and no tapscript
+        return witness.tapscript().filter(|script| is_time_locked(script));
+    }
+    let mut time_locked_leaves = psbt_in
+        .tap_scripts
+        .values()
+        .filter(|(script, _)| is_time_locked(script));
+    match psbt_in.tap_script_sigs.keys().next() {
+        // Signed, the leaf of the signature
+        Some((_, leaf_hash)) => time_locked_leaves
+            .find(|(script, leaf_version)| {
+                TapLeafHash::from_script(script, *leaf_version) == *leaf_hash
+            })
+            .map(|(script, _)| script.as_script()),
+        // Unsigned, only a PSBT minimized for an heir tells the leaf
+        None => match (time_locked_leaves.next(), time_locked_leaves.next()) {
+            (Some((script, _)), None) if psbt_in.tap_internal_key.is_some() => {
+                Some(script.as_script())
+            }
+            _ => None,
+        },
+    }
+}
+
+/// Return `true` if `script` has an absolute or relative time lock
+fn is_time_locked(script: &Script) -> bool {
+    script.instructions().any(|instruction| {
+        matches!(instruction, Ok(Instruction::Op(op)) if op == OP_CLTV || op == OP_CSV)
+    })
+}
+
+/// Return the absolute lock (a timestamp) and the relative lock (a number of blocks)
+/// committed in an heir leaf `script`
+fn script_time_locks(script: &Script) -> (Option<u32>, Option<u32>) {
+    let (mut absolute_lock, mut relative_lock) = (None, None);
+    let mut previous = None;
+    for instruction in script.instructions().flatten() {
+        if let Instruction::Op(op) = instruction {
+            // The lock value is the number pushed right before the opcode
+            let value = match previous {
+                Some(Instruction::PushBytes(bytes)) => read_scriptint(bytes.as_bytes()).ok(),
+                Some(Instruction::Op(op)) => match op.classify(ClassifyContext::TapScript) {
+                    Class::PushNum(value) => Some(value as i64),
+                    _ => None,
+                },
+                None => None,
+            }
+            .and_then(|value| u32::try_from(value).ok());
+            if op == OP_CLTV {
+                absolute_lock = value;
+            } else if op == OP_CSV {
+                relative_lock = value;
+            }
+        }
+        previous = Some(instruction);
+    }
+    (absolute_lock, relative_lock)
+}
+
+/// Verify that the lock time, the version and the sequences of `psbt` are consistent with
+/// the time locks committed in the heir leaf scripts its inputs are spent through, i.e. that
+/// they were not tampered with. Inputs spent by the owner are not concerned.
+///
+/// # Errors
+/// Returns [Error::InconsistentTimeLocks] listing every [TimeLockIssue] found
+pub fn check_heir_time_locks(psbt: &PartiallySignedTransaction) -> Result<(), Error> {
+    let tx = &psbt.unsigned_tx;
+    let mut issues = Vec::new();
+    let mut has_relative_lock = false;
+    for (input, (tx_in, psbt_in)) in tx.input.iter().zip(psbt.inputs.iter()).enumerate() {
+        let Some(script) = heir_leaf_script(psbt_in) else {
+            continue;
+        };
+        let (absolute_lock, relative_lock) = script_time_locks(script);
+        if let Some(maturity) = absolute_lock {
+            let satisfied = match tx.lock_time {
+                LockTime::Seconds(time) => time.to_consensus_u32() >= maturity,
+                LockTime::Blocks(_) => false,
+            };
+            if !satisfied {
+                issues.push(TimeLockIssue::LockTimeBeforeMaturity {
+                    input,
+                    lock_time: tx.lock_time,
+                    maturity,
+                });
+            }
+        }
+        if let Some(blocks) = relative_lock {
+            has_relative_lock = true;
+            let expected = Sequence::from_consensus(blocks);
+            if tx_in.sequence != expected {
+                issues.push(TimeLockIssue::SequenceMismatch {
+                    input,
+                    expected,
+                    found: tx_in.sequence,
+                });
+            }
+        }
+    }
+    if has_relative_lock && tx.version < 2 {
+        issues.push(TimeLockIssue::VersionTooLow(tx.version));
+    }
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        log::error!("check_heir_time_locks - issues={issues:?}");
+        Err(Error::InconsistentTimeLocks(issues))
+    }
+}
+
+/// Like [finalize_psbt], but also refuse a transaction that would remain non-final for more
+/// than `max_non_final_days` days after `now`, typically an heir transaction whose lock time
+/// was pushed far in the future.
+///
+/// # Errors
+/// Returns [Error::InconsistentTimeLocks] with a [TimeLockIssue::NonFinal] in this case,
+/// see [finalize_psbt] for the other errors
+pub fn finalize_heir_psbt(
+    psbt: PartiallySignedTransaction,
+    now: u64,
+    max_non_final_days: u16,
+) -> Result<Transaction, Error> {
+    if let LockTime::Seconds(time) = psbt.unsigned_tx.lock_time {
+        if time.to_consensus_u32() as u64 > now + max_non_final_days as u64 * 86400 {
+            let issue = TimeLockIssue::NonFinal {
+                lock_time: psbt.unsigned_tx.lock_time,
+                now,
+                max_non_final_days,
+            };
+            log::error!("finalize_heir_psbt - {issue}");
+            return Err(Error::InconsistentTimeLocks(vec![issue]));
+        }
+    }
+    finalize_psbt(psbt)
+}
+
 type BlockHeight = Option<u32>;
 /// Sort a [Vec] of Transaction-like objects that have
 /// parents information using the provided functions that
@@ -297,4 +526,161 @@ mod tests {
         assert!(extract_tx(get_test_unsigned_psbt(TestPsbt::BackupPresent)).is_err());
         assert!(extract_tx(get_test_unsigned_psbt(TestPsbt::WifePresent)).is_err());
     }
+    #[test]
+    fn finalize_psbt() {
+        for tp in [
+            TestPsbt::OwnerDrain,
+            TestPsbt::OwnerRecipients,
+            TestPsbt::BackupFuture,
+            TestPsbt::WifeFuture,
+            TestPsbt::BrotherFuture,
+            TestPsbt::BackupPresent,
+            TestPsbt::WifePresent,
+        ] {
+            // Same result as extract_tx for signed PSBTs
+            assert_eq!(
+                super::finalize_psbt(get_test_signed_psbt(tp)).unwrap(),
+                extract_tx(get_test_signed_psbt(tp)).unwrap()
+            );
+            // Every input of an unsigned PSBT is reported
+            let unsigned_psbt = get_test_unsigned_psbt(tp);
+            let inputs_len = unsigned_psbt.inputs.len();
+            match super::finalize_psbt(unsigned_psbt) {
+                Err(Error::UnfinalizablePsbtInputs(errors)) => {
+                    assert_eq!(errors.len(), inputs_len);
+                    assert!(errors
+                        .iter()
+                        .enumerate()
+                        .all(|(i, (index, reason))| i == *index && reason == "missing signature"));
+                }
+                _ => panic!("unsigned PSBT should not be finalizable"),
+            }
+        }
+    }
+
+    #[test]
+    fn format_timestamp() {
+        assert_eq!(super::format_timestamp(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(
+            super::format_timestamp(951782400),
+            "2000-02-29 00:00:00 UTC"
+        );
+        assert_eq!(
+            super::format_timestamp(1715552000),
+            "2024-05-12 22:13:20 UTC"
+        );
+    }
+
+    #[test]
+    fn script_time_locks() {
+        use crate::bitcoin::{opcodes::all::OP_DROP, script::Builder};
+
+        // Locks pushed as data
+        let script = Builder::new()
+            .push_int(1_700_000_000)
+            .push_opcode(OP_CLTV)
+            .push_opcode(OP_DROP)
+            .push_int(12_960)
+            .push_opcode(OP_CSV)
+            .into_script();
+        assert_eq!(
+            super::script_time_locks(&script),
+            (Some(1_700_000_000), Some(12_960))
+        );
+
+        // Small locks are pushed with OP_1 to OP_16
+        for lock in 1..=16 {
+            let script = Builder::new()
+                .push_int(lock)
+                .push_opcode(OP_CSV)
+                .push_opcode(OP_DROP)
+                .push_int(lock)
+                .push_opcode(OP_CLTV)
+                .into_script();
+            assert_eq!(
+                super::script_time_locks(&script),
+                (Some(lock as u32), Some(lock as u32))
+            );
+        }
+
+        // Negative values are not locks
+        let script = Builder::new()
+            .push_int(-1)
+            .push_opcode(OP_CSV)
+            .into_script();
+        assert_eq!(super::script_time_locks(&script), (None, None));
+    }
+
+    #[test]
+    fn check_heir_time_locks() {
+        for tp in [
+            TestPsbt::OwnerDrain,
+            TestPsbt::OwnerRecipients,
+            TestPsbt::BackupFuture,
+            TestPsbt::WifeFuture,
+            TestPsbt::BrotherFuture,
+            TestPsbt::BackupPresent,
+            TestPsbt::WifePresent,
+        ] {
+            assert!(super::check_heir_time_locks(&get_test_unsigned_psbt(tp)).is_ok());
+            assert!(super::check_heir_time_locks(&get_test_signed_psbt(tp)).is_ok());
+        }
+        // The owner is not concerned by the time locks
+        let mut psbt = get_test_signed_psbt(TestPsbt::OwnerDrain);
+        psbt.unsigned_tx.input[0].sequence = Sequence::MAX;
+        assert!(super::check_heir_time_locks(&psbt).is_ok());
+
+        // Tampered sequence and lock time
+        let psbt = get_test_signed_psbt(TestPsbt::BackupPresent);
+        let expected_sequence = psbt.unsigned_tx.input[0].sequence;
+        let mut tampered = psbt.clone();
+        tampered.unsigned_tx.input[0].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        tampered.unsigned_tx.lock_time = LockTime::from_height(800_000).unwrap();
+        match super::check_heir_time_locks(&tampered) {
+            Err(Error::InconsistentTimeLocks(issues)) => {
+                assert!(issues.contains(&TimeLockIssue::SequenceMismatch {
+                    input: 0,
+                    expected: expected_sequence,
+                    found: Sequence::ENABLE_RBF_NO_LOCKTIME
+                }));
+                assert!(issues
+                    .iter()
+                    .all(|issue| !matches!(issue, TimeLockIssue::NonFinal { .. })));
+                assert_eq!(
+                    issues
+                        .iter()
+                        .filter(|issue| matches!(
+                            issue,
+                            TimeLockIssue::LockTimeBeforeMaturity { .. }
+                        ))
+                        .count(),
+                    psbt.inputs.len()
+                );
+            }
+            _ => panic!("tampered PSBT should be rejected"),
+        }
+        assert!(matches!(
+            super::finalize_psbt(tampered),
+            Err(Error::InconsistentTimeLocks(_))
+        ));
+    }
+
+    #[test]
+    fn finalize_heir_psbt() {
+        let psbt = get_test_signed_psbt(TestPsbt::BackupFuture);
+        let LockTime::Seconds(lock_time) = psbt.unsigned_tx.lock_time else {
+            panic!("heir transactions are time-locked");
+        };
+        let lock_time = lock_time.to_consensus_u32() as u64;
+        assert_eq!(
+            super::finalize_heir_psbt(psbt.clone(), lock_time - 86400, 1).unwrap(),
+            extract_tx(psbt.clone()).unwrap()
+        );
+        match super::finalize_heir_psbt(psbt, lock_time - 2 * 86400, 1) {
+            Err(Error::InconsistentTimeLocks(issues)) => {
+                assert!(matches!(issues[..], [TimeLockIssue::NonFinal { .. }]));
+            }
+            _ => panic!("the transaction is non-final for more than a day"),
+        }
+    }
 }
@@ -7,7 +7,7 @@ use serde_json::Value;
 
 use crate::errors::{Error, Result};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Token(pub(crate) Box<str>);
 impl Token {
@@ -61,7 +61,7 @@ pub trait TokenCache {
     fn clear(&mut self) -> Result<bool>;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tokens {
     pub(crate) id_token: Token,
     pub(crate) access_token: Token,
@@ -101,7 +101,7 @@ impl Tokens {
             if timestamp_now() >= auth_expiration_ts {
                 return Err(Error::AuthenticationProcessExpired);
             }
-            std::thread::sleep(core::time::Duration::from_secs(sleep_interval));
+            super::sleep(core::time::Duration::from_secs(sleep_interval)).await;
 
             log::debug!("Trying to retrieve tokens");
             let req = client.post(auth_url).form(&[
@@ -120,7 +120,9 @@ impl Tokens {
                             access_token: Token(tokens.access_token.into()),
                             refresh_token: tokens
                                 .refresh_token
-                                .ok_or_else(|| Error::Generic("Missing refresh token".to_owned()))?
+                                .ok_or_else(|| {
+                                    Error::InvalidTokenResponse("missing refresh token".to_owned())
+                                })?
                                 .into(),
                             expiration_ts: timestamp_now() + tokens.expires_in as u64,
                             token_endpoint: auth_url.into(),
@@ -128,12 +130,11 @@ impl Tokens {
                         });
                     } else {
                         log::error!("Invalid response from the device token API: {body}");
-                        return Err(Error::Generic(format!(
-                            "Invalid response from the device token API: {body}"
-                        )));
+                        return Err(Error::InvalidTokenResponse(body));
                     }
                 }
-                Err(Error::ApiErrorResponse { code, message }) if code == 400 => {
+                // The OAuth errors of the device flow are 400 responses
+                Err(Error::Validation { message, .. }) => {
                     log::debug!("Got a 400 response from the device token API: {message}");
                     match serde_json::from_str::<DeviceFlowError>(&message)? {
                         DeviceFlowError::AccessDenied => return Err(Error::AuthenticationDenied),
@@ -179,6 +180,21 @@ impl Tokens {
         Ok(())
     }
 
+    /// Revoke the refresh token of the Tokens, and therefore every token derived from it,
+    /// at the `revocation_endpoint` of the authorization server (RFC 7009).
+    ///
+    /// # Errors
+    /// Return an error if the revocation failed
+    pub async fn revoke(self, revocation_endpoint: &str) -> Result<()> {
+        log::debug!("Tokens::revoke - revocation_endpoint={revocation_endpoint}");
+        let req = Client::new().post(revocation_endpoint).form(&[
+            ("client_id", self.client_id.as_ref()),
+            ("token", self.refresh_token.as_ref()),
+        ]);
+        super::client::req_builder_to_body(req).await?;
+        Ok(())
+    }
+
     pub fn need_refresh(&self) -> bool {
         log::debug!("Tokens::need_refresh");
         self.expiration_ts < timestamp_now() + 30
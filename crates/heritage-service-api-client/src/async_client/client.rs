@@ -1,4 +1,9 @@
+use super::auth::TokenCache;
 pub use super::auth::Tokens;
+use super::{
+    pagination::{Paginated, CONTINUATION_TOKEN_PARAM},
+    retry::{self, RetryPolicy, IDEMPOTENCY_KEY_HEADER},
+};
 use crate::{
     errors::{Error, Result},
     types::{AccountXPubWithStatus, HeritageWalletMeta, NewTx},
@@ -11,19 +16,35 @@ use btc_heritage::{
     BlockInclusionObjective, HeritageConfig, HeritageWalletBackup,
 };
 
-use reqwest::{Client, Method};
-use serde::Serialize;
+use reqwest::{Client, Method, RequestBuilder};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::json;
 use std::{
     collections::{BTreeSet, HashMap},
-    sync::{Arc, RwLock},
+    sync::{Arc, Mutex, RwLock},
 };
 
+/// The HTTP header carrying the API key of a self-hosted Heritage service
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
 #[derive(Debug, Clone)]
 pub struct HeritageServiceClient {
     client: Client,
     service_api_url: Arc<str>,
     tokens: Arc<RwLock<Option<Tokens>>>,
+    /// If present, the client authenticates with this API key instead of the OAuth tokens
+    api_key: Option<Arc<str>>,
+    retry_policy: RetryPolicy,
+    /// If present, the tokens are saved in this cache every time they are refreshed
+    token_cache: Option<SharedTokenCache>,
+}
+
+#[derive(Clone)]
+struct SharedTokenCache(Arc<Mutex<dyn TokenCache + Send>>);
+impl core::fmt::Debug for SharedTokenCache {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("SharedTokenCache")
+    }
 }
 
 pub(super) async fn req_builder_to_body(req: reqwest::RequestBuilder) -> Result<String> {
@@ -31,6 +52,12 @@ pub(super) async fn req_builder_to_body(req: reqwest::RequestBuilder) -> Result<
     let res = req.send().await?;
     log::debug!("res={res:?}");
     let status_code = res.status();
+    let retry_after = res
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .map(core::time::Duration::from_secs);
     let body_bytes = res
         .bytes()
         .await
@@ -50,12 +77,11 @@ pub(super) async fn req_builder_to_body(req: reqwest::RequestBuilder) -> Result<
             status_code.as_u16(),
             status_code.canonical_reason().unwrap_or("UNKNOWN")
         );
-        let mut error_body: HashMap<String, String> = serde_json::from_str(&body_str)?;
-        let error_message = error_body.remove("message").unwrap_or(body_str);
-        Err(Error::ApiErrorResponse {
-            code: status_code.as_u16(),
-            message: error_message,
-        })
+        Err(Error::from_response(
+            status_code.as_u16(),
+            retry_after,
+            body_str,
+        ))
     } else {
         Ok(body_str)
     }
@@ -67,9 +93,46 @@ impl HeritageServiceClient {
             client: Client::new(),
             service_api_url: service_api_url.into(),
             tokens: Arc::new(RwLock::new(tokens)),
+            api_key: None,
+            retry_policy: RetryPolicy::default(),
+            token_cache: None,
+        }
+    }
+
+    /// Create a client for a self-hosted Heritage service that authenticates
+    /// with an API key instead of OAuth tokens
+    pub fn new_with_api_key(service_api_url: String, api_key: String) -> Self {
+        Self {
+            client: Client::new(),
+            service_api_url: service_api_url.into(),
+            tokens: Arc::new(RwLock::new(None)),
+            api_key: Some(api_key.into()),
+            retry_policy: RetryPolicy::default(),
+            token_cache: None,
         }
     }
 
+    /// Replace the [RetryPolicy] of the client, [RetryPolicy::default] otherwise
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Save the tokens in `token_cache` every time the client refreshes them, so that
+    /// the next sessions start with up-to-date tokens
+    pub fn with_token_cache(mut self, token_cache: impl TokenCache + Send + 'static) -> Self {
+        self.token_cache = Some(SharedTokenCache(Arc::new(Mutex::new(token_cache))));
+        self
+    }
+
+    pub fn service_api_url(&self) -> &str {
+        &self.service_api_url
+    }
+
     pub fn has_tokens(&self) -> bool {
         self.tokens.read().expect("invalid rw_lock state").is_some()
     }
@@ -83,46 +146,167 @@ impl HeritageServiceClient {
         *mutex_guard = tokens;
     }
 
+    /// Revoke the tokens of the client at the `revocation_endpoint` of the authorization
+    /// server and remove them from the client and from its token cache, if any.
+    ///
+    /// The tokens are removed even if the revocation fails.
+    pub async fn revoke_tokens(&self, revocation_endpoint: &str) -> Result<()> {
+        let tokens = self.tokens.write().expect("invalid rw_lock state").take();
+        if let Some(token_cache) = &self.token_cache {
+            token_cache.0.lock().expect("invalid mutex state").clear()?;
+        }
+        match tokens {
+            Some(tokens) => tokens.revoke(revocation_endpoint).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Add the authentication of the client to the request.
+    ///
+    /// The tokens are refreshed if they are about to expire, or if `force_refresh` is `true`.
+    async fn authenticate(
+        &self,
+        req: RequestBuilder,
+        force_refresh: bool,
+    ) -> Result<RequestBuilder> {
+        if let Some(api_key) = &self.api_key {
+            return Ok(req.header(API_KEY_HEADER, api_key.as_ref()));
+        }
+        let read_guard = self.tokens.read().expect("invalid rw_lock state");
+        let tokens = read_guard.as_ref().ok_or(Error::Unauthenticated)?;
+        if !force_refresh && !tokens.need_refresh() {
+            Ok(req.bearer_auth(&tokens.id_token.0))
+        } else {
+            // Force drop the readguard so we can write-lock and get a &mut Tokens
+            drop(read_guard);
+            let mut write_guard = self.tokens.write().expect("invalid rw_lock state");
+            let tokens = write_guard.as_mut().ok_or(Error::Unauthenticated)?;
+            // To prevent double-refresh race-conditions, we re-check the tokens expiration status before calling refresh
+            if force_refresh || tokens.need_refresh() {
+                tokens.refresh().await?;
+                if let Some(token_cache) = &self.token_cache {
+                    // The refreshed tokens are usable anyway
+                    if let Err(e) = token_cache
+                        .0
+                        .lock()
+                        .expect("invalid mutex state")
+                        .save_tokens(tokens)
+                    {
+                        log::warn!("Could not save the refreshed tokens: {e}");
+                    }
+                }
+            }
+            Ok(req.bearer_auth(&tokens.id_token.0))
+        }
+    }
+
     async fn api_call<T: Serialize>(
         &self,
         method: Method,
         path: &str,
         body: Option<T>,
+    ) -> Result<serde_json::Value> {
+        self.api_call_with_retry(method, path, &[], body, false)
+            .await
+    }
+
+    /// Same as [HeritageServiceClient::api_call] but attach an idempotency key to the request,
+    /// so that the service does not apply the same mutating call twice
+    async fn api_call_idempotent<T: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<T>,
+    ) -> Result<serde_json::Value> {
+        self.api_call_with_retry(method, path, &[], body, true)
+            .await
+    }
+
+    async fn api_call_with_retry<T: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<T>,
+        idempotent: bool,
     ) -> Result<serde_json::Value> {
         let api_endpoint = format!("{}/{path}", self.service_api_url);
-        log::debug!("Initiating {method} {api_endpoint}");
-        let req = self.client.request(method, &api_endpoint);
-
-        let req = {
-            let read_guard = self.tokens.read().expect("invalid rw_lock state");
-            let tokens = read_guard.as_ref().ok_or(Error::Unauthenticated)?;
-            if !tokens.need_refresh() {
-                req.bearer_auth(&tokens.id_token.0)
+        let body_str = body.map(|body| serde_json::to_string(&body)).transpose()?;
+        log::debug!("body_str={body_str:?}");
+        let idempotency_key =
+            idempotent.then(|| retry::idempotency_key(&method, path, body_str.as_deref()));
+        // Without an idempotency key, only the calls that cannot have a side effect are retried
+        let retryable = idempotency_key.is_some() || method == Method::GET;
+
+        let mut attempt = 0;
+        // Set after an unauthorized response, the tokens are force-refreshed only once
+        let mut force_refresh = false;
+        let mut force_refreshed = false;
+        loop {
+            attempt += 1;
+            log::debug!("Initiating {method} {api_endpoint} (attempt {attempt})");
+            let req = self
+                .authenticate(
+                    self.client.request(method.clone(), &api_endpoint),
+                    force_refresh && !force_refreshed,
+                )
+                .await?;
+            force_refreshed = force_refresh;
+            let req = if query.is_empty() {
+                req
             } else {
-                // Force drop the readguard so we can write-lock and get a &mut Tokens
-                drop(read_guard);
-                let mut write_guard = self.tokens.write().expect("invalid rw_lock state");
-                let tokens = write_guard.as_mut().ok_or(Error::Unauthenticated)?;
-                // To prevent double-refresh race-conditions, we re-check the tokens expiration status before calling refresh
-                if tokens.need_refresh() {
-                    tokens.refresh().await?;
+                req.query(query)
+            };
+            let req = match &idempotency_key {
+                Some(idempotency_key) => req.header(IDEMPOTENCY_KEY_HEADER, idempotency_key),
+                None => req,
+            };
+            let req = match &body_str {
+                Some(body_str) => req.body(body_str.clone()),
+                None => req,
+            };
+            let error = match req_builder_to_body(req).await {
+                Ok(body) => {
+                    return match body.as_str() {
+                        "" => Ok(serde_json::Value::Null),
+                        _ => Ok(serde_json::from_str(&body)?),
+                    }
                 }
-                req.bearer_auth(&tokens.id_token.0)
+                Err(e) => e,
+            };
+            // The tokens may have been invalidated before their expiration,
+            // refresh them once instead of failing
+            if matches!(error, Error::Unauthorized { .. })
+                && self.api_key.is_none()
+                && !force_refresh
+            {
+                log::info!("{method} {api_endpoint} unauthorized, refreshing the tokens");
+                force_refresh = true;
+                attempt -= 1;
+                continue;
             }
-        };
-
-        let req = match body {
-            Some(body) => {
-                let body_str = serde_json::to_string(&body)?;
-                log::debug!("body_str={body_str}");
-                req.body(body_str)
+            // A connection error means the request never reached the service
+            let can_retry = error.is_transient()
+                && (retryable
+                    || matches!(&error, Error::SendRequestError { source } if retry::is_connect(source)));
+            match self.retry_policy.backoff(attempt) {
+                Some(backoff) if can_retry => {
+                    // Respect the delay requested by the service, if any
+                    let backoff = match &error {
+                        Error::RateLimited {
+                            retry_after: Some(retry_after),
+                            ..
+                        } => backoff.max(*retry_after),
+                        _ => backoff,
+                    };
+                    log::warn!(
+                        "{method} {api_endpoint} failed ({error}), retrying in {}ms",
+                        backoff.as_millis()
+                    );
+                    super::sleep(backoff).await;
+                }
+                _ => return Err(error),
             }
-            None => req,
-        };
-        let body = req_builder_to_body(req).await?;
-        match body.as_str() {
-            "" => Ok(serde_json::Value::Null),
-            _ => Ok(serde_json::from_str(&body)?),
         }
     }
 
@@ -130,6 +314,21 @@ impl HeritageServiceClient {
         self.api_call::<()>(Method::GET, path, None).await
     }
 
+    /// Retrieve one page of a paginated list endpoint
+    pub(super) async fn api_call_get_page(
+        &self,
+        path: &str,
+        continuation_token: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        let query = continuation_token.map(|token| (CONTINUATION_TOKEN_PARAM, token));
+        self.api_call_with_retry::<()>(Method::GET, path, query.as_slice(), None, false)
+            .await
+    }
+
+    fn paginate<T: DeserializeOwned>(&self, path: String) -> Paginated<T> {
+        Paginated::new(self.clone(), path)
+    }
+
     ////////////////////////
     //      Wallets       //
     ////////////////////////
@@ -161,7 +360,8 @@ impl HeritageServiceClient {
             map.insert("block_inclusion_objective", serde_json::to_value(val)?);
         }
         Ok(serde_json::from_value(
-            self.api_call(Method::PATCH, &path, Some(map)).await?,
+            self.api_call_idempotent(Method::PATCH, &path, Some(map))
+                .await?,
         )?)
     }
 
@@ -185,7 +385,7 @@ impl HeritageServiceClient {
     ) -> Result<()> {
         let path = format!("wallets/{wallet_id}/account-xpubs");
         serde_json::from_value(
-            self.api_call(Method::POST, &path, Some(account_xpubs))
+            self.api_call_idempotent(Method::POST, &path, Some(account_xpubs))
                 .await?,
         )?;
         Ok(())
@@ -206,7 +406,8 @@ impl HeritageServiceClient {
     ) -> Result<HeritageConfig> {
         let path = format!("wallets/{wallet_id}/heritage-configs");
         Ok(serde_json::from_value(
-            self.api_call(Method::POST, &path, Some(hc)).await?,
+            self.api_call_idempotent(Method::POST, &path, Some(hc))
+                .await?,
         )?)
     }
 
@@ -214,13 +415,21 @@ impl HeritageServiceClient {
         &self,
         wallet_id: &str,
     ) -> Result<Vec<TransactionSummary>> {
-        let path = format!("wallets/{wallet_id}/tx-summaries");
-        Ok(serde_json::from_value(self.api_call_get(&path).await?)?)
+        self.iter_wallet_transactions(wallet_id).collect_all().await
+    }
+
+    /// Lazily page through the transactions of the wallet
+    pub fn iter_wallet_transactions(&self, wallet_id: &str) -> Paginated<TransactionSummary> {
+        self.paginate(format!("wallets/{wallet_id}/tx-summaries"))
     }
 
     pub async fn list_wallet_utxos(&self, wallet_id: &str) -> Result<Vec<HeritageUtxo>> {
-        let path = format!("wallets/{wallet_id}/utxos");
-        Ok(serde_json::from_value(self.api_call_get(&path).await?)?)
+        self.iter_wallet_utxos(wallet_id).collect_all().await
+    }
+
+    /// Lazily page through the UTXOs of the wallet
+    pub fn iter_wallet_utxos(&self, wallet_id: &str) -> Paginated<HeritageUtxo> {
+        self.paginate(format!("wallets/{wallet_id}/utxos"))
     }
 
     pub async fn list_wallet_addresses(&self, wallet_id: &str) -> Result<Vec<WalletAddress>> {
@@ -268,7 +477,7 @@ impl HeritageServiceClient {
 
     pub async fn post_broadcast_tx(&self, psbt: Psbt) -> Result<Txid> {
         let mut ret: HashMap<String, Txid> = serde_json::from_value(
-            self.api_call(
+            self.api_call_idempotent(
                 Method::POST,
                 "broadcast-tx",
                 Some(json!({"psbt": psbt.to_string()})),
@@ -282,7 +491,12 @@ impl HeritageServiceClient {
     //       Heirs        //
     ////////////////////////
     pub async fn list_heirs(&self) -> Result<Vec<Heir>> {
-        Ok(serde_json::from_value(self.api_call_get("heirs").await?)?)
+        self.iter_heirs().collect_all().await
+    }
+
+    /// Lazily page through the heirs
+    pub fn iter_heirs(&self) -> Paginated<Heir> {
+        self.paginate("heirs".to_owned())
     }
 
     pub async fn post_heirs(&self, heir_create: HeirCreate) -> Result<Heir> {
@@ -300,7 +514,7 @@ impl HeritageServiceClient {
     pub async fn patch_heir(&self, heir_id: &str, heir_update: HeirUpdate) -> Result<Heir> {
         let path = format!("heirs/{heir_id}");
         Ok(serde_json::from_value(
-            self.api_call(Method::PATCH, &path, Some(heir_update))
+            self.api_call_idempotent(Method::PATCH, &path, Some(heir_update))
                 .await?,
         )?)
     }
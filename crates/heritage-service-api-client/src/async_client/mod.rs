@@ -1,5 +1,18 @@
 pub(crate) mod auth;
 pub(crate) mod client;
+mod pagination;
+mod retry;
 
 pub use auth::{TokenCache, Tokens};
-pub use client::HeritageServiceClient;
+pub use client::{HeritageServiceClient, API_KEY_HEADER};
+pub use pagination::Paginated;
+pub use retry::{RetryPolicy, IDEMPOTENCY_KEY_HEADER};
+
+/// Wait for `duration` without blocking the executor. On wasm32 there is no
+/// tokio runtime, the timers of the JS environment are used instead.
+pub(crate) async fn sleep(duration: core::time::Duration) {
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::sleep(duration).await;
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+
+use serde::de::DeserializeOwned;
+
+use super::HeritageServiceClient;
+use crate::{errors::Result, types::Page};
+
+/// The query parameter carrying the continuation token of a paginated list endpoint
+pub(super) const CONTINUATION_TOKEN_PARAM: &str = "continuation_token";
+
+/// A cursor over the items of a paginated list endpoint of the Heritage service.
+///
+/// Pages are retrieved lazily, following the continuation tokens of the service,
+/// when the items of the previous page are exhausted.
+#[derive(Debug)]
+pub struct Paginated<T> {
+    client: HeritageServiceClient,
+    path: String,
+    buffer: VecDeque<T>,
+    continuation_token: Option<String>,
+    exhausted: bool,
+}
+
+impl<T: DeserializeOwned> Paginated<T> {
+    pub(super) fn new(client: HeritageServiceClient, path: String) -> Self {
+        Self {
+            client,
+            path,
+            buffer: VecDeque::new(),
+            continuation_token: None,
+            exhausted: false,
+        }
+    }
+
+    /// Retrieve the next page of items, or [None] if all the pages were retrieved.
+    ///
+    /// Items still buffered by [Paginated::next] are returned first.
+    pub async fn next_page(&mut self) -> Option<Result<Vec<T>>> {
+        if !self.buffer.is_empty() {
+            return Some(Ok(self.buffer.drain(..).collect()));
+        }
+        if self.exhausted {
+            return None;
+        }
+        let page: Result<Page<T>> = self
+            .client
+            .api_call_get_page(&self.path, self.continuation_token.as_deref())
+            .await
+            .and_then(|value| Ok(serde_json::from_value(value)?));
+        match page {
+            Ok(Page {
+                items,
+                continuation_token,
+            }) => {
+                self.exhausted = continuation_token.is_none();
+                self.continuation_token = continuation_token;
+                Some(Ok(items))
+            }
+            Err(e) => {
+                // Do not loop on a failing page
+                self.exhausted = true;
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// Retrieve the next item, or [None] if all the items were retrieved
+    pub async fn next(&mut self) -> Option<Result<T>> {
+        while self.buffer.is_empty() {
+            match self.next_page().await? {
+                Ok(items) => self.buffer.extend(items),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.buffer.pop_front().map(Ok)
+    }
+
+    /// Retrieve all the remaining items
+    pub async fn collect_all(mut self) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        while let Some(page) = self.next_page().await {
+            items.extend(page?);
+        }
+        Ok(items)
+    }
+}
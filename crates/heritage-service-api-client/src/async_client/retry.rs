@@ -0,0 +1,158 @@
+use core::time::Duration;
+use std::hash::{BuildHasher, Hasher};
+
+use btc_heritage::bitcoin::hashes::{sha256, Hash, HashEngine};
+use reqwest::Method;
+
+use crate::errors::Error;
+
+/// The HTTP header carrying the idempotency key of a mutating call
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// The policy used by the [super::HeritageServiceClient] to retry the API calls
+/// failing with a transient error, see [Error::is_transient]
+///
+/// The delay before the retry `n` (starting at 1) is `initial_backoff * 2^(n-1)`, capped
+/// to `max_backoff`. If `jitter` is `true`, the actual delay is randomly chosen between
+/// half and the totality of this value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts of a call, including the first one.
+    /// A value of 0 or 1 disables the retries.
+    pub max_attempts: u32,
+    /// The delay before the first retry
+    pub initial_backoff: Duration,
+    /// The maximum delay between two attempts
+    pub max_backoff: Duration,
+    /// Randomize the delays so that concurrent clients do not retry in lockstep
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(8),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A [RetryPolicy] that never retries
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Return the delay to wait before the attempt following the failed `attempt` (starting at 1),
+    /// or [None] if there should be no other attempt
+    pub(super) fn backoff(&self, attempt: u32) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        if self.jitter {
+            // No need for a cryptographic RNG here
+            let random = std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish();
+            let half = backoff / 2;
+            Some(half + half.mul_f64((random % 1_000) as f64 / 1_000.0))
+        } else {
+            Some(backoff)
+        }
+    }
+}
+
+/// Compute the idempotency key of a mutating call, derived from its content so
+/// that re-running the same call, even from another process, yields the same key
+/// and the service does not apply it twice.
+pub(super) fn idempotency_key(method: &Method, path: &str, body: Option<&str>) -> String {
+    let mut engine = sha256::Hash::engine();
+    for part in [method.as_str(), path, body.unwrap_or_default()] {
+        engine.input(&(part.len() as u64).to_be_bytes());
+        engine.input(part.as_bytes());
+    }
+    sha256::Hash::from_engine(engine).to_string()
+}
+
+/// Return `true` if the request failed to connect to the service
+#[cfg(not(target_arch = "wasm32"))]
+pub(super) fn is_connect(error: &reqwest::Error) -> bool {
+    error.is_connect()
+}
+/// This information is not available on wasm32
+#[cfg(target_arch = "wasm32")]
+pub(super) fn is_connect(_error: &reqwest::Error) -> bool {
+    false
+}
+
+impl Error {
+    /// Return `true` if the error is likely to be transient, i.e. retrying
+    /// the same call later may succeed
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::SendRequestError { source } => {
+                source.is_timeout() || is_connect(source) || source.is_request()
+            }
+            Error::RateLimited { .. } => true,
+            Error::ApiErrorResponse { code, .. } => matches!(code, 408 | 502 | 503 | 504),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            jitter: false,
+        };
+        assert_eq!(policy.backoff(1), Some(Duration::from_millis(100)));
+        assert_eq!(policy.backoff(2), Some(Duration::from_millis(200)));
+        assert_eq!(policy.backoff(3), Some(Duration::from_millis(300)));
+        assert_eq!(policy.backoff(4), Some(Duration::from_millis(300)));
+        assert_eq!(policy.backoff(5), None);
+        assert_eq!(RetryPolicy::no_retry().backoff(1), None);
+
+        let policy = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for _ in 0..100 {
+            let backoff = policy.backoff(2).unwrap();
+            assert!(backoff >= Duration::from_millis(100));
+            assert!(backoff <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn idempotency_key_is_deterministic() {
+        let key = idempotency_key(&Method::POST, "broadcast-tx", Some("{\"psbt\":\"a\"}"));
+        assert_eq!(
+            key,
+            idempotency_key(&Method::POST, "broadcast-tx", Some("{\"psbt\":\"a\"}"))
+        );
+        assert_ne!(
+            key,
+            idempotency_key(&Method::POST, "broadcast-tx", Some("{\"psbt\":\"b\"}"))
+        );
+        assert_ne!(
+            key,
+            idempotency_key(&Method::PATCH, "broadcast-tx", Some("{\"psbt\":\"a\"}"))
+        );
+    }
+}
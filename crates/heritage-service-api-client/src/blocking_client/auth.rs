@@ -2,7 +2,7 @@ use crate::{async_client::auth::DeviceAuthorizationResponse, auth::Token, errors
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tokens {
     #[serde(flatten)]
     pub(super) inner: crate::async_client::Tokens,
@@ -41,6 +41,15 @@ impl Tokens {
         super::blocker().block_on(self.inner.refresh())
     }
 
+    /// Revoke the refresh token of the Tokens, and therefore every token derived from it,
+    /// at the `revocation_endpoint` of the authorization server (RFC 7009).
+    ///
+    /// # Errors
+    /// Return an error if the revocation failed
+    pub fn revoke(self, revocation_endpoint: &str) -> Result<()> {
+        super::blocker().block_on(self.inner.revoke(revocation_endpoint))
+    }
+
     pub fn need_refresh(&self) -> bool {
         self.inner.need_refresh()
     }
@@ -60,3 +69,19 @@ impl Tokens {
         db.load_tokens()
     }
 }
+
+/// Expose a blocking [TokenCache] to the async client
+pub(super) struct BlockingTokenCache<T>(pub(super) T);
+impl<T: TokenCache> crate::async_client::TokenCache for BlockingTokenCache<T> {
+    fn save_tokens(&mut self, tokens: &crate::async_client::Tokens) -> Result<()> {
+        self.0.save_tokens(&Tokens {
+            inner: tokens.clone(),
+        })
+    }
+    fn load_tokens(&self) -> Result<Option<crate::async_client::Tokens>> {
+        Ok(self.0.load_tokens()?.map(|tokens| tokens.inner))
+    }
+    fn clear(&mut self) -> Result<bool> {
+        self.0.clear()
+    }
+}
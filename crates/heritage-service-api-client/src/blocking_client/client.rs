@@ -3,6 +3,7 @@ use super::Blocker;
 use crate::{errors::Result, types::*};
 
 use btc_heritage::{bitcoin::psbt::Psbt, heritage_wallet::WalletAddress};
+use serde::de::DeserializeOwned;
 
 #[derive(Debug, Clone)]
 pub struct HeritageServiceClient {
@@ -10,6 +11,29 @@ pub struct HeritageServiceClient {
     blocker: &'static Blocker,
 }
 
+/// A blocking [Iterator] over the items of a paginated list endpoint of the Heritage service,
+/// see [crate::async_client::Paginated]
+#[derive(Debug)]
+pub struct Paginated<T> {
+    inner: crate::async_client::Paginated<T>,
+    blocker: &'static Blocker,
+}
+
+impl<T: DeserializeOwned> Paginated<T> {
+    /// Retrieve the next page of items, or [None] if all the pages were retrieved
+    pub fn next_page(&mut self) -> Option<Result<Vec<T>>> {
+        self.blocker.block_on(self.inner.next_page())
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for Paginated<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.blocker.block_on(self.inner.next())
+    }
+}
+
 macro_rules! impl_blocking {
     ($fn_name:ident(& $self:ident $(,$a:ident : $t:ty)*) -> $ret:ty) => {
         pub fn $fn_name(& $self $(,$a : $t)*) -> $ret {
@@ -18,6 +42,17 @@ macro_rules! impl_blocking {
     };
 }
 
+macro_rules! impl_paginated {
+    ($fn_name:ident(& $self:ident $(,$a:ident : $t:ty)*) -> $item:ty) => {
+        pub fn $fn_name(& $self $(,$a : $t)*) -> Paginated<$item> {
+            Paginated {
+                inner: $self.inner.$fn_name($($a),*),
+                blocker: $self.blocker,
+            }
+        }
+    };
+}
+
 impl HeritageServiceClient {
     pub fn new(service_api_url: String, tokens: Option<super::Tokens>) -> Self {
         Self {
@@ -29,6 +64,54 @@ impl HeritageServiceClient {
         }
     }
 
+    /// Create a client for a self-hosted Heritage service that authenticates
+    /// with an API key instead of OAuth tokens
+    pub fn new_with_api_key(service_api_url: String, api_key: String) -> Self {
+        Self {
+            inner: crate::async_client::HeritageServiceClient::new_with_api_key(
+                service_api_url,
+                api_key,
+            ),
+            blocker: super::blocker(),
+        }
+    }
+
+    /// Replace the [super::RetryPolicy] of the client, [super::RetryPolicy::default] otherwise
+    pub fn with_retry_policy(self, retry_policy: super::RetryPolicy) -> Self {
+        Self {
+            inner: self.inner.with_retry_policy(retry_policy),
+            blocker: self.blocker,
+        }
+    }
+
+    pub fn retry_policy(&self) -> &super::RetryPolicy {
+        self.inner.retry_policy()
+    }
+
+    /// Save the tokens in `token_cache` every time the client refreshes them, so that
+    /// the next sessions start with up-to-date tokens
+    pub fn with_token_cache(self, token_cache: impl super::TokenCache + Send + 'static) -> Self {
+        Self {
+            inner: self
+                .inner
+                .with_token_cache(super::auth::BlockingTokenCache(token_cache)),
+            blocker: self.blocker,
+        }
+    }
+
+    /// Revoke the tokens of the client at the `revocation_endpoint` of the authorization
+    /// server and remove them from the client and from its token cache, if any.
+    ///
+    /// The tokens are removed even if the revocation fails.
+    pub fn revoke_tokens(&self, revocation_endpoint: &str) -> Result<()> {
+        self.blocker
+            .block_on(self.inner.revoke_tokens(revocation_endpoint))
+    }
+
+    pub fn service_api_url(&self) -> &str {
+        self.inner.service_api_url()
+    }
+
     pub fn has_tokens(&self) -> bool {
         self.inner.has_tokens()
     }
@@ -49,7 +132,9 @@ impl HeritageServiceClient {
     impl_blocking!(list_wallet_heritage_configs(&self, wallet_id: &str) -> Result<Vec<HeritageConfig>>);
     impl_blocking!(post_wallet_heritage_configs(&self, wallet_id: &str, hc: HeritageConfig) -> Result<HeritageConfig>);
     impl_blocking!(list_wallet_transactions(&self, wallet_id: &str) -> Result<Vec<TransactionSummary>>);
+    impl_paginated!(iter_wallet_transactions(&self, wallet_id: &str) -> TransactionSummary);
     impl_blocking!(list_wallet_utxos(&self, wallet_id: &str) -> Result<Vec<HeritageUtxo>>);
+    impl_paginated!(iter_wallet_utxos(&self, wallet_id: &str) -> HeritageUtxo);
     impl_blocking!(list_wallet_addresses(&self, wallet_id: &str) -> Result<Vec<WalletAddress>>);
     impl_blocking!(post_wallet_create_address(&self, wallet_id: &str) -> Result<String>);
     impl_blocking!(post_wallet_synchronize(&self, wallet_id: &str) -> Result<Synchronization>);
@@ -62,6 +147,7 @@ impl HeritageServiceClient {
     //       Heirs        //
     ////////////////////////
     impl_blocking!(list_heirs(&self) -> Result<Vec<Heir>>);
+    impl_paginated!(iter_heirs(&self) -> Heir);
     impl_blocking!(post_heirs(&self, heir_create: HeirCreate) -> Result<Heir>);
     impl_blocking!(get_heir(&self, heir_id: &str) -> Result<Heir>);
     impl_blocking!(patch_heir(&self, heir_id: &str, heir_update: HeirUpdate) -> Result<Heir>);
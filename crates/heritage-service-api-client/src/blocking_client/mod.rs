@@ -1,9 +1,10 @@
 mod auth;
 mod client;
 
+pub use crate::async_client::{RetryPolicy, API_KEY_HEADER, IDEMPOTENCY_KEY_HEADER};
 pub use crate::errors::Error;
 pub use auth::{TokenCache, Tokens};
-pub use client::HeritageServiceClient;
+pub use client::{HeritageServiceClient, Paginated};
 
 use std::sync::OnceLock;
 fn blocker() -> &'static Blocker {
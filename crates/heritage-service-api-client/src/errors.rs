@@ -1,9 +1,17 @@
-use core::fmt::Debug;
+use core::{fmt::Debug, time::Duration};
 
+use serde::Deserialize;
 use thiserror::Error;
 
 pub type Result<T> = core::result::Result<T, Error>;
 
+/// A field of a request rejected by the Heritage service API, with the reason
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("The authentication process expired")]
@@ -12,11 +20,33 @@ pub enum Error {
     AuthenticationDenied,
     #[error("The client is not authenticated to the Heritage service API.")]
     Unauthenticated,
+    #[error("The token endpoint returned an invalid response: {0}")]
+    InvalidTokenResponse(String),
+    #[error("The Heritage service API rejected the credentials of the client: {message}")]
+    Unauthorized { message: String },
+    #[error("The client is not allowed to access this resource: {message}")]
+    Forbidden { message: String },
+    #[error("The resource does not exist: {message}")]
+    NotFound { message: String },
+    #[error("The Heritage service API rejected the request: {message}")]
+    Validation {
+        message: String,
+        /// The fields of the request that were rejected, if the API detailed them
+        fields: Vec<FieldError>,
+    },
+    #[error("Too many requests to the Heritage service API: {message}")]
+    RateLimited {
+        message: String,
+        /// How long to wait before retrying, if the API specified it
+        retry_after: Option<Duration>,
+    },
     #[error("The client received an unexpected response that could not be parsed: {source}")]
     MalformedJsonResponse {
         #[from]
         source: serde_json::Error,
     },
+    /// Transport error: the request could not reach the service, or its response
+    /// could not be received
     #[error("Could not send the request: {source}")]
     SendRequestError {
         #[from]
@@ -28,8 +58,90 @@ pub enum Error {
     TokenCacheReadError(String),
     #[error("Could not write the tokens in the cache: {0}")]
     TokenCacheWriteError(String),
+    /// Any other error response of the Heritage service API
     #[error("Heritage API responded with error {code}: {message}")]
     ApiErrorResponse { code: u16, message: String },
-    #[error("Generic error: {0}")]
-    Generic(String),
+}
+
+/// The body of an error response of the Heritage service API
+#[derive(Debug, Default, Deserialize)]
+struct ApiErrorBody {
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default, alias = "errors")]
+    fields: Vec<FieldError>,
+}
+
+impl Error {
+    /// Build the [Error] corresponding to an error response of the Heritage service API
+    pub(crate) fn from_response(code: u16, retry_after: Option<Duration>, body: String) -> Self {
+        // Errors produced upstream of the API (e.g. by a proxy) may not be JSON
+        let ApiErrorBody { message, fields } = serde_json::from_str(&body).unwrap_or_default();
+        let message = message.unwrap_or(body);
+        match code {
+            400 | 422 => Error::Validation { message, fields },
+            401 => Error::Unauthorized { message },
+            403 => Error::Forbidden { message },
+            404 => Error::NotFound { message },
+            429 => Error::RateLimited {
+                message,
+                retry_after,
+            },
+            code => Error::ApiErrorResponse { code, message },
+        }
+    }
+
+    /// Return `true` if the error is related to the authentication of the client,
+    /// i.e. the user should authenticate again
+    pub fn is_auth_error(&self) -> bool {
+        matches!(
+            self,
+            Error::AuthenticationProcessExpired
+                | Error::AuthenticationDenied
+                | Error::Unauthenticated
+                | Error::InvalidTokenResponse(_)
+                | Error::Unauthorized { .. }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_response() {
+        let error = Error::from_response(
+            422,
+            None,
+            r#"{"message":"Invalid heir","errors":[{"field":"name","message":"Too long"}]}"#
+                .to_owned(),
+        );
+        let Error::Validation { message, fields } = error else {
+            panic!("expected a validation error")
+        };
+        assert_eq!(message, "Invalid heir");
+        assert_eq!(
+            fields,
+            vec![FieldError {
+                field: "name".to_owned(),
+                message: "Too long".to_owned()
+            }]
+        );
+
+        assert!(matches!(
+            Error::from_response(404, None, r#"{"message":"No such wallet"}"#.to_owned()),
+            Error::NotFound { message } if message == "No such wallet"
+        ));
+        assert!(matches!(
+            Error::from_response(429, Some(Duration::from_secs(3)), "".to_owned()),
+            Error::RateLimited { retry_after: Some(retry_after), .. } if retry_after == Duration::from_secs(3)
+        ));
+        assert!(Error::from_response(401, None, "{}".to_owned()).is_auth_error());
+        // Not a JSON body
+        assert!(matches!(
+            Error::from_response(502, None, "Bad Gateway".to_owned()),
+            Error::ApiErrorResponse { code: 502, message } if message == "Bad Gateway"
+        ));
+    }
 }
@@ -0,0 +1,256 @@
+//! Types of the event notifications sent by the Heritage service to the webhooks
+//! registered by the users, and verification of their signature.
+//!
+//! Every notification is a JSON [Event] sent in the body of a `POST` request, with a
+//! [SIGNATURE_HEADER] of the form `t=<timestamp>,v1=<signature>` where `<signature>` is the
+//! hex-encoded HMAC-SHA256 of `<timestamp>.<body>` keyed with the secret of the webhook.
+
+use btc_heritage::{
+    bitcoin::{
+        bip32::Fingerprint,
+        hashes::{hmac, sha256, Hash, HashEngine},
+        hex::FromHex,
+        Txid,
+    },
+    Amount,
+};
+use serde::{Deserialize, Serialize};
+
+/// The HTTP header carrying the signature of an event notification
+pub const SIGNATURE_HEADER: &str = "X-Heritage-Signature";
+
+/// The default maximum age, in seconds, of a notification accepted by [WebhookVerifier]
+pub const DEFAULT_TOLERANCE: u64 = 300;
+
+/// An event notification of the Heritage service
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Event {
+    /// The unique identifier of the event, the same event may be delivered more than once
+    pub event_id: String,
+    /// The timestamp of the event
+    pub created_ts: u64,
+    #[serde(flatten)]
+    pub payload: EventPayload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", content = "data", rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EventPayload {
+    /// A new transaction is paying to the wallet
+    NewIncomingTx {
+        wallet_id: String,
+        txid: Txid,
+        /// The amount received by the wallet
+        #[serde(with = "btc_heritage::bitcoin::amount::serde::as_sat")]
+        amount: Amount,
+        /// The height of the block including the transaction, if it is confirmed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        confirmation_height: Option<u32>,
+    },
+    /// Some funds of the wallet will soon be spendable by an heir
+    HeirMaturityApproaching {
+        wallet_id: String,
+        /// The fingerprint of the heir
+        heir_fingerprint: Fingerprint,
+        /// The timestamp after which the heir will be able to spend
+        maturity_ts: u64,
+        /// The amount the heir will be able to spend
+        #[serde(with = "btc_heritage::bitcoin::amount::serde::as_sat")]
+        amount: Amount,
+    },
+    /// The current HeritageConfig of the wallet will soon expire, the owner should
+    /// renew it or move the funds to reset the timelocks
+    ConfigExpiring {
+        wallet_id: String,
+        /// The timestamp at which the first heir will be able to spend
+        expiration_ts: u64,
+    },
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum EventVerificationError {
+    #[error("The signature header is malformed")]
+    MalformedSignatureHeader,
+    #[error("The signature does not match the payload")]
+    InvalidSignature,
+    #[error("The notification is too old, or from the future (timestamp: {0})")]
+    OutdatedTimestamp(u64),
+    #[cfg(feature = "async_client")]
+    #[error("The payload is not a valid event: {0}")]
+    MalformedPayload(String),
+}
+
+/// Verify the signature of the event notifications sent to a webhook
+#[derive(Clone)]
+pub struct WebhookVerifier {
+    secret: Vec<u8>,
+    tolerance: u64,
+}
+
+impl core::fmt::Debug for WebhookVerifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("WebhookVerifier")
+            .field("secret", &"<redacted>")
+            .field("tolerance", &self.tolerance)
+            .finish()
+    }
+}
+
+impl WebhookVerifier {
+    /// Create a verifier using the `secret` of the webhook and accepting notifications
+    /// at most [DEFAULT_TOLERANCE] seconds old
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+            tolerance: DEFAULT_TOLERANCE,
+        }
+    }
+
+    /// Change the maximum age, in seconds, of the accepted notifications
+    pub fn with_tolerance(mut self, tolerance: u64) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Compute the value of the [SIGNATURE_HEADER] for `payload` at `timestamp`
+    pub fn sign(&self, payload: &[u8], timestamp: u64) -> String {
+        format!("t={timestamp},v1={}", self.hmac(payload, timestamp))
+    }
+
+    /// Verify that `signature_header` is a valid signature of `payload` produced less than
+    /// `tolerance` seconds away from `now`
+    pub fn verify(
+        &self,
+        payload: &[u8],
+        signature_header: &str,
+        now: u64,
+    ) -> Result<(), EventVerificationError> {
+        let (timestamp, signatures) = parse_signature_header(signature_header)?;
+        if timestamp.abs_diff(now) > self.tolerance {
+            return Err(EventVerificationError::OutdatedTimestamp(timestamp));
+        }
+        let expected = self.hmac(payload, timestamp).to_byte_array();
+        // The header may carry several signatures, e.g. during a secret rotation
+        if signatures
+            .iter()
+            .any(|signature| constant_time_eq(signature, &expected))
+        {
+            Ok(())
+        } else {
+            Err(EventVerificationError::InvalidSignature)
+        }
+    }
+
+    /// Verify the signature of `payload` then parse it as an [Event]
+    #[cfg(feature = "async_client")]
+    pub fn verify_event(
+        &self,
+        payload: &[u8],
+        signature_header: &str,
+        now: u64,
+    ) -> Result<Event, EventVerificationError> {
+        self.verify(payload, signature_header, now)?;
+        serde_json::from_slice(payload)
+            .map_err(|e| EventVerificationError::MalformedPayload(e.to_string()))
+    }
+
+    fn hmac(&self, payload: &[u8], timestamp: u64) -> hmac::Hmac<sha256::Hash> {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&self.secret);
+        engine.input(timestamp.to_string().as_bytes());
+        engine.input(b".");
+        engine.input(payload);
+        hmac::Hmac::from_engine(engine)
+    }
+}
+
+/// Parse a header of the form `t=<timestamp>,v1=<signature>[,v1=<signature>...]`
+fn parse_signature_header(header: &str) -> Result<(u64, Vec<Vec<u8>>), EventVerificationError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => {
+                timestamp = Some(
+                    value
+                        .parse()
+                        .map_err(|_| EventVerificationError::MalformedSignatureHeader)?,
+                )
+            }
+            Some(("v1", value)) => signatures.push(
+                Vec::<u8>::from_hex(value)
+                    .map_err(|_| EventVerificationError::MalformedSignatureHeader)?,
+            ),
+            // Ignore unknown schemes
+            Some(_) => (),
+            None => return Err(EventVerificationError::MalformedSignatureHeader),
+        }
+    }
+    match timestamp {
+        Some(timestamp) if !signatures.is_empty() => Ok((timestamp, signatures)),
+        _ => Err(EventVerificationError::MalformedSignatureHeader),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = br#"{"event_id":"evt-1","created_ts":1700000000,"type":"CONFIG_EXPIRING","data":{"wallet_id":"w1","expiration_ts":1710000000}}"#;
+
+    #[test]
+    fn verify_signature() {
+        let verifier = WebhookVerifier::new("secret");
+        let header = verifier.sign(PAYLOAD, 1_700_000_000);
+        assert_eq!(verifier.verify(PAYLOAD, &header, 1_700_000_100), Ok(()));
+
+        // Tampered payload
+        assert_eq!(
+            verifier.verify(b"{}", &header, 1_700_000_100),
+            Err(EventVerificationError::InvalidSignature)
+        );
+        // Wrong secret
+        assert_eq!(
+            WebhookVerifier::new("other secret").verify(PAYLOAD, &header, 1_700_000_100),
+            Err(EventVerificationError::InvalidSignature)
+        );
+        // Replay
+        assert_eq!(
+            verifier.verify(PAYLOAD, &header, 1_700_001_000),
+            Err(EventVerificationError::OutdatedTimestamp(1_700_000_000))
+        );
+        assert_eq!(
+            verifier.verify(PAYLOAD, "v1=00", 1_700_000_000),
+            Err(EventVerificationError::MalformedSignatureHeader)
+        );
+
+        // Multiple signatures during a secret rotation
+        let header = format!(
+            "{},v1={}",
+            header,
+            WebhookVerifier::new("other secret").hmac(PAYLOAD, 1_700_000_000)
+        );
+        assert_eq!(verifier.verify(PAYLOAD, &header, 1_700_000_000), Ok(()));
+    }
+
+    #[cfg(feature = "async_client")]
+    #[test]
+    fn verify_event() {
+        let verifier = WebhookVerifier::new("secret");
+        let header = verifier.sign(PAYLOAD, 1_700_000_000);
+        let event = verifier
+            .verify_event(PAYLOAD, &header, 1_700_000_000)
+            .unwrap();
+        assert_eq!(event.event_id, "evt-1");
+        assert_eq!(
+            event.payload,
+            EventPayload::ConfigExpiring {
+                wallet_id: "w1".to_owned(),
+                expiration_ts: 1_710_000_000
+            }
+        );
+    }
+}
@@ -1,12 +1,16 @@
 mod types;
 pub use types::*;
 
+pub mod events;
+
 #[cfg(any(feature = "async_client", feature = "blocking_client"))]
 pub mod errors;
 
 #[cfg(feature = "async_client")]
 pub mod async_client;
 #[cfg(feature = "async_client")]
+pub mod token_cache;
+#[cfg(feature = "async_client")]
 pub mod auth {
     pub use crate::async_client::auth::{DeviceAuthorizationResponse, Token};
 }
@@ -14,6 +18,9 @@ pub mod auth {
 #[cfg(all(feature = "async_client", not(feature = "blocking_client")))]
 pub use async_client::*;
 
+#[cfg(all(feature = "blocking_client", target_arch = "wasm32"))]
+compile_error!("the blocking client is not available on wasm32, disable the default features and use the async_client feature");
+
 #[cfg(feature = "blocking_client")]
 pub mod blocking_client;
 #[cfg(all(feature = "blocking_client"))]
@@ -0,0 +1,220 @@
+//! Ready-to-use [TokenCache] backends for the OAuth tokens of the Heritage service:
+//! - [MemoryTokenCache], that does not survive the process;
+//! - [FileTokenCache], a JSON file only readable by its owner;
+//! - [KeyringTokenCache], the credential store of the OS (requires the `keyring` feature).
+//!
+//! Every backend implements the [TokenCache] traits of both the async and the blocking clients.
+//!
+//! [TokenCache]: crate::async_client::TokenCache
+use std::path::PathBuf;
+
+use crate::errors::{Error, Result};
+
+/// Persistence of the serialized tokens, shared by the backends
+trait TokenStore {
+    fn write(&mut self, serialized_tokens: String) -> Result<()>;
+    fn read(&self) -> Result<Option<String>>;
+    fn erase(&mut self) -> Result<bool>;
+}
+
+macro_rules! impl_token_cache {
+    ($tokens:ty, $trait:path, $backend:ty) => {
+        impl $trait for $backend {
+            fn save_tokens(&mut self, tokens: &$tokens) -> Result<()> {
+                let serialized_tokens = serde_json::to_string(tokens)
+                    .map_err(|e| Error::TokenCacheWriteError(e.to_string()))?;
+                self.write(serialized_tokens)
+            }
+            fn load_tokens(&self) -> Result<Option<$tokens>> {
+                self.read()?
+                    .map(|serialized_tokens| {
+                        serde_json::from_str(&serialized_tokens)
+                            .map_err(|e| Error::TokenCacheReadError(e.to_string()))
+                    })
+                    .transpose()
+            }
+            fn clear(&mut self) -> Result<bool> {
+                self.erase()
+            }
+        }
+    };
+    ($backend:ty) => {
+        impl_token_cache!(
+            crate::async_client::Tokens,
+            crate::async_client::TokenCache,
+            $backend
+        );
+        #[cfg(feature = "blocking_client")]
+        impl_token_cache!(
+            crate::blocking_client::Tokens,
+            crate::blocking_client::TokenCache,
+            $backend
+        );
+    };
+}
+
+/// A [TokenCache](crate::async_client::TokenCache) keeping the tokens in memory
+#[derive(Debug, Default)]
+pub struct MemoryTokenCache(Option<String>);
+
+impl MemoryTokenCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl TokenStore for MemoryTokenCache {
+    fn write(&mut self, serialized_tokens: String) -> Result<()> {
+        self.0 = Some(serialized_tokens);
+        Ok(())
+    }
+    fn read(&self) -> Result<Option<String>> {
+        Ok(self.0.clone())
+    }
+    fn erase(&mut self) -> Result<bool> {
+        Ok(self.0.take().is_some())
+    }
+}
+impl_token_cache!(MemoryTokenCache);
+
+/// A [TokenCache](crate::async_client::TokenCache) storing the tokens in a JSON file.
+///
+/// On Unix, the file is created with the `0600` permissions so that only its owner can read it.
+#[derive(Debug, Clone)]
+pub struct FileTokenCache {
+    path: PathBuf,
+}
+
+impl FileTokenCache {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl TokenStore for FileTokenCache {
+    fn write(&mut self, serialized_tokens: String) -> Result<()> {
+        use std::io::Write;
+        let write_error = |e: std::io::Error| {
+            Error::TokenCacheWriteError(format!("{}: {e}", self.path.display()))
+        };
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+            options.mode(0o600);
+            // The mode only applies when the file is created
+            if self.path.exists() {
+                std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o600))
+                    .map_err(write_error)?;
+            }
+        }
+        options
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(serialized_tokens.as_bytes()))
+            .map_err(write_error)
+    }
+    fn read(&self) -> Result<Option<String>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(serialized_tokens) => Ok(Some(serialized_tokens)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Error::TokenCacheReadError(format!(
+                "{}: {e}",
+                self.path.display()
+            ))),
+        }
+    }
+    fn erase(&mut self) -> Result<bool> {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(Error::TokenCacheWriteError(format!(
+                "{}: {e}",
+                self.path.display()
+            ))),
+        }
+    }
+}
+impl_token_cache!(FileTokenCache);
+
+/// A [TokenCache](crate::async_client::TokenCache) storing the tokens in the credential
+/// store of the OS (Keychain, Credential Manager, Secret Service...)
+#[cfg(feature = "keyring")]
+#[derive(Debug)]
+pub struct KeyringTokenCache {
+    entry: keyring::Entry,
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringTokenCache {
+    /// The default service name of the keyring entries
+    pub const DEFAULT_SERVICE: &'static str = "heritage-service-api-client";
+
+    /// Create a cache using the keyring entry identified by `service` and `user`
+    pub fn new(service: &str, user: &str) -> Result<Self> {
+        Ok(Self {
+            entry: keyring::Entry::new(service, user)
+                .map_err(|e| Error::TokenCacheReadError(e.to_string()))?,
+        })
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl TokenStore for KeyringTokenCache {
+    fn write(&mut self, serialized_tokens: String) -> Result<()> {
+        self.entry
+            .set_password(&serialized_tokens)
+            .map_err(|e| Error::TokenCacheWriteError(e.to_string()))
+    }
+    fn read(&self) -> Result<Option<String>> {
+        match self.entry.get_password() {
+            Ok(serialized_tokens) => Ok(Some(serialized_tokens)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(Error::TokenCacheReadError(e.to_string())),
+        }
+    }
+    fn erase(&mut self) -> Result<bool> {
+        match self.entry.delete_credential() {
+            Ok(()) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(e) => Err(Error::TokenCacheWriteError(e.to_string())),
+        }
+    }
+}
+#[cfg(feature = "keyring")]
+impl_token_cache!(KeyringTokenCache);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::async_client::TokenCache;
+
+    #[test]
+    fn file_token_cache() {
+        let dir = std::env::temp_dir().join(format!("heritage-token-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut cache = FileTokenCache::new(dir.join("tokens.json"));
+
+        assert!(cache.load_tokens().unwrap().is_none());
+        assert!(!cache.clear().unwrap());
+        cache.write("{}".to_owned()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(cache.path())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(cache.read().unwrap().as_deref(), Some("{}"));
+        assert!(cache.clear().unwrap());
+        assert!(cache.read().unwrap().is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -378,3 +378,66 @@ impl From<UnsignedPsbt> for (PartiallySignedTransaction, TransactionSummary) {
         (psbt, tx_summary)
     }
 }
+
+/// A page of a paginated list endpoint of the Heritage service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "RawPage<T>")]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The token to provide to retrieve the next page, [None] if this is the last one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuation_token: Option<String>,
+}
+
+/// Endpoints of older versions of the service return a plain list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawPage<T> {
+    Paged {
+        items: Vec<T>,
+        #[serde(default)]
+        continuation_token: Option<String>,
+    },
+    Unpaged(Vec<T>),
+}
+impl<T> From<RawPage<T>> for Page<T> {
+    fn from(value: RawPage<T>) -> Self {
+        match value {
+            RawPage::Paged {
+                items,
+                continuation_token,
+            } => Page {
+                items,
+                continuation_token: continuation_token.filter(|token| !token.is_empty()),
+            },
+            RawPage::Unpaged(items) => Page {
+                items,
+                continuation_token: None,
+            },
+        }
+    }
+}
+
+#[cfg(all(test, feature = "async_client"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_deserialization() {
+        let page: Page<u32> =
+            serde_json::from_str(r#"{"items":[1,2],"continuation_token":"abc"}"#).unwrap();
+        assert_eq!(page.items, vec![1, 2]);
+        assert_eq!(page.continuation_token.as_deref(), Some("abc"));
+
+        // Last page
+        let page: Page<u32> =
+            serde_json::from_str(r#"{"items":[3],"continuation_token":""}"#).unwrap();
+        assert_eq!(page.items, vec![3]);
+        assert_eq!(page.continuation_token, None);
+
+        // Older versions of the service return a plain list
+        let page: Page<u32> = serde_json::from_str("[1,2,3]").unwrap();
+        assert_eq!(page.items, vec![1, 2, 3]);
+        assert_eq!(page.continuation_token, None);
+    }
+}